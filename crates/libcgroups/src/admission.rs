@@ -0,0 +1,505 @@
+//! Admission check of the resources of a container, for schedulers which
+//! want to know whether a container fits on the host before they create it.
+//!
+//! The resources the container asks for are compared with the headroom of
+//! the host and of the cgroup it is going to be created in:
+//!
+//! - cpu: the cpus of the cfs quota, or else the cpus of the cpuset, against
+//!   the online cpus of the host and the `cpu.max` and
+//!   `cpuset.cpus.effective` of the cgroup and its ancestors, in millicpus,
+//! - memory: the limit, or else the reservation, against the available
+//!   memory of the host and `memory.max` minus `memory.current`, in bytes,
+//! - hugepages: the limit of each page size against the free hugepages of
+//!   the host and the hugetlb limits minus their usage, in bytes,
+//! - pids: the limit against the free pids of the host and `pids.max` minus
+//!   `pids.current`.
+//!
+//! Cpus are not used up by containers, so their headroom is the number of
+//! cpus the container can use, not the idle ones. The cgroup limits are only
+//! checked on cgroup v2.
+use std::path::{Path, PathBuf};
+
+use oci_spec::runtime::LinuxResources;
+use serde::Serialize;
+
+use crate::common::{read_cgroup_file, WrappedIoError, DEFAULT_CGROUP_ROOT};
+
+const DEFAULT_CPU_PERIOD: u64 = 100_000;
+const CGROUP_CONTROLLERS: &str = "cgroup.controllers";
+
+#[derive(thiserror::Error, Debug)]
+pub enum AdmissionError {
+    #[error("io error: {0}")]
+    WrappedIo(#[from] WrappedIoError),
+    #[error("failed to parse {path:?}: {content:?}")]
+    Parse { path: PathBuf, content: String },
+    #[error("invalid hugepage size {0}")]
+    PageSize(String),
+    #[error("cpu quota {0} is out of range")]
+    CpuQuota(i64),
+    #[error("cgroup limits can only be checked on cgroup v2")]
+    NotUnified,
+}
+
+type Result<T> = std::result::Result<T, AdmissionError>;
+
+/// Whether a resource fits
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ResourceFit {
+    /// `cpu`, `memory`, `hugepages-<size>` or `pids`
+    pub resource: String,
+    pub requested: u64,
+    pub available: u64,
+    /// Cgroup whose limit leaves the least headroom, none if the host does
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub limited_by: Option<PathBuf>,
+    pub fits: bool,
+}
+
+/// Result of the admission check
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Verdict {
+    /// All requested resources fit
+    pub fits: bool,
+    pub resources: Vec<ResourceFit>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Resource {
+    Cpu,
+    Memory,
+    Hugepages { page_size: String, size: u64 },
+    Pids,
+}
+
+impl Resource {
+    fn name(&self) -> String {
+        match self {
+            Resource::Cpu => "cpu".to_owned(),
+            Resource::Memory => "memory".to_owned(),
+            Resource::Hugepages { page_size, .. } => format!("hugepages-{page_size}"),
+            Resource::Pids => "pids".to_owned(),
+        }
+    }
+}
+
+/// Files of the host the headroom is read from
+struct Host<'a> {
+    proc_root: &'a Path,
+    sys_root: &'a Path,
+    cgroup_root: &'a Path,
+}
+
+/// Checks whether the resources fit on the host and in the cgroup at
+/// `cgroup_path`, relative to the root of the cgroup v2 hierarchy
+pub fn fit(resources: &LinuxResources, cgroup_path: Option<&Path>) -> Result<Verdict> {
+    let host = Host {
+        proc_root: Path::new("/proc"),
+        sys_root: Path::new("/sys"),
+        cgroup_root: Path::new(DEFAULT_CGROUP_ROOT),
+    };
+    fit_with_host(resources, cgroup_path, &host)
+}
+
+fn fit_with_host(
+    resources: &LinuxResources,
+    cgroup_path: Option<&Path>,
+    host: &Host,
+) -> Result<Verdict> {
+    let cgroups = match cgroup_path {
+        Some(cgroup_path) => {
+            if !host.cgroup_root.join(CGROUP_CONTROLLERS).exists() {
+                return Err(AdmissionError::NotUnified);
+            }
+            ancestors(host.cgroup_root, cgroup_path)
+        }
+        None => Vec::new(),
+    };
+
+    let mut verdict = Verdict {
+        fits: true,
+        resources: Vec::new(),
+    };
+    for (resource, requested) in requests(resources)? {
+        let mut available = host_headroom(&resource, host)?;
+        let mut limited_by = None;
+        for cgroup in &cgroups {
+            if let Some(headroom) = cgroup_headroom(&resource, cgroup)? {
+                if headroom < available {
+                    available = headroom;
+                    limited_by = Some(cgroup.clone());
+                }
+            }
+        }
+        let fits = requested <= available;
+        verdict.fits &= fits;
+        verdict.resources.push(ResourceFit {
+            resource: resource.name(),
+            requested,
+            available,
+            limited_by,
+            fits,
+        });
+    }
+
+    Ok(verdict)
+}
+
+/// The cgroup and its ancestors below the root, which exist
+fn ancestors(cgroup_root: &Path, cgroup_path: &Path) -> Vec<PathBuf> {
+    let mut cgroups = Vec::new();
+    let mut current = cgroup_root.to_path_buf();
+    for component in cgroup_path.components() {
+        if let std::path::Component::Normal(name) = component {
+            current.push(name);
+            if !current.exists() {
+                break;
+            }
+            cgroups.push(current.clone());
+        }
+    }
+    cgroups
+}
+
+fn requests(resources: &LinuxResources) -> Result<Vec<(Resource, u64)>> {
+    let mut requests = Vec::new();
+    if let Some(cpu) = resources.cpu() {
+        let period = cpu.period().filter(|period| *period > 0);
+        match (cpu.quota(), cpu.cpus()) {
+            (Some(quota), _) if quota > 0 => {
+                let millicpus = (quota as u64)
+                    .checked_mul(1000)
+                    .ok_or(AdmissionError::CpuQuota(quota))?;
+                requests.push((
+                    Resource::Cpu,
+                    millicpus / period.unwrap_or(DEFAULT_CPU_PERIOD),
+                ))
+            }
+            (_, Some(cpus)) if !cpus.trim().is_empty() => {
+                requests.push((Resource::Cpu, count_cpus(cpus) * 1000))
+            }
+            _ => {}
+        }
+    }
+    if let Some(memory) = resources.memory() {
+        let requested = memory
+            .limit()
+            .filter(|limit| *limit > 0)
+            .or_else(|| memory.reservation().filter(|reservation| *reservation > 0));
+        if let Some(requested) = requested {
+            requests.push((Resource::Memory, requested as u64));
+        }
+    }
+    for hugepage in resources.hugepage_limits().iter().flatten() {
+        if hugepage.limit() > 0 {
+            let page_size = hugepage.page_size().to_owned();
+            let size = page_size_bytes(&page_size)?;
+            requests.push((
+                Resource::Hugepages { page_size, size },
+                hugepage.limit() as u64,
+            ));
+        }
+    }
+    if let Some(pids) = resources.pids() {
+        if pids.limit() > 0 {
+            requests.push((Resource::Pids, pids.limit() as u64));
+        }
+    }
+    Ok(requests)
+}
+
+/// Size of a hugepage of the spec, e.g. `2MB`
+fn page_size_bytes(page_size: &str) -> Result<u64> {
+    let split = page_size
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(page_size.len());
+    let (value, unit) = page_size.split_at(split);
+    let shift = match unit {
+        "KB" => 10,
+        "MB" => 20,
+        "GB" => 30,
+        _ => return Err(AdmissionError::PageSize(page_size.to_owned())),
+    };
+    value
+        .parse::<u64>()
+        .ok()
+        .filter(|value| *value > 0)
+        .map(|value| value << shift)
+        .ok_or_else(|| AdmissionError::PageSize(page_size.to_owned()))
+}
+
+/// Number of cpus of a cpu list like `0-3,6`
+fn count_cpus(list: &str) -> u64 {
+    list.trim()
+        .split(',')
+        .filter(|range| !range.is_empty())
+        .map(|range| match range.split_once('-') {
+            Some((first, last)) => match (first.parse::<u64>(), last.parse::<u64>()) {
+                (Ok(first), Ok(last)) if last >= first => last - first + 1,
+                _ => 0,
+            },
+            None => 1,
+        })
+        .sum()
+}
+
+fn parse_error(path: &Path, content: &str) -> AdmissionError {
+    AdmissionError::Parse {
+        path: path.to_owned(),
+        content: content.to_owned(),
+    }
+}
+
+fn read_u64(path: &Path) -> Result<u64> {
+    let content = read_cgroup_file(path)?;
+    content
+        .trim()
+        .parse()
+        .map_err(|_| parse_error(path, &content))
+}
+
+/// Value of a limit file, none if it is `max`
+fn read_limit(path: &Path) -> Result<Option<u64>> {
+    let content = read_cgroup_file(path)?;
+    match content.trim() {
+        "max" => Ok(None),
+        value => value
+            .parse()
+            .map(Some)
+            .map_err(|_| parse_error(path, &content)),
+    }
+}
+
+fn host_headroom(resource: &Resource, host: &Host) -> Result<u64> {
+    match resource {
+        Resource::Cpu => {
+            let online = host.sys_root.join("devices/system/cpu/online");
+            Ok(count_cpus(&read_cgroup_file(online)?) * 1000)
+        }
+        Resource::Memory => {
+            let meminfo = host.proc_root.join("meminfo");
+            let content = read_cgroup_file(&meminfo)?;
+            content
+                .lines()
+                .find_map(|line| line.strip_prefix("MemAvailable:"))
+                .and_then(|value| {
+                    value
+                        .trim()
+                        .trim_end_matches("kB")
+                        .trim()
+                        .parse::<u64>()
+                        .ok()
+                })
+                .map(|kb| kb * 1024)
+                .ok_or_else(|| parse_error(&meminfo, &content))
+        }
+        Resource::Hugepages { size, .. } => {
+            let free = host
+                .sys_root
+                .join(format!("kernel/mm/hugepages/hugepages-{}kB", size >> 10))
+                .join("free_hugepages");
+            // a page size the kernel does not support has no free pages
+            if !free.exists() {
+                return Ok(0);
+            }
+            Ok(read_u64(&free)? * size)
+        }
+        Resource::Pids => {
+            let pid_max = read_u64(&host.proc_root.join("sys/kernel/pid_max"))?;
+            // the fourth field of loadavg is runnable/total scheduling entities
+            let loadavg = host.proc_root.join("loadavg");
+            let content = read_cgroup_file(&loadavg)?;
+            let tasks = content
+                .split_whitespace()
+                .nth(3)
+                .and_then(|field| field.split_once('/'))
+                .and_then(|(_, total)| total.parse::<u64>().ok())
+                .ok_or_else(|| parse_error(&loadavg, &content))?;
+            Ok(pid_max.saturating_sub(tasks))
+        }
+    }
+}
+
+/// Headroom the limit of the cgroup leaves, none if it has no limit
+fn cgroup_headroom(resource: &Resource, cgroup: &Path) -> Result<Option<u64>> {
+    let limit_minus_usage = |limit: &str, usage: &str| -> Result<Option<u64>> {
+        let limit_file = cgroup.join(limit);
+        if !limit_file.exists() {
+            return Ok(None);
+        }
+        match read_limit(&limit_file)? {
+            Some(limit) => Ok(Some(limit.saturating_sub(read_u64(&cgroup.join(usage))?))),
+            None => Ok(None),
+        }
+    };
+
+    match resource {
+        Resource::Cpu => {
+            let mut headroom = None;
+            let cpu_max = cgroup.join("cpu.max");
+            if cpu_max.exists() {
+                let content = read_cgroup_file(&cpu_max)?;
+                let mut fields = content.split_whitespace();
+                match (fields.next(), fields.next().map(str::parse::<u64>)) {
+                    (Some("max"), _) => {}
+                    (Some(quota), Some(Ok(period))) if period > 0 => {
+                        let quota: u64 =
+                            quota.parse().map_err(|_| parse_error(&cpu_max, &content))?;
+                        // quotas beyond any host are no limit at all
+                        headroom = Some(quota.saturating_mul(1000) / period);
+                    }
+                    _ => return Err(parse_error(&cpu_max, &content)),
+                }
+            }
+            let effective = cgroup.join("cpuset.cpus.effective");
+            if effective.exists() {
+                let cpus = count_cpus(&read_cgroup_file(&effective)?) * 1000;
+                headroom = Some(headroom.map_or(cpus, |headroom: u64| headroom.min(cpus)));
+            }
+            Ok(headroom)
+        }
+        Resource::Memory => limit_minus_usage("memory.max", "memory.current"),
+        Resource::Hugepages { page_size, .. } => limit_minus_usage(
+            &format!("hugetlb.{page_size}.max"),
+            &format!("hugetlb.{page_size}.current"),
+        ),
+        Resource::Pids => limit_minus_usage("pids.max", "pids.current"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use oci_spec::runtime::{
+        LinuxCpuBuilder, LinuxHugepageLimitBuilder, LinuxMemoryBuilder, LinuxPidsBuilder,
+        LinuxResourcesBuilder,
+    };
+
+    use std::fs;
+
+    use super::*;
+
+    fn write(path: &Path, content: &str) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+    }
+
+    #[test]
+    fn test_page_size_bytes() {
+        assert_eq!(page_size_bytes("64KB").unwrap(), 64 << 10);
+        assert_eq!(page_size_bytes("2MB").unwrap(), 2 << 20);
+        assert_eq!(page_size_bytes("1GB").unwrap(), 1 << 30);
+        for invalid in ["", "2", "MB", "0MB", "2mb", "2TB"] {
+            assert!(page_size_bytes(invalid).is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn test_count_cpus() {
+        assert_eq!(count_cpus("0-3,6\n"), 5);
+        assert_eq!(count_cpus("0"), 1);
+        assert_eq!(count_cpus(""), 0);
+    }
+
+    #[test]
+    fn test_requests_cpu_quota() {
+        let resources = |quota: i64| {
+            LinuxResourcesBuilder::default()
+                .cpu(LinuxCpuBuilder::default().quota(quota).build().unwrap())
+                .build()
+                .unwrap()
+        };
+        assert_eq!(
+            requests(&resources(250_000)).unwrap(),
+            [(Resource::Cpu, 2500)]
+        );
+        assert!(matches!(
+            requests(&resources(i64::MAX)),
+            Err(AdmissionError::CpuQuota(i64::MAX))
+        ));
+    }
+
+    #[test]
+    fn test_fit() {
+        let tmp = tempfile::tempdir().unwrap();
+        let host = Host {
+            proc_root: &tmp.path().join("proc"),
+            sys_root: &tmp.path().join("sys"),
+            cgroup_root: &tmp.path().join("cgroup"),
+        };
+        write(&host.sys_root.join("devices/system/cpu/online"), "0-7\n");
+        write(
+            &host.proc_root.join("meminfo"),
+            "MemTotal:       16384000 kB\nMemAvailable:    8192000 kB\n",
+        );
+        write(&host.proc_root.join("sys/kernel/pid_max"), "4194304\n");
+        write(
+            &host.proc_root.join("loadavg"),
+            "0.50 0.40 0.30 2/1000 4242\n",
+        );
+        write(
+            &host
+                .sys_root
+                .join("kernel/mm/hugepages/hugepages-2048kB/free_hugepages"),
+            "4\n",
+        );
+        write(
+            &host.cgroup_root.join(CGROUP_CONTROLLERS),
+            "cpu memory pids\n",
+        );
+        let slice = host.cgroup_root.join("jobs.slice");
+        write(&slice.join("cpu.max"), "200000 100000\n");
+        write(&slice.join("memory.max"), "max\n");
+        write(&slice.join("pids.max"), "100\n");
+        write(&slice.join("pids.current"), "40\n");
+
+        let resources = LinuxResourcesBuilder::default()
+            .cpu(LinuxCpuBuilder::default().quota(150000i64).build().unwrap())
+            .memory(
+                LinuxMemoryBuilder::default()
+                    .limit(1i64 << 30)
+                    .build()
+                    .unwrap(),
+            )
+            .hugepage_limits(vec![LinuxHugepageLimitBuilder::default()
+                .page_size("2MB")
+                .limit(16i64 << 20)
+                .build()
+                .unwrap()])
+            .pids(LinuxPidsBuilder::default().limit(100i64).build().unwrap())
+            .build()
+            .unwrap();
+
+        let verdict = fit_with_host(&resources, None, &host).unwrap();
+        assert!(!verdict.fits);
+        let fits: Vec<_> = verdict
+            .resources
+            .iter()
+            .map(|fit| (fit.resource.as_str(), fit.available, fit.fits))
+            .collect();
+        assert_eq!(
+            fits,
+            [
+                ("cpu", 8000, true),
+                ("memory", 8192000 << 10, true),
+                ("hugepages-2MB", 8 << 20, false),
+                ("pids", 4193304, true),
+            ]
+        );
+
+        let verdict =
+            fit_with_host(&resources, Some(Path::new("jobs.slice/job-1")), &host).unwrap();
+        assert_eq!(verdict.resources[0].available, 2000);
+        assert_eq!(verdict.resources[0].limited_by, Some(slice.clone()));
+        assert_eq!(verdict.resources[1].limited_by, None);
+        assert_eq!(verdict.resources[3].available, 60);
+        assert!(!verdict.resources[3].fits);
+
+        fs::remove_file(host.cgroup_root.join(CGROUP_CONTROLLERS)).unwrap();
+        assert!(matches!(
+            fit_with_host(&resources, Some(Path::new("jobs.slice")), &host),
+            Err(AdmissionError::NotUnified)
+        ));
+    }
+}
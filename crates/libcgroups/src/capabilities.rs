@@ -0,0 +1,233 @@
+//! Probing which kinds of resource restrictions a cgroup can enforce, so
+//! that callers can reject a request up front instead of having it applied
+//! only partially.
+use std::collections::BTreeSet;
+use std::fmt::Display;
+
+use oci_spec::runtime::{LinuxBlockIo, LinuxMemory, LinuxResources};
+
+/// A kind of resource restriction of the runtime spec. The names follow the
+/// fields of `linux.resources`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ResourceType {
+    Devices,
+    Memory,
+    Cpu,
+    CpuSet,
+    Pids,
+    BlockIo,
+    HugePageLimits,
+    Network,
+    Rdma,
+    Unified,
+}
+
+impl Display for ResourceType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let print = match self {
+            Self::Devices => "devices",
+            Self::Memory => "memory",
+            Self::Cpu => "cpu",
+            Self::CpuSet => "cpuset",
+            Self::Pids => "pids",
+            Self::BlockIo => "blockIO",
+            Self::HugePageLimits => "hugepageLimits",
+            Self::Network => "network",
+            Self::Rdma => "rdma",
+            Self::Unified => "unified",
+        };
+
+        write!(f, "{print}")
+    }
+}
+
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+#[error("resources cannot be controlled in the cgroup of this container: {}", format_resources(.resources))]
+pub struct UnsupportedResourceError {
+    /// The requested resource types which are not controllable
+    pub resources: Vec<ResourceType>,
+}
+
+fn format_resources(resources: &[ResourceType]) -> String {
+    resources
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// The resource types a cgroup manager is able to control for its cgroup
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CgroupCapabilities {
+    resources: BTreeSet<ResourceType>,
+}
+
+impl CgroupCapabilities {
+    pub fn new<I: IntoIterator<Item = ResourceType>>(resources: I) -> Self {
+        Self {
+            resources: resources.into_iter().collect(),
+        }
+    }
+
+    pub fn supports(&self, resource: ResourceType) -> bool {
+        self.resources.contains(&resource)
+    }
+
+    pub fn resources(&self) -> impl Iterator<Item = ResourceType> + '_ {
+        self.resources.iter().copied()
+    }
+
+    /// Returns the capabilities which both self and other have
+    pub fn intersection(&self, other: &CgroupCapabilities) -> CgroupCapabilities {
+        Self {
+            resources: self
+                .resources
+                .intersection(&other.resources)
+                .copied()
+                .collect(),
+        }
+    }
+
+    /// Checks that every resource type set in `resources` can be controlled.
+    /// All types which cannot are reported at once.
+    pub fn validate(&self, resources: &LinuxResources) -> Result<(), UnsupportedResourceError> {
+        let unsupported: Vec<ResourceType> = requested_resources(resources)
+            .into_iter()
+            .filter(|resource| !self.supports(*resource))
+            .collect();
+
+        if unsupported.is_empty() {
+            Ok(())
+        } else {
+            Err(UnsupportedResourceError {
+                resources: unsupported,
+            })
+        }
+    }
+}
+
+/// Returns the resource types for which `resources` requests a restriction.
+/// Present but empty sections do not request anything.
+pub fn requested_resources(resources: &LinuxResources) -> BTreeSet<ResourceType> {
+    let mut requested = BTreeSet::new();
+
+    if resources
+        .devices()
+        .as_ref()
+        .map_or(false, |d| !d.is_empty())
+    {
+        requested.insert(ResourceType::Devices);
+    }
+    if resources
+        .memory()
+        .map_or(false, |m| m != LinuxMemory::default())
+    {
+        requested.insert(ResourceType::Memory);
+    }
+    if let Some(cpu) = resources.cpu() {
+        if cpu.shares().is_some()
+            || cpu.quota().is_some()
+            || cpu.period().is_some()
+            || cpu.burst().is_some()
+            || cpu.idle().is_some()
+            || cpu.realtime_runtime().is_some()
+            || cpu.realtime_period().is_some()
+        {
+            requested.insert(ResourceType::Cpu);
+        }
+        if cpu.cpus().is_some() || cpu.mems().is_some() {
+            requested.insert(ResourceType::CpuSet);
+        }
+    }
+    if resources.pids().is_some() {
+        requested.insert(ResourceType::Pids);
+    }
+    if resources
+        .block_io()
+        .as_ref()
+        .map_or(false, |b| b != &LinuxBlockIo::default())
+    {
+        requested.insert(ResourceType::BlockIo);
+    }
+    if resources
+        .hugepage_limits()
+        .as_ref()
+        .map_or(false, |h| !h.is_empty())
+    {
+        requested.insert(ResourceType::HugePageLimits);
+    }
+    if resources.network().is_some() {
+        requested.insert(ResourceType::Network);
+    }
+    if resources.rdma().as_ref().map_or(false, |r| !r.is_empty()) {
+        requested.insert(ResourceType::Rdma);
+    }
+    if resources
+        .unified()
+        .as_ref()
+        .map_or(false, |u| !u.is_empty())
+    {
+        requested.insert(ResourceType::Unified);
+    }
+
+    requested
+}
+
+#[cfg(test)]
+mod tests {
+    use oci_spec::runtime::{
+        LinuxCpuBuilder, LinuxMemoryBuilder, LinuxNetworkBuilder, LinuxPidsBuilder,
+        LinuxResourcesBuilder,
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_requested_resources() {
+        let resources = LinuxResourcesBuilder::default()
+            .cpu(LinuxCpuBuilder::default().cpus("0-1").build().unwrap())
+            .memory(LinuxMemoryBuilder::default().build().unwrap())
+            .pids(LinuxPidsBuilder::default().limit(10).build().unwrap())
+            .hugepage_limits(vec![])
+            .build()
+            .unwrap();
+
+        assert_eq!(
+            requested_resources(&resources),
+            BTreeSet::from([ResourceType::CpuSet, ResourceType::Pids])
+        );
+        assert!(requested_resources(&LinuxResources::default()).is_empty());
+    }
+
+    #[test]
+    fn test_validate_reports_all_unsupported() {
+        let capabilities = CgroupCapabilities::new([ResourceType::Cpu, ResourceType::Pids]);
+        let resources = LinuxResourcesBuilder::default()
+            .cpu(LinuxCpuBuilder::default().shares(1024u64).build().unwrap())
+            .memory(LinuxMemoryBuilder::default().limit(1024).build().unwrap())
+            .network(
+                LinuxNetworkBuilder::default()
+                    .class_id(1u32)
+                    .build()
+                    .unwrap(),
+            )
+            .build()
+            .unwrap();
+
+        let err = capabilities.validate(&resources).unwrap_err();
+        assert_eq!(
+            err.resources,
+            vec![ResourceType::Memory, ResourceType::Network]
+        );
+        assert_eq!(
+            err.to_string(),
+            "resources cannot be controlled in the cgroup of this container: memory, network"
+        );
+
+        let resources = LinuxResourcesBuilder::default()
+            .pids(LinuxPidsBuilder::default().limit(10).build().unwrap())
+            .build()
+            .unwrap();
+        assert!(capabilities.validate(&resources).is_ok());
+    }
+}
@@ -0,0 +1,256 @@
+//! Interpretation of `linux.cgroupsPath` of the runtime spec. An absolute
+//! path is always a path relative to the cgroup root. A relative path of the
+//! form `[slice]:[prefix]:[name]` names a systemd unit, which the systemd
+//! manager creates as `[prefix]-[name].scope` in the slice. The managers
+//! working on the cgroup filesystem place the cgroup where systemd would, so
+//! that a container ends up at the same path with either driver. Any other
+//! relative path is relative to the cgroup root as well.
+//!
+//! ref https://github.com/opencontainers/runtime-spec/blob/main/config-linux.md#cgroups-path
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+
+const SLICE_SUFFIX: &str = ".slice";
+const SCOPE_SUFFIX: &str = ".scope";
+
+#[derive(thiserror::Error, Debug)]
+pub enum CgroupsPathError {
+    #[error("no cgroups path has been provided")]
+    NoPath,
+    #[error("cgroups path does not contain valid utf8")]
+    InvalidUtf8(PathBuf),
+    #[error("cgroups path is malformed: {0}")]
+    MalformedPath(PathBuf),
+    #[error("invalid slice name: {0}")]
+    InvalidSliceName(String),
+    #[error("invalid unit name {name:?} in cgroups path {path}")]
+    InvalidUnitName { name: String, path: PathBuf },
+}
+
+/// Cgroups path naming a systemd unit, of the form `[slice]:[prefix]:[name]`.
+/// The slice is the "parent" and should be expanded properly, see
+/// [`expand_slice`]. The prefix may be left out along with its colon.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CgroupsPath {
+    pub parent: String,
+    pub prefix: String,
+    pub name: String,
+}
+
+impl TryFrom<&Path> for CgroupsPath {
+    type Error = CgroupsPathError;
+
+    fn try_from(cgroups_path: &Path) -> Result<Self, Self::Error> {
+        // if cgroups_path was provided it should be of the form [slice]:[prefix]:[name],
+        // for example: "system.slice:docker:1234".
+        if cgroups_path.as_os_str().is_empty() {
+            return Err(CgroupsPathError::NoPath);
+        }
+
+        let parts = cgroups_path
+            .to_str()
+            .ok_or_else(|| CgroupsPathError::InvalidUtf8(cgroups_path.to_path_buf()))?
+            .split(':')
+            .collect::<Vec<&str>>();
+
+        let destructured_path = match parts.len() {
+            2 => CgroupsPath {
+                parent: "".to_owned(),
+                prefix: parts[0].to_owned(),
+                name: parts[1].to_owned(),
+            },
+            3 => CgroupsPath {
+                parent: parts[0].to_owned(),
+                prefix: parts[1].to_owned(),
+                name: parts[2].to_owned(),
+            },
+            _ => return Err(CgroupsPathError::MalformedPath(cgroups_path.to_path_buf())),
+        };
+
+        if !destructured_path.parent.is_empty() {
+            expand_slice(&destructured_path.parent)?;
+        }
+        for part in [&destructured_path.prefix, &destructured_path.name] {
+            if !is_valid_unit_name_part(part) {
+                return Err(CgroupsPathError::InvalidUnitName {
+                    name: part.clone(),
+                    path: cgroups_path.to_path_buf(),
+                });
+            }
+        }
+        if destructured_path.name.is_empty() {
+            return Err(CgroupsPathError::InvalidUnitName {
+                name: String::new(),
+                path: cgroups_path.to_path_buf(),
+            });
+        }
+
+        Ok(destructured_path)
+    }
+}
+
+impl Display for CgroupsPath {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}:{}", self.parent, self.prefix, self.name)
+    }
+}
+
+impl CgroupsPath {
+    /// Name of the unit of the container, e.g. foo:docker:bar gives
+    /// docker-bar.scope. By default a scope is created unless a slice is
+    /// specified explicitly.
+    pub fn unit_name(&self) -> String {
+        if self.name.ends_with(SLICE_SUFFIX) {
+            return self.name.clone();
+        }
+        if self.prefix.is_empty() {
+            return format!("{}{SCOPE_SUFFIX}", self.name);
+        }
+        format!("{}-{}{SCOPE_SUFFIX}", self.prefix, self.name)
+    }
+
+    /// Path of the unit below the cgroup root of the systemd instance
+    /// managing it. Without a parent the unit is placed in the root slice.
+    pub fn unit_path(&self) -> Result<PathBuf, CgroupsPathError> {
+        let parent = match self.parent.as_str() {
+            "" => PathBuf::from("/"),
+            parent => expand_slice(parent)?,
+        };
+        Ok(parent.join(self.unit_name()))
+    }
+}
+
+/// Whether a cgroups path is of the form `[slice]:[prefix]:[name]` rather
+/// than a path of the cgroup filesystem
+pub fn is_systemd_path(cgroups_path: &Path) -> bool {
+    !cgroups_path.is_absolute()
+        && cgroups_path
+            .to_str()
+            .map_or(false, |path| path.contains(':') && !path.contains('/'))
+}
+
+/// Path of the cgroup relative to the cgroup root for the managers working on
+/// the cgroup filesystem. Paths naming a systemd unit are converted to the
+/// path the unit would have, any other path is kept.
+pub fn cgroupfs_path(cgroups_path: &Path) -> Result<PathBuf, CgroupsPathError> {
+    if !is_systemd_path(cgroups_path) {
+        return Ok(cgroups_path.to_path_buf());
+    }
+
+    let destructured: CgroupsPath = cgroups_path.try_into()?;
+    destructured.unit_path()
+}
+
+/// systemd represents slice hierarchy using `-`, so we need to follow suit when
+/// generating the path of slice. For example, 'test-a-b.slice' becomes
+/// '/test.slice/test-a.slice/test-a-b.slice'.
+pub fn expand_slice(slice: &str) -> Result<PathBuf, CgroupsPathError> {
+    if slice.len() <= SLICE_SUFFIX.len() || !slice.ends_with(SLICE_SUFFIX) {
+        return Err(CgroupsPathError::InvalidSliceName(slice.into()));
+    }
+    if slice.contains('/') {
+        return Err(CgroupsPathError::InvalidSliceName(slice.into()));
+    }
+    let mut path = "".to_owned();
+    let mut prefix = "".to_owned();
+    let slice_name = slice.trim_end_matches(SLICE_SUFFIX);
+    // if input was -.slice, we should just return root now
+    if slice_name == "-" {
+        return Ok(Path::new("/").to_path_buf());
+    }
+    for component in slice_name.split('-') {
+        if component.is_empty() {
+            return Err(CgroupsPathError::InvalidSliceName(slice.into()));
+        }
+        // Append the component to the path and to the prefix.
+        path = format!("{path}/{prefix}{component}{SLICE_SUFFIX}");
+        prefix = format!("{prefix}{component}-");
+    }
+    Ok(Path::new(&path).to_path_buf())
+}
+
+/// systemd only accepts these characters in unit names
+fn is_valid_unit_name_part(part: &str) -> bool {
+    part.chars()
+        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | '@' | '\\'))
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::{Context, Result};
+
+    use super::*;
+
+    #[test]
+    fn expand_slice_works() -> Result<()> {
+        assert_eq!(
+            expand_slice("test-a-b.slice")?,
+            PathBuf::from("/test.slice/test-a.slice/test-a-b.slice"),
+        );
+        assert_eq!(expand_slice("-.slice")?, PathBuf::from("/"));
+        for invalid in [".slice", "test", "test--a.slice", "a/b.slice"] {
+            assert!(expand_slice(invalid).is_err(), "{invalid}");
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_cgroups_path() -> Result<()> {
+        let path: CgroupsPath = Path::new("machine.slice:libpod:foo")
+            .try_into()
+            .context("parse path")?;
+        assert_eq!(
+            path,
+            CgroupsPath {
+                parent: "machine.slice".to_owned(),
+                prefix: "libpod".to_owned(),
+                name: "foo".to_owned(),
+            }
+        );
+        assert_eq!(path.unit_name(), "libpod-foo.scope");
+
+        let path: CgroupsPath = Path::new("::foo.slice").try_into().context("parse path")?;
+        assert_eq!(path.unit_name(), "foo.slice");
+        let path: CgroupsPath = Path::new("system.slice::foo")
+            .try_into()
+            .context("parse path")?;
+        assert_eq!(path.unit_name(), "foo.scope");
+
+        for invalid in [
+            "",
+            "foo",
+            "a:b:c:d",
+            "system.slice:youki:",
+            "system:youki:foo",
+            "system.slice:you ki:foo",
+            "system.slice:youki:foo/bar",
+        ] {
+            assert!(
+                CgroupsPath::try_from(Path::new(invalid)).is_err(),
+                "{invalid}"
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_cgroupfs_path() -> Result<()> {
+        assert_eq!(
+            cgroupfs_path(Path::new("test-a.slice:docker:foo"))?,
+            PathBuf::from("/test.slice/test-a.slice/docker-foo.scope")
+        );
+        assert_eq!(
+            cgroupfs_path(Path::new(":youki:foo"))?,
+            PathBuf::from("/youki-foo.scope")
+        );
+        // paths of the cgroup filesystem are kept
+        for path in ["/youki/foo", "youki/foo", "/youki:foo", "youki/a:b"] {
+            assert_eq!(cgroupfs_path(Path::new(path))?, PathBuf::from(path));
+        }
+        assert!(cgroupfs_path(Path::new("system.slice:a:b:c")).is_err());
+
+        Ok(())
+    }
+}
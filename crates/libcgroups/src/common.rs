@@ -1,3 +1,5 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
 use std::fmt::{Debug, Display};
 use std::fs::{self, File};
 use std::io::{BufRead, BufReader, Write};
@@ -6,12 +8,10 @@ use std::time::Duration;
 
 use nix::sys::statfs::{statfs, CGROUP2_SUPER_MAGIC, TMPFS_MAGIC};
 use nix::unistd::Pid;
-use oci_spec::runtime::LinuxResources;
-#[cfg(any(feature = "cgroupsv2_devices", feature = "v1"))]
-use oci_spec::runtime::{
-    LinuxDevice, LinuxDeviceBuilder, LinuxDeviceCgroup, LinuxDeviceCgroupBuilder, LinuxDeviceType,
-};
+use oci_spec::runtime::{LinuxMemory, LinuxMemoryBuilder, LinuxResources};
 
+use super::capabilities::{self, CgroupCapabilities, ResourceType};
+use super::cgroups_path::{self, CgroupsPathError};
 use super::stats::Stats;
 use super::{systemd, v1, v2};
 
@@ -20,7 +20,7 @@ pub const DEFAULT_CGROUP_ROOT: &str = "/sys/fs/cgroup";
 
 #[cfg(feature = "systemd")]
 #[inline]
-fn is_true_root() -> Result<bool, WrappedIoError> {
+pub(crate) fn is_true_root() -> Result<bool, WrappedIoError> {
     if !nix::unistd::geteuid().is_root() {
         return Ok(false);
     }
@@ -43,14 +43,39 @@ pub trait CgroupManager {
     /// Removes the cgroup
     fn remove(&self) -> Result<(), Self::Error>;
 
-    /// Sets the freezer cgroup to the specified state
-    fn freeze(&self, state: FreezerState) -> Result<(), Self::Error>;
+    /// Sets the freezer cgroup to the specified state, waiting up to
+    /// [`DEFAULT_FREEZE_TIMEOUT`] for the cgroup to freeze
+    fn freeze(&self, state: FreezerState) -> Result<(), Self::Error> {
+        self.freeze_with_timeout(state, DEFAULT_FREEZE_TIMEOUT)
+    }
+
+    /// Sets the freezer cgroup to the specified state, waiting up to the
+    /// timeout for the cgroup to freeze. Freezing fails with the tasks which
+    /// did not freeze in time, and the cgroup is thawed again.
+    fn freeze_with_timeout(
+        &self,
+        state: FreezerState,
+        timeout: Duration,
+    ) -> Result<(), Self::Error>;
 
     /// Retrieve statistics for the cgroup
     fn stats(&self) -> Result<Stats, Self::Error>;
 
     /// Gets the PIDs inside the cgroup
     fn get_all_pids(&self) -> Result<Vec<Pid>, Self::Error>;
+
+    /// Probes which resource types can currently be controlled in the cgroup
+    fn capabilities(&self) -> Result<CgroupCapabilities, Self::Error>;
+
+    /// Asks the kernel to reclaim the given amount of memory from the cgroup
+    /// without changing its limits. Only supported by cgroup v2.
+    fn reclaim(&self, bytes: u64) -> Result<(), Self::Error>;
+
+    /// Kills all processes of the cgroup and its descendants at once with
+    /// cgroup.kill, so that processes which are forked meanwhile are killed
+    /// as well. Returns false if the cgroup has no cgroup.kill, on cgroup v1
+    /// and on kernels before 5.14.
+    fn kill(&self) -> Result<bool, Self::Error>;
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -63,6 +88,20 @@ pub enum AnyManagerError {
     V2(#[from] v2::manager::V2ManagerError),
 }
 
+impl AnyManagerError {
+    /// Returns true if the operation failed because the freezer controller
+    /// is not available on this host, rather than because freezing failed.
+    pub fn is_freezer_unavailable(&self) -> bool {
+        match self {
+            #[cfg(feature = "v1")]
+            AnyManagerError::V1(v1::manager::V1ManagerError::CGroupRequired(
+                v1::ControllerType::Freezer,
+            )) => true,
+            _ => false,
+        }
+    }
+}
+
 // systemd is boxed due to size lint https://rust-lang.github.io/rust-clippy/master/index.html#/large_enum_variant
 pub enum AnyCgroupManager {
     Systemd(Box<systemd::manager::Manager>),
@@ -97,11 +136,15 @@ impl CgroupManager for AnyCgroupManager {
         }
     }
 
-    fn freeze(&self, state: FreezerState) -> Result<(), Self::Error> {
+    fn freeze_with_timeout(
+        &self,
+        state: FreezerState,
+        timeout: Duration,
+    ) -> Result<(), Self::Error> {
         match self {
-            AnyCgroupManager::Systemd(m) => Ok(m.freeze(state)?),
-            AnyCgroupManager::V1(m) => Ok(m.freeze(state)?),
-            AnyCgroupManager::V2(m) => Ok(m.freeze(state)?),
+            AnyCgroupManager::Systemd(m) => Ok(m.freeze_with_timeout(state, timeout)?),
+            AnyCgroupManager::V1(m) => Ok(m.freeze_with_timeout(state, timeout)?),
+            AnyCgroupManager::V2(m) => Ok(m.freeze_with_timeout(state, timeout)?),
         }
     }
 
@@ -120,9 +163,33 @@ impl CgroupManager for AnyCgroupManager {
             AnyCgroupManager::V2(m) => Ok(m.get_all_pids()?),
         }
     }
+
+    fn capabilities(&self) -> Result<CgroupCapabilities, Self::Error> {
+        match self {
+            AnyCgroupManager::Systemd(m) => Ok(m.capabilities()?),
+            AnyCgroupManager::V1(m) => Ok(m.capabilities()?),
+            AnyCgroupManager::V2(m) => Ok(m.capabilities()?),
+        }
+    }
+
+    fn reclaim(&self, bytes: u64) -> Result<(), Self::Error> {
+        match self {
+            AnyCgroupManager::Systemd(m) => Ok(m.reclaim(bytes)?),
+            AnyCgroupManager::V1(m) => Ok(m.reclaim(bytes)?),
+            AnyCgroupManager::V2(m) => Ok(m.reclaim(bytes)?),
+        }
+    }
+
+    fn kill(&self) -> Result<bool, Self::Error> {
+        match self {
+            AnyCgroupManager::Systemd(m) => Ok(m.kill()?),
+            AnyCgroupManager::V1(m) => Ok(m.kill()?),
+            AnyCgroupManager::V2(m) => Ok(m.kill()?),
+        }
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum CgroupSetup {
     Hybrid,
     Legacy,
@@ -152,6 +219,97 @@ pub enum FreezerState {
     Thawed,
 }
 
+/// Time a cgroup may take to freeze until freezing it fails
+pub const DEFAULT_FREEZE_TIMEOUT: Duration = Duration::from_secs(10);
+
+// kernel functions frozen tasks wait in, of the v1 and the v2 freezer
+const FROZEN_WCHANS: &[&str] = &["__refrigerator", "do_freezer_trap"];
+
+/// Task of a cgroup which did not freeze
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnfrozenTask {
+    pub tid: i32,
+    pub comm: String,
+    /// State of the task as in /proc/<tid>/stat, e.g. D while it sleeps
+    /// uninterruptibly, which keeps it from freezing
+    pub state: char,
+    /// Kernel function the task waits in, none if it runs or it is hidden
+    pub wchan: Option<String>,
+}
+
+impl Display for UnfrozenTask {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} ({}, state {}", self.tid, self.comm, self.state)?;
+        if let Some(wchan) = &self.wchan {
+            write!(f, ", waiting in {}", wchan)?;
+        }
+        write!(f, ")")
+    }
+}
+
+/// Tasks which refused to freeze
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct UnfrozenTasks(pub Vec<UnfrozenTask>);
+
+impl Display for UnfrozenTasks {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.0.is_empty() {
+            return write!(f, "none found");
+        }
+        for (i, task) in self.0.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            write!(f, "{}", task)?;
+        }
+        Ok(())
+    }
+}
+
+/// Tasks of the cgroup which are not frozen, given the file listing the
+/// threads of the cgroup. Tasks which exited in the meantime are skipped.
+pub(crate) fn unfrozen_tasks(threads_file: &Path) -> UnfrozenTasks {
+    let content = match fs::read_to_string(threads_file) {
+        Ok(content) => content,
+        Err(err) => {
+            tracing::warn!(
+                ?err,
+                ?threads_file,
+                "failed to list the tasks of the cgroup"
+            );
+            return UnfrozenTasks::default();
+        }
+    };
+
+    let tasks = content
+        .lines()
+        .filter_map(|line| line.trim().parse::<i32>().ok())
+        .filter_map(|tid| {
+            // threads are not listed in /proc, but they can be looked up
+            let task = procfs::process::Process::new(tid).ok()?;
+            let stat = task.stat().ok()?;
+            let wchan = task
+                .wchan()
+                .ok()
+                .filter(|wchan| !wchan.is_empty() && wchan != "0");
+            if is_frozen(wchan.as_deref()) {
+                return None;
+            }
+            Some(UnfrozenTask {
+                tid,
+                comm: stat.comm,
+                state: stat.state,
+                wchan,
+            })
+        })
+        .collect();
+    UnfrozenTasks(tasks)
+}
+
+fn is_frozen(wchan: Option<&str>) -> bool {
+    wchan.map_or(false, |wchan| FROZEN_WCHANS.contains(&wchan))
+}
+
 /// ControllerOpt is given all cgroup controller for applying cgroup configuration.
 #[derive(Clone, Debug)]
 pub struct ControllerOpt<'a> {
@@ -165,6 +323,146 @@ pub struct ControllerOpt<'a> {
     pub freezer_state: Option<FreezerState>,
 }
 
+#[cfg(feature = "v2")]
+impl ControllerOpt<'_> {
+    /// Warns that network resources are ignored. Only the cgroup v1 net_cls
+    /// and net_prio controllers can enforce them, there is no equivalent in
+    /// cgroup v2.
+    pub(crate) fn warn_ignored_network(&self) {
+        if let Some(network) = self.resources.network() {
+            tracing::warn!(
+                class_id = ?network.class_id(),
+                priorities = ?network.priorities(),
+                "network resources are not supported by cgroup v2, ignoring them"
+            );
+        }
+    }
+}
+
+// unified files taking a limit, for which -1 means no limit as it does in
+// the rest of the runtime spec
+const UNIFIED_LIMITS: &[&str] = &[
+    "memory.high",
+    "memory.low",
+    "memory.max",
+    "memory.min",
+    "memory.swap.high",
+    "memory.swap.max",
+    "memory.zswap.max",
+    "pids.max",
+];
+
+/// Brings resources into the form in which the cgroup managers apply them,
+/// so that the same resources result in the same writes to the cgroup,
+/// whether they come from the spec on create or from an update:
+///
+/// - sections which do not request anything are dropped, an empty memory
+///   section would reset the oom control of a cgroup v1 otherwise,
+/// - a swap limit of 0 is unset, cgroup v2 would reject it along with a
+///   memory limit while cgroup v1 ignores it,
+/// - unified values are trimmed and -1 of a limit becomes `max`.
+pub fn normalize_resources(resources: &LinuxResources) -> LinuxResources {
+    let requested = capabilities::requested_resources(resources);
+    let mut normalized = resources.clone();
+
+    if !requested.contains(&ResourceType::Devices) {
+        normalized.set_devices(None);
+    }
+    match resources.memory() {
+        Some(memory) if requested.contains(&ResourceType::Memory) => {
+            let memory = match memory.swap() {
+                Some(0) => without_swap(memory),
+                _ => *memory,
+            };
+            normalized.set_memory(Some(memory));
+        }
+        _ => {
+            normalized.set_memory(None);
+        }
+    }
+    if !requested.contains(&ResourceType::Cpu) && !requested.contains(&ResourceType::CpuSet) {
+        normalized.set_cpu(None);
+    }
+    if !requested.contains(&ResourceType::BlockIo) {
+        normalized.set_block_io(None);
+    }
+    if !requested.contains(&ResourceType::HugePageLimits) {
+        normalized.set_hugepage_limits(None);
+    }
+    if !requested.contains(&ResourceType::Rdma) {
+        normalized.set_rdma(None);
+    }
+    match resources.unified() {
+        Some(unified) if requested.contains(&ResourceType::Unified) => {
+            let unified = unified
+                .iter()
+                .map(|(file, value)| {
+                    let file = file.trim();
+                    let value = match value.trim() {
+                        "-1" if UNIFIED_LIMITS.contains(&file) => "max",
+                        value => value,
+                    };
+                    (file.to_owned(), value.to_owned())
+                })
+                .collect();
+            normalized.set_unified(Some(unified));
+        }
+        _ => {
+            normalized.set_unified(None);
+        }
+    }
+
+    normalized
+}
+
+// LinuxMemory has no setters, it is rebuilt with the other values kept
+fn without_swap(memory: &LinuxMemory) -> LinuxMemory {
+    let mut builder = LinuxMemoryBuilder::default();
+    if let Some(limit) = memory.limit() {
+        builder = builder.limit(limit);
+    }
+    if let Some(reservation) = memory.reservation() {
+        builder = builder.reservation(reservation);
+    }
+    if let Some(kernel) = memory.kernel() {
+        builder = builder.kernel(kernel);
+    }
+    if let Some(kernel_tcp) = memory.kernel_tcp() {
+        builder = builder.kernel_tcp(kernel_tcp);
+    }
+    if let Some(swappiness) = memory.swappiness() {
+        builder = builder.swappiness(swappiness);
+    }
+    if let Some(disable_oom_killer) = memory.disable_oom_killer() {
+        builder = builder.disable_oom_killer(disable_oom_killer);
+    }
+    if let Some(use_hierarchy) = memory.use_hierarchy() {
+        builder = builder.use_hierarchy(use_hierarchy);
+    }
+    if let Some(check_before_update) = memory.check_before_update() {
+        builder = builder.check_before_update(check_before_update);
+    }
+    // all fields are optional, building does not fail
+    builder.build().unwrap_or(*memory)
+}
+
+/// Applies resources to the cgroup of the manager, the way both creating a
+/// container and updating its resources do. The resources are normalized
+/// first, see [`normalize_resources`].
+pub fn apply_resources<C: CgroupManager + ?Sized>(
+    cmanager: &C,
+    resources: &LinuxResources,
+) -> Result<(), C::Error> {
+    let resources = normalize_resources(resources);
+    tracing::debug!(?resources, "applying normalized resources");
+    cmanager.apply(&ControllerOpt {
+        resources: &resources,
+        disable_oom_killer: false,
+        oom_score_adj: None,
+        freezer_state: None,
+    })
+}
+
 #[derive(thiserror::Error, Debug)]
 pub enum WrappedIoError {
     #[error("failed to open {path}: {err}")]
@@ -245,15 +543,65 @@ pub fn write_cgroup_file<P: AsRef<Path>, T: ToString>(
     Ok(())
 }
 
+/// Cgroup files read ahead of time on this thread and the paths of all
+/// cgroup files read since, see [`with_prefetched`]
+struct Prefetched {
+    files: HashMap<PathBuf, String>,
+    read: Vec<PathBuf>,
+}
+
+thread_local! {
+    static PREFETCHED: RefCell<Option<Prefetched>> = RefCell::new(None);
+}
+
+/// Records a read of the cgroup file at `path` when running in
+/// [`with_prefetched`] and takes its prefetched content if there is one
+pub(crate) fn take_prefetched(path: &Path) -> Option<String> {
+    PREFETCHED.with(|prefetched| {
+        prefetched.borrow_mut().as_mut().and_then(|prefetched| {
+            prefetched.read.push(path.to_path_buf());
+            prefetched.files.remove(path)
+        })
+    })
+}
+
 #[inline]
 pub fn read_cgroup_file<P: AsRef<Path>>(path: P) -> Result<String, WrappedIoError> {
     let path = path.as_ref();
+    if let Some(content) = take_prefetched(path) {
+        return Ok(content);
+    }
+
     fs::read_to_string(path).map_err(|err| WrappedIoError::Read {
         err,
         path: path.to_path_buf(),
     })
 }
 
+/// Runs `f` with the cgroup files it reads taken from `files` if they are
+/// contained, which allows to read the files of many cgroups in a batch.
+/// Every prefetched file is used at most once. Returns the result of `f`
+/// together with the paths of all cgroup files `f` read, which are the
+/// files to prefetch for the next run.
+pub fn with_prefetched<T, F: FnOnce() -> T>(
+    files: HashMap<PathBuf, String>,
+    f: F,
+) -> (T, Vec<PathBuf>) {
+    let outer = PREFETCHED.with(|prefetched| {
+        prefetched.replace(Some(Prefetched {
+            files,
+            read: Vec::new(),
+        }))
+    });
+    let result = f();
+    let read = PREFETCHED
+        .with(|prefetched| prefetched.replace(outer))
+        .map(|prefetched| prefetched.read)
+        .unwrap_or_default();
+
+    (result, read)
+}
+
 #[derive(thiserror::Error, Debug)]
 pub enum GetCgroupSetupError {
     #[error("io error: {0}")]
@@ -310,6 +658,263 @@ pub fn get_cgroup_setup() -> Result<CgroupSetup, GetCgroupSetupError> {
     get_cgroup_setup_with_root(Path::new(DEFAULT_CGROUP_ROOT))
 }
 
+/// What the cgroup managers of one process find the same on the host. A
+/// manager examines the host itself, unless its [`CgroupConfig`] passes a
+/// host which was examined before, e.g. by a batch which creates many
+/// containers.
+#[derive(Clone, Default)]
+pub struct CgroupHost {
+    setup: Option<CgroupSetup>,
+    systemd: Option<systemd::manager::Connection>,
+}
+
+impl Debug for CgroupHost {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CgroupHost")
+            .field("setup", &self.setup)
+            .field("systemd", &self.systemd.is_some())
+            .finish()
+    }
+}
+
+impl CgroupHost {
+    /// Determines the cgroup setup of the default cgroup root
+    pub fn detect() -> Result<Self, GetCgroupSetupError> {
+        Ok(Self {
+            setup: Some(get_cgroup_setup()?),
+            systemd: None,
+        })
+    }
+
+    pub fn setup(&self) -> Option<CgroupSetup> {
+        self.setup
+    }
+
+    /// Connects to systemd once for all managers which use it. Only cgroup
+    /// v2 hosts are managed through systemd.
+    #[cfg(feature = "systemd")]
+    pub fn connect_systemd(&mut self) -> Result<(), systemd::manager::SystemdManagerError> {
+        if self.setup != Some(CgroupSetup::Unified) || !systemd::booted() {
+            return Ok(());
+        }
+
+        let use_system =
+            is_true_root().map_err(systemd::manager::SystemdManagerError::WrappedIo)?;
+        self.systemd = Some(systemd::manager::Connection::new(use_system)?);
+        Ok(())
+    }
+
+    #[cfg(not(feature = "systemd"))]
+    pub fn connect_systemd(&mut self) -> Result<(), systemd::manager::SystemdManagerError> {
+        Ok(())
+    }
+}
+
+/// Checks whether processes can be frozen through the cgroup freezer on this
+/// host. On cgroup v2 freezing is part of the core interface, while on v1 it
+/// requires the freezer controller to be mounted.
+pub fn freezer_available() -> Result<bool, GetCgroupSetupError> {
+    Ok(match get_cgroup_setup()? {
+        CgroupSetup::Unified => true,
+        CgroupSetup::Legacy | CgroupSetup::Hybrid => v1_freezer_mounted(),
+    })
+}
+
+#[cfg(feature = "v1")]
+fn v1_freezer_mounted() -> bool {
+    v1::util::get_subsystem_mount_point(&v1::ControllerType::Freezer).is_ok()
+}
+
+#[cfg(not(feature = "v1"))]
+fn v1_freezer_mounted() -> bool {
+    false
+}
+
+const CGROUP_CONTROLLERS: &str = "cgroup.controllers";
+const CGROUP_SUBTREE_CONTROL: &str = "cgroup.subtree_control";
+
+/// Controller of a cgroup v2 hierarchy and whether it reaches a cgroup. A
+/// controller can only be used in a cgroup if each of its ancestors enables
+/// it in their `cgroup.subtree_control`, i.e. delegates it to their children.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ControllerAvailability {
+    pub controller: String,
+    /// The controller can be used in the cgroup
+    pub delegated: bool,
+    /// The highest cgroup of the path whose parent does not delegate the
+    /// controller, if it is not delegated
+    pub blocked_at: Option<PathBuf>,
+}
+
+impl ControllerAvailability {
+    /// Why the controller can not be used in the cgroup, if it can't
+    pub fn reason(&self) -> Option<String> {
+        self.blocked_at.as_ref().map(|blocked_at| {
+            format!(
+                "{} controller not delegated to {}",
+                self.controller,
+                blocked_at
+                    .file_name()
+                    .map(|name| name.to_string_lossy())
+                    .unwrap_or_else(|| blocked_at.to_string_lossy())
+            )
+        })
+    }
+}
+
+/// Reports the controllers of the cgroup v2 hierarchy mounted at `root` and
+/// whether they are delegated to the cgroup at `cgroup_path`, relative to the
+/// root. The parts of the path which do not exist yet are left out, so for a
+/// cgroup which is still to be created this tells which controllers it will
+/// get.
+pub fn probe_controllers(
+    root: &Path,
+    cgroup_path: &Path,
+) -> Result<Vec<ControllerAvailability>, WrappedIoError> {
+    let read_controllers = |path: &Path| -> Result<Vec<String>, WrappedIoError> {
+        Ok(read_cgroup_file(path.join(CGROUP_CONTROLLERS))?
+            .split_whitespace()
+            .map(str::to_owned)
+            .collect())
+    };
+
+    let mut levels = Vec::new();
+    let mut current = root.to_path_buf();
+    for component in cgroup_path.components() {
+        if let std::path::Component::Normal(name) = component {
+            current.push(name);
+            if !current.join(CGROUP_CONTROLLERS).exists() {
+                break;
+            }
+            let controllers = read_controllers(&current)?;
+            levels.push((current.clone(), controllers));
+        }
+    }
+
+    Ok(read_controllers(root)?
+        .into_iter()
+        .map(|controller| {
+            let blocked_at = levels
+                .iter()
+                .find(|(_, controllers)| !controllers.contains(&controller))
+                .map(|(path, _)| path.clone());
+            ControllerAvailability {
+                controller,
+                delegated: blocked_at.is_none(),
+                blocked_at,
+            }
+        })
+        .collect())
+}
+
+/// Reports the controllers delegated to the cgroup of the calling process,
+/// below which a rootless youki creates the cgroups of its containers. None
+/// without a unified hierarchy.
+pub fn probe_own_controllers() -> Result<Option<Vec<ControllerAvailability>>, WrappedIoError> {
+    let root = Path::new(DEFAULT_CGROUP_ROOT);
+    if !root.join(CGROUP_CONTROLLERS).exists() {
+        return Ok(None);
+    }
+    let own_cgroup = read_cgroup_file("/proc/self/cgroup")?
+        .lines()
+        .find_map(|line| line.strip_prefix("0::").map(str::to_owned));
+    match own_cgroup {
+        Some(own_cgroup) => probe_controllers(root, Path::new(&own_cgroup)).map(Some),
+        None => Ok(None),
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum SubtreeControlError {
+    #[error("{reason}, it is not enabled in cgroup.subtree_control of its parent: {source}")]
+    NotDelegated {
+        reason: String,
+        source: WrappedIoError,
+    },
+    #[error("{controller} controller is not available in the cgroup v2 hierarchy, it may be used by a cgroup v1 hierarchy: {source}")]
+    NotAvailable {
+        controller: String,
+        source: WrappedIoError,
+    },
+    #[error("no permission to delegate the {controller} controller below {path:?}, the cgroup is not delegated to uid {uid}: {source}")]
+    PermissionDenied {
+        controller: String,
+        path: PathBuf,
+        uid: u32,
+        source: WrappedIoError,
+    },
+    #[error("cannot delegate the {controller} controller below {path:?}, which has processes of its own: {source}")]
+    Busy {
+        controller: String,
+        path: PathBuf,
+        source: WrappedIoError,
+    },
+    #[error(transparent)]
+    WrappedIo(#[from] WrappedIoError),
+}
+
+/// Enables the controller in `cgroup.subtree_control` of the cgroup at
+/// `path`, delegating it to its children. If the kernel refuses, the error
+/// tells why.
+pub fn enable_controller(path: &Path, controller: &str) -> Result<(), SubtreeControlError> {
+    let err =
+        match write_cgroup_file_str(path.join(CGROUP_SUBTREE_CONTROL), &format!("+{controller}")) {
+            Ok(()) => return Ok(()),
+            Err(err) => err,
+        };
+
+    let controller = controller.to_owned();
+    match err.inner().raw_os_error() {
+        // the controller is not in cgroup.controllers of the cgroup
+        Some(nix::libc::ENOENT) | Some(nix::libc::EINVAL) => {
+            // the root of the hierarchy is the highest cgroup of the path
+            let root = match path
+                .ancestors()
+                .take_while(|ancestor| ancestor.join(CGROUP_CONTROLLERS).exists())
+                .last()
+            {
+                Some(root) => root,
+                None => return Err(err.into()),
+            };
+            let cgroup_path = path.strip_prefix(root).unwrap_or(path);
+            let availability = probe_controllers(root, cgroup_path)
+                .ok()
+                .and_then(|controllers| {
+                    controllers
+                        .into_iter()
+                        .find(|availability| availability.controller == controller)
+                });
+            match availability {
+                Some(availability) => match availability.reason() {
+                    Some(reason) => Err(SubtreeControlError::NotDelegated {
+                        reason,
+                        source: err,
+                    }),
+                    None => Err(err.into()),
+                },
+                None => Err(SubtreeControlError::NotAvailable {
+                    controller,
+                    source: err,
+                }),
+            }
+        }
+        Some(nix::libc::EACCES) | Some(nix::libc::EPERM) => {
+            Err(SubtreeControlError::PermissionDenied {
+                controller,
+                path: path.to_owned(),
+                uid: nix::unistd::geteuid().as_raw(),
+                source: err,
+            })
+        }
+        Some(nix::libc::EBUSY) => Err(SubtreeControlError::Busy {
+            controller,
+            path: path.to_owned(),
+            source: err,
+        }),
+        _ => Err(err.into()),
+    }
+}
+
 #[derive(thiserror::Error, Debug)]
 pub enum CreateCgroupSetupError {
     #[error("io error: {0}")]
@@ -324,6 +929,12 @@ pub enum CreateCgroupSetupError {
     V2(#[from] v2::manager::V2ManagerError),
     #[error("systemd error: {0}")]
     Systemd(#[from] systemd::manager::SystemdManagerError),
+    #[error("cgroups path: {0}")]
+    CgroupsPath(#[from] CgroupsPathError),
+    #[error("join safely: {0}")]
+    JoinSafely(#[from] JoinSafelyError),
+    #[error("slice {0} of the cgroups path does not exist")]
+    SliceNotFound(PathBuf),
 }
 
 #[derive(Clone)]
@@ -331,6 +942,14 @@ pub struct CgroupConfig {
     pub cgroup_path: PathBuf,
     pub systemd_cgroup: bool,
     pub container_name: String,
+    /// Place the processes in an `init` leaf below the cgroup, only
+    /// supported by the cgroup v2 manager
+    pub init_leaf: bool,
+    /// The host as examined before, if it was
+    pub host: CgroupHost,
+    /// Root directory of the container states, recorded with the unit of
+    /// the systemd manager, see [`crate::systemd::units`]
+    pub state_root: Option<PathBuf>,
 }
 
 // Create any cgroup manager with customize root path. If root_path provided
@@ -344,7 +963,11 @@ pub fn create_cgroup_manager_with_root(
         None => Path::new(DEFAULT_CGROUP_ROOT),
     };
 
-    let cgroup_setup = get_cgroup_setup_with_root(root).map_err(|err| match err {
+    let cgroup_setup = match config.host.setup {
+        Some(setup) if root == Path::new(DEFAULT_CGROUP_ROOT) => Ok(setup),
+        _ => get_cgroup_setup_with_root(root),
+    };
+    let cgroup_setup = cgroup_setup.map_err(|err| match err {
         GetCgroupSetupError::WrappedIo(err) => CreateCgroupSetupError::WrappedIo(err),
         GetCgroupSetupError::NonDefault => CreateCgroupSetupError::NonDefault,
         GetCgroupSetupError::FailedToDetect => CreateCgroupSetupError::FailedToDetect,
@@ -353,21 +976,57 @@ pub fn create_cgroup_manager_with_root(
 
     match cgroup_setup {
         CgroupSetup::Legacy | CgroupSetup::Hybrid => {
-            Ok(create_v1_cgroup_manager(cgroup_path)?.any())
+            if config.init_leaf {
+                tracing::warn!("init leaf is only supported with cgroup v2, ignoring it");
+            }
+            let cgroup_path = cgroups_path::cgroupfs_path(cgroup_path)?;
+            Ok(create_v1_cgroup_manager(&cgroup_path)?.any())
         }
         CgroupSetup::Unified => {
-            // ref https://github.com/opencontainers/runtime-spec/blob/main/config-linux.md#cgroups-path
-            if cgroup_path.is_absolute() || !config.systemd_cgroup {
-                return Ok(create_v2_cgroup_manager(root, cgroup_path)?.any());
+            if config.systemd_cgroup && cgroups_path::is_systemd_path(cgroup_path) {
+                if config.init_leaf {
+                    tracing::warn!(
+                        "init leaf is not supported by the systemd manager, ignoring it"
+                    );
+                }
+                return Ok(create_systemd_cgroup_manager(
+                    root,
+                    cgroup_path,
+                    config.container_name.as_str(),
+                    config.host.systemd.clone(),
+                )?
+                .with_state_root(config.state_root)
+                .any());
             }
-            Ok(
-                create_systemd_cgroup_manager(root, cgroup_path, config.container_name.as_str())?
-                    .any(),
-            )
+
+            let cgroup_path = cgroups_path::cgroupfs_path(cgroup_path)?;
+            ensure_slice_exists(root, &config.cgroup_path, &cgroup_path)?;
+            Ok(create_v2_cgroup_manager(root, &cgroup_path)?
+                .with_init_leaf(config.init_leaf)
+                .any())
         }
     }
 }
 
+/// Without systemd nobody creates the slice a cgroups path of systemd form
+/// names, so the cgroup would end up in a hierarchy systemd does not know.
+fn ensure_slice_exists(
+    root: &Path,
+    cgroups_path: &Path,
+    cgroup_path: &Path,
+) -> Result<(), CreateCgroupSetupError> {
+    if !cgroups_path::is_systemd_path(cgroups_path) {
+        return Ok(());
+    }
+
+    let slice = cgroup_path.parent().unwrap_or_else(|| Path::new("/"));
+    let slice_path = root.to_path_buf().join_safely(slice)?;
+    if !slice_path.exists() {
+        return Err(CreateCgroupSetupError::SliceNotFound(slice_path));
+    }
+    Ok(())
+}
+
 pub fn create_cgroup_manager(
     config: CgroupConfig,
 ) -> Result<AnyCgroupManager, CreateCgroupSetupError> {
@@ -411,7 +1070,20 @@ fn create_systemd_cgroup_manager(
     root_path: &Path,
     cgroup_path: &Path,
     container_name: &str,
+    connection: Option<systemd::manager::Connection>,
 ) -> Result<systemd::manager::Manager, systemd::manager::SystemdManagerError> {
+    if let Some(connection) = connection {
+        tracing::info!(
+            "systemd cgroup manager with shared connection to system bus {} will be used",
+            connection.is_system()
+        );
+        return systemd::manager::Manager::with_connection(
+            root_path.into(),
+            cgroup_path.to_owned(),
+            container_name.into(),
+            connection,
+        );
+    }
     if !systemd::booted() {
         panic!(
             "systemd cgroup flag passed, but systemd support for managing cgroups is not available"
@@ -437,6 +1109,7 @@ fn create_systemd_cgroup_manager(
     _root_path: &Path,
     _cgroup_path: &Path,
     _container_name: &str,
+    _connection: Option<systemd::manager::Connection>,
 ) -> Result<systemd::manager::Manager, systemd::manager::SystemdManagerError> {
     Err(systemd::manager::SystemdManagerError::NotEnabled)
 }
@@ -508,112 +1181,6 @@ impl PathBufExt for PathBuf {
     }
 }
 
-#[cfg(any(feature = "cgroupsv2_devices", feature = "v1"))]
-pub(crate) fn default_allow_devices() -> Vec<LinuxDeviceCgroup> {
-    vec![
-        LinuxDeviceCgroupBuilder::default()
-            .allow(true)
-            .typ(LinuxDeviceType::C)
-            .access("m")
-            .build()
-            .unwrap(),
-        LinuxDeviceCgroupBuilder::default()
-            .allow(true)
-            .typ(LinuxDeviceType::B)
-            .access("m")
-            .build()
-            .unwrap(),
-        // /dev/console
-        LinuxDeviceCgroupBuilder::default()
-            .allow(true)
-            .typ(LinuxDeviceType::C)
-            .major(5)
-            .minor(1)
-            .access("rwm")
-            .build()
-            .unwrap(),
-        // /dev/pts
-        LinuxDeviceCgroupBuilder::default()
-            .allow(true)
-            .typ(LinuxDeviceType::C)
-            .major(136)
-            .access("rwm")
-            .build()
-            .unwrap(),
-        LinuxDeviceCgroupBuilder::default()
-            .allow(true)
-            .typ(LinuxDeviceType::C)
-            .major(5)
-            .minor(2)
-            .access("rwm")
-            .build()
-            .unwrap(),
-        // tun/tap
-        LinuxDeviceCgroupBuilder::default()
-            .allow(true)
-            .typ(LinuxDeviceType::C)
-            .major(10)
-            .minor(200)
-            .access("rwm")
-            .build()
-            .unwrap(),
-    ]
-}
-
-#[cfg(any(feature = "cgroupsv2_devices", feature = "v1"))]
-pub(crate) fn default_devices() -> Vec<LinuxDevice> {
-    vec![
-        LinuxDeviceBuilder::default()
-            .path(PathBuf::from("/dev/null"))
-            .typ(LinuxDeviceType::C)
-            .major(1)
-            .minor(3)
-            .file_mode(0o066u32)
-            .build()
-            .unwrap(),
-        LinuxDeviceBuilder::default()
-            .path(PathBuf::from("/dev/zero"))
-            .typ(LinuxDeviceType::C)
-            .major(1)
-            .minor(5)
-            .file_mode(0o066u32)
-            .build()
-            .unwrap(),
-        LinuxDeviceBuilder::default()
-            .path(PathBuf::from("/dev/full"))
-            .typ(LinuxDeviceType::C)
-            .major(1)
-            .minor(7)
-            .file_mode(0o066u32)
-            .build()
-            .unwrap(),
-        LinuxDeviceBuilder::default()
-            .path(PathBuf::from("/dev/tty"))
-            .typ(LinuxDeviceType::C)
-            .major(5)
-            .minor(0)
-            .file_mode(0o066u32)
-            .build()
-            .unwrap(),
-        LinuxDeviceBuilder::default()
-            .path(PathBuf::from("/dev/urandom"))
-            .typ(LinuxDeviceType::C)
-            .major(1)
-            .minor(9)
-            .file_mode(0o066u32)
-            .build()
-            .unwrap(),
-        LinuxDeviceBuilder::default()
-            .path(PathBuf::from("/dev/random"))
-            .typ(LinuxDeviceType::C)
-            .major(1)
-            .minor(8)
-            .file_mode(0o066u32)
-            .build()
-            .unwrap(),
-    ]
-}
-
 /// Attempts to delete the path the requested number of times.
 pub(crate) fn delete_with_retry<P: AsRef<Path>, L: Into<Option<Duration>>>(
     path: P,
@@ -728,3 +1295,180 @@ impl Display for MustBePowerOfTwo {
         f.write_str("page size must be in the format of 2^(integer)")
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the resources of a config.json and of an update which mean the same
+    const SPEC_RESOURCES: &str = r#"{
+        "memory": {"limit": 1073741824, "swap": 0},
+        "pids": {"limit": 100},
+        "blockIO": {},
+        "hugepageLimits": [],
+        "unified": {"memory.swap.high": "-1", " memory.zswap.max ": "0\n"}
+    }"#;
+    const UPDATE_RESOURCES: &str = r#"{
+        "memory": {"limit": 1073741824},
+        "pids": {"limit": 100},
+        "unified": {"memory.swap.high": "max", "memory.zswap.max": "0"}
+    }"#;
+
+    #[test]
+    fn test_normalize_resources() {
+        let spec: LinuxResources = serde_json::from_str(SPEC_RESOURCES).unwrap();
+        let update: LinuxResources = serde_json::from_str(UPDATE_RESOURCES).unwrap();
+        assert_ne!(spec, update);
+        assert_eq!(normalize_resources(&spec), normalize_resources(&update));
+        assert_eq!(normalize_resources(&update), update);
+
+        let normalized = normalize_resources(&spec);
+        assert_eq!(normalized.memory().unwrap().swap(), None);
+        assert_eq!(normalized.memory().unwrap().limit(), Some(1073741824));
+        assert!(normalized.block_io().is_none());
+        assert!(normalized.hugepage_limits().is_none());
+
+        // -1 only means no limit for limits
+        let misc: LinuxResources =
+            serde_json::from_str(r#"{"unified": {"cpu.weight.nice": "-1"}}"#).unwrap();
+        assert_eq!(normalize_resources(&misc), misc);
+        assert_eq!(
+            normalize_resources(&LinuxResources::default()),
+            LinuxResources::default()
+        );
+    }
+
+    #[cfg(feature = "v2")]
+    #[test]
+    fn test_apply_resources_golden() {
+        use std::collections::BTreeMap;
+
+        const FILES: &[&str] = &[
+            "memory.max",
+            "memory.low",
+            "memory.swap.max",
+            "memory.swap.high",
+            "memory.zswap.max",
+            "pids.max",
+        ];
+        let golden: BTreeMap<&str, String> = [
+            ("memory.low", ""),
+            ("memory.max", "1073741824"),
+            ("memory.swap.high", "max"),
+            ("memory.swap.max", ""),
+            ("memory.zswap.max", "0"),
+            ("pids.max", "100"),
+        ]
+        .into_iter()
+        .map(|(file, value)| (file, value.to_owned()))
+        .collect();
+
+        for input in [SPEC_RESOURCES, UPDATE_RESOURCES] {
+            let root = tempfile::tempdir().unwrap();
+            fs::write(root.path().join("cgroup.controllers"), "memory pids").unwrap();
+            let cgroup = root.path().join("container");
+            fs::create_dir(&cgroup).unwrap();
+            for file in FILES {
+                fs::write(cgroup.join(file), "").unwrap();
+            }
+
+            let manager =
+                v2::manager::Manager::new(root.path().to_path_buf(), "container".into()).unwrap();
+            let resources: LinuxResources = serde_json::from_str(input).unwrap();
+            apply_resources(&manager, &resources).unwrap();
+
+            let written: BTreeMap<&str, String> = FILES
+                .iter()
+                .map(|file| (*file, fs::read_to_string(cgroup.join(file)).unwrap()))
+                .collect();
+            assert_eq!(written, golden, "resources {input}");
+        }
+    }
+
+    #[test]
+    fn test_unfrozen_tasks() {
+        let tmp = tempfile::tempdir().unwrap();
+        let threads_file = tmp.path().join("cgroup.threads");
+        // the running test is not frozen, a task which exited is skipped
+        let tid = nix::unistd::gettid().as_raw();
+        fs::write(&threads_file, format!("{}\n{}\n", tid, i32::MAX)).unwrap();
+
+        let tasks = unfrozen_tasks(&threads_file);
+        assert_eq!(tasks.0.len(), 1);
+        assert_eq!(tasks.0[0].tid, tid);
+        assert!(tasks.to_string().starts_with(&format!("{} (", tid)));
+
+        assert!(is_frozen(Some("do_freezer_trap")));
+        assert!(is_frozen(Some("__refrigerator")));
+        assert!(!is_frozen(Some("pipe_read")));
+        assert!(!is_frozen(None));
+        assert_eq!(UnfrozenTasks::default().to_string(), "none found");
+    }
+
+    #[test]
+    fn test_with_prefetched() {
+        let tmp = tempfile::tempdir().unwrap();
+        let on_disk = tmp.path().join("pids.current");
+        let prefetched = tmp.path().join("pids.max");
+        fs::write(&on_disk, "3\n").unwrap();
+        fs::write(&prefetched, "max\n").unwrap();
+
+        let files = HashMap::from([(prefetched.clone(), "10\n".to_owned())]);
+        let (contents, read) = with_prefetched(files, || {
+            [&prefetched, &on_disk, &prefetched]
+                .iter()
+                .map(|path| read_cgroup_file(path).unwrap())
+                .collect::<Vec<_>>()
+        });
+        // prefetched contents are only used once
+        assert_eq!(contents, ["10\n", "3\n", "max\n"]);
+        assert_eq!(read, [prefetched.clone(), on_disk, prefetched.clone()]);
+        assert_eq!(read_cgroup_file(&prefetched).unwrap(), "max\n");
+    }
+
+    #[test]
+    fn test_probe_controllers() {
+        let root = tempfile::tempdir().unwrap();
+        let user_slice = root.path().join("user.slice");
+        let service = user_slice.join("user@1000.service");
+        fs::create_dir_all(&service).unwrap();
+        fs::write(root.path().join(CGROUP_CONTROLLERS), "cpu memory pids\n").unwrap();
+        fs::write(user_slice.join(CGROUP_CONTROLLERS), "cpu memory pids\n").unwrap();
+        fs::write(service.join(CGROUP_CONTROLLERS), "memory pids\n").unwrap();
+
+        // the container cgroup does not exist yet
+        let controllers = probe_controllers(
+            root.path(),
+            Path::new("/user.slice/user@1000.service/app.slice/youki-1"),
+        )
+        .unwrap();
+        assert_eq!(
+            controllers,
+            vec![
+                ControllerAvailability {
+                    controller: "cpu".to_owned(),
+                    delegated: false,
+                    blocked_at: Some(service.clone()),
+                },
+                ControllerAvailability {
+                    controller: "memory".to_owned(),
+                    delegated: true,
+                    blocked_at: None,
+                },
+                ControllerAvailability {
+                    controller: "pids".to_owned(),
+                    delegated: true,
+                    blocked_at: None,
+                },
+            ]
+        );
+        assert_eq!(
+            controllers[0].reason().as_deref(),
+            Some("cpu controller not delegated to user@1000.service")
+        );
+        assert_eq!(controllers[1].reason(), None);
+
+        let controllers = probe_controllers(root.path(), Path::new("user.slice")).unwrap();
+        assert!(controllers.iter().all(|c| c.delegated));
+    }
+}
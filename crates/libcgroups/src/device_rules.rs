@@ -0,0 +1,282 @@
+//! Device cgroup rules shared by the devices controller of cgroup v1 and the
+//! eBPF program generated for cgroup v2. The rules of the spec are brought
+//! into a canonical form first and the implicit default rules of runc are
+//! appended to them, so both cgroup versions enforce the same device access.
+use oci_spec::runtime::{LinuxDeviceCgroup, LinuxDeviceCgroupBuilder, LinuxDeviceType};
+
+#[derive(thiserror::Error, Debug)]
+pub enum DeviceRuleError {
+    #[error("invalid access {access:?} in device rule {rule}")]
+    InvalidAccess { access: String, rule: String },
+    #[error("device type {0:?} can not be restricted by the device cgroup")]
+    UnsupportedType(LinuxDeviceType),
+}
+
+const ACCESS_ALL: &str = "rwm";
+
+/// Returns the canonical form of a rule, or None if the rule matches no
+/// access at all:
+/// - a negative major or minor number is a wildcard, like a missing one
+/// - rules of type `a` match every device and every access
+/// - unbuffered character devices are character devices to the kernel
+/// - access is deduplicated and ordered as `rwm`
+pub fn canonicalize(
+    rule: &LinuxDeviceCgroup,
+) -> Result<Option<LinuxDeviceCgroup>, DeviceRuleError> {
+    let mut canonical = rule.clone();
+    let typ = match rule.typ().unwrap_or_default() {
+        LinuxDeviceType::A => {
+            canonical.set_typ(Some(LinuxDeviceType::A));
+            canonical.set_major(None);
+            canonical.set_minor(None);
+            canonical.set_access(Some(ACCESS_ALL.to_owned()));
+            return Ok(Some(canonical));
+        }
+        LinuxDeviceType::B => LinuxDeviceType::B,
+        LinuxDeviceType::C | LinuxDeviceType::U => LinuxDeviceType::C,
+        typ @ LinuxDeviceType::P => return Err(DeviceRuleError::UnsupportedType(typ)),
+    };
+
+    let access = rule.access().as_deref().unwrap_or_default();
+    if access.chars().any(|c| !ACCESS_ALL.contains(c)) {
+        return Err(DeviceRuleError::InvalidAccess {
+            access: access.to_owned(),
+            rule: rule.to_string(),
+        });
+    }
+    let access: String = ACCESS_ALL.chars().filter(|c| access.contains(*c)).collect();
+    if access.is_empty() {
+        return Ok(None);
+    }
+
+    canonical.set_typ(Some(typ));
+    canonical.set_major(rule.major().filter(|major| *major >= 0));
+    canonical.set_minor(rule.minor().filter(|minor| *minor >= 0));
+    canonical.set_access(Some(access));
+    Ok(Some(canonical))
+}
+
+/// The rules runc always allows in addition to the ones of the spec: mknod
+/// of any device, the default devices created in every container, the
+/// console and ptys as well as tun/tap.
+pub fn default_rules() -> Vec<LinuxDeviceCgroup> {
+    [
+        (LinuxDeviceType::C, None, None, "m"),
+        (LinuxDeviceType::B, None, None, "m"),
+        // /dev/null
+        (LinuxDeviceType::C, Some(1), Some(3), ACCESS_ALL),
+        // /dev/random
+        (LinuxDeviceType::C, Some(1), Some(8), ACCESS_ALL),
+        // /dev/full
+        (LinuxDeviceType::C, Some(1), Some(7), ACCESS_ALL),
+        // /dev/tty
+        (LinuxDeviceType::C, Some(5), Some(0), ACCESS_ALL),
+        // /dev/zero
+        (LinuxDeviceType::C, Some(1), Some(5), ACCESS_ALL),
+        // /dev/urandom
+        (LinuxDeviceType::C, Some(1), Some(9), ACCESS_ALL),
+        // /dev/console
+        (LinuxDeviceType::C, Some(5), Some(1), ACCESS_ALL),
+        // /dev/pts
+        (LinuxDeviceType::C, Some(136), None, ACCESS_ALL),
+        // /dev/ptmx
+        (LinuxDeviceType::C, Some(5), Some(2), ACCESS_ALL),
+        // tun/tap
+        (LinuxDeviceType::C, Some(10), Some(200), ACCESS_ALL),
+    ]
+    .into_iter()
+    .map(|(typ, major, minor, access)| {
+        let mut rule = LinuxDeviceCgroupBuilder::default()
+            .allow(true)
+            .typ(typ)
+            .access(access)
+            .build()
+            .unwrap();
+        rule.set_major(major);
+        rule.set_minor(minor);
+        rule
+    })
+    .collect()
+}
+
+/// Returns the canonical rules of the spec followed by the default rules,
+/// in the order they have to be applied
+pub fn rules_with_defaults(
+    spec_rules: Option<&[LinuxDeviceCgroup]>,
+) -> Result<Vec<LinuxDeviceCgroup>, DeviceRuleError> {
+    let mut rules = Vec::new();
+    for rule in spec_rules.unwrap_or_default() {
+        if let Some(rule) = canonicalize(rule)? {
+            rules.push(rule);
+        }
+    }
+    rules.extend(default_rules());
+
+    Ok(rules)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(
+        allow: bool,
+        typ: Option<LinuxDeviceType>,
+        major: Option<i64>,
+        minor: Option<i64>,
+        access: Option<&str>,
+    ) -> LinuxDeviceCgroup {
+        let mut rule = LinuxDeviceCgroup::default();
+        rule.set_allow(allow);
+        rule.set_typ(typ);
+        rule.set_major(major);
+        rule.set_minor(minor);
+        rule.set_access(access.map(str::to_owned));
+        rule
+    }
+
+    #[test]
+    fn test_canonicalize() {
+        use LinuxDeviceType::*;
+
+        let cases = [
+            // already canonical
+            (
+                rule(true, Some(C), Some(1), Some(3), Some("rwm")),
+                Some("c 1:3 rwm"),
+            ),
+            (
+                rule(true, Some(B), Some(8), None, Some("r")),
+                Some("b 8:* r"),
+            ),
+            // negative numbers are wildcards
+            (
+                rule(true, Some(C), Some(-1), Some(-1), Some("m")),
+                Some("c *:* m"),
+            ),
+            (
+                rule(false, Some(B), Some(8), Some(-1), Some("w")),
+                Some("b 8:* w"),
+            ),
+            (
+                rule(true, Some(C), Some(-5), Some(2), Some("r")),
+                Some("c *:2 r"),
+            ),
+            // access is deduplicated and ordered
+            (
+                rule(true, Some(C), Some(1), Some(5), Some("mwrrw")),
+                Some("c 1:5 rwm"),
+            ),
+            (
+                rule(true, Some(C), Some(1), Some(5), Some("wm")),
+                Some("c 1:5 wm"),
+            ),
+            // type a ignores numbers and access, also when the type is missing
+            (
+                rule(false, Some(A), Some(10), Some(200), Some("m")),
+                Some("a *:* rwm"),
+            ),
+            (rule(false, None, None, None, None), Some("a *:* rwm")),
+            (
+                rule(true, Some(A), Some(-1), None, Some("")),
+                Some("a *:* rwm"),
+            ),
+            // unbuffered character devices are character devices
+            (
+                rule(true, Some(U), Some(4), Some(64), Some("rw")),
+                Some("c 4:64 rw"),
+            ),
+            // rules without access match nothing
+            (rule(true, Some(C), Some(1), Some(3), None), None),
+            (rule(false, Some(B), None, None, Some("")), None),
+        ];
+
+        for (input, expected) in cases {
+            let canonical = canonicalize(&input).unwrap();
+            assert_eq!(
+                canonical.as_ref().map(|rule| rule.to_string()).as_deref(),
+                expected,
+                "canonical form of {input:?}"
+            );
+            if let Some(canonical) = canonical {
+                assert_eq!(canonical.allow(), input.allow());
+                // canonicalizing is idempotent
+                assert_eq!(canonicalize(&canonical).unwrap(), Some(canonical));
+            }
+        }
+    }
+
+    #[test]
+    fn test_canonicalize_invalid() {
+        assert!(matches!(
+            canonicalize(&rule(
+                true,
+                Some(LinuxDeviceType::C),
+                Some(1),
+                Some(3),
+                Some("rwx")
+            )),
+            Err(DeviceRuleError::InvalidAccess { .. })
+        ));
+        assert!(matches!(
+            canonicalize(&rule(
+                true,
+                Some(LinuxDeviceType::P),
+                None,
+                None,
+                Some("rwm")
+            )),
+            Err(DeviceRuleError::UnsupportedType(LinuxDeviceType::P))
+        ));
+    }
+
+    #[test]
+    fn test_default_rules() {
+        let rules: Vec<String> = default_rules().iter().map(|r| r.to_string()).collect();
+        assert_eq!(
+            rules,
+            [
+                "c *:* m",
+                "b *:* m",
+                "c 1:3 rwm",
+                "c 1:8 rwm",
+                "c 1:7 rwm",
+                "c 5:0 rwm",
+                "c 1:5 rwm",
+                "c 1:9 rwm",
+                "c 5:1 rwm",
+                "c 136:* rwm",
+                "c 5:2 rwm",
+                "c 10:200 rwm",
+            ]
+        );
+        for rule in default_rules() {
+            assert!(rule.allow());
+            assert_eq!(canonicalize(&rule).unwrap(), Some(rule));
+        }
+    }
+
+    #[test]
+    fn test_rules_with_defaults() {
+        let spec_rules = [
+            rule(false, None, None, None, Some("rwm")),
+            rule(true, Some(LinuxDeviceType::C), Some(1), Some(-1), None),
+            rule(
+                true,
+                Some(LinuxDeviceType::B),
+                Some(8),
+                Some(-1),
+                Some("rw"),
+            ),
+        ];
+
+        let rules = rules_with_defaults(Some(&spec_rules)).unwrap();
+        assert_eq!(rules.len(), 2 + default_rules().len());
+        assert_eq!(rules[0].to_string(), "a *:* rwm");
+        assert!(!rules[0].allow());
+        assert_eq!(rules[1].to_string(), "b 8:* rw");
+        assert_eq!(rules[2..], default_rules());
+
+        assert_eq!(rules_with_defaults(None).unwrap(), default_rules());
+    }
+}
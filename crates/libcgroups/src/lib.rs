@@ -12,7 +12,15 @@ extern crate mockall;
 
 mod test;
 
+pub mod admission;
+pub mod capabilities;
+pub mod cgroups_path;
 pub mod common;
+#[cfg(any(feature = "cgroupsv2_devices", feature = "v1"))]
+pub mod device_rules;
+pub mod namespace;
+#[cfg(any(feature = "v1", feature = "v2"))]
+pub mod rdma;
 pub mod stats;
 #[cfg(feature = "systemd")]
 pub mod systemd;
@@ -30,3 +38,5 @@ pub mod v2;
 #[cfg(not(feature = "v2"))]
 #[path = "stub/v2/mod.rs"]
 pub mod v2;
+
+pub use admission::fit;
@@ -0,0 +1,165 @@
+//! Paths of cgroups in a cgroup namespace. `/proc/<pid>/cgroup` shows the
+//! cgroups of a process relative to the root of the cgroup namespace of the
+//! reader, so the paths youki reads are host paths, while a process in a
+//! container with a cgroup namespace sees the same cgroups relative to the
+//! root of its own namespace.
+//!
+//! The root of a cgroup namespace is the cgroup its creator was in when it
+//! created the namespace. The kernel does not expose it to other namespaces,
+//! so the cgroups of the creator are recorded with [`process_cgroups`] and
+//! passed to [`cgroup_paths`] later on.
+use std::path::{Path, PathBuf};
+
+use procfs::process::Process;
+use procfs::ProcError;
+use serde::{Deserialize, Serialize};
+
+#[derive(thiserror::Error, Debug)]
+pub enum NamespaceError {
+    #[error("failed to read the cgroups of process {pid}")]
+    Read { pid: i32, source: ProcError },
+}
+
+/// Cgroup of a process in one hierarchy
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProcessCgroup {
+    /// Controllers of the hierarchy, empty for the unified hierarchy
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub controllers: Vec<String>,
+    /// Path relative to the root of the hierarchy in the cgroup namespace of
+    /// the reader
+    pub path: PathBuf,
+}
+
+/// Path of a cgroup on the host and in the cgroup namespace of a container
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CgroupPaths {
+    /// Controllers of the hierarchy, empty for the unified hierarchy
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub controllers: Vec<String>,
+    /// Path relative to the root of the hierarchy in the cgroup namespace of
+    /// youki
+    pub host: PathBuf,
+    /// Path relative to the root of the cgroup namespace. None without a
+    /// cgroup namespace, or for a cgroup outside of it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub namespace: Option<PathBuf>,
+}
+
+/// Cgroups of the process `pid`, in the order of `/proc/<pid>/cgroup`
+pub fn process_cgroups(pid: i32) -> Result<Vec<ProcessCgroup>, NamespaceError> {
+    let cgroups = Process::new(pid)
+        .and_then(|process| process.cgroups())
+        .map_err(|source| {
+            tracing::error!(?source, pid, "failed to read cgroups of process");
+            NamespaceError::Read { pid, source }
+        })?;
+
+    Ok(cgroups
+        .0
+        .into_iter()
+        .map(|cgroup| ProcessCgroup {
+            controllers: cgroup.controllers,
+            path: PathBuf::from(cgroup.pathname),
+        })
+        .collect())
+}
+
+/// Path of the cgroup `host` relative to the namespace root `root`, None if
+/// the cgroup is outside of the namespace
+pub fn namespace_path(host: &Path, root: &Path) -> Option<PathBuf> {
+    host.strip_prefix(root)
+        .ok()
+        .map(|relative| Path::new("/").join(relative))
+}
+
+/// Host and namespace paths of the cgroups `current`. `roots` are the cgroups
+/// the creator of the cgroup namespace was in, None without a namespace.
+pub fn cgroup_paths(
+    current: &[ProcessCgroup],
+    roots: Option<&[ProcessCgroup]>,
+) -> Vec<CgroupPaths> {
+    current
+        .iter()
+        .map(|cgroup| {
+            let root = roots.and_then(|roots| {
+                roots
+                    .iter()
+                    .find(|root| root.controllers == cgroup.controllers)
+            });
+            CgroupPaths {
+                controllers: cgroup.controllers.clone(),
+                host: cgroup.path.clone(),
+                namespace: root.and_then(|root| namespace_path(&cgroup.path, &root.path)),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cgroup(controllers: &[&str], path: &str) -> ProcessCgroup {
+        ProcessCgroup {
+            controllers: controllers.iter().map(|c| c.to_string()).collect(),
+            path: PathBuf::from(path),
+        }
+    }
+
+    #[test]
+    fn test_namespace_path() {
+        let root = Path::new("/youki/abc/init");
+        assert_eq!(namespace_path(root, root), Some(PathBuf::from("/")));
+        assert_eq!(
+            namespace_path(Path::new("/youki/abc/init/jobs"), root),
+            Some(PathBuf::from("/jobs"))
+        );
+        assert_eq!(namespace_path(Path::new("/youki/abc/init2"), root), None);
+        assert_eq!(namespace_path(Path::new("/youki/abc"), root), None);
+    }
+
+    #[test]
+    fn test_cgroup_paths() {
+        let roots = [
+            cgroup(&[], "/youki/abc"),
+            cgroup(&["cpu", "cpuacct"], "/abc"),
+        ];
+        let current = [
+            cgroup(&[], "/youki/abc/jobs"),
+            cgroup(&["cpu", "cpuacct"], "/abc"),
+            cgroup(&["memory"], "/abc"),
+        ];
+
+        assert_eq!(
+            cgroup_paths(&current, Some(&roots)),
+            vec![
+                CgroupPaths {
+                    controllers: vec![],
+                    host: PathBuf::from("/youki/abc/jobs"),
+                    namespace: Some(PathBuf::from("/jobs")),
+                },
+                CgroupPaths {
+                    controllers: vec!["cpu".to_owned(), "cpuacct".to_owned()],
+                    host: PathBuf::from("/abc"),
+                    namespace: Some(PathBuf::from("/")),
+                },
+                CgroupPaths {
+                    controllers: vec!["memory".to_owned()],
+                    host: PathBuf::from("/abc"),
+                    namespace: None,
+                },
+            ]
+        );
+        assert!(cgroup_paths(&current, None)
+            .iter()
+            .all(|paths| paths.namespace.is_none()));
+    }
+
+    #[test]
+    fn test_process_cgroups() {
+        let cgroups = process_cgroups(std::process::id() as i32).unwrap();
+        assert!(!cgroups.is_empty());
+        assert!(cgroups.iter().all(|cgroup| cgroup.path.is_absolute()));
+    }
+}
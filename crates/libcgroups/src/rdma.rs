@@ -0,0 +1,211 @@
+//! The rdma controller limits the RDMA resources of the devices of a host,
+//! the handles of a host channel adapter (HCA) and the HCA objects, e.g.
+//! queue pairs and memory regions. cgroup v1 and v2 share its interface:
+//! `rdma.max` and `rdma.current` have a line per device in the nested keyed
+//! format, e.g. `mlx5_0 hca_handle=2 hca_object=2000`, and a write changes
+//! the limits of a single device.
+//!
+//! kernel doc: https://www.kernel.org/doc/html/latest/admin-guide/cgroup-v1/rdma.html
+use std::collections::HashMap;
+use std::path::Path;
+
+use oci_spec::runtime::LinuxRdma;
+
+use crate::common::{self, WrappedIoError};
+use crate::stats::{CgroupDir, ParseNestedKeyedDataError, RdmaStats};
+
+pub(crate) const CGROUP_RDMA_MAX: &str = "rdma.max";
+const CGROUP_RDMA_CURRENT: &str = "rdma.current";
+
+const HCA_HANDLE: &str = "hca_handle";
+const HCA_OBJECT: &str = "hca_object";
+
+#[derive(thiserror::Error, Debug)]
+pub enum RdmaError {
+    #[error("io error: {0}")]
+    WrappedIo(#[from] WrappedIoError),
+    #[error("while parsing stat table: {0}")]
+    ParseNestedKeyedData(#[from] ParseNestedKeyedDataError),
+    #[error("invalid rdma entry {entry:?} of device {device}")]
+    InvalidEntry { device: String, entry: String },
+}
+
+/// Counts of the resources of a device, None stands for `max`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct Resources {
+    hca_handles: Option<u32>,
+    hca_objects: Option<u32>,
+}
+
+/// Writes the limits of the devices, sorted by name. A resource without a
+/// limit in the spec keeps the limit of the cgroup.
+pub fn apply(cgroup_path: &Path, rdma: &HashMap<String, LinuxRdma>) -> Result<(), RdmaError> {
+    let mut devices: Vec<&String> = rdma.keys().collect();
+    devices.sort();
+    for device in devices {
+        if let Some(line) = limit_line(device, &rdma[device]) {
+            tracing::debug!(device, limit = %line, "set rdma limit");
+            common::write_cgroup_file(cgroup_path.join(CGROUP_RDMA_MAX), line)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn limit_line(device: &str, limit: &LinuxRdma) -> Option<String> {
+    let mut line = device.to_owned();
+    if let Some(hca_handles) = limit.hca_handles() {
+        line.push_str(&format!(" {HCA_HANDLE}={hca_handles}"));
+    }
+    if let Some(hca_objects) = limit.hca_objects() {
+        line.push_str(&format!(" {HCA_OBJECT}={hca_objects}"));
+    }
+
+    if line.len() == device.len() {
+        None
+    } else {
+        Some(line)
+    }
+}
+
+/// Usage and limits of the devices the cgroup knows, none if the controller
+/// is not enabled
+pub fn stats(dir: &mut CgroupDir) -> Result<HashMap<String, RdmaStats>, RdmaError> {
+    if !dir.exists(CGROUP_RDMA_CURRENT) {
+        return Ok(HashMap::new());
+    }
+
+    let mut stats = HashMap::new();
+    for (device, fields) in dir.nested_keyed(CGROUP_RDMA_CURRENT)? {
+        let usage = parse_resources(&device, &fields)?;
+        let device_stats = RdmaStats {
+            hca_handles: usage.hca_handles.unwrap_or_default(),
+            hca_objects: usage.hca_objects.unwrap_or_default(),
+            ..Default::default()
+        };
+        stats.insert(device, device_stats);
+    }
+
+    // the root cgroup has no limits
+    if dir.exists(CGROUP_RDMA_MAX) {
+        for (device, fields) in dir.nested_keyed(CGROUP_RDMA_MAX)? {
+            let limit = parse_resources(&device, &fields)?;
+            if let Some(device_stats) = stats.get_mut(&device) {
+                device_stats.hca_handle_limit = limit.hca_handles;
+                device_stats.hca_object_limit = limit.hca_objects;
+            }
+        }
+    }
+
+    Ok(stats)
+}
+
+fn parse_resources(device: &str, fields: &[String]) -> Result<Resources, RdmaError> {
+    let mut resources = Resources::default();
+    for field in fields {
+        let invalid = || RdmaError::InvalidEntry {
+            device: device.to_owned(),
+            entry: field.to_owned(),
+        };
+        let (key, value) = field.split_once('=').ok_or_else(invalid)?;
+        let value = match value {
+            "max" => None,
+            value => Some(value.parse().map_err(|_| invalid())?),
+        };
+        match key {
+            HCA_HANDLE => resources.hca_handles = value,
+            HCA_OBJECT => resources.hca_objects = value,
+            // resources of newer kernels
+            _ => {}
+        }
+    }
+
+    Ok(resources)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use oci_spec::runtime::LinuxRdmaBuilder;
+
+    use super::*;
+    use crate::test::{set_fixture, setup};
+
+    fn limit(hca_handles: Option<u32>, hca_objects: Option<u32>) -> LinuxRdma {
+        let mut builder = LinuxRdmaBuilder::default();
+        if let Some(hca_handles) = hca_handles {
+            builder = builder.hca_handles(hca_handles);
+        }
+        if let Some(hca_objects) = hca_objects {
+            builder = builder.hca_objects(hca_objects);
+        }
+        builder.build().unwrap()
+    }
+
+    #[test]
+    fn test_limit_line() {
+        assert_eq!(
+            limit_line("mlx5_0", &limit(Some(2), Some(2000))).as_deref(),
+            Some("mlx5_0 hca_handle=2 hca_object=2000")
+        );
+        assert_eq!(
+            limit_line("mlx5_1", &limit(None, Some(100))).as_deref(),
+            Some("mlx5_1 hca_object=100")
+        );
+        assert_eq!(limit_line("mlx5_2", &limit(None, None)), None);
+    }
+
+    #[test]
+    fn test_apply() {
+        let (tmp, max_file) = setup(CGROUP_RDMA_MAX);
+        apply(
+            tmp.path(),
+            &HashMap::from([("mlx5_0".to_owned(), limit(Some(2), Some(2000)))]),
+        )
+        .expect("apply rdma");
+        assert_eq!(
+            fs::read_to_string(max_file).unwrap(),
+            "mlx5_0 hca_handle=2 hca_object=2000"
+        );
+    }
+
+    #[test]
+    fn test_stats() {
+        let tmp = tempfile::tempdir().unwrap();
+        assert!(stats(&mut CgroupDir::open(tmp.path()).unwrap())
+            .unwrap()
+            .is_empty());
+
+        set_fixture(
+            tmp.path(),
+            CGROUP_RDMA_CURRENT,
+            "mlx5_0 hca_handle=1 hca_object=20\nmlx5_1 hca_handle=0 hca_object=0\n",
+        )
+        .unwrap();
+        set_fixture(
+            tmp.path(),
+            CGROUP_RDMA_MAX,
+            "mlx5_0 hca_handle=2 hca_object=max\nmlx5_1 hca_handle=max hca_object=max\n",
+        )
+        .unwrap();
+
+        let rdma = stats(&mut CgroupDir::open(tmp.path()).unwrap()).expect("get rdma stats");
+        assert_eq!(
+            rdma["mlx5_0"],
+            RdmaStats {
+                hca_handles: 1,
+                hca_objects: 20,
+                hca_handle_limit: Some(2),
+                hca_object_limit: None,
+            }
+        );
+        assert_eq!(rdma["mlx5_1"], RdmaStats::default());
+
+        set_fixture(tmp.path(), CGROUP_RDMA_CURRENT, "mlx5_0 hca_handle=x\n").unwrap();
+        assert!(matches!(
+            stats(&mut CgroupDir::open(tmp.path()).unwrap()),
+            Err(RdmaError::InvalidEntry { .. })
+        ));
+    }
+}
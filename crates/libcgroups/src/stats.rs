@@ -1,9 +1,15 @@
 use std::collections::HashMap;
 use std::fmt::Display;
-use std::fs;
+use std::fs::{self, File, OpenOptions};
+use std::io::Read;
 use std::num::ParseIntError;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::{AsRawFd, FromRawFd};
 use std::path::{Path, PathBuf};
+use std::time::Instant;
 
+use nix::fcntl::{self, OFlag};
+use nix::sys::stat::{self, Mode};
 use serde::Serialize;
 
 use super::common;
@@ -13,7 +19,103 @@ pub(crate) trait StatsProvider {
     type Error;
     type Stats;
 
-    fn stats(cgroup_path: &Path) -> Result<Self::Stats, Self::Error>;
+    fn stats(dir: &mut CgroupDir) -> Result<Self::Stats, Self::Error>;
+}
+
+/// Directory of a cgroup which is held open to read the files of its
+/// statistics relative to it. The path of the cgroup is resolved once
+/// instead of for every file, and the buffer of the reads is reused, which
+/// adds up when the statistics of many cgroups are collected every interval.
+#[derive(Debug)]
+pub struct CgroupDir {
+    path: PathBuf,
+    dir: File,
+    buf: String,
+}
+
+impl CgroupDir {
+    pub fn open(path: &Path) -> Result<Self, WrappedIoError> {
+        let dir = OpenOptions::new()
+            .read(true)
+            .custom_flags(nix::libc::O_PATH | nix::libc::O_DIRECTORY | nix::libc::O_CLOEXEC)
+            .open(path)
+            .wrap_open(path)?;
+
+        Ok(Self {
+            path: path.to_owned(),
+            dir,
+            buf: String::new(),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn exists(&self, file: &str) -> bool {
+        stat::fstatat(
+            Some(self.dir.as_raw_fd()),
+            file,
+            fcntl::AtFlags::AT_SYMLINK_NOFOLLOW,
+        )
+        .is_ok()
+    }
+
+    /// Reads a file of the cgroup into the buffer, unless it was prefetched
+    /// by [`common::with_prefetched`]
+    pub fn read(&mut self, file: &str) -> Result<&str, WrappedIoError> {
+        if let Some(content) = common::take_prefetched(&self.path.join(file)) {
+            self.buf = content;
+            return Ok(&self.buf);
+        }
+
+        let fd = fcntl::openat(
+            Some(self.dir.as_raw_fd()),
+            file,
+            OFlag::O_RDONLY | OFlag::O_CLOEXEC,
+            Mode::empty(),
+        )
+        .map_err(std::io::Error::from)
+        .wrap_open(self.path.join(file))?;
+        // SAFETY: the fd was just opened and is owned by nothing else
+        let mut file_handle = unsafe { File::from_raw_fd(fd) };
+
+        self.buf.clear();
+        file_handle
+            .read_to_string(&mut self.buf)
+            .wrap_read(self.path.join(file))?;
+        Ok(&self.buf)
+    }
+
+    /// Parses a single valued file of the cgroup to an u64
+    pub fn single_value(&mut self, file: &str) -> Result<u64, WrappedIoError> {
+        self.read(file)?;
+        parse_single_str(&self.buf, &self.path.join(file))
+    }
+
+    /// Parses a file of the cgroup in the flat keyed format
+    pub fn flat_keyed(
+        &mut self,
+        file: &str,
+    ) -> Result<HashMap<String, u64>, ParseFlatKeyedDataError> {
+        self.read(file)?;
+        parse_flat_keyed_str(&self.buf, &self.path.join(file))
+    }
+
+    /// Parses a file of the cgroup in the nested keyed format
+    pub fn nested_keyed(
+        &mut self,
+        file: &str,
+    ) -> Result<HashMap<String, Vec<String>>, ParseNestedKeyedDataError> {
+        self.read(file)?;
+        parse_nested_keyed_str(&self.buf, &self.path.join(file))
+    }
+
+    /// Parses a pressure stall information file of the cgroup
+    pub fn psi(&mut self, file: &str) -> Result<PSIStats, WrappedIoError> {
+        self.read(file)?;
+        parse_psi_str(&self.buf, &self.path.join(file))
+    }
 }
 
 /// Reports the statistics for a cgroup
@@ -29,6 +131,13 @@ pub struct Stats {
     pub blkio: BlkioStats,
     /// Memory statistics for the cgroup
     pub memory: MemoryStats,
+    /// Network classification and priorities of the cgroup (cgroup v1 only)
+    pub network: NetworkStats,
+    /// Usage of the resources of the misc controller per resource (cgroup v2
+    /// only)
+    pub misc: HashMap<String, MiscStats>,
+    /// Usage and limits of the RDMA resources per device
+    pub rdma: HashMap<String, RdmaStats>,
 }
 
 /// Reports the cpu statistics for a cgroup
@@ -38,6 +147,9 @@ pub struct CpuStats {
     pub usage: CpuUsage,
     /// Cpu Throttling statistics for the cgroup
     pub throttling: CpuThrottling,
+    /// Whether the tasks of the cgroup are scheduled like SCHED_IDLE tasks
+    /// (cgroup v2 cpu.idle), if supported by the kernel
+    pub idle: Option<i64>,
     /// Pressure Stall Information
     pub psi: PSIStats,
 }
@@ -51,11 +163,14 @@ pub struct CpuUsage {
     pub usage_user: u64,
     /// Cpu time consumed by tasks in kernel mode
     pub usage_kernel: u64,
-    /// Cpu time consumed by tasks itemized per core
+    /// Cpu time consumed by tasks itemized per core, indexed by cpu number.
+    /// Only cgroup v1 accounts per core, empty on cgroup v2.
     pub per_core_usage_total: Vec<u64>,
-    /// Cpu time consumed by tasks in user mode itemized per core
+    /// Cpu time consumed by tasks in user mode itemized per core, empty
+    /// before Linux 4.7
     pub per_core_usage_user: Vec<u64>,
-    /// Cpu time consumed by tasks in kernel mode itemized per core
+    /// Cpu time consumed by tasks in kernel mode itemized per core, empty
+    /// before Linux 4.7
     pub per_core_usage_kernel: Vec<u64>,
 }
 
@@ -89,6 +204,14 @@ pub struct MemoryStats {
     pub stats: HashMap<String, u64>,
     /// Pressure Stall Information
     pub psi: PSIStats,
+    /// Usage above which processes are throttled (cgroup v2 memory.high)
+    pub high_limit: u64,
+    /// Number of times usage exceeded the high limit and processes were
+    /// throttled
+    pub high_events: u64,
+    /// Compressed size of the memory in the zswap cache (cgroup v2
+    /// memory.zswap.current)
+    pub zswap_usage: u64,
 }
 
 /// Reports memory stats for one type of memory
@@ -104,6 +227,40 @@ pub struct MemoryData {
     pub limit: u64,
 }
 
+/// Reports how the network traffic of a cgroup is classified and prioritized
+#[derive(Debug, Default, PartialEq, Eq, Serialize)]
+pub struct NetworkStats {
+    /// Class id with which the packets of the cgroup are tagged (net_cls)
+    pub class_id: Option<u32>,
+    /// Priority of the packets of the cgroup per network interface (net_prio)
+    pub priorities: HashMap<String, u32>,
+}
+
+/// Reports the usage of a scalar resource of the misc controller, e.g. the
+/// SGX encrypted page cache
+#[derive(Debug, Default, PartialEq, Eq, Serialize)]
+pub struct MiscStats {
+    /// Current usage
+    pub usage: u64,
+    /// Usage limit, none if unlimited
+    pub limit: Option<u64>,
+    /// Number of times the usage was about to exceed the limit
+    pub fail_count: u64,
+}
+
+/// Reports the usage and the limits of the RDMA resources of a device
+#[derive(Debug, Default, PartialEq, Eq, Serialize)]
+pub struct RdmaStats {
+    /// Number of HCA handles in use
+    pub hca_handles: u32,
+    /// Number of HCA objects in use
+    pub hca_objects: u32,
+    /// Limit of the HCA handles, none if unlimited
+    pub hca_handle_limit: Option<u32>,
+    /// Limit of the HCA objects, none if unlimited
+    pub hca_object_limit: Option<u32>,
+}
+
 /// Reports pid stats for a cgroup
 #[derive(Debug, Default, PartialEq, Eq, Serialize)]
 pub struct PidStats {
@@ -132,6 +289,8 @@ pub struct BlkioStats {
     pub queued: Vec<BlkioDeviceStat>,
     // Number of requests merged into requests for I/O operations
     pub merged: Vec<BlkioDeviceStat>,
+    // Latency target in microseconds of a device (cgroup v2 io.latency)
+    pub latency_target: Vec<BlkioDeviceStat>,
     /// Pressure Stall Information
     pub psi: PSIStats,
 }
@@ -172,6 +331,10 @@ pub struct HugeTlbStats {
     pub max_usage: u64,
     /// Number of allocation failures due to HugeTlb usage limit
     pub fail_count: u64,
+    /// The stats are of the reservations of huge pages, the rsvd files of
+    /// kernels 5.7 and later, rather than of the pages which were faulted in.
+    /// Reservations include pages which are mapped but not used yet.
+    pub rsvd: bool,
 }
 
 /// Reports Pressure Stall Information for a cgroup
@@ -191,6 +354,80 @@ pub struct PSIData {
     pub avg60: f64,
     /// Running average over the last 300 seconds
     pub avg300: f64,
+    /// Total stall time in microseconds
+    pub total: u64,
+}
+
+/// Statistics of a cgroup together with the time they were collected, so
+/// that rates can be derived from two consecutive samples
+#[derive(Debug)]
+pub struct StatsSample {
+    /// Point in time at which the statistics were collected
+    pub taken_at: Instant,
+    /// Statistics of the cgroup
+    pub stats: Stats,
+}
+
+/// Reports resource usage rates between two samples of a cgroup
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub struct UsageRates {
+    /// Cpu usage in percent, where 100 corresponds to one fully used cpu
+    pub cpu_percent: f64,
+    /// Bytes read from block devices per second
+    pub io_read_bytes_per_sec: f64,
+    /// Bytes written to block devices per second
+    pub io_write_bytes_per_sec: f64,
+}
+
+impl StatsSample {
+    pub fn new(stats: Stats) -> Self {
+        Self {
+            taken_at: Instant::now(),
+            stats,
+        }
+    }
+
+    /// Calculates the usage rates between an earlier sample and this one.
+    /// Counters which went backwards, e.g. because the cgroup was recreated,
+    /// are reported as zero.
+    pub fn rates_since(&self, earlier: &StatsSample) -> UsageRates {
+        let elapsed = self
+            .taken_at
+            .saturating_duration_since(earlier.taken_at)
+            .as_secs_f64();
+        if elapsed == 0.0 {
+            return UsageRates::default();
+        }
+
+        let per_sec = |now: u64, before: u64| now.saturating_sub(before) as f64 / elapsed;
+        let cpu_ns_per_sec = per_sec(
+            self.stats.cpu.usage.usage_total,
+            earlier.stats.cpu.usage.usage_total,
+        );
+        let (now_read, now_write) = self.stats.blkio.io_bytes();
+        let (before_read, before_write) = earlier.stats.blkio.io_bytes();
+
+        UsageRates {
+            cpu_percent: cpu_ns_per_sec / 1e9 * 100.0,
+            io_read_bytes_per_sec: per_sec(now_read, before_read),
+            io_write_bytes_per_sec: per_sec(now_write, before_write),
+        }
+    }
+}
+
+impl BlkioStats {
+    /// Returns the total number of bytes read and written across all devices
+    pub fn io_bytes(&self) -> (u64, u64) {
+        self.service_bytes
+            .iter()
+            .fold((0, 0), |(read, write), stat| {
+                match stat.op_type.as_deref() {
+                    Some(op) if op.eq_ignore_ascii_case("read") => (read + stat.value, write),
+                    Some(op) if op.eq_ignore_ascii_case("write") => (read, write + stat.value),
+                    _ => (read, write),
+                }
+            })
+    }
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -271,7 +508,10 @@ pub fn parse_value(value: &str) -> Result<u64, ParseIntError> {
 /// assert_eq!(value, 32);
 /// ```
 pub fn parse_single_value(file_path: &Path) -> Result<u64, WrappedIoError> {
-    let value = common::read_cgroup_file(file_path)?;
+    parse_single_str(&common::read_cgroup_file(file_path)?, file_path)
+}
+
+fn parse_single_str(value: &str, file_path: &Path) -> Result<u64, WrappedIoError> {
     let value = value.trim();
     if value == "max" {
         return Ok(u64::MAX);
@@ -297,12 +537,11 @@ pub enum ParseFlatKeyedDataError {
     },
 }
 
-/// Parses a file that is structured according to the flat keyed format
-pub(crate) fn parse_flat_keyed_data(
+fn parse_flat_keyed_str(
+    keyed_data: &str,
     file_path: &Path,
 ) -> Result<HashMap<String, u64>, ParseFlatKeyedDataError> {
     let mut stats = HashMap::new();
-    let keyed_data = common::read_cgroup_file(file_path)?;
     for entry in keyed_data.lines() {
         let entry_fields: Vec<&str> = entry.split_ascii_whitespace().collect();
         if entry_fields.len() != 2 {
@@ -337,9 +576,15 @@ pub enum ParseNestedKeyedDataError {
 /// Parses a file that is structured according to the nested keyed format
 pub fn parse_nested_keyed_data(
     file_path: &Path,
+) -> Result<HashMap<String, Vec<String>>, ParseNestedKeyedDataError> {
+    parse_nested_keyed_str(&common::read_cgroup_file(file_path)?, file_path)
+}
+
+fn parse_nested_keyed_str(
+    keyed_data: &str,
+    file_path: &Path,
 ) -> Result<HashMap<String, Vec<String>>, ParseNestedKeyedDataError> {
     let mut stats: HashMap<String, Vec<String>> = HashMap::new();
-    let keyed_data = common::read_cgroup_file(file_path)?;
     for entry in keyed_data.lines() {
         let entry_fields: Vec<&str> = entry.split_ascii_whitespace().collect();
         if entry_fields.len() < 2 || !entry_fields[1..].iter().all(|p| p.contains('=')) {
@@ -405,17 +650,16 @@ pub enum PidStatsError {
 }
 
 /// Returns cgroup pid statistics
-pub fn pid_stats(cgroup_path: &Path) -> Result<PidStats, PidStatsError> {
+pub fn pid_stats(dir: &mut CgroupDir) -> Result<PidStats, PidStatsError> {
     let mut stats = PidStats::default();
 
-    let current = common::read_cgroup_file(cgroup_path.join("pids.current"))?;
+    let current = dir.read("pids.current")?;
     stats.current = current
         .trim()
         .parse()
         .map_err(PidStatsError::ParseCurrent)?;
 
-    let limit =
-        common::read_cgroup_file(cgroup_path.join("pids.max")).map(|l| l.trim().to_owned())?;
+    let limit = dir.read("pids.max")?.trim();
     if limit != "max" {
         stats.limit = limit.parse().map_err(PidStatsError::ParseLimit)?;
     }
@@ -424,13 +668,16 @@ pub fn pid_stats(cgroup_path: &Path) -> Result<PidStats, PidStatsError> {
 }
 
 pub fn psi_stats(psi_file: &Path) -> Result<PSIStats, WrappedIoError> {
+    parse_psi_str(&common::read_cgroup_file(psi_file)?, psi_file)
+}
+
+fn parse_psi_str(psi: &str, psi_file: &Path) -> Result<PSIStats, WrappedIoError> {
     let mut stats = PSIStats::default();
 
-    let psi = common::read_cgroup_file(psi_file)?;
     for line in psi.lines() {
-        match &line[0..4] {
-            "some" => stats.some = parse_psi(&line[4..], psi_file)?,
-            "full" => stats.full = parse_psi(&line[4..], psi_file)?,
+        match line.split_once(' ') {
+            Some(("some", data)) => stats.some = parse_psi(data, psi_file)?,
+            Some(("full", data)) => stats.full = parse_psi(data, psi_file)?,
             _ => continue,
         }
     }
@@ -463,6 +710,12 @@ fn parse_psi(stat_line: &str, path: &Path) -> Result<PSIData, WrappedIoError> {
                     .map_err(|err| Error::new(ErrorKind::InvalidData, err))
                     .wrap_other(path)?
             }
+            Some(("total", v)) => {
+                psi_data.total = v
+                    .parse()
+                    .map_err(|err| Error::new(ErrorKind::InvalidData, err))
+                    .wrap_other(path)?
+            }
             _ => continue,
         }
     }
@@ -475,6 +728,43 @@ mod tests {
     use super::*;
     use crate::test::set_fixture;
 
+    fn io_stat(op_type: &str, value: u64) -> BlkioDeviceStat {
+        BlkioDeviceStat {
+            major: 8,
+            minor: 0,
+            op_type: Some(op_type.to_owned()),
+            value,
+        }
+    }
+
+    #[test]
+    fn test_usage_rates() {
+        let earlier = StatsSample::new(Stats::default());
+        let mut later = StatsSample::new(Stats::default());
+        later.taken_at = earlier.taken_at + std::time::Duration::from_secs(2);
+        later.stats.cpu.usage.usage_total = 1_000_000_000;
+        later.stats.blkio.service_bytes = vec![
+            io_stat("Read", 4096),
+            io_stat("write", 2048),
+            io_stat("Total", 6144),
+        ];
+
+        let rates = later.rates_since(&earlier);
+        assert_eq!(
+            rates,
+            UsageRates {
+                cpu_percent: 50.0,
+                io_read_bytes_per_sec: 2048.0,
+                io_write_bytes_per_sec: 1024.0,
+            }
+        );
+
+        // counters going backwards must not underflow
+        let mut restarted = StatsSample::new(Stats::default());
+        restarted.taken_at = later.taken_at + std::time::Duration::from_secs(1);
+        assert_eq!(restarted.rates_since(&later), UsageRates::default());
+    }
+
     #[test]
     fn test_supported_page_sizes_gigabyte() {
         let page_size = extract_page_size("hugepages-1048576kB").unwrap();
@@ -493,6 +783,47 @@ mod tests {
         assert_eq!(page_size, "512KB");
     }
 
+    #[test]
+    fn test_cgroup_dir() {
+        let tmp = tempfile::tempdir().unwrap();
+        set_fixture(tmp.path(), "pids.current", "5\n").unwrap();
+        set_fixture(tmp.path(), "memory.stat", "anon 1024\nfile 2048\n").unwrap();
+
+        let mut dir = CgroupDir::open(tmp.path()).unwrap();
+        assert_eq!(dir.path(), tmp.path());
+        assert!(dir.exists("pids.current"));
+        assert!(!dir.exists("pids.max"));
+        assert_eq!(dir.single_value("pids.current").unwrap(), 5);
+        assert_eq!(dir.flat_keyed("memory.stat").unwrap()["file"], 2048);
+
+        // files are read relative to the open directory, also after a change
+        set_fixture(tmp.path(), "pids.current", "7\n").unwrap();
+        assert_eq!(dir.read("pids.current").unwrap(), "7\n");
+        assert!(dir.read("pids.max").is_err());
+        assert!(CgroupDir::open(&tmp.path().join("missing")).is_err());
+    }
+
+    #[test]
+    fn test_cgroup_dir_prefetched() {
+        let tmp = tempfile::tempdir().unwrap();
+        set_fixture(tmp.path(), "pids.current", "5\n").unwrap();
+        set_fixture(tmp.path(), "pids.max", "max\n").unwrap();
+
+        let mut dir = CgroupDir::open(tmp.path()).unwrap();
+        let files = HashMap::from([(tmp.path().join("pids.current"), "9\n".to_owned())]);
+        let (values, read) = common::with_prefetched(files, || {
+            (
+                dir.single_value("pids.current").unwrap(),
+                dir.read("pids.max").unwrap().to_owned(),
+            )
+        });
+        assert_eq!(values, (9, "max\n".to_owned()));
+        assert_eq!(
+            read,
+            [tmp.path().join("pids.current"), tmp.path().join("pids.max")]
+        );
+    }
+
     #[test]
     fn test_parse_single_value_valid() {
         let tmp = tempfile::tempdir().unwrap();
@@ -524,9 +855,12 @@ mod tests {
     fn test_parse_flat_keyed_data() {
         let tmp = tempfile::tempdir().unwrap();
         let file_content = ["key1 1", "key2 2", "key3 3"].join("\n");
-        let file_path = set_fixture(tmp.path(), "flat_keyed_data", &file_content).unwrap();
+        set_fixture(tmp.path(), "flat_keyed_data", &file_content).unwrap();
 
-        let actual = parse_flat_keyed_data(&file_path).unwrap();
+        let actual = CgroupDir::open(tmp.path())
+            .unwrap()
+            .flat_keyed("flat_keyed_data")
+            .unwrap();
         let mut expected = HashMap::with_capacity(3);
         expected.insert("key1".to_owned(), 1);
         expected.insert("key2".to_owned(), 2);
@@ -539,9 +873,11 @@ mod tests {
     fn test_parse_flat_keyed_data_with_characters() {
         let tmp = tempfile::tempdir().unwrap();
         let file_content = ["key1 1", "key2 a", "key3 b"].join("\n");
-        let file_path = set_fixture(tmp.path(), "flat_keyed_data", &file_content).unwrap();
+        set_fixture(tmp.path(), "flat_keyed_data", &file_content).unwrap();
 
-        let result = parse_flat_keyed_data(&file_path);
+        let result = CgroupDir::open(tmp.path())
+            .unwrap()
+            .flat_keyed("flat_keyed_data");
         assert!(result.is_err());
     }
 
@@ -549,9 +885,11 @@ mod tests {
     fn test_parse_space_separated_as_flat_keyed_data() {
         let tmp = tempfile::tempdir().unwrap();
         let file_content = ["key1", "key2", "key3", "key4"].join(" ");
-        let file_path = set_fixture(tmp.path(), "space_separated", &file_content).unwrap();
+        set_fixture(tmp.path(), "space_separated", &file_content).unwrap();
 
-        let result = parse_flat_keyed_data(&file_path);
+        let result = CgroupDir::open(tmp.path())
+            .unwrap()
+            .flat_keyed("space_separated");
         assert!(result.is_err());
     }
 
@@ -559,9 +897,11 @@ mod tests {
     fn test_parse_newline_separated_as_flat_keyed_data() {
         let tmp = tempfile::tempdir().unwrap();
         let file_content = ["key1", "key2", "key3", "key4"].join("\n");
-        let file_path = set_fixture(tmp.path(), "newline_separated", &file_content).unwrap();
+        set_fixture(tmp.path(), "newline_separated", &file_content).unwrap();
 
-        let result = parse_flat_keyed_data(&file_path);
+        let result = CgroupDir::open(tmp.path())
+            .unwrap()
+            .flat_keyed("newline_separated");
         assert!(result.is_err());
     }
 
@@ -574,9 +914,11 @@ mod tests {
             "key3 subkey1=value1 subkey2=value2 subkey3=value3",
         ]
         .join("\n");
-        let file_path = set_fixture(tmp.path(), "nested_keyed_data", &file_content).unwrap();
+        set_fixture(tmp.path(), "nested_keyed_data", &file_content).unwrap();
 
-        let result = parse_flat_keyed_data(&file_path);
+        let result = CgroupDir::open(tmp.path())
+            .unwrap()
+            .flat_keyed("nested_keyed_data");
         assert!(result.is_err());
     }
 
@@ -667,8 +1009,8 @@ mod tests {
     fn test_parse_psi_full_stats() {
         let tmp = tempfile::tempdir().unwrap();
         let file_content = [
-            "some avg10=80.00 avg60=50.00 avg300=90.00 total=0",
-            "full avg10=10.00 avg60=30.00 avg300=50.00 total=0",
+            "some avg10=80.00 avg60=50.00 avg300=90.00 total=123456",
+            "full avg10=10.00 avg60=30.00 avg300=50.00 total=7890",
         ]
         .join("\n");
         let psi_file = set_fixture(tmp.path(), "psi.pressure", &file_content).unwrap();
@@ -680,12 +1022,14 @@ mod tests {
                 some: PSIData {
                     avg10: 80.0,
                     avg60: 50.0,
-                    avg300: 90.0
+                    avg300: 90.0,
+                    total: 123456,
                 },
                 full: PSIData {
                     avg10: 10.0,
                     avg60: 30.0,
-                    avg300: 50.0
+                    avg300: 50.0,
+                    total: 7890,
                 },
             }
         )
@@ -704,10 +1048,28 @@ mod tests {
                 some: PSIData {
                     avg10: 80.0,
                     avg60: 50.0,
-                    avg300: 90.0
+                    avg300: 90.0,
+                    total: 0,
                 },
                 full: PSIData::default(),
             }
         )
     }
+
+    #[test]
+    fn test_parse_psi_truncated_line() {
+        let tmp = tempfile::tempdir().unwrap();
+        let file_content = [
+            "som",
+            "",
+            "some avg10=1.00 avg60=2.00 avg300=3.00 total=42",
+            "ful",
+        ]
+        .join("\n");
+        let psi_file = set_fixture(tmp.path(), "psi.pressure", &file_content).unwrap();
+
+        let result = psi_stats(&psi_file).unwrap();
+        assert_eq!(result.some.total, 42);
+        assert_eq!(result.full, PSIData::default());
+    }
 }
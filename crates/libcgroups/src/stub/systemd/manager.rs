@@ -8,7 +8,20 @@ pub enum SystemdManagerError {
 
 pub struct Manager {}
 
+#[derive(Clone)]
+pub struct Connection {}
+
+impl Connection {
+    pub fn new(_use_system: bool) -> Result<Self, SystemdManagerError> {
+        Err(SystemdManagerError::NotEnabled)
+    }
+}
+
 impl Manager {
+    pub fn with_state_root(self, _state_root: Option<std::path::PathBuf>) -> Self {
+        self
+    }
+
     pub fn any(self) -> AnyCgroupManager {
         AnyCgroupManager::Systemd(Box::new(self))
     }
@@ -29,7 +42,11 @@ impl CgroupManager for Manager {
         Err(SystemdManagerError::NotEnabled)
     }
 
-    fn freeze(&self, _state: crate::common::FreezerState) -> Result<(), Self::Error> {
+    fn freeze_with_timeout(
+        &self,
+        _state: crate::common::FreezerState,
+        _timeout: std::time::Duration,
+    ) -> Result<(), Self::Error> {
         Err(SystemdManagerError::NotEnabled)
     }
 
@@ -40,4 +57,16 @@ impl CgroupManager for Manager {
     fn get_all_pids(&self) -> Result<Vec<nix::unistd::Pid>, Self::Error> {
         Err(SystemdManagerError::NotEnabled)
     }
+
+    fn capabilities(&self) -> Result<crate::capabilities::CgroupCapabilities, Self::Error> {
+        Err(SystemdManagerError::NotEnabled)
+    }
+
+    fn reclaim(&self, _bytes: u64) -> Result<(), Self::Error> {
+        Err(SystemdManagerError::NotEnabled)
+    }
+
+    fn kill(&self) -> Result<bool, Self::Error> {
+        Err(SystemdManagerError::NotEnabled)
+    }
 }
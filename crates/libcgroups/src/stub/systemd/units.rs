@@ -0,0 +1,12 @@
+#[derive(thiserror::Error, Debug)]
+pub enum UnitsError {
+    #[error("systemd cgroup feature is required, but was not enabled during compile time")]
+    NotEnabled,
+}
+
+pub fn purge<F: Fn(&str) -> bool>(
+    _state_root: &std::path::Path,
+    _container_exists: F,
+) -> Result<Vec<String>, UnitsError> {
+    Err(UnitsError::NotEnabled)
+}
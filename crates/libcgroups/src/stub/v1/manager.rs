@@ -29,7 +29,11 @@ impl CgroupManager for Manager {
         Err(V1ManagerError::NotEnabled)
     }
 
-    fn freeze(&self, _state: crate::common::FreezerState) -> Result<(), Self::Error> {
+    fn freeze_with_timeout(
+        &self,
+        _state: crate::common::FreezerState,
+        _timeout: std::time::Duration,
+    ) -> Result<(), Self::Error> {
         Err(V1ManagerError::NotEnabled)
     }
 
@@ -40,4 +44,16 @@ impl CgroupManager for Manager {
     fn get_all_pids(&self) -> Result<Vec<nix::unistd::Pid>, Self::Error> {
         Err(V1ManagerError::NotEnabled)
     }
+
+    fn capabilities(&self) -> Result<crate::capabilities::CgroupCapabilities, Self::Error> {
+        Err(V1ManagerError::NotEnabled)
+    }
+
+    fn reclaim(&self, _bytes: u64) -> Result<(), Self::Error> {
+        Err(V1ManagerError::NotEnabled)
+    }
+
+    fn kill(&self) -> Result<bool, Self::Error> {
+        Err(V1ManagerError::NotEnabled)
+    }
 }
@@ -1,5 +1,9 @@
+use std::path::PathBuf;
+
 use crate::common::{AnyCgroupManager, CgroupManager};
 
+pub const INIT_LEAF: &str = "init";
+
 #[derive(thiserror::Error, Debug)]
 pub enum V2ManagerError {
     #[error("v2 cgroup feature is required, but was not enabled during compile time")]
@@ -9,6 +13,18 @@ pub enum V2ManagerError {
 pub struct Manager {}
 
 impl Manager {
+    pub fn new(_root_path: PathBuf, _cgroup_path: PathBuf) -> Result<Self, V2ManagerError> {
+        Err(V2ManagerError::NotEnabled)
+    }
+
+    pub fn with_init_leaf(self, _init_leaf: bool) -> Self {
+        self
+    }
+
+    pub fn move_to_init_leaf(&self) -> Result<PathBuf, V2ManagerError> {
+        Err(V2ManagerError::NotEnabled)
+    }
+
     pub fn any(self) -> AnyCgroupManager {
         crate::common::AnyCgroupManager::V2(self)
     }
@@ -29,7 +45,11 @@ impl CgroupManager for Manager {
         Err(V2ManagerError::NotEnabled)
     }
 
-    fn freeze(&self, _state: crate::common::FreezerState) -> Result<(), Self::Error> {
+    fn freeze_with_timeout(
+        &self,
+        _state: crate::common::FreezerState,
+        _timeout: std::time::Duration,
+    ) -> Result<(), Self::Error> {
         Err(V2ManagerError::NotEnabled)
     }
 
@@ -40,4 +60,16 @@ impl CgroupManager for Manager {
     fn get_all_pids(&self) -> Result<Vec<nix::unistd::Pid>, Self::Error> {
         Err(V2ManagerError::NotEnabled)
     }
+
+    fn capabilities(&self) -> Result<crate::capabilities::CgroupCapabilities, Self::Error> {
+        Err(V2ManagerError::NotEnabled)
+    }
+
+    fn reclaim(&self, _bytes: u64) -> Result<(), Self::Error> {
+        Err(V2ManagerError::NotEnabled)
+    }
+
+    fn kill(&self) -> Result<bool, Self::Error> {
+        Err(V2ManagerError::NotEnabled)
+    }
 }
@@ -1 +1,3 @@
 pub mod manager;
+pub mod placement;
+pub mod threaded;
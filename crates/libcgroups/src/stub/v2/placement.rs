@@ -0,0 +1,54 @@
+use std::fmt::{self, Display};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+#[derive(thiserror::Error, Debug)]
+pub enum PlacementError {
+    #[error("v2 cgroup feature is required, but was not enabled during compile time")]
+    NotEnabled,
+    #[error("cgroup {0:?} has no sub-cgroup to place the process in")]
+    NoSubCgroup(PathBuf),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlacementPolicy {
+    LeastLoaded,
+    RoundRobin,
+}
+
+impl FromStr for PlacementPolicy {
+    type Err = PlacementError;
+
+    fn from_str(_s: &str) -> Result<Self, Self::Err> {
+        Err(PlacementError::NotEnabled)
+    }
+}
+
+impl Display for PlacementPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::LeastLoaded => write!(f, "least-loaded"),
+            Self::RoundRobin => write!(f, "round-robin"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SubCgroup {
+    pub name: String,
+    pub path: PathBuf,
+    pub pids: u64,
+    pub cpu_pressure: f64,
+}
+
+pub fn sub_cgroups(_cgroup_path: &Path) -> Result<Vec<SubCgroup>, PlacementError> {
+    Err(PlacementError::NotEnabled)
+}
+
+pub fn place<'a>(
+    _sub_cgroups: &'a [SubCgroup],
+    _policy: PlacementPolicy,
+    _last: Option<&str>,
+) -> Option<&'a SubCgroup> {
+    None
+}
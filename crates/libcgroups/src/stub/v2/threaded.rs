@@ -0,0 +1,15 @@
+use std::path::{Path, PathBuf};
+
+#[derive(thiserror::Error, Debug)]
+pub enum ThreadedError {
+    #[error("v2 cgroup feature is required, but was not enabled during compile time")]
+    NotEnabled,
+}
+
+pub fn create_threaded(
+    _parent: &Path,
+    _name: &str,
+    _controllers: &[&str],
+) -> Result<PathBuf, ThreadedError> {
+    Err(ThreadedError::NotEnabled)
+}
@@ -5,7 +5,6 @@ use super::serialize::Variant;
 use super::utils::SystemdClientError;
 
 pub trait SystemdClient {
-    #[allow(dead_code)]
     fn is_system(&self) -> bool;
 
     fn transient_unit_exists(&self, unit_name: &str) -> bool;
@@ -20,6 +19,11 @@ pub trait SystemdClient {
 
     fn stop_transient_unit(&self, unit_name: &str) -> Result<(), SystemdClientError>;
 
+    /// Unloads a unit which failed, it stays loaded otherwise
+    fn reset_failed_unit(&self, unit_name: &str) -> Result<(), SystemdClientError>;
+
+    fn unit_description(&self, unit_name: &str) -> Result<String, SystemdClientError>;
+
     fn set_unit_properties(
         &self,
         unit_name: &str,
@@ -1,17 +1,20 @@
 use std::collections::HashMap;
 use std::io::{IoSlice, IoSliceMut};
 use std::os::fd::AsRawFd;
-use std::path::PathBuf;
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicU32, Ordering};
 
 use nix::errno::Errno;
 use nix::sys::socket;
+use nix::unistd::geteuid;
 
 use super::client::SystemdClient;
 use super::message::*;
 use super::proxy::Proxy;
 use super::utils::{DbusError, Result, SystemdClientError};
 use crate::systemd::dbus_native::serialize::{DbusSerialize, Structure, Variant};
+use crate::systemd::units::unit_description;
 
 const REPLY_BUF_SIZE: usize = 128; // seems good enough tradeoff between extra size and repeated calls
 
@@ -25,8 +28,8 @@ pub struct DbusConnection {
     /// Is the socket system level or session specific
     #[allow(dead_code)]
     system: bool,
-    /// socket fd
-    socket: i32,
+    /// transport to the bus
+    socket: UnixStream,
     /// name id assigned by dbus for the connection
     id: Option<String>,
     /// counter for messages
@@ -45,49 +48,73 @@ fn uid_to_hex_str(uid: u32) -> String {
     temp.join("")
 }
 
-fn parse_dbus_address(env_value: String) -> Result<String> {
+/// Address of a bus socket
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BusAddress {
+    /// Socket bound to a path in the filesystem
+    Path(PathBuf),
+    /// Socket in the abstract namespace, the name is without the leading nul byte
+    Abstract(String),
+}
+
+impl BusAddress {
+    fn to_unix_addr(&self) -> Result<socket::UnixAddr> {
+        let addr = match self {
+            BusAddress::Path(path) => socket::UnixAddr::new(path)?,
+            BusAddress::Abstract(name) => socket::UnixAddr::new_abstract(name.as_bytes())?,
+        };
+        Ok(addr)
+    }
+}
+
+fn parse_dbus_address(env_value: String) -> Result<BusAddress> {
     // as per spec, the env var can have multiple addresses separated by ;
-    let addr_list: Vec<_> = env_value.split(';').collect();
-    for addr in addr_list {
-        if let Some(s) = addr.strip_prefix("unix:path=") {
-            if !std::path::PathBuf::from(s).exists() {
-                continue;
+    // and each address is of form transport:key=value,key=value...
+    // e.g. unix:path=/run/user/1000/bus,guid=8ba2e3c9...
+    for addr in env_value.split(';') {
+        let params = match addr.strip_prefix("unix:") {
+            Some(params) => params,
+            None => continue,
+        };
+        let param = params
+            .split(',')
+            .filter_map(|param| param.split_once('='))
+            .find(|(key, _)| *key == "path" || *key == "abstract");
+
+        match param {
+            Some(("abstract", name)) => return Ok(BusAddress::Abstract(name.to_owned())),
+            Some((_, path)) if Path::new(path).exists() => {
+                return Ok(BusAddress::Path(path.into()))
             }
-            return Ok(s.to_owned());
-        }
-
-        if let Some(s) = addr.strip_prefix("unix:abstract=") {
-            return Ok(s.to_owned());
+            _ => continue,
         }
     }
-    // we do not support unix:runtime=
+    // we do not support unix:runtime= or any non unix transport
     Err(DbusError::BusAddressError(format!("no valid bus path found in list {}", env_value)).into())
 }
 
-fn get_session_bus_address() -> Result<String> {
+fn get_session_bus_address() -> Result<BusAddress> {
     if let Ok(s) = std::env::var("DBUS_SESSION_BUS_ADDRESS") {
         return parse_dbus_address(s);
     }
 
-    if let Ok(mut s) = std::env::var("XDG_RUNTIME_DIR") {
-        s.push_str("/bus");
-        if !std::path::PathBuf::from(&s).exists() {
-            return Err(DbusError::BusAddressError(format!(
-                "session bus address {} does not exist",
-                s
-            ))
-            .into());
-        }
-        return Ok(s);
+    // the user manager of systemd always listens at $XDG_RUNTIME_DIR/bus,
+    // which is /run/user/<uid> when the variable is not set, e.g. with sudo
+    let runtime_dir = std::env::var("XDG_RUNTIME_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|_| PathBuf::from(format!("/run/user/{}", geteuid())));
+    let path = runtime_dir.join("bus");
+    if !path.exists() {
+        return Err(DbusError::BusAddressError(format!(
+            "session bus address {} does not exist",
+            path.display()
+        ))
+        .into());
     }
-
-    Err(
-        DbusError::BusAddressError("could not find dbus session bus address from env".into())
-            .into(),
-    )
+    Ok(BusAddress::Path(path))
 }
 
-fn get_system_bus_address() -> Result<String> {
+fn get_system_bus_address() -> Result<BusAddress> {
     if let Ok(s) = std::env::var("DBUS_SYSTEM_BUS_ADDRESS") {
         return parse_dbus_address(s);
     }
@@ -95,7 +122,7 @@ fn get_system_bus_address() -> Result<String> {
     // there are multiple service files which we should try searching and finding bus address from
     // but we will instead just support the following, which is supposed to be
     // well known anyways according to spec
-    Ok("/var/run/dbus/system_bus_socket".into())
+    Ok(BusAddress::Path("/var/run/dbus/system_bus_socket".into()))
 }
 
 fn get_actual_uid() -> Result<u32> {
@@ -126,22 +153,49 @@ fn get_actual_uid() -> Result<u32> {
     Ok(uid)
 }
 
+/// Returns true if the uid map is the one of the initial user namespace
+fn is_initial_uid_map(uid_map: &str) -> bool {
+    let fields: Vec<&str> = uid_map.split_whitespace().collect();
+    fields == ["0", "0", "4294967295"]
+}
+
+/// Returns the uid the session bus knows us by. Outside of a user namespace
+/// this is the effective uid, inside one the uid has to be asked from the bus.
+fn get_session_uid() -> Result<u32> {
+    let in_initial_userns = std::fs::read_to_string("/proc/self/uid_map")
+        .map(|uid_map| is_initial_uid_map(&uid_map))
+        .unwrap_or(false);
+    if in_initial_userns {
+        return Ok(geteuid().as_raw());
+    }
+    get_actual_uid()
+}
+
 impl DbusConnection {
     /// Open a new dbus connection to given address
     /// authenticating as user with given uid
     pub fn new(addr: &str, uid: u32, system: bool) -> Result<Self> {
-        // Use ManuallyDrop to keep the socket open.
-        let socket = std::mem::ManuallyDrop::new(socket::socket(
+        Self::connect(&BusAddress::Path(addr.into()), uid, system)
+    }
+
+    fn connect(addr: &BusAddress, uid: u32, system: bool) -> Result<Self> {
+        let socket = socket::socket(
             socket::AddressFamily::Unix,
             socket::SockType::Stream,
             socket::SockFlag::empty(),
             None,
-        )?);
+        )?;
 
-        let addr = socket::UnixAddr::new(addr)?;
+        let addr = addr.to_unix_addr()?;
         socket::connect(socket.as_raw_fd(), &addr)?;
+        Self::from_stream(UnixStream::from(socket), uid, system)
+    }
+
+    /// Uses an already connected stream as transport to the bus and
+    /// authenticates as user with given uid
+    pub fn from_stream(socket: UnixStream, uid: u32, system: bool) -> Result<Self> {
         let mut dbus = Self {
-            socket: socket.as_raw_fd(),
+            socket,
             msg_ctr: AtomicU32::new(0),
             id: None,
             system,
@@ -152,13 +206,16 @@ impl DbusConnection {
 
     pub fn new_system() -> Result<Self> {
         let addr = get_system_bus_address()?;
-        Self::new(&addr, 0, true)
+        Self::connect(&addr, 0, true)
     }
 
+    /// Connects to the bus of the systemd user manager, which is used for
+    /// rootless containers
     pub fn new_session() -> Result<Self> {
         let addr = get_session_bus_address()?;
-        let uid = get_actual_uid()?;
-        Self::new(&addr, uid, false)
+        let uid = get_session_uid()?;
+        tracing::debug!(?addr, uid, "connecting to session bus");
+        Self::connect(&addr, uid, false)
     }
 
     /// Authenticates with dbus using given uid via external strategy
@@ -167,15 +224,19 @@ impl DbusConnection {
         let mut buf = [0; 64];
 
         // dbus connection always start with a 0 byte sent as first thing
-        socket::send(self.socket, &[0], socket::MsgFlags::empty())?;
+        socket::send(self.socket.as_raw_fd(), &[0], socket::MsgFlags::empty())?;
 
         let msg = format!("AUTH EXTERNAL {}\r\n", uid_to_hex_str(uid));
 
         // then we send our auth with uid
-        socket::send(self.socket, msg.as_bytes(), socket::MsgFlags::empty())?;
+        socket::send(
+            self.socket.as_raw_fd(),
+            msg.as_bytes(),
+            socket::MsgFlags::empty(),
+        )?;
 
         // we get the reply and check if all went well or not
-        socket::recv(self.socket, &mut buf, socket::MsgFlags::empty())?;
+        socket::recv(self.socket.as_raw_fd(), &mut buf, socket::MsgFlags::empty())?;
 
         let reply: Vec<u8> = buf.iter().filter(|v| **v != 0).copied().collect();
 
@@ -195,7 +256,7 @@ impl DbusConnection {
         // we can also send AGREE_UNIX_FD before this if we need to deal with sending/receiving
         // fds over the connection, but because youki doesn't need it, we can skip that
         socket::send(
-            self.socket,
+            self.socket.as_raw_fd(),
             "BEGIN\r\n".as_bytes(),
             socket::MsgFlags::empty(),
         )?;
@@ -251,7 +312,7 @@ impl DbusConnection {
             let mut reply_buffer = [IoSliceMut::new(&mut reply[0..])];
 
             let reply_res = socket::recvmsg::<()>(
-                self.socket,
+                self.socket.as_raw_fd(),
                 &mut reply_buffer,
                 None,
                 socket::MsgFlags::empty(),
@@ -297,7 +358,7 @@ impl DbusConnection {
         let serialized = message.serialize();
 
         socket::sendmsg::<()>(
-            self.socket,
+            self.socket.as_raw_fd(),
             &[IoSlice::new(&serialized)],
             &[],
             socket::MsgFlags::empty(),
@@ -400,7 +461,7 @@ impl SystemdClient for DbusConnection {
         let mut properties: Vec<(&str, Variant)> = Vec::with_capacity(6);
         properties.push((
             "Description",
-            Variant::String(format!("youki container {container_name}")),
+            Variant::String(unit_description(container_name)),
         ));
 
         // if we create a slice, the parent is defined via a Wants=
@@ -447,6 +508,17 @@ impl SystemdClient for DbusConnection {
         Ok(())
     }
 
+    fn reset_failed_unit(&self, unit_name: &str) -> Result<()> {
+        let proxy = self.create_proxy();
+        proxy.reset_failed_unit(unit_name)
+    }
+
+    fn unit_description(&self, unit_name: &str) -> Result<String> {
+        let unit_path = self.create_proxy().get_unit(unit_name)?;
+        self.proxy("org.freedesktop.systemd1", &unit_path)
+            .unit_description()
+    }
+
     fn set_unit_properties(
         &self,
         unit_name: &str,
@@ -497,10 +569,19 @@ impl SystemdClient for DbusConnection {
 
 #[cfg(test)]
 mod tests {
+    use std::collections::HashMap;
+    use std::path::PathBuf;
+
     use nix::unistd::getuid;
 
-    use super::super::utils::Result;
-    use super::{uid_to_hex_str, DbusConnection, SystemdClientError};
+    use super::super::client::SystemdClient;
+    use super::super::fake_systemd::FakeSystemd;
+    use super::super::serialize::Variant;
+    use super::super::utils::{DbusError, Result};
+    use super::{
+        is_initial_uid_map, parse_dbus_address, uid_to_hex_str, BusAddress, DbusConnection,
+        SystemdClientError,
+    };
 
     #[test]
     fn test_uid_to_hex_str() {
@@ -510,6 +591,158 @@ mod tests {
         assert_eq!(uid1000, "31303030");
     }
 
+    #[test]
+    fn test_parse_dbus_address() -> Result<()> {
+        let tmp = tempfile::tempdir().unwrap();
+        let bus = tmp.path().join("bus");
+        std::fs::write(&bus, "").unwrap();
+
+        // extra keys such as the guid are ignored
+        let addr = parse_dbus_address(format!("unix:path={},guid=8ba2e3c9", bus.display()))?;
+        assert_eq!(addr, BusAddress::Path(bus.clone()));
+
+        // addresses which can not be used are skipped
+        let addr = parse_dbus_address(format!(
+            "tcp:host=localhost,port=1234;unix:path=/does/not/exist;unix:path={}",
+            bus.display()
+        ))?;
+        assert_eq!(addr, BusAddress::Path(bus));
+
+        let addr = parse_dbus_address("unix:guid=1234,abstract=/tmp/dbus-x".to_owned())?;
+        assert_eq!(addr, BusAddress::Abstract("/tmp/dbus-x".to_owned()));
+
+        assert!(parse_dbus_address("unix:runtime=yes".to_owned()).is_err());
+        assert!(parse_dbus_address("unix:path=/does/not/exist".to_owned()).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_is_initial_uid_map() {
+        assert!(is_initial_uid_map("         0          0 4294967295\n"));
+        assert!(!is_initial_uid_map("         0       1000          1\n"));
+        assert!(!is_initial_uid_map(
+            "         0       1000          1\n         1     100000      65536\n"
+        ));
+    }
+
+    #[test]
+    fn test_fake_systemd_auth() -> Result<()> {
+        let systemd = FakeSystemd::default().with_uid(1000);
+        let conn = systemd.connect(1000, false)?;
+        assert_eq!(conn.id.as_deref(), Some(":1.42"));
+
+        assert!(matches!(
+            systemd.connect(1001, false),
+            Err(SystemdClientError::DBus(DbusError::AuthenticationErr(_)))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_fake_systemd_properties() -> Result<()> {
+        let systemd = FakeSystemd::default()
+            .with_version("v252.4-1")
+            .with_control_group("/user.slice/user-1000.slice/user@1000.service");
+        let conn = systemd.connect(1000, false)?;
+
+        assert!(!conn.is_system());
+        assert_eq!(conn.systemd_version()?, 252);
+        assert_eq!(
+            conn.control_cgroup_root()?,
+            PathBuf::from("/user.slice/user-1000.slice/user@1000.service")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_fake_systemd_transient_unit() -> Result<()> {
+        let systemd = FakeSystemd::default();
+        let conn = systemd.connect(0, true)?;
+
+        assert!(!conn.transient_unit_exists("youki-foo.scope"));
+        conn.start_transient_unit("foo", 1000, "system.slice", "youki-foo.scope")?;
+        assert!(conn.transient_unit_exists("youki-foo.scope"));
+
+        let unit = systemd.unit("youki-foo.scope").unwrap();
+        assert_eq!(
+            unit.properties["Description"],
+            Variant::String("youki container foo".into())
+        );
+        assert_eq!(
+            unit.properties["Slice"],
+            Variant::String("system.slice".into())
+        );
+        assert_eq!(unit.properties["Delegate"], Variant::Bool(true));
+        assert_eq!(unit.properties["PIDs"], Variant::ArrayU32(vec![1000]));
+        for accounting in [
+            "MemoryAccounting",
+            "CPUAccounting",
+            "IOAccounting",
+            "TasksAccounting",
+        ] {
+            assert_eq!(unit.properties[accounting], Variant::Bool(true));
+        }
+
+        // a slice is ordered after its parent instead of placed in it
+        conn.start_transient_unit("foo", 1000, "system.slice", "youki-foo.slice")?;
+        let unit = systemd.unit("youki-foo.slice").unwrap();
+        assert_eq!(
+            unit.properties["Wants"],
+            Variant::String("system.slice".into())
+        );
+        assert!(!unit.properties.contains_key("Delegate"));
+
+        let properties = HashMap::from([("TasksMax", Variant::U64(42))]);
+        conn.set_unit_properties("youki-foo.scope", &properties)?;
+        conn.add_process_to_unit("youki-foo.scope", "/sub", 1001)?;
+        let unit = systemd.unit("youki-foo.scope").unwrap();
+        assert_eq!(unit.properties["TasksMax"], Variant::U64(42));
+        assert_eq!(unit.attached, vec![("/sub".to_owned(), 1001)]);
+
+        conn.stop_transient_unit("youki-foo.scope")?;
+        assert!(!conn.transient_unit_exists("youki-foo.scope"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_fake_systemd_errors() -> Result<()> {
+        let systemd = FakeSystemd::default().with_unit("youki-foo.scope");
+        let conn = systemd.connect(0, true)?;
+
+        assert!(matches!(
+            conn.start_transient_unit("foo", 1000, "system.slice", "youki-foo.scope"),
+            Err(SystemdClientError::FailedTransient { .. })
+        ));
+        assert!(matches!(
+            conn.stop_transient_unit("youki-bar.scope"),
+            Err(SystemdClientError::FailedStop { .. })
+        ));
+        assert!(matches!(
+            conn.set_unit_properties("youki-bar.scope", &HashMap::new()),
+            Err(SystemdClientError::FailedProperties { .. })
+        ));
+
+        systemd.fail(
+            "SetUnitProperties",
+            "org.freedesktop.DBus.Error.AccessDenied",
+        );
+        assert!(conn
+            .set_unit_properties("youki-foo.scope", &HashMap::new())
+            .is_err());
+        // the failure only applies to a single call
+        conn.set_unit_properties("youki-foo.scope", &HashMap::new())?;
+
+        let proxy = conn.proxy("org.freedesktop.systemd1", "/org/freedesktop/systemd1");
+        let res =
+            proxy.method_call::<_, ()>("org.freedesktop.systemd1.Manager", "Reload", None::<()>);
+        assert!(matches!(
+            res,
+            Err(SystemdClientError::DBus(DbusError::MethodCallErr(_)))
+        ));
+        Ok(())
+    }
+
     #[test]
     #[cfg(feature = "systemd")]
     fn test_dbus_connection_auth() {
@@ -565,8 +798,6 @@ mod tests {
     #[test]
     #[cfg(feature = "systemd")]
     fn test_dbus_function_calls_errors() {
-        use crate::systemd::dbus_native::utils::DbusError;
-
         let uid: u32 = getuid().into();
 
         let dbus_pipe_path = format!("/run/user/{}/bus", uid);
@@ -0,0 +1,476 @@
+//! Fake systemd manager for testing the dbus client and the systemd cgroup
+//! manager without a running systemd. It serves a single connection over a
+//! unix socket pair and implements the part of the dbus protocol and of the
+//! org.freedesktop.systemd1.Manager interface youki makes use of.
+
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::os::unix::net::UnixStream;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread;
+
+use super::dbus::DbusConnection;
+use super::message::{Header, HeaderKind, HeaderValue, Message, MessageType};
+use super::serialize::{DbusSerialize, Structure, Variant};
+use super::utils::Result;
+
+const BUS_NAME: &str = "org.freedesktop.DBus";
+const PROPERTIES_INTERFACE: &str = "org.freedesktop.DBus.Properties";
+const SYSTEMD_NAME: &str = "org.freedesktop.systemd1";
+const SYSTEMD_PATH: &str = "/org/freedesktop/systemd1";
+const MANAGER_INTERFACE: &str = "org.freedesktop.systemd1.Manager";
+const UNIT_INTERFACE: &str = "org.freedesktop.systemd1.Unit";
+
+const NO_SUCH_UNIT: &str = "org.freedesktop.systemd1.NoSuchUnit";
+const UNIT_EXISTS: &str = "org.freedesktop.systemd1.UnitExists";
+const UNKNOWN_METHOD: &str = "org.freedesktop.DBus.Error.UnknownMethod";
+const UNKNOWN_PROPERTY: &str = "org.freedesktop.DBus.Error.UnknownProperty";
+const INVALID_ARGS: &str = "org.freedesktop.DBus.Error.InvalidArgs";
+
+/// unique name the bus assigns to the client
+const CLIENT_NAME: &str = ":1.42";
+
+/// Transient unit known to the fake
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Unit {
+    /// properties the unit was started with, updated by SetUnitProperties
+    pub properties: HashMap<String, Variant>,
+    /// sub cgroup and pid of the processes attached with AttachProcessesToUnit
+    pub attached: Vec<(String, u32)>,
+}
+
+#[derive(Debug)]
+struct State {
+    uid: Option<u32>,
+    version: String,
+    control_group: String,
+    units: HashMap<String, Unit>,
+    failures: HashMap<String, String>,
+    calls: Vec<String>,
+    serial: u32,
+    jobs: u32,
+}
+
+/// Handle to the state of the fake, which is shared with the thread
+/// serving the connection
+#[derive(Debug, Clone)]
+pub struct FakeSystemd {
+    state: Arc<Mutex<State>>,
+}
+
+/// Successful reply to a method call, with the signals emitted before it
+struct Reply {
+    signature: Option<&'static str>,
+    body: Vec<u8>,
+    signals: Vec<Message>,
+}
+
+impl Reply {
+    fn empty() -> Self {
+        Self {
+            signature: None,
+            body: vec![],
+            signals: vec![],
+        }
+    }
+
+    /// Reply with a single value. The signature is given explicitly, as
+    /// object paths are serialized as strings.
+    fn with<T: DbusSerialize>(signature: &'static str, value: T) -> Self {
+        let mut body = vec![];
+        value.serialize(&mut body);
+        Self {
+            signature: Some(signature),
+            body,
+            signals: vec![],
+        }
+    }
+}
+
+/// Error name and message of a failed method call
+type CallError = (String, String);
+type CallResult = std::result::Result<Reply, CallError>;
+
+impl Default for FakeSystemd {
+    fn default() -> Self {
+        Self {
+            state: Arc::new(Mutex::new(State {
+                uid: None,
+                version: "255.4-1".to_owned(),
+                control_group: "/".to_owned(),
+                units: HashMap::new(),
+                failures: HashMap::new(),
+                calls: vec![],
+                serial: 0,
+                jobs: 0,
+            })),
+        }
+    }
+}
+
+impl FakeSystemd {
+    /// Only accepts clients which authenticate with the given uid
+    pub fn with_uid(self, uid: u32) -> Self {
+        self.state().uid = Some(uid);
+        self
+    }
+
+    pub fn with_version(self, version: &str) -> Self {
+        self.state().version = version.to_owned();
+        self
+    }
+
+    pub fn with_control_group(self, control_group: &str) -> Self {
+        self.state().control_group = control_group.to_owned();
+        self
+    }
+
+    /// Adds a unit which exists before the client connects
+    pub fn with_unit(self, name: &str) -> Self {
+        self.state().units.insert(name.to_owned(), Unit::default());
+        self
+    }
+
+    /// Adds a unit with a description, which exists before the client
+    /// connects
+    pub fn with_described_unit(self, name: &str, description: &str) -> Self {
+        let unit = Unit {
+            properties: HashMap::from([(
+                "Description".to_owned(),
+                Variant::String(description.to_owned()),
+            )]),
+            attached: vec![],
+        };
+        self.state().units.insert(name.to_owned(), unit);
+        self
+    }
+
+    /// Makes the next call of the method fail with the given error name
+    pub fn fail(&self, member: &str, error_name: &str) {
+        self.state()
+            .failures
+            .insert(member.to_owned(), error_name.to_owned());
+    }
+
+    pub fn unit(&self, name: &str) -> Option<Unit> {
+        self.state().units.get(name).cloned()
+    }
+
+    /// Members of all method calls received so far, including Hello
+    pub fn calls(&self) -> Vec<String> {
+        self.state().calls.clone()
+    }
+
+    /// Connects a client to the fake, which is served on a separate thread
+    /// until the client closes the connection
+    pub fn connect(&self, uid: u32, system: bool) -> Result<DbusConnection> {
+        let (client, server) = UnixStream::pair().expect("create socket pair");
+        let fake = self.clone();
+        thread::spawn(move || fake.serve(server));
+        DbusConnection::from_stream(client, uid, system)
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap()
+    }
+
+    fn serve(&self, stream: UnixStream) {
+        let mut reader = BufReader::new(stream.try_clone().expect("clone server stream"));
+        let mut writer = stream;
+        if !self.authenticate(&mut reader, &mut writer) {
+            return;
+        }
+
+        while let Some(call) = read_message(&mut reader) {
+            // signals and the reply are written at once, the client reads
+            // until it gets a short read
+            let mut out = vec![];
+            for message in self.handle(call) {
+                out.extend(message.serialize());
+            }
+            if writer.write_all(&out).is_err() {
+                return;
+            }
+        }
+    }
+
+    /// Server side of the EXTERNAL authentication mechanism
+    fn authenticate(&self, reader: &mut impl BufRead, writer: &mut impl Write) -> bool {
+        let mut nul = [0u8; 1];
+        if reader.read_exact(&mut nul).is_err() || nul[0] != 0 {
+            return false;
+        }
+        let auth = read_line(reader);
+        let uid = auth.strip_prefix("AUTH EXTERNAL ").and_then(decode_uid);
+        let expected = self.state().uid;
+        if uid.is_none() || (expected.is_some() && uid != expected) {
+            let _ = writer.write_all(b"REJECTED EXTERNAL\r\n");
+            return false;
+        }
+
+        if writer
+            .write_all(b"OK 2d4e1f0b6c3a8f1e9b7c5d2a00000000\r\n")
+            .is_err()
+        {
+            return false;
+        }
+        read_line(reader) == "BEGIN"
+    }
+
+    fn handle(&self, call: Message) -> Vec<Message> {
+        let path = header(&call, HeaderKind::Path).unwrap_or_default();
+        let interface = header(&call, HeaderKind::Interface).unwrap_or_default();
+        let member = header(&call, HeaderKind::Member).unwrap_or_default();
+        let mut state = self.state();
+        state.calls.push(member.clone());
+
+        let result = match state.failures.remove(&member) {
+            Some(error_name) => Err((error_name, format!("injected failure of {member}"))),
+            None => state.call(&path, &interface, &member, &call.body),
+        };
+
+        match result {
+            Ok(reply) => {
+                let mut messages = reply.signals;
+                let mut headers = reply_headers(&call);
+                if let Some(signature) = reply.signature {
+                    headers.push(string_header(HeaderKind::BodySignature, signature));
+                }
+                let serial = state.next_serial();
+                messages.push(Message::new(
+                    MessageType::MethodReturn,
+                    serial,
+                    headers,
+                    reply.body,
+                ));
+                messages
+            }
+            Err((error_name, text)) => {
+                let mut headers = reply_headers(&call);
+                headers.push(string_header(HeaderKind::ErrorName, &error_name));
+                headers.push(string_header(HeaderKind::BodySignature, "s"));
+                let mut body = vec![];
+                text.serialize(&mut body);
+                vec![Message::new(
+                    MessageType::Error,
+                    state.next_serial(),
+                    headers,
+                    body,
+                )]
+            }
+        }
+    }
+}
+
+impl State {
+    fn call(&mut self, path: &str, interface: &str, member: &str, body: &[u8]) -> CallResult {
+        match (interface, member) {
+            (BUS_NAME, "Hello") => Ok(Reply::with("s", CLIENT_NAME.to_owned())),
+            (PROPERTIES_INTERFACE, "Get") if path != SYSTEMD_PATH => {
+                let (property_interface, property): (String, String) = args(body)?;
+                let unit = self
+                    .units
+                    .iter()
+                    .find(|(name, _)| unit_path(name) == path)
+                    .map(|(_, unit)| unit)
+                    .ok_or_else(|| (NO_SUCH_UNIT.to_owned(), format!("No unit at {path}.")))?;
+                match unit.properties.get(&property) {
+                    Some(value) if property_interface == UNIT_INTERFACE => {
+                        Ok(Reply::with("v", value.clone()))
+                    }
+                    _ => Err((
+                        UNKNOWN_PROPERTY.to_owned(),
+                        format!("Unknown property {property}"),
+                    )),
+                }
+            }
+            (PROPERTIES_INTERFACE, "Get") => {
+                let (_, property): (String, String) = args(body)?;
+                let value = match property.as_str() {
+                    "Version" => self.version.clone(),
+                    "ControlGroup" => self.control_group.clone(),
+                    _ => {
+                        return Err((
+                            UNKNOWN_PROPERTY.to_owned(),
+                            format!("Unknown property {property}"),
+                        ))
+                    }
+                };
+                Ok(Reply::with("v", Variant::String(value)))
+            }
+            (MANAGER_INTERFACE, "GetUnit") => {
+                let name: String = args(body)?;
+                self.unit(&name)?;
+                Ok(Reply::with("o", unit_path(&name)))
+            }
+            (MANAGER_INTERFACE, "StartTransientUnit") => {
+                #[allow(clippy::type_complexity)]
+                let (name, _mode, properties, _aux): (
+                    String,
+                    String,
+                    Vec<Structure<Variant>>,
+                    Vec<Structure<Vec<Structure<Variant>>>>,
+                ) = args(body)?;
+                if self.units.contains_key(&name) {
+                    return Err((
+                        UNIT_EXISTS.to_owned(),
+                        format!("Unit {name} was already loaded or has a fragment file."),
+                    ));
+                }
+                let unit = Unit {
+                    properties: properties.into_iter().map(|p| (p.key, p.val)).collect(),
+                    attached: vec![],
+                };
+                self.units.insert(name.clone(), unit);
+
+                let mut reply = Reply::with("o", self.next_job());
+                reply.signals.push(self.signal("UnitNew", &name));
+                Ok(reply)
+            }
+            (MANAGER_INTERFACE, "StopUnit") => {
+                let (name, _mode): (String, String) = args(body)?;
+                self.unit(&name)?;
+                self.units.remove(&name);
+
+                let mut reply = Reply::with("o", self.next_job());
+                reply.signals.push(self.signal("UnitRemoved", &name));
+                Ok(reply)
+            }
+            (MANAGER_INTERFACE, "ResetFailedUnit") => {
+                let name: String = args(body)?;
+                self.unit(&name)?;
+                Ok(Reply::empty())
+            }
+            (MANAGER_INTERFACE, "SetUnitProperties") => {
+                let (name, _runtime, properties): (String, bool, Vec<Structure<Variant>>) =
+                    args(body)?;
+                let unit = self.unit(&name)?;
+                unit.properties
+                    .extend(properties.into_iter().map(|p| (p.key, p.val)));
+                Ok(Reply::empty())
+            }
+            (MANAGER_INTERFACE, "AttachProcessesToUnit") => {
+                let (name, subcgroup, pids): (String, String, Vec<u32>) = args(body)?;
+                let unit = self.unit(&name)?;
+                unit.attached
+                    .extend(pids.into_iter().map(|pid| (subcgroup.clone(), pid)));
+                Ok(Reply::empty())
+            }
+            _ => Err((
+                UNKNOWN_METHOD.to_owned(),
+                format!("Unknown method {member} or interface {interface}."),
+            )),
+        }
+    }
+
+    fn unit(&mut self, name: &str) -> std::result::Result<&mut Unit, CallError> {
+        self.units
+            .get_mut(name)
+            .ok_or_else(|| (NO_SUCH_UNIT.to_owned(), format!("Unit {name} not loaded.")))
+    }
+
+    fn next_serial(&mut self) -> u32 {
+        self.serial += 1;
+        self.serial
+    }
+
+    fn next_job(&mut self) -> String {
+        self.jobs += 1;
+        format!("{SYSTEMD_PATH}/job/{}", self.jobs)
+    }
+
+    /// Signal of the manager about a unit, with the unit name and object path
+    fn signal(&mut self, member: &str, name: &str) -> Message {
+        let headers = vec![
+            string_header(HeaderKind::Path, SYSTEMD_PATH),
+            string_header(HeaderKind::Interface, MANAGER_INTERFACE),
+            string_header(HeaderKind::Member, member),
+            string_header(HeaderKind::Sender, SYSTEMD_NAME),
+            string_header(HeaderKind::BodySignature, "so"),
+        ];
+        let mut body = vec![];
+        (name.to_owned(), unit_path(name)).serialize(&mut body);
+        Message::new(MessageType::Signal, self.next_serial(), headers, body)
+    }
+}
+
+fn args<T: DbusSerialize>(body: &[u8]) -> std::result::Result<T, CallError> {
+    let mut ctr = 0;
+    T::deserialize(body, &mut ctr).map_err(|err| (INVALID_ARGS.to_owned(), err.to_string()))
+}
+
+fn header(message: &Message, kind: HeaderKind) -> Option<String> {
+    message
+        .headers
+        .iter()
+        .find(|h| h.kind == kind)
+        .and_then(|h| match &h.value {
+            HeaderValue::String(s) => Some(s.clone()),
+            HeaderValue::U32(_) => None,
+        })
+}
+
+fn string_header(kind: HeaderKind, value: &str) -> Header {
+    Header {
+        kind,
+        value: HeaderValue::String(value.to_owned()),
+    }
+}
+
+fn reply_headers(call: &Message) -> Vec<Header> {
+    let sender = header(call, HeaderKind::Destination).unwrap_or_else(|| BUS_NAME.to_owned());
+    vec![
+        Header {
+            kind: HeaderKind::ReplySerial,
+            value: HeaderValue::U32(call.serial),
+        },
+        string_header(HeaderKind::Destination, CLIENT_NAME),
+        string_header(HeaderKind::Sender, &sender),
+    ]
+}
+
+/// Reads a line of the authentication protocol, without the line ending
+fn read_line(reader: &mut impl BufRead) -> String {
+    let mut line = vec![];
+    let _ = reader.read_until(b'\n', &mut line);
+    String::from_utf8_lossy(&line).trim_end().to_owned()
+}
+
+/// Reads a complete message, which is the fixed part of the header, the
+/// header fields padded to 8 bytes and the body
+fn read_message(reader: &mut impl Read) -> Option<Message> {
+    let mut buf = vec![0u8; 16];
+    reader.read_exact(&mut buf).ok()?;
+    let body_length = u32::from_le_bytes(buf[4..8].try_into().unwrap()) as usize;
+    let header_length = u32::from_le_bytes(buf[12..16].try_into().unwrap()) as usize;
+    let body_start = (16 + header_length + 7) / 8 * 8;
+    buf.resize(body_start + body_length, 0);
+    reader.read_exact(&mut buf[16..]).ok()?;
+
+    let mut ctr = 0;
+    Message::deserialize(&buf, &mut ctr).ok()
+}
+
+/// The uid is sent as hex encoded ascii digits
+fn decode_uid(hex: &str) -> Option<u32> {
+    if hex.len() % 2 != 0 {
+        return None;
+    }
+    let digits = (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok().map(char::from))
+        .collect::<Option<String>>()?;
+    digits.parse().ok()
+}
+
+/// Object path of a unit, with the characters systemd escapes in them
+fn unit_path(name: &str) -> String {
+    let escaped: String = name
+        .bytes()
+        .map(|b| match b {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' => char::from(b).to_string(),
+            _ => format!("_{b:02x}"),
+        })
+        .collect();
+    format!("{SYSTEMD_PATH}/unit/{escaped}")
+}
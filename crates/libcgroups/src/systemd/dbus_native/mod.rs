@@ -3,6 +3,8 @@
 
 pub mod client;
 pub mod dbus;
+#[cfg(test)]
+pub mod fake_systemd;
 pub mod message;
 pub mod proxy;
 pub mod serialize;
@@ -202,6 +202,14 @@ impl<'conn> Proxy<'conn> {
         )
     }
 
+    pub fn reset_failed_unit(&self, name: &str) -> Result<()> {
+        self.method_call::<_, ()>(
+            "org.freedesktop.systemd1.Manager",
+            "ResetFailedUnit",
+            Some(name.to_string()),
+        )
+    }
+
     pub fn set_unit_properties(
         &self,
         name: &str,
@@ -241,6 +249,26 @@ impl<'conn> Proxy<'conn> {
             v => panic!("control group expected string variant, got {:?} instead", v),
         }
     }
+    /// Description of the unit, the proxy has to be created for the object
+    /// path of the unit as returned by get_unit
+    pub fn unit_description(&self) -> Result<String> {
+        let t = self.method_call::<_, Variant>(
+            "org.freedesktop.DBus.Properties",
+            "Get",
+            Some((
+                "org.freedesktop.systemd1.Unit".to_string(),
+                "Description".to_string(),
+            )),
+        )?;
+        match t {
+            Variant::String(s) => Ok(s),
+            v => Err(DbusError::DeserializationError(format!(
+                "description expected string variant, got {v:?} instead"
+            ))
+            .into()),
+        }
+    }
+
     pub fn attach_process(&self, name: &str, cgroup: &str, pid: u32) -> Result<()> {
         self.method_call::<_, ()>(
             "org.freedesktop.systemd1.Manager",
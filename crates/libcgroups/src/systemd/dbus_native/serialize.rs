@@ -34,8 +34,8 @@ pub enum Variant {
 
 #[derive(Debug, PartialEq, Eq)]
 pub struct Structure<T: DbusSerialize> {
-    key: String,
-    val: T,
+    pub(crate) key: String,
+    pub(crate) val: T,
 }
 
 impl<T: DbusSerialize> Structure<T> {
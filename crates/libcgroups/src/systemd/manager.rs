@@ -1,12 +1,13 @@
 use std::collections::HashMap;
 use std::convert::Infallible;
-use std::fmt::{Debug, Display};
+use std::fmt::Debug;
 use std::fs::{self};
 use std::path::Component::RootDir;
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
 
 use nix::unistd::Pid;
-use nix::NixPath;
 
 use super::controller::Controller;
 use super::controller_type::{ControllerType, CONTROLLER_TYPES};
@@ -17,9 +18,12 @@ use super::dbus_native::dbus::DbusConnection;
 use super::dbus_native::utils::SystemdClientError;
 use super::memory::Memory;
 use super::pids::Pids;
+use super::units::{TrackedUnit, UnitTracker, UnitsError};
+use crate::capabilities::{CgroupCapabilities, ResourceType};
+use crate::cgroups_path::{CgroupsPath, CgroupsPathError};
 use crate::common::{
     self, AnyCgroupManager, CgroupManager, ControllerOpt, FreezerState, JoinSafelyError,
-    PathBufExt, WrapIoResult, WrappedIoError,
+    PathBufExt, SubtreeControlError, WrapIoResult, WrappedIoError,
 };
 use crate::stats::Stats;
 use crate::systemd::dbus_native::serialize::Variant;
@@ -27,7 +31,6 @@ use crate::systemd::unified::Unified;
 use crate::v2::manager::{Manager as FsManager, V2ManagerError};
 
 const CGROUP_CONTROLLERS: &str = "cgroup.controllers";
-const CGROUP_SUBTREE_CONTROL: &str = "cgroup.subtree_control";
 
 pub struct Manager {
     /// Root path of the cgroup hierarchy e.g. /sys/fs/cgroup
@@ -44,71 +47,40 @@ pub struct Manager {
     /// Name of the systemd unit e.g. youki-569d5ce3afe1074769f67.scope
     unit_name: String,
     /// Client for communicating with systemd
-    client: DbusConnection,
+    client: Arc<DbusConnection>,
     /// Cgroup manager for the created transient unit
     fs_manager: FsManager,
     /// Last control group which is managed by systemd, e.g. /user.slice/user-1000/user@1000.service
     delegation_boundary: PathBuf,
+    /// Records of the started units, to collect them if youki dies
+    tracker: UnitTracker,
+    /// Root directory of the container states, recorded with the unit
+    state_root: Option<PathBuf>,
 }
 
-/// Represents the systemd cgroups path:
-/// It should be of the form [slice]:[scope_prefix]:[name].
-/// The slice is the "parent" and should be expanded properly,
-/// see expand_slice below.
-#[derive(Debug)]
-struct CgroupsPath {
-    parent: String,
-    prefix: String,
-    name: String,
+/// Connection to the bus of systemd which the managers of many containers
+/// can share, see [`crate::common::CgroupHost`]
+#[derive(Clone)]
+pub struct Connection {
+    client: Arc<DbusConnection>,
 }
 
-#[derive(thiserror::Error, Debug)]
-pub enum CgroupsPathError {
-    #[error("no cgroups path has been provided")]
-    NoPath,
-    #[error("cgroups path does not contain valid utf8")]
-    InvalidUtf8(PathBuf),
-    #[error("cgroups path is malformed: {0}")]
-    MalformedPath(PathBuf),
-}
-
-impl TryFrom<&Path> for CgroupsPath {
-    type Error = CgroupsPathError;
-
-    fn try_from(cgroups_path: &Path) -> Result<Self, Self::Error> {
-        // if cgroups_path was provided it should be of the form [slice]:[prefix]:[name],
-        // for example: "system.slice:docker:1234".
-        if cgroups_path.len() == 0 {
-            return Err(CgroupsPathError::NoPath);
-        }
-
-        let parts = cgroups_path
-            .to_str()
-            .ok_or_else(|| CgroupsPathError::InvalidUtf8(cgroups_path.to_path_buf()))?
-            .split(':')
-            .collect::<Vec<&str>>();
-
-        let destructured_path = match parts.len() {
-            2 => CgroupsPath {
-                parent: "".to_owned(),
-                prefix: parts[0].to_owned(),
-                name: parts[1].to_owned(),
-            },
-            3 => CgroupsPath {
-                parent: parts[0].to_owned(),
-                prefix: parts[1].to_owned(),
-                name: parts[2].to_owned(),
-            },
-            _ => return Err(CgroupsPathError::MalformedPath(cgroups_path.to_path_buf())),
+impl Connection {
+    /// Connects to the system bus, or to the bus of the user manager for
+    /// rootless containers
+    pub fn new(use_system: bool) -> Result<Self, SystemdManagerError> {
+        let client = match use_system {
+            true => DbusConnection::new_system()?,
+            false => DbusConnection::new_session()?,
         };
 
-        Ok(destructured_path)
+        Ok(Self {
+            client: Arc::new(client),
+        })
     }
-}
 
-impl Display for CgroupsPath {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}:{}:{}", self.parent, self.prefix, self.name)
+    pub fn is_system(&self) -> bool {
+        self.client.is_system()
     }
 }
 
@@ -143,12 +115,14 @@ pub enum SystemdManagerError {
     WrappedIo(#[from] WrappedIoError),
     #[error("failed to destructure cgroups path: {0}")]
     CgroupsPath(#[from] CgroupsPathError),
-    #[error("invalid slice name: {0}")]
-    InvalidSliceName(String),
     #[error(transparent)]
     SystemdClient(#[from] SystemdClientError),
+    #[error("failed to track unit: {0}")]
+    Units(#[from] UnitsError),
     #[error("failed to join safely: {0}")]
     JoinSafely(#[from] JoinSafelyError),
+    #[error(transparent)]
+    SubtreeControl(#[from] SubtreeControlError),
     #[error("file not found: {0}")]
     FileNotFound(PathBuf),
     #[error("bad delegation boundary {boundary} for cgroups path {cgroup}")]
@@ -166,6 +140,8 @@ pub enum SystemdManagerError {
     Pids(Infallible),
     #[error("in pids unified controller: {0}")]
     Unified(#[from] super::unified::SystemdUnifiedError),
+    #[error(transparent)]
+    Rdma(#[from] crate::rdma::RdmaError),
 }
 
 impl Manager {
@@ -175,16 +151,45 @@ impl Manager {
         container_name: String,
         use_system: bool,
     ) -> Result<Self, SystemdManagerError> {
-        let mut destructured_path: CgroupsPath = cgroups_path.as_path().try_into()?;
-        ensure_parent_unit(&mut destructured_path, use_system);
+        Self::with_connection(
+            root_path,
+            cgroups_path,
+            container_name,
+            Connection::new(use_system)?,
+        )
+    }
 
-        let client = match use_system {
-            true => DbusConnection::new_system()?,
-            false => DbusConnection::new_session()?,
-        };
+    /// Creates a manager which talks to systemd over a connection it shares
+    /// with other managers
+    pub fn with_connection(
+        root_path: PathBuf,
+        cgroups_path: PathBuf,
+        container_name: String,
+        connection: Connection,
+    ) -> Result<Self, SystemdManagerError> {
+        let tracker = UnitTracker::for_instance(connection.is_system());
+        Self::with_client(
+            root_path,
+            cgroups_path,
+            container_name,
+            connection.client,
+            tracker,
+        )
+    }
+
+    /// Creates a manager which talks to systemd over the given connection
+    fn with_client(
+        root_path: PathBuf,
+        cgroups_path: PathBuf,
+        container_name: String,
+        client: Arc<DbusConnection>,
+        tracker: UnitTracker,
+    ) -> Result<Self, SystemdManagerError> {
+        let mut destructured_path: CgroupsPath = cgroups_path.as_path().try_into()?;
+        ensure_parent_unit(&mut destructured_path, client.is_system());
 
         let (cgroups_path, delegation_boundary) =
-            Self::construct_cgroups_path(&destructured_path, &client)?;
+            Self::construct_cgroups_path(&destructured_path, client.as_ref())?;
         let full_path = root_path.join_safely(&cgroups_path)?;
         let fs_manager = FsManager::new(root_path.clone(), cgroups_path.clone())?;
 
@@ -193,22 +198,21 @@ impl Manager {
             cgroups_path,
             full_path,
             container_name,
-            unit_name: Self::get_unit_name(&destructured_path),
+            unit_name: destructured_path.unit_name(),
             destructured_path,
             client,
             fs_manager,
             delegation_boundary,
+            tracker,
+            state_root: None,
         })
     }
 
-    /// get_unit_name returns the unit (scope) name from the path provided by the user
-    /// for example: foo:docker:bar returns in '/docker-bar.scope'
-    fn get_unit_name(cgroups_path: &CgroupsPath) -> String {
-        // By default we create a scope unless specified explicitly.
-        if !cgroups_path.name.ends_with(".slice") {
-            return format!("{}-{}.scope", cgroups_path.prefix, cgroups_path.name);
-        }
-        cgroups_path.name.clone()
+    /// Records the units with the root directory of the container states, so
+    /// that purging a root only collects its own units
+    pub fn with_state_root(mut self, state_root: Option<PathBuf>) -> Self {
+        self.state_root = state_root;
+        self
     }
 
     // get_cgroups_path generates a cgroups path from the one provided by the user via cgroupsPath.
@@ -221,53 +225,32 @@ impl Manager {
         // if the user provided a '.slice' (as in a branch of a tree)
         // we need to convert it to a filesystem path.
 
-        let parent = Self::expand_slice(&cgroups_path.parent)?;
         let systemd_root = client.control_cgroup_root()?;
-        let unit_name = Self::get_unit_name(cgroups_path);
-
-        let cgroups_path = systemd_root.join_safely(parent)?.join_safely(unit_name)?;
+        let cgroups_path = systemd_root.join_safely(cgroups_path.unit_path()?)?;
         Ok((cgroups_path, systemd_root))
     }
 
-    // systemd represents slice hierarchy using `-`, so we need to follow suit when
-    // generating the path of slice. For example, 'test-a-b.slice' becomes
-    // '/test.slice/test-a.slice/test-a-b.slice'.
-    fn expand_slice(slice: &str) -> Result<PathBuf, SystemdManagerError> {
-        let suffix = ".slice";
-        if slice.len() <= suffix.len() || !slice.ends_with(suffix) {
-            return Err(SystemdManagerError::InvalidSliceName(slice.into()));
-        }
-        if slice.contains('/') {
-            return Err(SystemdManagerError::InvalidSliceName(slice.into()));
-        }
-        let mut path = "".to_owned();
-        let mut prefix = "".to_owned();
-        let slice_name = slice.trim_end_matches(suffix);
-        // if input was -.slice, we should just return root now
-        if slice_name == "-" {
-            return Ok(Path::new("/").to_path_buf());
-        }
-        for component in slice_name.split('-') {
-            if component.is_empty() {
-                return Err(SystemdManagerError::InvalidSliceName(slice.into()));
-            }
-            // Append the component to the path and to the prefix.
-            path = format!("{path}/{prefix}{component}{suffix}");
-            prefix = format!("{prefix}{component}-");
-        }
-        Ok(Path::new(&path).to_path_buf())
-    }
-
     /// ensures that each level in the downward path from the delegation boundary down to
     /// the scope or slice of the transient unit has all available controllers enabled
     fn ensure_controllers_attached(&self) -> Result<(), SystemdManagerError> {
         let full_boundary_path = self.root_path.join_safely(&self.delegation_boundary)?;
 
-        let controllers: Vec<String> = self
-            .get_available_controllers(&full_boundary_path)?
-            .into_iter()
-            .map(|c| format!("{}{}", "+", c))
-            .collect();
+        let available = self.get_available_controllers(&full_boundary_path)?;
+        // user managers only get the controllers delegated to user@.service,
+        // systemd silently ignores the limits of all others
+        if !self.client.is_system() {
+            for controller in ["cpu", "memory", "pids"] {
+                if !available.iter().any(|c| c.as_ref() == controller) {
+                    tracing::warn!(
+                        controller,
+                        boundary = ?self.delegation_boundary,
+                        "controller is not delegated to the systemd user manager, its limits are not enforced"
+                    );
+                }
+            }
+        }
+
+        let controllers: Vec<String> = available.into_iter().map(|c| c.to_string()).collect();
 
         Self::write_controllers(&full_boundary_path, &controllers)?;
 
@@ -332,7 +315,7 @@ impl Manager {
 
     fn write_controllers(path: &Path, controllers: &[String]) -> Result<(), SystemdManagerError> {
         for controller in controllers {
-            common::write_cgroup_file_str(path.join(CGROUP_SUBTREE_CONTROL), controller)?;
+            common::enable_controller(path, controller)?;
         }
 
         Ok(())
@@ -358,6 +341,12 @@ impl CgroupManager for Manager {
             return Ok(());
         }
 
+        // recorded first, a unit may be started even if the call fails
+        self.tracker.track(&TrackedUnit {
+            unit_name: self.unit_name.clone(),
+            container_name: self.container_name.clone(),
+            state_root: self.state_root.clone(),
+        })?;
         tracing::debug!("Starting {:?}", self.unit_name);
         self.client.start_transient_unit(
             &self.container_name,
@@ -373,6 +362,7 @@ impl CgroupManager for Manager {
         let mut properties: HashMap<&str, Variant> = HashMap::new();
         let systemd_version = self.client.systemd_version()?;
 
+        controller_opt.warn_ignored_network();
         for controller in CONTROLLER_TYPES {
             match controller {
                 ControllerType::Cpu => {
@@ -404,6 +394,35 @@ impl CgroupManager for Manager {
                 .set_unit_properties(&self.unit_name, &properties)?;
         }
 
+        // systemd has no property for the burst, it is written after the
+        // quota as it must not exceed it
+        let burst = controller_opt
+            .resources
+            .cpu()
+            .as_ref()
+            .and_then(|cpu| cpu.burst());
+        if let Some(burst) = burst {
+            common::write_cgroup_file(self.full_path.join("cpu.max.burst"), burst)?;
+        }
+        // nor for cpu.idle before systemd 252
+        let idle = controller_opt
+            .resources
+            .cpu()
+            .as_ref()
+            .and_then(|cpu| cpu.idle());
+        if let Some(idle) = idle {
+            let idle_file = self.full_path.join("cpu.idle");
+            if idle_file.exists() {
+                common::write_cgroup_file(idle_file, idle)?;
+            } else if idle != 0 {
+                tracing::warn!("cpu.idle is not supported by the kernel, ignoring it");
+            }
+        }
+        // nor for the rdma limits
+        if let Some(limits) = controller_opt.resources.rdma() {
+            crate::rdma::apply(&self.full_path, limits)?;
+        }
+
         Ok(())
     }
 
@@ -412,12 +431,17 @@ impl CgroupManager for Manager {
         if self.client.transient_unit_exists(&self.unit_name) {
             self.client.stop_transient_unit(&self.unit_name)?;
         }
+        self.tracker.untrack(&self.unit_name)?;
 
         Ok(())
     }
 
-    fn freeze(&self, state: FreezerState) -> Result<(), Self::Error> {
-        Ok(self.fs_manager.freeze(state)?)
+    fn freeze_with_timeout(
+        &self,
+        state: FreezerState,
+        timeout: Duration,
+    ) -> Result<(), Self::Error> {
+        Ok(self.fs_manager.freeze_with_timeout(state, timeout)?)
     }
 
     fn stats(&self) -> Result<Stats, Self::Error> {
@@ -427,15 +451,39 @@ impl CgroupManager for Manager {
     fn get_all_pids(&self) -> Result<Vec<Pid>, Self::Error> {
         Ok(common::get_all_pids(&self.full_path)?)
     }
+
+    fn capabilities(&self) -> Result<CgroupCapabilities, Self::Error> {
+        // apply only translates these into unit properties, anything else
+        // would be silently dropped
+        let handled = CgroupCapabilities::new([
+            ResourceType::Cpu,
+            ResourceType::CpuSet,
+            ResourceType::Memory,
+            ResourceType::Pids,
+            ResourceType::Rdma,
+            ResourceType::Unified,
+        ]);
+        Ok(self.fs_manager.capabilities()?.intersection(&handled))
+    }
+
+    fn reclaim(&self, bytes: u64) -> Result<(), Self::Error> {
+        Ok(self.fs_manager.reclaim(bytes)?)
+    }
+
+    fn kill(&self) -> Result<bool, Self::Error> {
+        Ok(self.fs_manager.kill()?)
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use anyhow::{Context, Result};
+    use oci_spec::runtime::{LinuxPidsBuilder, LinuxResourcesBuilder};
 
     use super::*;
     use crate::common::DEFAULT_CGROUP_ROOT;
     use crate::systemd::dbus_native::client::SystemdClient;
+    use crate::systemd::dbus_native::fake_systemd::FakeSystemd;
     use crate::systemd::dbus_native::serialize::Variant;
     use crate::systemd::dbus_native::utils::SystemdClientError;
 
@@ -464,6 +512,14 @@ mod tests {
             Ok(())
         }
 
+        fn reset_failed_unit(&self, _unit_name: &str) -> Result<(), SystemdClientError> {
+            Ok(())
+        }
+
+        fn unit_description(&self, _unit_name: &str) -> Result<String, SystemdClientError> {
+            Ok(String::new())
+        }
+
         fn set_unit_properties(
             &self,
             _unit_name: &str,
@@ -490,16 +546,6 @@ mod tests {
         }
     }
 
-    #[test]
-    fn expand_slice_works() -> Result<()> {
-        assert_eq!(
-            Manager::expand_slice("test-a-b.slice")?,
-            PathBuf::from("/test.slice/test-a.slice/test-a-b.slice"),
-        );
-
-        Ok(())
-    }
-
     #[test]
     fn get_cgroups_path_works_with_a_complex_slice() -> Result<()> {
         let cgroups_path = Path::new("test-a-b.slice:docker:foo")
@@ -542,6 +588,145 @@ mod tests {
 
         Ok(())
     }
+    #[test]
+    fn test_manager_with_fake_systemd() -> Result<()> {
+        let root = tempfile::tempdir()?;
+        crate::test::set_fixture(root.path(), CGROUP_CONTROLLERS, "cpu memory pids")?;
+        crate::test::set_fixture(root.path(), "cgroup.subtree_control", "")?;
+
+        let systemd = FakeSystemd::default();
+        let tracker = UnitTracker::new(root.path().join("units"));
+        let manager = Manager::with_client(
+            root.path().to_path_buf(),
+            "machine.slice:libpod:foo".into(),
+            "foo".into(),
+            Arc::new(systemd.connect(0, true)?),
+            tracker.clone(),
+        )?
+        .with_state_root(Some("/run/youki".into()));
+        assert_eq!(
+            manager.cgroups_path,
+            PathBuf::from("/machine.slice/libpod-foo.scope")
+        );
+
+        manager.add_task(Pid::from_raw(1000))?;
+        let unit = systemd.unit("libpod-foo.scope").context("unit started")?;
+        assert_eq!(
+            tracker.tracked()?,
+            vec![TrackedUnit {
+                unit_name: "libpod-foo.scope".into(),
+                container_name: "foo".into(),
+                state_root: Some("/run/youki".into()),
+            }]
+        );
+        assert_eq!(
+            unit.properties["Slice"],
+            Variant::String("machine.slice".into())
+        );
+        assert_eq!(unit.properties["Delegate"], Variant::Bool(true));
+        assert_eq!(unit.properties["PIDs"], Variant::ArrayU32(vec![1000]));
+
+        // the unit exists now, so further processes are attached to it
+        manager.add_task(Pid::from_raw(1001))?;
+        let unit = systemd.unit("libpod-foo.scope").context("unit exists")?;
+        assert_eq!(unit.attached, vec![(String::new(), 1001)]);
+
+        let resources = LinuxResourcesBuilder::default()
+            .pids(LinuxPidsBuilder::default().limit(42).build()?)
+            .build()?;
+        manager.apply(&ControllerOpt {
+            resources: &resources,
+            disable_oom_killer: false,
+            oom_score_adj: None,
+            freezer_state: None,
+        })?;
+        let unit = systemd.unit("libpod-foo.scope").context("unit exists")?;
+        assert_eq!(unit.properties["TasksMax"], Variant::U64(42));
+
+        manager.remove()?;
+        assert!(systemd.unit("libpod-foo.scope").is_none());
+        assert!(tracker.tracked()?.is_empty());
+        assert_eq!(
+            systemd.calls(),
+            [
+                "Hello",
+                "Get",
+                "GetUnit",
+                "StartTransientUnit",
+                "GetUnit",
+                "AttachProcessesToUnit",
+                "Get",
+                "SetUnitProperties",
+                "GetUnit",
+                "StopUnit",
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_managers_share_connection() -> Result<()> {
+        let root = tempfile::tempdir()?;
+        crate::test::set_fixture(root.path(), CGROUP_CONTROLLERS, "cpu memory pids")?;
+        crate::test::set_fixture(root.path(), "cgroup.subtree_control", "")?;
+
+        let systemd = FakeSystemd::default();
+        let connection = Connection {
+            client: Arc::new(systemd.connect(0, true)?),
+        };
+        let tracker = UnitTracker::new(root.path().join("units"));
+        for name in ["a", "b"] {
+            let manager = Manager::with_client(
+                root.path().to_path_buf(),
+                format!("machine.slice:libpod:{name}").into(),
+                name.into(),
+                connection.client.clone(),
+                tracker.clone(),
+            )?;
+            manager.add_task(Pid::from_raw(1000))?;
+        }
+
+        assert!(systemd.unit("libpod-a.scope").is_some());
+        assert!(systemd.unit("libpod-b.scope").is_some());
+        let hellos = systemd.calls().iter().filter(|c| *c == "Hello").count();
+        assert_eq!(hellos, 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_manager_with_fake_systemd_errors() -> Result<()> {
+        let root = tempfile::tempdir()?;
+        let systemd = FakeSystemd::default().with_control_group("/user.slice");
+        let manager = Manager::with_client(
+            root.path().to_path_buf(),
+            ":youki:foo".into(),
+            "foo".into(),
+            Arc::new(systemd.connect(1000, false)?),
+            UnitTracker::new(root.path().join("units")),
+        )?;
+        assert_eq!(
+            manager.cgroups_path,
+            PathBuf::from("/user.slice/user.slice/youki-foo.scope")
+        );
+
+        systemd.fail(
+            "StartTransientUnit",
+            "org.freedesktop.DBus.Error.AccessDenied",
+        );
+        let err = manager.add_task(Pid::from_raw(1000)).unwrap_err();
+        assert!(matches!(
+            err,
+            SystemdManagerError::SystemdClient(SystemdClientError::FailedTransient { .. })
+        ));
+        assert!(systemd.unit("youki-foo.scope").is_none());
+
+        // removing a unit which was never started is not an error
+        manager.remove()?;
+
+        Ok(())
+    }
+
     #[test]
     fn test_task_addition() {
         let manager = Manager::new(
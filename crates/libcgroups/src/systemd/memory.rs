@@ -11,6 +11,7 @@ pub const MEMORY_LOW: &str = "MemoryLow";
 pub const MEMORY_HIGH: &str = "MemoryHigh";
 pub const MEMORY_MAX: &str = "MemoryMax";
 pub const MEMORY_SWAP: &str = "MemorySwapMax";
+pub const OOM_POLICY: &str = "OOMPolicy";
 
 #[derive(thiserror::Error, Debug)]
 pub enum SystemdMemoryError {
@@ -9,6 +9,7 @@ pub mod manager;
 mod memory;
 mod pids;
 mod unified;
+pub mod units;
 
 /// Checks if the system was booted with systemd
 pub fn booted() -> bool {
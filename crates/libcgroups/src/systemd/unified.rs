@@ -30,6 +30,12 @@ pub enum SystemdUnifiedError {
     },
     #[error("failed to to parse pids.max {value}: {err}")]
     PidsMax { err: ParseIntError, value: String },
+    #[error("setting {name} requires systemd version {version} or later")]
+    SystemdVersion { name: String, version: u32 },
+    #[error("invalid value for memory.oom.group {0:?}")]
+    OomGroup(String),
+    #[error("{0} is not supported with the systemd cgroup driver")]
+    Unsupported(String),
 }
 
 pub struct Unified {}
@@ -117,14 +123,18 @@ impl Unified {
                     properties.insert(systemd_cpuset, Variant::ArrayU64(bitmask));
                 }
                 memory @ ("memory.min" | "memory.low" | "memory.high" | "memory.max") => {
-                    let value =
+                    // systemd uses u64::MAX for infinity
+                    let value = if value.trim() == "max" {
+                        u64::MAX
+                    } else {
                         value
                             .parse::<u64>()
                             .map_err(|err| SystemdUnifiedError::Memory {
                                 err,
                                 name: memory.into(),
                                 value: value.into(),
-                            })?;
+                            })?
+                    };
                     let systemd_memory = match memory {
                         "memory.min" => memory::MEMORY_MIN,
                         "memory.low" => memory::MEMORY_LOW,
@@ -134,6 +144,22 @@ impl Unified {
                     };
                     properties.insert(systemd_memory, Variant::U64(value));
                 }
+                // systemd writes memory.oom.group for the kill policy
+                oom_group @ "memory.oom.group" => {
+                    if systemd_version < 253 {
+                        return Err(SystemdUnifiedError::SystemdVersion {
+                            name: oom_group.into(),
+                            version: 253,
+                        });
+                    }
+
+                    let policy = match value.trim() {
+                        "1" => "kill",
+                        "0" => "continue",
+                        _ => return Err(SystemdUnifiedError::OomGroup(value.into())),
+                    };
+                    properties.insert(memory::OOM_POLICY, Variant::String(policy.into()));
+                }
                 "pids.max" => {
                     let pids = value.trim().parse::<i64>().map_err(|err| {
                         SystemdUnifiedError::PidsMax {
@@ -144,6 +170,12 @@ impl Unified {
                     properties.insert(pids::TASKS_MAX, Variant::U64(pids as u64));
                 }
 
+                // systemd has no property for it, and a container which asks
+                // for exclusive cpus must not run without them
+                partition @ "cpuset.cpus.partition" => {
+                    return Err(SystemdUnifiedError::Unsupported(partition.into()));
+                }
+
                 unknown => tracing::warn!("could not apply {}. Unknown property.", unknown),
             }
         }
@@ -249,4 +281,30 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_oom_group() -> Result<()> {
+        let unified: HashMap<String, String> =
+            HashMap::from([("memory.oom.group".to_owned(), "1".to_owned())]);
+        let mut actual: HashMap<&str, Variant> = HashMap::new();
+        Unified::apply(&unified, 253, &mut actual).context("apply unified")?;
+        let policy = &actual[memory::OOM_POLICY];
+        assert_eq!(
+            recast!(policy, Variant)?,
+            Variant::String("kill".to_owned())
+        );
+
+        assert!(matches!(
+            Unified::apply(&unified, 252, &mut actual),
+            Err(SystemdUnifiedError::SystemdVersion { version: 253, .. })
+        ));
+        let unified: HashMap<String, String> =
+            HashMap::from([("memory.oom.group".to_owned(), "yes".to_owned())]);
+        assert!(matches!(
+            Unified::apply(&unified, 253, &mut actual),
+            Err(SystemdUnifiedError::OomGroup(_))
+        ));
+
+        Ok(())
+    }
 }
@@ -0,0 +1,292 @@
+//! Bookkeeping of the transient units youki starts. Nothing stops the unit
+//! of a container if youki dies while the container is created, e.g. because
+//! it was killed, and the container state is gone as well. Every unit is
+//! therefore recorded before it is started and forgotten once it is stopped,
+//! so that the units of containers which no longer exist can be collected
+//! later on. A unit is recorded with the root directory of the container
+//! states, so that only units of containers kept in the same root are
+//! collected.
+use std::ffi::OsStr;
+use std::fs;
+use std::io::ErrorKind;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+
+use super::dbus_native::client::SystemdClient;
+use super::dbus_native::dbus::DbusConnection;
+use super::dbus_native::utils::SystemdClientError;
+use crate::common::{self, WrapIoResult, WrappedIoError};
+
+#[derive(thiserror::Error, Debug)]
+pub enum UnitsError {
+    #[error("io error: {0}")]
+    WrappedIo(#[from] WrappedIoError),
+    #[error(transparent)]
+    SystemdClient(#[from] SystemdClientError),
+}
+
+/// Description youki gives the unit of a container, which tells apart its
+/// units from units of others with the same name
+pub(crate) fn unit_description(container_name: &str) -> String {
+    format!("youki container {container_name}")
+}
+
+/// Unit youki started for a container
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TrackedUnit {
+    pub unit_name: String,
+    pub container_name: String,
+    /// Root directory of the container states the container is kept in,
+    /// None if the unit was recorded without one
+    pub state_root: Option<PathBuf>,
+}
+
+/// Records of the started units, a file per unit which is named after the
+/// unit and contains the name of the container and, on a second line, the
+/// state root
+#[derive(Debug, Clone)]
+pub struct UnitTracker {
+    dir: PathBuf,
+}
+
+impl UnitTracker {
+    pub fn new<P: Into<PathBuf>>(dir: P) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    /// Records of the units of the system or of the user instance of
+    /// systemd. They are kept below /run, which does not outlive the units.
+    pub fn for_instance(system: bool) -> Self {
+        if system {
+            return Self::new("/run/youki-systemd");
+        }
+
+        let runtime_dir = std::env::var_os("XDG_RUNTIME_DIR")
+            .map(PathBuf::from)
+            .unwrap_or_else(|| PathBuf::from(format!("/run/user/{}", nix::unistd::getuid())));
+        Self::new(runtime_dir.join("youki-systemd"))
+    }
+
+    pub fn track(&self, unit: &TrackedUnit) -> Result<(), UnitsError> {
+        fs::create_dir_all(&self.dir).wrap_create_dir(&self.dir)?;
+        let record = self.dir.join(&unit.unit_name);
+        let mut content = unit.container_name.as_bytes().to_vec();
+        if let Some(state_root) = &unit.state_root {
+            content.push(b'\n');
+            content.extend_from_slice(state_root.as_os_str().as_bytes());
+        }
+        fs::write(&record, content).wrap_write(&record, &*unit.container_name)?;
+        Ok(())
+    }
+
+    pub fn untrack(&self, unit_name: &str) -> Result<(), UnitsError> {
+        let record = self.dir.join(unit_name);
+        match fs::remove_file(&record) {
+            Err(err) if err.kind() != ErrorKind::NotFound => {
+                Err(WrappedIoError::Other { err, path: record }.into())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    pub fn tracked(&self) -> Result<Vec<TrackedUnit>, UnitsError> {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => {
+                return Err(WrappedIoError::Read {
+                    err,
+                    path: self.dir.clone(),
+                }
+                .into())
+            }
+        };
+
+        let mut units = Vec::new();
+        for entry in entries {
+            let record = entry.wrap_read(&self.dir)?.path();
+            let unit_name = match record.file_name().and_then(|name| name.to_str()) {
+                Some(unit_name) => unit_name.to_owned(),
+                None => continue,
+            };
+            let content = fs::read(&record).wrap_read(&record)?;
+            let (container_name, state_root) = match content.iter().position(|b| *b == b'\n') {
+                Some(end) => (
+                    &content[..end],
+                    Some(PathBuf::from(OsStr::from_bytes(&content[end + 1..]))),
+                ),
+                None => (&content[..], None),
+            };
+            units.push(TrackedUnit {
+                unit_name,
+                container_name: String::from_utf8_lossy(container_name).into_owned(),
+                state_root,
+            });
+        }
+        units.sort_by(|a, b| a.unit_name.cmp(&b.unit_name));
+
+        Ok(units)
+    }
+}
+
+/// Stops and resets the units of containers of the state root which no
+/// longer exist and returns their names. Units of containers kept in another
+/// root, or recorded without one, are left alone, as is a unit with the name
+/// of a recorded one which was not started by youki for the container.
+pub fn collect_stale_units<F: Fn(&str) -> bool>(
+    client: &dyn SystemdClient,
+    tracker: &UnitTracker,
+    state_root: &Path,
+    container_exists: F,
+) -> Result<Vec<String>, UnitsError> {
+    let mut collected = Vec::new();
+    for unit in tracker.tracked()? {
+        if unit.state_root.as_deref() != Some(state_root) {
+            tracing::debug!(
+                unit = unit.unit_name,
+                state_root = ?unit.state_root,
+                "unit belongs to another state root"
+            );
+            continue;
+        }
+        if container_exists(&unit.container_name) {
+            continue;
+        }
+
+        if client.transient_unit_exists(&unit.unit_name) {
+            let description = client.unit_description(&unit.unit_name)?;
+            if description == unit_description(&unit.container_name) {
+                tracing::info!(
+                    unit = unit.unit_name,
+                    container = unit.container_name,
+                    "stopping stale unit"
+                );
+                client.stop_transient_unit(&unit.unit_name)?;
+                if let Err(err) = client.reset_failed_unit(&unit.unit_name) {
+                    // systemd unloads units which did not fail by itself
+                    tracing::debug!(?err, unit = unit.unit_name, "failed to reset unit");
+                }
+                collected.push(unit.unit_name.clone());
+            } else {
+                tracing::warn!(
+                    unit = unit.unit_name,
+                    description,
+                    "unit was not started for the container, leaving it alone"
+                );
+            }
+        }
+        tracker.untrack(&unit.unit_name)?;
+    }
+
+    Ok(collected)
+}
+
+/// Collects the stale units of the state root in the instance of systemd the
+/// containers of the current user are managed by, see [`collect_stale_units`]
+pub fn purge<F: Fn(&str) -> bool>(
+    state_root: &Path,
+    container_exists: F,
+) -> Result<Vec<String>, UnitsError> {
+    let system = common::is_true_root()?;
+    let client = match system {
+        true => DbusConnection::new_system()?,
+        false => DbusConnection::new_session()?,
+    };
+
+    collect_stale_units(
+        &client,
+        &UnitTracker::for_instance(system),
+        state_root,
+        container_exists,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+
+    use super::*;
+    use crate::systemd::dbus_native::fake_systemd::FakeSystemd;
+
+    #[test]
+    fn test_track_units() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let tracker = UnitTracker::new(tmp.path().join("units"));
+        assert!(tracker.tracked()?.is_empty());
+
+        let unit = TrackedUnit {
+            unit_name: "youki-foo.scope".to_owned(),
+            container_name: "foo".to_owned(),
+            state_root: Some(PathBuf::from("/run/youki")),
+        };
+        tracker.track(&unit)?;
+        assert_eq!(tracker.tracked()?, vec![unit]);
+
+        // records of older versions have no state root
+        fs::write(tmp.path().join("units/youki-bar.scope"), "bar")?;
+        assert_eq!(tracker.tracked()?[0].container_name, "bar");
+        assert_eq!(tracker.tracked()?[0].state_root, None);
+        tracker.untrack("youki-bar.scope")?;
+
+        tracker.untrack("youki-foo.scope")?;
+        assert!(tracker.tracked()?.is_empty());
+        // forgetting a unit twice is fine
+        tracker.untrack("youki-foo.scope")?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_collect_stale_units() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let tracker = UnitTracker::new(tmp.path());
+        let state_root = Path::new("/run/youki");
+        for (unit_name, container_name, root) in [
+            ("youki-alive.scope", "alive", Some(state_root)),
+            ("youki-stale.scope", "stale", Some(state_root)),
+            ("youki-stopped.scope", "stopped", Some(state_root)),
+            ("youki-reused.scope", "reused", Some(state_root)),
+            ("youki-other.scope", "other", Some(Path::new("/run/other"))),
+            ("youki-unknown.scope", "unknown", None),
+        ] {
+            tracker.track(&TrackedUnit {
+                unit_name: unit_name.to_owned(),
+                container_name: container_name.to_owned(),
+                state_root: root.map(Path::to_path_buf),
+            })?;
+        }
+
+        let systemd = FakeSystemd::default()
+            .with_described_unit("youki-alive.scope", &unit_description("alive"))
+            .with_described_unit("youki-stale.scope", &unit_description("stale"))
+            .with_described_unit("youki-reused.scope", "someone else")
+            .with_described_unit("youki-other.scope", &unit_description("other"))
+            .with_described_unit("youki-unknown.scope", &unit_description("unknown"));
+        let client = systemd.connect(0, true)?;
+
+        let collected = collect_stale_units(&client, &tracker, state_root, |name| name == "alive")?;
+        assert_eq!(collected, vec!["youki-stale.scope"]);
+        assert!(systemd.unit("youki-alive.scope").is_some());
+        assert!(systemd.unit("youki-stale.scope").is_none());
+        assert!(systemd.unit("youki-reused.scope").is_some());
+        // the containers of other roots do not exist in this one
+        assert!(systemd.unit("youki-other.scope").is_some());
+        assert!(systemd.unit("youki-unknown.scope").is_some());
+
+        let tracked: Vec<_> = tracker
+            .tracked()?
+            .into_iter()
+            .map(|unit| unit.unit_name)
+            .collect();
+        assert_eq!(
+            tracked,
+            vec![
+                "youki-alive.scope",
+                "youki-other.scope",
+                "youki-unknown.scope"
+            ]
+        );
+
+        Ok(())
+    }
+}
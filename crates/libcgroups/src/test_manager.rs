@@ -3,6 +3,7 @@ use std::convert::Infallible;
 
 use nix::unistd::Pid;
 
+use crate::capabilities::CgroupCapabilities;
 use crate::common::{CgroupManager, ControllerOpt, FreezerState};
 use crate::stats::Stats;
 
@@ -39,7 +40,11 @@ impl CgroupManager for TestManager {
         unimplemented!()
     }
 
-    fn freeze(&self, _state: FreezerState) -> Result<(), Infallible> {
+    fn freeze_with_timeout(
+        &self,
+        _state: FreezerState,
+        _timeout: std::time::Duration,
+    ) -> Result<(), Infallible> {
         unimplemented!()
     }
 
@@ -50,6 +55,18 @@ impl CgroupManager for TestManager {
     fn get_all_pids(&self) -> Result<Vec<Pid>, Infallible> {
         unimplemented!()
     }
+
+    fn capabilities(&self) -> Result<CgroupCapabilities, Infallible> {
+        unimplemented!()
+    }
+
+    fn reclaim(&self, _bytes: u64) -> Result<(), Infallible> {
+        unimplemented!()
+    }
+
+    fn kill(&self) -> Result<bool, Infallible> {
+        unimplemented!()
+    }
 }
 
 impl TestManager {
@@ -5,7 +5,9 @@ use oci_spec::runtime::LinuxBlockIo;
 
 use super::controller::Controller;
 use crate::common::{self, ControllerOpt, WrappedIoError};
-use crate::stats::{self, BlkioDeviceStat, BlkioStats, ParseDeviceNumberError, StatsProvider};
+use crate::stats::{
+    self, BlkioDeviceStat, BlkioStats, CgroupDir, ParseDeviceNumberError, StatsProvider,
+};
 
 // Throttling/upper limit policy
 // ---------------------------------------
@@ -40,8 +42,14 @@ const BLKIO_BFQ_WEIGHT: &str = "blkio.bfq.weight";
 // Specifies the relative proportion of block I/O access for specific devices available
 // to the cgroup. This overrides the the blkio.weight value for the specified device
 // Format: Major:Minor weight (weight can range from 100 to 1000)
-#[allow(dead_code)]
 const BLKIO_WEIGHT_DEVICE: &str = "blkio.weight_device";
+// Similar to BLKIO_WEIGHT_DEVICE, but for the BFQ I/O scheduler, see BLKIO_BFQ_WEIGHT
+// Format: Major:Minor weight (weight can range from 1 to 1000)
+const BLKIO_BFQ_WEIGHT_DEVICE: &str = "blkio.bfq.weight_device";
+// Weight of the tasks of the cgroup for specific devices, relative to its child cgroups,
+// only available with the CFQ I/O scheduler
+// Format: Major:Minor weight (weight can range from 10 to 1000)
+const BLKIO_LEAF_WEIGHT_DEVICE: &str = "blkio.leaf_weight_device";
 
 // Common parameters which may be used for either policy but seem to be used only for
 // proportional weight division policy in practice
@@ -110,12 +118,12 @@ impl StatsProvider for Blkio {
     type Error = V1BlkioStatsError;
     type Stats = BlkioStats;
 
-    fn stats(cgroup_path: &Path) -> Result<Self::Stats, Self::Error> {
-        if cgroup_path.join(BLKIO_WEIGHT).exists() {
-            return Self::get_weight_division_policy_stats(cgroup_path);
+    fn stats(dir: &mut CgroupDir) -> Result<Self::Stats, Self::Error> {
+        if dir.exists(BLKIO_WEIGHT) {
+            return Self::get_weight_division_policy_stats(dir);
         }
 
-        Self::get_throttling_policy_stats(cgroup_path)
+        Self::get_throttling_policy_stats(dir)
     }
 }
 
@@ -134,6 +142,40 @@ impl Blkio {
             }
         }
 
+        // the weights of devices override the weight of the cgroup, they are
+        // set one device at a time and a weight of 0 removes the override
+        if let Some(weight_device) = blkio.weight_device() {
+            let mut weight_file = root_path.join(BLKIO_WEIGHT_DEVICE);
+            if !weight_file.exists() {
+                weight_file = root_path.join(BLKIO_BFQ_WEIGHT_DEVICE);
+            }
+            let leaf_weight_file = root_path.join(BLKIO_LEAF_WEIGHT_DEVICE);
+            for wd in weight_device {
+                if let Some(weight) = wd.weight() {
+                    common::write_cgroup_file_str(
+                        &weight_file,
+                        &format!("{}:{} {}", wd.major(), wd.minor(), weight),
+                    )?;
+                }
+                if let Some(leaf_weight) = wd.leaf_weight() {
+                    // only CFQ has leaf weights, which is gone with blk-mq
+                    if leaf_weight_file.exists() {
+                        common::write_cgroup_file_str(
+                            &leaf_weight_file,
+                            &format!("{}:{} {}", wd.major(), wd.minor(), leaf_weight),
+                        )?;
+                    } else {
+                        tracing::warn!(
+                            major = wd.major(),
+                            minor = wd.minor(),
+                            leaf_weight,
+                            "ignoring leaf weight of device, the I/O scheduler has no leaf weights"
+                        );
+                    }
+                }
+            }
+        }
+
         if let Some(throttle_read_bps_device) = blkio.throttle_read_bps_device().as_ref() {
             for trbd in throttle_read_bps_device {
                 common::write_cgroup_file_str(
@@ -173,12 +215,10 @@ impl Blkio {
         Ok(())
     }
 
-    fn get_throttling_policy_stats(cgroup_path: &Path) -> Result<BlkioStats, V1BlkioStatsError> {
+    fn get_throttling_policy_stats(dir: &mut CgroupDir) -> Result<BlkioStats, V1BlkioStatsError> {
         let stats = BlkioStats {
-            service_bytes: Self::parse_blkio_file(
-                &cgroup_path.join(BLKIO_THROTTLE_IO_SERVICE_BYTES),
-            )?,
-            serviced: Self::parse_blkio_file(&cgroup_path.join(BLKIO_THROTTLE_IO_SERVICED))?,
+            service_bytes: Self::parse_blkio_file(dir, BLKIO_THROTTLE_IO_SERVICE_BYTES)?,
+            serviced: Self::parse_blkio_file(dir, BLKIO_THROTTLE_IO_SERVICED)?,
             ..Default::default()
         };
 
@@ -186,25 +226,29 @@ impl Blkio {
     }
 
     fn get_weight_division_policy_stats(
-        cgroup_path: &Path,
+        dir: &mut CgroupDir,
     ) -> Result<BlkioStats, V1BlkioStatsError> {
         let stats = BlkioStats {
-            time: Self::parse_blkio_file(&cgroup_path.join(BLKIO_TIME))?,
-            sectors: Self::parse_blkio_file(&cgroup_path.join(BLKIO_SECTORS))?,
-            service_bytes: Self::parse_blkio_file(&cgroup_path.join(BLKIO_IO_SERVICE_BYTES))?,
-            serviced: Self::parse_blkio_file(&cgroup_path.join(BLKIO_IO_SERVICED))?,
-            service_time: Self::parse_blkio_file(&cgroup_path.join(BLKIO_IO_SERVICE_TIME))?,
-            wait_time: Self::parse_blkio_file(&cgroup_path.join(BLKIO_WAIT_TIME))?,
-            queued: Self::parse_blkio_file(&cgroup_path.join(BLKIO_QUEUED))?,
-            merged: Self::parse_blkio_file(&cgroup_path.join(BLKIO_MERGED))?,
+            time: Self::parse_blkio_file(dir, BLKIO_TIME)?,
+            sectors: Self::parse_blkio_file(dir, BLKIO_SECTORS)?,
+            service_bytes: Self::parse_blkio_file(dir, BLKIO_IO_SERVICE_BYTES)?,
+            serviced: Self::parse_blkio_file(dir, BLKIO_IO_SERVICED)?,
+            service_time: Self::parse_blkio_file(dir, BLKIO_IO_SERVICE_TIME)?,
+            wait_time: Self::parse_blkio_file(dir, BLKIO_WAIT_TIME)?,
+            queued: Self::parse_blkio_file(dir, BLKIO_QUEUED)?,
+            merged: Self::parse_blkio_file(dir, BLKIO_MERGED)?,
             ..Default::default()
         };
 
         Ok(stats)
     }
 
-    fn parse_blkio_file(blkio_file: &Path) -> Result<Vec<BlkioDeviceStat>, V1BlkioStatsError> {
-        let content = common::read_cgroup_file(blkio_file)?;
+    fn parse_blkio_file(
+        dir: &mut CgroupDir,
+        file: &str,
+    ) -> Result<Vec<BlkioDeviceStat>, V1BlkioStatsError> {
+        let blkio_file = dir.path().join(file);
+        let content = dir.read(file)?;
         let mut stats = Vec::new();
         for entry in content.lines() {
             let entry_fields: Vec<&str> = entry.split_ascii_whitespace().collect();
@@ -223,7 +267,7 @@ impl Blkio {
                     .parse()
                     .map_err(|err| V1BlkioStatsError::FailedParseValue {
                         value: entry_fields[2].into(),
-                        path: blkio_file.to_path_buf(),
+                        path: blkio_file.clone(),
                         err,
                     })?
             } else {
@@ -231,7 +275,7 @@ impl Blkio {
                     .parse()
                     .map_err(|err| V1BlkioStatsError::FailedParseValue {
                         value: entry_fields[1].into(),
-                        path: blkio_file.to_path_buf(),
+                        path: blkio_file.clone(),
                         err,
                     })?
             };
@@ -254,7 +298,9 @@ impl Blkio {
 mod tests {
     use std::fs;
 
-    use oci_spec::runtime::{LinuxBlockIoBuilder, LinuxThrottleDeviceBuilder};
+    use oci_spec::runtime::{
+        LinuxBlockIoBuilder, LinuxThrottleDeviceBuilder, LinuxWeightDeviceBuilder,
+    };
 
     use super::*;
     use crate::test::{set_fixture, setup};
@@ -274,6 +320,68 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_set_blkio_weight_device() {
+        for cgroup_file in &[BLKIO_WEIGHT_DEVICE, BLKIO_BFQ_WEIGHT_DEVICE] {
+            let (tmp, weight_file) = setup(cgroup_file);
+            let blkio = LinuxBlockIoBuilder::default()
+                .weight_device(vec![LinuxWeightDeviceBuilder::default()
+                    .major(8)
+                    .minor(0)
+                    .weight(500_u16)
+                    .build()
+                    .unwrap()])
+                .build()
+                .unwrap();
+
+            Blkio::apply(tmp.path(), &blkio).expect("apply blkio");
+            let content = fs::read_to_string(weight_file).expect("read blkio weight device");
+            assert_eq!("8:0 500", content);
+        }
+    }
+
+    #[test]
+    fn test_set_blkio_leaf_weight_device() {
+        let (tmp, weight_file) = setup(BLKIO_WEIGHT_DEVICE);
+        let leaf_weight_file = set_fixture(tmp.path(), BLKIO_LEAF_WEIGHT_DEVICE, "")
+            .expect("set fixture for leaf weight device");
+        let blkio = LinuxBlockIoBuilder::default()
+            .weight_device(vec![LinuxWeightDeviceBuilder::default()
+                .major(8)
+                .minor(16)
+                .leaf_weight(300_u16)
+                .build()
+                .unwrap()])
+            .build()
+            .unwrap();
+
+        Blkio::apply(tmp.path(), &blkio).expect("apply blkio");
+        let content = fs::read_to_string(leaf_weight_file).expect("read blkio leaf weight device");
+        assert_eq!("8:16 300", content);
+        let content = fs::read_to_string(weight_file).expect("read blkio weight device");
+        assert_eq!("", content);
+    }
+
+    #[test]
+    fn test_set_blkio_leaf_weight_device_unavailable() {
+        let (tmp, weight_file) = setup(BLKIO_BFQ_WEIGHT_DEVICE);
+        let blkio = LinuxBlockIoBuilder::default()
+            .weight_device(vec![LinuxWeightDeviceBuilder::default()
+                .major(8)
+                .minor(16)
+                .weight(100_u16)
+                .leaf_weight(300_u16)
+                .build()
+                .unwrap()])
+            .build()
+            .unwrap();
+
+        Blkio::apply(tmp.path(), &blkio).expect("apply blkio");
+        assert!(!tmp.path().join(BLKIO_LEAF_WEIGHT_DEVICE).exists());
+        let content = fs::read_to_string(weight_file).expect("read blkio bfq weight device");
+        assert_eq!("8:16 100", content);
+    }
+
     #[test]
     fn test_set_blkio_read_bps() {
         let (tmp, throttle) = setup(BLKIO_THROTTLE_READ_BPS);
@@ -374,7 +482,8 @@ mod tests {
         set_fixture(tmp.path(), BLKIO_THROTTLE_IO_SERVICE_BYTES, content).unwrap();
         set_fixture(tmp.path(), BLKIO_THROTTLE_IO_SERVICED, content).unwrap();
 
-        let actual = Blkio::stats(tmp.path()).expect("get cgroup stats");
+        let actual =
+            Blkio::stats(&mut CgroupDir::open(tmp.path()).unwrap()).expect("get cgroup stats");
         let mut expected = BlkioStats::default();
         let devices: Vec<BlkioDeviceStat> = ["Read", "Write", "Sync", "Async", "Discard", "Total"]
             .iter()
@@ -14,6 +14,7 @@ pub enum ControllerType {
     NetworkPriority,
     NetworkClassifier,
     Freezer,
+    Rdma,
 }
 
 impl Display for ControllerType {
@@ -31,6 +32,7 @@ impl Display for ControllerType {
             Self::NetworkPriority => "net_prio",
             Self::NetworkClassifier => "net_cls",
             Self::Freezer => "freezer",
+            Self::Rdma => "rdma",
         };
 
         write!(f, "{print}")
@@ -52,6 +54,7 @@ impl AsRef<str> for ControllerType {
             Self::NetworkPriority => "net_prio",
             Self::NetworkClassifier => "net_cls",
             Self::Freezer => "freezer",
+            Self::Rdma => "rdma",
         }
     }
 }
@@ -69,4 +72,5 @@ pub const CONTROLLERS: &[ControllerType] = &[
     ControllerType::NetworkPriority,
     ControllerType::NetworkClassifier,
     ControllerType::Freezer,
+    ControllerType::Rdma,
 ];
@@ -1,10 +1,11 @@
+use std::fs;
 use std::path::{Path, PathBuf};
 
 use oci_spec::runtime::LinuxCpu;
 
 use super::controller::Controller;
-use crate::common::{self, ControllerOpt, WrappedIoError};
-use crate::stats::{parse_flat_keyed_data, CpuThrottling, ParseFlatKeyedDataError, StatsProvider};
+use crate::common::{self, ControllerOpt, WrapIoResult, WrappedIoError};
+use crate::stats::{CgroupDir, CpuThrottling, ParseFlatKeyedDataError, StatsProvider};
 
 const CGROUP_CPU_SHARES: &str = "cpu.shares";
 const CGROUP_CPU_QUOTA: &str = "cpu.cfs_quota_us";
@@ -14,11 +15,13 @@ const CGROUP_CPU_RT_RUNTIME: &str = "cpu.rt_runtime_us";
 const CGROUP_CPU_RT_PERIOD: &str = "cpu.rt_period_us";
 const CGROUP_CPU_STAT: &str = "cpu.stat";
 const CGROUP_CPU_IDLE: &str = "cpu.idle";
+// shares of SCHED_IDLE tasks, the lowest ones
+const MIN_CPU_SHARES: u64 = 2;
 
 pub struct Cpu {}
 
 impl Controller for Cpu {
-    type Error = WrappedIoError;
+    type Error = V1CpuControllerError;
     type Resource = LinuxCpu;
 
     fn apply(controller_opt: &ControllerOpt, cgroup_root: &Path) -> Result<(), Self::Error> {
@@ -48,6 +51,23 @@ impl Controller for Cpu {
     }
 }
 
+#[derive(thiserror::Error, Debug)]
+pub enum V1CpuControllerError {
+    #[error("io error: {0}")]
+    WrappedIo(#[from] WrappedIoError),
+    #[error("realtime group scheduling is not supported by the kernel, it requires CONFIG_RT_GROUP_SCHED")]
+    RtGroupSchedUnsupported,
+    #[error("cgroup {cgroup:?} lacks realtime budget: {required}us per {period}us are required, but only {available}us are left")]
+    RtBudget {
+        cgroup: PathBuf,
+        required: i64,
+        available: i64,
+        period: u64,
+    },
+    #[error("invalid value {value:?} in {path:?}")]
+    InvalidValue { path: PathBuf, value: String },
+}
+
 #[derive(thiserror::Error, Debug)]
 pub enum V1CpuStatsError {
     #[error("error parsing data: {0}")]
@@ -60,11 +80,11 @@ impl StatsProvider for Cpu {
     type Error = V1CpuStatsError;
     type Stats = CpuThrottling;
 
-    fn stats(cgroup_path: &Path) -> Result<Self::Stats, Self::Error> {
+    fn stats(dir: &mut CgroupDir) -> Result<Self::Stats, Self::Error> {
         let mut stats = CpuThrottling::default();
-        let stat_path = cgroup_path.join(CGROUP_CPU_STAT);
+        let stat_path = dir.path().join(CGROUP_CPU_STAT);
 
-        let stat_table = parse_flat_keyed_data(&stat_path)?;
+        let stat_table = dir.flat_keyed(CGROUP_CPU_STAT)?;
 
         macro_rules! get {
             ($name: expr => $field: ident) => {
@@ -87,7 +107,7 @@ impl StatsProvider for Cpu {
 }
 
 impl Cpu {
-    fn apply(root_path: &Path, cpu: &LinuxCpu) -> Result<(), WrappedIoError> {
+    fn apply(root_path: &Path, cpu: &LinuxCpu) -> Result<(), V1CpuControllerError> {
         if let Some(cpu_shares) = cpu.shares() {
             if cpu_shares != 0 {
                 common::write_cgroup_file(root_path.join(CGROUP_CPU_SHARES), cpu_shares)?;
@@ -110,24 +130,172 @@ impl Cpu {
             common::write_cgroup_file(root_path.join(CGROUP_CPU_BURST), cpu_burst)?;
         }
 
-        if let Some(rt_runtime) = cpu.realtime_runtime() {
-            if rt_runtime != 0 {
-                common::write_cgroup_file(root_path.join(CGROUP_CPU_RT_RUNTIME), rt_runtime)?;
+        Self::apply_realtime(root_path, cpu)?;
+
+        if let Some(idle) = cpu.idle() {
+            let idle_file = root_path.join(CGROUP_CPU_IDLE);
+            if idle_file.exists() {
+                common::write_cgroup_file(idle_file, idle)?;
+            } else if idle != 0 && cpu.shares().is_none() {
+                // cpu.idle requires kernel 5.15, before the lowest shares
+                // come closest to it
+                tracing::warn!(
+                    "cpu.idle is not supported by the kernel, setting the lowest shares"
+                );
+                common::write_cgroup_file(root_path.join(CGROUP_CPU_SHARES), MIN_CPU_SHARES)?;
             }
         }
 
-        if let Some(rt_period) = cpu.realtime_period() {
-            if rt_period != 0 {
-                common::write_cgroup_file(root_path.join(CGROUP_CPU_RT_PERIOD), rt_period)?;
+        Ok(())
+    }
+
+    /// Sets the realtime bandwidth of the cgroup. The kernel requires the
+    /// runtime of a cgroup to cover the runtimes of its children, so the
+    /// ancestors are given the runtime the cgroup needs first, like runc
+    /// does. Only the root of the hierarchy is never changed.
+    fn apply_realtime(root_path: &Path, cpu: &LinuxCpu) -> Result<(), V1CpuControllerError> {
+        let rt_runtime = cpu.realtime_runtime().filter(|runtime| *runtime != 0);
+        let rt_period = cpu.realtime_period().filter(|period| *period != 0);
+        for (requested, file) in [
+            (rt_runtime.is_some(), CGROUP_CPU_RT_RUNTIME),
+            (rt_period.is_some(), CGROUP_CPU_RT_PERIOD),
+        ] {
+            if requested && !root_path.join(file).exists() {
+                return Err(V1CpuControllerError::RtGroupSchedUnsupported);
             }
         }
 
-        if let Some(idle) = cpu.idle() {
-            common::write_cgroup_file(root_path.join(CGROUP_CPU_IDLE), idle)?;
+        if let Some(runtime) = rt_runtime.filter(|runtime| *runtime > 0) {
+            let period = match rt_period {
+                Some(period) => period,
+                None => read_value(root_path.join(CGROUP_CPU_RT_PERIOD))?,
+            };
+            Self::ensure_rt_budget(root_path, RtBandwidth { runtime, period })?;
+        }
+
+        // the period first, as a shorter period may not fit the old runtime
+        if let Some(rt_period) = rt_period {
+            common::write_cgroup_file(root_path.join(CGROUP_CPU_RT_PERIOD), rt_period)?;
+        }
+        if let Some(rt_runtime) = rt_runtime {
+            common::write_cgroup_file(root_path.join(CGROUP_CPU_RT_RUNTIME), rt_runtime)?;
         }
 
         Ok(())
     }
+
+    /// Raises the realtime runtime of the ancestors of the cgroup, so that
+    /// each covers the runtime its children need with `required` for the
+    /// cgroup. Fails with the ancestor whose runtime can't be raised.
+    fn ensure_rt_budget(cgroup: &Path, required: RtBandwidth) -> Result<(), V1CpuControllerError> {
+        // parent first, up to the root of the hierarchy
+        let ancestors: Vec<&Path> = cgroup
+            .ancestors()
+            .skip(1)
+            .take_while(|dir| dir.join(CGROUP_CPU_RT_RUNTIME).exists())
+            .collect();
+        let period = required.period;
+
+        // runtime each ancestor needs, in the period of the cgroup
+        let mut needed = Vec::with_capacity(ancestors.len());
+        let mut child = cgroup;
+        let mut child_runtime = required.runtime;
+        for ancestor in &ancestors {
+            let runtime = child_runtime + Self::rt_runtime_of_children(ancestor, child, period)?;
+            needed.push(runtime);
+            let current = RtBandwidth::read(ancestor)?.runtime_in(period);
+            child_runtime = current.map_or(runtime, |current| current.max(runtime));
+            child = ancestor;
+        }
+
+        for (ancestor, needed) in ancestors.iter().zip(&needed).rev() {
+            let bandwidth = RtBandwidth::read(ancestor)?;
+            let available = match bandwidth.runtime_in(period) {
+                Some(available) if available < *needed => available,
+                _ => continue,
+            };
+            if Some(ancestor) == ancestors.last() {
+                tracing::error!(?ancestor, needed, available, "no realtime budget left");
+                return Err(V1CpuControllerError::RtBudget {
+                    cgroup: ancestor.to_path_buf(),
+                    required: *needed,
+                    available,
+                    period,
+                });
+            }
+
+            let runtime = RtBandwidth {
+                runtime: *needed,
+                period,
+            }
+            .runtime_in(bandwidth.period)
+            .unwrap_or(*needed);
+            tracing::debug!(?ancestor, runtime, "raise realtime runtime of ancestor");
+            common::write_cgroup_file(ancestor.join(CGROUP_CPU_RT_RUNTIME), runtime)?;
+        }
+
+        Ok(())
+    }
+
+    /// Sum of the realtime runtimes of the children of `dir` other than
+    /// `except`, in the period `period`
+    fn rt_runtime_of_children(
+        dir: &Path,
+        except: &Path,
+        period: u64,
+    ) -> Result<i64, V1CpuControllerError> {
+        let mut runtime = 0;
+        let entries = fs::read_dir(dir).wrap_read(dir)?;
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path == except || !path.join(CGROUP_CPU_RT_RUNTIME).exists() {
+                continue;
+            }
+            runtime += RtBandwidth::read(&path)?.runtime_in(period).unwrap_or(0);
+        }
+
+        Ok(runtime)
+    }
+}
+
+fn read_value<T: std::str::FromStr>(path: PathBuf) -> Result<T, V1CpuControllerError> {
+    let value = common::read_cgroup_file(&path)?;
+    value
+        .trim()
+        .parse()
+        .map_err(|_| V1CpuControllerError::InvalidValue {
+            value: value.trim().to_owned(),
+            path,
+        })
+}
+
+/// Realtime runtime of a cgroup per period, in microseconds
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct RtBandwidth {
+    /// -1 for a runtime without limit
+    runtime: i64,
+    period: u64,
+}
+
+impl RtBandwidth {
+    fn read(dir: &Path) -> Result<Self, V1CpuControllerError> {
+        Ok(Self {
+            runtime: read_value(dir.join(CGROUP_CPU_RT_RUNTIME))?,
+            period: read_value(dir.join(CGROUP_CPU_RT_PERIOD))?,
+        })
+    }
+
+    /// Runtime scaled to `period`, None without limit
+    fn runtime_in(&self, period: u64) -> Option<i64> {
+        if self.runtime < 0 {
+            return None;
+        }
+        if self.period == 0 || self.period == period {
+            return Some(self.runtime);
+        }
+
+        Some((self.runtime as i128 * period as i128 / self.period as i128) as i64)
+    }
 }
 
 #[cfg(test)]
@@ -193,6 +361,7 @@ mod tests {
         // arrange
         const RUNTIME: i64 = 100000;
         let (tmp, max) = setup(CGROUP_CPU_RT_RUNTIME);
+        set_fixture(tmp.path(), CGROUP_CPU_RT_PERIOD, "1000000").unwrap();
         let cpu = LinuxCpuBuilder::default()
             .realtime_runtime(RUNTIME)
             .build()
@@ -234,6 +403,19 @@ mod tests {
         assert_eq!(content, IDLE.to_string());
     }
 
+    #[test]
+    fn test_set_cpu_idle_without_kernel_support() {
+        let (tmp, shares) = setup(CGROUP_CPU_SHARES);
+        let cpu = LinuxCpuBuilder::default().idle(1).build().unwrap();
+
+        Cpu::apply(tmp.path(), &cpu).expect("apply cpu");
+
+        let content = fs::read_to_string(shares)
+            .unwrap_or_else(|_| panic!("read {CGROUP_CPU_SHARES} file content"));
+        assert_eq!(content, MIN_CPU_SHARES.to_string());
+        assert!(!tmp.path().join(CGROUP_CPU_IDLE).exists());
+    }
+
     #[test]
     fn test_set_rt_period() {
         // arrange
@@ -253,6 +435,83 @@ mod tests {
         assert_eq!(content, PERIOD.to_string());
     }
 
+    fn rt_cgroup(dir: &Path, runtime: i64, period: u64) {
+        fs::create_dir_all(dir).unwrap();
+        set_fixture(dir, CGROUP_CPU_RT_RUNTIME, &runtime.to_string()).unwrap();
+        set_fixture(dir, CGROUP_CPU_RT_PERIOD, &period.to_string()).unwrap();
+    }
+
+    fn rt_runtime(dir: &Path) -> String {
+        fs::read_to_string(dir.join(CGROUP_CPU_RT_RUNTIME)).unwrap()
+    }
+
+    #[test]
+    fn test_set_rt_runtime_propagates_to_ancestors() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        let parent = root.join("youki");
+        let container = parent.join("container");
+        rt_cgroup(root, 950000, 1000000);
+        rt_cgroup(&parent, 0, 1000000);
+        // a sibling with a budget of its own, in a different period
+        rt_cgroup(&parent.join("sibling"), 5000, 500000);
+        rt_cgroup(&container, 0, 1000000);
+
+        let cpu = LinuxCpuBuilder::default()
+            .realtime_runtime(20000)
+            .build()
+            .unwrap();
+        Cpu::apply(&container, &cpu).expect("apply cpu");
+
+        assert_eq!(rt_runtime(&container), "20000");
+        assert_eq!(rt_runtime(&parent), "30000");
+        assert_eq!(rt_runtime(root), "950000");
+    }
+
+    #[test]
+    fn test_set_rt_runtime_without_budget() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        let container = root.join("youki/container");
+        rt_cgroup(root, 10000, 1000000);
+        rt_cgroup(&root.join("youki"), 0, 1000000);
+        rt_cgroup(&container, 0, 1000000);
+
+        let cpu = LinuxCpuBuilder::default()
+            .realtime_runtime(20000)
+            .build()
+            .unwrap();
+        let err = Cpu::apply(&container, &cpu).unwrap_err();
+        assert!(
+            matches!(
+                &err,
+                V1CpuControllerError::RtBudget {
+                    cgroup,
+                    required: 20000,
+                    available: 10000,
+                    period: 1000000,
+                } if cgroup == root
+            ),
+            "{err:?}"
+        );
+        // nothing is changed if the budget is lacking
+        assert_eq!(rt_runtime(&root.join("youki")), "0");
+        assert_eq!(rt_runtime(&container), "0");
+    }
+
+    #[test]
+    fn test_set_rt_runtime_without_rt_group_sched() {
+        let (tmp, _) = setup(CGROUP_CPU_SHARES);
+        let cpu = LinuxCpuBuilder::default()
+            .realtime_runtime(20000)
+            .build()
+            .unwrap();
+        assert!(matches!(
+            Cpu::apply(tmp.path(), &cpu),
+            Err(V1CpuControllerError::RtGroupSchedUnsupported)
+        ));
+    }
+
     #[test]
     fn test_stat_cpu_throttling() {
         let tmp = tempfile::tempdir().unwrap();
@@ -264,7 +523,8 @@ mod tests {
         .join("\n");
         set_fixture(tmp.path(), CGROUP_CPU_STAT, stat_content).expect("create stat file");
 
-        let actual = Cpu::stats(tmp.path()).expect("get cgroup stats");
+        let actual =
+            Cpu::stats(&mut CgroupDir::open(tmp.path()).unwrap()).expect("get cgroup stats");
         let expected = CpuThrottling {
             periods: 165000,
             throttled_periods: 27,
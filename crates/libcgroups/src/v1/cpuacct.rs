@@ -2,8 +2,8 @@ use std::num::ParseIntError;
 use std::path::{Path, PathBuf};
 
 use super::controller::Controller;
-use crate::common::{self, ControllerOpt, WrappedIoError};
-use crate::stats::{parse_flat_keyed_data, CpuUsage, ParseFlatKeyedDataError, StatsProvider};
+use crate::common::{ControllerOpt, WrappedIoError};
+use crate::stats::{CgroupDir, CpuUsage, ParseFlatKeyedDataError, StatsProvider};
 
 // Contains user mode and kernel mode cpu consumption
 const CGROUP_CPUACCT_STAT: &str = "cpuacct.stat";
@@ -53,10 +53,10 @@ impl StatsProvider for CpuAcct {
     type Error = V1CpuAcctStatsError;
     type Stats = CpuUsage;
 
-    fn stats(cgroup_path: &Path) -> Result<Self::Stats, V1CpuAcctStatsError> {
+    fn stats(dir: &mut CgroupDir) -> Result<Self::Stats, V1CpuAcctStatsError> {
         let mut stats = CpuUsage::default();
-        Self::get_total_cpu_usage(cgroup_path, &mut stats)?;
-        Self::get_per_core_usage(cgroup_path, &mut stats)?;
+        Self::get_total_cpu_usage(dir, &mut stats)?;
+        Self::get_per_core_usage(dir, &mut stats)?;
 
         Ok(stats)
     }
@@ -64,11 +64,11 @@ impl StatsProvider for CpuAcct {
 
 impl CpuAcct {
     fn get_total_cpu_usage(
-        cgroup_path: &Path,
+        dir: &mut CgroupDir,
         stats: &mut CpuUsage,
     ) -> Result<(), V1CpuAcctStatsError> {
-        let stat_file_path = cgroup_path.join(CGROUP_CPUACCT_STAT);
-        let stat_table = parse_flat_keyed_data(&stat_file_path)?;
+        let stat_file_path = dir.path().join(CGROUP_CPUACCT_STAT);
+        let stat_table = dir.flat_keyed(CGROUP_CPUACCT_STAT)?;
 
         macro_rules! get {
             ($name: expr => $field: ident) => {
@@ -85,8 +85,8 @@ impl CpuAcct {
         get!("user" => usage_user);
         get!("system" => usage_kernel);
 
-        let total = common::read_cgroup_file(cgroup_path.join(CGROUP_CPUACCT_USAGE))?;
-        stats.usage_total = total
+        stats.usage_total = dir
+            .read(CGROUP_CPUACCT_USAGE)?
             .trim()
             .parse()
             .map_err(V1CpuAcctStatsError::ParseTotalCpu)?;
@@ -95,11 +95,23 @@ impl CpuAcct {
     }
 
     fn get_per_core_usage(
-        cgroup_path: &Path,
+        dir: &mut CgroupDir,
         stats: &mut CpuUsage,
     ) -> Result<(), V1CpuAcctStatsError> {
-        let path = cgroup_path.join(CGROUP_CPUACCT_USAGE_ALL);
-        let all_content = common::read_cgroup_file(&path)?;
+        let percpu_content = dir.read(CGROUP_CPUACCT_PERCPU)?;
+        stats.per_core_usage_total = percpu_content
+            .split_ascii_whitespace()
+            .map(|v| v.parse())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(V1CpuAcctStatsError::ParsePerCore)?;
+
+        // the user and kernel mode split is only available since kernel 4.7
+        if !dir.exists(CGROUP_CPUACCT_USAGE_ALL) {
+            return Ok(());
+        }
+
+        let path = dir.path().join(CGROUP_CPUACCT_USAGE_ALL);
+        let all_content = dir.read(CGROUP_CPUACCT_USAGE_ALL)?;
         // first line is header, skip it
         for entry in all_content.lines().skip(1) {
             let entry_parts: Vec<&str> = entry.split_ascii_whitespace().collect();
@@ -127,13 +139,6 @@ impl CpuAcct {
                 })?);
         }
 
-        let percpu_content = common::read_cgroup_file(cgroup_path.join(CGROUP_CPUACCT_PERCPU))?;
-        stats.per_core_usage_total = percpu_content
-            .split_ascii_whitespace()
-            .map(|v| v.parse())
-            .collect::<Result<Vec<_>, _>>()
-            .map_err(V1CpuAcctStatsError::ParsePerCore)?;
-
         Ok(())
     }
 }
@@ -190,7 +195,8 @@ mod tests {
         let tmp = setup_total_cpu(stat_content, usage_content);
 
         let mut stats = CpuUsage::default();
-        CpuAcct::get_total_cpu_usage(tmp.path(), &mut stats).expect("get cgroup stats");
+        CpuAcct::get_total_cpu_usage(&mut CgroupDir::open(tmp.path()).unwrap(), &mut stats)
+            .expect("get cgroup stats");
 
         assert_eq!(stats.usage_user, 1300888);
         assert_eq!(stats.usage_kernel, 364592);
@@ -211,7 +217,8 @@ mod tests {
         let tmp = setup_per_core(percpu_content, usage_all_content);
 
         let mut stats = CpuUsage::default();
-        CpuAcct::get_per_core_usage(tmp.path(), &mut stats).expect("get cgroup stats");
+        CpuAcct::get_per_core_usage(&mut CgroupDir::open(tmp.path()).unwrap(), &mut stats)
+            .expect("get cgroup stats");
 
         assert_eq!(
             stats.per_core_usage_user,
@@ -228,4 +235,23 @@ mod tests {
             [989683000640, 4409567860144, 4439880333849, 4273328034121]
         );
     }
+
+    #[test]
+    fn test_stat_per_cpu_usage_without_usage_all() {
+        let tmp = tempfile::tempdir().unwrap();
+        set_fixture(
+            tmp.path(),
+            CGROUP_CPUACCT_PERCPU,
+            "989683000640 4409567860144\n",
+        )
+        .unwrap();
+
+        let mut stats = CpuUsage::default();
+        CpuAcct::get_per_core_usage(&mut CgroupDir::open(tmp.path()).unwrap(), &mut stats)
+            .expect("get cgroup stats");
+
+        assert_eq!(stats.per_core_usage_total, [989683000640, 4409567860144]);
+        assert!(stats.per_core_usage_user.is_empty());
+        assert!(stats.per_core_usage_kernel.is_empty());
+    }
 }
@@ -3,30 +3,30 @@ use std::path::Path;
 use oci_spec::runtime::LinuxDeviceCgroup;
 
 use super::controller::Controller;
-use crate::common::{self, default_allow_devices, default_devices, ControllerOpt, WrappedIoError};
+use crate::common::{self, ControllerOpt, WrappedIoError};
+use crate::device_rules::{self, DeviceRuleError};
+
+#[derive(thiserror::Error, Debug)]
+pub enum V1DevicesControllerError {
+    #[error("io error: {0}")]
+    WrappedIo(#[from] WrappedIoError),
+    #[error("device rule error: {0}")]
+    DeviceRule(#[from] DeviceRuleError),
+}
 
 pub struct Devices {}
 
 impl Controller for Devices {
-    type Error = WrappedIoError;
+    type Error = V1DevicesControllerError;
     type Resource = ();
 
     fn apply(controller_opt: &ControllerOpt, cgroup_root: &Path) -> Result<(), Self::Error> {
         tracing::debug!("Apply Devices cgroup config");
 
-        if let Some(devices) = controller_opt.resources.devices().as_ref() {
-            for d in devices {
-                Self::apply_device(d, cgroup_root)?;
-            }
-        }
-
-        for d in [
-            default_devices().iter().map(|d| d.into()).collect(),
-            default_allow_devices(),
-        ]
-        .concat()
-        {
-            Self::apply_device(&d, cgroup_root)?;
+        let rules =
+            device_rules::rules_with_defaults(controller_opt.resources.devices().as_deref())?;
+        for d in &rules {
+            Self::apply_device(d, cgroup_root)?;
         }
 
         Ok(())
@@ -55,7 +55,7 @@ impl Devices {
 mod tests {
     use std::fs::read_to_string;
 
-    use oci_spec::runtime::{LinuxDeviceCgroupBuilder, LinuxDeviceType};
+    use oci_spec::runtime::{LinuxDeviceCgroupBuilder, LinuxDeviceType, LinuxResourcesBuilder};
 
     use super::*;
     use crate::test::set_fixture;
@@ -64,7 +64,7 @@ mod tests {
     fn test_set_default_devices() {
         let tmp = tempfile::tempdir().unwrap();
 
-        default_allow_devices().iter().for_each(|d| {
+        device_rules::default_rules().iter().for_each(|d| {
             // NOTE: We reset the fixtures every iteration because files aren't appended
             // so what happens in the tests is you get strange overwrites which can contain
             // remaining bytes from the last iteration. Resetting the files more appropriately
@@ -138,6 +138,35 @@ mod tests {
         });
     }
 
+    #[test]
+    fn test_apply_wildcard_rules() {
+        let tmp = tempfile::tempdir().unwrap();
+        set_fixture(tmp.path(), "devices.allow", "").expect("create allowed devices list");
+        set_fixture(tmp.path(), "devices.deny", "").expect("create denied devices list");
+        let resources = LinuxResourcesBuilder::default()
+            .devices(vec![LinuxDeviceCgroupBuilder::default()
+                .allow(false)
+                .typ(LinuxDeviceType::B)
+                .major(8)
+                .minor(-1)
+                .access("wr")
+                .build()
+                .unwrap()])
+            .build()
+            .unwrap();
+        let controller_opt = ControllerOpt {
+            resources: &resources,
+            freezer_state: None,
+            oom_score_adj: None,
+            disable_oom_killer: false,
+        };
+
+        Devices::apply(&controller_opt, tmp.path()).expect("apply devices");
+        let denied_content =
+            read_to_string(tmp.path().join("devices.deny")).expect("read to string");
+        assert_eq!(denied_content, "b 8:* rw");
+    }
+
     quickcheck! {
         fn property_test_apply_device(device: LinuxDeviceCgroup) -> bool {
             let tmp = tempfile::tempdir().unwrap();
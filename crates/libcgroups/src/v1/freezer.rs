@@ -1,12 +1,17 @@
 use std::fs::OpenOptions;
 use std::io::Read;
 use std::path::Path;
-use std::{thread, time};
+use std::thread;
+use std::time::{Duration, Instant};
 
 use super::controller::Controller;
-use crate::common::{self, ControllerOpt, FreezerState, WrapIoResult, WrappedIoError};
+use crate::common::{
+    self, ControllerOpt, FreezerState, UnfrozenTasks, WrapIoResult, WrappedIoError,
+    DEFAULT_FREEZE_TIMEOUT,
+};
 
 const CGROUP_FREEZER_STATE: &str = "freezer.state";
+const CGROUP_TASKS: &str = "tasks";
 const FREEZER_STATE_THAWED: &str = "THAWED";
 const FREEZER_STATE_FROZEN: &str = "FROZEN";
 const FREEZER_STATE_FREEZING: &str = "FREEZING";
@@ -17,8 +22,11 @@ pub enum V1FreezerControllerError {
     WrappedIo(#[from] WrappedIoError),
     #[error("unexpected state {state} while freezing")]
     UnexpectedState { state: String },
-    #[error("unable to freeze")]
-    UnableToFreeze,
+    #[error("unable to freeze within {timeout:?}, tasks which did not freeze: {tasks}")]
+    UnableToFreeze {
+        timeout: Duration,
+        tasks: UnfrozenTasks,
+    },
 }
 
 pub struct Freezer {}
@@ -47,6 +55,17 @@ impl Freezer {
     fn apply(
         freezer_state: &FreezerState,
         cgroup_root: &Path,
+    ) -> Result<(), V1FreezerControllerError> {
+        Self::set_state(*freezer_state, cgroup_root, DEFAULT_FREEZE_TIMEOUT)
+    }
+
+    /// Sets the state of the cgroup. Freezing waits up to the timeout until
+    /// the cgroup is FROZEN, otherwise it thaws the cgroup again and fails
+    /// with the tasks which did not freeze.
+    pub(crate) fn set_state(
+        freezer_state: FreezerState,
+        cgroup_root: &Path,
+        timeout: Duration,
     ) -> Result<(), V1FreezerControllerError> {
         match freezer_state {
             FreezerState::Undefined => {}
@@ -57,18 +76,19 @@ impl Freezer {
                 )?;
             }
             FreezerState::Frozen => {
+                let deadline = Instant::now() + timeout;
                 let r = || -> Result<(), V1FreezerControllerError> {
                     // We should do our best to retry if FREEZING is seen until it becomes FROZEN.
                     // Add sleep between retries occasionally helped when system is extremely slow.
                     // see:
                     // https://github.com/opencontainers/runc/blob/b9ee9c6314599f1b4a7f497e1f1f856fe433d3b7/libcontainer/cgroups/fs/freezer.go#L42
-                    for i in 0..1000 {
+                    for i in 0u64.. {
                         if i % 50 == 49 {
                             let _ = common::write_cgroup_file(
                                 cgroup_root.join(CGROUP_FREEZER_STATE),
                                 FREEZER_STATE_THAWED,
                             );
-                            thread::sleep(time::Duration::from_millis(10));
+                            thread::sleep(Duration::from_millis(10));
                         }
 
                         common::write_cgroup_file(
@@ -77,12 +97,16 @@ impl Freezer {
                         )?;
 
                         if i % 25 == 24 {
-                            thread::sleep(time::Duration::from_millis(10));
+                            thread::sleep(Duration::from_millis(10));
                         }
 
                         let r = Self::read_freezer_state(cgroup_root)?;
                         match r.trim() {
                             FREEZER_STATE_FREEZING => {
+                                // the tasks are listed before the cgroup is thawed
+                                if Instant::now() >= deadline {
+                                    break;
+                                }
                                 continue;
                             }
                             FREEZER_STATE_FROZEN => {
@@ -97,7 +121,10 @@ impl Freezer {
                             }
                         }
                     }
-                    Err(V1FreezerControllerError::UnableToFreeze)
+                    Err(V1FreezerControllerError::UnableToFreeze {
+                        timeout,
+                        tasks: common::unfrozen_tasks(&cgroup_root.join(CGROUP_TASKS)),
+                    })
                 }();
 
                 if r.is_err() {
@@ -5,10 +5,10 @@ use std::path::Path;
 use oci_spec::runtime::LinuxHugepageLimit;
 
 use super::controller::Controller;
-use crate::common::{
-    self, read_cgroup_file, ControllerOpt, EitherError, MustBePowerOfTwo, WrappedIoError,
+use crate::common::{self, ControllerOpt, EitherError, MustBePowerOfTwo, WrappedIoError};
+use crate::stats::{
+    supported_page_sizes, CgroupDir, HugeTlbStats, StatsProvider, SupportedPageSizesError,
 };
-use crate::stats::{supported_page_sizes, HugeTlbStats, StatsProvider, SupportedPageSizesError};
 
 #[derive(thiserror::Error, Debug)]
 pub enum V1HugeTlbControllerError {
@@ -67,12 +67,12 @@ impl StatsProvider for HugeTlb {
     type Error = V1HugeTlbStatsError;
     type Stats = HashMap<String, HugeTlbStats>;
 
-    fn stats(cgroup_path: &Path) -> Result<Self::Stats, Self::Error> {
+    fn stats(dir: &mut CgroupDir) -> Result<Self::Stats, Self::Error> {
         let page_sizes = supported_page_sizes()?;
         let mut hugetlb_stats = HashMap::with_capacity(page_sizes.len());
 
         for page_size in &page_sizes {
-            let stats = Self::stats_for_page_size(cgroup_path, page_size)?;
+            let stats = Self::stats_for_page_size(dir, page_size)?;
             hugetlb_stats.insert(page_size.to_owned(), stats);
         }
 
@@ -127,26 +127,29 @@ impl HugeTlb {
     }
 
     fn stats_for_page_size(
-        cgroup_path: &Path,
+        dir: &mut CgroupDir,
         page_size: &str,
     ) -> Result<HugeTlbStats, V1HugeTlbStatsError> {
         let mut stats = HugeTlbStats::default();
         let mut file_prefix = format!("hugetlb.{page_size}.rsvd");
-        let mut usage_file = format!("{file_prefix}.usage_in_bytes");
-        let usage_content = read_cgroup_file(cgroup_path.join(&usage_file)).or_else(|_| {
+        stats.rsvd = true;
+        // kernels without rsvd accounting
+        if !dir.exists(&format!("{file_prefix}.usage_in_bytes")) {
+            stats.rsvd = false;
             file_prefix = format!("hugetlb.{page_size}");
-            usage_file = format!("{file_prefix}.usage_in_bytes");
-            read_cgroup_file(cgroup_path.join(&usage_file))
-        })?;
-        stats.usage = usage_content.trim().parse()?;
-
-        let max_file = format!("{file_prefix}.max_usage_in_bytes");
-        let max_content = common::read_cgroup_file(cgroup_path.join(max_file))?;
-        stats.max_usage = max_content.trim().parse()?;
-
-        let failcnt_file = format!("{file_prefix}.failcnt");
-        let failcnt_content = common::read_cgroup_file(cgroup_path.join(failcnt_file))?;
-        stats.fail_count = failcnt_content.trim().parse()?;
+        }
+        stats.usage = dir
+            .read(&format!("{file_prefix}.usage_in_bytes"))?
+            .trim()
+            .parse()?;
+        stats.max_usage = dir
+            .read(&format!("{file_prefix}.max_usage_in_bytes"))?
+            .trim()
+            .parse()?;
+        stats.fail_count = dir
+            .read(&format!("{file_prefix}.failcnt"))?
+            .trim()
+            .parse()?;
 
         Ok(stats)
     }
@@ -255,12 +258,14 @@ mod tests {
             .expect("set hugetlb max usage");
         set_fixture(tmp.path(), "hugetlb.2MB.failcnt", "5").expect("set hugetlb fail count");
 
-        let actual = HugeTlb::stats_for_page_size(tmp.path(), "2MB").expect("get cgroup stats");
+        let actual = HugeTlb::stats_for_page_size(&mut CgroupDir::open(tmp.path()).unwrap(), "2MB")
+            .expect("get cgroup stats");
 
         let expected = HugeTlbStats {
             usage: 1024,
             max_usage: 4096,
             fail_count: 5,
+            rsvd: false,
         };
         assert_eq!(actual, expected);
     }
@@ -280,13 +285,15 @@ mod tests {
             .expect("set hugetlb max usage");
         set_fixture(tmp.path(), "hugetlb.2MB.failcnt", "10").expect("set hugetlb fail count");
 
-        let actual = HugeTlb::stats_for_page_size(tmp.path(), "2MB").expect("get cgroup stats");
+        let actual = HugeTlb::stats_for_page_size(&mut CgroupDir::open(tmp.path()).unwrap(), "2MB")
+            .expect("get cgroup stats");
 
         // Should prefer rsvd stats over non-rsvd stats
         let expected = HugeTlbStats {
             usage: 1024,
             max_usage: 4096,
             fail_count: 5,
+            rsvd: true,
         };
         assert_eq!(actual, expected);
     }
@@ -1,6 +1,8 @@
+use std::collections::hash_map::Entry;
 use std::collections::HashMap;
 use std::fs;
 use std::path::{Path, PathBuf};
+use std::sync::{Mutex, PoisonError};
 use std::time::Duration;
 
 use nix::unistd::Pid;
@@ -10,27 +12,32 @@ use procfs::ProcError;
 use super::blkio::{Blkio, V1BlkioStatsError};
 use super::controller::Controller;
 use super::controller_type::CONTROLLERS;
-use super::cpu::{Cpu, V1CpuStatsError};
+use super::cpu::{Cpu, V1CpuControllerError, V1CpuStatsError};
 use super::cpuacct::{CpuAcct, V1CpuAcctStatsError};
 use super::cpuset::{CpuSet, V1CpuSetControllerError};
-use super::devices::Devices;
+use super::devices::{Devices, V1DevicesControllerError};
 use super::freezer::{Freezer, V1FreezerControllerError};
 use super::hugetlb::{HugeTlb, V1HugeTlbControllerError, V1HugeTlbStatsError};
 use super::memory::{Memory, V1MemoryControllerError, V1MemoryStatsError};
 use super::network_classifier::NetworkClassifier;
-use super::network_priority::NetworkPriority;
+use super::network_priority::{NetworkPriority, V1NetworkPriorityControllerError};
 use super::perf_event::PerfEvent;
 use super::pids::Pids;
+use super::rdma::Rdma;
 use super::util::V1MountPointError;
 use super::{util, ControllerType as CtrlType};
+use crate::capabilities::{CgroupCapabilities, ResourceType};
 use crate::common::{
     self, AnyCgroupManager, CgroupManager, ControllerOpt, FreezerState, JoinSafelyError,
     PathBufExt, WrapIoResult, WrappedIoError, CGROUP_PROCS,
 };
-use crate::stats::{PidStatsError, Stats, StatsProvider};
+use crate::rdma::RdmaError;
+use crate::stats::{CgroupDir, ParseFlatKeyedDataError, PidStatsError, Stats, StatsProvider};
 
 pub struct Manager {
     subsystems: HashMap<CtrlType, PathBuf>,
+    /// Subsystem cgroups kept open between the collections of statistics
+    stats_dirs: Mutex<HashMap<CtrlType, CgroupDir>>,
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -47,16 +54,20 @@ pub enum V1ManagerError {
     CGroupRequired(CtrlType),
     #[error("subsystem does not exist")]
     SubsystemDoesNotExist,
+    #[error("memory reclaim is only supported by cgroup v2")]
+    ReclaimUnsupported,
 
     #[error(transparent)]
     BlkioController(WrappedIoError),
     #[error(transparent)]
-    CpuController(WrappedIoError),
+    CpuController(#[from] V1CpuControllerError),
     #[error(transparent)]
     CpuAcctController(WrappedIoError),
     #[error(transparent)]
     CpuSetController(#[from] V1CpuSetControllerError),
     #[error(transparent)]
+    DevicesController(#[from] V1DevicesControllerError),
+    #[error(transparent)]
     FreezerController(#[from] V1FreezerControllerError),
     #[error(transparent)]
     HugeTlbController(#[from] V1HugeTlbControllerError),
@@ -64,6 +75,10 @@ pub enum V1ManagerError {
     MemoryController(#[from] V1MemoryControllerError),
     #[error(transparent)]
     PidsController(WrappedIoError),
+    #[error(transparent)]
+    NetworkPriorityController(#[from] V1NetworkPriorityControllerError),
+    #[error(transparent)]
+    Rdma(#[from] RdmaError),
 
     #[error(transparent)]
     BlkioStats(#[from] V1BlkioStatsError),
@@ -77,6 +92,8 @@ pub enum V1ManagerError {
     HugeTlbStats(#[from] V1HugeTlbStatsError),
     #[error(transparent)]
     MemoryStats(#[from] V1MemoryStatsError),
+    #[error(transparent)]
+    NetworkPriorityStats(#[from] ParseFlatKeyedDataError),
 }
 
 impl Manager {
@@ -91,7 +108,10 @@ impl Manager {
             }
         }
 
-        Ok(Manager { subsystems })
+        Ok(Manager {
+            subsystems,
+            stats_dirs: Mutex::default(),
+        })
     }
 
     fn get_subsystem_path(
@@ -140,6 +160,7 @@ impl Manager {
                     NetworkClassifier::needs_to_handle(controller_opt).is_some()
                 }
                 CtrlType::Freezer => Freezer::needs_to_handle(controller_opt).is_some(),
+                CtrlType::Rdma => Rdma::needs_to_handle(controller_opt).is_some(),
             };
 
             if required {
@@ -154,6 +175,53 @@ impl Manager {
         Ok(required_controllers)
     }
 
+    fn collect_stats(
+        &self,
+        stats_dirs: &mut HashMap<CtrlType, CgroupDir>,
+    ) -> Result<Stats, V1ManagerError> {
+        let mut stats = Stats::default();
+
+        for (ctrl_type, cgroup_path) in &self.subsystems {
+            if !matches!(
+                ctrl_type,
+                CtrlType::Cpu
+                    | CtrlType::CpuAcct
+                    | CtrlType::Pids
+                    | CtrlType::HugeTlb
+                    | CtrlType::Blkio
+                    | CtrlType::Memory
+                    | CtrlType::NetworkClassifier
+                    | CtrlType::NetworkPriority
+                    | CtrlType::Rdma
+            ) {
+                continue;
+            }
+            let dir = match stats_dirs.entry(*ctrl_type) {
+                Entry::Occupied(entry) => entry.into_mut(),
+                Entry::Vacant(entry) => entry.insert(CgroupDir::open(cgroup_path)?),
+            };
+
+            match ctrl_type {
+                CtrlType::Cpu => stats.cpu.throttling = Cpu::stats(dir)?,
+                CtrlType::CpuAcct => stats.cpu.usage = CpuAcct::stats(dir)?,
+                CtrlType::Pids => stats.pids = Pids::stats(dir)?,
+                CtrlType::HugeTlb => stats.hugetlb = HugeTlb::stats(dir)?,
+                CtrlType::Blkio => stats.blkio = Blkio::stats(dir)?,
+                CtrlType::Memory => stats.memory = Memory::stats(dir)?,
+                CtrlType::NetworkClassifier => {
+                    stats.network.class_id = Some(NetworkClassifier::stats(dir)?)
+                }
+                CtrlType::NetworkPriority => {
+                    stats.network.priorities = NetworkPriority::stats(dir)?
+                }
+                CtrlType::Rdma => stats.rdma = Rdma::stats(dir)?,
+                _ => continue,
+            }
+        }
+
+        Ok(stats)
+    }
+
     pub fn any(self) -> AnyCgroupManager {
         AnyCgroupManager::V1(self)
     }
@@ -186,6 +254,7 @@ impl CgroupManager for Manager {
                 CtrlType::NetworkPriority => NetworkPriority::add_task(pid, cgroup_path)?,
                 CtrlType::NetworkClassifier => NetworkClassifier::add_task(pid, cgroup_path)?,
                 CtrlType::Freezer => Freezer::add_task(pid, cgroup_path)?,
+                CtrlType::Rdma => Rdma::add_task(pid, cgroup_path)?,
             }
         }
 
@@ -209,6 +278,7 @@ impl CgroupManager for Manager {
                     NetworkClassifier::apply(controller_opt, cgroup_path)?
                 }
                 CtrlType::Freezer => Freezer::apply(controller_opt, cgroup_path)?,
+                CtrlType::Rdma => Rdma::apply(controller_opt, cgroup_path)?,
             }
         }
 
@@ -237,36 +307,62 @@ impl CgroupManager for Manager {
         Ok(())
     }
 
-    fn freeze(&self, state: FreezerState) -> Result<(), Self::Error> {
-        let controller_opt = ControllerOpt {
-            resources: &Default::default(),
-            freezer_state: Some(state),
-            oom_score_adj: None,
-            disable_oom_killer: false,
-        };
-        Ok(Freezer::apply(
-            &controller_opt,
-            self.subsystems
-                .get(&CtrlType::Freezer)
-                .ok_or(V1ManagerError::SubsystemDoesNotExist)?,
-        )?)
+    fn freeze_with_timeout(
+        &self,
+        state: FreezerState,
+        timeout: Duration,
+    ) -> Result<(), Self::Error> {
+        let cgroup_path = self
+            .subsystems
+            .get(&CtrlType::Freezer)
+            .ok_or(V1ManagerError::CGroupRequired(CtrlType::Freezer))?;
+        Ok(Freezer::set_state(state, cgroup_path, timeout)?)
     }
 
     fn stats(&self) -> Result<Stats, Self::Error> {
-        let mut stats = Stats::default();
+        let mut stats_dirs = self
+            .stats_dirs
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        let stats = self.collect_stats(&mut stats_dirs);
+        if stats.is_err() {
+            // a subsystem cgroup may have been recreated, open them again on the next call
+            stats_dirs.clear();
+        }
+        stats
+    }
 
-        for (ctrl_type, cgroup_path) in &self.subsystems {
-            match ctrl_type {
-                CtrlType::Cpu => stats.cpu.throttling = Cpu::stats(cgroup_path)?,
-                CtrlType::CpuAcct => stats.cpu.usage = CpuAcct::stats(cgroup_path)?,
-                CtrlType::Pids => stats.pids = Pids::stats(cgroup_path)?,
-                CtrlType::HugeTlb => stats.hugetlb = HugeTlb::stats(cgroup_path)?,
-                CtrlType::Blkio => stats.blkio = Blkio::stats(cgroup_path)?,
-                CtrlType::Memory => stats.memory = Memory::stats(cgroup_path)?,
-                _ => continue,
+    fn capabilities(&self) -> Result<CgroupCapabilities, Self::Error> {
+        let available =
+            |ctrl_type| matches!(self.subsystems.get(&ctrl_type), Some(path) if path.exists());
+        let mut resources = Vec::new();
+        for (ctrl_type, resource) in [
+            (CtrlType::Cpu, ResourceType::Cpu),
+            (CtrlType::CpuSet, ResourceType::CpuSet),
+            (CtrlType::Devices, ResourceType::Devices),
+            (CtrlType::HugeTlb, ResourceType::HugePageLimits),
+            (CtrlType::Memory, ResourceType::Memory),
+            (CtrlType::Pids, ResourceType::Pids),
+            (CtrlType::Blkio, ResourceType::BlockIo),
+            (CtrlType::Rdma, ResourceType::Rdma),
+        ] {
+            if available(ctrl_type) {
+                resources.push(resource);
             }
         }
+        // network restrictions are split across two subsystems
+        if available(CtrlType::NetworkClassifier) && available(CtrlType::NetworkPriority) {
+            resources.push(ResourceType::Network);
+        }
 
-        Ok(stats)
+        Ok(CgroupCapabilities::new(resources))
+    }
+
+    fn reclaim(&self, _bytes: u64) -> Result<(), Self::Error> {
+        Err(V1ManagerError::ReclaimUnsupported)
+    }
+
+    fn kill(&self) -> Result<bool, Self::Error> {
+        Ok(false)
     }
 }
@@ -11,9 +11,7 @@ use oci_spec::runtime::LinuxMemory;
 
 use super::controller::Controller;
 use crate::common::{self, ControllerOpt, WrapIoResult, WrappedIoError};
-use crate::stats::{
-    self, parse_single_value, MemoryData, MemoryStats, ParseFlatKeyedDataError, StatsProvider,
-};
+use crate::stats::{CgroupDir, MemoryData, MemoryStats, ParseFlatKeyedDataError, StatsProvider};
 
 const CGROUP_MEMORY_SWAP_LIMIT: &str = "memory.memsw.limit_in_bytes";
 const CGROUP_MEMORY_LIMIT: &str = "memory.limit_in_bytes";
@@ -131,17 +129,13 @@ impl Controller for Memory {
                 }
             }
 
-            // NOTE: Seems as though kernel and kernelTCP are both deprecated
-            // neither are implemented by runc. Tests pass without this, but
-            // kept in per the spec.
+            // kernel and kernelTCP are deprecated by the spec and ignored by
+            // runc, they are set as far as the kernel still supports them
             if let Some(kmem) = memory.kernel() {
-                common::write_cgroup_file(cgroup_root.join(CGROUP_KERNEL_MEMORY_LIMIT), kmem)?;
+                Self::set_kernel_limit(&cgroup_root.join(CGROUP_KERNEL_MEMORY_LIMIT), kmem)?;
             }
             if let Some(tcp_mem) = memory.kernel_tcp() {
-                common::write_cgroup_file(
-                    cgroup_root.join(CGROUP_KERNEL_TCP_MEMORY_LIMIT),
-                    tcp_mem,
-                )?;
+                Self::set_kernel_limit(&cgroup_root.join(CGROUP_KERNEL_TCP_MEMORY_LIMIT), tcp_mem)?;
             }
         }
 
@@ -164,13 +158,13 @@ impl StatsProvider for Memory {
     type Error = V1MemoryStatsError;
     type Stats = MemoryStats;
 
-    fn stats(cgroup_path: &Path) -> Result<Self::Stats, Self::Error> {
-        let memory = Self::get_memory_data(cgroup_path, MEMORY_PREFIX)?;
-        let memswap = Self::get_memory_data(cgroup_path, MEMORY_AND_SWAP_PREFIX)?;
-        let kernel = Self::get_memory_data(cgroup_path, MEMORY_KERNEL_PREFIX)?;
-        let kernel_tcp = Self::get_memory_data(cgroup_path, MEMORY_KERNEL_TCP_PREFIX)?;
-        let hierarchy = Self::hierarchy_enabled(cgroup_path)?;
-        let stats = Self::get_stat_data(cgroup_path)?;
+    fn stats(dir: &mut CgroupDir) -> Result<Self::Stats, Self::Error> {
+        let memory = Self::get_memory_data(dir, MEMORY_PREFIX)?;
+        let memswap = Self::get_memory_data(dir, MEMORY_AND_SWAP_PREFIX)?;
+        let kernel = Self::get_kernel_memory_data(dir, MEMORY_KERNEL_PREFIX)?;
+        let kernel_tcp = Self::get_kernel_memory_data(dir, MEMORY_KERNEL_TCP_PREFIX)?;
+        let hierarchy = Self::hierarchy_enabled(dir)?;
+        let stats = Self::get_stat_data(dir)?;
 
         Ok(MemoryStats {
             memory,
@@ -187,37 +181,50 @@ impl StatsProvider for Memory {
 
 impl Memory {
     fn get_memory_data(
-        cgroup_path: &Path,
+        dir: &mut CgroupDir,
         file_prefix: &str,
     ) -> Result<MemoryData, WrappedIoError> {
         let memory_data = MemoryData {
-            usage: parse_single_value(
-                &cgroup_path.join(format!("{file_prefix}{MEMORY_USAGE_IN_BYTES}")),
-            )?,
-            max_usage: parse_single_value(
-                &cgroup_path.join(format!("{file_prefix}{MEMORY_MAX_USAGE_IN_BYTES}")),
-            )?,
-            limit: parse_single_value(
-                &cgroup_path.join(format!("{file_prefix}{MEMORY_LIMIT_IN_BYTES}")),
-            )?,
-            fail_count: parse_single_value(
-                &cgroup_path.join(format!("{file_prefix}{MEMORY_FAIL_COUNT}")),
-            )?,
+            usage: dir.single_value(&format!("{file_prefix}{MEMORY_USAGE_IN_BYTES}"))?,
+            max_usage: dir.single_value(&format!("{file_prefix}{MEMORY_MAX_USAGE_IN_BYTES}"))?,
+            limit: dir.single_value(&format!("{file_prefix}{MEMORY_LIMIT_IN_BYTES}"))?,
+            fail_count: dir.single_value(&format!("{file_prefix}{MEMORY_FAIL_COUNT}"))?,
         };
 
         Ok(memory_data)
     }
 
-    fn hierarchy_enabled(cgroup_path: &Path) -> Result<bool, WrappedIoError> {
-        let hierarchy_path = cgroup_path.join(MEMORY_USE_HIERARCHY);
-        let hierarchy = common::read_cgroup_file(hierarchy_path)?;
-        let enabled = matches!(hierarchy.trim(), "1");
+    /// Kernel memory data, zero where the kernel does not account kernel
+    /// memory or removed a file, like the limit of kernel memory in 6.1
+    fn get_kernel_memory_data(
+        dir: &mut CgroupDir,
+        file_prefix: &str,
+    ) -> Result<MemoryData, WrappedIoError> {
+        let mut value = |suffix: &str| {
+            let file = format!("{file_prefix}{suffix}");
+            if dir.exists(&file) {
+                dir.single_value(&file)
+            } else {
+                Ok(0)
+            }
+        };
+
+        Ok(MemoryData {
+            usage: value(MEMORY_USAGE_IN_BYTES)?,
+            max_usage: value(MEMORY_MAX_USAGE_IN_BYTES)?,
+            limit: value(MEMORY_LIMIT_IN_BYTES)?,
+            fail_count: value(MEMORY_FAIL_COUNT)?,
+        })
+    }
+
+    fn hierarchy_enabled(dir: &mut CgroupDir) -> Result<bool, WrappedIoError> {
+        let enabled = matches!(dir.read(MEMORY_USE_HIERARCHY)?.trim(), "1");
 
         Ok(enabled)
     }
 
-    fn get_stat_data(cgroup_path: &Path) -> Result<HashMap<String, u64>, ParseFlatKeyedDataError> {
-        stats::parse_flat_keyed_data(&cgroup_path.join(MEMORY_STAT))
+    fn get_stat_data(dir: &mut CgroupDir) -> Result<HashMap<String, u64>, ParseFlatKeyedDataError> {
+        dir.flat_keyed(MEMORY_STAT)
     }
 
     fn get_memory_usage(cgroup_root: &Path) -> Result<u64, V1MemoryControllerError> {
@@ -349,6 +356,32 @@ impl Memory {
         }
     }
 
+    /// Sets a kernel memory limit, unless the kernel does not account kernel
+    /// memory or stopped supporting the limit, like the limit of kernel
+    /// memory since 5.16
+    fn set_kernel_limit(path: &Path, limit: i64) -> Result<(), V1MemoryControllerError> {
+        if !path.exists() {
+            tracing::warn!(
+                ?path,
+                limit,
+                "kernel memory limit is not supported by the kernel, ignoring it"
+            );
+            return Ok(());
+        }
+
+        match common::write_cgroup_file(path, limit) {
+            Err(err) if err.inner().raw_os_error() == Some(Errno::EOPNOTSUPP as i32) => {
+                tracing::warn!(
+                    ?path,
+                    limit,
+                    "kernel memory limit is no longer supported by the kernel, ignoring it"
+                );
+                Ok(())
+            }
+            result => Ok(result?),
+        }
+    }
+
     fn set_swap(swap: i64, cgroup_root: &Path) -> Result<(), V1MemoryControllerError> {
         if swap == 0 {
             return Ok(());
@@ -652,7 +685,9 @@ mod tests {
         )
         .unwrap();
 
-        let actual = Memory::get_memory_data(tmp.path(), MEMORY_PREFIX).expect("get cgroup stats");
+        let actual =
+            Memory::get_memory_data(&mut CgroupDir::open(tmp.path()).unwrap(), MEMORY_PREFIX)
+                .expect("get cgroup stats");
         let expected = MemoryData {
             usage: 1024,
             max_usage: 2048,
@@ -663,12 +698,55 @@ mod tests {
         assert_eq!(actual, expected);
     }
 
+    #[test]
+    fn test_stat_kernel_memory_data_without_kmem_files() {
+        let tmp = tempfile::tempdir().unwrap();
+        let mut dir = CgroupDir::open(tmp.path()).unwrap();
+        assert_eq!(
+            Memory::get_kernel_memory_data(&mut dir, MEMORY_KERNEL_PREFIX).unwrap(),
+            MemoryData::default()
+        );
+
+        // the limit of kernel memory was removed in 6.1, the usage is kept
+        set_fixture(
+            tmp.path(),
+            &format!("{MEMORY_KERNEL_PREFIX}{MEMORY_USAGE_IN_BYTES}"),
+            "8192\n",
+        )
+        .unwrap();
+        let actual = Memory::get_kernel_memory_data(&mut dir, MEMORY_KERNEL_PREFIX).unwrap();
+        assert_eq!(
+            actual,
+            MemoryData {
+                usage: 8192,
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn test_set_kernel_limits_without_kmem_files() {
+        let tmp = tempfile::tempdir().unwrap();
+        set_fixture(tmp.path(), CGROUP_KERNEL_TCP_MEMORY_LIMIT, "0").unwrap();
+
+        Memory::set_kernel_limit(&tmp.path().join(CGROUP_KERNEL_MEMORY_LIMIT), 4096)
+            .expect("skip missing kernel memory limit");
+        Memory::set_kernel_limit(&tmp.path().join(CGROUP_KERNEL_TCP_MEMORY_LIMIT), 2048)
+            .expect("set kernel tcp memory limit");
+
+        assert!(!tmp.path().join(CGROUP_KERNEL_MEMORY_LIMIT).exists());
+        let content =
+            std::fs::read_to_string(tmp.path().join(CGROUP_KERNEL_TCP_MEMORY_LIMIT)).unwrap();
+        assert_eq!(content, "2048");
+    }
+
     #[test]
     fn test_stat_hierarchy_enabled() {
         let tmp = tempfile::tempdir().unwrap();
         set_fixture(tmp.path(), MEMORY_USE_HIERARCHY, "1").unwrap();
 
-        let enabled = Memory::hierarchy_enabled(tmp.path()).expect("get cgroup stats");
+        let enabled = Memory::hierarchy_enabled(&mut CgroupDir::open(tmp.path()).unwrap())
+            .expect("get cgroup stats");
         assert!(enabled)
     }
 
@@ -677,7 +755,8 @@ mod tests {
         let tmp = tempfile::tempdir().unwrap();
         set_fixture(tmp.path(), MEMORY_USE_HIERARCHY, "0").unwrap();
 
-        let enabled = Memory::hierarchy_enabled(tmp.path()).expect("get cgroup stats");
+        let enabled = Memory::hierarchy_enabled(&mut CgroupDir::open(tmp.path()).unwrap())
+            .expect("get cgroup stats");
         assert!(!enabled)
     }
 
@@ -697,7 +776,8 @@ mod tests {
         .join("\n");
         set_fixture(tmp.path(), MEMORY_STAT, &content).unwrap();
 
-        let actual = Memory::get_stat_data(tmp.path()).expect("get cgroup data");
+        let actual = Memory::get_stat_data(&mut CgroupDir::open(tmp.path()).unwrap())
+            .expect("get cgroup data");
         let expected: HashMap<String, u64> = [
             ("cache".to_owned(), 0),
             ("rss".to_owned(), 0),
@@ -13,6 +13,7 @@ mod network_classifier;
 mod network_priority;
 pub mod perf_event;
 mod pids;
+mod rdma;
 pub mod util;
 pub use controller_type::ControllerType;
 pub use manager::Manager;
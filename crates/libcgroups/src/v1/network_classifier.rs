@@ -4,6 +4,9 @@ use oci_spec::runtime::LinuxNetwork;
 
 use super::controller::Controller;
 use crate::common::{self, ControllerOpt, WrappedIoError};
+use crate::stats::{CgroupDir, StatsProvider};
+
+const CGROUP_NET_CLS_CLASSID: &str = "net_cls.classid";
 
 pub struct NetworkClassifier {}
 
@@ -26,16 +29,40 @@ impl Controller for NetworkClassifier {
     }
 }
 
+impl StatsProvider for NetworkClassifier {
+    type Error = WrappedIoError;
+    type Stats = u32;
+
+    fn stats(dir: &mut CgroupDir) -> Result<Self::Stats, Self::Error> {
+        let class_id = dir.single_value(CGROUP_NET_CLS_CLASSID)?;
+        // the kernel stores the class id as u32
+        Ok(class_id as u32)
+    }
+}
+
 impl NetworkClassifier {
     fn apply(root_path: &Path, network: &LinuxNetwork) -> Result<(), WrappedIoError> {
         if let Some(class_id) = network.class_id() {
-            common::write_cgroup_file(root_path.join("net_cls.classid"), class_id)?;
+            // The kernel only accepts decimal values. The class id is usually
+            // thought of as tc handle though, so that is what gets logged.
+            tracing::debug!(
+                class_id,
+                handle = %format_class_id(class_id),
+                "set network class id"
+            );
+            common::write_cgroup_file(root_path.join(CGROUP_NET_CLS_CLASSID), class_id)?;
         }
 
         Ok(())
     }
 }
 
+/// Formats a class id as tc handle, i.e. `major:minor` in hexadecimal, with
+/// the major number in the upper and the minor number in the lower 16 bits
+fn format_class_id(class_id: u32) -> String {
+    format!("{:x}:{:x}", class_id >> 16, class_id & 0xffff)
+}
+
 #[cfg(test)]
 mod tests {
     use oci_spec::runtime::LinuxNetworkBuilder;
@@ -46,7 +73,7 @@ mod tests {
     #[test]
     fn test_apply_network_classifier() {
         let tmp = tempfile::tempdir().unwrap();
-        set_fixture(tmp.path(), "net_cls.classid", "0").expect("set fixture for classID");
+        set_fixture(tmp.path(), CGROUP_NET_CLS_CLASSID, "0").expect("set fixture for classID");
 
         let id = 0x100001u32;
         let network = LinuxNetworkBuilder::default()
@@ -57,8 +84,26 @@ mod tests {
 
         NetworkClassifier::apply(tmp.path(), &network).expect("apply network classID");
 
-        let content = std::fs::read_to_string(tmp.path().join("net_cls.classid"))
+        let content = std::fs::read_to_string(tmp.path().join(CGROUP_NET_CLS_CLASSID))
             .expect("Read classID contents");
         assert_eq!(id.to_string(), content);
     }
+
+    #[test]
+    fn test_format_class_id() {
+        assert_eq!(format_class_id(0x100001), "10:1");
+        assert_eq!(format_class_id(0xffffabcd), "ffff:abcd");
+        assert_eq!(format_class_id(0), "0:0");
+    }
+
+    #[test]
+    fn test_stat_network_classifier() {
+        let tmp = tempfile::tempdir().unwrap();
+        set_fixture(tmp.path(), CGROUP_NET_CLS_CLASSID, "1048577\n")
+            .expect("set fixture for classID");
+
+        let class_id = NetworkClassifier::stats(&mut CgroupDir::open(tmp.path()).unwrap())
+            .expect("get classID");
+        assert_eq!(class_id, 0x100001);
+    }
 }
@@ -1,14 +1,34 @@
+use std::collections::HashMap;
 use std::path::Path;
 
+use nix::errno::Errno;
 use oci_spec::runtime::LinuxNetwork;
 
 use super::controller::Controller;
 use crate::common::{self, ControllerOpt, WrappedIoError};
+use crate::stats::{CgroupDir, ParseFlatKeyedDataError, StatsProvider};
+
+const CGROUP_NET_PRIO_IFPRIOMAP: &str = "net_prio.ifpriomap";
+/// Maximum length of a network interface name, IFNAMSIZ without the
+/// terminating null byte
+const MAX_INTERFACE_NAME_LEN: usize = 15;
+
+#[derive(thiserror::Error, Debug)]
+pub enum V1NetworkPriorityControllerError {
+    #[error("io error: {0}")]
+    WrappedIo(#[from] WrappedIoError),
+    #[error("invalid network interface name {0:?}")]
+    InvalidInterface(String),
+    // The kernel looks the interfaces up in the initial network namespace,
+    // regardless of the network namespace of the container.
+    #[error("network interface {0} does not exist in the initial network namespace")]
+    UnknownInterface(String),
+}
 
 pub struct NetworkPriority {}
 
 impl Controller for NetworkPriority {
-    type Error = WrappedIoError;
+    type Error = V1NetworkPriorityControllerError;
     type Resource = LinuxNetwork;
 
     fn apply(controller_opt: &ControllerOpt, cgroup_root: &Path) -> Result<(), Self::Error> {
@@ -26,17 +46,68 @@ impl Controller for NetworkPriority {
     }
 }
 
+impl StatsProvider for NetworkPriority {
+    type Error = ParseFlatKeyedDataError;
+    type Stats = HashMap<String, u32>;
+
+    fn stats(dir: &mut CgroupDir) -> Result<Self::Stats, Self::Error> {
+        let priorities = dir.flat_keyed(CGROUP_NET_PRIO_IFPRIOMAP)?;
+        Ok(priorities
+            .into_iter()
+            .map(|(interface, priority)| (interface, priority as u32))
+            .collect())
+    }
+}
+
 impl NetworkPriority {
-    fn apply(root_path: &Path, network: &LinuxNetwork) -> Result<(), WrappedIoError> {
-        if let Some(ni_priorities) = network.priorities() {
-            let priorities: String = ni_priorities.iter().map(|p| p.to_string()).collect();
-            common::write_cgroup_file_str(root_path.join("net_prio.ifpriomap"), priorities.trim())?;
+    fn apply(
+        root_path: &Path,
+        network: &LinuxNetwork,
+    ) -> Result<(), V1NetworkPriorityControllerError> {
+        let ni_priorities = match network.priorities() {
+            Some(ni_priorities) => ni_priorities,
+            None => return Ok(()),
+        };
+
+        for ni_priority in ni_priorities {
+            if !is_valid_interface_name(ni_priority.name()) {
+                return Err(V1NetworkPriorityControllerError::InvalidInterface(
+                    ni_priority.name().to_owned(),
+                ));
+            }
+        }
+
+        // The kernel only parses the first entry of every write
+        let path = root_path.join(CGROUP_NET_PRIO_IFPRIOMAP);
+        for ni_priority in ni_priorities {
+            let entry = format!("{} {}", ni_priority.name(), ni_priority.priority());
+            match common::write_cgroup_file_str(&path, &entry) {
+                Err(WrappedIoError::Write { err, .. })
+                    if err.raw_os_error() == Some(Errno::ENODEV as i32) =>
+                {
+                    tracing::error!(interface = ni_priority.name(), "unknown network interface");
+                    return Err(V1NetworkPriorityControllerError::UnknownInterface(
+                        ni_priority.name().to_owned(),
+                    ));
+                }
+                result => result?,
+            }
         }
 
         Ok(())
     }
 }
 
+fn is_valid_interface_name(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= MAX_INTERFACE_NAME_LEN
+        && name != "."
+        && name != ".."
+        && !name
+            .chars()
+            .any(|c| c == '/' || c == ':' || c.is_whitespace())
+}
+
 #[cfg(test)]
 mod tests {
     use oci_spec::runtime::{LinuxInterfacePriorityBuilder, LinuxNetworkBuilder};
@@ -44,32 +115,69 @@ mod tests {
     use super::*;
     use crate::test::set_fixture;
 
+    fn network(priorities: &[(&str, u32)]) -> LinuxNetwork {
+        let priorities = priorities
+            .iter()
+            .map(|(name, priority)| {
+                LinuxInterfacePriorityBuilder::default()
+                    .name(*name)
+                    .priority(*priority)
+                    .build()
+                    .unwrap()
+            })
+            .collect::<Vec<_>>();
+        LinuxNetworkBuilder::default()
+            .priorities(priorities)
+            .build()
+            .unwrap()
+    }
+
     #[test]
     fn test_apply_network_priorities() {
         let tmp = tempfile::tempdir().unwrap();
-        set_fixture(tmp.path(), "net_prio.ifpriomap", "").expect("set fixture for priority map");
-        let priorities = vec![
-            LinuxInterfacePriorityBuilder::default()
-                .name("a")
-                .priority(1u32)
-                .build()
-                .unwrap(),
-            LinuxInterfacePriorityBuilder::default()
-                .name("b")
-                .priority(2u32)
-                .build()
-                .unwrap(),
-        ];
-        let priorities_string = priorities.iter().map(|p| p.to_string()).collect::<String>();
-        let network = LinuxNetworkBuilder::default()
-            .priorities(priorities)
-            .build()
-            .unwrap();
+        set_fixture(tmp.path(), CGROUP_NET_PRIO_IFPRIOMAP, "")
+            .expect("set fixture for priority map");
+
+        NetworkPriority::apply(tmp.path(), &network(&[("a", 1), ("b", 2)]))
+            .expect("apply network priorities");
+
+        // every entry is written on its own, so only the last one remains in
+        // the fixture file
+        let content = std::fs::read_to_string(tmp.path().join(CGROUP_NET_PRIO_IFPRIOMAP))
+            .expect("Read priority map contents");
+        assert_eq!("b 2", content);
+    }
+
+    #[test]
+    fn test_apply_invalid_interface() {
+        let tmp = tempfile::tempdir().unwrap();
+        set_fixture(tmp.path(), CGROUP_NET_PRIO_IFPRIOMAP, "")
+            .expect("set fixture for priority map");
 
-        NetworkPriority::apply(tmp.path(), &network).expect("apply network priorities");
+        for name in ["", "eth 0", "a/b", "..", "averyveryverylongname"] {
+            let result = NetworkPriority::apply(tmp.path(), &network(&[("lo", 1), (name, 2)]));
+            assert!(
+                matches!(result, Err(V1NetworkPriorityControllerError::InvalidInterface(ref n)) if n == name),
+                "{name:?} should be invalid"
+            );
+        }
+        // nothing is written if any entry is invalid
+        let content = std::fs::read_to_string(tmp.path().join(CGROUP_NET_PRIO_IFPRIOMAP))
+            .expect("Read priority map contents");
+        assert_eq!("", content);
+    }
+
+    #[test]
+    fn test_stat_network_priorities() {
+        let tmp = tempfile::tempdir().unwrap();
+        set_fixture(tmp.path(), CGROUP_NET_PRIO_IFPRIOMAP, "lo 0\neth0 5\n")
+            .expect("set fixture for priority map");
 
-        let content = std::fs::read_to_string(tmp.path().join("net_prio.ifpriomap"))
-            .expect("Read classID contents");
-        assert_eq!(priorities_string.trim(), content);
+        let priorities = NetworkPriority::stats(&mut CgroupDir::open(tmp.path()).unwrap())
+            .expect("get priorities");
+        assert_eq!(
+            priorities,
+            HashMap::from([("lo".to_owned(), 0), ("eth0".to_owned(), 5)])
+        );
     }
 }
@@ -4,7 +4,7 @@ use oci_spec::runtime::LinuxPids;
 
 use super::controller::Controller;
 use crate::common::{self, ControllerOpt, WrappedIoError};
-use crate::stats::{self, PidStats, PidStatsError, StatsProvider};
+use crate::stats::{self, CgroupDir, PidStats, PidStatsError, StatsProvider};
 
 // Contains the maximum allowed number of active pids
 const CGROUP_PIDS_MAX: &str = "pids.max";
@@ -34,8 +34,8 @@ impl StatsProvider for Pids {
     type Error = PidStatsError;
     type Stats = PidStats;
 
-    fn stats(cgroup_path: &Path) -> Result<Self::Stats, Self::Error> {
-        stats::pid_stats(cgroup_path)
+    fn stats(dir: &mut CgroupDir) -> Result<Self::Stats, Self::Error> {
+        stats::pid_stats(dir)
     }
 }
 
@@ -95,7 +95,8 @@ mod tests {
         set_fixture(tmp.path(), CGROUP_PIDS_CURRENT, "5\n").unwrap();
         set_fixture(tmp.path(), CGROUP_PIDS_MAX, "30\n").unwrap();
 
-        let stats = Pids::stats(tmp.path()).expect("get cgroup stats");
+        let stats =
+            Pids::stats(&mut CgroupDir::open(tmp.path()).unwrap()).expect("get cgroup stats");
 
         assert_eq!(stats.current, 5);
         assert_eq!(stats.limit, 30);
@@ -107,7 +108,8 @@ mod tests {
         set_fixture(tmp.path(), CGROUP_PIDS_CURRENT, "5\n").unwrap();
         set_fixture(tmp.path(), CGROUP_PIDS_MAX, "max\n").unwrap();
 
-        let stats = Pids::stats(tmp.path()).expect("get cgroup stats");
+        let stats =
+            Pids::stats(&mut CgroupDir::open(tmp.path()).unwrap()).expect("get cgroup stats");
 
         assert_eq!(stats.current, 5);
         assert_eq!(stats.limit, 0);
@@ -0,0 +1,42 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use oci_spec::runtime::LinuxRdma;
+
+use super::controller::Controller;
+use crate::common::ControllerOpt;
+use crate::rdma::{self, RdmaError};
+use crate::stats::{CgroupDir, RdmaStats, StatsProvider};
+
+pub struct Rdma {}
+
+impl Controller for Rdma {
+    type Error = RdmaError;
+    type Resource = HashMap<String, LinuxRdma>;
+
+    fn apply(controller_opt: &ControllerOpt, cgroup_root: &Path) -> Result<(), Self::Error> {
+        if let Some(limits) = Self::needs_to_handle(controller_opt) {
+            tracing::debug!("Apply rdma cgroup config");
+            rdma::apply(cgroup_root, limits)?;
+        }
+
+        Ok(())
+    }
+
+    fn needs_to_handle<'a>(controller_opt: &'a ControllerOpt) -> Option<&'a Self::Resource> {
+        controller_opt
+            .resources
+            .rdma()
+            .as_ref()
+            .filter(|limits| !limits.is_empty())
+    }
+}
+
+impl StatsProvider for Rdma {
+    type Error = RdmaError;
+    type Stats = HashMap<String, RdmaStats>;
+
+    fn stats(dir: &mut CgroupDir) -> Result<Self::Stats, Self::Error> {
+        rdma::stats(dir)
+    }
+}
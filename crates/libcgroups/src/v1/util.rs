@@ -1,7 +1,7 @@
 use std::collections::HashMap;
 use std::path::PathBuf;
 
-use procfs::process::Process;
+use procfs::process::{MountInfo, Process};
 use procfs::ProcError;
 
 use super::controller_type::CONTROLLERS;
@@ -33,10 +33,11 @@ pub fn list_subsystem_mount_points() -> Result<Vec<PathBuf>, V1MountPointError>
 /// List the mount points of all currently supported cgroup subsystems.
 pub fn list_supported_mount_points() -> Result<HashMap<ControllerType, PathBuf>, V1MountPointError>
 {
+    let mountinfo = read_mountinfo()?;
     let mut mount_paths = HashMap::with_capacity(CONTROLLERS.len());
 
     for controller in CONTROLLERS {
-        if let Ok(mount_point) = get_subsystem_mount_point(controller) {
+        if let Some(mount_point) = find_subsystem_mount_point(&mountinfo, controller) {
             mount_paths.insert(controller.to_owned(), mount_point);
         }
     }
@@ -45,40 +46,125 @@ pub fn list_supported_mount_points() -> Result<HashMap<ControllerType, PathBuf>,
 }
 
 pub fn get_subsystem_mount_point(subsystem: &ControllerType) -> Result<PathBuf, V1MountPointError> {
-    let subsystem_name = subsystem.to_string();
-    Process::myself()
+    find_subsystem_mount_point(&read_mountinfo()?, subsystem).ok_or(V1MountPointError::NotFound {
+        subsystem: *subsystem,
+    })
+}
+
+fn read_mountinfo() -> Result<Vec<MountInfo>, V1MountPointError> {
+    Ok(Process::myself()
         .map_err(V1MountPointError::ReadSelf)?
         .mountinfo()
         .map_err(V1MountPointError::MountInfo)?
         .into_iter()
-        .find(|m| {
-            if m.fs_type == "cgroup" {
-                // Some systems mount net_prio and net_cls in the same directory
-                // other systems mount them in their own directories. This
-                // should handle both cases.
-                if subsystem_name == "net_cls" {
-                    return m.mount_point.ends_with("net_cls,net_prio")
-                        || m.mount_point.ends_with("net_prio,net_cls")
-                        || m.mount_point.ends_with("net_cls");
-                } else if subsystem_name == "net_prio" {
-                    return m.mount_point.ends_with("net_cls,net_prio")
-                        || m.mount_point.ends_with("net_prio,net_cls")
-                        || m.mount_point.ends_with("net_prio");
-                }
-
-                if subsystem_name == "cpu" {
-                    return m.mount_point.ends_with("cpu,cpuacct")
-                        || m.mount_point.ends_with("cpu");
-                }
-                if subsystem_name == "cpuacct" {
-                    return m.mount_point.ends_with("cpu,cpuacct")
-                        || m.mount_point.ends_with("cpuacct");
-                }
-            }
-            m.mount_point.ends_with(&subsystem_name)
-        })
-        .map(|m| m.mount_point)
-        .ok_or(V1MountPointError::NotFound {
-            subsystem: *subsystem,
-        })
+        .collect())
+}
+
+/// Finds the mount point of the hierarchy the subsystem is attached to.
+/// Distributions differ in which controllers they co-mount (e.g. cpu and
+/// cpuacct may share a hierarchy or be mounted separately) and in how they
+/// name the mount points, so the decision is based on the super options of
+/// the mount, which list the attached controllers, and not on its path.
+fn find_subsystem_mount_point(
+    mountinfo: &[MountInfo],
+    subsystem: &ControllerType,
+) -> Option<PathBuf> {
+    let subsystem_name = subsystem.as_ref();
+    mountinfo
+        .iter()
+        .find(|m| m.fs_type == "cgroup" && m.super_options.contains_key(subsystem_name))
+        .map(|m| m.mount_point.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mountinfo(fixture: &str) -> Vec<MountInfo> {
+        fixture
+            .lines()
+            .map(|line| MountInfo::from_line(line).expect("valid mountinfo line"))
+            .collect()
+    }
+
+    fn mount_point(fixture: &[MountInfo], subsystem: ControllerType) -> Option<PathBuf> {
+        find_subsystem_mount_point(fixture, &subsystem)
+    }
+
+    // systemd based distributions such as Fedora, Debian and Ubuntu co-mount
+    // cpu with cpuacct and net_cls with net_prio.
+    const COMOUNTED: &str = "\
+25 19 0:22 / /sys/fs/cgroup ro,nosuid,nodev,noexec shared:9 - tmpfs tmpfs ro,mode=755
+26 25 0:23 / /sys/fs/cgroup/unified rw,nosuid,nodev,noexec,relatime shared:10 - cgroup2 cgroup2 rw
+27 25 0:24 / /sys/fs/cgroup/systemd rw,nosuid,nodev,noexec,relatime shared:11 - cgroup cgroup rw,xattr,name=systemd
+30 25 0:27 / /sys/fs/cgroup/cpu,cpuacct rw,nosuid,nodev,noexec,relatime shared:14 - cgroup cgroup rw,cpu,cpuacct
+31 25 0:28 / /sys/fs/cgroup/cpuset rw,nosuid,nodev,noexec,relatime shared:15 - cgroup cgroup rw,cpuset
+32 25 0:29 / /sys/fs/cgroup/net_cls,net_prio rw,nosuid,nodev,noexec,relatime shared:16 - cgroup cgroup rw,net_cls,net_prio
+33 25 0:30 / /sys/fs/cgroup/memory rw,nosuid,nodev,noexec,relatime shared:17 - cgroup cgroup rw,memory
+34 25 0:31 / /sys/fs/cgroup/freezer rw,nosuid,nodev,noexec,relatime shared:18 - cgroup cgroup rw,freezer";
+
+    // Every controller in a hierarchy of its own.
+    const SPLIT: &str = "\
+25 19 0:22 / /sys/fs/cgroup rw,nosuid,nodev,noexec shared:9 - tmpfs tmpfs rw,mode=755
+30 25 0:27 / /sys/fs/cgroup/cpu rw,nosuid,nodev,noexec,relatime shared:14 - cgroup cgroup rw,cpu
+31 25 0:28 / /sys/fs/cgroup/cpuacct rw,nosuid,nodev,noexec,relatime shared:15 - cgroup cgroup rw,cpuacct
+32 25 0:29 / /sys/fs/cgroup/cpuset rw,nosuid,nodev,noexec,relatime shared:16 - cgroup cgroup rw,cpuset
+33 25 0:30 / /sys/fs/cgroup/net_prio rw,nosuid,nodev,noexec,relatime shared:17 - cgroup cgroup rw,net_prio";
+
+    // Older distributions (e.g. RHEL 6) mount the hierarchies below /cgroup,
+    // with mount points which do not match the controller names.
+    const CUSTOM_NAMES: &str = "\
+30 20 0:27 / /cgroup/cpu_and_acct rw,relatime - cgroup cgroup rw,cpuacct,cpu
+31 20 0:28 / /cgroup/cpuset rw,relatime - cgroup cgroup rw,cpuset
+32 20 0:29 / /cgroup/net rw,relatime - cgroup cgroup rw,net_prio,net_cls";
+
+    #[test]
+    fn test_comounted_layout() {
+        let mounts = mountinfo(COMOUNTED);
+        let cpu = Some(PathBuf::from("/sys/fs/cgroup/cpu,cpuacct"));
+        assert_eq!(mount_point(&mounts, ControllerType::Cpu), cpu);
+        assert_eq!(mount_point(&mounts, ControllerType::CpuAcct), cpu);
+        assert_eq!(
+            mount_point(&mounts, ControllerType::CpuSet),
+            Some(PathBuf::from("/sys/fs/cgroup/cpuset"))
+        );
+        let net = Some(PathBuf::from("/sys/fs/cgroup/net_cls,net_prio"));
+        assert_eq!(mount_point(&mounts, ControllerType::NetworkClassifier), net);
+        assert_eq!(mount_point(&mounts, ControllerType::NetworkPriority), net);
+        assert_eq!(mount_point(&mounts, ControllerType::Pids), None);
+    }
+
+    #[test]
+    fn test_split_layout() {
+        let mounts = mountinfo(SPLIT);
+        assert_eq!(
+            mount_point(&mounts, ControllerType::Cpu),
+            Some(PathBuf::from("/sys/fs/cgroup/cpu"))
+        );
+        assert_eq!(
+            mount_point(&mounts, ControllerType::CpuAcct),
+            Some(PathBuf::from("/sys/fs/cgroup/cpuacct"))
+        );
+        assert_eq!(
+            mount_point(&mounts, ControllerType::NetworkPriority),
+            Some(PathBuf::from("/sys/fs/cgroup/net_prio"))
+        );
+        assert_eq!(
+            mount_point(&mounts, ControllerType::NetworkClassifier),
+            None
+        );
+    }
+
+    #[test]
+    fn test_custom_mount_point_names() {
+        let mounts = mountinfo(CUSTOM_NAMES);
+        let cpu = Some(PathBuf::from("/cgroup/cpu_and_acct"));
+        assert_eq!(mount_point(&mounts, ControllerType::Cpu), cpu);
+        assert_eq!(mount_point(&mounts, ControllerType::CpuAcct), cpu);
+        assert_eq!(
+            mount_point(&mounts, ControllerType::NetworkClassifier),
+            Some(PathBuf::from("/cgroup/net"))
+        );
+        assert_eq!(mount_point(&mounts, ControllerType::Memory), None);
+    }
 }
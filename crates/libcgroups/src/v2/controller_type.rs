@@ -8,6 +8,8 @@ pub enum ControllerType {
     Memory,
     HugeTlb,
     Pids,
+    Misc,
+    Rdma,
 }
 
 impl Display for ControllerType {
@@ -19,6 +21,8 @@ impl Display for ControllerType {
             Self::Memory => "memory",
             Self::HugeTlb => "hugetlb",
             Self::Pids => "pids",
+            Self::Misc => "misc",
+            Self::Rdma => "rdma",
         };
 
         write!(f, "{print}")
@@ -31,7 +35,9 @@ pub const CONTROLLER_TYPES: &[ControllerType] = &[
     ControllerType::HugeTlb,
     ControllerType::Io,
     ControllerType::Memory,
+    ControllerType::Misc,
     ControllerType::Pids,
+    ControllerType::Rdma,
 ];
 
 #[derive(Clone, Copy, PartialEq, Eq, Hash)]
@@ -5,7 +5,7 @@ use oci_spec::runtime::LinuxCpu;
 
 use super::controller::Controller;
 use crate::common::{self, ControllerOpt, WrappedIoError};
-use crate::stats::{self, CpuStats, ParseFlatKeyedDataError, StatsProvider};
+use crate::stats::{CgroupDir, CpuStats, ParseFlatKeyedDataError, StatsProvider};
 
 const CGROUP_CPU_WEIGHT: &str = "cpu.weight";
 const CGROUP_CPU_MAX: &str = "cpu.max";
@@ -13,6 +13,7 @@ const CGROUP_CPU_BURST: &str = "cpu.max.burst";
 const CGROUP_CPU_IDLE: &str = "cpu.idle";
 const UNRESTRICTED_QUOTA: &str = "max";
 const MAX_CPU_WEIGHT: u64 = 10000;
+const MIN_CPU_WEIGHT: u64 = 1;
 
 const CPU_STAT: &str = "cpu.stat";
 const CPU_PSI: &str = "cpu.pressure";
@@ -23,6 +24,10 @@ pub enum V2CpuControllerError {
     WrappedIo(#[from] WrappedIoError),
     #[error("realtime is not supported on v2 yet")]
     RealtimeV2,
+    #[error("invalid cpu idle value {0}, must be 0 or 1")]
+    InvalidIdle(i64),
+    #[error("cpu burst is not supported by the kernel, cpu.max.burst requires 5.14 or later")]
+    BurstUnsupported,
 }
 
 pub struct Cpu {}
@@ -47,17 +52,19 @@ pub enum V2CpuStatsError {
     ParseNestedKeyedData(#[from] ParseFlatKeyedDataError),
     #[error("missing field {field} from {path}")]
     MissingField { field: &'static str, path: PathBuf },
+    #[error("invalid value {value} in {path}")]
+    InvalidValue { value: String, path: PathBuf },
 }
 
 impl StatsProvider for Cpu {
     type Error = V2CpuStatsError;
     type Stats = CpuStats;
 
-    fn stats(cgroup_path: &Path) -> Result<Self::Stats, Self::Error> {
+    fn stats(dir: &mut CgroupDir) -> Result<Self::Stats, Self::Error> {
         let mut stats = CpuStats::default();
-        let stats_path = cgroup_path.join(CPU_STAT);
+        let stats_path = dir.path().join(CPU_STAT);
 
-        let stats_table = stats::parse_flat_keyed_data(&stats_path)?;
+        let stats_table = dir.flat_keyed(CPU_STAT)?;
 
         macro_rules! get {
             ($name: expr => $field1:ident.$field2:ident) => {
@@ -78,7 +85,17 @@ impl StatsProvider for Cpu {
         get!("nr_throttled" => throttling.throttled_periods);
         get!("throttled_usec" => throttling.throttled_time);
 
-        stats.psi = stats::psi_stats(&cgroup_path.join(CPU_PSI))?;
+        // cpu.idle is only available since kernel 5.15
+        if dir.exists(CGROUP_CPU_IDLE) {
+            let path = dir.path().join(CGROUP_CPU_IDLE);
+            let idle = dir.read(CGROUP_CPU_IDLE)?.trim();
+            stats.idle = Some(idle.parse().map_err(|_| V2CpuStatsError::InvalidValue {
+                value: idle.to_owned(),
+                path,
+            })?);
+        }
+
+        stats.psi = dir.psi(CPU_PSI)?;
         Ok(stats)
     }
 }
@@ -89,6 +106,12 @@ impl Cpu {
             return Err(V2CpuControllerError::RealtimeV2);
         }
 
+        if let Some(idle) = cpu.idle() {
+            if !(0..=1).contains(&idle) {
+                return Err(V2CpuControllerError::InvalidIdle(idle));
+            }
+        }
+
         if let Some(mut shares) = cpu.shares() {
             shares = Self::convert_shares_to_cgroup2(shares);
             if shares != 0 {
@@ -113,16 +136,40 @@ impl Cpu {
         // the kernel default is 'max 100000'
         // 250000 250000 -> 1 CPU worth of runtime every 250ms
         // 10000 50000 -> 20% of one CPU every 50ms
+        // The kernel rejects a burst larger than the quota, so the burst is
+        // written first when the quota is lowered and last when it is raised.
+        // Which one it is is not known, so writing it first is tried.
+        let burst_file = path.join(CGROUP_CPU_BURST);
+        let mut burst = cpu.burst();
+        if let Some(value) = burst {
+            if !burst_file.exists() {
+                return Err(V2CpuControllerError::BurstUnsupported);
+            }
+            match common::write_cgroup_file(&burst_file, value) {
+                Ok(()) => burst = None,
+                Err(err) if new_cpu_max.is_none() => return Err(err.into()),
+                Err(err) => tracing::debug!(?err, "retrying cpu burst after cpu.max"),
+            }
+        }
         if let Some(cpu_max) = new_cpu_max {
             common::write_cgroup_file_str(&cpu_max_file, &cpu_max)?;
         }
-
-        if let Some(burst) = cpu.burst() {
-            common::write_cgroup_file(path.join(CGROUP_CPU_BURST), burst)?;
+        if let Some(value) = burst {
+            common::write_cgroup_file(&burst_file, value)?;
         }
 
         if let Some(idle) = cpu.idle() {
-            common::write_cgroup_file(path.join(CGROUP_CPU_IDLE), idle)?;
+            let idle_file = path.join(CGROUP_CPU_IDLE);
+            if idle_file.exists() {
+                common::write_cgroup_file(idle_file, idle)?;
+            } else if idle != 0 && cpu.shares().is_none() {
+                // cpu.idle requires kernel 5.15, before the lowest weight
+                // comes closest to it
+                tracing::warn!(
+                    "cpu.idle is not supported by the kernel, setting the lowest weight"
+                );
+                common::write_cgroup_file(path.join(CGROUP_CPU_WEIGHT), MIN_CPU_WEIGHT)?;
+            }
         }
 
         Ok(())
@@ -215,6 +262,32 @@ mod tests {
         assert_eq!(content, format!("{IDLE}"))
     }
 
+    #[test]
+    fn test_set_cpu_idle_without_kernel_support() {
+        let (tmp, weight) = setup(CGROUP_CPU_WEIGHT);
+        let cpu = LinuxCpuBuilder::default().idle(1).build().unwrap();
+
+        Cpu::apply(tmp.path(), &cpu).expect("apply cpu");
+
+        let content = fs::read_to_string(weight)
+            .unwrap_or_else(|_| panic!("read {CGROUP_CPU_WEIGHT} file content"));
+        assert_eq!(content, MIN_CPU_WEIGHT.to_string());
+        assert!(!tmp.path().join(CGROUP_CPU_IDLE).exists());
+    }
+
+    #[test]
+    fn test_set_invalid_cpu_idle() {
+        let (tmp, idle) = setup(CGROUP_CPU_IDLE);
+        let cpu = LinuxCpuBuilder::default().idle(2).build().unwrap();
+
+        let result = Cpu::apply(tmp.path(), &cpu);
+
+        assert!(matches!(result, Err(V2CpuControllerError::InvalidIdle(2))));
+        let content = fs::read_to_string(idle)
+            .unwrap_or_else(|_| panic!("read {CGROUP_CPU_IDLE} file content"));
+        assert_eq!(content, "");
+    }
+
     #[test]
     fn test_set_positive_quota() {
         // arrange
@@ -338,7 +411,8 @@ mod tests {
         set_fixture(tmp.path(), CPU_STAT, &content).expect("create stat file");
         set_fixture(tmp.path(), CPU_PSI, "").expect("create psi file");
 
-        let actual = Cpu::stats(tmp.path()).expect("get cgroup stats");
+        let actual =
+            Cpu::stats(&mut CgroupDir::open(tmp.path()).unwrap()).expect("get cgroup stats");
         let expected = CpuStats {
             usage: CpuUsage {
                 usage_total: 7730,
@@ -356,6 +430,28 @@ mod tests {
 
         assert_eq!(actual.usage, expected.usage);
         assert_eq!(actual.throttling, expected.throttling);
+        assert_eq!(actual.idle, None);
+    }
+
+    #[test]
+    fn test_stat_idle() {
+        let tmp = tempfile::tempdir().unwrap();
+        let content = [
+            "usage_usec 7730",
+            "user_usec 4387",
+            "system_usec 3498",
+            "nr_periods 0",
+            "nr_throttled 0",
+            "throttled_usec 0",
+        ]
+        .join("\n");
+        set_fixture(tmp.path(), CPU_STAT, &content).expect("create stat file");
+        set_fixture(tmp.path(), CPU_PSI, "").expect("create psi file");
+        set_fixture(tmp.path(), CGROUP_CPU_IDLE, "1\n").expect("create idle file");
+
+        let actual =
+            Cpu::stats(&mut CgroupDir::open(tmp.path()).unwrap()).expect("get cgroup stats");
+        assert_eq!(actual.idle, Some(1));
     }
 
     #[test]
@@ -369,4 +465,33 @@ mod tests {
         let actual = fs::read_to_string(burst_file).expect("read burst file");
         assert_eq!(actual, expected.to_string());
     }
+
+    #[test]
+    fn test_burst_with_quota() {
+        let (tmp, burst_file) = setup(CGROUP_CPU_BURST);
+        let max_file = set_fixture(tmp.path(), CGROUP_CPU_MAX, "").expect("create max file");
+        let cpu = LinuxCpuBuilder::default()
+            .quota(50000)
+            .period(100000u64)
+            .burst(20000u64)
+            .build()
+            .unwrap();
+
+        Cpu::apply(tmp.path(), &cpu).expect("apply cpu");
+
+        assert_eq!(fs::read_to_string(max_file).unwrap(), "50000 100000");
+        assert_eq!(fs::read_to_string(burst_file).unwrap(), "20000");
+    }
+
+    #[test]
+    fn test_burst_unsupported() {
+        let tmp = tempfile::tempdir().unwrap();
+        let cpu = LinuxCpuBuilder::default().burst(100000u64).build().unwrap();
+
+        let result = Cpu::apply(tmp.path(), &cpu);
+        assert!(matches!(
+            result,
+            Err(V2CpuControllerError::BurstUnsupported)
+        ));
+    }
 }
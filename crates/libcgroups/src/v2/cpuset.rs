@@ -1,4 +1,14 @@
-use std::path::Path;
+//! Besides the cpus and memory nodes of the spec, the cpuset of a cgroup can
+//! be turned into a partition with `cpuset.cpus.partition` of the unified
+//! map. The cpus of a `root` or `isolated` partition are exclusive to it, and
+//! those of an isolated partition are not load balanced by the scheduler,
+//! which real-time workloads need. The parent cgroup has to be a partition
+//! root itself, the root cgroup always is.
+//!
+//! kernel doc: https://www.kernel.org/doc/html/latest/admin-guide/cgroup-v2.html#cpuset-interface-files
+use std::fmt::{self, Display};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 
 use oci_spec::runtime::LinuxCpu;
 
@@ -7,17 +17,85 @@ use crate::common::{self, ControllerOpt, WrappedIoError};
 
 const CGROUP_CPUSET_CPUS: &str = "cpuset.cpus";
 const CGROUP_CPUSET_MEMS: &str = "cpuset.mems";
+pub(crate) const CGROUP_CPUSET_PARTITION: &str = "cpuset.cpus.partition";
+
+#[derive(thiserror::Error, Debug)]
+pub enum V2CpuSetControllerError {
+    #[error("io error: {0}")]
+    WrappedIo(#[from] WrappedIoError),
+    #[error("invalid cpuset partition {0:?}, expected member, root or isolated")]
+    InvalidPartition(String),
+    #[error("a {partition} partition requires the parent cgroup {path:?} to be a partition root, it is {parent}")]
+    ParentNotPartitionRoot {
+        partition: Partition,
+        path: PathBuf,
+        parent: String,
+    },
+    #[error("a {0} partition requires cpus in the cpuset")]
+    NoCpus(Partition),
+    #[error("the kernel rejected the {partition} partition: {reason}")]
+    Rejected {
+        partition: Partition,
+        reason: String,
+    },
+}
+
+/// Type of the cpuset partition of a cgroup
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Partition {
+    /// Takes its cpus from the partition of the parent, the default
+    Member,
+    /// Exclusive cpus which are still load balanced
+    Root,
+    /// Exclusive cpus without load balancing
+    Isolated,
+}
+
+impl FromStr for Partition {
+    type Err = V2CpuSetControllerError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "member" => Ok(Self::Member),
+            "root" => Ok(Self::Root),
+            "isolated" => Ok(Self::Isolated),
+            _ => Err(V2CpuSetControllerError::InvalidPartition(s.to_owned())),
+        }
+    }
+}
+
+impl Display for Partition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let partition = match self {
+            Self::Member => "member",
+            Self::Root => "root",
+            Self::Isolated => "isolated",
+        };
+        f.write_str(partition)
+    }
+}
 
 pub struct CpuSet {}
 
 impl Controller for CpuSet {
-    type Error = WrappedIoError;
+    type Error = V2CpuSetControllerError;
 
     fn apply(controller_opt: &ControllerOpt, cgroup_path: &Path) -> Result<(), Self::Error> {
         if let Some(cpuset) = &controller_opt.resources.cpu() {
             Self::apply(cgroup_path, cpuset)?;
         }
 
+        let partition = controller_opt
+            .resources
+            .unified()
+            .as_ref()
+            .and_then(|unified| unified.get(CGROUP_CPUSET_PARTITION));
+        if let Some(partition) = partition {
+            // after the cpus, a partition can only be made of the cpus the
+            // cgroup already has
+            Self::set_partition(cgroup_path, partition.parse()?)?;
+        }
+
         Ok(())
     }
 }
@@ -34,16 +112,74 @@ impl CpuSet {
 
         Ok(())
     }
+
+    fn set_partition(path: &Path, partition: Partition) -> Result<(), V2CpuSetControllerError> {
+        if partition != Partition::Member {
+            Self::check_partition_allowed(path, partition)?;
+        }
+
+        tracing::debug!(%partition, ?path, "setting cpuset partition");
+        let partition_file = path.join(CGROUP_CPUSET_PARTITION);
+        common::write_cgroup_file_str(&partition_file, &partition.to_string())?;
+
+        let state = common::read_cgroup_file(&partition_file)?;
+        Self::check_partition_state(partition, &state)
+    }
+
+    /// An invalid partition is accepted by the kernel, but read back as e.g.
+    /// "isolated invalid (Cpu list in cpuset.cpus not exclusive)"
+    fn check_partition_state(
+        partition: Partition,
+        state: &str,
+    ) -> Result<(), V2CpuSetControllerError> {
+        match state.trim().strip_prefix(&partition.to_string()) {
+            Some("") => Ok(()),
+            Some(reason) => Err(V2CpuSetControllerError::Rejected {
+                partition,
+                reason: reason.trim().to_owned(),
+            }),
+            None => Err(V2CpuSetControllerError::Rejected {
+                partition,
+                reason: format!("the cgroup is a {} partition", state.trim()),
+            }),
+        }
+    }
+
+    fn check_partition_allowed(
+        path: &Path,
+        partition: Partition,
+    ) -> Result<(), V2CpuSetControllerError> {
+        let cpus = common::read_cgroup_file(path.join(CGROUP_CPUSET_CPUS))?;
+        if cpus.trim().is_empty() {
+            return Err(V2CpuSetControllerError::NoCpus(partition));
+        }
+
+        // the root cgroup has no partition file and is always a partition root
+        let parent = match path.parent() {
+            Some(parent) if parent.join(CGROUP_CPUSET_PARTITION).exists() => parent,
+            _ => return Ok(()),
+        };
+        let parent_partition = common::read_cgroup_file(parent.join(CGROUP_CPUSET_PARTITION))?;
+        match parent_partition.trim().parse::<Partition>() {
+            Ok(Partition::Root | Partition::Isolated) => Ok(()),
+            _ => Err(V2CpuSetControllerError::ParentNotPartitionRoot {
+                partition,
+                path: parent.to_path_buf(),
+                parent: parent_partition.trim().to_owned(),
+            }),
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::collections::HashMap;
     use std::fs;
 
-    use oci_spec::runtime::LinuxCpuBuilder;
+    use oci_spec::runtime::{LinuxCpuBuilder, LinuxResourcesBuilder};
 
     use super::*;
-    use crate::test::setup;
+    use crate::test::{set_fixture, setup};
 
     #[test]
     fn test_set_cpus() {
@@ -80,4 +216,89 @@ mod tests {
             .unwrap_or_else(|_| panic!("read {CGROUP_CPUSET_MEMS} file content"));
         assert_eq!(content, "1-3");
     }
+
+    fn apply_partition(cgroup_path: &Path, partition: &str) -> Result<(), V2CpuSetControllerError> {
+        let resources = LinuxResourcesBuilder::default()
+            .unified(HashMap::from([(
+                CGROUP_CPUSET_PARTITION.to_owned(),
+                partition.to_owned(),
+            )]))
+            .build()
+            .unwrap();
+        let controller_opt = ControllerOpt {
+            resources: &resources,
+            freezer_state: None,
+            oom_score_adj: None,
+            disable_oom_killer: false,
+        };
+        <CpuSet as Controller>::apply(&controller_opt, cgroup_path)
+    }
+
+    #[test]
+    fn test_set_partition() {
+        let tmp = tempfile::tempdir().unwrap();
+        set_fixture(tmp.path(), CGROUP_CPUSET_PARTITION, "root\n").unwrap();
+        let cgroup = tmp.path().join("rt");
+        fs::create_dir(&cgroup).unwrap();
+        set_fixture(&cgroup, CGROUP_CPUSET_CPUS, "2-3\n").unwrap();
+        let partition_file = set_fixture(&cgroup, CGROUP_CPUSET_PARTITION, "").unwrap();
+
+        apply_partition(&cgroup, "isolated").expect("apply cpuset partition");
+        let content = fs::read_to_string(partition_file).expect("read partition");
+        assert_eq!(content, "isolated");
+    }
+
+    #[test]
+    fn test_partition_requires_partition_root_parent() {
+        let tmp = tempfile::tempdir().unwrap();
+        set_fixture(tmp.path(), CGROUP_CPUSET_PARTITION, "member\n").unwrap();
+        let cgroup = tmp.path().join("rt");
+        fs::create_dir(&cgroup).unwrap();
+        set_fixture(&cgroup, CGROUP_CPUSET_CPUS, "2-3\n").unwrap();
+        let partition_file = set_fixture(&cgroup, CGROUP_CPUSET_PARTITION, "").unwrap();
+
+        let result = apply_partition(&cgroup, "root");
+        assert!(
+            matches!(
+                result,
+                Err(V2CpuSetControllerError::ParentNotPartitionRoot { .. })
+            ),
+            "{result:?}"
+        );
+        assert_eq!(fs::read_to_string(&partition_file).unwrap(), "");
+
+        // a member partition needs nothing of the parent
+        apply_partition(&cgroup, "member").expect("apply member partition");
+    }
+
+    #[test]
+    fn test_invalid_partition() {
+        let tmp = tempfile::tempdir().unwrap();
+        set_fixture(tmp.path(), CGROUP_CPUSET_CPUS, "").unwrap();
+        set_fixture(tmp.path(), CGROUP_CPUSET_PARTITION, "").unwrap();
+
+        assert!(matches!(
+            apply_partition(tmp.path(), "exclusive"),
+            Err(V2CpuSetControllerError::InvalidPartition(_))
+        ));
+        assert!(matches!(
+            apply_partition(tmp.path(), "root"),
+            Err(V2CpuSetControllerError::NoCpus(Partition::Root))
+        ));
+
+        // the kernel reports why it did not create the partition
+        let result = CpuSet::check_partition_state(
+            Partition::Isolated,
+            "isolated invalid (Cpu list in cpuset.cpus not exclusive)\n",
+        );
+        assert!(
+            matches!(
+                &result,
+                Err(V2CpuSetControllerError::Rejected { reason, .. })
+                    if reason == "invalid (Cpu list in cpuset.cpus not exclusive)"
+            ),
+            "{result:?}"
+        );
+        assert!(CpuSet::check_partition_state(Partition::Isolated, "isolated\n").is_ok());
+    }
 }
@@ -17,10 +17,13 @@ pub mod prog {
     use std::os::unix::io::RawFd;
     use std::ptr;
 
-    use libbpf_sys::{bpf_insn, BPF_CGROUP_DEVICE, BPF_F_ALLOW_MULTI, BPF_PROG_TYPE_CGROUP_DEVICE};
+    use libbpf_sys::{
+        bpf_insn, BPF_CGROUP_DEVICE, BPF_F_ALLOW_MULTI, BPF_F_REPLACE, BPF_PROG_TYPE_CGROUP_DEVICE,
+    };
     #[cfg(not(test))]
     use libbpf_sys::{
-        bpf_prog_attach, bpf_prog_detach2, bpf_prog_get_fd_by_id, bpf_prog_load, bpf_prog_query,
+        bpf_prog_attach, bpf_prog_attach_opts, bpf_prog_detach2, bpf_prog_get_fd_by_id,
+        bpf_prog_load, bpf_prog_query,
     };
     #[cfg(not(test))]
     use libc::setrlimit;
@@ -30,7 +33,8 @@ pub mod prog {
     // TODO: consider use of #[mockall_double]
     #[cfg(test)]
     use crate::v2::devices::mocks::mock_libbpf_sys::{
-        bpf_prog_attach, bpf_prog_detach2, bpf_prog_get_fd_by_id, bpf_prog_load, bpf_prog_query,
+        bpf_prog_attach, bpf_prog_attach_opts, bpf_prog_detach2, bpf_prog_get_fd_by_id,
+        bpf_prog_load, bpf_prog_query,
     };
     // mocks
     // TODO: consider use of #[mockall_double]
@@ -136,6 +140,38 @@ pub mod prog {
         Ok(())
     }
 
+    /// Atomically replaces the attached program `old_prog_fd` with `prog_fd`,
+    /// so that the cgroup is never left without a program. Fails with EINVAL
+    /// on kernels without BPF_F_REPLACE (before 5.6).
+    pub fn replace(
+        prog_fd: RawFd,
+        cgroup_fd: RawFd,
+        old_prog_fd: RawFd,
+    ) -> Result<(), super::BpfError> {
+        let opts = libbpf_sys::bpf_prog_attach_opts {
+            sz: std::mem::size_of::<libbpf_sys::bpf_prog_attach_opts>() as libbpf_sys::size_t,
+            flags: BPF_F_ALLOW_MULTI | BPF_F_REPLACE,
+            __bindgen_anon_1: libbpf_sys::bpf_prog_attach_opts__bindgen_ty_1 {
+                replace_prog_fd: old_prog_fd,
+            },
+            ..Default::default()
+        };
+        #[allow(unused_unsafe)]
+        let ret = unsafe {
+            bpf_prog_attach_opts(
+                prog_fd,
+                cgroup_fd,
+                BPF_CGROUP_DEVICE,
+                &opts as *const libbpf_sys::bpf_prog_attach_opts,
+            )
+        };
+
+        if ret != 0 {
+            return Err(errno::errno().into());
+        }
+        Ok(())
+    }
+
     pub fn bump_memlock_rlimit() -> Result<(), super::BpfError> {
         let rlimit = rlimit {
             rlim_cur: 128 << 20,
@@ -154,10 +190,10 @@ pub mod prog {
 #[cfg(test)]
 mod tests {
     use errno::{set_errno, Errno};
-    use libc::{ENOSPC, ENOSYS};
+    use libc::{EINVAL, ENOSPC, ENOSYS};
     use serial_test::serial;
 
-    use super::prog;
+    use super::{prog, BpfError};
     use crate::v2::devices::mocks::{mock_libbpf_sys, mock_libc};
 
     #[test]
@@ -198,6 +234,51 @@ mod tests {
         assert!(r.is_ok());
     }
 
+    #[test]
+    #[serial(libbpf_sys)] // mock contexts are shared
+    fn test_bpf_replace() {
+        // arrange
+        let attach_opts = mock_libbpf_sys::bpf_prog_attach_opts_context();
+
+        // expect
+        attach_opts.expect().once().returning(
+            |_, _, _, opts: *const libbpf_sys::bpf_prog_attach_opts| {
+                let opts = unsafe { &*opts };
+                assert_eq!(
+                    opts.flags,
+                    libbpf_sys::BPF_F_ALLOW_MULTI | libbpf_sys::BPF_F_REPLACE
+                );
+                assert_eq!(unsafe { opts.__bindgen_anon_1.replace_prog_fd }, 7);
+                0
+            },
+        );
+
+        // act
+        let r = prog::replace(6, 0, 7);
+
+        // assert
+        assert!(r.is_ok());
+    }
+
+    #[test]
+    #[serial(libbpf_sys)] // mock contexts are shared
+    fn test_bpf_replace_error() {
+        // arrange
+        let attach_opts = mock_libbpf_sys::bpf_prog_attach_opts_context();
+
+        // expect
+        attach_opts.expect().once().returning(|_, _, _, _| {
+            set_errno(Errno(EINVAL));
+            -1
+        });
+
+        // act
+        let r = prog::replace(6, 0, 7);
+
+        // assert
+        assert!(matches!(r, Err(BpfError::Errno(Errno(EINVAL)))));
+    }
+
     #[test]
     #[serial(libbpf_sys)] // mock contexts are shared
     fn test_bpf_load_error() {
@@ -12,7 +12,8 @@ use oci_spec::runtime::LinuxDeviceCgroup;
 use super::bpf::BpfError;
 use super::program::ProgramError;
 use super::*;
-use crate::common::{default_allow_devices, default_devices, ControllerOpt};
+use crate::common::ControllerOpt;
+use crate::device_rules::{self, DeviceRuleError};
 use crate::v2::controller::Controller;
 
 const LICENSE: &str = "Apache";
@@ -27,6 +28,8 @@ pub enum DevicesControllerError {
     Nix(#[from] nix::Error),
     #[error("program error: {0}")]
     Program(#[from] ProgramError),
+    #[error("device rule error: {0}")]
+    DeviceRule(#[from] DeviceRuleError),
 }
 
 impl Controller for Devices {
@@ -54,21 +57,9 @@ impl Devices {
         // FIXME: should we start as "deny all"?
         let mut emulator = emulator::Emulator::with_default_allow(false);
 
-        // FIXME: apply user-defined and default rules in which order?
-        if let Some(devices) = linux_devices {
-            for d in devices {
-                tracing::debug!("apply user defined rule: {:?}", d);
-                emulator.add_rule(d);
-            }
-        }
-
-        for d in [
-            default_devices().iter().map(|d| d.into()).collect(),
-            default_allow_devices(),
-        ]
-        .concat()
-        {
-            tracing::debug!("apply default rule: {:?}", d);
+        // like runc, the default rules come after the user-defined ones
+        for d in device_rules::rules_with_defaults(linux_devices.as_deref())? {
+            tracing::debug!("apply rule: {:?}", d);
             emulator.add_rule(&d);
         }
 
@@ -79,19 +70,6 @@ impl Devices {
         bpf_prog::bump_memlock_rlimit()?;
         let prog_fd = bpf_prog::load(LICENSE, prog.bytecodes())?;
 
-        // FIXME: simple way to attach BPF program
-        //  1. get list of existing attached programs
-        //  2. attach this program (not use BPF_F_REPLACE, see below)
-        //  3. detach all programs of 1
-        //
-        // runc will use BPF_F_REPLACE to replace currently attached program if:
-        //   1. BPF_F_REPLACE is supported by kernel
-        //   2. there is exactly one attached program
-        // https://github.com/opencontainers/runc/blob/8e6871a3b14bb74e0ef358aca3b9f8f9cb80f041/libcontainer/cgroups/ebpf/ebpf_linux.go#L165
-        //
-        // IMHO, this is too complicated, and in most cases, we just attach program once without
-        // already attached programs.
-
         // get the fd of the cgroup root
         let fd = nix::dir::Dir::open(
             cgroup_root.as_os_str(),
@@ -101,9 +79,29 @@ impl Devices {
 
         // collect the programs attached to this cgroup
         let old_progs = bpf_prog::query(fd.as_raw_fd())?;
-        // attach our new program
+
+        // Like runc, a single attached program, i.e. the one of an earlier
+        // apply, is replaced atomically with BPF_F_REPLACE, so that updating
+        // the rules of a running container never leaves a window without a
+        // filter.
+        // https://github.com/opencontainers/runc/blob/8e6871a3b14bb74e0ef358aca3b9f8f9cb80f041/libcontainer/cgroups/ebpf/ebpf_linux.go#L165
+        if let [old_prog] = old_progs.as_slice() {
+            match bpf_prog::replace(prog_fd, fd.as_raw_fd(), old_prog.fd) {
+                Ok(()) => return Ok(()),
+                // the kernel does not support BPF_F_REPLACE
+                Err(BpfError::Errno(errno::Errno(libc::EINVAL))) => {
+                    tracing::debug!("BPF_F_REPLACE is not supported, attaching the program");
+                }
+                Err(err) => {
+                    tracing::error!(?err, "failed to replace the device program");
+                    return Err(err.into());
+                }
+            }
+        }
+
+        // Otherwise the new program is attached before the old ones are
+        // detached, the rules of both apply in between.
         bpf_prog::attach(prog_fd, fd.as_raw_fd())?;
-        // detach all previous programs
         for old_prog in old_progs {
             bpf_prog::detach2(old_prog.fd, fd.as_raw_fd())?;
         }
@@ -152,6 +150,83 @@ mod tests {
         Devices::apply_devices(tmp.path(), &Some(vec![a_type])).expect("Could not apply devices");
     }
 
+    #[test]
+    #[serial(bpf)] // mock contexts are shared
+    fn test_replace_program() {
+        // arrange
+        let (tmp, _) = setup("some.value");
+        let a_type = LinuxDeviceCgroupBuilder::default()
+            .typ(LinuxDeviceType::A)
+            .build()
+            .unwrap();
+        let file_descriptor: RawFd = 6;
+        let existing_program = bpf::ProgramInfo { id: 1, fd: 7 };
+
+        // expect
+        let bump_memlock_rlimit = mock_prog::bump_memlock_rlimit_context();
+        let load = mock_prog::load_context();
+        let query = mock_prog::query_context();
+        let replace = mock_prog::replace_context();
+        let attach = mock_prog::attach_context();
+        let detach2 = mock_prog::detach2_context();
+        bump_memlock_rlimit.expect().once().returning(|| Ok(()));
+        load.expect()
+            .once()
+            .returning(move |_, _| Ok(file_descriptor));
+        query
+            .expect()
+            .once()
+            .returning(move |_| Ok(vec![existing_program.clone()]));
+        replace
+            .expect()
+            .withf(|prog_fd, _, old_prog_fd| *prog_fd == 6 && *old_prog_fd == 7)
+            .once()
+            .returning(|_, _, _| Ok(()));
+        attach.expect().never();
+        detach2.expect().never();
+
+        // act
+        Devices::apply_devices(tmp.path(), &Some(vec![a_type])).expect("Could not apply devices");
+    }
+
+    #[test]
+    #[serial(bpf)] // mock contexts are shared
+    fn test_replace_not_supported() {
+        // arrange
+        let (tmp, _) = setup("some.value");
+        let a_type = LinuxDeviceCgroupBuilder::default()
+            .typ(LinuxDeviceType::A)
+            .build()
+            .unwrap();
+        let file_descriptor: RawFd = 6;
+        let existing_program = bpf::ProgramInfo { id: 1, fd: 7 };
+
+        // expect
+        let bump_memlock_rlimit = mock_prog::bump_memlock_rlimit_context();
+        let load = mock_prog::load_context();
+        let query = mock_prog::query_context();
+        let replace = mock_prog::replace_context();
+        let attach = mock_prog::attach_context();
+        let detach2 = mock_prog::detach2_context();
+        bump_memlock_rlimit.expect().once().returning(|| Ok(()));
+        load.expect()
+            .once()
+            .returning(move |_, _| Ok(file_descriptor));
+        query
+            .expect()
+            .once()
+            .returning(move |_| Ok(vec![existing_program.clone()]));
+        replace
+            .expect()
+            .once()
+            .returning(|_, _, _| Err(BpfError::Errno(errno::Errno(libc::EINVAL))));
+        attach.expect().once().returning(|_, _| Ok(()));
+        detach2.expect().once().returning(|_, _| Ok(()));
+
+        // act
+        Devices::apply_devices(tmp.path(), &Some(vec![a_type])).expect("Could not apply devices");
+    }
+
     #[test]
     #[serial(bpf)] // mock contexts are shared
     fn test_existing_programs() {
@@ -166,6 +241,7 @@ mod tests {
             id: u32::default(),
             fd: i32::default(),
         };
+        let existing_program_2 = bpf::ProgramInfo { id: 1, fd: 7 };
 
         // expect
         let bump_memlock_rlimit = mock_prog::bump_memlock_rlimit_context();
@@ -180,9 +256,9 @@ mod tests {
         query
             .expect()
             .once()
-            .returning(move |_| Ok(vec![existing_program_1.clone()]));
+            .returning(move |_| Ok(vec![existing_program_1.clone(), existing_program_2.clone()]));
         attach.expect().once().returning(|_, _| Ok(()));
-        detach2.expect().once().returning(|_, _| Ok(()));
+        detach2.expect().times(2).returning(|_, _| Ok(()));
 
         // act
         Devices::apply_devices(tmp.path(), &Some(vec![a_type])).expect("Could not apply devices");
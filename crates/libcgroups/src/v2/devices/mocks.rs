@@ -61,4 +61,13 @@ pub mod libbpf_sys {
     ) -> ::std::os::raw::c_int {
         unimplemented!();
     }
+
+    pub fn bpf_prog_attach_opts(
+        _prog_fd: ::std::os::raw::c_int,
+        _target: ::std::os::raw::c_int,
+        _type_: libbpf_sys::bpf_attach_type,
+        _opts: *const libbpf_sys::bpf_prog_attach_opts,
+    ) -> ::std::os::raw::c_int {
+        unimplemented!();
+    }
 }
@@ -3,13 +3,17 @@ use std::io::{BufRead, BufReader, Read, Seek, Write};
 use std::path::Path;
 use std::str::{self, Utf8Error};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use super::controller::Controller;
-use crate::common::{ControllerOpt, FreezerState, WrapIoResult, WrappedIoError};
+use crate::common::{
+    self, ControllerOpt, FreezerState, UnfrozenTasks, WrapIoResult, WrappedIoError,
+    DEFAULT_FREEZE_TIMEOUT,
+};
 
 const CGROUP_FREEZE: &str = "cgroup.freeze";
 const CGROUP_EVENTS: &str = "cgroup.events";
+const CGROUP_THREADS: &str = "cgroup.threads";
 
 #[derive(thiserror::Error, Debug)]
 pub enum V2FreezerError {
@@ -24,8 +28,11 @@ pub enum V2FreezerError {
     },
     #[error("unexpected \"cgroup.freeze\" state: {state}")]
     UnknownState { state: String },
-    #[error("timeout of {0} ms reached waiting for the cgroup to freeze")]
-    Timeout(u128),
+    #[error("cgroup did not freeze within {timeout:?}, tasks which did not freeze: {tasks}")]
+    Timeout {
+        timeout: Duration,
+        tasks: UnfrozenTasks,
+    },
     #[error("invalid utf8: {0}")]
     InvalidUtf8(#[from] Utf8Error),
 }
@@ -46,6 +53,17 @@ impl Controller for Freezer {
 
 impl Freezer {
     fn apply(freezer_state: FreezerState, path: &Path) -> Result<(), V2FreezerError> {
+        Self::set_state(freezer_state, path, DEFAULT_FREEZE_TIMEOUT)
+    }
+
+    /// Sets the state of the cgroup. Freezing waits up to the timeout until
+    /// cgroup.events reports the cgroup as frozen, otherwise it thaws the
+    /// cgroup again and fails with the tasks which did not freeze.
+    pub(crate) fn set_state(
+        freezer_state: FreezerState,
+        path: &Path,
+        timeout: Duration,
+    ) -> Result<(), V2FreezerError> {
         let state_str = match freezer_state {
             FreezerState::Undefined => return Ok(()),
             FreezerState::Frozen => "1",
@@ -69,7 +87,16 @@ impl Freezer {
         };
 
         // confirm that the cgroup did actually change states.
-        let actual_state = Self::read_freezer_state(path)?;
+        let actual_state = match Self::read_freezer_state(path, timeout) {
+            Err(err @ V2FreezerError::Timeout { .. }) => {
+                // a partially frozen cgroup must not be left behind
+                if let Err(err) = common::write_cgroup_file_str(path.join(CGROUP_FREEZE), "0") {
+                    tracing::warn!(?err, ?path, "failed to thaw cgroup after freezing failed");
+                }
+                return Err(err);
+            }
+            res => res?,
+        };
         if !actual_state.eq(&freezer_state) {
             return Err(V2FreezerError::ExpectedToBe {
                 expected: freezer_state,
@@ -80,7 +107,7 @@ impl Freezer {
         Ok(())
     }
 
-    fn read_freezer_state(path: &Path) -> Result<FreezerState, V2FreezerError> {
+    fn read_freezer_state(path: &Path, timeout: Duration) -> Result<FreezerState, V2FreezerError> {
         let target = path.join(CGROUP_FREEZE);
         let mut buf = [0; 1];
         OpenOptions::new()
@@ -94,7 +121,7 @@ impl Freezer {
         let state = str::from_utf8(&buf)?;
         match state {
             "0" => Ok(FreezerState::Thawed),
-            "1" => Self::wait_frozen(path),
+            "1" => Self::wait_frozen(path, timeout),
             _ => Err(V2FreezerError::UnknownState {
                 state: state.into(),
             }),
@@ -102,8 +129,8 @@ impl Freezer {
     }
 
     // wait_frozen polls cgroup.events until it sees "frozen 1" in it.
-    fn wait_frozen(path: &Path) -> Result<FreezerState, V2FreezerError> {
-        let path = path.join(CGROUP_EVENTS);
+    fn wait_frozen(cgroup_path: &Path, timeout: Duration) -> Result<FreezerState, V2FreezerError> {
+        let path = cgroup_path.join(CGROUP_EVENTS);
         let f = OpenOptions::new()
             .create(false)
             .read(true)
@@ -112,13 +139,16 @@ impl Freezer {
         let mut f = BufReader::new(f);
 
         let wait_time = Duration::from_millis(10);
-        let max_iter = 1000;
+        let deadline = Instant::now() + timeout;
         let mut iter = 0;
         let mut line = String::new();
 
         loop {
-            if iter == max_iter {
-                return Err(V2FreezerError::Timeout(wait_time.as_millis() * max_iter));
+            if Instant::now() >= deadline {
+                return Err(V2FreezerError::Timeout {
+                    timeout,
+                    tasks: common::unfrozen_tasks(&cgroup_path.join(CGROUP_THREADS)),
+                });
             }
             line.clear();
             let num_bytes = f.read_line(&mut line).wrap_read(&path)?;
@@ -210,4 +240,30 @@ mod tests {
             assert!(r.is_err());
         }
     }
+
+    #[test]
+    fn test_freeze_timeout() {
+        let tmp = tempfile::tempdir().unwrap();
+        set_fixture(tmp.path(), CGROUP_FREEZE, "").expect("Set fixure for freezer state");
+        set_fixture(tmp.path(), CGROUP_EVENTS, "populated 1\nfrozen 0")
+            .expect("Set fixure for freezer events");
+        let tid = nix::unistd::gettid();
+        set_fixture(tmp.path(), CGROUP_THREADS, &tid.to_string())
+            .expect("Set fixure for cgroup threads");
+
+        let err = Freezer::set_state(FreezerState::Frozen, tmp.path(), Duration::from_millis(50))
+            .unwrap_err();
+        match err {
+            V2FreezerError::Timeout { tasks, .. } => {
+                assert_eq!(tasks.0.len(), 1);
+                assert_eq!(tasks.0[0].tid, tid.as_raw());
+            }
+            err => panic!("unexpected error {err}"),
+        }
+
+        // the cgroup is thawed again
+        let state_content =
+            std::fs::read_to_string(tmp.path().join(CGROUP_FREEZE)).expect("Read to string");
+        assert_eq!("0", state_content);
+    }
 }
@@ -5,11 +5,9 @@ use std::path::{Path, PathBuf};
 use oci_spec::runtime::LinuxHugepageLimit;
 
 use super::controller::Controller;
-use crate::common::{
-    self, read_cgroup_file, ControllerOpt, EitherError, MustBePowerOfTwo, WrappedIoError,
-};
+use crate::common::{self, ControllerOpt, EitherError, MustBePowerOfTwo, WrappedIoError};
 use crate::stats::{
-    parse_single_value, supported_page_sizes, HugeTlbStats, StatsProvider, SupportedPageSizesError,
+    supported_page_sizes, CgroupDir, HugeTlbStats, StatsProvider, SupportedPageSizesError,
 };
 
 #[derive(thiserror::Error, Debug)]
@@ -56,14 +54,14 @@ impl StatsProvider for HugeTlb {
     type Error = V2HugeTlbStatsError;
     type Stats = HashMap<String, HugeTlbStats>;
 
-    fn stats(cgroup_path: &Path) -> Result<Self::Stats, Self::Error> {
+    fn stats(dir: &mut CgroupDir) -> Result<Self::Stats, Self::Error> {
         let page_sizes = supported_page_sizes()?;
         let mut hugetlb_stats = HashMap::with_capacity(page_sizes.len());
 
         for page_size in page_sizes {
             hugetlb_stats.insert(
                 page_size.clone(),
-                Self::stats_for_page_size(cgroup_path, &page_size)?,
+                Self::stats_for_page_size(dir, &page_size)?,
             );
         }
 
@@ -115,18 +113,21 @@ impl HugeTlb {
     }
 
     fn stats_for_page_size(
-        cgroup_path: &Path,
+        dir: &mut CgroupDir,
         page_size: &str,
     ) -> Result<HugeTlbStats, V2HugeTlbStatsError> {
+        let mut rsvd = true;
         let mut file_prefix = format!("hugetlb.{page_size}.rsvd");
-        let mut path = cgroup_path.join(format!("{file_prefix}.events"));
-        let events = read_cgroup_file(&path).or_else(|_| {
+        // kernels without rsvd accounting
+        if !dir.exists(&format!("{file_prefix}.events")) {
+            rsvd = false;
             file_prefix = format!("hugetlb.{page_size}");
-            path = cgroup_path.join(format!("{file_prefix}.events"));
-            read_cgroup_file(&path)
-        })?;
+        }
+        let events_file = format!("{file_prefix}.events");
+        let path = dir.path().join(&events_file);
 
-        let fail_count: u64 = events
+        let fail_count: u64 = dir
+            .read(&events_file)?
             .lines()
             .find(|l| l.starts_with("max"))
             .map(|l| l[3..].trim().parse())
@@ -138,8 +139,9 @@ impl HugeTlb {
             .unwrap_or_default();
 
         Ok(HugeTlbStats {
-            usage: parse_single_value(&cgroup_path.join(format!("{file_prefix}.current")))?,
+            usage: dir.single_value(&format!("{file_prefix}.current"))?,
             fail_count,
+            rsvd,
             ..Default::default()
         })
     }
@@ -243,12 +245,14 @@ mod tests {
         set_fixture(tmp.path(), "hugetlb.2MB.current", "1024\n").expect("set hugetlb current");
         set_fixture(tmp.path(), "hugetlb.2MB.events", "max 5\n").expect("set hugetlb events");
 
-        let actual = HugeTlb::stats_for_page_size(tmp.path(), "2MB").expect("get cgroup stats");
+        let actual = HugeTlb::stats_for_page_size(&mut CgroupDir::open(tmp.path()).unwrap(), "2MB")
+            .expect("get cgroup stats");
 
         let expected = HugeTlbStats {
             usage: 1024,
             max_usage: 0,
             fail_count: 5,
+            rsvd: false,
         };
         assert_eq!(actual, expected);
     }
@@ -263,13 +267,15 @@ mod tests {
         set_fixture(tmp.path(), "hugetlb.2MB.rsvd.events", "max 5\n")
             .expect("set hugetlb rsvd events");
 
-        let actual = HugeTlb::stats_for_page_size(tmp.path(), "2MB").expect("get cgroup stats");
+        let actual = HugeTlb::stats_for_page_size(&mut CgroupDir::open(tmp.path()).unwrap(), "2MB")
+            .expect("get cgroup stats");
 
         // Should prefer rsvd stats over non-rsvd stats if available
         let expected = HugeTlbStats {
             usage: 1024,
             max_usage: 0,
             fail_count: 5,
+            rsvd: true,
         };
         assert_eq!(actual, expected);
     }
@@ -1,12 +1,14 @@
+use std::fmt::{self, Display};
 use std::num::ParseIntError;
 use std::path::{Path, PathBuf};
+use std::str::FromStr;
 
 use oci_spec::runtime::LinuxBlockIo;
 
 use super::controller::Controller;
 use crate::common::{self, ControllerOpt, WrappedIoError};
 use crate::stats::{
-    self, psi_stats, BlkioDeviceStat, BlkioStats, ParseDeviceNumberError,
+    self, BlkioDeviceStat, BlkioStats, CgroupDir, ParseDeviceNumberError,
     ParseNestedKeyedDataError, StatsProvider,
 };
 
@@ -14,6 +16,7 @@ const CGROUP_BFQ_IO_WEIGHT: &str = "io.bfq.weight";
 const CGROUP_IO_WEIGHT: &str = "io.weight";
 const CGROUP_IO_STAT: &str = "io.stat";
 const CGROUP_IO_PSI: &str = "io.pressure";
+const CGROUP_IO_LATENCY: &str = "io.latency";
 
 #[derive(thiserror::Error, Debug)]
 pub enum V2IoControllerError {
@@ -47,14 +50,82 @@ pub enum V2IoStatsError {
     ParseDeviceNumber(#[from] ParseDeviceNumberError),
     #[error("while parsing table value: {0}")]
     ParseInt(#[from] ParseIntError),
+    #[error("invalid io.latency entry {0:?}")]
+    ParseLatency(String),
+}
+
+/// Latency target of a device, the content of a line of io.latency. While
+/// the completion latency of the device exceeds the target, siblings of the
+/// cgroup with a higher target are throttled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IoLatency {
+    pub major: u64,
+    pub minor: u64,
+    /// Target in microseconds, no target removes the one of the device
+    pub target: Option<u64>,
+}
+
+impl FromStr for IoLatency {
+    type Err = V2IoStatsError;
+
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        let invalid = || V2IoStatsError::ParseLatency(line.to_owned());
+        let (device, target) = line.trim().split_once(' ').ok_or_else(invalid)?;
+        let (major, minor) = stats::parse_device_number(device)?;
+        let target = match target.trim().strip_prefix("target=").ok_or_else(invalid)? {
+            "max" => None,
+            target => Some(target.parse()?),
+        };
+
+        Ok(Self {
+            major,
+            minor,
+            target,
+        })
+    }
+}
+
+impl Display for IoLatency {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.target {
+            Some(target) => write!(f, "{}:{} target={}", self.major, self.minor, target),
+            None => write!(f, "{}:{} target=max", self.major, self.minor),
+        }
+    }
+}
+
+/// Sets or removes the latency target of a device
+pub fn set_latency(cgroup_path: &Path, latency: &IoLatency) -> Result<(), V2IoControllerError> {
+    common::write_cgroup_file(cgroup_path.join(CGROUP_IO_LATENCY), latency)?;
+    Ok(())
+}
+
+/// Latency targets of the cgroup, the kernel lists devices with a target
+/// only. Without the io.latency file, e.g. on kernels built without
+/// CONFIG_BLK_CGROUP_IOLATENCY, there are none.
+pub fn latency(cgroup_path: &Path) -> Result<Vec<IoLatency>, V2IoStatsError> {
+    let latency_file = cgroup_path.join(CGROUP_IO_LATENCY);
+    if !latency_file.exists() {
+        return Ok(Vec::new());
+    }
+
+    parse_latency(&common::read_cgroup_file(latency_file)?)
+}
+
+fn parse_latency(content: &str) -> Result<Vec<IoLatency>, V2IoStatsError> {
+    content
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(str::parse)
+        .collect()
 }
 
 impl StatsProvider for Io {
     type Error = V2IoStatsError;
     type Stats = BlkioStats;
 
-    fn stats(cgroup_path: &Path) -> Result<Self::Stats, Self::Error> {
-        let keyed_data = stats::parse_nested_keyed_data(&cgroup_path.join(CGROUP_IO_STAT))?;
+    fn stats(dir: &mut CgroupDir) -> Result<Self::Stats, Self::Error> {
+        let keyed_data = dir.nested_keyed(CGROUP_IO_STAT)?;
         let mut service_bytes = Vec::with_capacity(keyed_data.len());
         let mut serviced = Vec::with_capacity(keyed_data.len());
         for entry in keyed_data {
@@ -92,10 +163,27 @@ impl StatsProvider for Io {
             }
         }
 
+        let latencies = match dir.exists(CGROUP_IO_LATENCY) {
+            true => parse_latency(dir.read(CGROUP_IO_LATENCY)?)?,
+            false => Vec::new(),
+        };
+        let latency_target = latencies
+            .into_iter()
+            .filter_map(|latency| {
+                Some(BlkioDeviceStat {
+                    major: latency.major,
+                    minor: latency.minor,
+                    op_type: None,
+                    value: latency.target?,
+                })
+            })
+            .collect();
+
         let stats = BlkioStats {
             service_bytes,
             serviced,
-            psi: psi_stats(&cgroup_path.join(CGROUP_IO_PSI))?,
+            latency_target,
+            psi: dir.psi(CGROUP_IO_PSI)?,
             ..Default::default()
         };
 
@@ -345,7 +433,8 @@ mod test {
         set_fixture(tmp.path(), "io.stat", &stat_content).unwrap();
         set_fixture(tmp.path(), CGROUP_IO_PSI, "").expect("create psi file");
 
-        let mut actual = Io::stats(tmp.path()).expect("get cgroup stats");
+        let mut actual =
+            Io::stats(&mut CgroupDir::open(tmp.path()).unwrap()).expect("get cgroup stats");
         let expected = BlkioStats {
             service_bytes: vec![
                 BlkioDeviceStat {
@@ -407,4 +496,62 @@ mod test {
 
         assert_eq!(actual, expected);
     }
+
+    #[test]
+    fn test_set_latency() {
+        let (tmp, latency_file) = setup(CGROUP_IO_LATENCY);
+
+        let mut latency = IoLatency {
+            major: 8,
+            minor: 0,
+            target: Some(10000),
+        };
+        set_latency(tmp.path(), &latency).expect("set io latency");
+        let content = fs::read_to_string(&latency_file).expect("read io.latency");
+        assert_eq!("8:0 target=10000", content);
+
+        latency.target = None;
+        let (tmp, latency_file) = setup(CGROUP_IO_LATENCY);
+        set_latency(tmp.path(), &latency).expect("remove io latency");
+        let content = fs::read_to_string(&latency_file).expect("read io.latency");
+        assert_eq!("8:0 target=max", content);
+    }
+
+    #[test]
+    fn test_latency_stats() {
+        let tmp = tempfile::tempdir().unwrap();
+        assert_eq!(latency(tmp.path()).expect("no io.latency"), vec![]);
+
+        set_fixture(
+            tmp.path(),
+            CGROUP_IO_LATENCY,
+            "8:0 target=10000\n259:0 target=2500\n",
+        )
+        .unwrap();
+        set_fixture(tmp.path(), CGROUP_IO_STAT, "").unwrap();
+        set_fixture(tmp.path(), CGROUP_IO_PSI, "").unwrap();
+
+        let stats = Io::stats(&mut CgroupDir::open(tmp.path()).unwrap()).expect("get cgroup stats");
+        assert_eq!(
+            stats.latency_target,
+            vec![
+                BlkioDeviceStat {
+                    major: 8,
+                    minor: 0,
+                    op_type: None,
+                    value: 10000,
+                },
+                BlkioDeviceStat {
+                    major: 259,
+                    minor: 0,
+                    op_type: None,
+                    value: 2500,
+                },
+            ]
+        );
+
+        for invalid in ["8:0", "8:0 10000", "8:0 target=ten", "8 target=10"] {
+            assert!(invalid.parse::<IoLatency>().is_err(), "{invalid}");
+        }
+    }
 }
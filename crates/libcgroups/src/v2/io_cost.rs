@@ -0,0 +1,445 @@
+//! Configuration of the io cost controller, which shares the capacity of a
+//! device between cgroups by their io.weight. The controller is configured
+//! per device in the root cgroup only, so the settings apply to every cgroup
+//! using the device.
+//!
+//! kernel doc: https://www.kernel.org/doc/html/latest/admin-guide/cgroup-v2.html#io-interface-files
+use std::collections::HashMap;
+use std::fmt::{self, Display};
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::common::{self, WrappedIoError};
+use crate::stats::{self, ParseDeviceNumberError};
+
+pub(crate) const CGROUP_IO_COST_QOS: &str = "io.cost.qos";
+pub(crate) const CGROUP_IO_COST_MODEL: &str = "io.cost.model";
+
+const QOS_KEYS: &[&str] = &[
+    "enable", "ctrl", "rpct", "rlat", "wpct", "wlat", "min", "max",
+];
+const MODEL_KEYS: &[&str] = &[
+    "ctrl",
+    "model",
+    "rbps",
+    "rseqiops",
+    "rrandiops",
+    "wbps",
+    "wseqiops",
+    "wrandiops",
+];
+
+#[derive(thiserror::Error, Debug)]
+pub enum IoCostError {
+    #[error("io error: {0}")]
+    WrappedIo(#[from] WrappedIoError),
+    #[error("invalid device in {line:?}: {err}")]
+    Device {
+        line: String,
+        err: ParseDeviceNumberError,
+    },
+    #[error("invalid entry {entry:?} in {line:?}, expected key=value")]
+    Entry { entry: String, line: String },
+    #[error("unknown key {key} in {line:?}")]
+    UnknownKey { key: String, line: String },
+    #[error("duplicate key {key} in {line:?}")]
+    DuplicateKey { key: String, line: String },
+    #[error("invalid value {value:?} for {key} in {line:?}")]
+    InvalidValue {
+        key: String,
+        value: String,
+        line: String,
+    },
+    #[error("min {min} is larger than max {max}")]
+    MinAboveMax { min: f64, max: f64 },
+}
+
+/// Who controls the parameters of a device
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IoCostCtrl {
+    /// The kernel chooses them
+    Auto,
+    /// They are set explicitly
+    User,
+}
+
+impl FromStr for IoCostCtrl {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(Self::Auto),
+            "user" => Ok(Self::User),
+            _ => Err(()),
+        }
+    }
+}
+
+impl Display for IoCostCtrl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Auto => write!(f, "auto"),
+            Self::User => write!(f, "user"),
+        }
+    }
+}
+
+/// Quality of service of a device, the content of a line of io.cost.qos.
+/// Unset parameters are left as they are.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct IoCostQos {
+    pub major: u64,
+    pub minor: u64,
+    pub enable: Option<bool>,
+    pub ctrl: Option<IoCostCtrl>,
+    /// Percentile of read latencies which must be below `rlat`
+    pub rpct: Option<f64>,
+    /// Read latency target in microseconds
+    pub rlat: Option<u64>,
+    /// Percentile of write latencies which must be below `wlat`
+    pub wpct: Option<f64>,
+    /// Write latency target in microseconds
+    pub wlat: Option<u64>,
+    /// Lower bound of the rate the device is issued io at, in percent of
+    /// the cost model
+    pub min: Option<f64>,
+    /// Upper bound of the rate, in percent of the cost model
+    pub max: Option<f64>,
+}
+
+impl FromStr for IoCostQos {
+    type Err = IoCostError;
+
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        let ((major, minor), values) = parse_line(line, QOS_KEYS)?;
+        let value = |key| values.get(key).copied();
+
+        let qos = Self {
+            major,
+            minor,
+            enable: value("enable")
+                .map(|v| match v {
+                    "0" => Ok(false),
+                    "1" => Ok(true),
+                    _ => Err(invalid_value("enable", v, line)),
+                })
+                .transpose()?,
+            ctrl: parse_value(line, "ctrl", value("ctrl"))?,
+            rpct: parse_percent(line, "rpct", value("rpct"), 0.0..=100.0)?,
+            rlat: parse_value(line, "rlat", value("rlat"))?,
+            wpct: parse_percent(line, "wpct", value("wpct"), 0.0..=100.0)?,
+            wlat: parse_value(line, "wlat", value("wlat"))?,
+            min: parse_percent(line, "min", value("min"), 1.0..=10000.0)?,
+            max: parse_percent(line, "max", value("max"), 1.0..=10000.0)?,
+        };
+        if let (Some(min), Some(max)) = (qos.min, qos.max) {
+            if min > max {
+                return Err(IoCostError::MinAboveMax { min, max });
+            }
+        }
+
+        Ok(qos)
+    }
+}
+
+impl Display for IoCostQos {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.major, self.minor)?;
+        if let Some(enable) = self.enable {
+            write!(f, " enable={}", u8::from(enable))?;
+        }
+        write_value(f, "ctrl", self.ctrl)?;
+        write_percent(f, "rpct", self.rpct)?;
+        write_value(f, "rlat", self.rlat)?;
+        write_percent(f, "wpct", self.wpct)?;
+        write_value(f, "wlat", self.wlat)?;
+        write_percent(f, "min", self.min)?;
+        write_percent(f, "max", self.max)
+    }
+}
+
+/// Cost model of a device, the content of a line of io.cost.model. Unset
+/// parameters are left as they are.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IoCostModel {
+    pub major: u64,
+    pub minor: u64,
+    pub ctrl: Option<IoCostCtrl>,
+    /// Sequential read throughput in bytes per second
+    pub rbps: Option<u64>,
+    pub rseqiops: Option<u64>,
+    pub rrandiops: Option<u64>,
+    /// Sequential write throughput in bytes per second
+    pub wbps: Option<u64>,
+    pub wseqiops: Option<u64>,
+    pub wrandiops: Option<u64>,
+}
+
+impl FromStr for IoCostModel {
+    type Err = IoCostError;
+
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        let ((major, minor), values) = parse_line(line, MODEL_KEYS)?;
+        let value = |key| values.get(key).copied();
+
+        // linear is the only model the kernel implements
+        if let Some(model) = value("model") {
+            if model != "linear" {
+                return Err(invalid_value("model", model, line));
+            }
+        }
+
+        Ok(Self {
+            major,
+            minor,
+            ctrl: parse_value(line, "ctrl", value("ctrl"))?,
+            rbps: parse_value(line, "rbps", value("rbps"))?,
+            rseqiops: parse_value(line, "rseqiops", value("rseqiops"))?,
+            rrandiops: parse_value(line, "rrandiops", value("rrandiops"))?,
+            wbps: parse_value(line, "wbps", value("wbps"))?,
+            wseqiops: parse_value(line, "wseqiops", value("wseqiops"))?,
+            wrandiops: parse_value(line, "wrandiops", value("wrandiops"))?,
+        })
+    }
+}
+
+impl Display for IoCostModel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.major, self.minor)?;
+        write_value(f, "ctrl", self.ctrl)?;
+        write_value(f, "rbps", self.rbps)?;
+        write_value(f, "rseqiops", self.rseqiops)?;
+        write_value(f, "rrandiops", self.rrandiops)?;
+        write_value(f, "wbps", self.wbps)?;
+        write_value(f, "wseqiops", self.wseqiops)?;
+        write_value(f, "wrandiops", self.wrandiops)
+    }
+}
+
+/// Sets the quality of service of a device, `root_path` is the root of the
+/// cgroup v2 hierarchy
+pub fn set_qos(root_path: &Path, qos: &IoCostQos) -> Result<(), IoCostError> {
+    common::write_cgroup_file(root_path.join(CGROUP_IO_COST_QOS), qos)?;
+    Ok(())
+}
+
+/// Sets the cost model of a device, `root_path` is the root of the cgroup
+/// v2 hierarchy
+pub fn set_model(root_path: &Path, model: &IoCostModel) -> Result<(), IoCostError> {
+    common::write_cgroup_file(root_path.join(CGROUP_IO_COST_MODEL), model)?;
+    Ok(())
+}
+
+/// Quality of service of all devices the controller knows
+pub fn qos(root_path: &Path) -> Result<Vec<IoCostQos>, IoCostError> {
+    read_lines(&root_path.join(CGROUP_IO_COST_QOS))
+}
+
+/// Cost models of all devices the controller knows
+pub fn model(root_path: &Path) -> Result<Vec<IoCostModel>, IoCostError> {
+    read_lines(&root_path.join(CGROUP_IO_COST_MODEL))
+}
+
+fn read_lines<T: FromStr<Err = IoCostError>>(path: &Path) -> Result<Vec<T>, IoCostError> {
+    parse_lines(&common::read_cgroup_file(path)?)
+}
+
+/// Parses the settings of one device per line
+pub(crate) fn parse_lines<T: FromStr<Err = IoCostError>>(
+    content: &str,
+) -> Result<Vec<T>, IoCostError> {
+    content
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(str::parse)
+        .collect()
+}
+
+/// Major and minor number
+type Device = (u64, u64);
+
+/// Splits a line into its device and its values, only the given keys are
+/// accepted
+fn parse_line<'a>(
+    line: &'a str,
+    keys: &[&str],
+) -> Result<(Device, HashMap<&'a str, &'a str>), IoCostError> {
+    let mut entries = line.split_whitespace();
+    let device = entries.next().unwrap_or_default();
+    let device = stats::parse_device_number(device).map_err(|err| IoCostError::Device {
+        line: line.to_owned(),
+        err,
+    })?;
+
+    let mut values = HashMap::new();
+    for entry in entries {
+        let (key, value) = entry.split_once('=').ok_or_else(|| IoCostError::Entry {
+            entry: entry.to_owned(),
+            line: line.to_owned(),
+        })?;
+        if !keys.contains(&key) {
+            return Err(IoCostError::UnknownKey {
+                key: key.to_owned(),
+                line: line.to_owned(),
+            });
+        }
+        if values.insert(key, value).is_some() {
+            return Err(IoCostError::DuplicateKey {
+                key: key.to_owned(),
+                line: line.to_owned(),
+            });
+        }
+    }
+
+    Ok((device, values))
+}
+
+fn invalid_value(key: &str, value: &str, line: &str) -> IoCostError {
+    IoCostError::InvalidValue {
+        key: key.to_owned(),
+        value: value.to_owned(),
+        line: line.to_owned(),
+    }
+}
+
+fn parse_value<T: FromStr>(
+    line: &str,
+    key: &str,
+    value: Option<&str>,
+) -> Result<Option<T>, IoCostError> {
+    value
+        .map(|v| v.parse().map_err(|_| invalid_value(key, v, line)))
+        .transpose()
+}
+
+fn parse_percent(
+    line: &str,
+    key: &str,
+    value: Option<&str>,
+    range: std::ops::RangeInclusive<f64>,
+) -> Result<Option<f64>, IoCostError> {
+    let percent: Option<f64> = parse_value(line, key, value)?;
+    match (percent, value) {
+        (Some(percent), Some(value)) if !range.contains(&percent) => {
+            Err(invalid_value(key, value, line))
+        }
+        _ => Ok(percent),
+    }
+}
+
+fn write_value<T: Display>(f: &mut fmt::Formatter<'_>, key: &str, value: Option<T>) -> fmt::Result {
+    match value {
+        Some(value) => write!(f, " {key}={value}"),
+        None => Ok(()),
+    }
+}
+
+fn write_percent(f: &mut fmt::Formatter<'_>, key: &str, value: Option<f64>) -> fmt::Result {
+    match value {
+        Some(value) => write!(f, " {key}={value:.2}"),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::test::set_fixture;
+
+    #[test]
+    fn test_parse_qos() {
+        let line = "8:16 enable=1 ctrl=user rpct=95.00 rlat=10000 wpct=95.00 wlat=20000 min=50.00 max=150.00";
+        let qos: IoCostQos = line.parse().expect("parse qos");
+        assert_eq!(
+            qos,
+            IoCostQos {
+                major: 8,
+                minor: 16,
+                enable: Some(true),
+                ctrl: Some(IoCostCtrl::User),
+                rpct: Some(95.0),
+                rlat: Some(10000),
+                wpct: Some(95.0),
+                wlat: Some(20000),
+                min: Some(50.0),
+                max: Some(150.0),
+            }
+        );
+        assert_eq!(qos.to_string(), line);
+
+        let qos: IoCostQos = "259:0 enable=0".parse().expect("parse qos");
+        assert_eq!(qos.enable, Some(false));
+        assert_eq!(qos.to_string(), "259:0 enable=0");
+    }
+
+    #[test]
+    fn test_parse_invalid_qos() {
+        for line in [
+            "",
+            "8 enable=1",
+            "8:16 enable",
+            "8:16 enable=2",
+            "8:16 enable=1 enable=0",
+            "8:16 rbps=1",
+            "8:16 ctrl=manual",
+            "8:16 rpct=101",
+            "8:16 min=0",
+            "8:16 rlat=-1",
+        ] {
+            assert!(line.parse::<IoCostQos>().is_err(), "{line}");
+        }
+        assert!(matches!(
+            "8:16 min=150 max=50".parse::<IoCostQos>(),
+            Err(IoCostError::MinAboveMax { .. })
+        ));
+    }
+
+    #[test]
+    fn test_parse_model() {
+        let line = "8:16 ctrl=user model=linear rbps=2706339840 rseqiops=89698 rrandiops=110036 wbps=1063126016 wseqiops=135560 wrandiops=130734";
+        let model: IoCostModel = line.parse().expect("parse model");
+        assert_eq!(model.ctrl, Some(IoCostCtrl::User));
+        assert_eq!(model.rbps, Some(2706339840));
+        assert_eq!(model.wrandiops, Some(130734));
+        assert_eq!(
+            model.to_string(),
+            line.replace(" model=linear", "").as_str()
+        );
+
+        assert!("8:16 model=quadratic".parse::<IoCostModel>().is_err());
+        assert!("8:16 rpct=95".parse::<IoCostModel>().is_err());
+    }
+
+    #[test]
+    fn test_set_and_read() {
+        let tmp = tempfile::tempdir().unwrap();
+        let qos_file = set_fixture(tmp.path(), CGROUP_IO_COST_QOS, "").unwrap();
+        let model_file = set_fixture(tmp.path(), CGROUP_IO_COST_MODEL, "").unwrap();
+
+        let qos = IoCostQos {
+            major: 8,
+            minor: 0,
+            enable: Some(true),
+            rpct: Some(90.5),
+            ..Default::default()
+        };
+        set_qos(tmp.path(), &qos).expect("set qos");
+        assert_eq!(
+            fs::read_to_string(qos_file).unwrap(),
+            "8:0 enable=1 rpct=90.50"
+        );
+        assert_eq!(self::qos(tmp.path()).expect("read qos"), vec![qos]);
+
+        let model = IoCostModel {
+            major: 8,
+            minor: 0,
+            ctrl: Some(IoCostCtrl::Auto),
+            ..Default::default()
+        };
+        set_model(tmp.path(), &model).expect("set model");
+        assert_eq!(fs::read_to_string(model_file).unwrap(), "8:0 ctrl=auto");
+        assert_eq!(self::model(tmp.path()).expect("read model"), vec![model]);
+    }
+}
@@ -2,6 +2,7 @@ use std::fs::{self};
 use std::os::unix::fs::PermissionsExt;
 use std::path::Component::RootDir;
 use std::path::{Path, PathBuf};
+use std::sync::{Mutex, PoisonError};
 use std::time::Duration;
 
 use nix::unistd::Pid;
@@ -11,23 +12,30 @@ use super::controller_type::{
     ControllerType, PseudoControllerType, CONTROLLER_TYPES, PSEUDO_CONTROLLER_TYPES,
 };
 use super::cpu::{Cpu, V2CpuControllerError, V2CpuStatsError};
-use super::cpuset::CpuSet;
+use super::cpuset::{CpuSet, V2CpuSetControllerError};
 #[cfg(feature = "cgroupsv2_devices")]
 use super::devices::Devices;
 use super::freezer::{Freezer, V2FreezerError};
 use super::hugetlb::{HugeTlb, V2HugeTlbControllerError, V2HugeTlbStatsError};
 use super::io::{Io, V2IoControllerError, V2IoStatsError};
 use super::memory::{Memory, V2MemoryControllerError, V2MemoryStatsError};
+use super::misc::{Misc, V2MiscControllerError, V2MiscStatsError};
 use super::pids::Pids;
+use super::rdma::Rdma;
 use super::unified::{Unified, V2UnifiedError};
-use super::util::{self, V2UtilError, CGROUP_SUBTREE_CONTROL};
+use super::util::{self, V2UtilError};
+use crate::capabilities::{CgroupCapabilities, ResourceType};
 use crate::common::{
     self, AnyCgroupManager, CgroupManager, ControllerOpt, FreezerState, JoinSafelyError,
-    PathBufExt, WrapIoResult, WrappedIoError, CGROUP_PROCS,
+    PathBufExt, SubtreeControlError, WrapIoResult, WrappedIoError, CGROUP_PROCS,
 };
-use crate::stats::{PidStatsError, Stats, StatsProvider};
+use crate::rdma::RdmaError;
+use crate::stats::{CgroupDir, PidStatsError, Stats, StatsProvider};
 
 pub const CGROUP_KILL: &str = "cgroup.kill";
+/// Leaf below the container cgroup which holds the processes of the
+/// container if the manager uses one
+pub const INIT_LEAF: &str = "init";
 
 #[derive(thiserror::Error, Debug)]
 pub enum V2ManagerError {
@@ -37,11 +45,13 @@ pub enum V2ManagerError {
     JoinSafely(#[from] JoinSafelyError),
     #[error(transparent)]
     Util(#[from] V2UtilError),
+    #[error(transparent)]
+    SubtreeControl(#[from] SubtreeControlError),
 
     #[error(transparent)]
     CpuController(#[from] V2CpuControllerError),
     #[error(transparent)]
-    CpuSetController(WrappedIoError),
+    CpuSetController(#[from] V2CpuSetControllerError),
     #[error(transparent)]
     HugeTlbController(#[from] V2HugeTlbControllerError),
     #[error(transparent)]
@@ -49,8 +59,12 @@ pub enum V2ManagerError {
     #[error(transparent)]
     MemoryController(#[from] V2MemoryControllerError),
     #[error(transparent)]
+    MiscController(#[from] V2MiscControllerError),
+    #[error(transparent)]
     PidsController(WrappedIoError),
     #[error(transparent)]
+    Rdma(#[from] RdmaError),
+    #[error(transparent)]
     UnifiedController(#[from] V2UnifiedError),
     #[error(transparent)]
     FreezerController(#[from] V2FreezerError),
@@ -68,6 +82,8 @@ pub enum V2ManagerError {
     MemoryStats(#[from] V2MemoryStatsError),
     #[error(transparent)]
     IoStats(#[from] V2IoStatsError),
+    #[error(transparent)]
+    MiscStats(#[from] V2MiscStatsError),
 }
 
 /// Represents a management interface for a cgroup located at `{root_path}/{cgroup_path}`
@@ -77,6 +93,9 @@ pub struct Manager {
     root_path: PathBuf,
     cgroup_path: PathBuf,
     full_path: PathBuf,
+    init_leaf: bool,
+    /// Cgroup kept open between the collections of statistics
+    stats_dir: Mutex<Option<CgroupDir>>,
 }
 
 impl Manager {
@@ -89,14 +108,25 @@ impl Manager {
             root_path,
             cgroup_path,
             full_path,
+            init_leaf: false,
+            stats_dir: Mutex::default(),
         })
     }
 
+    /// Places the processes in the `init` leaf below the cgroup instead of
+    /// the cgroup itself. A cgroup with processes can't enable controllers
+    /// for its children, the leaf keeps the cgroup free of processes so that
+    /// child cgroups, e.g. for exec sessions, can be limited as well.
+    pub fn with_init_leaf(mut self, init_leaf: bool) -> Self {
+        self.init_leaf = init_leaf;
+        self
+    }
+
     /// Creates a unified cgroup at `self.full_path` and attaches a process to it
     fn create_unified_cgroup(&self, pid: Pid) -> Result<(), V2ManagerError> {
         let controllers: Vec<String> = util::get_available_controllers(&self.root_path)?
             .iter()
-            .map(|c| format!("+{c}"))
+            .map(|c| c.to_string())
             .collect();
 
         Self::write_controllers(&self.root_path, &controllers)?;
@@ -124,30 +154,123 @@ impl Manager {
             }
         }
 
-        common::write_cgroup_file(self.full_path.join(CGROUP_PROCS), pid)?;
+        let task_path = self.task_path()?;
+        common::write_cgroup_file(task_path.join(CGROUP_PROCS), pid)?;
         Ok(())
     }
 
+    /// Cgroup which takes the processes. With an init leaf, the leaf is
+    /// created and the controllers of the cgroup are enabled for it, which
+    /// the kernel only allows as long as the cgroup itself has no processes.
+    fn task_path(&self) -> Result<PathBuf, V2ManagerError> {
+        if !self.init_leaf {
+            return Ok(self.full_path.clone());
+        }
+
+        let leaf = self.create_init_leaf()?;
+        let controllers: Vec<String> = util::get_available_controllers(&self.full_path)?
+            .iter()
+            .map(|c| c.to_string())
+            .collect();
+        Self::write_controllers(&self.full_path, &controllers)?;
+        Ok(leaf)
+    }
+
+    /// Moves the processes of the cgroup into the init leaf, which is created
+    /// if it does not exist yet, and returns the path of the leaf. Unlike
+    /// placing processes with an init leaf, this leaves the controllers of
+    /// the cgroup to the caller, e.g. to enable only the delegated ones.
+    pub fn move_to_init_leaf(&self) -> Result<PathBuf, V2ManagerError> {
+        let leaf = self.create_init_leaf()?;
+        // besides the init process this catches an intermediate process
+        // which did not exit yet
+        let procs = self.full_path.join(CGROUP_PROCS);
+        for pid in fs::read_to_string(&procs).wrap_read(&procs)?.lines() {
+            common::write_cgroup_file_str(leaf.join(CGROUP_PROCS), pid)?;
+        }
+        Ok(leaf)
+    }
+
+    fn create_init_leaf(&self) -> Result<PathBuf, V2ManagerError> {
+        let leaf = self.full_path.join(INIT_LEAF);
+        if !leaf.exists() {
+            fs::create_dir(&leaf).wrap_create_dir(&leaf)?;
+        }
+        Ok(leaf)
+    }
+
     /// Writes a list of controllers to the `{path}/cgroup.subtree_control` file
-    fn write_controllers(path: &Path, controllers: &[String]) -> Result<(), WrappedIoError> {
+    fn write_controllers(path: &Path, controllers: &[String]) -> Result<(), SubtreeControlError> {
         for controller in controllers {
-            common::write_cgroup_file_str(path.join(CGROUP_SUBTREE_CONTROL), controller)?;
+            common::enable_controller(path, controller)?;
         }
 
         Ok(())
     }
 
+    fn collect_stats(dir: &mut CgroupDir) -> Result<Stats, V2ManagerError> {
+        let mut stats = Stats::default();
+
+        for subsystem in CONTROLLER_TYPES {
+            match subsystem {
+                ControllerType::Cpu => stats.cpu = Cpu::stats(dir)?,
+                ControllerType::HugeTlb => stats.hugetlb = HugeTlb::stats(dir)?,
+                ControllerType::Pids => {
+                    stats.pids = Pids::stats(dir).map_err(V2ManagerError::PidsStats)?
+                }
+                ControllerType::Memory => stats.memory = Memory::stats(dir)?,
+                ControllerType::Io => stats.blkio = Io::stats(dir)?,
+                ControllerType::Misc => stats.misc = Misc::stats(dir)?,
+                ControllerType::Rdma => stats.rdma = Rdma::stats(dir)?,
+                _ => continue,
+            }
+        }
+
+        Ok(stats)
+    }
+
     pub fn any(self) -> AnyCgroupManager {
         AnyCgroupManager::V2(self)
     }
 }
 
+/// Maps a controller to the resource type it controls and, if there is one,
+/// an interface file which has to exist for the controller to be usable
+fn controller_resource(controller: ControllerType) -> (ResourceType, Option<&'static str>) {
+    match controller {
+        ControllerType::Cpu => (ResourceType::Cpu, Some("cpu.weight")),
+        ControllerType::CpuSet => (ResourceType::CpuSet, Some("cpuset.cpus")),
+        ControllerType::HugeTlb => (ResourceType::HugePageLimits, None),
+        ControllerType::Io => (ResourceType::BlockIo, None),
+        ControllerType::Memory => (ResourceType::Memory, Some("memory.max")),
+        ControllerType::Pids => (ResourceType::Pids, Some("pids.max")),
+        // limited through the unified map only
+        ControllerType::Misc => (ResourceType::Unified, None),
+        ControllerType::Rdma => (ResourceType::Rdma, Some("rdma.max")),
+    }
+}
+
+/// Removes the cgroups below a cgroup, deepest first. They exist if the
+/// cgroup was delegated to the container.
+fn remove_children(path: &Path) -> Result<(), WrappedIoError> {
+    for entry in fs::read_dir(path).wrap_read(path)? {
+        let child = entry.wrap_read(path)?.path();
+        if child.is_dir() {
+            remove_children(&child)?;
+            common::delete_with_retry(&child, 4, Duration::from_millis(100))?;
+        }
+    }
+
+    Ok(())
+}
+
 impl CgroupManager for Manager {
     type Error = V2ManagerError;
 
     fn add_task(&self, pid: Pid) -> Result<(), Self::Error> {
         if self.full_path.exists() {
-            common::write_cgroup_file(self.full_path.join(CGROUP_PROCS), pid)?;
+            let task_path = self.task_path()?;
+            common::write_cgroup_file(task_path.join(CGROUP_PROCS), pid)?;
             return Ok(());
         }
         self.create_unified_cgroup(pid)?;
@@ -155,6 +278,8 @@ impl CgroupManager for Manager {
     }
 
     fn apply(&self, controller_opt: &ControllerOpt) -> Result<(), Self::Error> {
+        controller_opt.warn_ignored_network();
+        Unified::validate(controller_opt)?;
         for controller in CONTROLLER_TYPES {
             match controller {
                 ControllerType::Cpu => Cpu::apply(controller_opt, &self.full_path)?,
@@ -163,16 +288,19 @@ impl CgroupManager for Manager {
                 ControllerType::Io => Io::apply(controller_opt, &self.full_path)?,
                 ControllerType::Memory => Memory::apply(controller_opt, &self.full_path)?,
                 ControllerType::Pids => Pids::apply(controller_opt, &self.full_path)?,
+                ControllerType::Misc => Misc::apply(controller_opt, &self.full_path)?,
+                ControllerType::Rdma => Rdma::apply(controller_opt, &self.full_path)?,
             }
         }
 
         #[cfg(feature = "cgroupsv2_devices")]
-        Devices::apply(controller_opt, &self.cgroup_path)?;
+        Devices::apply(controller_opt, &self.full_path)?;
 
         for pseudoctlr in PSEUDO_CONTROLLER_TYPES {
             if let PseudoControllerType::Unified = pseudoctlr {
                 Unified::apply(
                     controller_opt,
+                    &self.root_path,
                     &self.full_path,
                     util::get_available_controllers(&self.root_path)?,
                 )?;
@@ -185,58 +313,224 @@ impl CgroupManager for Manager {
     fn remove(&self) -> Result<(), Self::Error> {
         if self.full_path.exists() {
             tracing::debug!("remove cgroup {:?}", self.full_path);
-            let kill_file = self.full_path.join(CGROUP_KILL);
-            if kill_file.exists() {
-                fs::write(&kill_file, "1").wrap_write(&kill_file, "1")?;
-            } else {
-                let procs_path = self.full_path.join(CGROUP_PROCS);
-                let procs = fs::read_to_string(&procs_path).wrap_read(&procs_path)?;
-
-                for line in procs.lines() {
-                    let pid: i32 = line
-                        .parse()
-                        .map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err))
-                        .wrap_other(&procs_path)?;
-                    let _ = nix::sys::signal::kill(Pid::from_raw(pid), nix::sys::signal::SIGKILL);
+            if !self.kill()? {
+                // the processes may be in the init leaf or other children
+                for pid in common::get_all_pids(&self.full_path)? {
+                    let _ = nix::sys::signal::kill(pid, nix::sys::signal::SIGKILL);
                 }
             }
 
+            remove_children(&self.full_path)?;
             common::delete_with_retry(&self.full_path, 4, Duration::from_millis(100))?;
         }
 
         Ok(())
     }
 
-    fn freeze(&self, state: FreezerState) -> Result<(), Self::Error> {
-        let controller_opt = ControllerOpt {
-            resources: &Default::default(),
-            freezer_state: Some(state),
-            oom_score_adj: None,
-            disable_oom_killer: false,
-        };
-        Ok(Freezer::apply(&controller_opt, &self.full_path)?)
+    fn freeze_with_timeout(
+        &self,
+        state: FreezerState,
+        timeout: Duration,
+    ) -> Result<(), Self::Error> {
+        Ok(Freezer::set_state(state, &self.full_path, timeout)?)
     }
 
     fn stats(&self) -> Result<Stats, Self::Error> {
-        let mut stats = Stats::default();
+        let mut stats_dir = self
+            .stats_dir
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        let dir = match &mut *stats_dir {
+            Some(dir) => dir,
+            None => stats_dir.insert(CgroupDir::open(&self.full_path)?),
+        };
+        let stats = Self::collect_stats(dir);
+        if stats.is_err() {
+            // the cgroup may have been recreated, open it again on the next call
+            *stats_dir = None;
+        }
+        stats
+    }
 
-        for subsystem in CONTROLLER_TYPES {
-            match subsystem {
-                ControllerType::Cpu => stats.cpu = Cpu::stats(&self.full_path)?,
-                ControllerType::HugeTlb => stats.hugetlb = HugeTlb::stats(&self.full_path)?,
-                ControllerType::Pids => {
-                    stats.pids = Pids::stats(&self.full_path).map_err(V2ManagerError::PidsStats)?
+    fn get_all_pids(&self) -> Result<Vec<Pid>, Self::Error> {
+        Ok(common::get_all_pids(&self.full_path)?)
+    }
+
+    fn capabilities(&self) -> Result<CgroupCapabilities, Self::Error> {
+        // cgroup.controllers of the cgroup itself lists the controllers which
+        // have been enabled for it by its parent
+        let mut resources: Vec<ResourceType> = util::get_available_controllers(&self.full_path)?
+            .into_iter()
+            .filter_map(|controller| {
+                let (resource, interface_file) = controller_resource(controller);
+                match interface_file {
+                    Some(file) if !self.full_path.join(file).exists() => None,
+                    _ => Some(resource),
                 }
-                ControllerType::Memory => stats.memory = Memory::stats(&self.full_path)?,
-                ControllerType::Io => stats.blkio = Io::stats(&self.full_path)?,
-                _ => continue,
-            }
+            })
+            .collect();
+        resources.push(ResourceType::Unified);
+        #[cfg(feature = "cgroupsv2_devices")]
+        resources.push(ResourceType::Devices);
+
+        Ok(CgroupCapabilities::new(resources))
+    }
+
+    fn reclaim(&self, bytes: u64) -> Result<(), Self::Error> {
+        Ok(Memory::reclaim(&self.full_path, bytes)?)
+    }
+
+    fn kill(&self) -> Result<bool, Self::Error> {
+        let kill_file = self.full_path.join(CGROUP_KILL);
+        if !kill_file.exists() {
+            return Ok(false);
         }
 
-        Ok(stats)
+        tracing::debug!("kill all processes of cgroup {:?}", self.full_path);
+        fs::write(&kill_file, "1").wrap_write(&kill_file, "1")?;
+        Ok(true)
     }
+}
 
-    fn get_all_pids(&self) -> Result<Vec<Pid>, Self::Error> {
-        Ok(common::get_all_pids(&self.full_path)?)
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::set_fixture;
+
+    #[test]
+    fn test_capabilities_require_enabled_controller_and_file() {
+        let tmp = tempfile::tempdir().unwrap();
+        let cgroup = tmp.path().join("youki");
+        fs::create_dir(&cgroup).unwrap();
+        set_fixture(&cgroup, "cgroup.controllers", "cpu memory pids\n").unwrap();
+        set_fixture(&cgroup, "cpu.weight", "100").unwrap();
+        set_fixture(&cgroup, "pids.max", "max").unwrap();
+        // memory.max is missing, and io is not enabled although io.max exists
+        set_fixture(&cgroup, "io.max", "").unwrap();
+
+        let manager = Manager::new(tmp.path().to_path_buf(), PathBuf::from("youki")).unwrap();
+        let capabilities = manager.capabilities().unwrap();
+
+        assert!(capabilities.supports(ResourceType::Cpu));
+        assert!(capabilities.supports(ResourceType::Pids));
+        assert!(capabilities.supports(ResourceType::Unified));
+        assert!(!capabilities.supports(ResourceType::Memory));
+        assert!(!capabilities.supports(ResourceType::BlockIo));
+        assert!(!capabilities.supports(ResourceType::Network));
+    }
+
+    #[test]
+    #[cfg(feature = "cgroupsv2_devices")]
+    #[serial_test::serial(bpf)] // mock contexts are shared
+    fn test_apply_devices_to_full_path() {
+        use oci_spec::runtime::LinuxResourcesBuilder;
+
+        use crate::v2::devices::bpf::mock_prog;
+
+        let tmp = tempfile::tempdir().unwrap();
+        set_fixture(tmp.path(), "cgroup.controllers", "").unwrap();
+        // the cgroup path does not exist relative to the working directory
+        fs::create_dir(tmp.path().join("youki-devices")).unwrap();
+        let manager =
+            Manager::new(tmp.path().to_path_buf(), PathBuf::from("youki-devices")).unwrap();
+        let resources = LinuxResourcesBuilder::default()
+            .devices(vec![])
+            .build()
+            .unwrap();
+        let controller_opt = ControllerOpt {
+            resources: &resources,
+            disable_oom_killer: false,
+            oom_score_adj: None,
+            freezer_state: None,
+        };
+
+        let bump_memlock_rlimit = mock_prog::bump_memlock_rlimit_context();
+        let load = mock_prog::load_context();
+        let query = mock_prog::query_context();
+        let attach = mock_prog::attach_context();
+        bump_memlock_rlimit.expect().once().returning(|| Ok(()));
+        load.expect().once().returning(|_, _| Ok(6));
+        // only reached if the cgroup directory could be opened
+        query.expect().once().returning(|_| Ok(vec![]));
+        attach.expect().once().returning(|_, _| Ok(()));
+
+        manager.apply(&controller_opt).expect("apply devices");
+    }
+
+    #[test]
+    fn test_kill() {
+        let tmp = tempfile::tempdir().unwrap();
+        let cgroup = tmp.path().join("youki");
+        fs::create_dir(&cgroup).unwrap();
+        let manager = Manager::new(tmp.path().to_path_buf(), PathBuf::from("youki")).unwrap();
+        // kernels before 5.14 have no cgroup.kill
+        assert!(!manager.kill().unwrap());
+
+        set_fixture(&cgroup, CGROUP_KILL, "").unwrap();
+        assert!(manager.kill().unwrap());
+        assert_eq!(fs::read_to_string(cgroup.join(CGROUP_KILL)).unwrap(), "1");
+    }
+
+    #[test]
+    fn test_add_task_to_init_leaf() {
+        let tmp = tempfile::tempdir().unwrap();
+        let cgroup = tmp.path().join("youki");
+        let leaf = cgroup.join(INIT_LEAF);
+        fs::create_dir_all(&leaf).unwrap();
+        set_fixture(&cgroup, "cgroup.controllers", "pids\n").unwrap();
+        set_fixture(&cgroup, "cgroup.subtree_control", "").unwrap();
+        set_fixture(&cgroup, CGROUP_PROCS, "").unwrap();
+        set_fixture(&leaf, CGROUP_PROCS, "").unwrap();
+
+        let manager = Manager::new(tmp.path().to_path_buf(), PathBuf::from("youki"))
+            .unwrap()
+            .with_init_leaf(true);
+        manager.add_task(Pid::from_raw(1000)).unwrap();
+
+        assert_eq!(fs::read_to_string(cgroup.join(CGROUP_PROCS)).unwrap(), "");
+        assert_eq!(fs::read_to_string(leaf.join(CGROUP_PROCS)).unwrap(), "1000");
+        assert_eq!(
+            fs::read_to_string(cgroup.join("cgroup.subtree_control")).unwrap(),
+            "+pids"
+        );
+    }
+
+    #[test]
+    fn test_move_to_init_leaf() {
+        let tmp = tempfile::tempdir().unwrap();
+        let cgroup = tmp.path().join("youki");
+        fs::create_dir(&cgroup).unwrap();
+        set_fixture(&cgroup, "cgroup.subtree_control", "").unwrap();
+        set_fixture(&cgroup, CGROUP_PROCS, "1000\n").unwrap();
+
+        let manager = Manager::new(tmp.path().to_path_buf(), PathBuf::from("youki")).unwrap();
+        // the fixture of the leaf only exists once the leaf was created
+        assert!(manager.move_to_init_leaf().is_err());
+        let leaf = cgroup.join(INIT_LEAF);
+        assert!(leaf.is_dir());
+        set_fixture(&leaf, CGROUP_PROCS, "").unwrap();
+
+        assert_eq!(manager.move_to_init_leaf().unwrap(), leaf);
+        assert_eq!(fs::read_to_string(leaf.join(CGROUP_PROCS)).unwrap(), "1000");
+        assert_eq!(
+            fs::read_to_string(cgroup.join("cgroup.subtree_control")).unwrap(),
+            ""
+        );
+    }
+
+    #[test]
+    fn test_remove_children() {
+        let tmp = tempfile::tempdir().unwrap();
+        fs::create_dir_all(tmp.path().join("init")).unwrap();
+        fs::create_dir_all(tmp.path().join("payload/a/b")).unwrap();
+        set_fixture(tmp.path(), CGROUP_PROCS, "").unwrap();
+
+        remove_children(tmp.path()).unwrap();
+
+        let entries: Vec<_> = fs::read_dir(tmp.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        assert_eq!(entries, [CGROUP_PROCS]);
     }
 }
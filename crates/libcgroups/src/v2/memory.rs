@@ -1,14 +1,24 @@
 use std::path::Path;
 
-use oci_spec::runtime::LinuxMemory;
+use nix::errno::Errno;
+use oci_spec::runtime::{LinuxMemory, LinuxResources};
 
 use super::controller::Controller;
 use crate::common::{self, ControllerOpt, WrappedIoError};
-use crate::stats::{self, MemoryData, MemoryStats, ParseFlatKeyedDataError, StatsProvider};
+use crate::stats::{
+    self, CgroupDir, MemoryData, MemoryStats, ParseFlatKeyedDataError, StatsProvider,
+};
 
 const CGROUP_MEMORY_SWAP: &str = "memory.swap.max";
 const CGROUP_MEMORY_MAX: &str = "memory.max";
 const CGROUP_MEMORY_LOW: &str = "memory.low";
+pub const CGROUP_MEMORY_HIGH: &str = "memory.high";
+const CGROUP_MEMORY_RECLAIM: &str = "memory.reclaim";
+pub const CGROUP_MEMORY_SWAP_HIGH: &str = "memory.swap.high";
+pub const CGROUP_MEMORY_ZSWAP_MAX: &str = "memory.zswap.max";
+pub const CGROUP_MEMORY_OOM_GROUP: &str = "memory.oom.group";
+const CGROUP_MEMORY_ZSWAP_CURRENT: &str = "memory.zswap.current";
+const MEMORY_EVENTS: &str = "memory.events";
 const MEMORY_STAT: &str = "memory.stat";
 const MEMORY_PSI: &str = "memory.pressure";
 
@@ -26,6 +36,20 @@ pub enum V2MemoryControllerError {
     SwapWithoutLimit,
     #[error("invalid memory reservation value: {0}")]
     MemoryReservation(i64),
+    #[error("invalid memory.high value {0:?}")]
+    HighValue(String),
+    #[error("memory.high ({high}) should not be bigger than memory limit ({limit})")]
+    HighAboveLimit { high: i64, limit: i64 },
+    #[error("invalid {file} value {value:?}")]
+    UnifiedValue { file: &'static str, value: String },
+    #[error("memory.swap.high is not available, it requires Linux 5.8 or later")]
+    SwapHighUnavailable,
+    #[error("memory.oom.group is not available, it requires Linux 4.19 or later")]
+    OomGroupUnavailable,
+    #[error("memory.reclaim is not available, it requires Linux 5.19 or later")]
+    ReclaimUnavailable,
+    #[error("could not reclaim {0} bytes, the cgroup has less reclaimable memory")]
+    ReclaimIncomplete(u64),
 }
 
 pub struct Memory {}
@@ -38,6 +62,37 @@ impl Controller for Memory {
             Self::apply(cgroup_path, memory)?;
         }
 
+        // The runtime spec has no field for memory.high, it is passed in the
+        // unified map. It is applied here as well to validate it against the
+        // memory limit before the unified map is written.
+        if let Some(high) = Self::high(controller_opt.resources)? {
+            let limit = match controller_opt
+                .resources
+                .memory()
+                .as_ref()
+                .and_then(|memory| memory.limit())
+            {
+                Some(limit) => limit,
+                None => Self::current_limit(cgroup_path)?,
+            };
+            Self::set_high(cgroup_path, high, limit)?;
+        }
+
+        // neither has a field in the runtime spec
+        if let Some(swap_high) =
+            Self::unified_limit(controller_opt.resources, CGROUP_MEMORY_SWAP_HIGH)?
+        {
+            Self::set_swap_high(cgroup_path, swap_high)?;
+        }
+        if let Some(zswap_max) =
+            Self::unified_limit(controller_opt.resources, CGROUP_MEMORY_ZSWAP_MAX)?
+        {
+            Self::set_zswap_max(cgroup_path, zswap_max)?;
+        }
+        if let Some(oom_group) = Self::oom_group(controller_opt.resources)? {
+            Self::set_oom_group(cgroup_path, oom_group)?;
+        }
+
         Ok(())
     }
 }
@@ -53,13 +108,17 @@ impl StatsProvider for Memory {
     type Error = V2MemoryStatsError;
     type Stats = MemoryStats;
 
-    fn stats(cgroup_path: &Path) -> Result<Self::Stats, Self::Error> {
+    fn stats(dir: &mut CgroupDir) -> Result<Self::Stats, Self::Error> {
         let stats = MemoryStats {
-            memory: Self::get_memory_data(cgroup_path, "memory", "oom")?,
-            memswap: Self::get_memory_data(cgroup_path, "memory.swap", "fail")?,
+            memory: Self::get_memory_data(dir, "memory", "oom")?,
+            memswap: Self::get_memory_data(dir, "memory.swap", "fail")?,
             hierarchy: true,
-            stats: stats::parse_flat_keyed_data(&cgroup_path.join(MEMORY_STAT))?,
-            psi: stats::psi_stats(&cgroup_path.join(MEMORY_PSI))?,
+            stats: dir.flat_keyed(MEMORY_STAT)?,
+            psi: dir.psi(MEMORY_PSI)?,
+            high_limit: dir.single_value(CGROUP_MEMORY_HIGH).unwrap_or(0),
+            high_events: Self::get_high_events(dir)?,
+            // only kernels with zswap have it
+            zswap_usage: dir.single_value(CGROUP_MEMORY_ZSWAP_CURRENT).unwrap_or(0),
             ..Default::default()
         };
 
@@ -69,21 +128,17 @@ impl StatsProvider for Memory {
 
 impl Memory {
     fn get_memory_data(
-        cgroup_path: &Path,
+        dir: &mut CgroupDir,
         file_prefix: &str,
         fail_event: &str,
     ) -> Result<MemoryData, V2MemoryStatsError> {
-        let usage =
-            stats::parse_single_value(&cgroup_path.join(format!("{}.{}", file_prefix, "current")))?;
-        let limit =
-            stats::parse_single_value(&cgroup_path.join(format!("{}.{}", file_prefix, "max")))?;
-        let max_usage =
-            stats::parse_single_value(&cgroup_path.join(format!("{}.{}", file_prefix, "peak")))
-                .unwrap_or(0);
-
-        let events = stats::parse_flat_keyed_data(
-            &cgroup_path.join(format!("{}.{}", file_prefix, "events")),
-        )?;
+        let usage = dir.single_value(&format!("{}.{}", file_prefix, "current"))?;
+        let limit = dir.single_value(&format!("{}.{}", file_prefix, "max"))?;
+        let max_usage = dir
+            .single_value(&format!("{}.{}", file_prefix, "peak"))
+            .unwrap_or(0);
+
+        let events = dir.flat_keyed(&format!("{}.{}", file_prefix, "events"))?;
         let fail_count = if let Some((_, v)) = events.get_key_value(fail_event) {
             *v
         } else {
@@ -98,6 +153,170 @@ impl Memory {
         })
     }
 
+    /// Returns how often memory usage exceeded memory.high and the
+    /// processes of the cgroup were throttled
+    fn get_high_events(dir: &mut CgroupDir) -> Result<u64, V2MemoryStatsError> {
+        let events = dir.flat_keyed(MEMORY_EVENTS)?;
+        Ok(events.get("high").copied().unwrap_or_default())
+    }
+
+    /// Returns the memory.high value of the unified map of the resources,
+    /// -1 stands for max
+    pub fn high(resources: &LinuxResources) -> Result<Option<i64>, V2MemoryControllerError> {
+        let value = match resources
+            .unified()
+            .as_ref()
+            .and_then(|unified| unified.get(CGROUP_MEMORY_HIGH))
+        {
+            Some(value) => value.trim(),
+            None => return Ok(None),
+        };
+
+        if value == "max" {
+            return Ok(Some(-1));
+        }
+        match value.parse::<i64>() {
+            Ok(high) if high >= 0 => Ok(Some(high)),
+            _ => Err(V2MemoryControllerError::HighValue(value.to_owned())),
+        }
+    }
+
+    /// Sets memory.high, above which the processes of the cgroup are
+    /// throttled and put under heavy reclaim pressure instead of being oom
+    /// killed. A limit of -1 means no memory limit.
+    pub fn set_high(path: &Path, high: i64, limit: i64) -> Result<(), V2MemoryControllerError> {
+        if limit != -1 && (high == -1 || high > limit) {
+            return Err(V2MemoryControllerError::HighAboveLimit { high, limit });
+        }
+
+        if high == -1 {
+            common::write_cgroup_file_str(path.join(CGROUP_MEMORY_HIGH), "max")?;
+        } else {
+            common::write_cgroup_file(path.join(CGROUP_MEMORY_HIGH), high)?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns a limit of the unified map of the resources which takes a
+    /// number of bytes or max, -1 stands for max
+    fn unified_limit(
+        resources: &LinuxResources,
+        file: &'static str,
+    ) -> Result<Option<i64>, V2MemoryControllerError> {
+        let value = match resources
+            .unified()
+            .as_ref()
+            .and_then(|unified| unified.get(file))
+        {
+            Some(value) => value.trim(),
+            None => return Ok(None),
+        };
+
+        if value == "max" {
+            return Ok(Some(-1));
+        }
+        match value.parse::<i64>() {
+            Ok(limit) if limit >= 0 => Ok(Some(limit)),
+            _ => Err(V2MemoryControllerError::UnifiedValue {
+                file,
+                value: value.to_owned(),
+            }),
+        }
+    }
+
+    /// Sets memory.swap.high, above which the processes of the cgroup are
+    /// throttled while they swap out. -1 means no limit.
+    pub fn set_swap_high(path: &Path, swap_high: i64) -> Result<(), V2MemoryControllerError> {
+        let file = path.join(CGROUP_MEMORY_SWAP_HIGH);
+        if !file.exists() {
+            return Err(V2MemoryControllerError::SwapHighUnavailable);
+        }
+
+        Self::set_limit(&file, swap_high)?;
+        Ok(())
+    }
+
+    /// Sets memory.zswap.max, the limit of the compressed swap cache of the
+    /// cgroup. -1 means no limit. It is skipped on kernels without zswap.
+    pub fn set_zswap_max(path: &Path, zswap_max: i64) -> Result<(), V2MemoryControllerError> {
+        let file = path.join(CGROUP_MEMORY_ZSWAP_MAX);
+        if !file.exists() {
+            tracing::warn!("memory.zswap.max is not available, the kernel has no zswap");
+            return Ok(());
+        }
+
+        Self::set_limit(&file, zswap_max)?;
+        Ok(())
+    }
+
+    fn oom_group(resources: &LinuxResources) -> Result<Option<bool>, V2MemoryControllerError> {
+        let value = match resources
+            .unified()
+            .as_ref()
+            .and_then(|unified| unified.get(CGROUP_MEMORY_OOM_GROUP))
+        {
+            Some(value) => value.trim(),
+            None => return Ok(None),
+        };
+
+        match value {
+            "0" => Ok(Some(false)),
+            "1" => Ok(Some(true)),
+            _ => Err(V2MemoryControllerError::UnifiedValue {
+                file: CGROUP_MEMORY_OOM_GROUP,
+                value: value.to_owned(),
+            }),
+        }
+    }
+
+    /// Sets memory.oom.group. If it is set, the OOM killer kills all
+    /// processes of the cgroup together instead of a single one of them.
+    pub fn set_oom_group(path: &Path, oom_group: bool) -> Result<(), V2MemoryControllerError> {
+        let file = path.join(CGROUP_MEMORY_OOM_GROUP);
+        if !file.exists() {
+            return Err(V2MemoryControllerError::OomGroupUnavailable);
+        }
+
+        common::write_cgroup_file(file, u8::from(oom_group))?;
+        Ok(())
+    }
+
+    // unlike set, 0 is written as well
+    fn set_limit(file: &Path, limit: i64) -> Result<(), WrappedIoError> {
+        if limit == -1 {
+            common::write_cgroup_file_str(file, "max")?;
+        } else {
+            common::write_cgroup_file(file, limit)?;
+        }
+        Ok(())
+    }
+
+    /// Asks the kernel to reclaim the given amount of memory from the cgroup,
+    /// e.g. by writing its pages out to swap. Unlike lowering memory.high,
+    /// this does not change the limits of the cgroup.
+    pub fn reclaim(path: &Path, bytes: u64) -> Result<(), V2MemoryControllerError> {
+        let reclaim = path.join(CGROUP_MEMORY_RECLAIM);
+        if !reclaim.exists() {
+            return Err(V2MemoryControllerError::ReclaimUnavailable);
+        }
+
+        match common::write_cgroup_file(&reclaim, bytes) {
+            // the kernel gave up before reclaiming the requested amount
+            Err(err) if err.inner().raw_os_error() == Some(Errno::EAGAIN as i32) => {
+                Err(V2MemoryControllerError::ReclaimIncomplete(bytes))
+            }
+            result => Ok(result?),
+        }
+    }
+
+    fn current_limit(path: &Path) -> Result<i64, WrappedIoError> {
+        match stats::parse_single_value(&path.join(CGROUP_MEMORY_MAX))? {
+            u64::MAX => Ok(-1),
+            limit => Ok(limit as i64),
+        }
+    }
+
     fn set<P: AsRef<Path>>(path: P, val: i64) -> Result<(), WrappedIoError> {
         if val == 0 {
             Ok(())
@@ -166,9 +385,10 @@ impl Memory {
 
 #[cfg(test)]
 mod tests {
+    use std::collections::HashMap;
     use std::fs::read_to_string;
 
-    use oci_spec::runtime::LinuxMemoryBuilder;
+    use oci_spec::runtime::{LinuxMemoryBuilder, LinuxResourcesBuilder};
 
     use super::*;
     use crate::test::set_fixture;
@@ -370,7 +590,8 @@ mod tests {
         set_fixture(tmp.path(), "memory.events", &events).unwrap();
 
         let actual =
-            Memory::get_memory_data(tmp.path(), "memory", "oom").expect("get cgroup stats");
+            Memory::get_memory_data(&mut CgroupDir::open(tmp.path()).unwrap(), "memory", "oom")
+                .expect("get cgroup stats");
         let expected = MemoryData {
             usage: 12500,
             limit: 25000,
@@ -391,7 +612,8 @@ mod tests {
         set_fixture(tmp.path(), "memory.events", &events).unwrap();
 
         let actual =
-            Memory::get_memory_data(tmp.path(), "memory", "oom").expect("get cgroup stats");
+            Memory::get_memory_data(&mut CgroupDir::open(tmp.path()).unwrap(), "memory", "oom")
+                .expect("get cgroup stats");
         let expected = MemoryData {
             usage: 12500,
             max_usage: 20000,
@@ -401,4 +623,189 @@ mod tests {
 
         assert_eq!(actual, expected);
     }
+
+    fn apply_high(
+        path: &Path,
+        limit: Option<i64>,
+        high: &str,
+    ) -> Result<(), V2MemoryControllerError> {
+        let mut builder = LinuxResourcesBuilder::default().unified(HashMap::from([(
+            CGROUP_MEMORY_HIGH.to_owned(),
+            high.to_owned(),
+        )]));
+        if let Some(limit) = limit {
+            builder = builder.memory(LinuxMemoryBuilder::default().limit(limit).build().unwrap());
+        }
+        let resources = builder.build().unwrap();
+        let controller_opt = ControllerOpt {
+            resources: &resources,
+            disable_oom_killer: false,
+            oom_score_adj: None,
+            freezer_state: None,
+        };
+
+        <Memory as Controller>::apply(&controller_opt, path)
+    }
+
+    #[test]
+    fn test_set_memory_high() {
+        let tmp = tempfile::tempdir().unwrap();
+        set_fixture(tmp.path(), CGROUP_MEMORY_MAX, "max").unwrap();
+        set_fixture(tmp.path(), CGROUP_MEMORY_HIGH, "max").unwrap();
+        set_fixture(tmp.path(), CGROUP_MEMORY_SWAP, "0").unwrap();
+
+        apply_high(tmp.path(), Some(4096), "2048").expect("apply memory.high below limit");
+        assert_eq!(
+            read_to_string(tmp.path().join(CGROUP_MEMORY_MAX)).unwrap(),
+            "4096"
+        );
+        assert_eq!(
+            read_to_string(tmp.path().join(CGROUP_MEMORY_HIGH)).unwrap(),
+            "2048"
+        );
+
+        // without a limit in the resources the current limit applies
+        assert!(matches!(
+            apply_high(tmp.path(), None, "8192"),
+            Err(V2MemoryControllerError::HighAboveLimit {
+                high: 8192,
+                limit: 4096
+            })
+        ));
+        assert!(matches!(
+            apply_high(tmp.path(), None, "max"),
+            Err(V2MemoryControllerError::HighAboveLimit { high: -1, .. })
+        ));
+        apply_high(tmp.path(), None, "1024").expect("apply memory.high below current limit");
+        assert_eq!(
+            read_to_string(tmp.path().join(CGROUP_MEMORY_HIGH)).unwrap(),
+            "1024"
+        );
+
+        // cgroup files are not truncated on writes
+        set_fixture(tmp.path(), CGROUP_MEMORY_HIGH, "").unwrap();
+        apply_high(tmp.path(), Some(-1), "max").expect("apply memory.high without limit");
+        assert_eq!(
+            read_to_string(tmp.path().join(CGROUP_MEMORY_HIGH)).unwrap(),
+            "max"
+        );
+
+        for invalid in ["-1", "1G", ""] {
+            assert!(matches!(
+                apply_high(tmp.path(), Some(-1), invalid),
+                Err(V2MemoryControllerError::HighValue(_))
+            ));
+        }
+    }
+
+    fn apply_unified(path: &Path, file: &str, value: &str) -> Result<(), V2MemoryControllerError> {
+        let resources = LinuxResourcesBuilder::default()
+            .unified(HashMap::from([(file.to_owned(), value.to_owned())]))
+            .build()
+            .unwrap();
+        let controller_opt = ControllerOpt {
+            resources: &resources,
+            disable_oom_killer: false,
+            oom_score_adj: None,
+            freezer_state: None,
+        };
+
+        <Memory as Controller>::apply(&controller_opt, path)
+    }
+
+    #[test]
+    fn test_set_swap_high() {
+        let tmp = tempfile::tempdir().unwrap();
+        assert!(matches!(
+            apply_unified(tmp.path(), CGROUP_MEMORY_SWAP_HIGH, "0"),
+            Err(V2MemoryControllerError::SwapHighUnavailable)
+        ));
+
+        set_fixture(tmp.path(), CGROUP_MEMORY_SWAP_HIGH, "").unwrap();
+        apply_unified(tmp.path(), CGROUP_MEMORY_SWAP_HIGH, "0").expect("apply memory.swap.high");
+        assert_eq!(
+            read_to_string(tmp.path().join(CGROUP_MEMORY_SWAP_HIGH)).unwrap(),
+            "0"
+        );
+
+        set_fixture(tmp.path(), CGROUP_MEMORY_SWAP_HIGH, "").unwrap();
+        apply_unified(tmp.path(), CGROUP_MEMORY_SWAP_HIGH, "max").expect("apply memory.swap.high");
+        assert_eq!(
+            read_to_string(tmp.path().join(CGROUP_MEMORY_SWAP_HIGH)).unwrap(),
+            "max"
+        );
+
+        for invalid in ["-1", "1G"] {
+            assert!(matches!(
+                apply_unified(tmp.path(), CGROUP_MEMORY_SWAP_HIGH, invalid),
+                Err(V2MemoryControllerError::UnifiedValue { .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn test_set_zswap_max() {
+        let tmp = tempfile::tempdir().unwrap();
+        // skipped without zswap
+        apply_unified(tmp.path(), CGROUP_MEMORY_ZSWAP_MAX, "4096").expect("skip memory.zswap.max");
+        assert!(!tmp.path().join(CGROUP_MEMORY_ZSWAP_MAX).exists());
+
+        set_fixture(tmp.path(), CGROUP_MEMORY_ZSWAP_MAX, "").unwrap();
+        apply_unified(tmp.path(), CGROUP_MEMORY_ZSWAP_MAX, "4096").expect("apply memory.zswap.max");
+        assert_eq!(
+            read_to_string(tmp.path().join(CGROUP_MEMORY_ZSWAP_MAX)).unwrap(),
+            "4096"
+        );
+    }
+
+    #[test]
+    fn test_set_oom_group() {
+        let tmp = tempfile::tempdir().unwrap();
+        assert!(matches!(
+            apply_unified(tmp.path(), CGROUP_MEMORY_OOM_GROUP, "1"),
+            Err(V2MemoryControllerError::OomGroupUnavailable)
+        ));
+
+        set_fixture(tmp.path(), CGROUP_MEMORY_OOM_GROUP, "0").unwrap();
+        apply_unified(tmp.path(), CGROUP_MEMORY_OOM_GROUP, "1").expect("apply memory.oom.group");
+        assert_eq!(
+            read_to_string(tmp.path().join(CGROUP_MEMORY_OOM_GROUP)).unwrap(),
+            "1"
+        );
+
+        for invalid in ["true", "2", ""] {
+            assert!(matches!(
+                apply_unified(tmp.path(), CGROUP_MEMORY_OOM_GROUP, invalid),
+                Err(V2MemoryControllerError::UnifiedValue { .. })
+            ));
+        }
+    }
+
+    #[test]
+    fn test_get_high_events() {
+        let tmp = tempfile::tempdir().unwrap();
+        let events = ["low 0", "high 42", "max 7", "oom 3"].join("\n");
+        set_fixture(tmp.path(), MEMORY_EVENTS, &events).unwrap();
+
+        assert_eq!(
+            Memory::get_high_events(&mut CgroupDir::open(tmp.path()).unwrap()).unwrap(),
+            42
+        );
+    }
+
+    #[test]
+    fn test_reclaim() {
+        let tmp = tempfile::tempdir().unwrap();
+        assert!(matches!(
+            Memory::reclaim(tmp.path(), 4096),
+            Err(V2MemoryControllerError::ReclaimUnavailable)
+        ));
+
+        set_fixture(tmp.path(), CGROUP_MEMORY_RECLAIM, "").unwrap();
+        Memory::reclaim(tmp.path(), 1 << 20).expect("reclaim memory");
+        assert_eq!(
+            read_to_string(tmp.path().join(CGROUP_MEMORY_RECLAIM)).unwrap(),
+            "1048576"
+        );
+    }
 }
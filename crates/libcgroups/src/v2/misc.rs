@@ -0,0 +1,254 @@
+//! The misc controller limits scalar resources which have no controller of
+//! their own, e.g. the encrypted page cache of SGX enclaves (sgx_epc) or the
+//! address space ids of AMD SEV guests (sev, sev_es).
+//!
+//! The spec has no resource for it, the limits are taken from `misc.max` of
+//! the unified map, with one `name value` line per resource.
+//!
+//! kernel doc: https://www.kernel.org/doc/html/latest/admin-guide/cgroup-v2.html#misc
+use std::collections::HashMap;
+use std::fmt::{self, Display};
+use std::path::Path;
+use std::str::FromStr;
+
+use super::controller::Controller;
+use crate::common::{self, ControllerOpt, WrappedIoError};
+use crate::stats::{CgroupDir, MiscStats, ParseFlatKeyedDataError, StatsProvider};
+
+pub(crate) const CGROUP_MISC_MAX: &str = "misc.max";
+const CGROUP_MISC_CURRENT: &str = "misc.current";
+const CGROUP_MISC_EVENTS: &str = "misc.events";
+
+#[derive(thiserror::Error, Debug)]
+pub enum V2MiscControllerError {
+    #[error("io error: {0}")]
+    WrappedIo(#[from] WrappedIoError),
+    #[error("invalid misc.max entry {0:?}, expected a resource name and a limit")]
+    InvalidLimit(String),
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum V2MiscStatsError {
+    #[error("io error: {0}")]
+    WrappedIo(#[from] WrappedIoError),
+    #[error("while parsing stat table: {0}")]
+    ParseFlatKeyedData(#[from] ParseFlatKeyedDataError),
+    #[error(transparent)]
+    InvalidLimit(#[from] V2MiscControllerError),
+}
+
+/// Limit of a resource, the content of a line of misc.max
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MiscLimit {
+    /// Name of the resource, e.g. sgx_epc
+    pub name: String,
+    /// Limit of the usage, no limit allows to use all of the resource the
+    /// parent may use
+    pub max: Option<u64>,
+}
+
+impl FromStr for MiscLimit {
+    type Err = V2MiscControllerError;
+
+    fn from_str(line: &str) -> Result<Self, Self::Err> {
+        let invalid = || V2MiscControllerError::InvalidLimit(line.to_owned());
+        let mut fields = line.split_whitespace();
+        let (name, max) = match (fields.next(), fields.next(), fields.next()) {
+            (Some(name), Some(max), None) => (name, max),
+            _ => return Err(invalid()),
+        };
+        let max = match max {
+            "max" => None,
+            max => Some(max.parse().map_err(|_| invalid())?),
+        };
+
+        Ok(Self {
+            name: name.to_owned(),
+            max,
+        })
+    }
+}
+
+impl Display for MiscLimit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.max {
+            Some(max) => write!(f, "{} {}", self.name, max),
+            None => write!(f, "{} max", self.name),
+        }
+    }
+}
+
+/// Sets the limit of a resource, the kernel accepts a single one per write
+pub fn set_limit(cgroup_path: &Path, limit: &MiscLimit) -> Result<(), V2MiscControllerError> {
+    common::write_cgroup_file(cgroup_path.join(CGROUP_MISC_MAX), limit)?;
+    Ok(())
+}
+
+/// Parses limits given one per line, as in misc.max
+pub(crate) fn parse_limits(content: &str) -> Result<Vec<MiscLimit>, V2MiscControllerError> {
+    content
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(str::parse)
+        .collect()
+}
+
+pub struct Misc {}
+
+impl Controller for Misc {
+    type Error = V2MiscControllerError;
+
+    fn apply(controller_opt: &ControllerOpt, cgroup_root: &Path) -> Result<(), Self::Error> {
+        let limits = controller_opt
+            .resources
+            .unified()
+            .as_ref()
+            .and_then(|unified| unified.get(CGROUP_MISC_MAX));
+        if let Some(limits) = limits {
+            tracing::debug!("Apply misc cgroup v2 config");
+            // validated before anything is written
+            for limit in parse_limits(limits)? {
+                set_limit(cgroup_root, &limit)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl StatsProvider for Misc {
+    type Error = V2MiscStatsError;
+    type Stats = HashMap<String, MiscStats>;
+
+    /// Resources the kernel knows, none if the controller is not enabled
+    fn stats(dir: &mut CgroupDir) -> Result<Self::Stats, Self::Error> {
+        if !dir.exists(CGROUP_MISC_CURRENT) {
+            return Ok(HashMap::new());
+        }
+
+        let mut misc: HashMap<String, MiscStats> = dir
+            .flat_keyed(CGROUP_MISC_CURRENT)?
+            .into_iter()
+            .map(|(name, usage)| {
+                let stats = MiscStats {
+                    usage,
+                    ..Default::default()
+                };
+                (name, stats)
+            })
+            .collect();
+
+        // the root cgroup has neither limits nor events
+        if dir.exists(CGROUP_MISC_MAX) {
+            for limit in parse_limits(dir.read(CGROUP_MISC_MAX)?)? {
+                if let Some(stats) = misc.get_mut(&limit.name) {
+                    stats.limit = limit.max;
+                }
+            }
+        }
+        if dir.exists(CGROUP_MISC_EVENTS) {
+            for (event, count) in dir.flat_keyed(CGROUP_MISC_EVENTS)? {
+                let stats = event
+                    .strip_suffix(".max")
+                    .and_then(|name| misc.get_mut(name));
+                if let Some(stats) = stats {
+                    stats.fail_count = count;
+                }
+            }
+        }
+
+        Ok(misc)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::fs;
+
+    use oci_spec::runtime::LinuxResourcesBuilder;
+
+    use super::*;
+    use crate::test::{set_fixture, setup};
+
+    #[test]
+    fn test_set_misc_limits() {
+        let (tmp, max_file) = setup(CGROUP_MISC_MAX);
+        let resources = LinuxResourcesBuilder::default()
+            .unified(HashMap::from([(
+                CGROUP_MISC_MAX.to_owned(),
+                "sgx_epc 1048576\n".to_owned(),
+            )]))
+            .build()
+            .unwrap();
+        let controller_opt = ControllerOpt {
+            resources: &resources,
+            freezer_state: None,
+            oom_score_adj: None,
+            disable_oom_killer: false,
+        };
+
+        Misc::apply(&controller_opt, tmp.path()).expect("apply misc");
+        let content = fs::read_to_string(max_file).expect("read misc.max");
+        assert_eq!(content, "sgx_epc 1048576");
+    }
+
+    #[test]
+    fn test_parse_misc_limits() {
+        assert_eq!(
+            parse_limits("sgx_epc 1048576\nsev max\n").expect("parse limits"),
+            vec![
+                MiscLimit {
+                    name: "sgx_epc".to_owned(),
+                    max: Some(1048576),
+                },
+                MiscLimit {
+                    name: "sev".to_owned(),
+                    max: None,
+                },
+            ]
+        );
+        assert_eq!(
+            MiscLimit {
+                name: "sev".to_owned(),
+                max: None,
+            }
+            .to_string(),
+            "sev max"
+        );
+
+        for invalid in ["sgx_epc", "sgx_epc 1m", "sgx_epc 1 2"] {
+            assert!(invalid.parse::<MiscLimit>().is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn test_misc_stats() {
+        let tmp = tempfile::tempdir().unwrap();
+        assert!(Misc::stats(&mut CgroupDir::open(tmp.path()).unwrap())
+            .expect("no misc controller")
+            .is_empty());
+
+        set_fixture(tmp.path(), CGROUP_MISC_CURRENT, "sgx_epc 4096\nsev 0\n").unwrap();
+        set_fixture(tmp.path(), CGROUP_MISC_MAX, "sgx_epc 8192\nsev max\n").unwrap();
+        set_fixture(tmp.path(), CGROUP_MISC_EVENTS, "sgx_epc.max 3\nsev.max 0\n").unwrap();
+
+        let stats = Misc::stats(&mut CgroupDir::open(tmp.path()).unwrap()).expect("get misc stats");
+        assert_eq!(
+            stats["sgx_epc"],
+            MiscStats {
+                usage: 4096,
+                limit: Some(8192),
+                fail_count: 3,
+            }
+        );
+        assert_eq!(
+            stats["sev"],
+            MiscStats {
+                usage: 0,
+                limit: None,
+                fail_count: 0,
+            }
+        );
+    }
+}
@@ -1,14 +1,19 @@
 mod controller;
 pub mod controller_type;
 mod cpu;
-mod cpuset;
+pub mod cpuset;
 #[cfg(feature = "cgroupsv2_devices")]
 pub mod devices;
 mod freezer;
 mod hugetlb;
-mod io;
+pub mod io;
+pub mod io_cost;
 pub mod manager;
 mod memory;
+pub mod misc;
 mod pids;
+pub mod placement;
+mod rdma;
+pub mod threaded;
 mod unified;
 pub mod util;
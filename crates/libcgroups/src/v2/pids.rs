@@ -4,7 +4,7 @@ use oci_spec::runtime::LinuxPids;
 
 use super::controller::Controller;
 use crate::common::{self, ControllerOpt, WrappedIoError};
-use crate::stats::{self, PidStats, PidStatsError, StatsProvider};
+use crate::stats::{self, CgroupDir, PidStats, PidStatsError, StatsProvider};
 
 pub struct Pids {}
 
@@ -27,8 +27,8 @@ impl StatsProvider for Pids {
     type Error = PidStatsError;
     type Stats = PidStats;
 
-    fn stats(cgroup_path: &Path) -> Result<Self::Stats, Self::Error> {
-        stats::pid_stats(cgroup_path)
+    fn stats(dir: &mut CgroupDir) -> Result<Self::Stats, Self::Error> {
+        stats::pid_stats(dir)
     }
 }
 
@@ -0,0 +1,232 @@
+//! Placement of processes in one of the sub-cgroups of a cgroup, for setups
+//! which create sub-cgroups to run processes in, e.g. one per job or debug
+//! session of a pod-style container.
+//!
+//! The sub-cgroups are the direct children of the cgroup, with their current
+//! load: the number of their tasks and the cpu pressure, the share of time
+//! some of their tasks waited for a cpu in the last 10 seconds. A policy
+//! picks one of them:
+//!
+//! - `least-loaded`: the sub-cgroup with the lowest cpu pressure, of those
+//!   the one with the fewest tasks,
+//! - `round-robin`: the sub-cgroup after the one picked last, in the order of
+//!   their names. The caller keeps track of the last one.
+use std::cmp::Ordering;
+use std::fmt::{self, Display};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use crate::common::{self, WrapIoResult, WrappedIoError};
+use crate::stats;
+
+const CGROUP_PIDS_CURRENT: &str = "pids.current";
+const CGROUP_CPU_PRESSURE: &str = "cpu.pressure";
+
+#[derive(thiserror::Error, Debug)]
+pub enum PlacementError {
+    #[error("io error: {0}")]
+    WrappedIo(#[from] WrappedIoError),
+    #[error("invalid placement policy {0:?}, expected least-loaded or round-robin")]
+    InvalidPolicy(String),
+    #[error("cgroup {0:?} has no sub-cgroup to place the process in")]
+    NoSubCgroup(PathBuf),
+}
+
+/// How the sub-cgroup of a process is picked
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlacementPolicy {
+    LeastLoaded,
+    RoundRobin,
+}
+
+impl FromStr for PlacementPolicy {
+    type Err = PlacementError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "least-loaded" => Ok(Self::LeastLoaded),
+            "round-robin" => Ok(Self::RoundRobin),
+            _ => Err(PlacementError::InvalidPolicy(s.to_owned())),
+        }
+    }
+}
+
+impl Display for PlacementPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::LeastLoaded => write!(f, "least-loaded"),
+            Self::RoundRobin => write!(f, "round-robin"),
+        }
+    }
+}
+
+/// Sub-cgroup with its current load
+#[derive(Debug, Clone, PartialEq)]
+pub struct SubCgroup {
+    pub name: String,
+    pub path: PathBuf,
+    /// Tasks of the sub-cgroup and its descendants
+    pub pids: u64,
+    /// Average share of time in percent in which some tasks waited for a cpu
+    /// in the last 10 seconds, 0 without pressure stall information
+    pub cpu_pressure: f64,
+}
+
+impl SubCgroup {
+    fn load(name: String, path: PathBuf) -> Result<Self, PlacementError> {
+        // pids.current is only there with the pids controller enabled
+        let pids_current = path.join(CGROUP_PIDS_CURRENT);
+        let pids = if pids_current.exists() {
+            common::read_cgroup_file(&pids_current)?
+                .trim()
+                .parse()
+                .unwrap_or_default()
+        } else {
+            common::get_all_pids(&path)?.len() as u64
+        };
+
+        let cpu_pressure_file = path.join(CGROUP_CPU_PRESSURE);
+        let cpu_pressure = if cpu_pressure_file.exists() {
+            stats::psi_stats(&cpu_pressure_file)?.some.avg10
+        } else {
+            0.0
+        };
+
+        Ok(Self {
+            name,
+            path,
+            pids,
+            cpu_pressure,
+        })
+    }
+}
+
+/// Sub-cgroups of the cgroup with their load, ordered by name
+pub fn sub_cgroups(cgroup_path: &Path) -> Result<Vec<SubCgroup>, PlacementError> {
+    let mut sub_cgroups = Vec::new();
+    for entry in fs::read_dir(cgroup_path).wrap_read(cgroup_path)? {
+        let entry = entry.wrap_read(cgroup_path)?;
+        if !entry.file_type().wrap_read(entry.path())?.is_dir() {
+            continue;
+        }
+        let name = entry.file_name().to_string_lossy().into_owned();
+        sub_cgroups.push(SubCgroup::load(name, entry.path())?);
+    }
+    sub_cgroups.sort_by(|a, b| a.name.cmp(&b.name));
+
+    Ok(sub_cgroups)
+}
+
+/// Picks the sub-cgroup for a process. `last` is the name of the sub-cgroup
+/// picked last, which round robin continues after.
+pub fn place<'a>(
+    sub_cgroups: &'a [SubCgroup],
+    policy: PlacementPolicy,
+    last: Option<&str>,
+) -> Option<&'a SubCgroup> {
+    match policy {
+        PlacementPolicy::LeastLoaded => sub_cgroups.iter().min_by(|a, b| {
+            a.cpu_pressure
+                .partial_cmp(&b.cpu_pressure)
+                .unwrap_or(Ordering::Equal)
+                .then(a.pids.cmp(&b.pids))
+                .then(a.name.cmp(&b.name))
+        }),
+        PlacementPolicy::RoundRobin => {
+            // the last one may have been removed in the meantime
+            let next = last
+                .map(|last| sub_cgroups.iter().position(|s| s.name.as_str() > last))
+                .unwrap_or(Some(0));
+            next.and_then(|i| sub_cgroups.get(i))
+                .or_else(|| sub_cgroups.first())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test::set_fixture;
+
+    fn sub_cgroup(name: &str, pids: u64, cpu_pressure: f64) -> SubCgroup {
+        SubCgroup {
+            name: name.to_owned(),
+            path: PathBuf::from(name),
+            pids,
+            cpu_pressure,
+        }
+    }
+
+    #[test]
+    fn test_place_least_loaded() {
+        let sub_cgroups = [
+            sub_cgroup("a", 1, 4.5),
+            sub_cgroup("b", 9, 0.0),
+            sub_cgroup("c", 2, 0.0),
+        ];
+        let picked = place(&sub_cgroups, PlacementPolicy::LeastLoaded, None).unwrap();
+        assert_eq!(picked.name, "c");
+        assert!(place(&[], PlacementPolicy::LeastLoaded, None).is_none());
+    }
+
+    #[test]
+    fn test_place_round_robin() {
+        let sub_cgroups = [
+            sub_cgroup("a", 0, 0.0),
+            sub_cgroup("b", 0, 0.0),
+            sub_cgroup("c", 0, 0.0),
+        ];
+        let next = |last| {
+            place(&sub_cgroups, PlacementPolicy::RoundRobin, last)
+                .unwrap()
+                .name
+                .clone()
+        };
+        assert_eq!(next(None), "a");
+        assert_eq!(next(Some("a")), "b");
+        assert_eq!(next(Some("c")), "a");
+        // continues after a sub-cgroup which is gone
+        assert_eq!(next(Some("bb")), "c");
+    }
+
+    #[test]
+    fn test_sub_cgroups() {
+        let tmp = tempfile::tempdir().unwrap();
+        for (name, pids, pressure) in [("job-1", "3", "0.50"), ("job-0", "1", "0.00")] {
+            let dir = tmp.path().join(name);
+            fs::create_dir(&dir).unwrap();
+            set_fixture(&dir, CGROUP_PIDS_CURRENT, pids).unwrap();
+            set_fixture(
+                &dir,
+                CGROUP_CPU_PRESSURE,
+                &format!("some avg10={pressure} avg60=0.00 avg300=0.00 total=0\n"),
+            )
+            .unwrap();
+        }
+        set_fixture(tmp.path(), "cgroup.procs", "").unwrap();
+
+        let loads = sub_cgroups(tmp.path()).unwrap();
+        assert_eq!(
+            loads,
+            vec![
+                SubCgroup {
+                    name: "job-0".to_owned(),
+                    path: tmp.path().join("job-0"),
+                    pids: 1,
+                    cpu_pressure: 0.0,
+                },
+                SubCgroup {
+                    name: "job-1".to_owned(),
+                    path: tmp.path().join("job-1"),
+                    pids: 3,
+                    cpu_pressure: 0.5,
+                },
+            ]
+        );
+
+        let picked = place(&loads, PlacementPolicy::LeastLoaded, None).unwrap();
+        assert_eq!(picked.name, "job-0");
+        assert!(sub_cgroups(&tmp.path().join("job-0")).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,31 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use super::controller::Controller;
+use crate::common::ControllerOpt;
+use crate::rdma::{self, RdmaError};
+use crate::stats::{CgroupDir, RdmaStats, StatsProvider};
+
+pub struct Rdma {}
+
+impl Controller for Rdma {
+    type Error = RdmaError;
+
+    fn apply(controller_opt: &ControllerOpt, cgroup_root: &Path) -> Result<(), Self::Error> {
+        if let Some(limits) = controller_opt.resources.rdma() {
+            tracing::debug!("Apply rdma cgroup v2 config");
+            rdma::apply(cgroup_root, limits)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl StatsProvider for Rdma {
+    type Error = RdmaError;
+    type Stats = HashMap<String, RdmaStats>;
+
+    fn stats(dir: &mut CgroupDir) -> Result<Self::Stats, Self::Error> {
+        rdma::stats(dir)
+    }
+}
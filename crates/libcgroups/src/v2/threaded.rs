@@ -0,0 +1,275 @@
+//! Threaded cgroups, in which the threads of a process can be placed in
+//! different cgroups, e.g. to give the worker threads of a process their own
+//! cpu weight. A cgroup is made threaded by writing `threaded` to its
+//! `cgroup.type`, it then joins the resource domain of its parent, which
+//! becomes a `domain threaded` cgroup.
+//!
+//! The kernel only allows this if the parent is a valid domain without
+//! domain controllers enabled in its `cgroup.subtree_control` and without
+//! populated domain children, and only controllers which work on threads can
+//! be enabled in a threaded subtree. These rules are checked up front, so
+//! that a violation is reported with the reason rather than the bare error
+//! of the write.
+use std::fmt::{self, Display};
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::str::FromStr;
+
+use crate::common::{
+    self, read_cgroup_file, write_cgroup_file_str, SubtreeControlError, WrapIoResult,
+    WrappedIoError,
+};
+
+const CGROUP_TYPE: &str = "cgroup.type";
+const CGROUP_EVENTS: &str = "cgroup.events";
+const CGROUP_SUBTREE_CONTROL: &str = "cgroup.subtree_control";
+
+/// Controllers which can be enabled in a threaded subtree
+pub const THREADED_CONTROLLERS: &[&str] = &["cpu", "cpuset", "perf_event", "pids"];
+
+#[derive(thiserror::Error, Debug)]
+pub enum ThreadedError {
+    #[error("io error: {0}")]
+    WrappedIo(#[from] WrappedIoError),
+    #[error("invalid name {0:?} of a threaded cgroup, expected a single path component")]
+    InvalidName(String),
+    #[error("unknown cgroup type {0:?}")]
+    UnknownType(String),
+    #[error("{0} controller does not work on threads and can't be enabled in a threaded subtree")]
+    NotThreaded(String),
+    #[error("cgroup {0:?} is an invalid domain and can't be the parent of a threaded cgroup")]
+    InvalidParent(PathBuf),
+    #[error("cgroup {path:?} enables the domain controllers {controllers} in its subtree, which have to be disabled for a threaded child")]
+    DomainControllers { path: PathBuf, controllers: String },
+    #[error("cgroup {path:?} has the populated domain child {child:?}, which can't be a sibling of a threaded cgroup")]
+    PopulatedDomainChild { path: PathBuf, child: String },
+    #[error("cgroup {0:?} is a domain with processes or children and can't be made threaded")]
+    PopulatedDomain(PathBuf),
+    #[error(transparent)]
+    SubtreeControl(#[from] SubtreeControlError),
+}
+
+/// Mode of a cgroup, the content of its `cgroup.type`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CgroupType {
+    Domain,
+    /// Domain which is the resource domain of a threaded subtree
+    DomainThreaded,
+    /// Domain in a threaded subtree, which can't be used
+    DomainInvalid,
+    Threaded,
+}
+
+impl FromStr for CgroupType {
+    type Err = ThreadedError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "domain" => Ok(Self::Domain),
+            "domain threaded" => Ok(Self::DomainThreaded),
+            "domain invalid" => Ok(Self::DomainInvalid),
+            "threaded" => Ok(Self::Threaded),
+            _ => Err(ThreadedError::UnknownType(s.trim().to_owned())),
+        }
+    }
+}
+
+impl Display for CgroupType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let cgroup_type = match self {
+            Self::Domain => "domain",
+            Self::DomainThreaded => "domain threaded",
+            Self::DomainInvalid => "domain invalid",
+            Self::Threaded => "threaded",
+        };
+        write!(f, "{cgroup_type}")
+    }
+}
+
+/// Type of the cgroup at `path`. None for the root of the hierarchy, which
+/// has no `cgroup.type`.
+pub fn cgroup_type(path: &Path) -> Result<Option<CgroupType>, ThreadedError> {
+    let type_file = path.join(CGROUP_TYPE);
+    if !type_file.exists() {
+        return Ok(None);
+    }
+    read_cgroup_file(type_file)?.parse().map(Some)
+}
+
+fn is_populated(path: &Path) -> Result<bool, WrappedIoError> {
+    Ok(read_cgroup_file(path.join(CGROUP_EVENTS))?
+        .lines()
+        .any(|line| line.trim() == "populated 1"))
+}
+
+/// Creates the threaded child `name` of the cgroup at `parent`, or makes an
+/// existing child threaded, and enables the controllers in the subtree of
+/// the parent. Returns the path of the threaded cgroup.
+pub fn create_threaded(
+    parent: &Path,
+    name: &str,
+    controllers: &[&str],
+) -> Result<PathBuf, ThreadedError> {
+    let mut components = Path::new(name).components();
+    match (components.next(), components.next()) {
+        (Some(Component::Normal(_)), None) => {}
+        _ => return Err(ThreadedError::InvalidName(name.to_owned())),
+    }
+    if let Some(controller) = controllers
+        .iter()
+        .find(|controller| !THREADED_CONTROLLERS.contains(controller))
+    {
+        return Err(ThreadedError::NotThreaded(controller.to_string()));
+    }
+    validate_parent(parent, name)?;
+
+    let path = parent.join(name);
+    if !path.exists() {
+        fs::create_dir(&path).wrap_create_dir(&path)?;
+    }
+    match cgroup_type(&path)? {
+        Some(CgroupType::Threaded) => {}
+        Some(CgroupType::Domain) | None if is_populated(&path)? => {
+            return Err(ThreadedError::PopulatedDomain(path));
+        }
+        _ => write_cgroup_file_str(path.join(CGROUP_TYPE), "threaded")?,
+    }
+
+    let enabled = read_cgroup_file(parent.join(CGROUP_SUBTREE_CONTROL))?;
+    for controller in controllers {
+        if !enabled.split_whitespace().any(|c| c == *controller) {
+            common::enable_controller(parent, controller)?;
+        }
+    }
+    tracing::debug!(?path, ?controllers, "created threaded cgroup");
+
+    Ok(path)
+}
+
+/// Checks that a child of the cgroup can be made threaded
+fn validate_parent(parent: &Path, name: &str) -> Result<(), ThreadedError> {
+    match cgroup_type(parent)? {
+        // the root and threaded domains take threaded children as they are
+        None | Some(CgroupType::DomainThreaded) | Some(CgroupType::Threaded) => return Ok(()),
+        Some(CgroupType::DomainInvalid) => {
+            return Err(ThreadedError::InvalidParent(parent.to_owned()))
+        }
+        Some(CgroupType::Domain) => {}
+    }
+
+    let subtree_control = read_cgroup_file(parent.join(CGROUP_SUBTREE_CONTROL))?;
+    let domain_controllers: Vec<&str> = subtree_control
+        .split_whitespace()
+        .filter(|controller| !THREADED_CONTROLLERS.contains(controller))
+        .collect();
+    if !domain_controllers.is_empty() {
+        return Err(ThreadedError::DomainControllers {
+            path: parent.to_owned(),
+            controllers: domain_controllers.join(" "),
+        });
+    }
+
+    for entry in fs::read_dir(parent).wrap_read(parent)? {
+        let child = entry.wrap_read(parent)?.path();
+        if !child.is_dir() || child.file_name() == Some(name.as_ref()) {
+            continue;
+        }
+        if cgroup_type(&child)? == Some(CgroupType::Domain) && is_populated(&child)? {
+            return Err(ThreadedError::PopulatedDomainChild {
+                path: parent.to_owned(),
+                child: child
+                    .file_name()
+                    .unwrap_or_default()
+                    .to_string_lossy()
+                    .into_owned(),
+            });
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cgroup(path: &Path, cgroup_type: &str, populated: bool) {
+        fs::create_dir_all(path).unwrap();
+        fs::write(path.join(CGROUP_TYPE), cgroup_type).unwrap();
+        fs::write(
+            path.join(CGROUP_EVENTS),
+            format!("populated {}\nfrozen 0\n", populated as u8),
+        )
+        .unwrap();
+        fs::write(path.join(CGROUP_SUBTREE_CONTROL), "").unwrap();
+    }
+
+    #[test]
+    fn test_cgroup_type() {
+        for cgroup_type in [
+            CgroupType::Domain,
+            CgroupType::DomainThreaded,
+            CgroupType::DomainInvalid,
+            CgroupType::Threaded,
+        ] {
+            assert_eq!(
+                format!("{cgroup_type}\n").parse::<CgroupType>().unwrap(),
+                cgroup_type
+            );
+        }
+        assert!(matches!(
+            "domain thread".parse::<CgroupType>(),
+            Err(ThreadedError::UnknownType(_))
+        ));
+    }
+
+    #[test]
+    fn test_create_threaded() {
+        let tmp = tempfile::tempdir().unwrap();
+        let parent = tmp.path().join("container");
+        cgroup(&parent, "domain", true);
+        cgroup(&parent.join("threads"), "domain", false);
+
+        assert!(matches!(
+            create_threaded(&parent, "../threads", &[]),
+            Err(ThreadedError::InvalidName(_))
+        ));
+        assert!(matches!(
+            create_threaded(&parent, "threads", &["memory"]),
+            Err(ThreadedError::NotThreaded(_))
+        ));
+
+        fs::write(parent.join(CGROUP_SUBTREE_CONTROL), "cpu memory io").unwrap();
+        match create_threaded(&parent, "threads", &["cpu"]) {
+            Err(ThreadedError::DomainControllers { controllers, .. }) => {
+                assert_eq!(controllers, "memory io")
+            }
+            other => panic!("unexpected result {other:?}"),
+        }
+
+        fs::write(parent.join(CGROUP_SUBTREE_CONTROL), "").unwrap();
+        cgroup(&parent.join("init"), "domain", true);
+        assert!(matches!(
+            create_threaded(&parent, "threads", &["cpu"]),
+            Err(ThreadedError::PopulatedDomainChild { child, .. }) if child == "init"
+        ));
+
+        fs::remove_dir_all(parent.join("init")).unwrap();
+        let path = create_threaded(&parent, "threads", &["cpu"]).unwrap();
+        assert_eq!(path, parent.join("threads"));
+        assert_eq!(
+            fs::read_to_string(path.join(CGROUP_TYPE)).unwrap(),
+            "threaded"
+        );
+        assert_eq!(
+            fs::read_to_string(parent.join(CGROUP_SUBTREE_CONTROL)).unwrap(),
+            "+cpu"
+        );
+
+        fs::write(parent.join(CGROUP_TYPE), "domain invalid").unwrap();
+        assert!(matches!(
+            create_threaded(&parent, "threads", &[]),
+            Err(ThreadedError::InvalidParent(_))
+        ));
+    }
+}
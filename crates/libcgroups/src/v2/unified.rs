@@ -1,30 +1,121 @@
 use std::collections::HashMap;
 use std::path::Path;
 
+use oci_spec::runtime::LinuxResources;
+
 use super::controller_type::ControllerType;
+use super::cpuset;
+use super::io_cost::{self, IoCostError, IoCostModel, IoCostQos};
+use super::memory::{CGROUP_MEMORY_OOM_GROUP, CGROUP_MEMORY_SWAP_HIGH, CGROUP_MEMORY_ZSWAP_MAX};
+use super::misc;
 use crate::common::{self, ControllerOpt, WrappedIoError};
 
 #[derive(thiserror::Error, Debug)]
 pub enum V2UnifiedError {
-    #[error("io error: {0}")]
-    WrappedIo(#[from] WrappedIoError),
-    #[error("subsystem {subsystem} is not available: {err}")]
+    #[error("failed to write unified key {key}: {err}")]
+    Write { key: String, err: WrappedIoError },
+    #[error("subsystem {subsystem} of unified key {key} is not available: {err}")]
     SubsystemNotAvailable {
+        key: String,
         subsystem: String,
         err: WrappedIoError,
     },
+    #[error("invalid unified key {0:?}, expected the name of a file in the cgroup")]
+    InvalidKey(String),
+    #[error(
+        "unified key {key} conflicts with {field} of the resources, only one of them can be set"
+    )]
+    Conflict { key: String, field: &'static str },
+    #[error("io cost: {0}")]
+    IoCost(#[from] IoCostError),
 }
 
+/// Whether a typed field of the resources is set
+type IsSet = fn(&LinuxResources) -> bool;
+
+/// Unified keys which set the same cgroup file as a typed field of the
+/// resources
+const TYPED_KEYS: &[(&str, &str, IsSet)] = &[
+    ("cpu.weight", "cpu.shares", |r| {
+        r.cpu().as_ref().and_then(|c| c.shares()).is_some()
+    }),
+    ("cpu.max", "cpu.quota and cpu.period", |r| {
+        r.cpu()
+            .as_ref()
+            .map_or(false, |c| c.quota().is_some() || c.period().is_some())
+    }),
+    ("cpu.idle", "cpu.idle", |r| {
+        r.cpu().as_ref().and_then(|c| c.idle()).is_some()
+    }),
+    ("cpuset.cpus", "cpu.cpus", |r| {
+        r.cpu().as_ref().and_then(|c| c.cpus().as_ref()).is_some()
+    }),
+    ("cpuset.mems", "cpu.mems", |r| {
+        r.cpu().as_ref().and_then(|c| c.mems().as_ref()).is_some()
+    }),
+    ("memory.max", "memory.limit", |r| {
+        r.memory().as_ref().and_then(|m| m.limit()).is_some()
+    }),
+    ("memory.low", "memory.reservation", |r| {
+        r.memory().as_ref().and_then(|m| m.reservation()).is_some()
+    }),
+    ("memory.swap.max", "memory.swap", |r| {
+        r.memory().as_ref().and_then(|m| m.swap()).is_some()
+    }),
+    ("pids.max", "pids.limit", |r| r.pids().is_some()),
+    ("io.weight", "blockIO.weight", |r| {
+        r.block_io().as_ref().and_then(|b| b.weight()).is_some()
+    }),
+    ("rdma.max", "rdma", |r| {
+        r.rdma().as_ref().map_or(false, |rdma| !rdma.is_empty())
+    }),
+];
+
 pub struct Unified {}
 
 impl Unified {
+    /// Checks that the keys of the unified map name files of the cgroup and
+    /// do not set a file which a typed field of the resources sets as well.
+    /// Has to be called before any resource is applied, as the unified map
+    /// is applied last.
+    pub fn validate(controller_opt: &ControllerOpt) -> Result<(), V2UnifiedError> {
+        let resources = controller_opt.resources;
+        let unified = match resources.unified() {
+            Some(unified) => unified,
+            None => return Ok(()),
+        };
+
+        let mut keys: Vec<&String> = unified.keys().collect();
+        keys.sort();
+        for key in keys {
+            if key.contains('/') || !key.contains('.') || key.starts_with('.') {
+                return Err(V2UnifiedError::InvalidKey(key.to_owned()));
+            }
+            if let Some((_, field, _)) = TYPED_KEYS
+                .iter()
+                .find(|(typed_key, _, is_set)| typed_key == key && is_set(resources))
+            {
+                tracing::error!(key, field, "unified key conflicts with the resources");
+                return Err(V2UnifiedError::Conflict {
+                    key: key.to_owned(),
+                    field,
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// `root_path` is the root of the hierarchy, which holds the files
+    /// configuring a device for all cgroups
     pub fn apply(
         controller_opt: &ControllerOpt,
+        root_path: &Path,
         cgroup_path: &Path,
         controllers: Vec<ControllerType>,
     ) -> Result<(), V2UnifiedError> {
         if let Some(unified) = &controller_opt.resources.unified() {
-            Self::apply_impl(unified, cgroup_path, &controllers)?;
+            Self::apply_impl(unified, root_path, cgroup_path, &controllers)?;
         }
 
         Ok(())
@@ -32,18 +123,51 @@ impl Unified {
 
     fn apply_impl(
         unified: &HashMap<String, String>,
+        root_path: &Path,
         cgroup_path: &Path,
         controllers: &[ControllerType],
     ) -> Result<(), V2UnifiedError> {
         tracing::debug!("Apply unified cgroup config");
-        for (cgroup_file, value) in unified {
+        // validated before anything is written
+        let io_cost_model = unified
+            .get(io_cost::CGROUP_IO_COST_MODEL)
+            .map(|value| io_cost::parse_lines::<IoCostModel>(value))
+            .transpose()?;
+        let io_cost_qos = unified
+            .get(io_cost::CGROUP_IO_COST_QOS)
+            .map(|value| io_cost::parse_lines::<IoCostQos>(value))
+            .transpose()?;
+
+        let mut keys: Vec<&String> = unified.keys().collect();
+        keys.sort();
+        for cgroup_file in keys {
+            let value = &unified[cgroup_file];
+            // misc.max takes a single limit per write, the misc controller
+            // writes it. The cpuset controller sets the partition after the
+            // cpus. The memory controller sets the swap limits and the
+            // oom group, which may be missing from the cgroup.
+            if cgroup_file == io_cost::CGROUP_IO_COST_MODEL
+                || cgroup_file == io_cost::CGROUP_IO_COST_QOS
+                || cgroup_file == misc::CGROUP_MISC_MAX
+                || cgroup_file == cpuset::CGROUP_CPUSET_PARTITION
+                || cgroup_file == CGROUP_MEMORY_SWAP_HIGH
+                || cgroup_file == CGROUP_MEMORY_ZSWAP_MAX
+                || cgroup_file == CGROUP_MEMORY_OOM_GROUP
+            {
+                continue;
+            }
             if let Err(err) = common::write_cgroup_file_str(cgroup_path.join(cgroup_file), value) {
                 let (subsystem, _) = cgroup_file.split_once('.').unwrap_or((cgroup_file, ""));
 
+                tracing::error!(key = cgroup_file, ?err, "failed to write unified key");
                 if controllers.iter().any(|c| c.to_string() == subsystem) {
-                    Err(err)?;
+                    return Err(V2UnifiedError::Write {
+                        key: cgroup_file.to_owned(),
+                        err,
+                    });
                 } else {
                     return Err(V2UnifiedError::SubsystemNotAvailable {
+                        key: cgroup_file.to_owned(),
                         subsystem: subsystem.into(),
                         err,
                     });
@@ -51,6 +175,14 @@ impl Unified {
             }
         }
 
+        // these configure a device for all cgroups, so they only exist in the root
+        for model in io_cost_model.iter().flatten() {
+            io_cost::set_model(root_path, model)?;
+        }
+        for qos in io_cost_qos.iter().flatten() {
+            io_cost::set_qos(root_path, qos)?;
+        }
+
         Ok(())
     }
 }
@@ -60,7 +192,7 @@ mod tests {
     use std::collections::HashMap;
     use std::fs;
 
-    use oci_spec::runtime::LinuxResourcesBuilder;
+    use oci_spec::runtime::{LinuxMemoryBuilder, LinuxResourcesBuilder};
 
     use super::*;
     use crate::test::set_fixture;
@@ -96,7 +228,7 @@ mod tests {
         };
 
         // act
-        Unified::apply(&controller_opt, tmp.path(), vec![]).expect("apply unified");
+        Unified::apply(&controller_opt, tmp.path(), tmp.path(), vec![]).expect("apply unified");
 
         // assert
         let hugetlb_limit = fs::read_to_string(hugetlb_limit_path).expect("read hugetlb limit");
@@ -133,7 +265,7 @@ mod tests {
         };
 
         // act
-        let result = Unified::apply(&controller_opt, tmp.path(), vec![]);
+        let result = Unified::apply(&controller_opt, tmp.path(), tmp.path(), vec![]);
 
         // assert
         assert!(result.is_err());
@@ -170,10 +302,153 @@ mod tests {
         let result = Unified::apply(
             &controller_opt,
             tmp.path(),
+            tmp.path(),
             vec![ControllerType::HugeTlb, ControllerType::Cpu],
         );
 
         // assert
         assert!(result.is_err());
     }
+
+    #[test]
+    fn test_set_unified_io_cost() {
+        let root = tempfile::tempdir().unwrap();
+        let cgroup = tempfile::tempdir().unwrap();
+        let qos_path = set_fixture(root.path(), "io.cost.qos", "").unwrap();
+        let model_path = set_fixture(root.path(), "io.cost.model", "").unwrap();
+        let weight_path = set_fixture(cgroup.path(), "io.weight", "").unwrap();
+
+        let unified = HashMap::from([
+            (
+                "io.cost.qos".to_owned(),
+                "8:0 enable=1 ctrl=user rpct=95 rlat=5000".to_owned(),
+            ),
+            ("io.cost.model".to_owned(), "8:0 ctrl=auto".to_owned()),
+            ("io.weight".to_owned(), "default 200".to_owned()),
+        ]);
+        let resources = LinuxResourcesBuilder::default()
+            .unified(unified)
+            .build()
+            .unwrap();
+        let controller_opt = ControllerOpt {
+            resources: &resources,
+            freezer_state: None,
+            oom_score_adj: None,
+            disable_oom_killer: false,
+        };
+
+        Unified::apply(&controller_opt, root.path(), cgroup.path(), vec![]).expect("apply unified");
+
+        assert_eq!(
+            fs::read_to_string(qos_path).unwrap(),
+            "8:0 enable=1 ctrl=user rpct=95.00 rlat=5000"
+        );
+        assert_eq!(fs::read_to_string(model_path).unwrap(), "8:0 ctrl=auto");
+        assert_eq!(fs::read_to_string(weight_path).unwrap(), "default 200");
+    }
+
+    #[test]
+    fn test_set_unified_invalid_io_cost() {
+        let tmp = tempfile::tempdir().unwrap();
+        let weight_path = set_fixture(tmp.path(), "io.weight", "").unwrap();
+
+        let unified = HashMap::from([
+            (
+                "io.cost.qos".to_owned(),
+                "8:0 enable=1 latency=5".to_owned(),
+            ),
+            ("io.weight".to_owned(), "default 200".to_owned()),
+        ]);
+        let resources = LinuxResourcesBuilder::default()
+            .unified(unified)
+            .build()
+            .unwrap();
+        let controller_opt = ControllerOpt {
+            resources: &resources,
+            freezer_state: None,
+            oom_score_adj: None,
+            disable_oom_killer: false,
+        };
+
+        let result = Unified::apply(&controller_opt, tmp.path(), tmp.path(), vec![]);
+
+        assert!(matches!(
+            result,
+            Err(V2UnifiedError::IoCost(IoCostError::UnknownKey { .. }))
+        ));
+        // nothing is written when the io cost settings are invalid
+        assert_eq!(fs::read_to_string(weight_path).unwrap(), "");
+    }
+
+    #[test]
+    fn test_validate_unified() {
+        let validate = |unified: &[(&str, &str)], limit: Option<i64>| {
+            let mut builder = LinuxResourcesBuilder::default().unified(
+                unified
+                    .iter()
+                    .map(|(key, value)| (key.to_string(), value.to_string()))
+                    .collect::<HashMap<_, _>>(),
+            );
+            if let Some(limit) = limit {
+                builder =
+                    builder.memory(LinuxMemoryBuilder::default().limit(limit).build().unwrap());
+            }
+            let resources = builder.build().unwrap();
+            Unified::validate(&ControllerOpt {
+                resources: &resources,
+                freezer_state: None,
+                oom_score_adj: None,
+                disable_oom_killer: false,
+            })
+        };
+
+        validate(
+            &[("memory.max", "1073741824"), ("memory.high", "max")],
+            None,
+        )
+        .unwrap();
+        validate(&[("memory.high", "max")], Some(1073741824)).unwrap();
+        assert!(matches!(
+            validate(&[("memory.max", "1073741824")], Some(1073741824)),
+            Err(V2UnifiedError::Conflict { key, field: "memory.limit" }) if key == "memory.max"
+        ));
+        for invalid in ["../memory.max", "memory", ".max"] {
+            assert!(matches!(
+                validate(&[(invalid, "1")], None),
+                Err(V2UnifiedError::InvalidKey(key)) if key == invalid
+            ));
+        }
+    }
+
+    #[test]
+    fn test_set_unified_reports_failed_key() {
+        let tmp = tempfile::tempdir().unwrap();
+        set_fixture(tmp.path(), "cpu.weight", "").unwrap();
+
+        let unified = HashMap::from([
+            ("cpu.weight".to_owned(), "5000".to_owned()),
+            ("memory.zswap.writeback".to_owned(), "0".to_owned()),
+        ]);
+        let resources = LinuxResourcesBuilder::default()
+            .unified(unified)
+            .build()
+            .unwrap();
+        let controller_opt = ControllerOpt {
+            resources: &resources,
+            freezer_state: None,
+            oom_score_adj: None,
+            disable_oom_killer: false,
+        };
+
+        let result = Unified::apply(
+            &controller_opt,
+            tmp.path(),
+            tmp.path(),
+            vec![ControllerType::Cpu, ControllerType::Memory],
+        );
+        assert!(matches!(
+            result,
+            Err(V2UnifiedError::Write { key, .. }) if key == "memory.zswap.writeback"
+        ));
+    }
 }
@@ -50,7 +50,9 @@ pub fn get_available_controllers<P: AsRef<Path>>(
             "hugetlb" => controllers.push(ControllerType::HugeTlb),
             "io" => controllers.push(ControllerType::Io),
             "memory" => controllers.push(ControllerType::Memory),
+            "misc" => controllers.push(ControllerType::Misc),
             "pids" => controllers.push(ControllerType::Pids),
+            "rdma" => controllers.push(ControllerType::Rdma),
             tpe => tracing::warn!("Controller {} is not yet implemented.", tpe),
         }
     }
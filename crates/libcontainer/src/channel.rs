@@ -1,8 +1,11 @@
 use std::io::{IoSlice, IoSliceMut};
 use std::marker::PhantomData;
-use std::os::fd::AsRawFd;
+use std::os::fd::{AsRawFd, BorrowedFd};
 use std::os::unix::prelude::RawFd;
+use std::time::Instant;
 
+use nix::errno::Errno;
+use nix::poll::{poll, PollFd, PollFlags, PollTimeout};
 use nix::sys::socket::{self, UnixAddr};
 use nix::unistd::{self};
 use serde::{Deserialize, Serialize};
@@ -15,9 +18,13 @@ pub enum ChannelError {
     Serde(#[from] serde_json::Error),
     #[error("channel connection broken")]
     BrokenChannel,
+    #[error("timed out waiting for a message")]
+    Timeout,
 }
 pub struct Receiver<T> {
     receiver: RawFd,
+    // Receiving fails with a timeout once this point in time has passed
+    deadline: Option<Instant>,
     phantom: PhantomData<T>,
 }
 
@@ -26,6 +33,18 @@ pub struct Sender<T> {
     phantom: PhantomData<T>,
 }
 
+impl<T> AsRawFd for Sender<T> {
+    fn as_raw_fd(&self) -> RawFd {
+        self.sender
+    }
+}
+
+impl<T> AsRawFd for Receiver<T> {
+    fn as_raw_fd(&self) -> RawFd {
+        self.receiver
+    }
+}
+
 impl<T> Sender<T>
 where
     T: Serialize,
@@ -86,6 +105,32 @@ impl<T> Receiver<T>
 where
     T: serde::de::DeserializeOwned,
 {
+    /// Sets the point in time until which receiving waits for a message,
+    /// `None` waits forever
+    pub fn set_deadline(&mut self, deadline: Option<Instant>) {
+        self.deadline = deadline;
+    }
+
+    fn wait_for_message(&self) -> Result<(), ChannelError> {
+        let Some(deadline) = self.deadline else {
+            return Ok(());
+        };
+
+        // The fd stays open as long as the receiver is not closed
+        let fd = unsafe { BorrowedFd::borrow_raw(self.receiver) };
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            let timeout = PollTimeout::try_from(remaining.as_millis()).unwrap_or(PollTimeout::MAX);
+            let mut fds = [PollFd::new(fd, PollFlags::POLLIN)];
+            match poll(&mut fds, timeout) {
+                Ok(0) => return Err(ChannelError::Timeout),
+                Ok(_) => return Ok(()),
+                Err(Errno::EINTR) => continue,
+                Err(err) => return Err(err.into()),
+            }
+        }
+    }
+
     fn peek_size_iovec(&mut self) -> Result<u64, ChannelError> {
         let mut len: u64 = 0;
         let mut iov = [IoSliceMut::new(unsafe {
@@ -144,6 +189,7 @@ where
     where
         F: Default + AsMut<[RawFd]>,
     {
+        self.wait_for_message()?;
         let msg_len = self.peek_size_iovec()?;
         let mut len: u64 = 0;
         let mut buf = vec![0u8; msg_len as usize];
@@ -195,6 +241,7 @@ where
     let (os_sender, os_receiver) = unix_channel()?;
     let receiver = Receiver {
         receiver: os_receiver,
+        deadline: None,
         phantom: PhantomData,
     };
     let sender = Sender {
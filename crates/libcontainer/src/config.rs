@@ -3,9 +3,13 @@ use std::io::{BufReader, BufWriter, Write};
 use std::path::{Path, PathBuf};
 
 use oci_spec::runtime::{Hooks, Spec};
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 
+use crate::propagation::SharedMount;
+use crate::swap::ProvisionedSwap;
 use crate::utils;
+use crate::volume::VolumeHelper;
 
 #[derive(Debug, thiserror::Error)]
 pub enum ConfigError {
@@ -36,6 +40,7 @@ pub enum ConfigError {
 type Result<T> = std::result::Result<T, ConfigError>;
 
 const YOUKI_CONFIG_NAME: &str = "youki_config.json";
+const EFFECTIVE_SPEC_NAME: &str = "effective_spec.json";
 
 /// A configuration for passing information obtained during container creation to other commands.
 /// Keeping the information to a minimum improves performance.
@@ -44,6 +49,23 @@ const YOUKI_CONFIG_NAME: &str = "youki_config.json";
 pub struct YoukiConfig {
     pub hooks: Option<Hooks>,
     pub cgroup_path: PathBuf,
+    /// Helper which provisioned the volumes of the container
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub volume_helper: Option<VolumeHelper>,
+    /// Volumes to release when the container is deleted
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub volumes: Vec<String>,
+    /// Swap to remove when the container is deleted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub swap: Option<ProvisionedSwap>,
+    /// Sources of shared mounts whose stray mounts are detached when the
+    /// container is deleted
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub shared_mounts: Vec<SharedMount>,
+    /// Whether the stray mounts of the shared mounts are detached when the
+    /// container is deleted, see [`crate::propagation`]
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub detach_stray_mounts: bool,
 }
 
 impl<'a> YoukiConfig {
@@ -57,45 +79,70 @@ impl<'a> YoukiConfig {
                     .cgroups_path(),
                 container_id,
             ),
+            volume_helper: None,
+            volumes: Vec::new(),
+            swap: None,
+            shared_mounts: Vec::new(),
+            detach_stray_mounts: false,
         })
     }
 
     pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
-        let file = fs::File::create(path.as_ref().join(YOUKI_CONFIG_NAME)).map_err(|err| {
-            ConfigError::SaveIO {
-                source: err,
-                path: path.as_ref().to_owned(),
-            }
-        })?;
-        let mut writer = BufWriter::new(file);
-        serde_json::to_writer(&mut writer, self).map_err(|err| ConfigError::SaveEncode {
-            source: err,
-            path: path.as_ref().to_owned(),
-        })?;
-        writer.flush().map_err(|err| ConfigError::SaveIO {
-            source: err,
-            path: path.as_ref().to_owned(),
-        })?;
-
-        Ok(())
+        save_json(self, path.as_ref(), YOUKI_CONFIG_NAME)
     }
 
     pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
-        let path = path.as_ref();
-        let file =
-            fs::File::open(path.join(YOUKI_CONFIG_NAME)).map_err(|err| ConfigError::LoadIO {
-                source: err,
-                path: path.to_owned(),
-            })?;
-        let reader = BufReader::new(file);
-        let config = serde_json::from_reader(reader).map_err(|err| ConfigError::LoadParse {
-            source: err,
-            path: path.to_owned(),
-        })?;
-        Ok(config)
+        load_json(path.as_ref(), YOUKI_CONFIG_NAME)
     }
 }
 
+/// Saves the spec the container is created with, i.e. the spec of the bundle
+/// with the defaults, presets and volumes youki applied to it, so that later
+/// commands do not have to derive it from the bundle again.
+pub fn save_effective_spec<P: AsRef<Path>>(spec: &Spec, path: P) -> Result<()> {
+    save_json(spec, path.as_ref(), EFFECTIVE_SPEC_NAME)
+}
+
+/// Loads the spec saved by [`save_effective_spec`]. Returns None for
+/// containers created before youki saved it.
+pub fn load_effective_spec<P: AsRef<Path>>(path: P) -> Result<Option<Spec>> {
+    let path = path.as_ref();
+    if !path.join(EFFECTIVE_SPEC_NAME).exists() {
+        return Ok(None);
+    }
+    load_json(path, EFFECTIVE_SPEC_NAME).map(Some)
+}
+
+fn save_json<T: Serialize>(value: &T, path: &Path, name: &str) -> Result<()> {
+    let file = fs::File::create(path.join(name)).map_err(|err| ConfigError::SaveIO {
+        source: err,
+        path: path.to_owned(),
+    })?;
+    let mut writer = BufWriter::new(file);
+    serde_json::to_writer(&mut writer, value).map_err(|err| ConfigError::SaveEncode {
+        source: err,
+        path: path.to_owned(),
+    })?;
+    writer.flush().map_err(|err| ConfigError::SaveIO {
+        source: err,
+        path: path.to_owned(),
+    })?;
+
+    Ok(())
+}
+
+fn load_json<T: DeserializeOwned>(path: &Path, name: &str) -> Result<T> {
+    let file = fs::File::open(path.join(name)).map_err(|err| ConfigError::LoadIO {
+        source: err,
+        path: path.to_owned(),
+    })?;
+    let reader = BufReader::new(file);
+    serde_json::from_reader(reader).map_err(|err| ConfigError::LoadParse {
+        source: err,
+        path: path.to_owned(),
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use anyhow::Result;
@@ -127,4 +174,16 @@ mod tests {
         assert_eq!(act, config);
         Ok(())
     }
+
+    #[test]
+    fn test_effective_spec_save_and_load() -> Result<()> {
+        let tmp = tempfile::tempdir().expect("create temp dir");
+        assert!(load_effective_spec(&tmp)?.is_none());
+
+        let mut spec = Spec::default();
+        spec.set_hostname(Some("effective".into()));
+        save_effective_spec(&spec, &tmp)?;
+        assert_eq!(load_effective_spec(&tmp)?, Some(spec));
+        Ok(())
+    }
 }
@@ -0,0 +1,305 @@
+//! Tee mode for containers with a console socket. Usually the pty master of
+//! the container is handed straight to the consumer of the console socket,
+//! so nothing else ever sees what happens on the console. In tee mode youki
+//! keeps the master of the container for itself and hands the consumer the
+//! master of a second pty instead. A proxy process copies between both
+//! terminals and appends everything the container writes to a log file,
+//! which allows to record console sessions.
+//!
+//! The proxy lives until the container closed its side of the terminal. It
+//! forwards window size changes made by the consumer to the container. The
+//! proxy takes over the process it runs in, so callers run it in a process
+//! of their own, which they start before the container is built: forking
+//! one from within the builder would not be safe in a multi-threaded
+//! caller. youki executes itself for it, with the hidden `console-tee`
+//! command.
+use std::fs::{File, OpenOptions};
+use std::io::{self, IoSliceMut, Read, Write};
+use std::os::fd::{AsFd, AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+
+use nix::errno::Errno;
+use nix::poll::{poll, PollFd, PollFlags, PollTimeout};
+use nix::sys::signal::{SigSet, Signal};
+use nix::sys::signalfd::SignalFd;
+use nix::sys::socket::{self, ControlMessageOwned, MsgFlags, UnixAddr};
+use nix::sys::termios::{self, SetArg};
+use nix::unistd::{dup2, setsid};
+
+use crate::utils;
+
+#[derive(Debug, thiserror::Error)]
+pub enum ConsoleTeeError {
+    #[error("failed to open console log {path:?}")]
+    OpenLog { path: PathBuf, source: io::Error },
+    #[error("failed to receive the pty master of the container")]
+    ReceiveMaster(#[source] nix::Error),
+    #[error("the container did not send a pty master")]
+    NoMaster,
+    #[error("could not create pseudo terminal")]
+    CreatePseudoTerminal(#[source] nix::Error),
+    #[error("failed to send pty master to the console socket")]
+    SendMaster(#[source] nix::Error),
+}
+
+type Result<T> = std::result::Result<T, ConsoleTeeError>;
+
+const BUFFER_SIZE: usize = 4096;
+
+pub struct ConsoleTee {
+    consumer: OwnedFd,
+    log: File,
+}
+
+impl ConsoleTee {
+    /// Prepares tee mode for the console socket connected to `consumer`,
+    /// appending the console output to the file at `log_path`.
+    pub fn new(consumer: OwnedFd, log_path: &Path) -> Result<Self> {
+        let log = OpenOptions::new()
+            .append(true)
+            .create(true)
+            .mode(0o600)
+            .open(log_path)
+            .map_err(|err| {
+                tracing::error!(?err, ?log_path, "failed to open console log");
+                ConsoleTeeError::OpenLog {
+                    path: log_path.to_owned(),
+                    source: err,
+                }
+            })?;
+
+        Ok(Self { consumer, log })
+    }
+
+    /// Receives the pty master of the container over `receiver`, the
+    /// console socket the container init connected to, and hands the
+    /// consumer the master of a new pty. Returns the proxy between both.
+    pub fn hand_over(self, receiver: &OwnedFd) -> Result<ConsoleProxy> {
+        let Self { consumer, log } = self;
+
+        let container_master = receive_master(receiver)?;
+        let pty = nix::pty::openpty(None, None).map_err(ConsoleTeeError::CreatePseudoTerminal)?;
+        send_master(&consumer, &pty.master)?;
+        // the consumer owns the new master from now on
+
+        Ok(ConsoleProxy {
+            container: container_master,
+            consumer: pty.slave,
+            log,
+        })
+    }
+}
+
+/// Copies between the terminal of the container and the one handed to the
+/// consumer, see [`ConsoleTee::hand_over`].
+pub struct ConsoleProxy {
+    container: OwnedFd,
+    consumer: OwnedFd,
+    log: File,
+}
+
+impl ConsoleProxy {
+    /// Runs until the container closed its side of the terminal. The proxy
+    /// becomes a session leader, replaces the stdio of the process with
+    /// /dev/null and closes every other file, so it must run in a process
+    /// of its own.
+    pub fn run(self) -> io::Result<()> {
+        proxy(self.container, self.consumer, self.log)
+    }
+}
+
+/// Receives the pty master which the container init sends over its end of
+/// the console socket.
+pub fn receive_master(receiver: &OwnedFd) -> Result<OwnedFd> {
+    let mut buf = [0u8; BUFFER_SIZE];
+    let mut iov = [IoSliceMut::new(&mut buf)];
+    let mut cmsg = nix::cmsg_space!(RawFd);
+    let msg = socket::recvmsg::<UnixAddr>(
+        receiver.as_raw_fd(),
+        &mut iov,
+        Some(&mut cmsg),
+        MsgFlags::MSG_CMSG_CLOEXEC,
+    )
+    .map_err(|err| {
+        tracing::error!(?err, "failed to receive pty master");
+        ConsoleTeeError::ReceiveMaster(err)
+    })?;
+
+    msg.cmsgs()
+        .find_map(|cmsg| match cmsg {
+            ControlMessageOwned::ScmRights(fds) => fds.first().copied(),
+            _ => None,
+        })
+        // SAFETY: the fd was just received and is not owned by anything else
+        .map(|fd| unsafe { OwnedFd::from_raw_fd(fd) })
+        .ok_or(ConsoleTeeError::NoMaster)
+}
+
+fn send_master(consumer: &OwnedFd, master: &OwnedFd) -> Result<()> {
+    let pty_name: &[u8] = b"/dev/ptmx";
+    let iov = [io::IoSlice::new(pty_name)];
+    let fds = [master.as_raw_fd()];
+    let cmsg = socket::ControlMessage::ScmRights(&fds);
+    socket::sendmsg::<UnixAddr>(consumer.as_raw_fd(), &iov, &[cmsg], MsgFlags::empty(), None)
+        .map_err(|err| {
+            tracing::error!(?err, "failed to send pty master");
+            ConsoleTeeError::SendMaster(err)
+        })?;
+
+    Ok(())
+}
+
+fn proxy(container: OwnedFd, consumer: OwnedFd, mut log: File) -> io::Result<()> {
+    // Becoming the session leader with the pty of the consumer as
+    // controlling terminal makes the kernel send SIGWINCH on resizes.
+    setsid()?;
+    if unsafe { libc::ioctl(consumer.as_raw_fd(), libc::TIOCSCTTY, 0) } < 0 {
+        tracing::warn!("could not TIOCSCTTY, window size changes are not forwarded");
+    }
+    let mut attrs = termios::tcgetattr(&consumer)?;
+    termios::cfmakeraw(&mut attrs);
+    termios::tcsetattr(&consumer, SetArg::TCSANOW, &attrs)?;
+    copy_window_size(&consumer, &container);
+
+    let mut mask = SigSet::empty();
+    mask.add(Signal::SIGWINCH);
+    mask.thread_block()?;
+    let mut sigfd = SignalFd::new(&mask)?;
+
+    // Do not keep the stdio or any other file of the caller open, callers
+    // may wait for them to be closed.
+    let null = File::options().read(true).write(true).open("/dev/null")?;
+    for stdio in 0..3 {
+        dup2(null.as_raw_fd(), stdio)?;
+    }
+    drop(null);
+    utils::close_other_fds(&[
+        container.as_raw_fd(),
+        consumer.as_raw_fd(),
+        log.as_raw_fd(),
+        sigfd.as_raw_fd(),
+    ])?;
+
+    let mut container = File::from(container);
+    let mut consumer = File::from(consumer);
+    let mut consumer_open = true;
+    let mut buf = [0u8; BUFFER_SIZE];
+    loop {
+        let mut fds = vec![
+            PollFd::new(container.as_fd(), PollFlags::POLLIN),
+            PollFd::new(sigfd.as_fd(), PollFlags::POLLIN),
+        ];
+        if consumer_open {
+            fds.push(PollFd::new(consumer.as_fd(), PollFlags::POLLIN));
+        }
+        match poll(&mut fds, PollTimeout::NONE) {
+            Err(Errno::EINTR) => continue,
+            result => result?,
+        };
+        let ready: Vec<bool> = fds
+            .iter()
+            .map(|fd| fd.revents().map_or(false, |revents| !revents.is_empty()))
+            .collect();
+        drop(fds);
+
+        if ready[0] {
+            let n = match container.read(&mut buf) {
+                // the container closed all of its terminal fds
+                Ok(0) | Err(_) => return Ok(()),
+                Ok(n) => n,
+            };
+            log.write_all(&buf[..n])?;
+            if consumer_open && consumer.write_all(&buf[..n]).is_err() {
+                consumer_open = false;
+            }
+        }
+        if ready[1] && sigfd.read_signal()?.is_some() {
+            copy_window_size(&consumer, &container);
+        }
+        if consumer_open && ready[2] {
+            match consumer.read(&mut buf) {
+                // the consumer closed its master, keep recording
+                Ok(0) | Err(_) => consumer_open = false,
+                Ok(n) => container.write_all(&buf[..n])?,
+            }
+        }
+    }
+}
+
+/// Applies the window size of the terminal `from` to the terminal `to`.
+pub fn copy_window_size<F: AsRawFd, T: AsRawFd>(from: &F, to: &T) {
+    let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+    if unsafe { libc::ioctl(from.as_raw_fd(), libc::TIOCGWINSZ, &mut size) } < 0 {
+        return;
+    }
+    if unsafe { libc::ioctl(to.as_raw_fd(), libc::TIOCSWINSZ, &size) } < 0 {
+        tracing::warn!("failed to set the window size of the container terminal");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::os::unix::net::UnixStream;
+
+    use anyhow::Result;
+    use nix::sys::wait::{waitpid, WaitStatus};
+    use nix::unistd::{fork, ForkResult};
+
+    use super::*;
+
+    #[test]
+    fn test_console_tee() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let log_path = tmp.path().join("console.log");
+        let (consumer, consumer_peer) = UnixStream::pair()?;
+
+        let (receiver, init_socket) = UnixStream::pair()?;
+
+        let tee = ConsoleTee::new(consumer.into(), &log_path)?;
+        // play the container init, which sends its pty master
+        let container_pty = nix::pty::openpty(None, None)?;
+        send_master(&init_socket.into(), &container_pty.master)?;
+        drop(container_pty.master);
+        let console_proxy = tee.hand_over(&receiver.into())?;
+        // the proxy takes over the process it runs in
+        let proxy = match unsafe { fork() }? {
+            ForkResult::Parent { child } => child,
+            ForkResult::Child => {
+                let code = i32::from(console_proxy.run().is_err());
+                std::process::exit(code);
+            }
+        };
+
+        let consumer_master = receive_master(&consumer_peer.into())?;
+        let mut container = File::from(container_pty.slave);
+        container.write_all(b"hello")?;
+        let mut consumer = File::from(consumer_master);
+        let mut buf = [0u8; 5];
+        consumer.read_exact(&mut buf)?;
+        assert_eq!(&buf, b"hello");
+
+        drop(container);
+        assert_eq!(waitpid(proxy, None)?, WaitStatus::Exited(proxy, 0));
+        assert_eq!(fs::read(&log_path)?, b"hello");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_missing_master() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let (consumer, _consumer_peer) = UnixStream::pair()?;
+        let (receiver, init_socket) = UnixStream::pair()?;
+        let tee = ConsoleTee::new(consumer.into(), &tmp.path().join("log"))?;
+        drop(init_socket);
+
+        assert!(matches!(
+            tee.hand_over(&receiver.into()),
+            Err(ConsoleTeeError::NoMaster)
+        ));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,324 @@
+//! Runs an ordered list of lifecycle operations on containers, e.g. for test
+//! harnesses or launchers which manage many containers at once. Setup which
+//! all operations share, like resolving the root path, detecting the cgroup
+//! setup of the host and connecting to systemd, is done once for the whole
+//! batch instead of once per operation, and every operation gets a result of
+//! its own.
+//!
+//! Operations are usually read from JSON:
+//!
+//! ```json
+//! [
+//!   {"op": "create", "id": "web", "bundle": "/bundles/web"},
+//!   {"op": "start", "id": "web"},
+//!   {"op": "kill", "id": "web", "signal": "SIGTERM"},
+//!   {"op": "delete", "id": "web", "force": true}
+//! ]
+//! ```
+use std::fs;
+use std::path::PathBuf;
+
+use libcgroups::common::CgroupHost;
+use serde::{Deserialize, Serialize};
+
+use super::builder::ContainerBuilder;
+use super::Container;
+use crate::error::LibcontainerError;
+use crate::signal::Signal;
+use crate::syscall::syscall::SyscallType;
+use crate::workload::Executor;
+
+/// A lifecycle operation on one container
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(tag = "op", rename_all = "lowercase", deny_unknown_fields)]
+pub enum Operation {
+    /// Creates a container from a bundle
+    Create { id: String, bundle: PathBuf },
+    /// Starts a created container
+    Start { id: String },
+    /// Sends a signal to the container, SIGTERM by default
+    Kill {
+        id: String,
+        #[serde(default = "default_signal")]
+        signal: String,
+        #[serde(default)]
+        all: bool,
+    },
+    /// Deletes a container, force also deletes running containers
+    Delete {
+        id: String,
+        #[serde(default)]
+        force: bool,
+    },
+}
+
+fn default_signal() -> String {
+    "SIGTERM".to_owned()
+}
+
+impl Operation {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Operation::Create { .. } => "create",
+            Operation::Start { .. } => "start",
+            Operation::Kill { .. } => "kill",
+            Operation::Delete { .. } => "delete",
+        }
+    }
+
+    pub fn id(&self) -> &str {
+        match self {
+            Operation::Create { id, .. }
+            | Operation::Start { id }
+            | Operation::Kill { id, .. }
+            | Operation::Delete { id, .. } => id,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OperationStatus {
+    Ok,
+    Failed,
+    /// Not run because an earlier operation failed and the batch stops on
+    /// errors
+    Skipped,
+}
+
+/// Outcome of one operation of a batch
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct OperationResult {
+    pub op: &'static str,
+    pub id: String,
+    pub status: OperationStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Runs operations with a shared configuration
+pub struct Batch<E: Executor + Clone + 'static> {
+    root_path: PathBuf,
+    syscall: SyscallType,
+    executor: E,
+    cgroup_host: CgroupHost,
+    use_systemd: bool,
+    stop_on_error: bool,
+}
+
+impl<E: Executor + Clone + 'static> Batch<E> {
+    /// Prepares a batch for the containers below `root_path`
+    pub fn new<P: Into<PathBuf>>(
+        root_path: P,
+        syscall: SyscallType,
+        executor: E,
+    ) -> Result<Self, LibcontainerError> {
+        let root_path = root_path.into();
+        fs::create_dir_all(&root_path).map_err(|err| {
+            tracing::error!(?err, ?root_path, "failed to create root path");
+            LibcontainerError::OtherIO(err)
+        })?;
+        let root_path = fs::canonicalize(&root_path).map_err(|err| {
+            tracing::error!(?err, ?root_path, "failed to canonicalize root path");
+            LibcontainerError::OtherIO(err)
+        })?;
+        // Detecting the cgroup setup fails the same way for every operation
+        let cgroup_host = CgroupHost::detect()?;
+
+        Ok(Self {
+            root_path,
+            syscall,
+            executor,
+            cgroup_host,
+            use_systemd: false,
+            stop_on_error: false,
+        })
+    }
+
+    /// Sets if created containers use systemd to manage their cgroups
+    pub fn with_systemd(mut self, use_systemd: bool) -> Self {
+        self.use_systemd = use_systemd;
+        self
+    }
+
+    /// Sets if the remaining operations are skipped after one failed
+    pub fn with_stop_on_error(mut self, stop_on_error: bool) -> Self {
+        self.stop_on_error = stop_on_error;
+        self
+    }
+
+    /// Runs the operations in order and returns one result for each of them
+    pub fn run(&self, operations: &[Operation]) -> Vec<OperationResult> {
+        let mut cgroup_host = self.cgroup_host.clone();
+        if self.use_systemd {
+            // Without a shared connection every container connects itself,
+            // so a failure is reported by the operations which need it.
+            if let Err(err) = cgroup_host.connect_systemd() {
+                tracing::warn!(?err, "failed to connect to systemd for the batch");
+            }
+        }
+
+        let mut failed = false;
+        operations
+            .iter()
+            .map(|operation| {
+                let (status, error) = if failed && self.stop_on_error {
+                    (OperationStatus::Skipped, None)
+                } else {
+                    match self.run_operation(operation, &cgroup_host) {
+                        Ok(()) => (OperationStatus::Ok, None),
+                        Err(err) => {
+                            tracing::error!(?err, ?operation, "batch operation failed");
+                            failed = true;
+                            (OperationStatus::Failed, Some(err.to_string()))
+                        }
+                    }
+                };
+
+                OperationResult {
+                    op: operation.name(),
+                    id: operation.id().to_owned(),
+                    status,
+                    error,
+                }
+            })
+            .collect()
+    }
+
+    fn run_operation(
+        &self,
+        operation: &Operation,
+        cgroup_host: &CgroupHost,
+    ) -> Result<(), LibcontainerError> {
+        match operation {
+            Operation::Create { id, bundle } => {
+                ContainerBuilder::new(id.clone(), self.syscall)
+                    .with_executor(self.executor.clone())
+                    .with_root_path(&self.root_path)?
+                    .validate_id()?
+                    .as_init(bundle)
+                    .with_systemd(self.use_systemd)
+                    .with_cgroup_host(cgroup_host.clone())
+                    .with_detach(true)
+                    .build()?;
+            }
+            Operation::Start { id } => self.load(id, cgroup_host)?.start()?,
+            Operation::Kill { id, signal, all } => {
+                let signal = Signal::try_from(signal.as_str()).map_err(|err| {
+                    tracing::error!(?err, ?signal, "failed to parse signal");
+                    LibcontainerError::InvalidInput(err.to_string())
+                })?;
+                self.load(id, cgroup_host)?.kill(signal, *all)?;
+            }
+            Operation::Delete { id, force } => {
+                let container_root = self.root_path.join(id);
+                if !container_root.exists() && *force {
+                    return Ok(());
+                }
+                self.load(id, cgroup_host)?.delete(*force)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn load(&self, id: &str, cgroup_host: &CgroupHost) -> Result<Container, LibcontainerError> {
+        let container_root = self.root_path.join(id);
+        if !container_root.exists() {
+            return Err(LibcontainerError::NoDirectory);
+        }
+
+        Ok(Container::load(container_root)?.with_cgroup_host(cgroup_host.clone()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+
+    use super::*;
+    use crate::workload::default::DefaultExecutor;
+
+    #[test]
+    fn test_parse_operations() -> Result<()> {
+        let operations: Vec<Operation> = serde_json::from_str(
+            r#"[
+                {"op": "create", "id": "a", "bundle": "/bundles/a"},
+                {"op": "start", "id": "a"},
+                {"op": "kill", "id": "a"},
+                {"op": "kill", "id": "a", "signal": "9", "all": true},
+                {"op": "delete", "id": "a", "force": true}
+            ]"#,
+        )?;
+
+        assert_eq!(
+            operations,
+            vec![
+                Operation::Create {
+                    id: "a".to_owned(),
+                    bundle: "/bundles/a".into(),
+                },
+                Operation::Start { id: "a".to_owned() },
+                Operation::Kill {
+                    id: "a".to_owned(),
+                    signal: "SIGTERM".to_owned(),
+                    all: false,
+                },
+                Operation::Kill {
+                    id: "a".to_owned(),
+                    signal: "9".to_owned(),
+                    all: true,
+                },
+                Operation::Delete {
+                    id: "a".to_owned(),
+                    force: true,
+                },
+            ]
+        );
+        assert!(serde_json::from_str::<Operation>(r#"{"op": "pause", "id": "a"}"#).is_err());
+        assert!(
+            serde_json::from_str::<Operation>(r#"{"op": "start", "id": "a", "force": true}"#)
+                .is_err()
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_run_reports_each_operation() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let batch = Batch::new(tmp.path(), SyscallType::default(), DefaultExecutor {})?;
+        let operations = [
+            Operation::Delete {
+                id: "missing".to_owned(),
+                force: true,
+            },
+            Operation::Start {
+                id: "missing".to_owned(),
+            },
+            Operation::Delete {
+                id: "missing".to_owned(),
+                force: false,
+            },
+        ];
+
+        let results = batch.run(&operations);
+        let statuses: Vec<_> = results.iter().map(|r| r.status).collect();
+        assert_eq!(
+            statuses,
+            [
+                OperationStatus::Ok,
+                OperationStatus::Failed,
+                OperationStatus::Failed
+            ]
+        );
+        assert_eq!(results[1].op, "start");
+        assert!(results[1].error.is_some());
+
+        let results = batch.with_stop_on_error(true).run(&operations);
+        assert_eq!(results[2].status, OperationStatus::Skipped);
+        assert_eq!(results[2].error, None);
+
+        Ok(())
+    }
+}
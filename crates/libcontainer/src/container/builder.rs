@@ -1,13 +1,20 @@
 use std::os::fd::OwnedFd;
 use std::path::PathBuf;
 
+use super::finished::FINISHED_DIR;
 use super::init_builder::InitContainerBuilder;
 use super::tenant_builder::TenantContainerBuilder;
 use crate::error::{ErrInvalidID, LibcontainerError};
+use crate::fault_injection::{self, FaultPoint};
+use crate::process::timeouts::Timeouts;
+use crate::seccomp_profile;
 use crate::syscall::syscall::SyscallType;
 use crate::utils::PathBufExt;
 use crate::workload::{self, Executor};
 
+/// Names of the directories in the root path which are not containers
+const RESERVED_IDS: &[&str] = &[FINISHED_DIR, seccomp_profile::CACHE_DIR];
+
 pub struct ContainerBuilder {
     /// Id of the container
     pub(super) container_id: String,
@@ -31,6 +38,11 @@ pub struct ContainerBuilder {
     pub stdout: Option<OwnedFd>,
     // RawFd set to stderr of the container init process.
     pub stderr: Option<OwnedFd>,
+    /// Point at which the container processes fail on purpose, only used
+    /// with the `fault-injection` feature
+    pub(super) fault_point: Option<FaultPoint>,
+    /// Time limits for the creation of the container
+    pub(super) timeouts: Timeouts,
 }
 
 /// Builder that can be used to configure the common properties of
@@ -80,6 +92,8 @@ impl ContainerBuilder {
             stdin: None,
             stdout: None,
             stderr: None,
+            fault_point: fault_injection::from_env(),
+            timeouts: Timeouts::default(),
         }
     }
 
@@ -99,7 +113,8 @@ impl ContainerBuilder {
     /// - period (.).
     ///
     /// In addition, IDs that can't be used to represent a file name
-    /// (such as . or ..) are rejected.
+    /// (such as . or ..) and the names of the directories youki keeps in the
+    /// root path (such as .finished) are rejected.
     pub fn validate_id(self) -> Result<Self, LibcontainerError> {
         let container_id = self.container_id.clone();
         if container_id.is_empty() {
@@ -110,6 +125,10 @@ impl ContainerBuilder {
             Err(ErrInvalidID::FileName)?;
         }
 
+        if RESERVED_IDS.contains(&container_id.as_str()) {
+            Err(ErrInvalidID::Reserved(container_id.clone()))?;
+        }
+
         for c in container_id.chars() {
             match c {
                 'a'..='z' | 'A'..='Z' | '0'..='9' | '_' | '+' | '-' | '.' => (),
@@ -336,6 +355,53 @@ impl ContainerBuilder {
         self.stderr = Some(stderr.into());
         self
     }
+
+    /// Sets the time limits for creating the container. A container process
+    /// which is stuck longer is killed and the creation is rolled back.
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use std::time::Duration;
+    /// # use libcontainer::container::builder::ContainerBuilder;
+    /// # use libcontainer::process::timeouts::{Phase, Timeouts};
+    /// # use libcontainer::syscall::syscall::SyscallType;
+    ///
+    /// ContainerBuilder::new(
+    ///     "74f1a4cb3801".to_owned(),
+    ///     SyscallType::default(),
+    /// )
+    /// .with_timeouts(
+    ///     Timeouts::default()
+    ///         .with_create(Duration::from_secs(60))
+    ///         .with_phase(Phase::Hooks, Duration::from_secs(10)),
+    /// );
+    /// ```
+    pub fn with_timeouts(mut self, timeouts: Timeouts) -> Self {
+        self.timeouts = timeouts;
+        self
+    }
+
+    /// Makes the creation or the start of the container fail at the given
+    /// point, to test that partial failures are cleaned up. Overrides the
+    /// point set in the environment.
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use libcontainer::container::builder::ContainerBuilder;
+    /// # use libcontainer::fault_injection::FaultPoint;
+    /// # use libcontainer::syscall::syscall::SyscallType;
+    ///
+    /// ContainerBuilder::new(
+    ///     "74f1a4cb3801".to_owned(),
+    ///     SyscallType::default(),
+    /// )
+    /// .with_fault_injection(Some(FaultPoint::AfterCgroupCreate));
+    /// ```
+    #[cfg(feature = "fault-injection")]
+    pub fn with_fault_injection(mut self, fault_point: Option<FaultPoint>) -> Self {
+        self.fault_point = fault_point;
+        self
+    }
 }
 
 #[cfg(test)]
@@ -411,6 +477,12 @@ mod tests {
         let result = ContainerBuilder::new("...".to_owned(), syscall).validate_id();
         assert!(result.is_ok());
 
+        let result = ContainerBuilder::new(".finished".to_owned(), syscall).validate_id();
+        assert!(result.is_err());
+
+        let result = ContainerBuilder::new("finished".to_owned(), syscall).validate_id();
+        assert!(result.is_ok());
+
         let result = ContainerBuilder::new("74f1a4cb3801".to_owned(), syscall).validate_id();
         assert!(result.is_ok());
         Ok(())
@@ -1,23 +1,30 @@
 use std::fs;
 use std::io::Write;
 use std::os::fd::{AsRawFd, OwnedFd};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
 
-use libcgroups::common::CgroupManager;
+use libcgroups::common::{CgroupConfig, CgroupHost};
 use nix::unistd::Pid;
 use oci_spec::runtime::Spec;
 
+use super::guard::{CgroupGuard, NamespaceGuard};
 use super::{Container, ContainerStatus};
+use crate::debug_capture::InitReportFile;
 use crate::error::{CreateContainerError, LibcontainerError, MissingSpecError};
+use crate::fault_injection::FaultPoint;
 use crate::notify_socket::NotifyListener;
 use crate::process::args::{ContainerArgs, ContainerType};
+use crate::process::cgroup_delegation;
 use crate::process::intel_rdt::delete_resctrl_subdirectory;
+use crate::process::parent_death::ParentDeath;
+use crate::process::timeouts::Timeouts;
 use crate::process::{self};
+use crate::rootfs;
 use crate::syscall::syscall::SyscallType;
 use crate::user_ns::UserNamespaceConfig;
+use crate::utils;
 use crate::workload::Executor;
-use crate::{hooks, utils};
 
 pub(super) struct ContainerBuilderImpl {
     /// Flag indicating if an init or a tenant container should be created
@@ -41,8 +48,18 @@ pub(super) struct ContainerBuilderImpl {
     pub user_ns_config: Option<UserNamespaceConfig>,
     /// Path to the Unix Domain Socket to communicate container start
     pub notify_path: PathBuf,
-    /// Container state
+    /// Listener for the container start to use instead of binding one to
+    /// notify_path
+    pub notify_listener: Option<NotifyListener>,
+    /// State of the container which is created or joined
     pub container: Option<Container>,
+    /// Cgroup a tenant joins instead of the container cgroup
+    pub tenant_cgroup: Option<PathBuf>,
+    /// Name of the threaded cgroup created below the cgroup of the init
+    /// process
+    pub threaded_cgroup: Option<String>,
+    /// Host as examined before for the cgroup managers
+    pub cgroup_host: CgroupHost,
     /// File descriptos preserved/passed to the container init process.
     pub preserve_fds: i32,
     /// If the container is to be run in detached mode
@@ -59,20 +76,36 @@ pub(super) struct ContainerBuilderImpl {
     pub stderr: Option<OwnedFd>,
     // Indicate if the init process should be a sibling of the main process.
     pub as_sibling: bool,
+    /// What happens to the container processes when their parent dies
+    pub parent_death: ParentDeath,
+    /// Point at which the container processes fail on purpose
+    pub fault_point: Option<FaultPoint>,
+    /// Time limits of the container setup
+    pub timeouts: Timeouts,
+    /// Report the init process keeps of its stages and failure
+    pub init_report: Option<InitReportFile>,
 }
 
 impl ContainerBuilderImpl {
     pub(super) fn create(&mut self) -> Result<Pid, LibcontainerError> {
+        // Only the init container creates a cgroup, a tenant joins the one of
+        // the init container and must leave it alone.
+        let cgroup_guard = if self.is_init_container() {
+            Some(CgroupGuard::new(self.cgroup_config()?))
+        } else {
+            None
+        };
+
         match self.run_container() {
-            Ok(pid) => Ok(pid),
+            Ok(pid) => {
+                if let Some(cgroup_guard) = cgroup_guard {
+                    cgroup_guard.commit();
+                }
+                Ok(pid)
+            }
             Err(outer) => {
-                // Only the init container should be cleaned up in the case of
-                // an error.
-                let cleanup_err = if self.is_init_container() {
-                    self.cleanup_container().err()
-                } else {
-                    None
-                };
+                let cleanup_err =
+                    cgroup_guard.and_then(|guard| self.cleanup_container(guard).err());
 
                 Err(CreateContainerError::new(outer, cleanup_err).into())
             }
@@ -83,37 +116,44 @@ impl ContainerBuilderImpl {
         matches!(self.container_type, ContainerType::InitContainer)
     }
 
-    fn run_container(&mut self) -> Result<Pid, LibcontainerError> {
+    fn cgroup_config(&self) -> Result<CgroupConfig, LibcontainerError> {
         let linux = self.spec.linux().as_ref().ok_or(MissingSpecError::Linux)?;
         let cgroups_path = utils::get_cgroup_path(linux.cgroups_path(), &self.container_id);
-        let cgroup_config = libcgroups::common::CgroupConfig {
+
+        Ok(CgroupConfig {
             cgroup_path: cgroups_path,
             systemd_cgroup: self.use_systemd || self.user_ns_config.is_some(),
             container_name: self.container_id.to_owned(),
-        };
+            init_leaf: cgroup_delegation::init_leaf(self.spec.annotations().as_ref())?,
+            host: self.cgroup_host.clone(),
+            // the container directory is kept in the state root
+            state_root: self
+                .container
+                .as_ref()
+                .and_then(|container| container.root.parent())
+                .map(Path::to_path_buf),
+        })
+    }
+
+    fn run_container(&mut self) -> Result<Pid, LibcontainerError> {
+        let linux = self.spec.linux().as_ref().ok_or(MissingSpecError::Linux)?;
+        let cgroup_config = self.cgroup_config()?;
         let process = self
             .spec
             .process()
             .as_ref()
             .ok_or(MissingSpecError::Process)?;
 
-        if matches!(self.container_type, ContainerType::InitContainer) {
-            if let Some(hooks) = self.spec.hooks() {
-                hooks::run_hooks(
-                    hooks.create_runtime().as_ref(),
-                    self.container.as_ref(),
-                    None,
-                )?
-            }
-        }
-
         // Need to create the notify socket before we pivot root, since the unix
         // domain socket used here is outside of the rootfs of container. During
         // exec, need to create the socket before we enter into existing mount
         // namespace. We also need to create to socket before entering into the
         // user namespace in the case that the path is located in paths only
         // root can access.
-        let notify_listener = NotifyListener::new(&self.notify_path)?;
+        let notify_listener = match self.notify_listener.take() {
+            Some(notify_listener) => notify_listener,
+            None => NotifyListener::new(&self.notify_path)?,
+        };
 
         // If Out-of-memory score adjustment is set in specification.  set the score
         // value for the current process check
@@ -154,6 +194,13 @@ impl ContainerBuilderImpl {
             })?;
         }
 
+        // Idmapped mounts of host filesystems can only be created outside of
+        // the user namespace of the container.
+        let idmapped_mounts = match self.container_type {
+            ContainerType::InitContainer => rootfs::remap::prepare_idmapped_mounts(&self.spec),
+            ContainerType::TenantContainer { .. } => Default::default(),
+        };
+
         // This container_args will be passed to the container processes,
         // therefore we will have to move all the variable by value. Since self
         // is a shared reference, we have to clone these variables here.
@@ -168,6 +215,8 @@ impl ContainerBuilderImpl {
             container: self.container.to_owned(),
             user_ns_config: self.user_ns_config.to_owned(),
             cgroup_config,
+            tenant_cgroup: self.tenant_cgroup.to_owned(),
+            threaded_cgroup: self.threaded_cgroup.to_owned(),
             detached: self.detached,
             executor: self.executor.clone(),
             no_pivot: self.no_pivot,
@@ -175,15 +224,23 @@ impl ContainerBuilderImpl {
             stdout: self.stdout.as_ref().map(|x| x.as_raw_fd()),
             stderr: self.stderr.as_ref().map(|x| x.as_raw_fd()),
             as_sibling: self.as_sibling,
+            parent_death: self.parent_death,
+            fault_point: self.fault_point,
+            timeouts: self.timeouts,
+            init_report: self.init_report.clone(),
+            idmapped_mounts,
         };
 
-        let (init_pid, need_to_clean_up_intel_rdt_dir) =
+        let (init_pid, need_to_clean_up_intel_rdt_dir, cgroup_namespace_roots) =
             process::container_main_process::container_main_process(&container_args).map_err(
                 |err| {
                     tracing::error!("failed to run container process {}", err);
                     LibcontainerError::MainProcess(err)
                 },
             )?;
+        // From here on the process of the container is running, it has to be
+        // killed if anything else fails.
+        let namespace_guard = NamespaceGuard::new(init_pid);
 
         // if file to write the pid to is specified, write pid of the child
         if let Some(pid_file) = &self.pid_file {
@@ -193,33 +250,29 @@ impl ContainerBuilderImpl {
             })?;
         }
 
-        if let Some(container) = &mut self.container {
+        // a tenant leaves the state of the container it joined alone
+        let is_init_container = self.is_init_container();
+        if let Some(container) = self.container.as_mut().filter(|_| is_init_container) {
             // update status and pid of the container process
             container
                 .set_status(ContainerStatus::Created)
                 .set_creator(nix::unistd::geteuid().as_raw())
                 .set_pid(init_pid.as_raw())
-                .set_clean_up_intel_rdt_directory(need_to_clean_up_intel_rdt_dir)
-                .save()?;
+                .set_clean_up_intel_rdt_directory(need_to_clean_up_intel_rdt_dir);
+            if let Some(roots) = cgroup_namespace_roots {
+                container.set_cgroup_namespace_roots(roots);
+            }
+            container.save()?;
         }
 
+        namespace_guard.commit();
         Ok(init_pid)
     }
 
-    fn cleanup_container(&self) -> Result<(), LibcontainerError> {
-        let linux = self.spec.linux().as_ref().ok_or(MissingSpecError::Linux)?;
-        let cgroups_path = utils::get_cgroup_path(linux.cgroups_path(), &self.container_id);
-        let cmanager =
-            libcgroups::common::create_cgroup_manager(libcgroups::common::CgroupConfig {
-                cgroup_path: cgroups_path,
-                systemd_cgroup: self.use_systemd || self.user_ns_config.is_some(),
-                container_name: self.container_id.to_string(),
-            })?;
-
+    fn cleanup_container(&self, cgroup_guard: CgroupGuard) -> Result<(), LibcontainerError> {
         let mut errors = Vec::new();
 
-        if let Err(e) = cmanager.remove() {
-            tracing::error!(error = ?e, "failed to remove cgroup manager");
+        if let Err(e) = cgroup_guard.remove() {
             errors.push(e.to_string());
         }
 
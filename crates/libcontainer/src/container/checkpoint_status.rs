@@ -0,0 +1,214 @@
+//! Progress reporting of a checkpoint to a file descriptor of the caller.
+//! Every event is written as one line of JSON, with a source telling the
+//! caller whether it comes from CRIU or from youki:
+//!
+//! ```text
+//! {"source":"criu","type":"progress","message":"(00.004) Dumping pid 42"}
+//! {"source":"criu","type":"error","message":"dump failed: ..."}
+//! {"source":"youki","type":"done"}
+//! ```
+//!
+//! Progress events are the lines CRIU writes to its log while dumping.
+use std::fs::File;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::io::{FromRawFd, RawFd};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use serde_json::json;
+
+use super::CheckpointError;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(super) enum StatusSource {
+    Criu,
+    Youki,
+}
+
+impl StatusSource {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Criu => "criu",
+            Self::Youki => "youki",
+        }
+    }
+}
+
+pub(super) struct StatusWriter {
+    file: File,
+}
+
+impl StatusWriter {
+    /// Writes to a duplicate of `fd`, which stays owned by the caller
+    pub fn new(fd: RawFd) -> Result<Self, CheckpointError> {
+        let fd = nix::unistd::dup(fd).map_err(|err| {
+            tracing::error!(?err, fd, "invalid checkpoint status fd");
+            CheckpointError::StatusFd(err.into())
+        })?;
+        // SAFETY: the fd was just duplicated and is not owned by anything else
+        let file = unsafe { File::from_raw_fd(fd) };
+
+        Ok(Self { file })
+    }
+
+    pub fn try_clone(&self) -> Result<Self, CheckpointError> {
+        let file = self.file.try_clone().map_err(|err| {
+            tracing::error!(?err, "failed to duplicate checkpoint status fd");
+            CheckpointError::StatusFd(err)
+        })?;
+
+        Ok(Self { file })
+    }
+
+    pub fn progress(&mut self, source: StatusSource, message: &str) {
+        self.write(json!({
+            "source": source.as_str(),
+            "type": "progress",
+            "message": message,
+        }));
+    }
+
+    pub fn error(&mut self, source: StatusSource, message: &str) {
+        self.write(json!({
+            "source": source.as_str(),
+            "type": "error",
+            "message": message,
+        }));
+    }
+
+    pub fn done(&mut self) {
+        self.write(json!({
+            "source": StatusSource::Youki.as_str(),
+            "type": "done",
+        }));
+    }
+
+    // Reporting is best effort, a caller which stopped reading must not
+    // make the checkpoint fail.
+    fn write(&mut self, event: serde_json::Value) {
+        if let Err(err) = writeln!(self.file, "{event}") {
+            tracing::warn!(?err, "failed to write checkpoint status");
+        }
+    }
+}
+
+/// Forwards the lines CRIU writes to its log file as progress events until
+/// stopped
+pub(super) struct LogFollower {
+    stop: Arc<AtomicBool>,
+    handle: JoinHandle<()>,
+}
+
+impl LogFollower {
+    pub fn start(log_path: PathBuf, writer: StatusWriter) -> Self {
+        let stop = Arc::new(AtomicBool::new(false));
+        let handle = {
+            let stop = stop.clone();
+            thread::spawn(move || follow(log_path, writer, stop))
+        };
+
+        Self { stop, handle }
+    }
+
+    /// Stops following once everything written so far was forwarded
+    pub fn stop(self) {
+        self.stop.store(true, Ordering::Release);
+        if self.handle.join().is_err() {
+            tracing::warn!("checkpoint log follower panicked");
+        }
+    }
+}
+
+fn follow(log_path: PathBuf, mut writer: StatusWriter, stop: Arc<AtomicBool>) {
+    let mut reader: Option<BufReader<File>> = None;
+    let mut line = String::new();
+
+    loop {
+        // Read the flag first, so that the last pass sees everything CRIU
+        // wrote before it was set.
+        let stopping = stop.load(Ordering::Acquire);
+        if reader.is_none() {
+            // CRIU only creates the log once it started
+            reader = File::open(&log_path).ok().map(BufReader::new);
+        }
+        if let Some(reader) = reader.as_mut() {
+            while let Ok(n) = reader.read_line(&mut line) {
+                // a line without a newline is still being written
+                if n == 0 || !line.ends_with('\n') {
+                    break;
+                }
+                writer.progress(StatusSource::Criu, line.trim_end());
+                line.clear();
+            }
+        }
+
+        if stopping {
+            break;
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+
+    if !line.is_empty() {
+        writer.progress(StatusSource::Criu, line.trim_end());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+    use std::os::unix::io::AsRawFd;
+
+    use anyhow::Result;
+    use serde_json::Value;
+
+    use super::*;
+
+    fn read_events(mut reader: File) -> Result<Vec<Value>> {
+        let mut out = String::new();
+        reader.read_to_string(&mut out)?;
+        Ok(out
+            .lines()
+            .map(serde_json::from_str)
+            .collect::<serde_json::Result<_>>()?)
+    }
+
+    #[test]
+    fn test_follow_criu_log() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let log_path = tmp.path().join("dump.log");
+        let (read_end, write_end) = nix::unistd::pipe()?;
+
+        let mut writer = StatusWriter::new(write_end.as_raw_fd())?;
+        drop(write_end);
+        let follower = LogFollower::start(log_path.clone(), writer.try_clone()?);
+        std::fs::write(&log_path, "(00.001) Dumping pid 42\n(00.002) Writing image")?;
+        follower.stop();
+        writer.error(StatusSource::Criu, "dump failed");
+        drop(writer);
+
+        let events = read_events(File::from(read_end))?;
+        assert_eq!(
+            events,
+            vec![
+                json!({"source": "criu", "type": "progress", "message": "(00.001) Dumping pid 42"}),
+                json!({"source": "criu", "type": "progress", "message": "(00.002) Writing image"}),
+                json!({"source": "criu", "type": "error", "message": "dump failed"}),
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_status_fd() {
+        assert!(matches!(
+            StatusWriter::new(-1),
+            Err(CheckpointError::StatusFd(_))
+        ));
+    }
+}
@@ -1,15 +1,24 @@
 use std::collections::HashMap;
 use std::ffi::OsString;
 use std::fs;
+use std::os::unix::io::RawFd;
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
 use chrono::{DateTime, Utc};
+use libcgroups::common::CgroupHost;
+use libcgroups::namespace::{self, CgroupPaths, ProcessCgroup};
 use nix::unistd::Pid;
+use oci_spec::runtime::Spec;
 use procfs::process::Process;
 
-use crate::config::YoukiConfig;
-use crate::container::{ContainerStatus, State};
+use crate::config::{self, YoukiConfig};
+use crate::container::{Confinement, ContainerStatus, ExecProcess, State};
 use crate::error::LibcontainerError;
+use crate::health::Health;
+use crate::notify_socket::StartNotifier;
+use crate::process::parent_death::ParentDeath;
+use crate::seccomp_broker::{self, SeccompAgent};
 use crate::syscall::syscall::create_syscall;
 
 /// Structure representing the container data
@@ -19,6 +28,11 @@ pub struct Container {
     pub state: State,
     // indicated the directory for the root path in the container
     pub root: PathBuf,
+    // starts the container instead of the notify socket, if it was created
+    // with StartHandshake::SocketPair
+    pub(crate) start_notifier: Option<Arc<StartNotifier>>,
+    // the host as examined before, for the cgroup managers of the container
+    pub(crate) cgroup_host: CgroupHost,
 }
 
 impl Default for Container {
@@ -26,6 +40,8 @@ impl Default for Container {
         Self {
             state: State::default(),
             root: PathBuf::from("/run/youki"),
+            start_notifier: None,
+            cgroup_host: CgroupHost::default(),
         }
     }
 }
@@ -51,6 +67,8 @@ impl Container {
         Ok(Self {
             state,
             root: container_root,
+            start_notifier: None,
+            cgroup_host: CgroupHost::default(),
         })
     }
 
@@ -139,6 +157,94 @@ impl Container {
         self.state.clean_up_intel_rdt_subdirectory
     }
 
+    pub fn set_paused_by_signal(&mut self, paused_by_signal: bool) -> &mut Self {
+        self.state.paused_by_signal = paused_by_signal.then_some(true);
+        self
+    }
+
+    pub fn paused_by_signal(&self) -> bool {
+        self.state.paused_by_signal.unwrap_or_default()
+    }
+
+    pub fn set_parent_death(&mut self, parent_death: ParentDeath) -> &mut Self {
+        self.state.parent_death = (!parent_death.is_default()).then_some(parent_death);
+        self
+    }
+
+    pub fn parent_death(&self) -> ParentDeath {
+        self.state.parent_death.unwrap_or_default()
+    }
+
+    pub fn set_confinement(&mut self, confinement: Confinement) -> &mut Self {
+        self.state.confinement = Some(confinement);
+        self
+    }
+
+    /// Records a process executed in the container and forgets the ones
+    /// which exited in the meantime
+    pub fn add_exec_process(&mut self, process: ExecProcess) -> &mut Self {
+        self.state.exec_processes.retain(ExecProcess::is_running);
+        self.state.exec_processes.push(process);
+        self
+    }
+
+    /// Refers to the exec history of the container from its state
+    pub fn set_exec_history(&mut self, path: PathBuf) -> &mut Self {
+        self.state.exec_history = Some(path);
+        self
+    }
+
+    /// Processes executed in the container which are still running
+    pub fn exec_processes(&self) -> Vec<ExecProcess> {
+        self.state
+            .exec_processes
+            .iter()
+            .filter(|process| process.is_running())
+            .cloned()
+            .collect()
+    }
+
+    /// Seccomp agent of the container, if it has a seccomp broker
+    pub fn seccomp_agent(&self) -> Option<SeccompAgent> {
+        seccomp_broker::agent(&self.root)
+    }
+
+    /// Records the cgroups of the init process as the roots of the cgroup
+    /// namespace it creates
+    pub fn set_cgroup_namespace_roots(&mut self, roots: Vec<ProcessCgroup>) -> &mut Self {
+        self.state.cgroup_namespace_roots = Some(roots);
+        self
+    }
+
+    /// Cgroups of the init process, with their paths in the cgroup namespace
+    /// of the container if it has one. Empty if the init process is gone.
+    pub fn cgroup_paths(&self) -> Result<Vec<CgroupPaths>, LibcontainerError> {
+        let pid = match self.pid() {
+            Some(pid) if self.status() != ContainerStatus::Stopped => pid,
+            _ => return Ok(Vec::new()),
+        };
+        let current = match namespace::process_cgroups(pid.as_raw()) {
+            Ok(current) => current,
+            // the init process exited since the status was refreshed
+            Err(_) if Process::new(pid.as_raw()).is_err() => return Ok(Vec::new()),
+            Err(err) => return Err(err.into()),
+        };
+
+        Ok(namespace::cgroup_paths(
+            &current,
+            self.state.cgroup_namespace_roots.as_deref(),
+        ))
+    }
+
+    pub fn set_health(&mut self, health: Health) -> &mut Self {
+        self.state.health = Some(health);
+        self
+    }
+
+    pub fn health(&self) -> Option<&Health> {
+        self.state.health.as_ref()
+    }
+
     pub fn status(&self) -> ContainerStatus {
         self.state.status
     }
@@ -196,11 +302,20 @@ impl Container {
         let mut container = Self {
             state,
             root: container_root,
+            start_notifier: None,
+            cgroup_host: CgroupHost::default(),
         };
         container.refresh_status()?;
         Ok(container)
     }
 
+    /// Sets the host the cgroup managers of the container use instead of
+    /// examining it themselves
+    pub fn with_cgroup_host(mut self, cgroup_host: CgroupHost) -> Self {
+        self.cgroup_host = cgroup_host;
+        self
+    }
+
     pub fn save(&self) -> Result<(), LibcontainerError> {
         tracing::debug!("Save container status: {:?} in {:?}", self, self.root);
         self.state.save(&self.root)?;
@@ -208,10 +323,33 @@ impl Container {
         Ok(())
     }
 
+    /// Changes the state with `update` and saves it while holding the lock of
+    /// the state file. The state is reloaded first, so that what other
+    /// processes saved since the container was loaded is not lost.
+    pub fn update_state<F: FnOnce(&mut Self)>(
+        &mut self,
+        update: F,
+    ) -> Result<(), LibcontainerError> {
+        let _lock = State::lock(&self.root)?;
+        self.refresh_state()?;
+        update(self);
+        self.save()
+    }
+
     pub fn spec(&self) -> Result<YoukiConfig, LibcontainerError> {
         let spec = YoukiConfig::load(&self.root)?;
         Ok(spec)
     }
+
+    /// The spec the container was created with, including what youki applied
+    /// to the spec of the bundle. Falls back to the spec of the bundle for
+    /// containers created before youki saved it.
+    pub fn effective_spec(&self) -> Result<Spec, LibcontainerError> {
+        match config::load_effective_spec(&self.root)? {
+            Some(spec) => Ok(spec),
+            None => Ok(Spec::load(self.bundle().join("config.json"))?),
+        }
+    }
 }
 
 /// Checkpoint parameter structure
@@ -223,6 +361,9 @@ pub struct CheckpointOptions {
     pub shell_job: bool,
     pub tcp_established: bool,
     pub work_path: Option<PathBuf>,
+    /// Fd to which the progress and the outcome of the checkpoint are
+    /// reported as lines of JSON
+    pub status_fd: Option<RawFd>,
 }
 
 #[cfg(test)]
@@ -325,11 +466,36 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_update_state() -> Result<()> {
+        let tmp_dir = tempfile::tempdir().unwrap();
+        let mut container_1 = Container::new(
+            "container_id_1",
+            ContainerStatus::Created,
+            None,
+            &PathBuf::from("."),
+            tmp_dir.path(),
+        )?;
+        container_1.save()?;
+
+        // another process records something after the container was loaded
+        let mut container_2 = container_1.clone();
+        container_2.set_systemd(true).save()?;
+
+        container_1.update_state(|container| {
+            container.set_status(ContainerStatus::Running);
+        })?;
+        let saved = State::load(tmp_dir.path())?;
+        assert_eq!(saved.status, ContainerStatus::Running);
+        assert!(saved.use_systemd);
+
+        Ok(())
+    }
+
     #[test]
     #[serial]
     fn test_get_spec() -> Result<()> {
         let tmp_dir = tempfile::tempdir().unwrap();
-        use oci_spec::runtime::Spec;
         let spec = Spec::default();
         let config = YoukiConfig::from_spec(&spec, "123").context("convert spec to config")?;
         config.save(tmp_dir.path()).context("save config")?;
@@ -343,6 +509,29 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_get_effective_spec() -> Result<()> {
+        let bundle = tempfile::tempdir().unwrap();
+        let root = tempfile::tempdir().unwrap();
+        let bundle_spec = Spec::default();
+        bundle_spec.save(bundle.path().join("config.json"))?;
+        let container = Container::new(
+            "container_id",
+            ContainerStatus::Created,
+            None,
+            bundle.path(),
+            root.path(),
+        )?;
+        assert_eq!(container.effective_spec()?, bundle_spec);
+
+        let mut spec = Spec::default();
+        spec.set_hostname(Some("effective".into()));
+        config::save_effective_spec(&spec, root.path())?;
+        assert_eq!(container.effective_spec()?, spec);
+
+        Ok(())
+    }
+
     #[test]
     #[serial]
     fn test_get_set_refresh_status() -> Result<()> {
@@ -1,27 +1,84 @@
 use std::fs::{self, File};
 use std::io::Write;
 use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
 
+use libcgroups::common::CgroupManager;
 use libcgroups::common::CgroupSetup::{Hybrid, Legacy};
 #[cfg(feature = "v1")]
 use libcgroups::common::DEFAULT_CGROUP_ROOT;
-use oci_spec::runtime::Spec;
+use oci_spec::runtime::{LinuxMemoryBuilder, LinuxResourcesBuilder, Spec};
+use procfs::{Current, Meminfo};
+use serde::{Deserialize, Serialize};
 
+use super::checkpoint_status::{LogFollower, StatusSource, StatusWriter};
 use super::{Container, ContainerStatus};
 use crate::container::container::CheckpointOptions;
 use crate::error::LibcontainerError;
 
 const CRIU_CHECKPOINT_LOG_FILE: &str = "dump.log";
 const DESCRIPTORS_JSON: &str = "descriptors.json";
+const INVENTORY_IMG: &str = "inventory.img";
+const MEMORY_JSON: &str = "memory.json";
 
 #[derive(thiserror::Error, Debug)]
 pub enum CheckpointError {
     #[error("criu error: {0}")]
     CriuError(String),
+    #[error("checkpoint image is missing {0:?}")]
+    MissingImage(PathBuf),
+    #[error("checkpoint image has invalid descriptors in {0:?}")]
+    InvalidDescriptors(PathBuf),
+    #[error("failed to use checkpoint status fd")]
+    StatusFd(#[source] std::io::Error),
+    #[error("checkpoint image has an invalid memory footprint in {0:?}")]
+    InvalidMemoryFootprint(PathBuf),
+    #[error("failed to read the available memory of the host")]
+    HostMemory(#[source] procfs::ProcError),
+    #[error("restoring needs {required} bytes of memory, but the host has only {available} bytes available")]
+    InsufficientMemory { required: u64, available: u64 },
+    #[error("restoring needs {required} bytes of memory, but the memory limit of the container is {limit} bytes")]
+    MemoryLimitTooLow { required: u64, limit: i64 },
+    #[error("failed to adjust the memory limit of the container")]
+    AdjustMemoryLimit(#[source] oci_spec::OciSpecError),
+}
+
+/// Memory the container used when it was checkpointed, which it needs again
+/// right after it is restored. Recorded as `memory.json` in the image and
+/// checked by [`check_restore_memory`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MemoryFootprint {
+    /// Memory usage of the container cgroup in bytes
+    pub usage: u64,
+    /// Memory limit of the container in its spec, if any
+    pub limit: Option<i64>,
 }
 
 impl Container {
+    /// Checkpoints the container with CRIU. If a status fd is given, the
+    /// progress of CRIU and the outcome are reported to it.
     pub fn checkpoint(&mut self, opts: &CheckpointOptions) -> Result<(), LibcontainerError> {
+        let mut status = opts.status_fd.map(StatusWriter::new).transpose()?;
+        let result = self.dump(opts, status.as_ref());
+
+        if let Some(status) = status.as_mut() {
+            match &result {
+                Ok(()) => status.done(),
+                Err(LibcontainerError::Checkpoint(CheckpointError::CriuError(msg))) => {
+                    status.error(StatusSource::Criu, msg)
+                }
+                Err(err) => status.error(StatusSource::Youki, &err.to_string()),
+            }
+        }
+
+        result
+    }
+
+    fn dump(
+        &mut self,
+        opts: &CheckpointOptions,
+        status: Option<&StatusWriter>,
+    ) -> Result<(), LibcontainerError> {
         self.refresh_status()?;
 
         // can_pause() checks if the container is running. That also works for
@@ -100,6 +157,13 @@ impl Container {
             work_dir = std::fs::File::open(wp).map_err(LibcontainerError::OtherIO)?;
             criu.set_work_dir_fd(work_dir.as_raw_fd());
         }
+        // CRIU writes its log to the work directory, which defaults to the
+        // image directory
+        let log_path = opts
+            .work_path
+            .as_ref()
+            .unwrap_or(&opts.image_path)
+            .join(CRIU_CHECKPOINT_LOG_FILE);
 
         let pid: i32 = self
             .pid()
@@ -119,6 +183,8 @@ impl Container {
             };
             descriptors.push(link_path);
         }
+        self.record_memory_footprint(&spec, &opts.image_path);
+
         let descriptors_json_path = opts.image_path.join(DESCRIPTORS_JSON);
         let mut descriptors_json =
             File::create(descriptors_json_path).map_err(LibcontainerError::OtherIO)?;
@@ -147,16 +213,346 @@ impl Container {
                 .unwrap(),
         );
 
-        criu.dump().map_err(|err| {
-            tracing::error!(?err, id = ?self.id(), logfile = ?opts.image_path.join(CRIU_CHECKPOINT_LOG_FILE), "checkpointing container failed");
-            LibcontainerError::Other(err.to_string())
+        let follower = match status {
+            Some(status) => {
+                // a log left over from an earlier checkpoint would be
+                // reported as progress of this one
+                if let Err(err) = fs::remove_file(&log_path) {
+                    if err.kind() != std::io::ErrorKind::NotFound {
+                        tracing::error!(?err, ?log_path, "failed to remove old criu log");
+                        return Err(LibcontainerError::OtherIO(err));
+                    }
+                }
+                Some(LogFollower::start(log_path.clone(), status.try_clone()?))
+            }
+            None => None,
+        };
+        let dumped = criu.dump();
+        if let Some(follower) = follower {
+            follower.stop();
+        }
+        dumped.map_err(|err| {
+            tracing::error!(?err, id = ?self.id(), logfile = ?log_path, "checkpointing container failed");
+            CheckpointError::CriuError(format!("dump failed: {err}"))
         })?;
 
+        validate_image(&opts.image_path)?;
+
         if !opts.leave_running {
-            self.set_status(ContainerStatus::Stopped).save()?;
+            self.update_state(|container| {
+                container.set_status(ContainerStatus::Stopped);
+            })?;
         }
 
         tracing::debug!("container {} checkpointed", self.id());
         Ok(())
     }
+
+    /// Writes the memory footprint of the container to the image. Images
+    /// without it can still be restored, only without the memory checks, so
+    /// failing to record it does not fail the checkpoint.
+    fn record_memory_footprint(&self, spec: &Spec, image_path: &Path) {
+        let path = image_path.join(MEMORY_JSON);
+        let recorded = self.memory_footprint(spec).and_then(|footprint| {
+            let content =
+                serde_json::to_vec(&footprint).map_err(LibcontainerError::OtherSerialization)?;
+            fs::write(&path, content).map_err(LibcontainerError::OtherIO)
+        });
+        if let Err(err) = recorded {
+            tracing::warn!(?err, ?path, "failed to record memory footprint");
+        }
+    }
+
+    fn memory_footprint(&self, spec: &Spec) -> Result<MemoryFootprint, LibcontainerError> {
+        let cgroup_manager =
+            libcgroups::common::create_cgroup_manager(libcgroups::common::CgroupConfig {
+                cgroup_path: self.spec()?.cgroup_path,
+                systemd_cgroup: self.systemd(),
+                container_name: self.id().to_string(),
+                init_leaf: false,
+                host: self.cgroup_host.clone(),
+                state_root: None,
+            })?;
+        let stats = cgroup_manager.stats()?;
+
+        Ok(MemoryFootprint {
+            usage: stats.memory.memory.usage,
+            limit: memory_limit(spec),
+        })
+    }
+}
+
+fn memory_limit(spec: &Spec) -> Option<i64> {
+    spec.linux()
+        .as_ref()
+        .and_then(|linux| linux.resources().as_ref())
+        .and_then(|resources| resources.memory().as_ref())
+        .and_then(|memory| memory.limit())
+        .filter(|limit| *limit >= 0)
+}
+
+/// Checks before a restore that the host has enough memory available for the
+/// memory footprint recorded in the checkpoint image, and that the memory
+/// limit of the spec the container is restored with is not below it.
+///
+/// With `adjust_limit` a limit that is too low is raised in the spec instead,
+/// to the limit recorded with the checkpoint or else to the footprint. Images
+/// without a recorded footprint are not checked.
+pub fn check_restore_memory(
+    image_path: &Path,
+    spec: &mut Spec,
+    adjust_limit: bool,
+) -> Result<(), CheckpointError> {
+    let path = image_path.join(MEMORY_JSON);
+    let footprint = match fs::read(&path) {
+        Ok(content) => serde_json::from_slice::<MemoryFootprint>(&content).map_err(|err| {
+            tracing::error!(?err, ?path, "failed to parse memory footprint");
+            CheckpointError::InvalidMemoryFootprint(path.clone())
+        })?,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+            tracing::debug!(
+                ?path,
+                "checkpoint has no memory footprint, skipping memory checks"
+            );
+            return Ok(());
+        }
+        Err(err) => {
+            tracing::error!(?err, ?path, "failed to read memory footprint");
+            return Err(CheckpointError::InvalidMemoryFootprint(path));
+        }
+    };
+
+    let meminfo = Meminfo::current().map_err(|err| {
+        tracing::error!(?err, "failed to read meminfo");
+        CheckpointError::HostMemory(err)
+    })?;
+    let available = meminfo.mem_available.unwrap_or(meminfo.mem_free);
+
+    check_memory(&footprint, available, spec, adjust_limit)
+}
+
+fn check_memory(
+    footprint: &MemoryFootprint,
+    available: u64,
+    spec: &mut Spec,
+    adjust_limit: bool,
+) -> Result<(), CheckpointError> {
+    if footprint.usage > available {
+        tracing::error!(
+            ?footprint,
+            available,
+            "not enough memory to restore container"
+        );
+        return Err(CheckpointError::InsufficientMemory {
+            required: footprint.usage,
+            available,
+        });
+    }
+
+    let limit = match memory_limit(spec) {
+        Some(limit) if (limit as u64) < footprint.usage => limit,
+        _ => return Ok(()),
+    };
+    if !adjust_limit {
+        tracing::error!(
+            ?footprint,
+            limit,
+            "memory limit is below the memory footprint"
+        );
+        return Err(CheckpointError::MemoryLimitTooLow {
+            required: footprint.usage,
+            limit,
+        });
+    }
+
+    let adjusted = footprint
+        .limit
+        .filter(|recorded| *recorded as u64 >= footprint.usage)
+        .unwrap_or(footprint.usage as i64);
+    tracing::warn!(
+        limit,
+        adjusted,
+        "raising memory limit to restore the container"
+    );
+
+    let mut linux = spec.linux().clone().unwrap_or_default();
+    let mut resources = match linux.resources().clone() {
+        Some(resources) => resources,
+        None => LinuxResourcesBuilder::default()
+            .build()
+            .map_err(CheckpointError::AdjustMemoryLimit)?,
+    };
+    // LinuxMemory has no setters, rebuild it with the other values kept
+    let memory = resources.memory().unwrap_or_default();
+    let mut builder = LinuxMemoryBuilder::default().limit(adjusted);
+    if let Some(reservation) = memory.reservation() {
+        builder = builder.reservation(reservation);
+    }
+    if let Some(swap) = memory.swap() {
+        builder = builder.swap(swap);
+    }
+    if let Some(kernel) = memory.kernel() {
+        builder = builder.kernel(kernel);
+    }
+    if let Some(kernel_tcp) = memory.kernel_tcp() {
+        builder = builder.kernel_tcp(kernel_tcp);
+    }
+    if let Some(swappiness) = memory.swappiness() {
+        builder = builder.swappiness(swappiness);
+    }
+    if let Some(disable_oom_killer) = memory.disable_oom_killer() {
+        builder = builder.disable_oom_killer(disable_oom_killer);
+    }
+    if let Some(use_hierarchy) = memory.use_hierarchy() {
+        builder = builder.use_hierarchy(use_hierarchy);
+    }
+    if let Some(check_before_update) = memory.check_before_update() {
+        builder = builder.check_before_update(check_before_update);
+    }
+    resources.set_memory(Some(
+        builder
+            .build()
+            .map_err(CheckpointError::AdjustMemoryLimit)?,
+    ));
+    linux.set_resources(Some(resources));
+    spec.set_linux(Some(linux));
+
+    Ok(())
+}
+
+/// Checks that a dump produced the files a restore needs
+fn validate_image(image_path: &Path) -> Result<(), CheckpointError> {
+    let inventory = image_path.join(INVENTORY_IMG);
+    if !fs::metadata(&inventory).map_or(false, |m| m.is_file() && m.len() > 0) {
+        tracing::error!(?inventory, "checkpoint image has no inventory");
+        return Err(CheckpointError::MissingImage(inventory));
+    }
+
+    let descriptors_path = image_path.join(DESCRIPTORS_JSON);
+    let content = fs::read(&descriptors_path).map_err(|err| {
+        tracing::error!(?err, ?descriptors_path, "failed to read descriptors");
+        CheckpointError::MissingImage(descriptors_path.clone())
+    })?;
+    match serde_json::from_slice::<Vec<String>>(&content) {
+        Ok(descriptors) if descriptors.len() == 3 => Ok(()),
+        _ => {
+            tracing::error!(
+                ?descriptors_path,
+                "descriptors are not the three stdio paths"
+            );
+            Err(CheckpointError::InvalidDescriptors(descriptors_path))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+
+    use super::*;
+
+    #[test]
+    fn test_validate_image() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let image_path = tmp.path();
+        assert!(matches!(
+            validate_image(image_path),
+            Err(CheckpointError::MissingImage(path)) if path.ends_with(INVENTORY_IMG)
+        ));
+
+        fs::write(image_path.join(INVENTORY_IMG), b"\x54\x56\x51\x58")?;
+        assert!(matches!(
+            validate_image(image_path),
+            Err(CheckpointError::MissingImage(path)) if path.ends_with(DESCRIPTORS_JSON)
+        ));
+
+        fs::write(image_path.join(DESCRIPTORS_JSON), r#"["/dev/null"]"#)?;
+        assert!(matches!(
+            validate_image(image_path),
+            Err(CheckpointError::InvalidDescriptors(_))
+        ));
+
+        fs::write(
+            image_path.join(DESCRIPTORS_JSON),
+            r#"["/dev/null","pipe:[1]","pipe:[2]"]"#,
+        )?;
+        assert!(validate_image(image_path).is_ok());
+
+        Ok(())
+    }
+
+    fn spec_with_limit(limit: i64) -> Result<Spec> {
+        let mut spec = Spec::default();
+        let mut linux = spec.linux().clone().unwrap();
+        linux.set_resources(Some(
+            LinuxResourcesBuilder::default()
+                .memory(LinuxMemoryBuilder::default().limit(limit).build()?)
+                .build()?,
+        ));
+        spec.set_linux(Some(linux));
+        Ok(spec)
+    }
+
+    #[test]
+    fn test_memory_limit() -> Result<()> {
+        assert_eq!(memory_limit(&Spec::default()), None);
+        assert_eq!(memory_limit(&spec_with_limit(-1)?), None);
+        assert_eq!(memory_limit(&spec_with_limit(8192)?), Some(8192));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_memory() -> Result<()> {
+        let footprint = MemoryFootprint {
+            usage: 4096,
+            limit: Some(8192),
+        };
+
+        let mut spec = spec_with_limit(-1)?;
+        assert!(check_memory(&footprint, 8192, &mut spec, false).is_ok());
+        assert!(matches!(
+            check_memory(&footprint, 1024, &mut spec, true),
+            Err(CheckpointError::InsufficientMemory {
+                required: 4096,
+                available: 1024
+            })
+        ));
+
+        let mut spec = spec_with_limit(2048)?;
+        assert!(matches!(
+            check_memory(&footprint, 8192, &mut spec, false),
+            Err(CheckpointError::MemoryLimitTooLow {
+                required: 4096,
+                limit: 2048
+            })
+        ));
+        check_memory(&footprint, 8192, &mut spec, true)?;
+        assert_eq!(memory_limit(&spec), Some(8192));
+
+        let footprint = MemoryFootprint {
+            usage: 4096,
+            limit: None,
+        };
+        let mut spec = spec_with_limit(2048)?;
+        check_memory(&footprint, 8192, &mut spec, true)?;
+        assert_eq!(memory_limit(&spec), Some(4096));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_restore_memory_without_footprint() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let mut spec = spec_with_limit(1)?;
+        check_restore_memory(tmp.path(), &mut spec, false)?;
+
+        fs::write(tmp.path().join(MEMORY_JSON), "{")?;
+        assert!(matches!(
+            check_restore_memory(tmp.path(), &mut spec, false),
+            Err(CheckpointError::InvalidMemoryFootprint(_))
+        ));
+
+        Ok(())
+    }
 }
@@ -1,14 +1,16 @@
 use std::fs;
+use std::path::PathBuf;
 
 use libcgroups::common::CgroupManager;
 use libcgroups::{self};
 use nix::sys::signal;
 
-use super::{Container, ContainerStatus};
+use super::{finished, Container, ContainerStatus};
 use crate::config::YoukiConfig;
 use crate::error::LibcontainerError;
-use crate::hooks;
+use crate::hooks::{self, LifecyclePoint};
 use crate::process::intel_rdt::delete_resctrl_subdirectory;
+use crate::{propagation, seccomp_broker};
 
 impl Container {
     /// Deletes the container
@@ -32,6 +34,27 @@ impl Container {
     /// # }
     /// ```
     pub fn delete(&mut self, force: bool) -> Result<(), LibcontainerError> {
+        self.delete_impl(force, None).map(|_| ())
+    }
+
+    /// Deletes the container like [`Container::delete`], but keeps its
+    /// state, its spec and the exit status of its process in the finished
+    /// directory of the root path for a postmortem, see
+    /// [`finished`](super::finished). Returns the directory of the record,
+    /// none if the container had no state directory left.
+    pub fn delete_and_keep(
+        &mut self,
+        force: bool,
+        exit_status: Option<i32>,
+    ) -> Result<Option<PathBuf>, LibcontainerError> {
+        self.delete_impl(force, Some(exit_status))
+    }
+
+    fn delete_impl(
+        &mut self,
+        force: bool,
+        keep: Option<Option<i32>>,
+    ) -> Result<Option<PathBuf>, LibcontainerError> {
         self.refresh_status()?;
 
         tracing::debug!("container status: {:?}", self.status());
@@ -46,7 +69,9 @@ impl Container {
                 // `runc` and `crun` allows deleting `created`. Therefore we
                 // decided to follow `runc` and `crun`.
                 self.do_kill(signal::Signal::SIGKILL, true)?;
-                self.set_status(ContainerStatus::Stopped).save()?;
+                self.update_state(|container| {
+                    container.set_status(ContainerStatus::Stopped);
+                })?;
             }
             ContainerStatus::Creating | ContainerStatus::Running | ContainerStatus::Paused => {
                 // Containers can't be deleted while in these status, unless
@@ -54,7 +79,9 @@ impl Container {
                 // processes associated with containers.
                 if force {
                     self.do_kill(signal::Signal::SIGKILL, true)?;
-                    self.set_status(ContainerStatus::Stopped).save()?;
+                    self.update_state(|container| {
+                        container.set_status(ContainerStatus::Stopped);
+                    })?;
                 } else {
                     tracing::error!(
                         id = ?self.id(),
@@ -90,6 +117,9 @@ impl Container {
                             cgroup_path: config.cgroup_path.to_owned(),
                             systemd_cgroup: self.systemd(),
                             container_name: self.id().to_string(),
+                            init_leaf: false,
+                            host: self.cgroup_host.clone(),
+                            state_root: None,
                         },
                     )?;
                     cmanager.remove().map_err(|err| {
@@ -97,13 +127,29 @@ impl Container {
                         err
                     })?;
 
-                    if let Some(hooks) = config.hooks.as_ref() {
-                        hooks::run_hooks(hooks.poststop().as_ref(), Some(self), None).map_err(
-                            |err| {
-                                tracing::error!(err = ?err, "failed to run post stop hooks");
-                                err
-                            },
-                        )?;
+                    hooks::run_lifecycle_hooks(
+                        LifecyclePoint::Deleted,
+                        config.hooks.as_ref(),
+                        Some(self),
+                        None,
+                    )?;
+
+                    if let Some(helper) = &config.volume_helper {
+                        helper.release_all(&config.volumes, self.id());
+                    }
+                    if let Some(swap) = &config.swap {
+                        if let Err(err) = swap.release() {
+                            tracing::warn!(?err, ?swap, "failed to remove swap");
+                        }
+                    }
+                    if let Some(root_path) =
+                        self.root.parent().filter(|_| config.detach_stray_mounts)
+                    {
+                        if let Err(err) =
+                            propagation::detach_stray_mounts(root_path, &config.shared_mounts)
+                        {
+                            tracing::warn!(?err, "failed to detach stray mounts of shared mounts");
+                        }
                     }
                 }
                 Err(err) => {
@@ -117,14 +163,25 @@ impl Container {
                 }
             }
 
+            let finished = match keep {
+                Some(exit_status) => Some(finished::archive(self, exit_status).map_err(|err| {
+                    tracing::error!(?err, id = ?self.id(), "failed to keep finished container");
+                    err
+                })?),
+                None => None,
+            };
+
+            seccomp_broker::stop(&self.root);
+
             // remove the directory storing container state
             tracing::debug!("remove dir {:?}", self.root);
             fs::remove_dir_all(&self.root).map_err(|err| {
                 tracing::error!(?err, path = ?self.root, "failed to remove container dir");
                 LibcontainerError::OtherIO(err)
             })?;
+            return Ok(finished);
         }
 
-        Ok(())
+        Ok(None)
     }
 }
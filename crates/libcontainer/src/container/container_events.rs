@@ -39,6 +39,9 @@ impl Container {
                 cgroup_path: self.spec()?.cgroup_path,
                 systemd_cgroup: self.systemd(),
                 container_name: self.id().to_string(),
+                init_leaf: false,
+                host: self.cgroup_host.clone(),
+                state_root: None,
             })?;
         match stats {
             true => {
@@ -41,7 +41,9 @@ impl Container {
                 return Err(LibcontainerError::IncorrectStatus);
             }
         }
-        self.set_status(ContainerStatus::Stopped).save()?;
+        self.update_state(|container| {
+            container.set_status(ContainerStatus::Stopped);
+        })?;
         Ok(())
     }
 
@@ -87,6 +89,9 @@ impl Container {
                             cgroup_path: self.spec()?.cgroup_path,
                             systemd_cgroup: self.systemd(),
                             container_name: self.id().to_string(),
+                            init_leaf: false,
+                            host: self.cgroup_host.clone(),
+                            state_root: None,
                         },
                     )?;
                     cmanager.freeze(libcgroups::common::FreezerState::Thawed)?;
@@ -104,8 +109,26 @@ impl Container {
                 cgroup_path: self.spec()?.cgroup_path,
                 systemd_cgroup: self.systemd(),
                 container_name: self.id().to_string(),
+                init_leaf: false,
+                host: self.cgroup_host.clone(),
+                state_root: None,
             })?;
 
+        // cgroup.kill also kills the processes which are forked meanwhile,
+        // which a loop over the processes would miss
+        if signal == signal::Signal::SIGKILL {
+            match cmanager.kill() {
+                Ok(true) => {
+                    tracing::debug!(id = ?self.id(), "killed container with cgroup.kill");
+                    return Ok(());
+                }
+                Ok(false) => {}
+                Err(err) => {
+                    tracing::warn!(?err, id = ?self.id(), "failed to kill container with cgroup.kill");
+                }
+            }
+        }
+
         if let Err(e) = cmanager.freeze(libcgroups::common::FreezerState::Frozen) {
             tracing::warn!(
                 err = ?e,
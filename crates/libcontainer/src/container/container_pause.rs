@@ -1,4 +1,7 @@
-use libcgroups::common::{CgroupManager, FreezerState};
+use std::time::Duration;
+
+use libcgroups::common::{AnyCgroupManager, CgroupManager, FreezerState, DEFAULT_FREEZE_TIMEOUT};
+use nix::sys::signal::{self, Signal};
 
 use super::{Container, ContainerStatus};
 use crate::error::LibcontainerError;
@@ -25,6 +28,13 @@ impl Container {
     /// # }
     /// ```
     pub fn pause(&mut self) -> Result<(), LibcontainerError> {
+        self.pause_with_timeout(DEFAULT_FREEZE_TIMEOUT)
+    }
+
+    /// Suspends all processes within the container, waiting up to the
+    /// timeout for all of them to be frozen. If some do not freeze in time,
+    /// the container is thawed again and the error lists them.
+    pub fn pause_with_timeout(&mut self, timeout: Duration) -> Result<(), LibcontainerError> {
         self.refresh_status()?;
 
         if !self.can_pause() {
@@ -32,18 +42,85 @@ impl Container {
             return Err(LibcontainerError::IncorrectStatus);
         }
 
-        let cmanager =
-            libcgroups::common::create_cgroup_manager(libcgroups::common::CgroupConfig {
-                cgroup_path: self.spec()?.cgroup_path,
-                systemd_cgroup: self.systemd(),
-                container_name: self.id().to_string(),
-            })?;
-        cmanager.freeze(FreezerState::Frozen)?;
+        let cmanager = self.cgroup_manager()?;
+        let frozen = cmanager.freeze_with_timeout(FreezerState::Frozen, timeout);
+        frozen.map_err(|err| {
+            if err.is_freezer_unavailable() {
+                tracing::error!(id = ?self.id(), "cannot pause container without the freezer cgroup");
+                LibcontainerError::FreezerUnavailable
+            } else {
+                err.into()
+            }
+        })?;
 
         tracing::debug!("saving paused status");
-        self.set_status(ContainerStatus::Paused).save()?;
+        self.update_state(|container| {
+            container.set_status(ContainerStatus::Paused);
+        })?;
 
         tracing::debug!("container {} paused", self.id());
         Ok(())
     }
+
+    /// Suspends all processes within the container by sending them SIGSTOP.
+    ///
+    /// This is a fallback for hosts without the cgroup freezer. Unlike
+    /// freezing, the stop is observable by the container processes and
+    /// their parents, and processes forked while the signals are being
+    /// delivered may escape it. [`Container::resume`] sends SIGCONT to
+    /// containers paused this way.
+    pub fn pause_with_signal(&mut self) -> Result<(), LibcontainerError> {
+        self.refresh_status()?;
+
+        if !self.can_pause() {
+            tracing::error!(status = ?self.status(), id = ?self.id(), "cannot pause container");
+            return Err(LibcontainerError::IncorrectStatus);
+        }
+
+        let cmanager = self.cgroup_manager()?;
+        signal_all(&cmanager, Signal::SIGSTOP)?;
+
+        tracing::debug!("saving paused status");
+        self.update_state(|container| {
+            container
+                .set_paused_by_signal(true)
+                .set_status(ContainerStatus::Paused);
+        })?;
+
+        tracing::debug!("container {} paused with SIGSTOP", self.id());
+        Ok(())
+    }
+
+    pub(crate) fn cgroup_manager(&self) -> Result<AnyCgroupManager, LibcontainerError> {
+        Ok(libcgroups::common::create_cgroup_manager(
+            libcgroups::common::CgroupConfig {
+                cgroup_path: self.spec()?.cgroup_path,
+                systemd_cgroup: self.systemd(),
+                container_name: self.id().to_string(),
+                init_leaf: false,
+                host: self.cgroup_host.clone(),
+                state_root: None,
+            },
+        )?)
+    }
+}
+
+/// Sends the signal to every process in the cgroup, ignoring processes which
+/// exited in the meantime.
+pub(crate) fn signal_all(
+    cmanager: &AnyCgroupManager,
+    sig: Signal,
+) -> Result<(), LibcontainerError> {
+    for pid in cmanager.get_all_pids()? {
+        tracing::debug!("send {} to {}", sig, pid);
+        match signal::kill(pid, sig) {
+            Ok(()) | Err(nix::errno::Errno::ESRCH) => {}
+            Err(err) => {
+                tracing::error!(?err, ?pid, ?sig, "failed to signal process");
+                return Err(LibcontainerError::OtherSyscall(err));
+            }
+        }
+    }
+
+    Ok(())
 }
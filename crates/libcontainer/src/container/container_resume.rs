@@ -1,5 +1,8 @@
 use libcgroups::common::{CgroupManager, FreezerState};
 
+use nix::sys::signal::Signal;
+
+use super::container_pause::signal_all;
 use super::{Container, ContainerStatus};
 use crate::error::LibcontainerError;
 
@@ -33,17 +36,21 @@ impl Container {
             return Err(LibcontainerError::IncorrectStatus);
         }
 
-        let cmanager =
-            libcgroups::common::create_cgroup_manager(libcgroups::common::CgroupConfig {
-                cgroup_path: self.spec()?.cgroup_path,
-                systemd_cgroup: self.systemd(),
-                container_name: self.id().to_string(),
-            })?;
-        // resume the frozen container
-        cmanager.freeze(FreezerState::Thawed)?;
+        let cmanager = self.cgroup_manager()?;
+        if self.paused_by_signal() {
+            // the container was stopped with SIGSTOP because the freezer is unavailable
+            signal_all(&cmanager, Signal::SIGCONT)?;
+        } else {
+            // resume the frozen container
+            cmanager.freeze(FreezerState::Thawed)?;
+        }
 
         tracing::debug!("saving running status");
-        self.set_status(ContainerStatus::Running).save()?;
+        self.update_state(|container| {
+            container
+                .set_paused_by_signal(false)
+                .set_status(ContainerStatus::Running);
+        })?;
 
         tracing::debug!("container {} resumed", self.id());
         Ok(())
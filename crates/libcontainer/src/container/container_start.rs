@@ -1,10 +1,9 @@
-use nix::sys::signal;
-
 use super::{Container, ContainerStatus};
 use crate::config::YoukiConfig;
 use crate::error::LibcontainerError;
-use crate::hooks;
-use crate::notify_socket::{NotifySocket, NOTIFY_FILE};
+use crate::hooks::{self, HookError, LifecyclePoint};
+use crate::notify_socket::{NotifyListenerError, NotifySocket, NOTIFY_FILE};
+use crate::process::timeouts::{Deadline, Phase, Timeouts};
 
 impl Container {
     /// Starts a previously created container
@@ -28,6 +27,14 @@ impl Container {
     /// # }
     /// ```
     pub fn start(&mut self) -> Result<(), LibcontainerError> {
+        self.start_with_timeouts(&Timeouts::default())
+    }
+
+    /// Starts a previously created container like [`Self::start`]. The
+    /// [`Phase::Start`] timeout limits the whole start, and the
+    /// [`Phase::Hooks`] timeout the poststart hooks.
+    pub fn start_with_timeouts(&mut self, timeouts: &Timeouts) -> Result<(), LibcontainerError> {
+        let start_deadline = Deadline::begin(timeouts, Phase::Start, None);
         self.refresh_status()?;
 
         if !self.can_start() {
@@ -43,39 +50,42 @@ impl Container {
             );
             err
         })?;
-        if let Some(hooks) = config.hooks.as_ref() {
-            // While prestart is marked as deprecated in the OCI spec, the docker and integration test still
-            // uses it.
-            #[allow(deprecated)]
-            hooks::run_hooks(hooks.prestart().as_ref(), Some(self), None).map_err(|err| {
-                tracing::error!("failed to run pre start hooks: {}", err);
-                // In the case where prestart hook fails, the runtime must
-                // stop the container before generating an error and exiting.
-                let _ = self.kill(signal::Signal::SIGKILL, true);
-
-                err
-            })?;
+        let timeout = start_deadline.remaining();
+        let notified = match &self.start_notifier {
+            Some(notifier) => notifier.notify_container_start_within(timeout),
+            None => NotifySocket::new(self.root.join(NOTIFY_FILE))
+                .notify_container_start_within(timeout),
+        };
+        match notified {
+            Ok(()) => self.start_notifier = None,
+            Err(NotifyListenerError::Timeout) => {
+                tracing::error!(id = ?self.id(), "container did not accept the start in time");
+                return Err(LibcontainerError::Timeout(Phase::Start));
+            }
+            Err(err) => return Err(err.into()),
         }
-
-        let mut notify_socket = NotifySocket::new(self.root.join(NOTIFY_FILE));
-        notify_socket.notify_container_start()?;
-        self.set_status(ContainerStatus::Running)
-            .save()
-            .map_err(|err| {
-                tracing::error!(id = ?self.id(), ?err, "failed to save state for container");
-                err
-            })?;
+        self.update_state(|container| {
+            container.set_status(ContainerStatus::Running);
+        })
+        .map_err(|err| {
+            tracing::error!(id = ?self.id(), ?err, "failed to save state for container");
+            err
+        })?;
 
         // Run post start hooks. It runs after the container process is started.
         // It is called in the runtime namespace.
-        if let Some(hooks) = config.hooks.as_ref() {
-            hooks::run_hooks(hooks.poststart().as_ref(), Some(self), Some(&self.root)).map_err(
-                |err| {
-                    tracing::error!("failed to run post start hooks: {}", err);
-                    err
-                },
-            )?;
-        }
+        let deadline = Deadline::begin(timeouts, Phase::Hooks, start_deadline.at);
+        hooks::run_lifecycle_hooks_until(
+            LifecyclePoint::Started,
+            config.hooks.as_ref(),
+            Some(self),
+            Some(&self.root),
+            deadline.at,
+        )
+        .map_err(|err| match err {
+            HookError::DeadlineExceeded => LibcontainerError::Timeout(deadline.phase),
+            err => err.into(),
+        })?;
 
         Ok(())
     }
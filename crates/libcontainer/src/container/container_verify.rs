@@ -0,0 +1,728 @@
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::{fmt, fs};
+
+use caps::{CapSet, Capability as CapsCapability, CapsHashSet};
+use libcgroups::common::{self, CgroupSetup, DEFAULT_CGROUP_ROOT};
+use oci_spec::runtime::{LinuxNamespaceType, LinuxResources, Spec};
+use procfs::process::{MountInfo, Process};
+use serde::Serialize;
+
+use super::{Container, ContainerStatus};
+use crate::capabilities::CapabilityExt;
+use crate::error::LibcontainerError;
+use crate::propagation::{stray_mounts, LivePeers};
+
+const SECCOMP_MODE_FILTER: u32 = 2;
+const CGROUP_MAX: &str = "max";
+
+/// Part of the container a finding is about
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FindingCategory {
+    Namespace,
+    Capabilities,
+    Seccomp,
+    Mount,
+    Cgroup,
+    Propagation,
+}
+
+impl fmt::Display for FindingCategory {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let category = match self {
+            FindingCategory::Namespace => "namespace",
+            FindingCategory::Capabilities => "capabilities",
+            FindingCategory::Seccomp => "seccomp",
+            FindingCategory::Mount => "mount",
+            FindingCategory::Cgroup => "cgroup",
+            FindingCategory::Propagation => "propagation",
+        };
+        write!(f, "{category}")
+    }
+}
+
+/// Difference between the spec of a container and the state of its init
+/// process in the kernel
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Finding {
+    pub category: FindingCategory,
+    /// What was checked, e.g. a namespace type, mount point or cgroup file
+    pub subject: String,
+    pub expected: String,
+    pub actual: String,
+}
+
+impl Finding {
+    fn new(
+        category: FindingCategory,
+        subject: impl Into<String>,
+        expected: impl Into<String>,
+        actual: impl Into<String>,
+    ) -> Self {
+        Self {
+            category,
+            subject: subject.into(),
+            expected: expected.into(),
+            actual: actual.into(),
+        }
+    }
+}
+
+impl Container {
+    /// Compares the spec the container was created with, see
+    /// [`Container::effective_spec`], with the namespaces, capabilities,
+    /// seccomp mode, mounts and cgroup values of its init process, and looks
+    /// for stray mounts beneath the sources of its shared mounts on the host
+    /// if the container detaches them, see [`crate::propagation`]. An empty
+    /// result means no drift was found.
+    ///
+    /// The namespaces of the container are compared with the namespaces of
+    /// the calling process, so it has to run in the namespaces youki created
+    /// the container from. Namespaces the spec does not list are not checked.
+    pub fn verify(&mut self) -> Result<Vec<Finding>, LibcontainerError> {
+        self.refresh_status()?;
+        if !matches!(
+            self.status(),
+            ContainerStatus::Running | ContainerStatus::Paused
+        ) {
+            tracing::error!(id = ?self.id(), status = ?self.status(), "container is not running");
+            return Err(LibcontainerError::IncorrectStatus);
+        }
+        let pid = self.pid().ok_or(LibcontainerError::Other(
+            "container process pid not found in state".into(),
+        ))?;
+
+        let spec = self.effective_spec()?;
+        let process = Process::new(pid.as_raw())?;
+
+        let mut findings = verify_namespaces(&spec, &process)?;
+        findings.extend(verify_credentials(&spec, &process)?);
+        findings.extend(verify_mounts(&spec, &process)?);
+        findings.extend(verify_cgroup(&spec, &process)?);
+        findings.extend(self.verify_propagation()?);
+
+        tracing::debug!(id = ?self.id(), ?findings, "verified container");
+        Ok(findings)
+    }
+}
+
+impl Container {
+    fn verify_propagation(&self) -> Result<Vec<Finding>, LibcontainerError> {
+        let config = self.spec()?;
+        let shared_mounts = config.shared_mounts;
+        let root_path = match self.root.parent() {
+            Some(root_path) if config.detach_stray_mounts && !shared_mounts.is_empty() => root_path,
+            _ => return Ok(Vec::new()),
+        };
+        let mounts = Process::myself()?.mountinfo()?.0;
+        let peers = LivePeers::find(root_path, &shared_mounts);
+
+        let mut findings = Vec::new();
+        for shared in &shared_mounts {
+            for mount in stray_mounts(shared, &mounts, &peers) {
+                findings.push(Finding::new(
+                    FindingCategory::Propagation,
+                    mount.mount_point.display().to_string(),
+                    format!(
+                        "no mount beneath {} outside a container",
+                        shared.source.display()
+                    ),
+                    "stray mount of a deleted container",
+                ));
+            }
+        }
+        Ok(findings)
+    }
+}
+
+fn namespace_name(typ: LinuxNamespaceType) -> &'static str {
+    match typ {
+        LinuxNamespaceType::Mount => "mnt",
+        LinuxNamespaceType::Cgroup => "cgroup",
+        LinuxNamespaceType::Uts => "uts",
+        LinuxNamespaceType::Ipc => "ipc",
+        LinuxNamespaceType::User => "user",
+        LinuxNamespaceType::Pid => "pid",
+        LinuxNamespaceType::Network => "net",
+        LinuxNamespaceType::Time => "time",
+    }
+}
+
+fn verify_namespaces(spec: &Spec, process: &Process) -> Result<Vec<Finding>, LibcontainerError> {
+    let namespaces = match spec
+        .linux()
+        .as_ref()
+        .and_then(|linux| linux.namespaces().as_ref())
+    {
+        Some(namespaces) => namespaces,
+        None => return Ok(Vec::new()),
+    };
+    let container_namespaces = process.namespaces()?.0;
+    let own_namespaces = Process::myself()?.namespaces()?.0;
+
+    let mut findings = Vec::new();
+    for namespace in namespaces {
+        let name = namespace_name(namespace.typ());
+        let actual = match container_namespaces.get(&OsString::from(name)) {
+            Some(actual) => actual,
+            None => {
+                findings.push(Finding::new(
+                    FindingCategory::Namespace,
+                    name,
+                    "available",
+                    "not supported by the kernel",
+                ));
+                continue;
+            }
+        };
+
+        match namespace.path() {
+            Some(path) => {
+                let metadata = fs::metadata(path).map_err(|err| {
+                    tracing::error!(?err, ?path, "failed to inspect namespace of spec");
+                    LibcontainerError::OtherIO(err)
+                })?;
+                if (metadata.dev(), metadata.ino()) != (actual.device_id, actual.identifier) {
+                    findings.push(Finding::new(
+                        FindingCategory::Namespace,
+                        name,
+                        format!("joined {}", path.display()),
+                        format!("{name}:[{}]", actual.identifier),
+                    ));
+                }
+            }
+            None => {
+                if own_namespaces.get(&OsString::from(name)) == Some(actual) {
+                    findings.push(Finding::new(
+                        FindingCategory::Namespace,
+                        name,
+                        "new namespace",
+                        "namespace of the runtime",
+                    ));
+                }
+            }
+        }
+    }
+
+    Ok(findings)
+}
+
+fn verify_credentials(spec: &Spec, process: &Process) -> Result<Vec<Finding>, LibcontainerError> {
+    let status = process.status()?;
+    let own_status = Process::myself()?.status()?;
+    let spec_process = match spec.process() {
+        Some(spec_process) => spec_process,
+        None => return Ok(Vec::new()),
+    };
+
+    let mut findings = Vec::new();
+    if let Some(capabilities) = spec_process.capabilities() {
+        let to_set = |caps: &Option<oci_spec::runtime::Capabilities>| -> CapsHashSet {
+            caps.iter().flatten().map(|cap| cap.to_cap()).collect()
+        };
+        let bounding = to_set(capabilities.bounding());
+        let ambient = to_set(capabilities.ambient());
+        let effective = expected_effective(
+            spec_process.user().uid() == 0,
+            spec_process.no_new_privileges().unwrap_or(false),
+            &bounding,
+            &to_set(capabilities.inheritable()),
+            &to_set(capabilities.permitted()),
+            &ambient,
+        );
+        let supported = caps::runtime::procfs_all_supported(None)?;
+
+        for (set, expected, actual) in [
+            (CapSet::Effective, effective, Some(status.capeff)),
+            (CapSet::Bounding, bounding, status.capbnd),
+            (CapSet::Ambient, ambient, status.capamb),
+        ] {
+            // the ambient set and the bounding set in status are missing on
+            // old kernels
+            if let Some(actual) = actual {
+                findings.extend(compare_capabilities(set, &expected, actual, &supported));
+            }
+        }
+    }
+
+    let expected_seccomp = match spec
+        .linux()
+        .as_ref()
+        .and_then(|linux| linux.seccomp().as_ref())
+    {
+        Some(_) => Some(SECCOMP_MODE_FILTER),
+        // the container inherits a filter the runtime runs with
+        None => own_status.seccomp,
+    };
+    if status.seccomp != expected_seccomp {
+        findings.push(Finding::new(
+            FindingCategory::Seccomp,
+            "mode",
+            seccomp_mode(expected_seccomp),
+            seccomp_mode(status.seccomp),
+        ));
+    }
+
+    let expected_nnp = match spec_process.no_new_privileges() {
+        Some(true) => Some(1),
+        _ => own_status.nonewprivs,
+    };
+    if let (Some(expected), Some(actual)) = (expected_nnp, status.nonewprivs) {
+        if expected != actual {
+            findings.push(Finding::new(
+                FindingCategory::Seccomp,
+                "no_new_privs",
+                expected.to_string(),
+                actual.to_string(),
+            ));
+        }
+    }
+
+    Ok(findings)
+}
+
+/// Returns the effective capabilities the kernel gives the container process
+/// when it executes its entrypoint. File capabilities of the executable are
+/// not taken into account.
+fn expected_effective(
+    root: bool,
+    no_new_privileges: bool,
+    bounding: &CapsHashSet,
+    inheritable: &CapsHashSet,
+    permitted: &CapsHashSet,
+    ambient: &CapsHashSet,
+) -> CapsHashSet {
+    if !root {
+        return ambient.clone();
+    }
+
+    // root gets the bounding and inheritable set, but cannot gain
+    // capabilities on exec with no_new_privileges
+    let effective = bounding
+        .union(inheritable)
+        .chain(ambient.iter())
+        .copied()
+        .collect::<CapsHashSet>();
+    match no_new_privileges {
+        true => effective.intersection(permitted).copied().collect(),
+        false => effective,
+    }
+}
+
+fn compare_capabilities(
+    set: CapSet,
+    expected: &CapsHashSet,
+    actual: u64,
+    supported: &CapsHashSet,
+) -> Vec<Finding> {
+    let mut capabilities = supported.iter().copied().collect::<Vec<CapsCapability>>();
+    capabilities.sort_by_key(|cap| cap.index());
+
+    let presence = |present: bool| if present { "present" } else { "absent" };
+    capabilities
+        .into_iter()
+        .filter_map(|cap| {
+            let want = expected.contains(&cap);
+            let have = actual & cap.bitmask() != 0;
+            (want != have).then(|| {
+                Finding::new(
+                    FindingCategory::Capabilities,
+                    format!("{} {cap}", format!("{set:?}").to_lowercase()),
+                    presence(want),
+                    presence(have),
+                )
+            })
+        })
+        .collect()
+}
+
+fn seccomp_mode(mode: Option<u32>) -> String {
+    match mode {
+        Some(0) => "disabled".to_owned(),
+        Some(1) => "strict".to_owned(),
+        Some(2) => "filter".to_owned(),
+        Some(mode) => mode.to_string(),
+        None => "unknown".to_owned(),
+    }
+}
+
+fn verify_mounts(spec: &Spec, process: &Process) -> Result<Vec<Finding>, LibcontainerError> {
+    let mount_points = mount_points(process.mountinfo()?.0);
+    let root = process.root()?;
+
+    let mut findings = Vec::new();
+    let mut check_readonly =
+        |destination: &Path, readonly: bool| match mount_points.get(destination) {
+            Some(mount) if readonly && !mount.mount_options.contains_key("ro") => {
+                findings.push(Finding::new(
+                    FindingCategory::Mount,
+                    destination.display().to_string(),
+                    "read only",
+                    "read write",
+                ));
+            }
+            Some(_) => {}
+            None => findings.push(Finding::new(
+                FindingCategory::Mount,
+                destination.display().to_string(),
+                "mounted",
+                "not mounted",
+            )),
+        };
+
+    let readonly_root = spec
+        .root()
+        .as_ref()
+        .and_then(|root| root.readonly())
+        .unwrap_or(false);
+    check_readonly(Path::new("/"), readonly_root);
+
+    for mount in spec.mounts().iter().flatten() {
+        let readonly = mount
+            .options()
+            .iter()
+            .flatten()
+            .any(|option| option == "ro");
+        check_readonly(mount.destination(), readonly);
+    }
+
+    // masked and read only paths are skipped by the runtime when they do not
+    // exist in the container
+    let exists = |path: &str| root.join(path.trim_start_matches('/')).exists();
+    if let Some(linux) = spec.linux() {
+        for path in linux.readonly_paths().iter().flatten() {
+            if exists(path) {
+                check_readonly(Path::new(path), true);
+            }
+        }
+        for path in linux.masked_paths().iter().flatten() {
+            if exists(path) && !mount_points.contains_key(Path::new(path)) {
+                findings.push(Finding::new(
+                    FindingCategory::Mount,
+                    path.as_str(),
+                    "masked",
+                    "not masked",
+                ));
+            }
+        }
+    }
+
+    Ok(findings)
+}
+
+/// Returns the mounts by their mount point. Of stacked mounts only the top
+/// one is visible in the container.
+fn mount_points(mounts: Vec<MountInfo>) -> HashMap<PathBuf, MountInfo> {
+    mounts
+        .into_iter()
+        .map(|mount| (mount.mount_point.clone(), mount))
+        .collect()
+}
+
+fn verify_cgroup(spec: &Spec, process: &Process) -> Result<Vec<Finding>, LibcontainerError> {
+    let resources = match spec
+        .linux()
+        .as_ref()
+        .and_then(|linux| linux.resources().as_ref())
+    {
+        Some(resources) => resources,
+        None => return Ok(Vec::new()),
+    };
+    let setup = common::get_cgroup_setup()?;
+
+    let mut paths = HashMap::new();
+    for cgroup in process.cgroups()?.0 {
+        if cgroup.hierarchy == 0 {
+            paths.insert(String::new(), cgroup.pathname);
+            continue;
+        }
+        for controller in cgroup.controllers {
+            paths.insert(controller, cgroup.pathname.clone());
+        }
+    }
+
+    let mut findings = Vec::new();
+    for (controller, file, expected) in expected_cgroup_values(setup, resources) {
+        let path = match setup {
+            CgroupSetup::Unified => paths
+                .get("")
+                .map(|path| Path::new(DEFAULT_CGROUP_ROOT).join(path.trim_start_matches('/'))),
+            CgroupSetup::Legacy | CgroupSetup::Hybrid => paths.get(controller).map(|path| {
+                Path::new(DEFAULT_CGROUP_ROOT)
+                    .join(controller)
+                    .join(path.trim_start_matches('/'))
+            }),
+        };
+        let actual = match path {
+            Some(path) => match common::read_cgroup_file(path.join(&file)) {
+                Ok(actual) => actual.trim().to_owned(),
+                Err(err) => {
+                    tracing::debug!(?err, "failed to read cgroup file");
+                    "unavailable".to_owned()
+                }
+            },
+            None => "controller not available".to_owned(),
+        };
+        if !cgroup_value_matches(&expected, &actual) {
+            findings.push(Finding::new(
+                FindingCategory::Cgroup,
+                file,
+                expected,
+                actual,
+            ));
+        }
+    }
+
+    Ok(findings)
+}
+
+/// Returns the controller, file and value the spec resources are expected to
+/// have in the cgroup of the container
+fn expected_cgroup_values(
+    setup: CgroupSetup,
+    resources: &LinuxResources,
+) -> Vec<(&'static str, String, String)> {
+    let limit = |value: i64| match value {
+        value if value < 0 => CGROUP_MAX.to_owned(),
+        value => value.to_string(),
+    };
+
+    let mut values = Vec::new();
+    let mut expect = |controller, file: &str, value: String| {
+        values.push((controller, file.to_owned(), value));
+    };
+    let unified = setup == CgroupSetup::Unified;
+
+    if let Some(memory) = resources.memory() {
+        match (memory.limit(), unified) {
+            (Some(limit_value), true) => expect("memory", "memory.max", limit(limit_value)),
+            // v1 reports no limit as a large page aligned number
+            (Some(limit_value), false) if limit_value >= 0 => {
+                expect("memory", "memory.limit_in_bytes", limit(limit_value))
+            }
+            _ => {}
+        }
+        match (memory.reservation(), unified) {
+            (Some(reservation), true) => expect("memory", "memory.low", limit(reservation)),
+            (Some(reservation), false) if reservation >= 0 => {
+                expect("memory", "memory.soft_limit_in_bytes", limit(reservation))
+            }
+            _ => {}
+        }
+    }
+
+    if let Some(pids) = resources.pids() {
+        let value = match pids.limit() {
+            value if value <= 0 => CGROUP_MAX.to_owned(),
+            value => value.to_string(),
+        };
+        expect("pids", "pids.max", value);
+    }
+
+    if let Some(cpu) = resources.cpu() {
+        if unified {
+            if let Some(quota) = cpu.quota() {
+                let period = cpu.period().unwrap_or(100_000);
+                expect("cpu", "cpu.max", format!("{} {period}", limit(quota)));
+            }
+            if let Some(idle) = cpu.idle() {
+                expect("cpu", "cpu.idle", idle.to_string());
+            }
+        } else {
+            if let Some(quota) = cpu.quota() {
+                expect("cpu", "cpu.cfs_quota_us", quota.max(-1).to_string());
+            }
+            if let Some(period) = cpu.period() {
+                expect("cpu", "cpu.cfs_period_us", period.to_string());
+            }
+            if let Some(shares) = cpu.shares() {
+                expect("cpu", "cpu.shares", shares.to_string());
+            }
+        }
+    }
+
+    if unified {
+        for (file, value) in resources.unified().iter().flatten() {
+            values.push(("", file.clone(), value.trim().to_owned()));
+        }
+    }
+
+    values
+}
+
+/// Compares the values token by token, as files such as `cpu.max` hold
+/// several. Sizes may be given with the suffixes the kernel accepts, and
+/// memory limits are rounded down to the page size by the kernel.
+fn cgroup_value_matches(expected: &str, actual: &str) -> bool {
+    let expected: Vec<&str> = expected.split_whitespace().collect();
+    let actual: Vec<&str> = actual.split_whitespace().collect();
+
+    expected.len() == actual.len()
+        && expected
+            .iter()
+            .zip(&actual)
+            .all(|(expected, actual)| cgroup_token_matches(expected, actual))
+}
+
+fn cgroup_token_matches(expected: &str, actual: &str) -> bool {
+    if expected == actual {
+        return true;
+    }
+
+    match (parse_memparse(expected), parse_memparse(actual)) {
+        (Some(expected), Some(actual)) => {
+            let page_size = nix::unistd::sysconf(nix::unistd::SysconfVar::PAGE_SIZE)
+                .ok()
+                .flatten()
+                .unwrap_or(4096) as u64;
+            actual <= expected && expected - actual < page_size
+        }
+        _ => false,
+    }
+}
+
+/// Parses a size like the kernel does for cgroup files, with an optional
+/// suffix K, M, G, T, P or E in either case
+fn parse_memparse(value: &str) -> Option<u64> {
+    let (number, shift) = match value.char_indices().last() {
+        Some((i, 'K' | 'k')) => (&value[..i], 10),
+        Some((i, 'M' | 'm')) => (&value[..i], 20),
+        Some((i, 'G' | 'g')) => (&value[..i], 30),
+        Some((i, 'T' | 't')) => (&value[..i], 40),
+        Some((i, 'P' | 'p')) => (&value[..i], 50),
+        Some((i, 'E' | 'e')) => (&value[..i], 60),
+        _ => (value, 0),
+    };
+
+    number.parse::<u64>().ok()?.checked_mul(1 << shift)
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use oci_spec::runtime::{
+        LinuxCpuBuilder, LinuxMemoryBuilder, LinuxPidsBuilder, LinuxResourcesBuilder,
+    };
+
+    use super::*;
+
+    fn caps(caps: &[CapsCapability]) -> CapsHashSet {
+        caps.iter().copied().collect()
+    }
+
+    #[test]
+    fn test_expected_effective() {
+        let bounding = caps(&[CapsCapability::CAP_CHOWN, CapsCapability::CAP_KILL]);
+        let inheritable = caps(&[CapsCapability::CAP_NET_RAW]);
+        let permitted = caps(&[CapsCapability::CAP_CHOWN]);
+        let ambient = caps(&[CapsCapability::CAP_KILL]);
+
+        assert_eq!(
+            expected_effective(true, false, &bounding, &inheritable, &permitted, &ambient),
+            caps(&[
+                CapsCapability::CAP_CHOWN,
+                CapsCapability::CAP_KILL,
+                CapsCapability::CAP_NET_RAW
+            ])
+        );
+        assert_eq!(
+            expected_effective(true, true, &bounding, &inheritable, &permitted, &ambient),
+            permitted
+        );
+        assert_eq!(
+            expected_effective(false, false, &bounding, &inheritable, &permitted, &ambient),
+            ambient
+        );
+    }
+
+    #[test]
+    fn test_compare_capabilities() {
+        let supported = caps(&[
+            CapsCapability::CAP_CHOWN,
+            CapsCapability::CAP_KILL,
+            CapsCapability::CAP_NET_RAW,
+        ]);
+        let expected = caps(&[CapsCapability::CAP_CHOWN, CapsCapability::CAP_KILL]);
+        // CAP_SYS_ADMIN is not supported and ignored
+        let actual = CapsCapability::CAP_CHOWN.bitmask()
+            | CapsCapability::CAP_NET_RAW.bitmask()
+            | CapsCapability::CAP_SYS_ADMIN.bitmask();
+
+        assert_eq!(
+            compare_capabilities(CapSet::Bounding, &expected, actual, &supported),
+            vec![
+                Finding::new(
+                    FindingCategory::Capabilities,
+                    "bounding CAP_KILL",
+                    "present",
+                    "absent"
+                ),
+                Finding::new(
+                    FindingCategory::Capabilities,
+                    "bounding CAP_NET_RAW",
+                    "absent",
+                    "present"
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_expected_cgroup_values() -> Result<()> {
+        let resources = LinuxResourcesBuilder::default()
+            .memory(LinuxMemoryBuilder::default().limit(-1).build()?)
+            .pids(LinuxPidsBuilder::default().limit(100).build()?)
+            .cpu(LinuxCpuBuilder::default().quota(50_000).build()?)
+            .build()?;
+
+        assert_eq!(
+            expected_cgroup_values(CgroupSetup::Unified, &resources),
+            vec![
+                ("memory", "memory.max".to_owned(), "max".to_owned()),
+                ("pids", "pids.max".to_owned(), "100".to_owned()),
+                ("cpu", "cpu.max".to_owned(), "50000 100000".to_owned()),
+            ]
+        );
+        assert_eq!(
+            expected_cgroup_values(CgroupSetup::Legacy, &resources),
+            vec![
+                ("pids", "pids.max".to_owned(), "100".to_owned()),
+                ("cpu", "cpu.cfs_quota_us".to_owned(), "50000".to_owned()),
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_cgroup_value_matches() {
+        assert!(cgroup_value_matches("max", "max"));
+        assert!(cgroup_value_matches("1000000", "999424"));
+        assert!(!cgroup_value_matches("1000000", "2000000"));
+        assert!(!cgroup_value_matches("max", "1000"));
+
+        // suffixes of the unified map
+        assert!(cgroup_value_matches("1M", "1048576"));
+        assert!(cgroup_value_matches("1g", "1073741824"));
+        assert!(!cgroup_value_matches("2M", "1048576"));
+
+        // multi-value files
+        assert!(cgroup_value_matches("max  100000", "max 100000"));
+        assert!(cgroup_value_matches("50000 100000\n", "50000 100000"));
+        assert!(!cgroup_value_matches("50000 100000", "50000 200000"));
+        assert!(!cgroup_value_matches("50000", "50000 100000"));
+    }
+
+    #[test]
+    fn test_parse_memparse() {
+        assert_eq!(parse_memparse("4096"), Some(4096));
+        assert_eq!(parse_memparse("4k"), Some(4096));
+        assert_eq!(parse_memparse("2T"), Some(2 << 40));
+        assert_eq!(parse_memparse("max"), None);
+        assert_eq!(parse_memparse("K"), None);
+        assert_eq!(parse_memparse("100E"), None);
+    }
+}
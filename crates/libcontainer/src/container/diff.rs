@@ -0,0 +1,336 @@
+//! Changes to the filesystem of a container whose rootfs is an overlay, as
+//! set up by container engines from the layers of an image. All writes of
+//! the container end up in the upper directory of the overlay, so the
+//! changes are found by scanning it instead of comparing whole trees:
+//!
+//! - a whiteout, a character device 0/0, is a deleted path,
+//! - a path which exists in a lower directory is modified, any other one is
+//!   added,
+//! - an opaque directory hides the lower directories below it, the paths
+//!   which only exist there are deleted.
+//!
+//! Directories which are only in the upper directory because a path below
+//! them changed are reported as modified, like `docker diff` does. The
+//! overlay is found in the mounts of the calling process at the rootfs of
+//! the bundle.
+use std::collections::BTreeMap;
+use std::ffi::CString;
+use std::fmt::{self, Display};
+use std::fs;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{FileTypeExt, MetadataExt};
+use std::path::{Path, PathBuf};
+
+use oci_spec::runtime::Spec;
+use procfs::process::Process;
+use serde::Serialize;
+
+use super::Container;
+use crate::error::{LibcontainerError, MissingSpecError};
+
+#[derive(Debug, thiserror::Error)]
+pub enum DiffError {
+    #[error("rootfs {0:?} is not an overlay mount")]
+    NotOverlay(PathBuf),
+    #[error("failed to read {path:?} of the overlay")]
+    Read { path: PathBuf, source: io::Error },
+}
+
+type Result<T> = std::result::Result<T, DiffError>;
+
+/// Kind of a change, with the letters of `docker diff`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ChangeKind {
+    Added,
+    Modified,
+    Deleted,
+}
+
+impl Display for ChangeKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self {
+            Self::Added => "A",
+            Self::Modified => "C",
+            Self::Deleted => "D",
+        };
+        write!(f, "{kind}")
+    }
+}
+
+/// Changed path, absolute in the container
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Change {
+    pub path: PathBuf,
+    pub kind: ChangeKind,
+}
+
+/// Upper and lower directories of an overlay
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Overlay {
+    /// None for an overlay without upper directory, which can't change
+    pub upper: Option<PathBuf>,
+    /// Lower directories, the top one first
+    pub lowers: Vec<PathBuf>,
+    /// Prefix of the xattrs of the overlay, `user.overlay.` with the
+    /// userxattr option
+    pub xattr_prefix: &'static str,
+}
+
+impl Overlay {
+    /// The overlay mounted at `rootfs` in the mounts of the calling process
+    pub fn at(rootfs: &Path) -> std::result::Result<Self, LibcontainerError> {
+        let mounts = Process::myself()?.mountinfo()?.0;
+        // the last mount at the path covers the others
+        let mount = mounts
+            .iter()
+            .rev()
+            .find(|mount| mount.mount_point == rootfs)
+            .filter(|mount| mount.fs_type == "overlay")
+            .ok_or_else(|| DiffError::NotOverlay(rootfs.to_owned()))?;
+        let option = |name: &str| mount.super_options.get(name).cloned().flatten();
+
+        Ok(Self {
+            upper: option("upperdir").map(PathBuf::from),
+            lowers: option("lowerdir")
+                .map(|lowers| split_lowers(&lowers))
+                .unwrap_or_default(),
+            xattr_prefix: if mount.super_options.contains_key("userxattr") {
+                "user.overlay."
+            } else {
+                "trusted.overlay."
+            },
+        })
+    }
+
+    /// Changes of the upper directory to the lower ones, sorted by path
+    pub fn changes(&self) -> Result<Vec<Change>> {
+        let mut changes = BTreeMap::new();
+        if let Some(upper) = &self.upper {
+            self.scan(upper, Path::new(""), true, &mut changes)?;
+        }
+
+        Ok(changes
+            .into_iter()
+            .map(|(path, kind)| Change {
+                path: Path::new("/").join(path),
+                kind,
+            })
+            .collect())
+    }
+
+    /// Scans the directory `relative` of the upper directory. `in_lower` is
+    /// whether the lower directories may have paths below it.
+    fn scan(
+        &self,
+        upper: &Path,
+        relative: &Path,
+        in_lower: bool,
+        changes: &mut BTreeMap<PathBuf, ChangeKind>,
+    ) -> Result<()> {
+        let dir = upper.join(relative);
+        let read_error = |source| DiffError::Read {
+            path: dir.clone(),
+            source,
+        };
+        for entry in fs::read_dir(&dir).map_err(read_error)? {
+            let entry = entry.map_err(read_error)?;
+            let path = relative.join(entry.file_name());
+            let metadata = entry.metadata().map_err(read_error)?;
+            if metadata.file_type().is_char_device() && metadata.rdev() == 0 {
+                changes.insert(path, ChangeKind::Deleted);
+                continue;
+            }
+
+            let lower = in_lower && self.in_lower(&path);
+            let kind = if lower {
+                ChangeKind::Modified
+            } else {
+                ChangeKind::Added
+            };
+            changes.insert(path.clone(), kind);
+            if !metadata.is_dir() {
+                continue;
+            }
+            let opaque = lower && self.is_opaque(&upper.join(&path));
+            if opaque {
+                self.hidden_by_opaque(upper, &path, changes)?;
+            }
+            self.scan(upper, &path, lower && !opaque, changes)?;
+        }
+
+        Ok(())
+    }
+
+    fn in_lower(&self, path: &Path) -> bool {
+        self.lowers
+            .iter()
+            .any(|lower| fs::symlink_metadata(lower.join(path)).is_ok())
+    }
+
+    fn is_opaque(&self, dir: &Path) -> bool {
+        let (Ok(path), Ok(name)) = (
+            CString::new(dir.as_os_str().as_bytes()),
+            CString::new(format!("{}opaque", self.xattr_prefix)),
+        ) else {
+            return false;
+        };
+        let mut value = [0u8; 1];
+        let len = unsafe {
+            libc::lgetxattr(
+                path.as_ptr(),
+                name.as_ptr(),
+                value.as_mut_ptr().cast(),
+                value.len(),
+            )
+        };
+        len == 1 && value[0] == b'y'
+    }
+
+    /// Marks the paths of the lower directories below the opaque directory
+    /// `path` as deleted, unless the upper directory has them
+    fn hidden_by_opaque(
+        &self,
+        upper: &Path,
+        path: &Path,
+        changes: &mut BTreeMap<PathBuf, ChangeKind>,
+    ) -> Result<()> {
+        for lower in &self.lowers {
+            let dir = lower.join(path);
+            let entries = match fs::read_dir(&dir) {
+                Ok(entries) => entries,
+                Err(err) if err.kind() == io::ErrorKind::NotFound => continue,
+                Err(source) => return Err(DiffError::Read { path: dir, source }),
+            };
+            for entry in entries.flatten() {
+                let hidden = path.join(entry.file_name());
+                if fs::symlink_metadata(upper.join(&hidden)).is_err() {
+                    changes.insert(hidden, ChangeKind::Deleted);
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Splits the lowerdir option at the colons which are not escaped
+fn split_lowers(lowers: &str) -> Vec<PathBuf> {
+    let mut dirs = Vec::new();
+    let mut current = String::new();
+    let mut chars = lowers.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => current.extend(chars.next()),
+            ':' => dirs.push(std::mem::take(&mut current)),
+            c => current.push(c),
+        }
+    }
+    dirs.push(current);
+    dirs.into_iter()
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .collect()
+}
+
+impl Container {
+    /// Lists the paths the container added, modified or deleted in its
+    /// rootfs, which has to be an overlay mounted in the mount namespace of
+    /// the caller. See [`diff`](self).
+    pub fn diff(&self) -> std::result::Result<Vec<Change>, LibcontainerError> {
+        let bundle = self.bundle();
+        let spec = Spec::load(bundle.join("config.json"))?;
+        let root = spec.root().as_ref().ok_or(MissingSpecError::Root)?;
+        let rootfs = fs::canonicalize(bundle.join(root.path())).map_err(|err| {
+            tracing::error!(?err, rootfs = ?root.path(), "failed to resolve rootfs");
+            LibcontainerError::OtherIO(err)
+        })?;
+
+        Ok(Overlay::at(&rootfs)?.changes()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use nix::sys::stat::{mknod, Mode, SFlag};
+
+    use super::*;
+
+    fn write(path: &Path) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, "content").unwrap();
+    }
+
+    #[test]
+    fn test_split_lowers() {
+        assert_eq!(
+            split_lowers("/layers/2:/layers/a\\:b::/data"),
+            vec![
+                PathBuf::from("/layers/2"),
+                PathBuf::from("/layers/a:b"),
+                PathBuf::from("/data")
+            ]
+        );
+    }
+
+    #[test]
+    fn test_changes() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let upper = tmp.path().join("upper");
+        let lower = tmp.path().join("lower");
+        for file in [
+            "etc/hosts",
+            "etc/passwd",
+            "var/cache/a",
+            "var/cache/b",
+            "tmp/x",
+        ] {
+            write(&lower.join(file));
+        }
+
+        write(&upper.join("etc/hosts"));
+        write(&upper.join("srv/app/run.sh"));
+        mknod(&upper.join("etc/passwd"), SFlag::S_IFCHR, Mode::empty(), 0)?;
+        write(&upper.join("var/cache/b"));
+        let name = CString::new("trusted.overlay.opaque")?;
+        let path = CString::new(upper.join("var/cache").as_os_str().as_bytes())?;
+        let ret =
+            unsafe { libc::lsetxattr(path.as_ptr(), name.as_ptr(), b"y".as_ptr().cast(), 1, 0) };
+        assert_eq!(ret, 0, "set opaque xattr");
+
+        let overlay = Overlay {
+            upper: Some(upper),
+            lowers: vec![lower],
+            xattr_prefix: "trusted.overlay.",
+        };
+        let changes: Vec<_> = overlay
+            .changes()?
+            .into_iter()
+            .map(|change| (change.path.display().to_string(), change.kind))
+            .collect();
+        assert_eq!(
+            changes,
+            vec![
+                ("/etc".to_owned(), ChangeKind::Modified),
+                ("/etc/hosts".to_owned(), ChangeKind::Modified),
+                ("/etc/passwd".to_owned(), ChangeKind::Deleted),
+                ("/srv".to_owned(), ChangeKind::Added),
+                ("/srv/app".to_owned(), ChangeKind::Added),
+                ("/srv/app/run.sh".to_owned(), ChangeKind::Added),
+                ("/var".to_owned(), ChangeKind::Modified),
+                ("/var/cache".to_owned(), ChangeKind::Modified),
+                ("/var/cache/a".to_owned(), ChangeKind::Deleted),
+                ("/var/cache/b".to_owned(), ChangeKind::Added),
+            ]
+        );
+
+        let read_only = Overlay {
+            upper: None,
+            ..overlay
+        };
+        assert!(read_only.changes()?.is_empty());
+        Ok(())
+    }
+}
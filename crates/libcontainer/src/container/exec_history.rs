@@ -0,0 +1,325 @@
+//! History of the processes executed in a container, so that operators can
+//! reconstruct who executed what and when. Every exec appends an event to
+//! `exec-history.jsonl` in the state directory of the container, which is
+//! never rewritten:
+//!
+//! - `started` when the process runs, or `failed` when it could not be
+//!   executed, with the uid of the caller, the user of the process and the
+//!   digest of its arguments,
+//! - `exited` with the exit status, if the caller waited for the process.
+//!
+//! The arguments are only kept as a digest, as they may contain secrets. The
+//! history is kept with the other files of the state directory when a
+//! finished container record is kept, see [`finished`](super::finished).
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Utc};
+use nix::unistd::{self, Pid};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use super::Container;
+use crate::error::LibcontainerError;
+
+pub const EXEC_HISTORY_FILE: &str = "exec-history.jsonl";
+
+#[derive(Debug, thiserror::Error)]
+pub enum ExecHistoryError {
+    #[error("failed to append to exec history {path:?}")]
+    Append { path: PathBuf, source: io::Error },
+    #[error("failed to read exec history {path:?}")]
+    Read { path: PathBuf, source: io::Error },
+}
+
+type Result<T> = std::result::Result<T, ExecHistoryError>;
+
+/// Who executed which process
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct Invocation {
+    /// Real uid of the process which called youki
+    pub caller_uid: u32,
+    /// Uid of the user who called youki through sudo, as `SUDO_UID` claims.
+    /// The caller controls its environment, so this is no proof of who
+    /// called, only `caller_uid` is.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub unverified_sudo_uid: Option<u32>,
+    /// User and group the process runs as in the container
+    pub uid: u32,
+    pub gid: u32,
+    /// Digest of the arguments of the process
+    pub args_digest: String,
+}
+
+impl Invocation {
+    /// Invocation of a process with the arguments `args` as `uid` and `gid`
+    /// by the calling process
+    pub fn new(args: &[String], uid: u32, gid: u32) -> Self {
+        let args = serde_json::to_vec(args).unwrap_or_default();
+        Self {
+            caller_uid: unistd::getuid().as_raw(),
+            unverified_sudo_uid: std::env::var("SUDO_UID")
+                .ok()
+                .and_then(|uid| uid.parse().ok()),
+            uid,
+            gid,
+            args_digest: format!("sha256:{:x}", Sha256::digest(args)),
+        }
+    }
+}
+
+/// Line of the exec history
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "event", rename_all = "camelCase")]
+pub enum ExecEvent {
+    #[serde(rename_all = "camelCase")]
+    Started {
+        pid: i32,
+        #[serde(flatten)]
+        invocation: Invocation,
+        time: DateTime<Utc>,
+    },
+    #[serde(rename_all = "camelCase")]
+    Failed {
+        #[serde(flatten)]
+        invocation: Invocation,
+        error: String,
+        time: DateTime<Utc>,
+    },
+    #[serde(rename_all = "camelCase")]
+    Exited {
+        pid: i32,
+        exit_status: i32,
+        time: DateTime<Utc>,
+    },
+}
+
+/// An exec of the history, with the exit of its process
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct ExecSession {
+    /// Pid of the process, none if it could not be executed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pid: Option<i32>,
+    #[serde(flatten)]
+    pub invocation: Invocation,
+    pub started: DateTime<Utc>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exited: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exit_status: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Exec history of the container with the state directory `container_root`
+pub fn history_path(container_root: &Path) -> PathBuf {
+    container_root.join(EXEC_HISTORY_FILE)
+}
+
+/// Appends an event to the exec history of the container
+pub fn append(container_root: &Path, event: &ExecEvent) -> Result<()> {
+    let path = history_path(container_root);
+    let append_err = |source| ExecHistoryError::Append {
+        path: path.clone(),
+        source,
+    };
+    let mut line = serde_json::to_vec(event).map_err(|err| append_err(err.into()))?;
+    line.push(b'\n');
+    let mut file = OpenOptions::new()
+        .create(true)
+        .read(true)
+        .append(true)
+        .open(&path)
+        .map_err(append_err)?;
+    // a line cut short by a crash is ended first, so that the event does not
+    // get lost with it
+    if !ends_with_newline(&file).map_err(append_err)? {
+        line.insert(0, b'\n');
+    }
+    // a single write, so that concurrent execs do not interleave their lines
+    file.write_all(&line).map_err(append_err)
+}
+
+fn ends_with_newline(file: &fs::File) -> io::Result<bool> {
+    let len = file.metadata()?.len();
+    if len == 0 {
+        return Ok(true);
+    }
+    let mut last = [0; 1];
+    file.read_exact_at(&mut last, len - 1)?;
+    Ok(last[0] == b'\n')
+}
+
+/// Execs of the history of the container, the oldest first. Lines which
+/// can't be parsed, e.g. one cut short by a crash, are skipped.
+pub fn history(container_root: &Path) -> Result<Vec<ExecSession>> {
+    let path = history_path(container_root);
+    let content = match fs::read_to_string(&path) {
+        Ok(content) => content,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(source) => return Err(ExecHistoryError::Read { path, source }),
+    };
+
+    let mut sessions: Vec<ExecSession> = Vec::new();
+    for (number, line) in content.lines().enumerate() {
+        // left by concurrent execs which both ended a line cut short
+        if line.is_empty() {
+            continue;
+        }
+        let event = match serde_json::from_str(line) {
+            Ok(event) => event,
+            Err(err) => {
+                tracing::warn!(
+                    ?err,
+                    ?path,
+                    line = number + 1,
+                    "skip invalid exec history line"
+                );
+                continue;
+            }
+        };
+        match event {
+            ExecEvent::Started {
+                pid,
+                invocation,
+                time,
+            } => sessions.push(ExecSession {
+                pid: Some(pid),
+                invocation,
+                started: time,
+                exited: None,
+                exit_status: None,
+                error: None,
+            }),
+            ExecEvent::Failed {
+                invocation,
+                error,
+                time,
+            } => sessions.push(ExecSession {
+                pid: None,
+                invocation,
+                started: time,
+                exited: None,
+                exit_status: None,
+                error: Some(error),
+            }),
+            ExecEvent::Exited {
+                pid,
+                exit_status,
+                time,
+            } => {
+                // pids are reused, the exit belongs to the latest process
+                let session = sessions
+                    .iter_mut()
+                    .rev()
+                    .find(|session| session.pid == Some(pid) && session.exited.is_none());
+                if let Some(session) = session {
+                    session.exited = Some(time);
+                    session.exit_status = Some(exit_status);
+                }
+            }
+        }
+    }
+
+    Ok(sessions)
+}
+
+impl Container {
+    /// Processes executed in the container, see [`exec_history`](self)
+    pub fn exec_history(&self) -> std::result::Result<Vec<ExecSession>, LibcontainerError> {
+        Ok(history(&self.root)?)
+    }
+
+    /// Records the exit status of a process executed in the container
+    pub fn record_exec_exit(
+        &self,
+        pid: Pid,
+        exit_status: i32,
+    ) -> std::result::Result<(), LibcontainerError> {
+        let event = ExecEvent::Exited {
+            pid: pid.as_raw(),
+            exit_status,
+            time: Utc::now(),
+        };
+        Ok(append(&self.root, &event)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+
+    use super::*;
+
+    #[test]
+    fn test_invocation() {
+        let args = ["sh".to_owned(), "-c".to_owned(), "id".to_owned()];
+        let invocation = Invocation::new(&args, 1000, 100);
+        assert_eq!(invocation.caller_uid, unistd::getuid().as_raw());
+        assert_eq!((invocation.uid, invocation.gid), (1000, 100));
+        assert!(invocation.args_digest.starts_with("sha256:"));
+        assert_eq!(
+            invocation.args_digest,
+            Invocation::new(&args, 0, 0).args_digest
+        );
+        assert_ne!(
+            invocation.args_digest,
+            Invocation::new(&args[..1], 1000, 100).args_digest
+        );
+    }
+
+    #[test]
+    fn test_history() -> Result<()> {
+        let root = tempfile::tempdir()?;
+        assert!(history(root.path())?.is_empty());
+
+        let invocation = Invocation::new(&["sh".to_owned()], 0, 0);
+        let time = Utc::now();
+        let started = |pid| ExecEvent::Started {
+            pid,
+            invocation: invocation.clone(),
+            time,
+        };
+        append(root.path(), &started(10))?;
+        append(
+            root.path(),
+            &ExecEvent::Failed {
+                invocation: invocation.clone(),
+                error: "executable not found".to_owned(),
+                time,
+            },
+        )?;
+        append(root.path(), &started(11))?;
+        append(
+            root.path(),
+            &ExecEvent::Exited {
+                pid: 10,
+                exit_status: 3,
+                time,
+            },
+        )?;
+        // a line cut short by a crash
+        let mut file = OpenOptions::new()
+            .append(true)
+            .open(history_path(root.path()))?;
+        file.write_all(b"{\"event\":\"sta")?;
+        // the next event is not lost with it
+        append(root.path(), &started(12))?;
+
+        let sessions = history(root.path())?;
+        assert_eq!(sessions.len(), 4);
+        assert_eq!(sessions[0].pid, Some(10));
+        assert_eq!(sessions[0].exit_status, Some(3));
+        assert_eq!(sessions[0].exited, Some(time));
+        assert_eq!(sessions[1].pid, None);
+        assert_eq!(sessions[1].error.as_deref(), Some("executable not found"));
+        assert_eq!(sessions[2].pid, Some(11));
+        assert_eq!(sessions[2].exit_status, None);
+        assert_eq!(sessions[3].pid, Some(12));
+        Ok(())
+    }
+}
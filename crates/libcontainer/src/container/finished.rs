@@ -0,0 +1,236 @@
+//! Records of deleted containers, kept for a postmortem. A container which is
+//! deleted with its state kept has its cgroup removed like any other, but the
+//! files of its state directory are copied to the `finished` directory of the
+//! root path instead of being removed:
+//!
+//! - the `state.json` and the other files youki kept for the container,
+//! - `config.json`, the spec of the bundle at the time of the deletion,
+//! - `init.json`, the report of the init process if it was debug captured,
+//! - `setup-trace.jsonl`, the syscall trace of its setup if it was traced,
+//! - `finished.json`, with the exit status of the container process if it is
+//!   known and when the container was created and deleted.
+//!
+//! The records stay until they are removed explicitly with [`remove`].
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use super::Container;
+use crate::debug_capture::{DEBUG_DIR, INIT_REPORT_FILE};
+use crate::syscall::trace::SETUP_TRACE_FILE;
+
+/// Directory of the finished containers in the root path. Container ids can't
+/// take its name.
+pub const FINISHED_DIR: &str = ".finished";
+
+const RECORD_FILE: &str = "finished.json";
+const SPEC_FILE: &str = "config.json";
+
+#[derive(Debug, thiserror::Error)]
+pub enum FinishedError {
+    #[error("failed to create finished container record {path:?}")]
+    Create { path: PathBuf, source: io::Error },
+    #[error("failed to copy {from:?} to the finished container record")]
+    Copy { from: PathBuf, source: io::Error },
+    #[error("failed to read finished container record {path:?}")]
+    Read { path: PathBuf, source: io::Error },
+    #[error("failed to remove finished container record {path:?}")]
+    Remove { path: PathBuf, source: io::Error },
+}
+
+type Result<T> = std::result::Result<T, FinishedError>;
+
+/// Content of `finished.json`
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct FinishedRecord {
+    pub id: String,
+    pub bundle: PathBuf,
+    /// Exit code of the container process, or the number of the signal which
+    /// killed it, if the caller waited for it
+    pub exit_status: Option<i32>,
+    pub created: Option<DateTime<Utc>>,
+    pub finished: DateTime<Utc>,
+}
+
+/// Record of a deleted container in the finished directory
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FinishedContainer {
+    pub path: PathBuf,
+    pub record: FinishedRecord,
+}
+
+/// Finished directory of the root path of the container
+pub fn finished_dir(root_path: &Path) -> PathBuf {
+    root_path.join(FINISHED_DIR)
+}
+
+/// Keeps the files of the container in the finished directory of its root
+/// path. The container has to be stopped, and it is left in place for the
+/// caller to remove.
+pub(super) fn archive(container: &Container, exit_status: Option<i32>) -> Result<PathBuf> {
+    let finished = Utc::now();
+    let root_path = container.root.parent().unwrap_or(&container.root);
+    let dir = finished_dir(root_path).join(format!("{}-{}", container.id(), finished.timestamp()));
+    let create_err = |source| FinishedError::Create {
+        path: dir.clone(),
+        source,
+    };
+    fs::create_dir_all(finished_dir(root_path)).map_err(create_err)?;
+    // a record is never overwritten
+    fs::create_dir(&dir).map_err(create_err)?;
+
+    // sockets and the like are of no use once the container is gone
+    for entry in fs::read_dir(&container.root).map_err(|source| FinishedError::Read {
+        path: container.root.clone(),
+        source,
+    })? {
+        let entry = entry.map_err(|source| FinishedError::Read {
+            path: container.root.clone(),
+            source,
+        })?;
+        if entry.file_type().map_or(false, |t| t.is_file()) {
+            copy(&entry.path(), &dir.join(entry.file_name()))?;
+        }
+    }
+
+    let bundle = container.bundle();
+    copy_if_exists(&bundle.join(SPEC_FILE), &dir.join(SPEC_FILE))?;
+    copy_if_exists(
+        &bundle.join(DEBUG_DIR).join(INIT_REPORT_FILE),
+        &dir.join(INIT_REPORT_FILE),
+    )?;
+    copy_if_exists(
+        &bundle.join(DEBUG_DIR).join(SETUP_TRACE_FILE),
+        &dir.join(SETUP_TRACE_FILE),
+    )?;
+
+    let record = FinishedRecord {
+        id: container.id().to_owned(),
+        bundle: bundle.to_owned(),
+        exit_status,
+        created: container.created(),
+        finished,
+    };
+    let content = serde_json::to_vec_pretty(&record).map_err(|err| FinishedError::Create {
+        path: dir.clone(),
+        source: err.into(),
+    })?;
+    fs::write(dir.join(RECORD_FILE), content).map_err(create_err)?;
+    tracing::debug!(id = container.id(), ?dir, "kept finished container record");
+
+    Ok(dir)
+}
+
+/// Records in the finished directory of the root path, the oldest first
+pub fn list(root_path: &Path) -> Result<Vec<FinishedContainer>> {
+    let dir = finished_dir(root_path);
+    let read_err = |source| FinishedError::Read {
+        path: dir.clone(),
+        source,
+    };
+    let entries = match fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(read_err(err)),
+    };
+
+    let mut finished = Vec::new();
+    for entry in entries {
+        let path = entry.map_err(read_err)?.path();
+        let record_file = path.join(RECORD_FILE);
+        let content = match fs::read(&record_file) {
+            Ok(content) => content,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => continue,
+            Err(source) => {
+                return Err(FinishedError::Read {
+                    path: record_file,
+                    source,
+                })
+            }
+        };
+        let record = serde_json::from_slice(&content).map_err(|err| FinishedError::Read {
+            path: record_file,
+            source: err.into(),
+        })?;
+        finished.push(FinishedContainer { path, record });
+    }
+    finished.sort_by(|a, b| a.record.finished.cmp(&b.record.finished));
+
+    Ok(finished)
+}
+
+/// Removes a record of the finished directory
+pub fn remove(finished: &FinishedContainer) -> Result<()> {
+    fs::remove_dir_all(&finished.path).map_err(|source| FinishedError::Remove {
+        path: finished.path.clone(),
+        source,
+    })
+}
+
+fn copy(from: &Path, to: &Path) -> Result<()> {
+    fs::copy(from, to)
+        .map(|_| ())
+        .map_err(|source| FinishedError::Copy {
+            from: from.to_owned(),
+            source,
+        })
+}
+
+fn copy_if_exists(from: &Path, to: &Path) -> Result<()> {
+    if from.exists() {
+        copy(from, to)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::net::UnixListener;
+
+    use anyhow::Result;
+
+    use super::*;
+    use crate::container::{ContainerStatus, State};
+
+    #[test]
+    fn test_archive() -> Result<()> {
+        let root_path = tempfile::tempdir()?;
+        let bundle = tempfile::tempdir()?;
+        fs::write(bundle.path().join(SPEC_FILE), "{}")?;
+
+        let container_root = root_path.path().join("finished-test");
+        fs::create_dir(&container_root)?;
+        let container = Container::new(
+            "finished-test",
+            ContainerStatus::Stopped,
+            None,
+            bundle.path(),
+            &container_root,
+        )?;
+        container.save()?;
+        let _socket = UnixListener::bind(container_root.join("notify.sock"))?;
+
+        let dir = archive(&container, Some(137))?;
+        assert!(dir.starts_with(finished_dir(container.root.parent().unwrap())));
+        assert!(State::file_path(&dir).exists());
+        assert!(dir.join(SPEC_FILE).exists());
+        assert!(!dir.join("notify.sock").exists());
+        assert!(!dir.join(INIT_REPORT_FILE).exists());
+
+        let finished = list(container.root.parent().unwrap())?;
+        assert_eq!(finished.len(), 1);
+        assert_eq!(finished[0].path, dir);
+        assert_eq!(finished[0].record.id, "finished-test");
+        assert_eq!(finished[0].record.exit_status, Some(137));
+        assert_eq!(&finished[0].record.bundle, container.bundle());
+
+        remove(&finished[0])?;
+        assert!(list(container.root.parent().unwrap())?.is_empty());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,213 @@
+//! Guards which undo the partial creation of a container. Each guard owns a
+//! resource the builder created and releases it when dropped, unless the
+//! container was created successfully and the guard was committed. Errors,
+//! early returns and panics in the middle of a build therefore never leave a
+//! cgroup or the namespaces of a half created container behind, which
+//! matters to embedders that keep running after a failed build.
+use libcgroups::common::{CgroupConfig, CgroupManager};
+use nix::errno::Errno;
+use nix::sys::signal::{self, Signal};
+use nix::sys::wait::waitpid;
+use nix::unistd::Pid;
+
+use crate::error::LibcontainerError;
+use crate::swap::ProvisionedSwap;
+use crate::volume::VolumeHelper;
+
+/// Removes the cgroup of a container which could not be created
+pub(super) struct CgroupGuard {
+    config: CgroupConfig,
+    armed: bool,
+}
+
+impl CgroupGuard {
+    pub fn new(config: CgroupConfig) -> Self {
+        Self {
+            config,
+            armed: true,
+        }
+    }
+
+    /// Keeps the cgroup, it belongs to the created container from now on
+    pub fn commit(mut self) {
+        self.armed = false;
+    }
+
+    /// Removes the cgroup right away and reports a failure instead of only
+    /// logging it
+    pub fn remove(mut self) -> Result<(), LibcontainerError> {
+        self.armed = false;
+        remove_cgroup(&self.config)
+    }
+}
+
+impl Drop for CgroupGuard {
+    fn drop(&mut self) {
+        if !self.armed {
+            return;
+        }
+
+        if let Err(err) = remove_cgroup(&self.config) {
+            tracing::warn!(?err, cgroup = ?self.config.cgroup_path, "failed to remove cgroup");
+        }
+    }
+}
+
+fn remove_cgroup(config: &CgroupConfig) -> Result<(), LibcontainerError> {
+    let cmanager = libcgroups::common::create_cgroup_manager(config.clone())?;
+    cmanager.remove().map_err(|err| {
+        tracing::error!(?err, "failed to remove cgroup manager");
+        err
+    })?;
+
+    Ok(())
+}
+
+/// Releases the namespaces of a container which could not be created. The
+/// builder holds no namespace fds itself, the namespaces are owned by the
+/// process of the container, so they are released by killing it.
+pub(super) struct NamespaceGuard {
+    pid: Pid,
+    armed: bool,
+}
+
+impl NamespaceGuard {
+    pub fn new(pid: Pid) -> Self {
+        Self { pid, armed: true }
+    }
+
+    /// Keeps the process, it belongs to the created container from now on
+    pub fn commit(mut self) {
+        self.armed = false;
+    }
+}
+
+impl Drop for NamespaceGuard {
+    fn drop(&mut self) {
+        if !self.armed {
+            return;
+        }
+
+        tracing::debug!(pid = ?self.pid, "killing the process of a container which could not be created");
+        match signal::kill(self.pid, Signal::SIGKILL) {
+            Ok(()) | Err(Errno::ESRCH) => {}
+            Err(err) => {
+                tracing::warn!(?err, pid = ?self.pid, "failed to kill container process");
+                return;
+            }
+        }
+        // The process is only our child if it was not forked by the
+        // intermediate process, otherwise its reaper collects it.
+        match waitpid(self.pid, None) {
+            Ok(_) | Err(Errno::ECHILD) => {}
+            Err(err) => tracing::warn!(?err, pid = ?self.pid, "failed to reap container process"),
+        }
+    }
+}
+
+/// Releases the volumes provisioned for a container which could not be
+/// created
+pub(super) struct VolumeGuard<'a> {
+    helper: &'a VolumeHelper,
+    volumes: Vec<String>,
+    container_id: String,
+    armed: bool,
+}
+
+impl<'a> VolumeGuard<'a> {
+    pub fn new(helper: &'a VolumeHelper, volumes: Vec<String>, container_id: &str) -> Self {
+        Self {
+            helper,
+            volumes,
+            container_id: container_id.to_owned(),
+            armed: true,
+        }
+    }
+
+    /// Keeps the volumes, they are released when the container is deleted
+    pub fn commit(mut self) {
+        self.armed = false;
+    }
+}
+
+impl Drop for VolumeGuard<'_> {
+    fn drop(&mut self) {
+        if self.armed {
+            self.helper.release_all(&self.volumes, &self.container_id);
+        }
+    }
+}
+
+/// Removes the swap provisioned for a container which could not be created
+pub(super) struct SwapGuard<'a> {
+    swap: &'a ProvisionedSwap,
+    armed: bool,
+}
+
+impl<'a> SwapGuard<'a> {
+    pub fn new(swap: &'a ProvisionedSwap) -> Self {
+        Self { swap, armed: true }
+    }
+
+    /// Keeps the swap, it is removed when the container is deleted
+    pub fn commit(mut self) {
+        self.armed = false;
+    }
+}
+
+impl Drop for SwapGuard<'_> {
+    fn drop(&mut self) {
+        if !self.armed {
+            return;
+        }
+
+        if let Err(err) = self.swap.release() {
+            tracing::warn!(?err, swap = ?self.swap, "failed to remove swap");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use nix::sys::wait::WaitStatus;
+    use nix::unistd::{fork, ForkResult};
+
+    use super::*;
+
+    fn spawn_sleeper() -> Result<Pid> {
+        // SAFETY: the child only sleeps until it is killed
+        match unsafe { fork() }? {
+            ForkResult::Parent { child } => Ok(child),
+            ForkResult::Child => loop {
+                std::thread::sleep(std::time::Duration::from_secs(1));
+            },
+        }
+    }
+
+    #[test]
+    fn test_namespace_guard_kills_on_drop() -> Result<()> {
+        let pid = spawn_sleeper()?;
+        drop(NamespaceGuard::new(pid));
+
+        // the guard reaped the process already
+        assert_eq!(waitpid(pid, None), Err(Errno::ECHILD));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_namespace_guard_commit() -> Result<()> {
+        let pid = spawn_sleeper()?;
+        NamespaceGuard::new(pid).commit();
+
+        signal::kill(pid, None)?;
+        signal::kill(pid, Signal::SIGKILL)?;
+        assert_eq!(
+            waitpid(pid, None)?,
+            WaitStatus::Signaled(pid, Signal::SIGKILL, false)
+        );
+
+        Ok(())
+    }
+}
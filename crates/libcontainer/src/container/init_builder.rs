@@ -1,18 +1,34 @@
 use std::fs;
 use std::path::{Path, PathBuf};
 use std::rc::Rc;
+use std::sync::Arc;
 
-use oci_spec::runtime::Spec;
+use libcgroups::common::CgroupHost;
+use nix::sys::signal::Signal;
+use oci_spec::runtime::{PosixRlimit, Spec};
 use user_ns::UserNamespaceConfig;
 
 use super::builder::ContainerBuilder;
 use super::builder_impl::ContainerBuilderImpl;
-use super::{Container, ContainerStatus};
-use crate::config::YoukiConfig;
+use super::guard::{SwapGuard, VolumeGuard};
+use super::{Confinement, Container, ContainerStatus};
+use crate::config::{self, YoukiConfig};
+use crate::debug_capture::{DebugCapture, InitReportFile, DEBUG_DIR};
 use crate::error::{ErrInvalidSpec, LibcontainerError, MissingSpecError};
-use crate::notify_socket::NOTIFY_FILE;
+use crate::hardening;
+use crate::notify_socket::{NotifyListener, StartHandshake, NOTIFY_FILE};
+use crate::prefault::Prefault;
 use crate::process::args::ContainerType;
-use crate::{apparmor, tty, user_ns, utils};
+use crate::process::cgroup_delegation::{self, SubtreeControl};
+use crate::process::parent_death::{OrphanPolicy, ParentDeath};
+use crate::propagation;
+use crate::seccomp_broker;
+use crate::seccomp_profile::{self, ProfileCache};
+use crate::swap;
+use crate::syscall::trace::SetupTrace;
+use crate::timezone::{self, Timezone};
+use crate::volume::{self, VolumeHelper};
+use crate::{apparmor, cpuset_partition, oom_group, rlimit, sysctl, tty, user_ns, utils};
 
 // Builder that can be used to configure the properties of a new container
 pub struct InitContainerBuilder {
@@ -22,6 +38,16 @@ pub struct InitContainerBuilder {
     detached: bool,
     no_pivot: bool,
     as_sibling: bool,
+    pdeathsig: Option<Signal>,
+    orphan_policy: Option<OrphanPolicy>,
+    default_rlimits: Vec<PosixRlimit>,
+    volume_helper: Option<VolumeHelper>,
+    timezone: Option<Timezone>,
+    start_handshake: StartHandshake,
+    debug_capture: Option<DebugCapture>,
+    setup_trace: bool,
+    threaded_cgroup: Option<String>,
+    cgroup_host: CgroupHost,
 }
 
 impl InitContainerBuilder {
@@ -35,6 +61,16 @@ impl InitContainerBuilder {
             detached: true,
             no_pivot: false,
             as_sibling: false,
+            pdeathsig: None,
+            orphan_policy: None,
+            default_rlimits: Vec::new(),
+            volume_helper: None,
+            timezone: None,
+            start_handshake: StartHandshake::default(),
+            debug_capture: None,
+            setup_trace: false,
+            threaded_cgroup: None,
+            cgroup_host: CgroupHost::default(),
         }
     }
 
@@ -44,6 +80,13 @@ impl InitContainerBuilder {
         self
     }
 
+    /// Sets the host the cgroup managers use instead of examining it
+    /// themselves, e.g. to share one connection to systemd
+    pub fn with_cgroup_host(mut self, cgroup_host: CgroupHost) -> Self {
+        self.cgroup_host = cgroup_host;
+        self
+    }
+
     /// Sets if the init process should be run as a child or a sibling of
     /// the calling process
     pub fn as_sibling(mut self, as_sibling: bool) -> Self {
@@ -61,17 +104,123 @@ impl InitContainerBuilder {
         self
     }
 
+    /// Sets the signal the container init receives when its parent dies,
+    /// overriding the org.youki.pdeathsig annotation
+    pub fn with_pdeathsig(mut self, signal: Option<Signal>) -> Self {
+        self.pdeathsig = signal;
+        self
+    }
+
+    /// Sets what happens to the container processes if youki dies while
+    /// creating the container, overriding the org.youki.orphan-policy
+    /// annotation
+    pub fn with_orphan_policy(mut self, policy: Option<OrphanPolicy>) -> Self {
+        self.orphan_policy = policy;
+        self
+    }
+
+    /// Sets rlimits which apply to the container process for every resource
+    /// type the spec does not limit itself
+    pub fn with_default_rlimits(mut self, rlimits: Vec<PosixRlimit>) -> Self {
+        self.default_rlimits = rlimits;
+        self
+    }
+
+    /// Sets the helper which provisions the mounts with `volume://` sources,
+    /// see [`crate::volume`]. Without a helper such mounts are refused.
+    pub fn with_volume_helper(mut self, helper: Option<VolumeHelper>) -> Self {
+        self.volume_helper = helper;
+        self
+    }
+
+    /// Sets the timezone of the container, replacing the one of the spec
+    pub fn with_timezone(mut self, timezone: Option<Timezone>) -> Self {
+        self.timezone = timezone;
+        self
+    }
+
+    /// Sets how the container is told to start, see [`StartHandshake`]
+    pub fn with_start_handshake(mut self, start_handshake: StartHandshake) -> Self {
+        self.start_handshake = start_handshake;
+        self
+    }
+
+    /// Sets what is captured of the init process in the debug directory of
+    /// the bundle, see [`crate::debug_capture`]
+    pub fn with_debug_capture(mut self, capture: Option<DebugCapture>) -> Self {
+        self.debug_capture = capture;
+        self
+    }
+
+    /// Sets if the syscalls of the setup of the container are traced in the
+    /// debug directory of the bundle, see [`crate::syscall::trace`]. The trace
+    /// is enabled for the whole process.
+    pub fn with_setup_trace(mut self, setup_trace: bool) -> Self {
+        self.setup_trace = setup_trace;
+        self
+    }
+
+    /// Sets the name of a threaded cgroup which is created below the cgroup
+    /// of the init process, overriding the org.youki.cgroup.threaded
+    /// annotation, see [`cgroup_delegation`]
+    pub fn with_threaded_cgroup(mut self, name: Option<String>) -> Self {
+        self.threaded_cgroup = name;
+        self
+    }
+
     /// Creates a new container
     pub fn build(self) -> Result<Container, LibcontainerError> {
-        let spec = self.load_spec()?;
+        if self.setup_trace {
+            // before anything of the container is set up
+            SetupTrace::enable(&self.bundle.join(DEBUG_DIR)).map_err(|err| {
+                tracing::error!(?err, "failed to enable the setup trace");
+                LibcontainerError::OtherIO(err)
+            })?;
+        }
+
+        let mut spec = self.load_spec()?;
+        let parent_death = self.parent_death(&spec)?;
+        let threaded_cgroup = cgroup_delegation::threaded_cgroup(
+            spec.annotations().as_ref(),
+            self.threaded_cgroup.as_deref(),
+        )?;
+        let detach_stray_mounts =
+            propagation::detach_stray_mounts_enabled(spec.annotations().as_ref())?;
         let container_dir = self.create_container_dir()?;
 
+        let volumes = match &self.volume_helper {
+            Some(helper) => helper.provision(&mut spec, &self.base.container_id)?,
+            None => Vec::new(),
+        };
+        let volume_guard = self
+            .volume_helper
+            .as_ref()
+            .map(|helper| VolumeGuard::new(helper, volumes.clone(), &self.base.container_id));
+        let swap = swap::provision(&spec, &self.base.container_id)?;
+        let swap_guard = swap.as_ref().map(SwapGuard::new);
+
         let mut container = self.create_container_state(&container_dir)?;
         container
             .set_systemd(self.use_systemd)
-            .set_annotations(spec.annotations().clone());
+            .set_annotations(spec.annotations().clone())
+            .set_parent_death(parent_death)
+            .set_confinement(Confinement::new(
+                spec.process()
+                    .as_ref()
+                    .and_then(|process| process.apparmor_profile().as_deref()),
+                spec.linux()
+                    .as_ref()
+                    .and_then(|linux| linux.seccomp().as_ref()),
+            ));
 
         let notify_path = container_dir.join(NOTIFY_FILE);
+        let (notify_listener, start_notifier) = match self.start_handshake {
+            StartHandshake::NotifySocket => (None, None),
+            StartHandshake::SocketPair => {
+                let (listener, notifier) = NotifyListener::pair()?;
+                (Some(listener), Some(notifier))
+            }
+        };
         // convert path of root file system of the container to absolute path
         let rootfs = fs::canonicalize(spec.root().as_ref().ok_or(MissingSpecError::Root)?.path())
             .map_err(LibcontainerError::OtherIO)?;
@@ -89,12 +238,25 @@ impl InitContainerBuilder {
         };
 
         let user_ns_config = UserNamespaceConfig::new(&spec)?;
+        let init_report = self
+            .debug_capture
+            .map(|capture| InitReportFile::create(&self.bundle, capture))
+            .transpose()?;
 
-        let config = YoukiConfig::from_spec(&spec, container.id())?;
+        let mut config = YoukiConfig::from_spec(&spec, container.id())?;
+        config.volume_helper = self.volume_helper.clone();
+        config.volumes = volumes;
+        config.swap = swap.clone();
+        config.shared_mounts = propagation::shared_mounts(&spec, &self.bundle, &rootfs)?;
+        config.detach_stray_mounts = detach_stray_mounts;
         config.save(&container_dir).map_err(|err| {
             tracing::error!(?container_dir, "failed to save config: {}", err);
             err
         })?;
+        config::save_effective_spec(&spec, &container_dir).map_err(|err| {
+            tracing::error!(?container_dir, "failed to save effective spec: {}", err);
+            err
+        })?;
 
         let mut builder_impl = ContainerBuilderImpl {
             container_type: ContainerType::InitContainer,
@@ -107,7 +269,11 @@ impl InitContainerBuilder {
             rootfs,
             user_ns_config,
             notify_path,
+            notify_listener,
             container: Some(container.clone()),
+            tenant_cgroup: None,
+            threaded_cgroup,
+            cgroup_host: self.cgroup_host,
             preserve_fds: self.base.preserve_fds,
             detached: self.detached,
             executor: self.base.executor,
@@ -116,15 +282,42 @@ impl InitContainerBuilder {
             stdout: self.base.stdout,
             stderr: self.base.stderr,
             as_sibling: self.as_sibling,
+            parent_death,
+            fault_point: self.base.fault_point,
+            timeouts: self.base.timeouts,
+            init_report,
         };
 
-        builder_impl.create()?;
+        let init_pid = builder_impl.create()?;
+        if let Some(mut notifier) = start_notifier {
+            notifier.watch(init_pid);
+            container.start_notifier = Some(Arc::new(notifier));
+        }
+        if let Some(guard) = volume_guard {
+            guard.commit();
+        }
+        if let Some(guard) = swap_guard {
+            guard.commit();
+        }
 
         container.refresh_state()?;
 
         Ok(container)
     }
 
+    fn parent_death(&self, spec: &Spec) -> Result<ParentDeath, LibcontainerError> {
+        let mut parent_death = ParentDeath::from_annotations(spec.annotations().as_ref())?;
+        if let Some(signal) = self.pdeathsig {
+            parent_death.signal = Some(signal);
+        }
+        if let Some(policy) = self.orphan_policy {
+            parent_death.orphan_policy = policy;
+        }
+        parent_death.validate(self.detached, self.as_sibling)?;
+
+        Ok(parent_death)
+    }
+
     fn create_container_dir(&self) -> Result<PathBuf, LibcontainerError> {
         let container_dir = self.base.root_path.join(&self.base.container_id);
         tracing::debug!("container directory will be {:?}", container_dir);
@@ -150,6 +343,26 @@ impl InitContainerBuilder {
         let source_spec_path = self.bundle.join("config.json");
         let mut spec = Spec::load(source_spec_path)?;
         Self::validate_spec(&spec)?;
+        SubtreeControl::from_annotations(spec.annotations().as_ref())?;
+        Prefault::from_spec(&spec)?;
+        if self.volume_helper.is_none() {
+            volume::ensure_no_volumes(&spec)?;
+        }
+        let mut default_rlimits = self.default_rlimits.clone();
+        let core_rlimit = self.debug_capture.and_then(|capture| capture.core_rlimit());
+        if let Some(core_rlimit) = core_rlimit {
+            // an explicit default for cores wins
+            default_rlimits.insert(0, core_rlimit);
+        }
+        rlimit::apply_defaults(&mut spec, &default_rlimits);
+        cpuset_partition::apply(&mut spec)?;
+        oom_group::apply(&mut spec)?;
+        if let Some(timezone) = &self.timezone {
+            timezone::apply(&mut spec, timezone)?;
+        }
+        seccomp_profile::apply(&mut spec, &ProfileCache::in_root(&self.base.root_path))?;
+        hardening::apply(&mut spec)?;
+        seccomp_broker::requested(&spec)?;
 
         spec.canonicalize_rootfs(&self.bundle).map_err(|err| {
             tracing::error!(bundle = ?self.bundle, "failed to canonicalize rootfs: {}", err);
@@ -200,6 +413,14 @@ impl InitContainerBuilder {
             }
         }
 
+        if let Some(linux) = spec.linux() {
+            if let Some(kernel_params) = linux.sysctl() {
+                sysctl::validate(kernel_params, linux.namespaces().as_ref())
+                    .map_err(ErrInvalidSpec::Sysctl)?;
+            }
+        }
+
+        utils::validate_cpu_idle(spec)?;
         utils::validate_spec_for_new_user_ns(spec)?;
 
         Ok(())
@@ -212,7 +433,8 @@ impl InitContainerBuilder {
             None,
             &self.bundle,
             container_dir,
-        )?;
+        )?
+        .with_cgroup_host(self.cgroup_host.clone());
         container.save()?;
         Ok(container)
     }
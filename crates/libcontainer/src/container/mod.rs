@@ -4,8 +4,10 @@
 /// namespaces and cgroups will be created (usually) and a tenant container process that will move
 /// into the existing namespaces and cgroups of the initial container process (e.g. used to implement
 /// the exec command).
+pub mod batch;
 pub mod builder;
 mod builder_impl;
+mod checkpoint_status;
 #[allow(clippy::module_inception)]
 mod container;
 mod container_checkpoint;
@@ -15,9 +17,15 @@ mod container_kill;
 mod container_pause;
 mod container_resume;
 mod container_start;
+mod container_verify;
+pub mod diff;
+pub mod exec_history;
+pub mod finished;
+mod guard;
 pub mod init_builder;
 pub mod state;
 pub mod tenant_builder;
 pub use container::{CheckpointOptions, Container};
-pub use container_checkpoint::CheckpointError;
-pub use state::{ContainerProcessState, ContainerStatus, State};
+pub use container_checkpoint::{check_restore_memory, CheckpointError, MemoryFootprint};
+pub use container_verify::{Finding, FindingCategory};
+pub use state::{Confinement, ContainerProcessState, ContainerStatus, ExecProcess, State};
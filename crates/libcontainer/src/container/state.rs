@@ -7,9 +7,17 @@ use std::io::{BufReader, BufWriter, Write};
 use std::path::{Path, PathBuf};
 
 use chrono::{DateTime, Utc};
+use libcgroups::namespace::{CgroupPaths, ProcessCgroup};
+use nix::fcntl::{Flock, FlockArg};
+use oci_spec::runtime::{LinuxSeccomp, LinuxSeccompAction};
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use tracing::instrument;
 
+use crate::health::Health;
+use crate::process::parent_death::ParentDeath;
+use crate::seccomp_broker::SeccompAgent;
+
 /// Indicates status of the container
 #[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq, Default)]
 #[serde(rename_all = "camelCase")]
@@ -84,6 +92,11 @@ pub enum StateError {
         state_file_path: PathBuf,
         source: std::io::Error,
     },
+    #[error("failed to lock container state file {state_file_path:?}")]
+    LockStateFile {
+        state_file_path: PathBuf,
+        source: nix::Error,
+    },
 }
 
 type Result<T> = std::result::Result<T, StateError>;
@@ -116,6 +129,40 @@ pub struct State {
     pub use_systemd: bool,
     // Specifies if the Intel RDT subdirectory needs be cleaned up.
     pub clean_up_intel_rdt_subdirectory: Option<bool>,
+    // Specifies if the container was paused by sending SIGSTOP to its
+    // processes instead of using the cgroup freezer.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub paused_by_signal: Option<bool>,
+    // Specifies what happens to the container processes when their parent
+    // dies, if it differs from the default.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parent_death: Option<ParentDeath>,
+    // Security profiles the container init runs with.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub confinement: Option<Confinement>,
+    // Processes executed in the container, which may have been running when
+    // the state was saved.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exec_processes: Vec<ExecProcess>,
+    // Append-only history of the processes executed in the container, once
+    // a process was executed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exec_history: Option<PathBuf>,
+    // Health of the container, if youki run checks it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub health: Option<Health>,
+    // Seccomp agent of the seccomp broker, only filled in for display. The
+    // broker keeps it in a file of its own.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seccomp_agent: Option<SeccompAgent>,
+    // Cgroups the init process was in when it created the cgroup namespace
+    // of the container, the roots of the namespace.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cgroup_namespace_roots: Option<Vec<ProcessCgroup>>,
+    // Cgroups of the init process on the host and in the cgroup namespace,
+    // only filled in for display.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub cgroup_paths: Vec<CgroupPaths>,
 }
 
 impl State {
@@ -138,6 +185,15 @@ impl State {
             creator: None,
             use_systemd: false,
             clean_up_intel_rdt_subdirectory: None,
+            paused_by_signal: None,
+            parent_death: None,
+            confinement: None,
+            exec_processes: Vec::new(),
+            exec_history: None,
+            health: None,
+            seccomp_agent: None,
+            cgroup_namespace_roots: None,
+            cgroup_paths: Vec::new(),
         }
     }
 
@@ -218,6 +274,25 @@ impl State {
         Ok(state)
     }
 
+    /// Locks the state file exclusively until the lock is dropped. Updates
+    /// which load the state, change it and save it again while other
+    /// processes may do the same hold the lock, so that no update is lost.
+    pub fn lock(container_root: &Path) -> Result<Flock<File>> {
+        let state_file_path = Self::file_path(container_root);
+        let file = File::open(&state_file_path).map_err(|err| StateError::OpenStateFile {
+            state_file_path: state_file_path.to_owned(),
+            source: err,
+        })?;
+
+        Flock::lock(file, FlockArg::LockExclusive).map_err(|(_, errno)| {
+            tracing::error!(?state_file_path, %errno, "failed to lock container state file");
+            StateError::LockStateFile {
+                state_file_path,
+                source: errno,
+            }
+        })
+    }
+
     /// Returns the path to the state JSON file for the provided `container_root`.
     ///
     /// ```
@@ -233,6 +308,79 @@ impl State {
     }
 }
 
+/// Security profiles a process of the container runs with
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct Confinement {
+    // AppArmor profile, none if the process is not confined by AppArmor.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub apparmor_profile: Option<String>,
+    // Digest of the seccomp profile, none if the process runs without
+    // seccomp. Processes with the same profile have the same digest.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seccomp_digest: Option<String>,
+    // Action of the seccomp profile for syscalls without a rule.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub seccomp_default_action: Option<LinuxSeccompAction>,
+}
+
+impl Confinement {
+    pub fn new(apparmor_profile: Option<&str>, seccomp: Option<&LinuxSeccomp>) -> Self {
+        // without the seccomp feature youki does not load any profile
+        let seccomp = seccomp.filter(|_| cfg!(feature = "libseccomp"));
+        Self {
+            apparmor_profile: apparmor_profile
+                .filter(|profile| !profile.is_empty())
+                .map(str::to_owned),
+            seccomp_digest: seccomp.map(|seccomp| {
+                let profile = serde_json::to_vec(seccomp).unwrap_or_default();
+                format!("sha256:{:x}", Sha256::digest(profile))
+            }),
+            seccomp_default_action: seccomp.map(|seccomp| seccomp.default_action()),
+        }
+    }
+}
+
+/// A process executed in the container
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct ExecProcess {
+    // Pid of the process as seen by the runtime.
+    pub pid: i32,
+    // Start time of the process in clock ticks after boot, which tells the
+    // process apart from a later one with the same pid.
+    pub start_time: u64,
+    // Command line the process was started with.
+    pub args: Vec<String>,
+    // Security profiles the process runs with.
+    pub confinement: Confinement,
+}
+
+impl ExecProcess {
+    /// Returns None if the process already exited
+    pub fn new(pid: i32, args: Vec<String>, confinement: Confinement) -> Option<Self> {
+        let start_time = procfs::process::Process::new(pid)
+            .and_then(|process| process.stat())
+            .ok()?
+            .starttime;
+        Some(Self {
+            pid,
+            start_time,
+            args,
+            confinement,
+        })
+    }
+
+    /// Checks if the process is still running
+    pub fn is_running(&self) -> bool {
+        procfs::process::Process::new(self.pid)
+            .and_then(|process| process.stat())
+            .map_or(false, |stat| {
+                stat.starttime == self.start_time && stat.state != 'Z'
+            })
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, Default)]
 #[serde(rename_all = "camelCase")]
 pub struct ContainerProcessState {
@@ -303,4 +451,80 @@ mod tests {
         assert!(!cstatus.can_pause());
         assert!(cstatus.can_resume());
     }
+
+    #[test]
+    fn test_confinement() {
+        let confinement = Confinement::new(Some(""), None);
+        assert_eq!(confinement, Confinement::default());
+
+        let seccomp = oci_spec::runtime::LinuxSeccompBuilder::default()
+            .default_action(LinuxSeccompAction::ScmpActErrno)
+            .build()
+            .unwrap();
+        let confinement = Confinement::new(Some("youki-default"), Some(&seccomp));
+        assert_eq!(
+            confinement.apparmor_profile.as_deref(),
+            Some("youki-default")
+        );
+        if cfg!(feature = "libseccomp") {
+            assert!(confinement
+                .seccomp_digest
+                .as_ref()
+                .map_or(false, |digest| digest.starts_with("sha256:")));
+            assert_eq!(
+                confinement.seccomp_default_action,
+                Some(LinuxSeccompAction::ScmpActErrno)
+            );
+            assert_eq!(
+                confinement,
+                Confinement::new(Some("youki-default"), Some(&seccomp.clone()))
+            );
+        } else {
+            assert_eq!(confinement.seccomp_digest, None);
+        }
+    }
+
+    #[test]
+    fn test_exec_process_is_running() {
+        let pid = std::process::id() as i32;
+        let process = ExecProcess::new(pid, vec!["sh".to_owned()], Confinement::default()).unwrap();
+        assert!(process.is_running());
+
+        let reused = ExecProcess {
+            start_time: process.start_time + 1,
+            ..process
+        };
+        assert!(!reused.is_running());
+    }
+
+    #[test]
+    fn test_lock() -> anyhow::Result<()> {
+        let tmp = tempfile::tempdir()?;
+        assert!(State::lock(tmp.path()).is_err());
+
+        State::new("c", ContainerStatus::Running, None, PathBuf::from("/b")).save(tmp.path())?;
+        let lock = State::lock(tmp.path())?;
+        let file = File::open(State::file_path(tmp.path()))?;
+        let file = match Flock::lock(file, FlockArg::LockExclusiveNonblock) {
+            Err((file, errno)) => {
+                assert_eq!(errno, nix::errno::Errno::EWOULDBLOCK);
+                file
+            }
+            Ok(_) => anyhow::bail!("the state file is not locked"),
+        };
+        drop(lock);
+        assert!(Flock::lock(file, FlockArg::LockExclusiveNonblock).is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn test_state_without_exec_processes() {
+        let state: State = serde_json::from_str(
+            r#"{"ociVersion":"v1.0.2","id":"c","status":"running","bundle":"/b","useSystemd":false}"#,
+        )
+        .unwrap();
+        assert!(state.exec_processes.is_empty());
+        let json = serde_json::to_string(&state).unwrap();
+        assert!(!json.contains("execProcesses"));
+    }
 }
@@ -9,28 +9,50 @@ use std::rc::Rc;
 use std::str::FromStr;
 
 use caps::Capability;
+use chrono::Utc;
+use libcgroups::v2::manager::INIT_LEAF;
+use libcgroups::v2::placement::{self, PlacementPolicy};
 use nix::fcntl::OFlag;
 use nix::unistd::{pipe2, read, Pid};
 use oci_spec::runtime::{
     Capabilities as SpecCapabilities, Capability as SpecCapability, LinuxBuilder,
     LinuxCapabilities, LinuxCapabilitiesBuilder, LinuxNamespace, LinuxNamespaceBuilder,
-    LinuxNamespaceType, LinuxSchedulerPolicy, Process, ProcessBuilder, Spec,
+    LinuxNamespaceType, LinuxSchedulerPolicy, LinuxSeccomp, Process, ProcessBuilder, Spec,
 };
 use procfs::process::Namespace;
 
 use super::builder::ContainerBuilder;
-use super::Container;
+use super::exec_history::{self, ExecEvent, Invocation};
+use super::{Confinement, Container, ExecProcess};
 use crate::capabilities::CapabilityExt;
 use crate::container::builder_impl::ContainerBuilderImpl;
 use crate::error::{ErrInvalidSpec, LibcontainerError, MissingSpecError};
 use crate::notify_socket::NotifySocket;
 use crate::process::args::ContainerType;
+use crate::process::cgroup_delegation::{self, SubtreeControl};
+use crate::process::parent_death::ParentDeath;
+use crate::seccomp_profile::{self, ProfileCache};
 use crate::user_ns::UserNamespaceConfig;
-use crate::{tty, utils};
+use crate::workload::{ExecFailure, ExecFailureKind};
+use crate::{hardening, tty, utils};
 
-const NAMESPACE_TYPES: &[&str] = &["ipc", "uts", "net", "pid", "mnt", "cgroup"];
+const NAMESPACE_TYPES: &[&str] = &["ipc", "uts", "net", "pid", "mnt", "cgroup", "time"];
 const TENANT_NOTIFY: &str = "tenant-notify-";
 const TENANT_TTY: &str = "tenant-tty-";
+// sub-cgroup of the container picked last by round robin placement
+const CGROUP_PLACEMENT: &str = "cgroup-placement";
+
+/// Seccomp profile of a process which joins a container
+#[derive(Debug, Clone, Default)]
+pub enum TenantSeccomp {
+    /// The profile of the container init
+    #[default]
+    Inherit,
+    /// No seccomp profile at all
+    Unconfined,
+    /// A profile differing from the one of the container init
+    Profile(LinuxSeccomp),
+}
 
 /// Builder that can be used to configure the properties of a process
 /// that will join an existing container sandbox
@@ -44,6 +66,10 @@ pub struct TenantContainerBuilder {
     process: Option<PathBuf>,
     detached: bool,
     as_sibling: bool,
+    terminal: Option<bool>,
+    apparmor_profile: Option<String>,
+    seccomp: TenantSeccomp,
+    cgroup_policy: Option<PlacementPolicy>,
 }
 
 impl TenantContainerBuilder {
@@ -61,6 +87,10 @@ impl TenantContainerBuilder {
             process: None,
             detached: false,
             as_sibling: false,
+            terminal: None,
+            apparmor_profile: None,
+            seccomp: TenantSeccomp::default(),
+            cgroup_policy: None,
         }
     }
 
@@ -109,14 +139,61 @@ impl TenantContainerBuilder {
         self
     }
 
+    /// Sets if the process is attached to a terminal. The pty master is
+    /// sent to the console socket, which has to be set as well.
+    pub fn with_terminal(mut self, terminal: bool) -> Self {
+        self.terminal = Some(terminal);
+        self
+    }
+
+    /// Sets the AppArmor profile of the process. Without it the profile of
+    /// the process.json is used, or the one of the container init if that
+    /// does not set one either.
+    pub fn with_apparmor_profile(mut self, profile: Option<String>) -> Self {
+        self.apparmor_profile = profile;
+        self
+    }
+
+    /// Sets the seccomp profile of the process
+    pub fn with_seccomp(mut self, seccomp: TenantSeccomp) -> Self {
+        self.seccomp = seccomp;
+        self
+    }
+
+    /// Places the process in one of the sub-cgroups of the container cgroup,
+    /// picked by the policy. The sub-cgroups have to be created beforehand,
+    /// e.g. one per job or debug session, so that their load is isolated
+    /// from each other. The leaf of the init process of a delegated cgroup or
+    /// of a cgroup with an init leaf is left out.
+    pub fn with_cgroup_policy(mut self, policy: Option<PlacementPolicy>) -> Self {
+        self.cgroup_policy = policy;
+        self
+    }
+
     /// Joins an existing container
     pub fn build(self) -> Result<Pid, LibcontainerError> {
+        if self.terminal == Some(true) && self.base.console_socket.is_none() {
+            tracing::error!("a terminal was requested without a console socket");
+            return Err(LibcontainerError::Other(
+                "a terminal requires a console socket".into(),
+            ));
+        }
+
         let container_dir = self.lookup_container_dir()?;
         let container = self.load_container_state(container_dir.clone())?;
         let mut spec = self.load_init_spec(&container)?;
         self.adapt_spec_for_tenant(&mut spec, &container)?;
 
         tracing::debug!("{:#?}", spec);
+        let process = spec.process().as_ref().ok_or(MissingSpecError::Process)?;
+        let exec_args = process.args().clone().unwrap_or_default();
+        let invocation = Invocation::new(&exec_args, process.user().uid(), process.user().gid());
+        let confinement = Confinement::new(
+            process.apparmor_profile().as_deref(),
+            spec.linux()
+                .as_ref()
+                .and_then(|linux| linux.seccomp().as_ref()),
+        );
 
         let notify_path = Self::setup_notify_listener(&container_dir)?;
         // convert path of root file system of the container to absolute path
@@ -128,6 +205,7 @@ impl TenantContainerBuilder {
         let csocketfd = self.setup_tty_socket(&container_dir)?;
 
         let use_systemd = self.should_use_systemd(&container);
+        let tenant_cgroup = self.tenant_cgroup(&spec, &container)?;
         let user_ns_config = UserNamespaceConfig::new(&spec)?;
 
         let (read_end, write_end) =
@@ -146,7 +224,11 @@ impl TenantContainerBuilder {
             rootfs,
             user_ns_config,
             notify_path: notify_path.clone(),
-            container: None,
+            notify_listener: None,
+            container: Some(container.clone()),
+            tenant_cgroup,
+            threaded_cgroup: None,
+            cgroup_host: container.cgroup_host.clone(),
             preserve_fds: self.base.preserve_fds,
             detached: self.detached,
             executor: self.base.executor,
@@ -155,6 +237,10 @@ impl TenantContainerBuilder {
             stdout: self.base.stdout,
             stderr: self.base.stderr,
             as_sibling: self.as_sibling,
+            parent_death: ParentDeath::default(),
+            fault_point: self.base.fault_point,
+            timeouts: self.base.timeouts,
+            init_report: None,
         };
 
         let pid = builder_impl.create()?;
@@ -177,11 +263,32 @@ impl TenantContainerBuilder {
             match read(read_end.as_raw_fd(), &mut buf).map_err(LibcontainerError::OtherSyscall)? {
                 0 => {
                     if err_str_buf.is_empty() {
+                        let event = ExecEvent::Started {
+                            pid: pid.as_raw(),
+                            invocation,
+                            time: Utc::now(),
+                        };
+                        let process = ExecProcess::new(pid.as_raw(), exec_args, confinement);
+                        if process.is_none() {
+                            tracing::debug!(?pid, "exec process exited before it was recorded");
+                        }
+                        Self::record_exec(container_dir, event, process);
                         return Ok(pid);
                     } else {
-                        return Err(LibcontainerError::Other(
-                            String::from_utf8_lossy(&err_str_buf).to_string(),
-                        ));
+                        let failure = serde_json::from_slice::<ExecFailure>(&err_str_buf)
+                            .unwrap_or_else(|_| {
+                                ExecFailure::new(
+                                    ExecFailureKind::Other,
+                                    String::from_utf8_lossy(&err_str_buf),
+                                )
+                            });
+                        let event = ExecEvent::Failed {
+                            invocation,
+                            error: failure.message.clone(),
+                            time: Utc::now(),
+                        };
+                        Self::record_exec(container_dir, event, None);
+                        return Err(LibcontainerError::Exec(failure));
                     }
                 }
                 _ => {
@@ -191,6 +298,29 @@ impl TenantContainerBuilder {
         }
     }
 
+    // The exec is decided already, failing to record it must not change its
+    // outcome
+    fn record_exec(container_dir: PathBuf, event: ExecEvent, process: Option<ExecProcess>) {
+        if let Err(err) = exec_history::append(&container_dir, &event) {
+            tracing::warn!(?err, ?event, "failed to record exec in the exec history");
+        }
+
+        // other execs of the container may update its state at the same time
+        let result = Container::load(container_dir).and_then(|mut container| {
+            container.update_state(|container| {
+                if container.state.exec_history.is_none() {
+                    container.set_exec_history(exec_history::history_path(&container.root));
+                }
+                if let Some(process) = process {
+                    container.add_exec_process(process);
+                }
+            })
+        });
+        if let Err(err) = result {
+            tracing::warn!(?err, "failed to record exec process in the container state");
+        }
+    }
+
     fn lookup_container_dir(&self) -> Result<PathBuf, LibcontainerError> {
         let container_dir = self.base.root_path.join(&self.base.container_id);
         if !container_dir.exists() {
@@ -210,6 +340,8 @@ impl TenantContainerBuilder {
         })?;
 
         Self::validate_spec(&spec)?;
+        seccomp_profile::apply(&mut spec, &ProfileCache::in_root(&self.base.root_path))?;
+        hardening::apply(&mut spec)?;
 
         spec.canonicalize_rootfs(container.bundle())?;
         Ok(spec)
@@ -300,6 +432,7 @@ impl TenantContainerBuilder {
             }
         }
 
+        utils::validate_cpu_idle(spec)?;
         utils::validate_spec_for_new_user_ns(spec)?;
 
         Ok(())
@@ -320,7 +453,7 @@ impl TenantContainerBuilder {
         spec: &mut Spec,
         container: &Container,
     ) -> Result<(), LibcontainerError> {
-        let process = if let Some(process) = &self.process {
+        let mut process = if let Some(process) = &self.process {
             self.get_process(process)?
         } else {
             let mut process_builder = ProcessBuilder::default()
@@ -338,6 +471,10 @@ impl TenantContainerBuilder {
                 process_builder = process_builder.capabilities(caps);
             }
 
+            if let Some(terminal) = self.terminal {
+                process_builder = process_builder.terminal(terminal);
+            }
+
             process_builder.build()?
         };
 
@@ -346,7 +483,19 @@ impl TenantContainerBuilder {
         ))?;
 
         let init_process = procfs::process::Process::new(container_pid.as_raw())?;
-        let ns = self.get_namespaces(init_process.namespaces()?.0)?;
+        let own_namespaces = procfs::process::Process::myself()?.namespaces()?.0;
+        let ns = self.get_namespaces(init_process.namespaces()?.0, &own_namespaces)?;
+
+        let apparmor_profile = self
+            .apparmor_profile
+            .clone()
+            .or_else(|| process.apparmor_profile().clone())
+            .or_else(|| {
+                spec.process()
+                    .as_ref()
+                    .and_then(|init| init.apparmor_profile().clone())
+            });
+        process.set_apparmor_profile(apparmor_profile);
 
         // it should never be the case that linux is not present in spec
         let spec_linux = spec.linux().as_ref().unwrap();
@@ -355,12 +504,77 @@ impl TenantContainerBuilder {
         if let Some(ref cgroup_path) = spec_linux.cgroups_path() {
             linux_builder = linux_builder.cgroups_path(cgroup_path.clone());
         }
+        let seccomp = match &self.seccomp {
+            TenantSeccomp::Inherit => spec_linux.seccomp().clone(),
+            TenantSeccomp::Unconfined => None,
+            TenantSeccomp::Profile(seccomp) => Some(seccomp.clone()),
+        };
+        if let Some(seccomp) = seccomp {
+            linux_builder = linux_builder.seccomp(seccomp);
+        }
         let linux = linux_builder.build()?;
         spec.set_process(Some(process)).set_linux(Some(linux));
 
         Ok(())
     }
 
+    // A delegated container cgroup does not accept processes anymore, the
+    // tenant joins the cgroup of the init process instead. With a placement
+    // policy the tenant joins a sub-cgroup of the container cgroup, which is
+    // the parent of the init leaf if the container has one.
+    fn tenant_cgroup(
+        &self,
+        spec: &Spec,
+        container: &Container,
+    ) -> Result<Option<PathBuf>, LibcontainerError> {
+        let delegated = SubtreeControl::from_annotations(spec.annotations().as_ref())?.is_some();
+        if !delegated && self.cgroup_policy.is_none() {
+            return Ok(None);
+        }
+
+        let init_pid = container.pid().ok_or(LibcontainerError::Other(
+            "could not retrieve container init pid".into(),
+        ))?;
+        let init_cgroup = cgroup_delegation::unified_cgroup(init_pid)?;
+        let policy = match self.cgroup_policy {
+            Some(policy) => policy,
+            None => return Ok(Some(init_cgroup)),
+        };
+
+        let in_leaf = delegated || cgroup_delegation::init_leaf(spec.annotations().as_ref())?;
+        let container_cgroup = match in_leaf {
+            true => init_cgroup.parent().unwrap_or(&init_cgroup).to_path_buf(),
+            false => init_cgroup,
+        };
+        let sub_cgroups: Vec<_> = placement::sub_cgroups(&container_cgroup)?
+            .into_iter()
+            .filter(|sub_cgroup| !in_leaf || sub_cgroup.name != INIT_LEAF)
+            .collect();
+
+        let last_file = container.root.join(CGROUP_PLACEMENT);
+        let last = fs::read_to_string(&last_file).ok();
+        let sub_cgroup = placement::place(&sub_cgroups, policy, last.as_deref().map(str::trim))
+            .ok_or_else(|| {
+                tracing::error!(?container_cgroup, %policy, "container cgroup has no sub-cgroup");
+                placement::PlacementError::NoSubCgroup(container_cgroup.clone())
+            })?;
+        tracing::debug!(
+            %policy,
+            cgroup = ?sub_cgroup.path,
+            pids = sub_cgroup.pids,
+            cpu_pressure = sub_cgroup.cpu_pressure,
+            "placing tenant"
+        );
+        if policy == PlacementPolicy::RoundRobin {
+            fs::write(&last_file, &sub_cgroup.name).map_err(|err| {
+                tracing::error!(?err, ?last_file, "failed to record cgroup placement");
+                LibcontainerError::OtherIO(err)
+            })?;
+        }
+
+        Ok(Some(sub_cgroup.path.clone()))
+    }
+
     fn get_process(&self, process: &Path) -> Result<Process, LibcontainerError> {
         if !process.exists() {
             tracing::error!(?process, "process.json file does not exist");
@@ -483,11 +697,22 @@ impl TenantContainerBuilder {
     fn get_namespaces(
         &self,
         init_namespaces: HashMap<OsString, Namespace>,
+        own_namespaces: &HashMap<OsString, Namespace>,
     ) -> Result<Vec<LinuxNamespace>, LibcontainerError> {
         let mut tenant_namespaces = Vec::with_capacity(init_namespaces.len());
 
         for &ns_type in NAMESPACE_TYPES {
             if let Some(init_ns) = init_namespaces.get(OsStr::new(ns_type)) {
+                // Most containers share the time namespace of the host, which
+                // a rootless tenant could not join again from inside its user
+                // namespace. Only a time namespace of the container is joined.
+                let own_ns = own_namespaces.get(OsStr::new(ns_type));
+                if ns_type == "time"
+                    && own_ns.map_or(false, |own_ns| own_ns.identifier == init_ns.identifier)
+                {
+                    continue;
+                }
+
                 let tenant_ns = LinuxNamespaceType::try_from(ns_type)?;
                 tenant_namespaces.push(
                     LinuxNamespaceBuilder::default()
@@ -0,0 +1,73 @@
+//! Exclusive cpus for a container on cgroup v2. The cpuset of the container
+//! cgroup is turned into a partition with `cpuset.cpus.partition` of the
+//! unified map, or with the annotation
+//!
+//! - `org.youki.cpuset.partition`: `member`, `root` or `isolated`.
+//!
+//! The annotation is a shorthand for the unified entry, which the cgroup
+//! manager applies after the cpus of the container. The cpus of an
+//! `isolated` partition are not load balanced, the parent cgroup of the
+//! container has to be a partition root.
+use oci_spec::runtime::Spec;
+
+use crate::unified_annotation::{self, UnifiedConflict};
+
+pub const CPUSET_PARTITION_ANNOTATION: &str = "org.youki.cpuset.partition";
+
+const CGROUP_CPUSET_PARTITION: &str = "cpuset.cpus.partition";
+const PARTITIONS: &[&str] = &["member", "root", "isolated"];
+
+#[derive(Debug, thiserror::Error)]
+pub enum CpusetPartitionError {
+    #[error("invalid cpuset partition {0:?}, expected member, root or isolated")]
+    InvalidPartition(String),
+    #[error(transparent)]
+    Conflict(#[from] UnifiedConflict),
+}
+
+type Result<T> = std::result::Result<T, CpusetPartitionError>;
+
+/// Adds the partition of the annotation to the unified resources of the
+/// spec. Without the annotation this is a no-op.
+pub fn apply(spec: &mut Spec) -> Result<()> {
+    unified_annotation::apply(
+        spec,
+        CPUSET_PARTITION_ANNOTATION,
+        CGROUP_CPUSET_PARTITION,
+        parse,
+    )
+}
+
+fn parse(partition: &str) -> Result<String> {
+    if !PARTITIONS.contains(&partition) {
+        return Err(CpusetPartitionError::InvalidPartition(partition.to_owned()));
+    }
+
+    Ok(partition.to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+
+    use super::*;
+    use crate::unified_annotation::tests::{spec_with, unified_value};
+
+    #[test]
+    fn test_apply_partition() -> Result<()> {
+        let mut spec = spec_with((CPUSET_PARTITION_ANNOTATION, "isolated"), None)?;
+        apply(&mut spec)?;
+        assert_eq!(
+            unified_value(&spec, CGROUP_CPUSET_PARTITION).as_deref(),
+            Some("isolated")
+        );
+
+        let mut spec = spec_with((CPUSET_PARTITION_ANNOTATION, "exclusive"), None)?;
+        assert!(matches!(
+            apply(&mut spec),
+            Err(CpusetPartitionError::InvalidPartition(_))
+        ));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,320 @@
+//! Debug information of container init processes, for containers which were
+//! created but whose process failed right when they were started, e.g. because
+//! of a seccomp profile which kills it, or a missing interpreter of the
+//! process. youki has exited by then, so nobody reports the failure.
+//!
+//! With debug capture the init process keeps a report in `debug/init.json` of
+//! the bundle, which records the last stage it reached:
+//!
+//! - `creating`: the init process was not set up yet,
+//! - `ready`: the container is created and waits to be started,
+//! - `started`: the container was started, the start hooks run,
+//! - `exec`: the process of the container is executed,
+//! - `failed`: the init process failed, the report has the error with its
+//!   causes, the errno of the failed call and the exit code of the init.
+//!
+//! A report left at `started` or `exec` by an init process which is gone
+//! means it was killed by a signal, e.g. SIGSYS by seccomp. With core dumps
+//! enabled as well, the core limit of the container defaults to unlimited
+//! and the init is dumpable once it was started, so that the kernel dumps
+//! its core. Where the core goes is up to `kernel.core_pattern`, which youki
+//! does not change as it applies to the whole host. The report has the
+//! pattern: a pattern which is a relative path is resolved against the
+//! working directory of the process in the container, one starting with `|`
+//! passes the core to a helper of the host.
+//!
+//! A dumpable process may be inspected by other processes of the same user,
+//! so core dumps are meant for debugging only.
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::os::unix::fs::{FileExt, OpenOptionsExt};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use nix::errno::Errno;
+use oci_spec::runtime::{PosixRlimit, PosixRlimitBuilder, PosixRlimitType};
+use serde::{Deserialize, Serialize};
+
+use crate::process::message::{ErrorFrame, ProcessRole};
+use crate::syscall::SyscallError;
+
+pub const DEBUG_DIR: &str = "debug";
+pub const INIT_REPORT_FILE: &str = "init.json";
+
+const CORE_PATTERN: &str = "/proc/sys/kernel/core_pattern";
+
+#[derive(Debug, thiserror::Error)]
+pub enum DebugCaptureError {
+    #[error("failed to create debug directory {path:?}")]
+    CreateDir { path: PathBuf, source: io::Error },
+    #[error("failed to write init report {path:?}")]
+    Write { path: PathBuf, source: io::Error },
+    #[error("failed to make the init process dumpable")]
+    Dumpable(#[source] Errno),
+}
+
+type Result<T> = std::result::Result<T, DebugCaptureError>;
+
+/// What is captured of the init process of a container
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DebugCapture {
+    /// Lets the init process dump its core if it crashes once started
+    pub core_dumps: bool,
+}
+
+impl DebugCapture {
+    /// Core limit which lets the kernel dump the whole core, used if no
+    /// other one is set
+    pub fn core_rlimit(&self) -> Option<PosixRlimit> {
+        if !self.core_dumps {
+            return None;
+        }
+
+        PosixRlimitBuilder::default()
+            .typ(PosixRlimitType::RlimitCore)
+            .soft(libc::RLIM_INFINITY)
+            .hard(libc::RLIM_INFINITY)
+            .build()
+            .ok()
+    }
+}
+
+/// Last stage the init process reached
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum InitStage {
+    Creating,
+    Ready,
+    Started,
+    Exec,
+    Failed,
+}
+
+/// Content of the init report
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct InitReport {
+    pub stage: InitStage,
+    /// Error the init process failed with
+    pub error: Option<String>,
+    /// Causes of the error, the innermost one last
+    #[serde(default)]
+    pub causes: Vec<String>,
+    /// Errno of the failed call, or the last errno of the init process if the
+    /// error carries none
+    pub errno: Option<String>,
+    pub exit_code: Option<i32>,
+    pub core_dumps: bool,
+    /// `kernel.core_pattern` of the host when the container was created
+    pub core_pattern: Option<String>,
+}
+
+impl InitReport {
+    /// Reads the report of the bundle, none if there is none
+    pub fn load(bundle: &Path) -> io::Result<Option<Self>> {
+        let path = bundle.join(DEBUG_DIR).join(INIT_REPORT_FILE);
+        match fs::read(path) {
+            Ok(content) => Ok(Some(serde_json::from_slice(&content)?)),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+}
+
+/// Report which the init process keeps up to date. The file is opened by
+/// youki and passed on to the init process, so that it can write it after it
+/// changed its user and its root.
+#[derive(Debug, Clone)]
+pub struct InitReportFile {
+    file: Rc<File>,
+    report: InitReport,
+}
+
+impl InitReportFile {
+    /// Creates the report in the debug directory of the bundle, replacing the
+    /// one of an earlier container
+    pub fn create(bundle: &Path, capture: DebugCapture) -> Result<Self> {
+        let dir = bundle.join(DEBUG_DIR);
+        fs::create_dir_all(&dir).map_err(|source| DebugCaptureError::CreateDir {
+            path: dir.clone(),
+            source,
+        })?;
+
+        let path = dir.join(INIT_REPORT_FILE);
+        let write_err = |source| DebugCaptureError::Write {
+            path: path.clone(),
+            source,
+        };
+        let file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o644)
+            .open(&path)
+            .map_err(write_err)?;
+
+        let core_pattern = match capture.core_dumps {
+            true => fs::read_to_string(CORE_PATTERN)
+                .map(|pattern| pattern.trim().to_owned())
+                .map_err(|err| tracing::warn!(?err, "failed to read the core pattern"))
+                .ok(),
+            false => None,
+        };
+        let report_file = Self {
+            file: Rc::new(file),
+            report: InitReport {
+                stage: InitStage::Creating,
+                error: None,
+                causes: Vec::new(),
+                errno: None,
+                exit_code: None,
+                core_dumps: capture.core_dumps,
+                core_pattern,
+            },
+        };
+        report_file.write().map_err(write_err)?;
+
+        Ok(report_file)
+    }
+
+    pub fn report(&self) -> &InitReport {
+        &self.report
+    }
+
+    pub(crate) fn as_raw_fd(&self) -> RawFd {
+        self.file.as_raw_fd()
+    }
+
+    /// Records the stage the init process reached. Once started, the init
+    /// process becomes dumpable if core dumps are captured.
+    pub(crate) fn reached(&mut self, stage: InitStage) -> Result<()> {
+        if stage == InitStage::Started && self.report.core_dumps {
+            // changing the user reset it to fs.suid_dumpable
+            prctl::set_dumpable(true)
+                .map_err(|err| DebugCaptureError::Dumpable(Errno::from_raw(err)))?;
+        }
+
+        self.report.stage = stage;
+        self.write_or_warn();
+        Ok(())
+    }
+
+    /// Records the error the init process fails with. `last_errno` is the
+    /// errno right after the failure, before it is overwritten while the
+    /// error is reported.
+    pub(crate) fn failed(
+        &mut self,
+        err: &(dyn std::error::Error + 'static),
+        last_errno: Errno,
+        exit_code: i32,
+    ) {
+        let frame = ErrorFrame::new(ProcessRole::Init, err);
+        let errno = errno_of(err).unwrap_or(last_errno);
+
+        self.report.stage = InitStage::Failed;
+        self.report.error = Some(frame.message);
+        self.report.causes = frame.causes;
+        self.report.errno = (errno != Errno::UnknownErrno).then(|| errno.to_string());
+        self.report.exit_code = Some(exit_code);
+        self.write_or_warn();
+    }
+
+    fn write(&self) -> io::Result<()> {
+        let content = serde_json::to_vec_pretty(&self.report)?;
+        self.file.set_len(0)?;
+        self.file.write_all_at(&content, 0)
+    }
+
+    // the report must never make the init process fail itself
+    fn write_or_warn(&self) {
+        if let Err(err) = self.write() {
+            tracing::warn!(?err, stage = ?self.report.stage, "failed to write init report");
+        }
+    }
+}
+
+// Errno the innermost error of the chain with one carries
+fn errno_of(err: &(dyn std::error::Error + 'static)) -> Option<Errno> {
+    let mut errno = None;
+    let mut source = Some(err);
+    while let Some(err) = source {
+        if let Some(e) = err.downcast_ref::<Errno>() {
+            errno = Some(*e);
+        } else if let Some(SyscallError::Nix(e)) = err.downcast_ref::<SyscallError>() {
+            // transparent, the chain ends with it
+            errno = Some(*e);
+        } else if let Some(raw) = err
+            .downcast_ref::<io::Error>()
+            .and_then(|e| e.raw_os_error())
+        {
+            errno = Some(Errno::from_raw(raw));
+        }
+        source = err.source();
+    }
+    errno
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+
+    use super::*;
+
+    #[derive(Debug, thiserror::Error)]
+    #[error("failed to execute payload")]
+    struct ExecError(#[source] SyscallError);
+
+    #[test]
+    fn test_init_report() -> Result<()> {
+        let bundle = tempfile::tempdir()?;
+        let mut report_file = InitReportFile::create(bundle.path(), DebugCapture::default())?;
+        let report = InitReport::load(bundle.path())?.unwrap();
+        assert_eq!(report.stage, InitStage::Creating);
+        assert!(!report.core_dumps);
+        assert_eq!(report.core_pattern, None);
+
+        report_file.reached(InitStage::Ready)?;
+        assert_eq!(
+            InitReport::load(bundle.path())?.unwrap().stage,
+            InitStage::Ready
+        );
+
+        let err = ExecError(SyscallError::Nix(Errno::ENOENT));
+        report_file.failed(&err, Errno::EINTR, 127);
+        let report = InitReport::load(bundle.path())?.unwrap();
+        assert_eq!(report.stage, InitStage::Failed);
+        assert_eq!(report.error.as_deref(), Some("failed to execute payload"));
+        assert!(!report.causes.is_empty());
+        assert_eq!(report.errno, Some(Errno::ENOENT.to_string()));
+        assert_eq!(report.exit_code, Some(127));
+        assert_eq!(report, *report_file.report());
+
+        // a new container starts with a new report
+        InitReportFile::create(bundle.path(), DebugCapture::default())?;
+        let report = InitReport::load(bundle.path())?.unwrap();
+        assert_eq!(report.stage, InitStage::Creating);
+        assert_eq!(report.error, None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_errno_of() {
+        let err = ExecError(SyscallError::Nix(Errno::EACCES));
+        assert_eq!(errno_of(&err), Some(Errno::EACCES));
+
+        let err = io::Error::from_raw_os_error(libc::ENOEXEC);
+        assert_eq!(errno_of(&err), Some(Errno::ENOEXEC));
+
+        let err = io::Error::new(io::ErrorKind::Other, "no errno");
+        assert_eq!(errno_of(&err), None);
+    }
+
+    #[test]
+    fn test_core_rlimit() {
+        assert!(DebugCapture::default().core_rlimit().is_none());
+        let rlimit = DebugCapture { core_dumps: true }.core_rlimit().unwrap();
+        assert_eq!(rlimit.typ(), PosixRlimitType::RlimitCore);
+        assert_eq!(rlimit.soft(), libc::RLIM_INFINITY);
+    }
+}
@@ -0,0 +1,360 @@
+//! Default mounts of a generated spec. The mounts oci-spec provides are the
+//! same everywhere, which fails on hosts and with images they do not fit:
+//!
+//! - kernels built without POSIX message queues can not mount mqueue,
+//! - rootless containers can neither mount sysfs nor set uid or gid options,
+//! - images without a tty group, e.g. minimal busybox ones, end up with
+//!   ptys owned by a group which does not exist. Alpine and most distros
+//!   use 5, which is the default without a rootfs to look at.
+//!
+//! [`DefaultMounts`] adjusts the mounts to the host, to the rootfs if one is
+//! given, and lets embedders filter or extend them with hooks before the
+//! spec is saved.
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use oci_spec::runtime::{get_default_mounts, Mount};
+
+#[derive(Debug, thiserror::Error)]
+pub enum DefaultMountsError {
+    #[error("mount destination {0:?} is a symlink in the rootfs")]
+    SymlinkDestination(PathBuf),
+    #[error("failed to read {path:?}")]
+    Read {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+}
+
+type Result<T> = std::result::Result<T, DefaultMountsError>;
+
+const FILESYSTEMS: &str = "/proc/filesystems";
+const TTY_GROUP: &str = "tty";
+
+/// What is mounted at /dev/shm
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DevShm {
+    /// A private tmpfs of the given size in bytes
+    Tmpfs(u64),
+    /// Nothing, /dev/shm is then part of the /dev tmpfs
+    None,
+}
+
+impl Default for DevShm {
+    fn default() -> Self {
+        DevShm::Tmpfs(64 * 1024 * 1024)
+    }
+}
+
+type Hook = Box<dyn Fn(&mut Vec<Mount>)>;
+
+/// Builds the default mounts of a spec
+pub struct DefaultMounts {
+    rootless: bool,
+    mqueue: bool,
+    dev_shm: DevShm,
+    rootfs: Option<PathBuf>,
+    hooks: Vec<Hook>,
+}
+
+impl Default for DefaultMounts {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DefaultMounts {
+    /// Mounts for the current host
+    pub fn new() -> Self {
+        Self {
+            rootless: false,
+            mqueue: filesystem_supported(Path::new(FILESYSTEMS), "mqueue"),
+            dev_shm: DevShm::default(),
+            rootfs: None,
+            hooks: Vec::new(),
+        }
+    }
+
+    /// Mounts which work in a user namespace without privileges on the host
+    pub fn with_rootless(mut self, rootless: bool) -> Self {
+        self.rootless = rootless;
+        self
+    }
+
+    /// Overrides whether the kernel supports mqueue
+    pub fn with_mqueue(mut self, mqueue: bool) -> Self {
+        self.mqueue = mqueue;
+        self
+    }
+
+    pub fn with_dev_shm(mut self, dev_shm: DevShm) -> Self {
+        self.dev_shm = dev_shm;
+        self
+    }
+
+    /// Rootfs of the container, used to adapt the mounts to the image
+    pub fn with_rootfs<P: Into<PathBuf>>(mut self, rootfs: Option<P>) -> Self {
+        self.rootfs = rootfs.map(Into::into);
+        self
+    }
+
+    /// Adds a hook which can filter, change or extend the mounts. Hooks run
+    /// in the order they were added, after all other adjustments.
+    pub fn with_hook<F: Fn(&mut Vec<Mount>) + 'static>(mut self, hook: F) -> Self {
+        self.hooks.push(Box::new(hook));
+        self
+    }
+
+    pub fn build(self) -> Result<Vec<Mount>> {
+        let tty_gid = match &self.rootfs {
+            Some(rootfs) => group_id(&rootfs.join("etc/group"), TTY_GROUP)?,
+            None => Some(5),
+        };
+
+        let mut mounts = Vec::new();
+        for mut mount in get_default_mounts() {
+            let destination = mount.destination().clone();
+            if destination == Path::new("/dev/mqueue") && !self.mqueue {
+                continue;
+            }
+            if destination == Path::new("/dev/shm") {
+                match self.dev_shm {
+                    DevShm::None => continue,
+                    DevShm::Tmpfs(size) => {
+                        set_option(&mut mount, "size=", Some(format!("{}k", size / 1024)))
+                    }
+                }
+            }
+            if destination == Path::new("/dev/pts") {
+                set_option(&mut mount, "gid=", tty_gid.map(|gid| gid.to_string()));
+            }
+
+            if self.rootless {
+                if destination == Path::new("/sys") {
+                    mount
+                        .set_source(Some(PathBuf::from("/sys")))
+                        .set_typ(Some(String::from("none")))
+                        .set_options(Some(vec![
+                            "rbind".to_string(),
+                            "nosuid".to_string(),
+                            "noexec".to_string(),
+                            "nodev".to_string(),
+                            "ro".to_string(),
+                        ]));
+                } else {
+                    set_option(&mut mount, "uid=", None);
+                    set_option(&mut mount, "gid=", None);
+                }
+            }
+            mounts.push(mount);
+        }
+
+        for hook in &self.hooks {
+            hook(&mut mounts);
+        }
+        if let Some(rootfs) = &self.rootfs {
+            check_destinations(rootfs, &mounts)?;
+        }
+
+        Ok(mounts)
+    }
+}
+
+/// Replaces the option with the given prefix in place, or removes it without
+/// a value
+fn set_option(mount: &mut Mount, prefix: &str, value: Option<String>) {
+    let mut options = mount.options().clone().unwrap_or_default();
+    let value = value.map(|value| format!("{prefix}{value}"));
+    match (options.iter().position(|o| o.starts_with(prefix)), value) {
+        (Some(i), Some(value)) => options[i] = value,
+        (Some(i), None) => {
+            options.remove(i);
+        }
+        (None, Some(value)) => options.push(value),
+        (None, None) => {}
+    }
+    mount.set_options(Some(options));
+}
+
+fn filesystem_supported(filesystems: &Path, name: &str) -> bool {
+    match fs::read_to_string(filesystems) {
+        Ok(content) => content
+            .lines()
+            .any(|line| line.split_whitespace().last() == Some(name)),
+        Err(err) => {
+            // assume the common case rather than dropping the mount
+            tracing::warn!(?err, ?filesystems, "failed to read supported filesystems");
+            true
+        }
+    }
+}
+
+/// Looks up a group in a group file, a missing file has no groups
+fn group_id(group_file: &Path, name: &str) -> Result<Option<u32>> {
+    let content = match fs::read_to_string(group_file) {
+        Ok(content) => content,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(source) => {
+            return Err(DefaultMountsError::Read {
+                path: group_file.to_owned(),
+                source,
+            })
+        }
+    };
+
+    Ok(content.lines().find_map(|line| {
+        let mut fields = line.split(':');
+        if fields.next() != Some(name) {
+            return None;
+        }
+        fields.nth(1)?.parse().ok()
+    }))
+}
+
+/// Rejects mounts onto symlinks of the rootfs, which would be followed on
+/// the host when the spec is used with a runtime not resolving them in the
+/// rootfs. Destinations below another mount are not part of the rootfs.
+fn check_destinations(rootfs: &Path, mounts: &[Mount]) -> Result<()> {
+    for (i, mount) in mounts.iter().enumerate() {
+        let destination = mount.destination();
+        let covered = mounts[..i]
+            .iter()
+            .any(|earlier| destination.starts_with(earlier.destination()));
+        if covered {
+            continue;
+        }
+
+        let mut path = rootfs.to_path_buf();
+        for component in destination.components().skip(1) {
+            path.push(component);
+            if path.is_symlink() {
+                return Err(DefaultMountsError::SymlinkDestination(
+                    destination.to_owned(),
+                ));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use oci_spec::runtime::MountBuilder;
+
+    use super::*;
+
+    fn find<'a>(mounts: &'a [Mount], destination: &str) -> Option<&'a Mount> {
+        mounts
+            .iter()
+            .find(|mount| mount.destination() == Path::new(destination))
+    }
+
+    fn options(mount: &Mount) -> Vec<String> {
+        mount.options().clone().unwrap_or_default()
+    }
+
+    #[test]
+    fn test_default_mounts() -> Result<()> {
+        let mounts = DefaultMounts::new().with_mqueue(true).build()?;
+        assert_eq!(mounts, get_default_mounts());
+
+        let mounts = DefaultMounts::new()
+            .with_mqueue(false)
+            .with_dev_shm(DevShm::Tmpfs(1 << 20))
+            .build()?;
+        assert!(find(&mounts, "/dev/mqueue").is_none());
+        assert!(options(find(&mounts, "/dev/shm").unwrap()).contains(&"size=1024k".to_owned()));
+
+        let mounts = DefaultMounts::new().with_dev_shm(DevShm::None).build()?;
+        assert!(find(&mounts, "/dev/shm").is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_rootless_mounts() -> Result<()> {
+        let mounts = DefaultMounts::new().with_rootless(true).build()?;
+        let sys = find(&mounts, "/sys").unwrap();
+        assert_eq!(sys.typ().as_deref(), Some("none"));
+        assert!(options(sys).contains(&"rbind".to_owned()));
+        assert!(mounts
+            .iter()
+            .flat_map(options)
+            .all(|option| !option.starts_with("gid=") && !option.starts_with("uid=")));
+        Ok(())
+    }
+
+    #[test]
+    fn test_rootfs_tty_group() -> Result<()> {
+        let rootfs = tempfile::tempdir()?;
+        let mounts = DefaultMounts::new()
+            .with_rootfs(Some(rootfs.path()))
+            .build()?;
+        assert!(options(find(&mounts, "/dev/pts").unwrap())
+            .iter()
+            .all(|option| !option.starts_with("gid=")));
+
+        fs::create_dir(rootfs.path().join("etc"))?;
+        fs::write(
+            rootfs.path().join("etc/group"),
+            "root:x:0:root\nttyusers:x:6:\ntty:x:7:\n",
+        )?;
+        let mounts = DefaultMounts::new()
+            .with_rootfs(Some(rootfs.path()))
+            .build()?;
+        assert!(options(find(&mounts, "/dev/pts").unwrap()).contains(&"gid=7".to_owned()));
+        Ok(())
+    }
+
+    #[test]
+    fn test_symlink_destination() -> Result<()> {
+        let rootfs = tempfile::tempdir()?;
+        // below the /dev tmpfs, so not part of the rootfs
+        fs::create_dir(rootfs.path().join("dev"))?;
+        std::os::unix::fs::symlink("/run/shm", rootfs.path().join("dev/shm"))?;
+        DefaultMounts::new()
+            .with_rootfs(Some(rootfs.path()))
+            .build()?;
+
+        std::os::unix::fs::symlink("/", rootfs.path().join("proc"))?;
+        assert!(matches!(
+            DefaultMounts::new().with_rootfs(Some(rootfs.path())).build(),
+            Err(DefaultMountsError::SymlinkDestination(path)) if path == Path::new("/proc")
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_hooks() -> Result<()> {
+        let mounts = DefaultMounts::new()
+            .with_hook(|mounts| mounts.retain(|mount| mount.destination() != Path::new("/sys")))
+            .with_hook(|mounts| {
+                mounts.push(
+                    MountBuilder::default()
+                        .destination("/run")
+                        .typ("tmpfs")
+                        .source("tmpfs")
+                        .build()
+                        .unwrap(),
+                )
+            })
+            .build()?;
+        assert!(find(&mounts, "/sys").is_none());
+        assert_eq!(mounts.last().unwrap().destination(), Path::new("/run"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_filesystem_supported() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let filesystems = tmp.path().join("filesystems");
+        fs::write(&filesystems, "nodev\tsysfs\nnodev\tmqueue\n\text4\n")?;
+        assert!(filesystem_supported(&filesystems, "mqueue"));
+        assert!(filesystem_supported(&filesystems, "ext4"));
+        assert!(!filesystem_supported(&filesystems, "devpts"));
+        // unknown without the file
+        assert!(filesystem_supported(&tmp.path().join("missing"), "mqueue"));
+        Ok(())
+    }
+}
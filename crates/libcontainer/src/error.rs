@@ -1,3 +1,8 @@
+use crate::process::channel::ChannelError;
+use crate::process::container_main_process::ProcessError;
+use crate::process::timeouts::Phase;
+use crate::workload::{ExecFailure, ExecFailureKind};
+
 #[derive(Debug, thiserror::Error)]
 pub enum MissingSpecError {
     #[error("missing process in spec")]
@@ -24,6 +29,12 @@ pub enum LibcontainerError {
     NoExecutors,
     #[error("rootless container requires valid user namespace definition")]
     NoUserNamespace,
+    #[error("the freezer cgroup controller is not available on this host")]
+    FreezerUnavailable,
+    #[error("{0}")]
+    Exec(ExecFailure),
+    #[error("timed out in the {0} phase")]
+    Timeout(Phase),
 
     // Invalid inputs
     #[error(transparent)]
@@ -37,6 +48,8 @@ pub enum LibcontainerError {
     #[error(transparent)]
     Tty(#[from] crate::tty::TTYError),
     #[error(transparent)]
+    ConsoleTee(#[from] crate::console_tee::ConsoleTeeError),
+    #[error(transparent)]
     UserNamespace(#[from] crate::user_ns::UserNamespaceError),
     #[error(transparent)]
     NotifyListener(#[from] crate::notify_socket::NotifyListenerError),
@@ -45,7 +58,39 @@ pub enum LibcontainerError {
     #[error(transparent)]
     Hook(#[from] crate::hooks::HookError),
     #[error(transparent)]
+    Volume(#[from] crate::volume::VolumeError),
+    #[error(transparent)]
+    Timezone(#[from] crate::timezone::TimezoneError),
+    #[error(transparent)]
+    Swap(#[from] crate::swap::SwapError),
+    #[error(transparent)]
+    Propagation(#[from] crate::propagation::PropagationError),
+    #[error(transparent)]
+    CpusetPartition(#[from] crate::cpuset_partition::CpusetPartitionError),
+    #[error(transparent)]
+    DebugCapture(#[from] crate::debug_capture::DebugCaptureError),
+    #[error(transparent)]
+    OomGroup(#[from] crate::oom_group::OomGroupError),
+    #[error(transparent)]
+    Hardening(#[from] crate::hardening::HardeningError),
+    #[error(transparent)]
+    Prefault(#[from] crate::prefault::PrefaultError),
+    #[error(transparent)]
+    SeccompProfile(#[from] crate::seccomp_profile::SeccompProfileError),
+    #[error(transparent)]
+    SeccompBroker(#[from] crate::seccomp_broker::SeccompBrokerError),
+    #[error(transparent)]
+    ParentDeath(#[from] crate::process::parent_death::ParentDeathError),
+    #[error(transparent)]
+    CgroupDelegation(#[from] crate::process::cgroup_delegation::CgroupDelegationError),
+    #[error(transparent)]
     State(#[from] crate::container::state::StateError),
+    #[error(transparent)]
+    Finished(#[from] crate::container::finished::FinishedError),
+    #[error(transparent)]
+    Diff(#[from] crate::container::diff::DiffError),
+    #[error(transparent)]
+    ExecHistory(#[from] crate::container::exec_history::ExecHistoryError),
     #[error("oci spec error")]
     Spec(#[from] oci_spec::OciSpecError),
     #[error(transparent)]
@@ -60,6 +105,10 @@ pub enum LibcontainerError {
     CgroupCreate(#[from] libcgroups::common::CreateCgroupSetupError),
     #[error(transparent)]
     CgroupGet(#[from] libcgroups::common::GetCgroupSetupError),
+    #[error(transparent)]
+    CgroupPlacement(#[from] libcgroups::v2::placement::PlacementError),
+    #[error(transparent)]
+    CgroupNamespace(#[from] libcgroups::namespace::NamespaceError),
     #[error[transparent]]
     Checkpoint(#[from] crate::container::CheckpointError),
     #[error[transparent]]
@@ -78,6 +127,30 @@ pub enum LibcontainerError {
     Other(String),
 }
 
+impl LibcontainerError {
+    /// Returns why the process of the container could not be executed, if
+    /// the error is about that
+    pub fn exec_failure_kind(&self) -> Option<ExecFailureKind> {
+        match self {
+            Self::Exec(failure)
+            | Self::MainProcess(ProcessError::Channel(ChannelError::ExecError(failure))) => {
+                Some(failure.kind)
+            }
+            Self::CreateContainerError(CreateContainerError(err, _)) => err.exec_failure_kind(),
+            _ => None,
+        }
+    }
+
+    /// Returns the phase which got stuck, if the error is a timeout
+    pub fn timeout_phase(&self) -> Option<Phase> {
+        match self {
+            Self::Timeout(phase) | Self::MainProcess(ProcessError::Timeout(phase)) => Some(*phase),
+            Self::CreateContainerError(CreateContainerError(err, _)) => err.timeout_phase(),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum ErrInvalidID {
     #[error("container id can't be empty")]
@@ -86,6 +159,8 @@ pub enum ErrInvalidID {
     InvalidChars(char),
     #[error("container id can't be used to represent a file name (such as . or ..)")]
     FileName,
+    #[error("container id {0} is reserved for a directory of the root path")]
+    Reserved(String),
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -98,6 +173,8 @@ pub enum ErrInvalidSpec {
     IoPriority,
     #[error("invalid scheduler config for process")]
     Scheduler,
+    #[error(transparent)]
+    Sysctl(#[from] crate::sysctl::SysctlError),
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -126,7 +203,7 @@ impl std::fmt::Display for CreateContainerError {
 mod tests {
     use libcgroups::common::CreateCgroupSetupError;
 
-    use super::{CreateContainerError, ErrInvalidID};
+    use super::*;
 
     #[test]
     fn test_create_container() {
@@ -149,4 +226,31 @@ mod tests {
             msg
         );
     }
+
+    #[test]
+    fn test_exec_failure_kind() {
+        let not_found = || ExecFailure::new(ExecFailureKind::NotFound, "not found");
+
+        let err = LibcontainerError::Exec(not_found());
+        assert_eq!(err.exec_failure_kind(), Some(ExecFailureKind::NotFound));
+
+        let err = LibcontainerError::MainProcess(ProcessError::Channel(ChannelError::ExecError(
+            not_found(),
+        )));
+        assert_eq!(err.exec_failure_kind(), Some(ExecFailureKind::NotFound));
+
+        let err = LibcontainerError::CreateContainerError(CreateContainerError::new(
+            LibcontainerError::Exec(ExecFailure::new(
+                ExecFailureKind::NotExecutable,
+                "not executable",
+            )),
+            None,
+        ));
+        assert_eq!(
+            err.exec_failure_kind(),
+            Some(ExecFailureKind::NotExecutable)
+        );
+
+        assert_eq!(LibcontainerError::NoDirectory.exec_failure_kind(), None);
+    }
 }
@@ -0,0 +1,117 @@
+//! Fault injection for testing how the creation and start of a container
+//! handle failures at points which can not be triggered from the outside.
+//!
+//! A failure point is selected with [`ContainerBuilder::with_fault_injection`]
+//! or, for the youki binary, with the [`FAULT_INJECTION_ENV`] environment
+//! variable. Both only take effect when the `fault-injection` feature is
+//! enabled, which is meant for test builds only.
+//!
+//! [`ContainerBuilder::with_fault_injection`]: crate::container::builder::ContainerBuilder
+use std::fmt;
+use std::str::FromStr;
+
+/// Environment variable selecting the failure point, e.g.
+/// `YOUKI_FAULT_INJECTION=after-cgroup-create`
+pub const FAULT_INJECTION_ENV: &str = "YOUKI_FAULT_INJECTION";
+
+/// Point in the setup of a container process at which it fails
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FaultPoint {
+    /// Once the container process has been added to the cgroup of the
+    /// container
+    AfterCgroupCreate,
+    /// In the init process, once the rootfs has been prepared and before
+    /// the process changed its root
+    AfterRootfsPrepare,
+    /// In the init process, right before the executor is called after the
+    /// container was started
+    BeforeExec,
+}
+
+const FAULT_POINTS: &[(FaultPoint, &str)] = &[
+    (FaultPoint::AfterCgroupCreate, "after-cgroup-create"),
+    (FaultPoint::AfterRootfsPrepare, "after-rootfs-prepare"),
+    (FaultPoint::BeforeExec, "before-exec"),
+];
+
+#[derive(Debug, thiserror::Error)]
+#[error("unknown fault injection point {0:?}")]
+pub struct UnknownFaultPoint(String);
+
+#[derive(Debug, thiserror::Error)]
+#[error("injected fault {0}")]
+pub struct InjectedFault(pub FaultPoint);
+
+impl fmt::Display for FaultPoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = FAULT_POINTS
+            .iter()
+            .find(|(point, _)| point == self)
+            .map(|(_, name)| *name)
+            .unwrap_or_default();
+        f.write_str(name)
+    }
+}
+
+impl FromStr for FaultPoint {
+    type Err = UnknownFaultPoint;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        FAULT_POINTS
+            .iter()
+            .find(|(_, name)| *name == s)
+            .map(|(point, _)| *point)
+            .ok_or_else(|| UnknownFaultPoint(s.to_owned()))
+    }
+}
+
+/// Reads the failure point from [`FAULT_INJECTION_ENV`]. Always `None`
+/// without the `fault-injection` feature. An invalid value is logged and
+/// ignored, so that a typo does not silently pass as a successful test.
+pub fn from_env() -> Option<FaultPoint> {
+    if !cfg!(feature = "fault-injection") {
+        return None;
+    }
+
+    let value = std::env::var(FAULT_INJECTION_ENV).ok()?;
+    match value.parse() {
+        Ok(point) => Some(point),
+        Err(err) => {
+            tracing::error!(?err, "ignoring {}", FAULT_INJECTION_ENV);
+            None
+        }
+    }
+}
+
+/// Fails if the configured failure point is the current one
+pub fn inject(configured: Option<FaultPoint>, point: FaultPoint) -> Result<(), InjectedFault> {
+    if cfg!(feature = "fault-injection") && configured == Some(point) {
+        tracing::warn!(%point, "injecting fault");
+        return Err(InjectedFault(point));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fault_point_names() {
+        for (point, name) in FAULT_POINTS {
+            assert_eq!(point.to_string(), *name);
+            assert_eq!(name.parse::<FaultPoint>().unwrap(), *point);
+        }
+        assert!("after-cgroup".parse::<FaultPoint>().is_err());
+    }
+
+    #[test]
+    fn test_inject() {
+        assert!(inject(None, FaultPoint::BeforeExec).is_ok());
+        assert!(inject(Some(FaultPoint::AfterCgroupCreate), FaultPoint::BeforeExec).is_ok());
+        assert_eq!(
+            inject(Some(FaultPoint::BeforeExec), FaultPoint::BeforeExec).is_err(),
+            cfg!(feature = "fault-injection")
+        );
+    }
+}
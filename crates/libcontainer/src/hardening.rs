@@ -0,0 +1,455 @@
+//! Hardening presets, curated restrictions applied on top of the spec with
+//! the annotation
+//!
+//! - `io.youki.hardening`: `baseline` or `restricted`.
+//!
+//! A preset adds masked and read-only paths below `/proc` and `/sys`, limits
+//! the sysctls the spec may set and adds a seccomp rule which denies
+//! syscalls a container rarely needs with EPERM. `restricted` has everything
+//! of `baseline` and more.
+//!
+//! The presets compose with the spec instead of overriding it: a path the
+//! spec masks is not made read-only and the other way around, a syscall which
+//! the spec mentions in any rule of `linux.seccomp` keeps the action of the
+//! spec, and without `linux.seccomp` a filter which allows everything else is
+//! added. A sysctl of the spec which the preset does not allow fails the
+//! create, as the preset can't drop it silently. For the same reason a
+//! preset fails the create of a youki built without seccomp support.
+use std::fmt::{self, Display};
+use std::str::FromStr;
+
+use oci_spec::runtime::{LinuxSeccompAction, LinuxSeccompBuilder, LinuxSyscallBuilder, Spec};
+use oci_spec::OciSpecError;
+
+use crate::sysctl;
+
+pub const HARDENING_ANNOTATION: &str = "io.youki.hardening";
+
+#[derive(Debug, thiserror::Error)]
+pub enum HardeningError {
+    #[error("invalid hardening preset {0:?}, expected baseline or restricted")]
+    InvalidPreset(String),
+    #[error("sysctl {key:?} is not allowed by the {preset} hardening preset")]
+    Sysctl { key: String, preset: Preset },
+    #[error("failed to build the seccomp rule of the hardening preset")]
+    Seccomp(#[source] OciSpecError),
+    #[error("the {0} hardening preset requires seccomp, but youki was built without it")]
+    SeccompUnavailable(Preset),
+}
+
+type Result<T> = std::result::Result<T, HardeningError>;
+
+/// Hardening preset of the annotation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Preset {
+    Baseline,
+    Restricted,
+}
+
+impl FromStr for Preset {
+    type Err = HardeningError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim() {
+            "baseline" => Ok(Self::Baseline),
+            "restricted" => Ok(Self::Restricted),
+            _ => Err(HardeningError::InvalidPreset(s.to_owned())),
+        }
+    }
+}
+
+impl Display for Preset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let preset = match self {
+            Self::Baseline => "baseline",
+            Self::Restricted => "restricted",
+        };
+        write!(f, "{preset}")
+    }
+}
+
+const BASELINE_MASKED_PATHS: &[&str] = &[
+    "/proc/acpi",
+    "/proc/asound",
+    "/proc/kcore",
+    "/proc/keys",
+    "/proc/latency_stats",
+    "/proc/sched_debug",
+    "/proc/scsi",
+    "/proc/timer_list",
+    "/proc/timer_stats",
+    "/sys/devices/virtual/powercap",
+    "/sys/firmware",
+];
+
+const RESTRICTED_MASKED_PATHS: &[&str] = &[
+    "/proc/interrupts",
+    "/proc/kallsyms",
+    "/proc/kmsg",
+    "/proc/modules",
+    "/sys/kernel/debug",
+    "/sys/kernel/security",
+];
+
+const BASELINE_READONLY_PATHS: &[&str] = &[
+    "/proc/bus",
+    "/proc/fs",
+    "/proc/irq",
+    "/proc/sys",
+    "/proc/sysrq-trigger",
+];
+
+const RESTRICTED_READONLY_PATHS: &[&str] = &["/proc/driver", "/proc/tty"];
+
+/// Sysctls `baseline` denies, which let a container route or accept traffic
+/// of other hosts through its network namespace. `*` matches one component.
+const BASELINE_DENIED_SYSCTLS: &[&str] = &[
+    "net.ipv4.ip_forward",
+    "net.ipv4.conf.*.forwarding",
+    "net.ipv4.conf.*.route_localnet",
+    "net.ipv6.conf.*.forwarding",
+];
+
+/// The only sysctls `restricted` allows, the safe set of kubernetes
+const RESTRICTED_ALLOWED_SYSCTLS: &[&str] = &[
+    "kernel.shm_rmid_forced",
+    "net.ipv4.ip_local_port_range",
+    "net.ipv4.ip_local_reserved_ports",
+    "net.ipv4.ip_unprivileged_port_start",
+    "net.ipv4.ping_group_range",
+    "net.ipv4.tcp_fin_timeout",
+    "net.ipv4.tcp_keepalive_intvl",
+    "net.ipv4.tcp_keepalive_probes",
+    "net.ipv4.tcp_keepalive_time",
+    "net.ipv4.tcp_syncookies",
+];
+
+const BASELINE_DENIED_SYSCALLS: &[&str] = &[
+    "acct",
+    "delete_module",
+    "finit_module",
+    "init_module",
+    "kexec_file_load",
+    "kexec_load",
+    "open_by_handle_at",
+    "reboot",
+    "swapoff",
+    "swapon",
+];
+
+const RESTRICTED_DENIED_SYSCALLS: &[&str] = &[
+    "add_key",
+    "bpf",
+    "keyctl",
+    "perf_event_open",
+    "ptrace",
+    "request_key",
+    "userfaultfd",
+];
+
+impl Preset {
+    /// Preset of the annotation, None without one
+    pub fn from_spec(spec: &Spec) -> Result<Option<Self>> {
+        spec.annotations()
+            .as_ref()
+            .and_then(|annotations| annotations.get(HARDENING_ANNOTATION))
+            .map(|preset| preset.parse())
+            .transpose()
+    }
+
+    pub fn masked_paths(self) -> Vec<&'static str> {
+        self.with_restricted(BASELINE_MASKED_PATHS, RESTRICTED_MASKED_PATHS)
+    }
+
+    pub fn readonly_paths(self) -> Vec<&'static str> {
+        self.with_restricted(BASELINE_READONLY_PATHS, RESTRICTED_READONLY_PATHS)
+    }
+
+    pub fn denied_syscalls(self) -> Vec<&'static str> {
+        self.with_restricted(BASELINE_DENIED_SYSCALLS, RESTRICTED_DENIED_SYSCALLS)
+    }
+
+    /// Whether the preset lets the spec set the sysctl
+    pub fn allows_sysctl(self, key: &str) -> bool {
+        let key = sysctl::normalize(key);
+        match self {
+            Self::Baseline => !BASELINE_DENIED_SYSCTLS
+                .iter()
+                .any(|pattern| matches_sysctl(pattern, &key)),
+            Self::Restricted => RESTRICTED_ALLOWED_SYSCTLS.contains(&key.as_str()),
+        }
+    }
+
+    fn with_restricted(
+        self,
+        baseline: &[&'static str],
+        restricted: &[&'static str],
+    ) -> Vec<&'static str> {
+        let mut entries = baseline.to_vec();
+        if self == Self::Restricted {
+            entries.extend_from_slice(restricted);
+        }
+        entries
+    }
+}
+
+fn matches_sysctl(pattern: &str, key: &str) -> bool {
+    let mut pattern = pattern.split('.');
+    let mut key = key.split('.');
+    loop {
+        match (pattern.next(), key.next()) {
+            (None, None) => return true,
+            (Some(p), Some(k)) if p == "*" || p == k => continue,
+            _ => return false,
+        }
+    }
+}
+
+/// Applies the preset of the annotation to the spec. Without the annotation
+/// this is a no-op.
+pub fn apply(spec: &mut Spec) -> Result<()> {
+    let preset = match Preset::from_spec(spec)? {
+        Some(preset) => preset,
+        None => return Ok(()),
+    };
+    tracing::debug!(%preset, "applying hardening preset");
+
+    let mut linux = spec.linux().clone().unwrap_or_default();
+    if let Some(key) = linux
+        .sysctl()
+        .iter()
+        .flat_map(|sysctls| sysctls.keys())
+        .find(|key| !preset.allows_sysctl(key))
+    {
+        tracing::error!(?key, %preset, "sysctl is not allowed by the hardening preset");
+        return Err(HardeningError::Sysctl {
+            key: key.to_owned(),
+            preset,
+        });
+    }
+    if !cfg!(feature = "libseccomp") {
+        tracing::error!(%preset, "hardening preset requires seccomp support");
+        return Err(HardeningError::SeccompUnavailable(preset));
+    }
+
+    let mut masked = linux.masked_paths().clone().unwrap_or_default();
+    let mut readonly = linux.readonly_paths().clone().unwrap_or_default();
+    let listed = |path: &str, masked: &[String], readonly: &[String]| {
+        masked.iter().chain(readonly).any(|listed| listed == path)
+    };
+    for path in preset.masked_paths() {
+        if !listed(path, &masked, &readonly) {
+            masked.push(path.to_owned());
+        }
+    }
+    for path in preset.readonly_paths() {
+        if !listed(path, &masked, &readonly) {
+            readonly.push(path.to_owned());
+        }
+    }
+    linux.set_masked_paths(Some(masked));
+    linux.set_readonly_paths(Some(readonly));
+
+    let mut seccomp = match linux.seccomp() {
+        Some(seccomp) => seccomp.clone(),
+        None => LinuxSeccompBuilder::default()
+            .default_action(LinuxSeccompAction::ScmpActAllow)
+            .build()
+            .map_err(HardeningError::Seccomp)?,
+    };
+    let mut syscalls = seccomp.syscalls().clone().unwrap_or_default();
+    let names: Vec<String> = preset
+        .denied_syscalls()
+        .into_iter()
+        .filter(|name| {
+            !syscalls
+                .iter()
+                .any(|syscall| syscall.names().iter().any(|listed| listed == name))
+        })
+        .map(String::from)
+        .collect();
+    if !names.is_empty() {
+        syscalls.push(
+            LinuxSyscallBuilder::default()
+                .names(names)
+                .action(LinuxSeccompAction::ScmpActErrno)
+                .errno_ret(libc::EPERM as u32)
+                .build()
+                .map_err(HardeningError::Seccomp)?,
+        );
+    }
+    seccomp.set_syscalls(Some(syscalls));
+    linux.set_seccomp(Some(seccomp));
+
+    spec.set_linux(Some(linux));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use anyhow::Result;
+    #[cfg(feature = "libseccomp")]
+    use oci_spec::runtime::LinuxSeccomp;
+    use oci_spec::runtime::{LinuxBuilder, SpecBuilder};
+
+    use super::*;
+
+    fn spec(preset: Option<&str>, linux: LinuxBuilder) -> Result<Spec> {
+        let mut annotations = HashMap::new();
+        if let Some(preset) = preset {
+            annotations.insert(HARDENING_ANNOTATION.to_owned(), preset.to_owned());
+        }
+        Ok(SpecBuilder::default()
+            .annotations(annotations)
+            .linux(linux.build()?)
+            .build()?)
+    }
+
+    #[cfg(feature = "libseccomp")]
+    fn strings(values: &[&str]) -> Vec<String> {
+        values.iter().map(|value| value.to_string()).collect()
+    }
+
+    #[cfg(feature = "libseccomp")]
+    fn denied(seccomp: &LinuxSeccomp) -> Vec<String> {
+        seccomp
+            .syscalls()
+            .iter()
+            .flatten()
+            .filter(|syscall| syscall.action() == LinuxSeccompAction::ScmpActErrno)
+            .flat_map(|syscall| syscall.names().clone())
+            .collect()
+    }
+
+    #[test]
+    fn test_preset() {
+        assert_eq!("baseline".parse::<Preset>().unwrap(), Preset::Baseline);
+        assert_eq!(" restricted".parse::<Preset>().unwrap(), Preset::Restricted);
+        assert!(matches!(
+            "strict".parse::<Preset>(),
+            Err(HardeningError::InvalidPreset(_))
+        ));
+        for entries in [
+            Preset::Baseline.masked_paths(),
+            Preset::Baseline.readonly_paths(),
+            Preset::Baseline.denied_syscalls(),
+        ] {
+            assert!(!entries.is_empty());
+        }
+        assert!(Preset::Restricted
+            .masked_paths()
+            .starts_with(&Preset::Baseline.masked_paths()));
+    }
+
+    #[test]
+    fn test_allows_sysctl() {
+        assert!(Preset::Baseline.allows_sysctl("net.ipv4.tcp_syncookies"));
+        assert!(Preset::Baseline.allows_sysctl("kernel.msgmax"));
+        assert!(!Preset::Baseline.allows_sysctl("net.ipv4.ip_forward"));
+        assert!(!Preset::Baseline.allows_sysctl("net/ipv4/conf/eth0/forwarding"));
+        assert!(Preset::Baseline.allows_sysctl("net.ipv4.conf.eth0.rp_filter"));
+        assert!(Preset::Restricted.allows_sysctl("net.ipv4.ping_group_range"));
+        assert!(!Preset::Restricted.allows_sysctl("kernel.msgmax"));
+    }
+
+    #[test]
+    fn test_apply_without_annotation() -> Result<()> {
+        let mut spec = spec(None, LinuxBuilder::default())?;
+        let original = spec.clone();
+        apply(&mut spec)?;
+        assert_eq!(spec, original);
+        Ok(())
+    }
+
+    #[cfg(feature = "libseccomp")]
+    #[test]
+    fn test_apply_composes_with_spec() -> Result<()> {
+        let user_rule = LinuxSyscallBuilder::default()
+            .names(strings(&["ptrace", "getcwd"]))
+            .action(LinuxSeccompAction::ScmpActAllow)
+            .build()?;
+        let mut spec = spec(
+            Some("restricted"),
+            LinuxBuilder::default()
+                .masked_paths(strings(&["/proc/sys", "/data/secret"]))
+                .readonly_paths(strings(&["/proc/kcore"]))
+                .sysctl(HashMap::from([(
+                    "net.ipv4.tcp_syncookies".to_owned(),
+                    "1".to_owned(),
+                )]))
+                .seccomp(
+                    LinuxSeccompBuilder::default()
+                        .default_action(LinuxSeccompAction::ScmpActErrno)
+                        .syscalls(vec![user_rule.clone()])
+                        .build()?,
+                ),
+        )?;
+        apply(&mut spec)?;
+
+        let linux = spec.linux().as_ref().unwrap();
+        let masked = linux.masked_paths().as_ref().unwrap();
+        let readonly = linux.readonly_paths().as_ref().unwrap();
+        // the paths of the spec stay where the spec put them
+        assert_eq!(masked[..2], strings(&["/proc/sys", "/data/secret"]));
+        assert_eq!(readonly[0], "/proc/kcore");
+        assert!(!masked.contains(&"/proc/kcore".to_owned()));
+        assert!(!readonly.contains(&"/proc/sys".to_owned()));
+        assert!(masked.contains(&"/proc/kallsyms".to_owned()));
+        assert!(readonly.contains(&"/proc/sysrq-trigger".to_owned()));
+        assert_eq!(linux.sysctl().as_ref().unwrap().len(), 1);
+
+        let seccomp = linux.seccomp().as_ref().unwrap();
+        assert_eq!(seccomp.default_action(), LinuxSeccompAction::ScmpActErrno);
+        assert_eq!(seccomp.syscalls().as_ref().unwrap()[0], user_rule);
+        let denied = denied(seccomp);
+        assert!(denied.contains(&"kexec_load".to_owned()));
+        assert!(denied.contains(&"bpf".to_owned()));
+        assert!(!denied.contains(&"ptrace".to_owned()));
+
+        // applying the preset again adds nothing
+        let hardened = spec.clone();
+        apply(&mut spec)?;
+        assert_eq!(spec, hardened);
+        Ok(())
+    }
+
+    #[cfg(feature = "libseccomp")]
+    #[test]
+    fn test_apply_adds_seccomp() -> Result<()> {
+        let mut spec = spec(Some("baseline"), LinuxBuilder::default())?;
+        apply(&mut spec)?;
+        let seccomp = spec.linux().as_ref().unwrap().seccomp().clone().unwrap();
+        assert_eq!(seccomp.default_action(), LinuxSeccompAction::ScmpActAllow);
+        assert_eq!(denied(&seccomp), strings(BASELINE_DENIED_SYSCALLS));
+        Ok(())
+    }
+
+    #[cfg(feature = "libseccomp")]
+    #[test]
+    fn test_apply_rejects_sysctl() -> Result<()> {
+        let linux = || {
+            LinuxBuilder::default().sysctl(HashMap::from([(
+                "kernel.msgmax".to_owned(),
+                "65536".to_owned(),
+            )]))
+        };
+        apply(&mut spec(Some("baseline"), linux())?)?;
+        assert!(matches!(
+            apply(&mut spec(Some("restricted"), linux())?),
+            Err(HardeningError::Sysctl { key, preset: Preset::Restricted }) if key == "kernel.msgmax"
+        ));
+        Ok(())
+    }
+
+    #[cfg(not(feature = "libseccomp"))]
+    #[test]
+    fn test_apply_without_seccomp() -> Result<()> {
+        assert!(matches!(
+            apply(&mut spec(Some("baseline"), LinuxBuilder::default())?),
+            Err(HardeningError::SeccompUnavailable(Preset::Baseline))
+        ));
+        Ok(())
+    }
+}
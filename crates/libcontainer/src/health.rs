@@ -0,0 +1,285 @@
+//! Health checks of containers supervised by `youki run`, for standalone
+//! deployments which want a health status like the one of docker. The check
+//! is configured with annotations:
+//!
+//! - `org.youki.health.cmd`: command run with `/bin/sh -c` in the container,
+//! - `org.youki.health.interval`: seconds between the checks, 30 by default,
+//! - `org.youki.health.retries`: failed checks in a row after which the
+//!   container is unhealthy, 3 by default.
+//!
+//! The command is executed in the namespaces of the container like with
+//! `youki exec`. It succeeds if it exits with 0, a check which runs longer
+//! than the interval fails. The health is kept in `state.json`, and each
+//! change of the status is appended as an event to `health-events.jsonl` of
+//! the container state directory.
+use std::collections::HashMap;
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+pub const HEALTH_CMD_ANNOTATION: &str = "org.youki.health.cmd";
+pub const HEALTH_INTERVAL_ANNOTATION: &str = "org.youki.health.interval";
+pub const HEALTH_RETRIES_ANNOTATION: &str = "org.youki.health.retries";
+
+pub const HEALTH_EVENTS_FILE: &str = "health-events.jsonl";
+
+const DEFAULT_INTERVAL: Duration = Duration::from_secs(30);
+const DEFAULT_RETRIES: u32 = 3;
+
+#[derive(Debug, thiserror::Error)]
+pub enum HealthError {
+    #[error("invalid health check annotation {annotation}: {value:?}")]
+    InvalidAnnotation {
+        annotation: &'static str,
+        value: String,
+    },
+    #[error("health check annotations without the command of org.youki.health.cmd")]
+    MissingCommand,
+    #[error("failed to write health event to {path:?}")]
+    WriteEvent { path: PathBuf, source: io::Error },
+}
+
+type Result<T> = std::result::Result<T, HealthError>;
+
+/// Health check of the annotations
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HealthCheck {
+    /// Arguments of the process executed in the container
+    pub args: Vec<String>,
+    pub interval: Duration,
+    pub retries: u32,
+}
+
+impl HealthCheck {
+    /// Health check configured by the annotations, if there is one
+    pub fn from_annotations(annotations: Option<&HashMap<String, String>>) -> Result<Option<Self>> {
+        let annotations = match annotations {
+            Some(annotations) => annotations,
+            None => return Ok(None),
+        };
+        let command = match annotations.get(HEALTH_CMD_ANNOTATION) {
+            Some(command) if !command.trim().is_empty() => command,
+            Some(command) => {
+                return Err(HealthError::InvalidAnnotation {
+                    annotation: HEALTH_CMD_ANNOTATION,
+                    value: command.to_owned(),
+                })
+            }
+            None if annotations.contains_key(HEALTH_INTERVAL_ANNOTATION)
+                || annotations.contains_key(HEALTH_RETRIES_ANNOTATION) =>
+            {
+                return Err(HealthError::MissingCommand)
+            }
+            None => return Ok(None),
+        };
+
+        let interval = match annotations.get(HEALTH_INTERVAL_ANNOTATION) {
+            Some(value) => match value.trim().parse::<u64>() {
+                Ok(secs) if secs > 0 => Duration::from_secs(secs),
+                _ => {
+                    return Err(HealthError::InvalidAnnotation {
+                        annotation: HEALTH_INTERVAL_ANNOTATION,
+                        value: value.to_owned(),
+                    })
+                }
+            },
+            None => DEFAULT_INTERVAL,
+        };
+        let retries = match annotations.get(HEALTH_RETRIES_ANNOTATION) {
+            Some(value) => match value.trim().parse::<u32>() {
+                Ok(retries) if retries > 0 => retries,
+                _ => {
+                    return Err(HealthError::InvalidAnnotation {
+                        annotation: HEALTH_RETRIES_ANNOTATION,
+                        value: value.to_owned(),
+                    })
+                }
+            },
+            None => DEFAULT_RETRIES,
+        };
+
+        Ok(Some(Self {
+            args: vec!["/bin/sh".to_owned(), "-c".to_owned(), command.to_owned()],
+            interval,
+            retries,
+        }))
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum HealthStatus {
+    /// No check has succeeded yet, and there were fewer failures than retries
+    Starting,
+    Healthy,
+    Unhealthy,
+}
+
+/// Health of the container in `state.json`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct Health {
+    pub status: HealthStatus,
+    /// Checks which failed in a row
+    pub failing_streak: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_check: Option<DateTime<Utc>>,
+    /// Exit code of the last check, none if it timed out
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_exit_code: Option<i32>,
+}
+
+impl Default for Health {
+    fn default() -> Self {
+        Self {
+            status: HealthStatus::Starting,
+            failing_streak: 0,
+            last_check: None,
+            last_exit_code: None,
+        }
+    }
+}
+
+impl Health {
+    /// Records the result of a check, the exit code of the command or none
+    /// if it timed out. Returns whether the status changed.
+    pub fn record(&mut self, exit_code: Option<i32>, retries: u32, at: DateTime<Utc>) -> bool {
+        let previous = self.status;
+        self.last_check = Some(at);
+        self.last_exit_code = exit_code;
+        if exit_code == Some(0) {
+            self.failing_streak = 0;
+            self.status = HealthStatus::Healthy;
+        } else {
+            self.failing_streak += 1;
+            if self.failing_streak >= retries {
+                self.status = HealthStatus::Unhealthy;
+            }
+        }
+        self.status != previous
+    }
+}
+
+/// Line of `health-events.jsonl`, in the format of the events of runc
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct HealthEvent {
+    #[serde(rename = "type")]
+    pub typ: String,
+    pub id: String,
+    pub time: DateTime<Utc>,
+    pub data: Health,
+}
+
+/// Appends an event with the health of the container to its events file
+pub fn append_event(container_root: &Path, id: &str, health: &Health) -> Result<()> {
+    let path = container_root.join(HEALTH_EVENTS_FILE);
+    let event = HealthEvent {
+        typ: "health".to_owned(),
+        id: id.to_owned(),
+        time: Utc::now(),
+        data: health.clone(),
+    };
+    let write_err = |source| HealthError::WriteEvent {
+        path: path.clone(),
+        source,
+    };
+    let mut line = serde_json::to_vec(&event).map_err(|err| write_err(err.into()))?;
+    line.push(b'\n');
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .and_then(|mut file| file.write_all(&line))
+        .map_err(write_err)
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+
+    use super::*;
+
+    #[test]
+    fn test_health_check_from_annotations() -> Result<()> {
+        assert_eq!(HealthCheck::from_annotations(None)?, None);
+        assert_eq!(HealthCheck::from_annotations(Some(&HashMap::new()))?, None);
+
+        let mut annotations = HashMap::from([(
+            HEALTH_CMD_ANNOTATION.to_owned(),
+            "curl -f http://localhost/".to_owned(),
+        )]);
+        assert_eq!(
+            HealthCheck::from_annotations(Some(&annotations))?,
+            Some(HealthCheck {
+                args: vec![
+                    "/bin/sh".to_owned(),
+                    "-c".to_owned(),
+                    "curl -f http://localhost/".to_owned()
+                ],
+                interval: DEFAULT_INTERVAL,
+                retries: DEFAULT_RETRIES,
+            })
+        );
+
+        annotations.insert(HEALTH_INTERVAL_ANNOTATION.to_owned(), "5".to_owned());
+        annotations.insert(HEALTH_RETRIES_ANNOTATION.to_owned(), "1".to_owned());
+        let check = HealthCheck::from_annotations(Some(&annotations))?.unwrap();
+        assert_eq!(check.interval, Duration::from_secs(5));
+        assert_eq!(check.retries, 1);
+
+        annotations.insert(HEALTH_INTERVAL_ANNOTATION.to_owned(), "0".to_owned());
+        assert!(matches!(
+            HealthCheck::from_annotations(Some(&annotations)),
+            Err(HealthError::InvalidAnnotation { .. })
+        ));
+
+        annotations.remove(HEALTH_CMD_ANNOTATION);
+        assert!(matches!(
+            HealthCheck::from_annotations(Some(&annotations)),
+            Err(HealthError::MissingCommand)
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_health_record() {
+        let now = Utc::now();
+        let mut health = Health::default();
+        assert!(!health.record(Some(1), 2, now));
+        assert_eq!(health.status, HealthStatus::Starting);
+        assert!(health.record(None, 2, now));
+        assert_eq!(health.status, HealthStatus::Unhealthy);
+        assert_eq!(health.failing_streak, 2);
+        assert_eq!(health.last_exit_code, None);
+
+        assert!(health.record(Some(0), 2, now));
+        assert_eq!(health.status, HealthStatus::Healthy);
+        assert_eq!(health.failing_streak, 0);
+        assert!(!health.record(Some(1), 2, now));
+        assert_eq!(health.status, HealthStatus::Healthy);
+    }
+
+    #[test]
+    fn test_append_event() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let health = Health::default();
+        append_event(tmp.path(), "web", &health)?;
+        append_event(tmp.path(), "web", &health)?;
+
+        let content = std::fs::read_to_string(tmp.path().join(HEALTH_EVENTS_FILE))?;
+        let events = content
+            .lines()
+            .map(serde_json::from_str)
+            .collect::<std::result::Result<Vec<HealthEvent>, _>>()?;
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].typ, "health");
+        assert_eq!(events[0].id, "web");
+        assert_eq!(events[0].data, health);
+        Ok(())
+    }
+}
@@ -2,11 +2,12 @@ use std::collections::HashMap;
 use std::io::{ErrorKind, Write};
 use std::os::unix::prelude::CommandExt;
 use std::path::Path;
+use std::time::Instant;
 use std::{process, thread, time};
 
 use nix::sys::signal;
 use nix::unistd::Pid;
-use oci_spec::runtime::Hook;
+use oci_spec::runtime::{Hook, Hooks};
 
 use crate::container::Container;
 use crate::utils;
@@ -23,6 +24,8 @@ pub enum HookError {
     Killed,
     #[error("failed to execute hook command due to a timeout")]
     Timeout,
+    #[error("hook command did not finish before the deadline")]
+    DeadlineExceeded,
     #[error("container state is required to run hook")]
     MissingContainerState,
     #[error("failed to write container state to stdin")]
@@ -31,10 +34,142 @@ pub enum HookError {
 
 type Result<T> = std::result::Result<T, HookError>;
 
+/// The hooks defined by the OCI runtime spec.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HookStage {
+    Prestart,
+    CreateRuntime,
+    CreateContainer,
+    StartContainer,
+    Poststart,
+    Poststop,
+}
+
+/// The points of the container lifecycle at which the runtime runs hooks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LifecyclePoint {
+    /// In the runtime namespace, once the container process exists and its
+    /// namespaces are set up, but before the createContainer hooks.
+    Created,
+    /// In the container namespaces, once the mounts of the rootfs are set up
+    /// but before pivot_root and the readonly and masked paths.
+    ContainerCreated,
+    /// In the container namespaces, right before the user process is executed.
+    ContainerStarting,
+    /// In the runtime namespace, after the user process was started.
+    Started,
+    /// In the runtime namespace, after the container was deleted.
+    Deleted,
+}
+
+/// The order in which hook stages run. It matches runc, which runs the
+/// deprecated prestart hooks right before the createRuntime hooks.
+const LIFECYCLE: &[(LifecyclePoint, HookStage)] = &[
+    (LifecyclePoint::Created, HookStage::Prestart),
+    (LifecyclePoint::Created, HookStage::CreateRuntime),
+    (LifecyclePoint::ContainerCreated, HookStage::CreateContainer),
+    (LifecyclePoint::ContainerStarting, HookStage::StartContainer),
+    (LifecyclePoint::Started, HookStage::Poststart),
+    (LifecyclePoint::Deleted, HookStage::Poststop),
+];
+
+impl HookStage {
+    /// The name of the stage as used in config.json.
+    pub fn name(self) -> &'static str {
+        match self {
+            HookStage::Prestart => "prestart",
+            HookStage::CreateRuntime => "createRuntime",
+            HookStage::CreateContainer => "createContainer",
+            HookStage::StartContainer => "startContainer",
+            HookStage::Poststart => "poststart",
+            HookStage::Poststop => "poststop",
+        }
+    }
+
+    pub fn is_deprecated(self) -> bool {
+        matches!(self, HookStage::Prestart)
+    }
+
+    // prestart is deprecated in the OCI spec, but docker still relies on it
+    #[allow(deprecated)]
+    fn hooks(self, hooks: &Hooks) -> Option<&Vec<Hook>> {
+        match self {
+            HookStage::Prestart => hooks.prestart().as_ref(),
+            HookStage::CreateRuntime => hooks.create_runtime().as_ref(),
+            HookStage::CreateContainer => hooks.create_container().as_ref(),
+            HookStage::StartContainer => hooks.start_container().as_ref(),
+            HookStage::Poststart => hooks.poststart().as_ref(),
+            HookStage::Poststop => hooks.poststop().as_ref(),
+        }
+    }
+}
+
+/// Returns the stages which run at the lifecycle point, in the order they run.
+pub fn stages(point: LifecyclePoint) -> impl Iterator<Item = HookStage> {
+    LIFECYCLE
+        .iter()
+        .filter(move |(p, _)| *p == point)
+        .map(|(_, stage)| *stage)
+}
+
+/// Runs the hooks of every stage belonging to the lifecycle point.
+pub fn run_lifecycle_hooks(
+    point: LifecyclePoint,
+    hooks: Option<&Hooks>,
+    container: Option<&Container>,
+    cwd: Option<&Path>,
+) -> Result<()> {
+    run_lifecycle_hooks_until(point, hooks, container, cwd, None)
+}
+
+/// Runs the hooks like [`run_lifecycle_hooks`], but kills a hook which is
+/// still running at the deadline, even if the hook has no timeout itself.
+pub fn run_lifecycle_hooks_until(
+    point: LifecyclePoint,
+    hooks: Option<&Hooks>,
+    container: Option<&Container>,
+    cwd: Option<&Path>,
+    deadline: Option<Instant>,
+) -> Result<()> {
+    let Some(hooks) = hooks else {
+        return Ok(());
+    };
+
+    for stage in stages(point) {
+        let stage_hooks = stage.hooks(hooks);
+        if stage.is_deprecated() {
+            if let Some(stage_hooks) = stage_hooks.filter(|h| !h.is_empty()) {
+                tracing::warn!(
+                    hook_stage = stage.name(),
+                    count = stage_hooks.len(),
+                    "deprecated hooks are configured, use createRuntime, createContainer \
+                     or startContainer instead"
+                );
+            }
+        }
+
+        run_hooks_until(stage_hooks, container, cwd, deadline).map_err(|err| {
+            tracing::error!(hook_stage = stage.name(), ?err, "failed to run hooks");
+            err
+        })?;
+    }
+
+    Ok(())
+}
+
 pub fn run_hooks(
     hooks: Option<&Vec<Hook>>,
     container: Option<&Container>,
     cwd: Option<&Path>,
+) -> Result<()> {
+    run_hooks_until(hooks, container, cwd, None)
+}
+
+fn run_hooks_until(
+    hooks: Option<&Vec<Hook>>,
+    container: Option<&Container>,
+    cwd: Option<&Path>,
+    deadline: Option<Instant>,
 ) -> Result<()> {
     let state = &(container.ok_or(HookError::MissingContainerState)?.state);
 
@@ -96,7 +231,21 @@ pub fn run_hooks(
                 }
             }
 
-            let res = if let Some(timeout_sec) = hook.timeout() {
+            let hook_timeout = hook
+                .timeout()
+                .map(|timeout_sec| time::Duration::from_secs(timeout_sec as u64));
+            let remaining =
+                deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()));
+            // The deadline ends the hook if it comes before its own timeout
+            let (timeout, timeout_err) = match (hook_timeout, remaining) {
+                (Some(timeout), Some(remaining)) if remaining < timeout => {
+                    (Some(remaining), HookError::DeadlineExceeded)
+                }
+                (None, Some(remaining)) => (Some(remaining), HookError::DeadlineExceeded),
+                (timeout, _) => (timeout, HookError::Timeout),
+            };
+
+            let res = if let Some(timeout) = timeout {
                 // Rust does not make it easy to handle executing a command and
                 // timeout. Here we decided to wait for the command in a
                 // different thread, so the main thread is not blocked. We use a
@@ -112,13 +261,13 @@ pub fn run_hooks(
                     let res = hook_process.wait();
                     let _ = s.send(res);
                 });
-                match r.recv_timeout(time::Duration::from_secs(timeout_sec as u64)) {
+                match r.recv_timeout(timeout) {
                     Ok(res) => res,
                     Err(std::sync::mpsc::RecvTimeoutError::Timeout) => {
                         // Kill the process. There is no need to further clean
                         // up because we will be error out.
                         let _ = signal::kill(hook_process_pid, signal::Signal::SIGKILL);
-                        return Err(HookError::Timeout);
+                        return Err(timeout_err);
                     }
                     Err(_) => {
                         unreachable!();
@@ -147,7 +296,7 @@ mod test {
     use std::{env, fs};
 
     use anyhow::{bail, Context, Result};
-    use oci_spec::runtime::HookBuilder;
+    use oci_spec::runtime::{HookBuilder, HooksBuilder};
     use serial_test::serial;
 
     use super::*;
@@ -231,6 +380,66 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn test_lifecycle_order() {
+        let order: Vec<HookStage> = [
+            LifecyclePoint::Created,
+            LifecyclePoint::ContainerCreated,
+            LifecyclePoint::ContainerStarting,
+            LifecyclePoint::Started,
+            LifecyclePoint::Deleted,
+        ]
+        .into_iter()
+        .flat_map(stages)
+        .collect();
+
+        assert_eq!(
+            order,
+            vec![
+                HookStage::Prestart,
+                HookStage::CreateRuntime,
+                HookStage::CreateContainer,
+                HookStage::StartContainer,
+                HookStage::Poststart,
+                HookStage::Poststop,
+            ]
+        );
+    }
+
+    #[test]
+    #[serial]
+    fn test_run_lifecycle_hooks_order() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let log = tmp.path().join("log");
+        let log_hook = |name: &str| {
+            HookBuilder::default()
+                .path("bash")
+                .args(vec![
+                    String::from("bash"),
+                    String::from("-c"),
+                    format!("echo {name} >> {}", log.display()),
+                ])
+                .build()
+        };
+        #[allow(deprecated)]
+        let hooks = oci_spec::runtime::HooksBuilder::default()
+            .create_runtime(vec![log_hook("createRuntime")?])
+            .prestart(vec![log_hook("prestart")?])
+            .create_container(vec![log_hook("createContainer")?])
+            .build()?;
+
+        let default_container: Container = Default::default();
+        run_lifecycle_hooks(
+            LifecyclePoint::Created,
+            Some(&hooks),
+            Some(&default_container),
+            None,
+        )?;
+        assert_eq!(fs::read_to_string(&log)?, "prestart\ncreateRuntime\n");
+
+        Ok(())
+    }
+
     #[test]
     #[serial]
     // This will test executing hook with a timeout. Since the timeout is set in
@@ -263,4 +472,34 @@ mod test {
 
         Ok(())
     }
+
+    #[test]
+    #[serial]
+    fn test_run_hook_deadline() -> Result<()> {
+        let default_container: Container = Default::default();
+        // The hook has no timeout of its own, the deadline ends it
+        let hook = HookBuilder::default()
+            .path("tail")
+            .args(vec![
+                String::from("tail"),
+                String::from("-f"),
+                String::from("/dev/null"),
+            ])
+            .build()?;
+        let hooks = HooksBuilder::default().create_runtime(vec![hook]).build()?;
+        let deadline = Instant::now() + time::Duration::from_millis(200);
+        let result = run_lifecycle_hooks_until(
+            LifecyclePoint::Created,
+            Some(&hooks),
+            Some(&default_container),
+            None,
+            Some(deadline),
+        );
+        assert!(
+            matches!(result, Err(HookError::DeadlineExceeded)),
+            "expected the hook to exceed the deadline, got {result:?}"
+        );
+
+        Ok(())
+    }
 }
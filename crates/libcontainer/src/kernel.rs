@@ -0,0 +1,254 @@
+//! Kernel version and feature detection. Features are probed the first time
+//! they are asked for and the result is cached for the rest of the process,
+//! so code which depends on a feature asks here instead of probing with a
+//! syscall of its own. Probing a syscall tells apart kernels which lack it
+//! from filters which block it, e.g. seccomp profiles of a surrounding
+//! container returning ENOSYS, which the version alone can not.
+use std::fmt::{self, Display};
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+use nix::errno::Errno;
+use once_cell::sync::{Lazy, OnceCell};
+
+#[derive(Debug, thiserror::Error)]
+#[error("invalid kernel release {0:?}")]
+pub struct ParseKernelVersionError(String);
+
+/// Version of the running kernel, without the distribution specific suffix
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct KernelVersion {
+    pub major: u32,
+    pub minor: u32,
+    pub patch: u32,
+}
+
+impl KernelVersion {
+    pub const fn new(major: u32, minor: u32, patch: u32) -> Self {
+        Self {
+            major,
+            minor,
+            patch,
+        }
+    }
+}
+
+impl FromStr for KernelVersion {
+    type Err = ParseKernelVersionError;
+
+    /// Parses a release as reported by uname, e.g. 6.8.0-51-generic
+    fn from_str(release: &str) -> Result<Self, Self::Err> {
+        let err = || ParseKernelVersionError(release.to_owned());
+        let numbers: Vec<&str> = release
+            .split(|c: char| !c.is_ascii_digit() && c != '.')
+            .next()
+            .unwrap_or_default()
+            .split('.')
+            .take(3)
+            .collect();
+        let number = |i: usize| -> Result<u32, Self::Err> {
+            match numbers.get(i) {
+                Some(n) if !n.is_empty() => n.parse().map_err(|_| err()),
+                // releases like 4.19 have no patch level
+                _ if i == 2 => Ok(0),
+                _ => Err(err()),
+            }
+        };
+
+        Ok(Self::new(number(0)?, number(1)?, number(2)?))
+    }
+}
+
+impl Display for KernelVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+static VERSION: Lazy<Option<KernelVersion>> = Lazy::new(|| {
+    let uname = nix::sys::utsname::uname()
+        .map_err(|err| tracing::warn!(?err, "failed to get kernel release"))
+        .ok()?;
+    let release = uname.release().to_string_lossy();
+    release
+        .parse()
+        .map_err(|err| tracing::warn!(?err, "failed to parse kernel release"))
+        .ok()
+});
+
+/// Returns the version of the running kernel, None if it is unknown
+pub fn version() -> Option<KernelVersion> {
+    *VERSION
+}
+
+/// Returns if the running kernel is at least the given version. An unknown
+/// version is assumed to be recent enough, the feature in question then
+/// fails where it is used instead of being refused up front.
+pub fn at_least(major: u32, minor: u32) -> bool {
+    version().map_or(true, |version| {
+        version >= KernelVersion::new(major, minor, 0)
+    })
+}
+
+/// Kernel features which youki uses if they are available
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Feature {
+    /// clone3(2), since 5.3
+    Clone3,
+    /// openat2(2), since 5.6
+    Openat2,
+    /// close_range(2) with CLOSE_RANGE_CLOEXEC, since 5.11
+    CloseRange,
+    /// mount_setattr(2), since 5.12
+    MountSetattr,
+    /// killing all processes of a cgroup with cgroup.kill, since 5.14
+    CgroupKill,
+}
+
+impl Feature {
+    pub const ALL: [Feature; 5] = [
+        Feature::Clone3,
+        Feature::Openat2,
+        Feature::CloseRange,
+        Feature::MountSetattr,
+        Feature::CgroupKill,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Feature::Clone3 => "clone3",
+            Feature::Openat2 => "openat2",
+            Feature::CloseRange => "close_range",
+            Feature::MountSetattr => "mount_setattr",
+            Feature::CgroupKill => "cgroup.kill",
+        }
+    }
+
+    fn probe(&self) -> bool {
+        match self {
+            // Each syscall is called with arguments which the kernel rejects
+            // before doing anything, so only ENOSYS means it is missing.
+            Feature::Clone3 => syscall_exists(|| unsafe { libc::syscall(libc::SYS_clone3, 0, 0) }),
+            Feature::Openat2 => syscall_exists(|| unsafe {
+                libc::syscall(libc::SYS_openat2, libc::AT_FDCWD, 0, 0, 0)
+            }),
+            // Closing the highest possible fd with CLOSE_RANGE_CLOEXEC only
+            // succeeds if the flag is known as well.
+            Feature::CloseRange => unsafe {
+                libc::syscall(
+                    libc::SYS_close_range,
+                    libc::c_uint::MAX,
+                    libc::c_uint::MAX,
+                    libc::CLOSE_RANGE_CLOEXEC,
+                ) == 0
+            },
+            Feature::MountSetattr => {
+                syscall_exists(|| unsafe { libc::syscall(libc::SYS_mount_setattr, -1, 0, 0, 0, 0) })
+            }
+            Feature::CgroupKill => cgroup_kill_exists(),
+        }
+    }
+
+    fn cache(&self) -> &'static OnceCell<bool> {
+        static CACHE: [OnceCell<bool>; 5] = [
+            OnceCell::new(),
+            OnceCell::new(),
+            OnceCell::new(),
+            OnceCell::new(),
+            OnceCell::new(),
+        ];
+        &CACHE[*self as usize]
+    }
+}
+
+impl Display for Feature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Returns if the kernel supports the feature and it can be used by this
+/// process
+pub fn has(feature: Feature) -> bool {
+    *feature.cache().get_or_init(|| {
+        let available = feature.probe();
+        tracing::debug!(%feature, available, "probed kernel feature");
+        available
+    })
+}
+
+fn syscall_exists<F: FnOnce() -> libc::c_long>(call: F) -> bool {
+    call() != -1 || Errno::last() != Errno::ENOSYS
+}
+
+fn cgroup_kill_exists() -> bool {
+    const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+    // The root cgroup has no cgroup.kill, so look at the cgroup of this
+    // process and only fall back to the version in the root cgroup.
+    let own_cgroup = fs::read_to_string("/proc/self/cgroup")
+        .ok()
+        .and_then(|content| {
+            content
+                .lines()
+                .find_map(|line| line.strip_prefix("0::").map(str::to_owned))
+        });
+    match own_cgroup.as_deref() {
+        Some("/") => {
+            Path::new(CGROUP_ROOT).join("cgroup.controllers").exists()
+                && version().map_or(false, |version| version >= KernelVersion::new(5, 14, 0))
+        }
+        Some(cgroup) => Path::new(CGROUP_ROOT)
+            .join(cgroup.trim_start_matches('/'))
+            .join("cgroup.kill")
+            .exists(),
+        // no unified hierarchy
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_kernel_version() {
+        let cases = [
+            ("6.8.0-51-generic", KernelVersion::new(6, 8, 0)),
+            (
+                "5.15.167.4-microsoft-standard-WSL2",
+                KernelVersion::new(5, 15, 167),
+            ),
+            ("4.19", KernelVersion::new(4, 19, 0)),
+            ("6.12.1+rpt-rpi-v8", KernelVersion::new(6, 12, 1)),
+            ("3.10.0-1160.el7.x86_64", KernelVersion::new(3, 10, 0)),
+        ];
+        for (release, expected) in cases {
+            assert_eq!(
+                release.parse::<KernelVersion>().unwrap(),
+                expected,
+                "{release}"
+            );
+        }
+
+        for invalid in ["", "6", "linux", "a.b.c"] {
+            assert!(invalid.parse::<KernelVersion>().is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn test_kernel_version_order() {
+        assert!(KernelVersion::new(5, 14, 0) > KernelVersion::new(5, 9, 21));
+        assert!(KernelVersion::new(6, 0, 0) > KernelVersion::new(5, 19, 3));
+        assert_eq!(KernelVersion::new(5, 4, 1).to_string(), "5.4.1");
+    }
+
+    #[test]
+    fn test_features_are_cached() {
+        assert!(version().is_some());
+        for feature in Feature::ALL {
+            assert_eq!(has(feature), feature.cache().get().copied().unwrap());
+            assert_eq!(has(feature), has(feature));
+        }
+    }
+}
@@ -1,22 +1,56 @@
+//! Library to create and manage OCI containers on Linux.
+//!
+//! # Thread safety
+//!
+//! Containers may be created and operated on from several threads of one
+//! process at the same time. The calling process keeps its working
+//! directory, environment, umask and signal dispositions: everything which
+//! changes them runs in the forked container processes only. Sockets with
+//! paths too long for a socket address are reached through
+//! [`utils::SocketPath`] rather than by changing the working directory.
+//!
+//! The only process wide change is that creating a container which joins or
+//! creates namespaces makes the calling process non-dumpable, so that
+//! processes in those namespaces cannot access it. Builders are not `Send`,
+//! every thread creates its containers with a builder of its own.
 pub mod apparmor;
 pub mod capabilities;
 pub mod channel;
 pub mod config;
+pub mod console_tee;
 pub mod container;
+pub mod cpuset_partition;
+pub mod debug_capture;
+pub mod default_mounts;
 pub mod error;
+pub mod fault_injection;
+pub mod hardening;
+pub mod health;
 pub mod hooks;
+pub mod kernel;
 pub mod namespaces;
 pub mod notify_socket;
+pub mod oom_group;
+pub mod prefault;
 pub mod process;
+pub mod propagation;
+pub mod rlimit;
 pub mod rootfs;
 #[cfg(feature = "libseccomp")]
 pub mod seccomp;
+pub mod seccomp_broker;
+pub mod seccomp_profile;
 pub mod signal;
+pub mod swap;
 pub mod syscall;
+pub mod sysctl;
 pub mod test_utils;
+pub mod timezone;
 pub mod tty;
+pub mod unified_annotation;
 pub mod user_ns;
 pub mod utils;
+pub mod volume;
 pub mod workload;
 
 // Because the `libcontainer` api uses the oci_spec who resides in a different
@@ -5,7 +5,8 @@
 //! Interprocess Communication (Control or communication between processes),
 //! Network (which network devices can be seen by the processes in the namespace), User (User configs),
 //! UTS (hostname and domain information, processes will think they're running on servers with different names),
-//! Cgroup (Resource limits, execution priority etc.)
+//! Cgroup (Resource limits, execution priority etc.),
+//! Time (offsets of the monotonic and boot time clocks)
 
 use std::collections;
 
@@ -31,9 +32,15 @@ pub enum NamespaceError {
     NotSupported(String),
 }
 
+/// Flag of time namespaces, which nix does not know. Like pid namespaces, a
+/// new time namespace only applies to the children of the process creating
+/// it, while joining one with setns also moves the calling process.
+pub const CLONE_NEWTIME: CloneFlags = CloneFlags::from_bits_retain(0x80);
+
 static ORDERED_NAMESPACES: &[CloneFlags] = &[
     CloneFlags::CLONE_NEWUSER,
     CloneFlags::CLONE_NEWPID,
+    CLONE_NEWTIME,
     CloneFlags::CLONE_NEWUTS,
     CloneFlags::CLONE_NEWIPC,
     CloneFlags::CLONE_NEWNET,
@@ -56,7 +63,7 @@ fn get_clone_flag(namespace_type: LinuxNamespaceType) -> Result<CloneFlags> {
         LinuxNamespaceType::Network => CloneFlags::CLONE_NEWNET,
         LinuxNamespaceType::Cgroup => CloneFlags::CLONE_NEWCGROUP,
         LinuxNamespaceType::Mount => CloneFlags::CLONE_NEWNS,
-        LinuxNamespaceType::Time => return Err(NamespaceError::NotSupported("time".to_string())),
+        LinuxNamespaceType::Time => CLONE_NEWTIME,
     };
 
     Ok(flag)
@@ -200,4 +207,27 @@ mod tests {
         expect.sort();
         assert_eq!(unshare_args, expect)
     }
+
+    #[test]
+    #[serial]
+    fn test_time_namespace() {
+        let namespaces = Namespaces::try_from(Some(&vec![LinuxNamespaceBuilder::default()
+            .typ(LinuxNamespaceType::Time)
+            .path("/dev/null")
+            .build()
+            .unwrap()]))
+        .expect("time namespaces are supported");
+        assert!(namespaces.get(LinuxNamespaceType::Time).unwrap().is_some());
+
+        let test_command: &TestHelperSyscall = namespaces.command.as_any().downcast_ref().unwrap();
+        namespaces
+            .apply_namespaces(|ns_type| ns_type == CLONE_NEWTIME)
+            .unwrap();
+        let setns_args: Vec<_> = test_command
+            .get_setns_args()
+            .into_iter()
+            .map(|(_fd, cf)| cf)
+            .collect();
+        assert_eq!(setns_args, vec![CLONE_NEWTIME]);
+    }
 }
@@ -1,18 +1,26 @@
-use std::env;
 use std::io::prelude::*;
-use std::os::fd::FromRawFd;
-use std::os::unix::io::AsRawFd;
+use std::io::ErrorKind;
+use std::os::fd::{AsFd, FromRawFd, OwnedFd};
+use std::os::unix::io::{AsRawFd, RawFd};
 use std::os::unix::net::{UnixListener, UnixStream};
 use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 
-use nix::unistd::{self, close};
+use nix::errno::Errno;
+use nix::poll::{poll, PollFd, PollFlags, PollTimeout};
+use nix::unistd::{close, Pid};
+
+use crate::utils::SocketPath;
 
 pub const NOTIFY_FILE: &str = "notify.sock";
 
 #[derive(Debug, thiserror::Error)]
 pub enum NotifyListenerError {
-    #[error("failed to chdir {path} while creating notify socket: {source}")]
-    Chdir { source: nix::Error, path: PathBuf },
+    #[error("failed to open the directory of notify socket {path}: {source}")]
+    SocketDir {
+        source: std::io::Error,
+        path: PathBuf,
+    },
     #[error("invalid path: {0}")]
     InvalidPath(PathBuf),
     #[error("failed to bind notify socket: {name}")]
@@ -25,8 +33,6 @@ pub enum NotifyListenerError {
         source: std::io::Error,
         name: String,
     },
-    #[error("failed to get cwd")]
-    GetCwd(#[source] std::io::Error),
     #[error("failed to accept notify listener")]
     Accept(#[source] std::io::Error),
     #[error("failed to close notify listener")]
@@ -35,12 +41,50 @@ pub enum NotifyListenerError {
     Read(#[source] std::io::Error),
     #[error("failed to send start container")]
     SendStartContainer(#[source] std::io::Error),
+    #[error("failed to create notify socket pair")]
+    SocketPair(#[source] std::io::Error),
+    #[error("the creator of the container closed the start socket without starting it")]
+    StartAborted,
+    #[error("failed to wait for the init process to accept the start")]
+    Poll(#[source] Errno),
+    #[error("the init process exited before the container was started")]
+    InitExited,
+    #[error("the init process did not accept the start in time")]
+    Timeout,
 }
 
 type Result<T> = std::result::Result<T, NotifyListenerError>;
 
+/// How the init process of a created container is told to execute the
+/// container process
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum StartHandshake {
+    /// Through a unix socket bound to notify.sock in the container directory,
+    /// so that any later process, e.g. `youki start`, can start the container
+    #[default]
+    NotifySocket,
+    /// Through a socket pair inherited from the creating process. Nothing is
+    /// bound in the filesystem, so the length of the container directory does
+    /// not matter and nothing is left behind, but only the [`Container`]
+    /// returned by the builder can start the container. If it is dropped
+    /// without starting the container, the init process exits.
+    ///
+    /// [`Container`]: crate::container::Container
+    SocketPair,
+}
+
 pub struct NotifyListener {
-    socket: UnixListener,
+    socket: ListenerSocket,
+}
+
+enum ListenerSocket {
+    Bound(UnixListener),
+    Pair {
+        receiver: UnixStream,
+        // The end of the starter, which the init process inherited and has
+        // to close, to notice if the starter goes away.
+        starter: RawFd,
+    },
 }
 
 impl NotifyListener {
@@ -49,58 +93,89 @@ impl NotifyListener {
         // Unix domain socket has a maximum length of 108, different from
         // normal path length of 255. Due to how docker create the path name
         // to the container working directory, there is a high chance that
-        // the full absolute path is over the limit. To work around this
-        // limitation, we chdir first into the workdir where the socket is,
-        // and chdir back after the socket is created.
-        let workdir = socket_path
-            .parent()
-            .ok_or_else(|| NotifyListenerError::InvalidPath(socket_path.to_owned()))?;
-        let socket_name = socket_path
-            .file_name()
-            .ok_or_else(|| NotifyListenerError::InvalidPath(socket_path.to_owned()))?;
-        let cwd = env::current_dir().map_err(NotifyListenerError::GetCwd)?;
-        tracing::debug!(?cwd, "the cwd to create the notify socket");
-        unistd::chdir(workdir).map_err(|e| NotifyListenerError::Chdir {
-            source: e,
-            path: workdir.to_owned(),
-        })?;
-        let stream = UnixListener::bind(socket_name).map_err(|e| NotifyListenerError::Bind {
-            source: e,
-            // ok to unwrap here as OsStr should always be utf-8 compatible
-            name: socket_name.to_str().unwrap().to_owned(),
-        })?;
-        unistd::chdir(&cwd).map_err(|e| NotifyListenerError::Chdir {
-            source: e,
-            path: cwd,
-        })?;
+        // the full absolute path is over the limit, so the socket is bound
+        // through a short path relative to its directory.
+        let short_path = socket_path_of(socket_path)?;
+        let stream =
+            UnixListener::bind(short_path.as_path()).map_err(|e| NotifyListenerError::Bind {
+                source: e,
+                name: socket_path.display().to_string(),
+            })?;
+
+        Ok(Self {
+            socket: ListenerSocket::Bound(stream),
+        })
+    }
 
-        Ok(Self { socket: stream })
+    /// Creates a listener together with the only [`StartNotifier`] which can
+    /// start the container, see [`StartHandshake::SocketPair`]
+    pub fn pair() -> Result<(Self, StartNotifier)> {
+        tracing::debug!("create notify socket pair");
+        let (receiver, starter) = UnixStream::pair().map_err(NotifyListenerError::SocketPair)?;
+        let listener = Self {
+            socket: ListenerSocket::Pair {
+                receiver,
+                starter: starter.as_raw_fd(),
+            },
+        };
+        let notifier = StartNotifier {
+            socket: starter,
+            init: None,
+        };
+
+        Ok((listener, notifier))
     }
 
     pub fn wait_for_container_start(&self) -> Result<()> {
-        match self.socket.accept() {
-            Ok((mut socket, _)) => {
-                let mut response = String::new();
-                socket
-                    .read_to_string(&mut response)
+        match &self.socket {
+            ListenerSocket::Bound(listener) => match listener.accept() {
+                Ok((mut socket, _)) => {
+                    let mut response = String::new();
+                    socket
+                        .read_to_string(&mut response)
+                        .map_err(NotifyListenerError::Read)?;
+                    tracing::debug!("received: {}", response);
+                }
+                Err(e) => Err(NotifyListenerError::Accept(e))?,
+            },
+            ListenerSocket::Pair { receiver, starter } => {
+                close(*starter).map_err(NotifyListenerError::Close)?;
+                // The starter only ever writes a single message, but keeps
+                // its end open as long as the container object lives, so
+                // waiting for the end of the stream is not an option.
+                let mut receiver: &UnixStream = receiver;
+                let mut response = [0; 64];
+                let len = receiver
+                    .read(&mut response)
                     .map_err(NotifyListenerError::Read)?;
-                tracing::debug!("received: {}", response);
+                if len == 0 {
+                    return Err(NotifyListenerError::StartAborted);
+                }
+                tracing::debug!("received: {}", String::from_utf8_lossy(&response[..len]));
             }
-            Err(e) => Err(NotifyListenerError::Accept(e))?,
         }
 
         Ok(())
     }
 
     pub fn close(&self) -> Result<()> {
-        close(self.socket.as_raw_fd()).map_err(NotifyListenerError::Close)?;
+        close(self.as_raw_fd()).map_err(NotifyListenerError::Close)?;
         Ok(())
     }
 }
 
+impl AsRawFd for NotifyListener {
+    fn as_raw_fd(&self) -> std::os::unix::io::RawFd {
+        match &self.socket {
+            ListenerSocket::Bound(listener) => listener.as_raw_fd(),
+            ListenerSocket::Pair { receiver, .. } => receiver.as_raw_fd(),
+        }
+    }
+}
+
 impl Clone for NotifyListener {
     fn clone(&self) -> Self {
-        let fd = self.socket.as_raw_fd();
+        let fd = self.as_raw_fd();
         // This is safe because we just duplicate a valid fd. Theoretically, to
         // truly clone a unix listener, we have to use dup(2) to duplicate the
         // fd, and then use from_raw_fd to create a new UnixListener. However,
@@ -110,11 +185,87 @@ impl Clone for NotifyListener {
         // should be safe to use, as long as we be careful with not closing the
         // same fd in different places. If we observe an issue, we will switch
         // to `dup`.
-        let socket = unsafe { UnixListener::from_raw_fd(fd) };
+        let socket = match &self.socket {
+            ListenerSocket::Bound(_) => {
+                ListenerSocket::Bound(unsafe { UnixListener::from_raw_fd(fd) })
+            }
+            ListenerSocket::Pair { starter, .. } => ListenerSocket::Pair {
+                receiver: unsafe { UnixStream::from_raw_fd(fd) },
+                starter: *starter,
+            },
+        };
         Self { socket }
     }
 }
 
+/// The starting end of a [`StartHandshake::SocketPair`]
+#[derive(Debug)]
+pub struct StartNotifier {
+    socket: UnixStream,
+    // pidfd of the init process, to notice if it exited instead of waiting
+    // for the start
+    init: Option<OwnedFd>,
+}
+
+impl StartNotifier {
+    /// Watches the init process of the container, so that starting the
+    /// container fails if it exited in the meantime, even if its pid has
+    /// been reused. Kernels without pidfd_open(2) (5.3) are not watched.
+    pub fn watch(&mut self, init_pid: Pid) {
+        let pidfd = unsafe { libc::syscall(libc::SYS_pidfd_open, init_pid.as_raw(), 0) };
+        if pidfd < 0 {
+            tracing::debug!(err = ?Errno::last(), "failed to open pidfd of the init process");
+            return;
+        }
+        // Safe because the fd has just been opened and is owned by nobody else
+        self.init = Some(unsafe { OwnedFd::from_raw_fd(pidfd as RawFd) });
+    }
+
+    pub fn notify_container_start(&self) -> Result<()> {
+        self.notify_container_start_within(None)
+    }
+
+    /// Starts the container like [`Self::notify_container_start`], but fails
+    /// if the init process does not accept the start within the timeout
+    pub fn notify_container_start_within(&self, timeout: Option<Duration>) -> Result<()> {
+        tracing::debug!("notify container start through socket pair");
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        if let Some(init) = &self.init {
+            let mut fds = [
+                PollFd::new(self.socket.as_fd(), PollFlags::POLLOUT),
+                PollFd::new(init.as_fd(), PollFlags::POLLIN),
+            ];
+            loop {
+                let timeout = match deadline {
+                    Some(deadline) => {
+                        let remaining = deadline.saturating_duration_since(Instant::now());
+                        PollTimeout::try_from(remaining.as_millis()).unwrap_or(PollTimeout::MAX)
+                    }
+                    None => PollTimeout::NONE,
+                };
+                match poll(&mut fds, timeout) {
+                    Ok(0) => return Err(NotifyListenerError::Timeout),
+                    Ok(_) => break,
+                    Err(Errno::EINTR) => continue,
+                    Err(err) => return Err(NotifyListenerError::Poll(err)),
+                }
+            }
+            // A pidfd becomes readable once the process exited
+            if fds[1].revents().map_or(false, |events| !events.is_empty()) {
+                return Err(NotifyListenerError::InitExited);
+            }
+        }
+        self.socket
+            .set_write_timeout(deadline.map(remaining_until).transpose()?)
+            .map_err(NotifyListenerError::SendStartContainer)?;
+        (&self.socket)
+            .write_all(b"start container")
+            .map_err(send_error)?;
+        tracing::debug!("notify finished");
+        Ok(())
+    }
+}
+
 pub struct NotifySocket {
     path: PathBuf,
 }
@@ -127,40 +278,61 @@ impl NotifySocket {
     }
 
     pub fn notify_container_start(&mut self) -> Result<()> {
+        self.notify_container_start_within(None)
+    }
+
+    /// Starts the container like [`Self::notify_container_start`], but fails
+    /// if the init process does not accept the start within the timeout
+    pub fn notify_container_start_within(&mut self, timeout: Option<Duration>) -> Result<()> {
         tracing::debug!("notify container start");
-        let cwd = env::current_dir().map_err(NotifyListenerError::GetCwd)?;
-        let workdir = self
-            .path
-            .parent()
-            .ok_or_else(|| NotifyListenerError::InvalidPath(self.path.to_owned()))?;
-        unistd::chdir(workdir).map_err(|e| NotifyListenerError::Chdir {
-            source: e,
-            path: workdir.to_owned(),
-        })?;
-        let socket_name = self
-            .path
-            .file_name()
-            .ok_or_else(|| NotifyListenerError::InvalidPath(self.path.to_owned()))?;
-        let mut stream =
-            UnixStream::connect(socket_name).map_err(|e| NotifyListenerError::Connect {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        let short_path = socket_path_of(&self.path)?;
+        let mut stream = UnixStream::connect(short_path.as_path()).map_err(|e| {
+            NotifyListenerError::Connect {
                 source: e,
-                // ok to unwrap as OsStr should always be utf-8 compatible
-                name: socket_name.to_str().unwrap().to_owned(),
-            })?;
+                name: self.path.display().to_string(),
+            }
+        })?;
         stream
-            .write_all(b"start container")
+            .set_write_timeout(deadline.map(remaining_until).transpose()?)
             .map_err(NotifyListenerError::SendStartContainer)?;
+        stream.write_all(b"start container").map_err(send_error)?;
         tracing::debug!("notify finished");
-        unistd::chdir(&cwd).map_err(|e| NotifyListenerError::Chdir {
-            source: e,
-            path: cwd,
-        })?;
         Ok(())
     }
 }
 
+// A zero write timeout would mean no timeout
+fn remaining_until(deadline: Instant) -> Result<Duration> {
+    match deadline.saturating_duration_since(Instant::now()) {
+        remaining if remaining.is_zero() => Err(NotifyListenerError::Timeout),
+        remaining => Ok(remaining),
+    }
+}
+
+fn send_error(err: std::io::Error) -> NotifyListenerError {
+    match err.kind() {
+        ErrorKind::WouldBlock | ErrorKind::TimedOut => NotifyListenerError::Timeout,
+        _ => NotifyListenerError::SendStartContainer(err),
+    }
+}
+
+fn socket_path_of(socket_path: &Path) -> Result<SocketPath> {
+    if socket_path.file_name().is_none() {
+        return Err(NotifyListenerError::InvalidPath(socket_path.to_owned()));
+    }
+
+    SocketPath::new(socket_path).map_err(|err| NotifyListenerError::SocketDir {
+        source: err,
+        path: socket_path.to_owned(),
+    })
+}
+
 #[cfg(test)]
 mod test {
+    use nix::sys::wait;
+    use nix::unistd;
+    use serial_test::serial;
     use tempfile::tempdir;
 
     use super::*;
@@ -188,4 +360,98 @@ mod test {
         socket.notify_container_start().unwrap();
         thread_handle.join().unwrap();
     }
+
+    #[test]
+    /// Containers may be created from several threads of one process, so
+    /// the sockets must not depend on the working directory.
+    fn test_notify_concurrently_with_long_paths() {
+        let cwd = std::env::current_dir().unwrap();
+        let tempdir = tempdir().unwrap();
+
+        let handles: Vec<_> = (0..8)
+            .map(|i| {
+                // longer than a unix socket address can hold
+                let dir = tempdir.path().join(format!("{}-{i}", "c".repeat(120)));
+                std::thread::spawn(move || {
+                    std::fs::create_dir(&dir).unwrap();
+                    let socket_path = dir.join(NOTIFY_FILE);
+                    let listener = NotifyListener::new(&socket_path).unwrap();
+                    let waiter = std::thread::spawn(move || listener.wait_for_container_start());
+                    NotifySocket::new(socket_path)
+                        .notify_container_start()
+                        .unwrap();
+                    waiter.join().unwrap().unwrap();
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        assert_eq!(std::env::current_dir().unwrap(), cwd);
+    }
+
+    #[test]
+    #[serial]
+    fn test_notify_socket_pair() {
+        let (listener, mut notifier) = NotifyListener::pair().unwrap();
+        match unsafe { unistd::fork().unwrap() } {
+            unistd::ForkResult::Parent { child } => {
+                drop(listener);
+                notifier.watch(child);
+                notifier.notify_container_start().unwrap();
+                assert_eq!(
+                    wait::waitpid(child, None).unwrap(),
+                    wait::WaitStatus::Exited(child, 0)
+                );
+            }
+            unistd::ForkResult::Child => {
+                let code = match listener.wait_for_container_start() {
+                    Ok(()) => 0,
+                    Err(_) => 1,
+                };
+                std::process::exit(code);
+            }
+        }
+    }
+
+    #[test]
+    #[serial]
+    fn test_notify_socket_pair_aborted() {
+        let (listener, notifier) = NotifyListener::pair().unwrap();
+        match unsafe { unistd::fork().unwrap() } {
+            unistd::ForkResult::Parent { child } => {
+                drop(listener);
+                drop(notifier);
+                assert_eq!(
+                    wait::waitpid(child, None).unwrap(),
+                    wait::WaitStatus::Exited(child, 0)
+                );
+            }
+            unistd::ForkResult::Child => {
+                let code = match listener.wait_for_container_start() {
+                    Err(NotifyListenerError::StartAborted) => 0,
+                    _ => 1,
+                };
+                std::process::exit(code);
+            }
+        }
+    }
+
+    #[test]
+    #[serial]
+    fn test_notify_socket_pair_init_exited() {
+        let (_listener, mut notifier) = NotifyListener::pair().unwrap();
+        match unsafe { unistd::fork().unwrap() } {
+            unistd::ForkResult::Parent { child } => {
+                notifier.watch(child);
+                wait::waitpid(child, None).unwrap();
+                assert!(matches!(
+                    notifier.notify_container_start(),
+                    Err(NotifyListenerError::InitExited)
+                ));
+            }
+            unistd::ForkResult::Child => std::process::exit(0),
+        }
+    }
 }
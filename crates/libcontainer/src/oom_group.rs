@@ -0,0 +1,68 @@
+//! Whole-container OOM kills on cgroup v2. By default the OOM killer kills a
+//! single process of the container, which may leave the rest of the workload
+//! behind in a broken state. With `memory.oom.group` of the unified map, or
+//! with the annotation
+//!
+//! - `org.youki.memory.oom-group`: `true` or `false`,
+//!
+//! all processes of the container cgroup are killed together. The annotation
+//! is a shorthand for the unified entry. With the systemd cgroup driver the
+//! entry becomes the `kill` OOM policy of the unit, which requires systemd 253
+//! or later.
+use oci_spec::runtime::Spec;
+
+use crate::unified_annotation::{self, UnifiedConflict};
+
+pub const OOM_GROUP_ANNOTATION: &str = "org.youki.memory.oom-group";
+
+const CGROUP_MEMORY_OOM_GROUP: &str = "memory.oom.group";
+
+#[derive(Debug, thiserror::Error)]
+pub enum OomGroupError {
+    #[error("invalid oom group annotation {0:?}, expected true or false")]
+    InvalidValue(String),
+    #[error(transparent)]
+    Conflict(#[from] UnifiedConflict),
+}
+
+type Result<T> = std::result::Result<T, OomGroupError>;
+
+/// Adds the oom group of the annotation to the unified resources of the
+/// spec. Without the annotation this is a no-op.
+pub fn apply(spec: &mut Spec) -> Result<()> {
+    unified_annotation::apply(spec, OOM_GROUP_ANNOTATION, CGROUP_MEMORY_OOM_GROUP, parse)
+}
+
+fn parse(value: &str) -> Result<String> {
+    match value {
+        "true" => Ok("1".to_owned()),
+        "false" => Ok("0".to_owned()),
+        _ => Err(OomGroupError::InvalidValue(value.to_owned())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+
+    use super::*;
+    use crate::unified_annotation::tests::{spec_with, unified_value};
+
+    #[test]
+    fn test_apply_oom_group() -> Result<()> {
+        let mut spec = spec_with((OOM_GROUP_ANNOTATION, "true"), None)?;
+        apply(&mut spec)?;
+        assert_eq!(
+            unified_value(&spec, CGROUP_MEMORY_OOM_GROUP).as_deref(),
+            Some("1")
+        );
+
+        let mut spec = spec_with((OOM_GROUP_ANNOTATION, "1"), None)?;
+        assert!(matches!(
+            apply(&mut spec),
+            Err(OomGroupError::InvalidValue(_))
+        ));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,303 @@
+//! Memory prefaulted by the init process, so that a latency-critical
+//! workload does not pay for page faults and reclaim on its first requests.
+//! It is requested with annotations in the spec:
+//!
+//! - `org.youki.memory.prefault`: amount of anonymous memory to touch, in
+//!   bytes or with a binary suffix `K`, `M` or `G`, e.g. `512M`.
+//! - `org.youki.memory.prefault.files`: comma separated absolute paths of
+//!   files in the container, e.g. a model or a database, which are mapped and
+//!   locked into memory.
+//!
+//! The init process prefaults the memory after the container cgroup limits
+//! it and before the payload is executed, so the memory is charged to the
+//! container. The mappings and their locks end with the exec: the pages of
+//! the files stay in the page cache of the container, the anonymous memory
+//! is given back, but the kernel has reclaimed that much memory for the
+//! container at create time instead of on its first allocations. A create
+//! fails if the anonymous memory exceeds the memory limit of the container.
+use std::fs::File;
+use std::io;
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+
+use nix::errno::Errno;
+use nix::sys::mman::{self, MapFlags, ProtFlags};
+use oci_spec::runtime::Spec;
+
+pub const PREFAULT_ANNOTATION: &str = "org.youki.memory.prefault";
+pub const PREFAULT_FILES_ANNOTATION: &str = "org.youki.memory.prefault.files";
+
+#[derive(Debug, thiserror::Error)]
+pub enum PrefaultError {
+    #[error("invalid prefault size {0:?}, expected bytes with an optional K, M or G suffix")]
+    InvalidSize(String),
+    #[error("prefault of {size} bytes exceeds the memory limit of {limit} bytes")]
+    AboveLimit { size: u64, limit: i64 },
+    #[error("prefault file {0:?} is not an absolute path")]
+    RelativePath(PathBuf),
+    #[error("failed to prefault {size} bytes of anonymous memory")]
+    Anonymous { size: u64, source: Errno },
+    #[error("failed to open prefault file {path:?}")]
+    Open { path: PathBuf, source: io::Error },
+    #[error("failed to map prefault file {path:?}")]
+    Map { path: PathBuf, source: Errno },
+}
+
+type Result<T> = std::result::Result<T, PrefaultError>;
+
+/// Memory to prefault for a container
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Prefault {
+    /// Bytes of anonymous memory
+    pub anonymous: u64,
+    /// Files in the container to map and lock
+    pub files: Vec<PathBuf>,
+}
+
+impl Prefault {
+    /// Reads the prefault from the annotations of the spec. Returns None if
+    /// they do not request any.
+    pub fn from_spec(spec: &Spec) -> Result<Option<Self>> {
+        let annotations = match spec.annotations() {
+            Some(annotations) => annotations,
+            None => return Ok(None),
+        };
+
+        let mut prefault = Self::default();
+        if let Some(size) = annotations.get(PREFAULT_ANNOTATION) {
+            prefault.anonymous = parse_size(size)?;
+        }
+        if let Some(files) = annotations.get(PREFAULT_FILES_ANNOTATION) {
+            for file in files.split(',').map(str::trim).filter(|f| !f.is_empty()) {
+                let file = PathBuf::from(file);
+                if !file.is_absolute() {
+                    return Err(PrefaultError::RelativePath(file));
+                }
+                prefault.files.push(file);
+            }
+        }
+        if prefault == Self::default() {
+            return Ok(None);
+        }
+
+        let limit = spec
+            .linux()
+            .as_ref()
+            .and_then(|linux| linux.resources().as_ref())
+            .and_then(|resources| resources.memory().as_ref())
+            .and_then(|memory| memory.limit());
+        if let Some(limit) = limit {
+            if limit >= 0 && prefault.anonymous > limit as u64 {
+                return Err(PrefaultError::AboveLimit {
+                    size: prefault.anonymous,
+                    limit,
+                });
+            }
+        }
+
+        Ok(Some(prefault))
+    }
+
+    /// Touches the anonymous memory and maps and locks the files. Has to be
+    /// called in the container, after the mounts are set up. A file which
+    /// can't be locked, e.g. due to RLIMIT_MEMLOCK, is still read into the
+    /// page cache.
+    pub fn apply(&self) -> Result<()> {
+        if let Some(length) = NonZeroUsize::new(self.anonymous as usize) {
+            // MAP_POPULATE allocates every page of a private writable mapping
+            let populated = unsafe {
+                mman::mmap_anonymous(
+                    None,
+                    length,
+                    ProtFlags::PROT_READ | ProtFlags::PROT_WRITE,
+                    MapFlags::MAP_PRIVATE | MapFlags::MAP_POPULATE,
+                )
+                .and_then(|addr| mman::munmap(addr, length.get()))
+            };
+            populated.map_err(|source| {
+                tracing::error!(size = self.anonymous, ?source, "failed to prefault memory");
+                PrefaultError::Anonymous {
+                    size: self.anonymous,
+                    source,
+                }
+            })?;
+            tracing::debug!(size = self.anonymous, "prefaulted anonymous memory");
+        }
+
+        for path in &self.files {
+            prefault_file(path)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn prefault_file(path: &Path) -> Result<()> {
+    let file = File::open(path).map_err(|source| {
+        tracing::error!(?path, ?source, "failed to open prefault file");
+        PrefaultError::Open {
+            path: path.to_owned(),
+            source,
+        }
+    })?;
+    let size = file
+        .metadata()
+        .map_err(|source| PrefaultError::Open {
+            path: path.to_owned(),
+            source,
+        })?
+        .len();
+    let length = match NonZeroUsize::new(size as usize) {
+        Some(length) => length,
+        None => return Ok(()),
+    };
+
+    let addr = unsafe {
+        mman::mmap(
+            None,
+            length,
+            ProtFlags::PROT_READ,
+            MapFlags::MAP_SHARED | MapFlags::MAP_POPULATE,
+            &file,
+            0,
+        )
+    }
+    .map_err(|source| {
+        tracing::error!(?path, ?source, "failed to map prefault file");
+        PrefaultError::Map {
+            path: path.to_owned(),
+            source,
+        }
+    })?;
+    if let Err(err) = unsafe { mman::mlock(addr, length.get()) } {
+        tracing::warn!(?path, ?err, "failed to lock prefault file, it is only read");
+    }
+    if let Err(err) = unsafe { mman::munmap(addr, length.get()) } {
+        tracing::warn!(?path, ?err, "failed to unmap prefault file");
+    }
+    tracing::debug!(?path, size, "prefaulted file");
+
+    Ok(())
+}
+
+fn parse_size(size: &str) -> Result<u64> {
+    let size = size.trim();
+    let (number, factor) = match size.char_indices().last() {
+        Some((i, 'K' | 'k')) => (&size[..i], 1 << 10),
+        Some((i, 'M' | 'm')) => (&size[..i], 1 << 20),
+        Some((i, 'G' | 'g')) => (&size[..i], 1 << 30),
+        _ => (size, 1),
+    };
+    number
+        .parse::<u64>()
+        .ok()
+        .and_then(|number| number.checked_mul(factor))
+        .ok_or_else(|| PrefaultError::InvalidSize(size.to_owned()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use anyhow::Result;
+    use oci_spec::runtime::{LinuxBuilder, LinuxMemoryBuilder, LinuxResourcesBuilder, SpecBuilder};
+
+    use super::*;
+
+    fn spec(annotations: &[(&str, &str)], limit: Option<i64>) -> Result<Spec> {
+        let mut memory = LinuxMemoryBuilder::default();
+        if let Some(limit) = limit {
+            memory = memory.limit(limit);
+        }
+        Ok(SpecBuilder::default()
+            .annotations(
+                annotations
+                    .iter()
+                    .map(|(key, value)| (key.to_string(), value.to_string()))
+                    .collect::<HashMap<_, _>>(),
+            )
+            .linux(
+                LinuxBuilder::default()
+                    .resources(
+                        LinuxResourcesBuilder::default()
+                            .memory(memory.build()?)
+                            .build()?,
+                    )
+                    .build()?,
+            )
+            .build()?)
+    }
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("4096").unwrap(), 4096);
+        assert_eq!(parse_size("64K").unwrap(), 64 << 10);
+        assert_eq!(parse_size(" 512M ").unwrap(), 512 << 20);
+        assert_eq!(parse_size("2g").unwrap(), 2 << 30);
+        for invalid in ["", "M", "1.5G", "-1", "1T", "99999999999G"] {
+            assert!(matches!(
+                parse_size(invalid),
+                Err(PrefaultError::InvalidSize(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_from_spec() -> Result<()> {
+        assert_eq!(Prefault::from_spec(&spec(&[], None)?)?, None);
+
+        let prefault = Prefault::from_spec(&spec(
+            &[
+                (PREFAULT_ANNOTATION, "1M"),
+                (PREFAULT_FILES_ANNOTATION, "/data/model.bin, /data/index"),
+            ],
+            Some(2 << 20),
+        )?)?;
+        assert_eq!(
+            prefault,
+            Some(Prefault {
+                anonymous: 1 << 20,
+                files: vec![
+                    PathBuf::from("/data/model.bin"),
+                    PathBuf::from("/data/index")
+                ],
+            })
+        );
+
+        assert!(matches!(
+            Prefault::from_spec(&spec(&[(PREFAULT_ANNOTATION, "4M")], Some(2 << 20))?),
+            Err(PrefaultError::AboveLimit { .. })
+        ));
+        assert!(Prefault::from_spec(&spec(&[(PREFAULT_ANNOTATION, "4M")], Some(-1))?)?.is_some());
+        assert!(matches!(
+            Prefault::from_spec(&spec(
+                &[(PREFAULT_FILES_ANNOTATION, "data/model.bin")],
+                None
+            )?),
+            Err(PrefaultError::RelativePath(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_apply() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let file = tmp.path().join("model.bin");
+        std::fs::write(&file, vec![1u8; 64 << 10])?;
+        let empty = tmp.path().join("empty");
+        std::fs::write(&empty, "")?;
+
+        let prefault = Prefault {
+            anonymous: 1 << 20,
+            files: vec![file, empty],
+        };
+        prefault.apply()?;
+
+        let missing = Prefault {
+            anonymous: 0,
+            files: vec![tmp.path().join("missing")],
+        };
+        assert!(matches!(missing.apply(), Err(PrefaultError::Open { .. })));
+        Ok(())
+    }
+}
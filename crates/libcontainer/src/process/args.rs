@@ -6,7 +6,12 @@ use libcgroups::common::CgroupConfig;
 use oci_spec::runtime::Spec;
 
 use crate::container::Container;
+use crate::debug_capture::InitReportFile;
+use crate::fault_injection::FaultPoint;
 use crate::notify_socket::NotifyListener;
+use crate::process::parent_death::ParentDeath;
+use crate::process::timeouts::Timeouts;
+use crate::rootfs::remap::IdmappedMounts;
 use crate::syscall::syscall::SyscallType;
 use crate::user_ns::UserNamespaceConfig;
 use crate::workload::Executor;
@@ -38,6 +43,12 @@ pub struct ContainerArgs {
     pub user_ns_config: Option<UserNamespaceConfig>,
     /// Cgroup Manager Config
     pub cgroup_config: CgroupConfig,
+    /// Cgroup a tenant joins instead of the container cgroup, set if the
+    /// container cgroup is delegated to the container
+    pub tenant_cgroup: Option<PathBuf>,
+    /// Name of the threaded cgroup created below the cgroup of the init
+    /// process
+    pub threaded_cgroup: Option<String>,
     /// If the container is to be run in detached mode
     pub detached: bool,
     /// Manage the functions that actually run on the container
@@ -52,4 +63,14 @@ pub struct ContainerArgs {
     pub stderr: Option<RawFd>,
     // Indicate if the init process should be a sibling of the main process.
     pub as_sibling: bool,
+    /// What happens to the container processes when their parent dies
+    pub parent_death: ParentDeath,
+    /// Point at which the container processes fail on purpose
+    pub fault_point: Option<FaultPoint>,
+    /// Time limits of the container setup
+    pub timeouts: Timeouts,
+    /// Report the init process keeps of its stages and failure
+    pub init_report: Option<InitReportFile>,
+    /// Idmapped mounts of the remapped mounts, prepared by the main process
+    pub idmapped_mounts: IdmappedMounts,
 }
@@ -0,0 +1,418 @@
+//! Delegation of the cgroup of a container to the container itself, so that
+//! systemd or a nested runtime inside of the container can create child
+//! cgroups with resource control right away.
+//!
+//! The controllers are requested with the `org.youki.cgroup.subtree-control`
+//! annotation, a comma separated list like `cpu,memory,pids`. Controllers can
+//! only be enabled in the `cgroup.subtree_control` of a cgroup without
+//! processes, so once the init process is set up and has entered its cgroup
+//! namespace, the processes of the container are moved into the `init` leaf
+//! below the container cgroup. The container cgroup stays the root of the
+//! cgroup namespace. If the container has a user namespace, the container
+//! cgroup and the leaf are handed to the root user of the container before
+//! the controllers are enabled.
+//!
+//! Only cgroup v2 supports delegation. Processes which are executed in the
+//! container later on join the cgroup of the init process, since the
+//! container cgroup itself does not accept processes anymore.
+//!
+//! For per-thread cpu control, the `org.youki.cgroup.threaded` annotation
+//! names a threaded cgroup which is created below the cgroup of the init
+//! process, i.e. below the `init` leaf of a delegated container cgroup. The
+//! cpu controller is enabled for it, and threads of the container are moved
+//! into it through its `cgroup.threads`.
+//!
+//! A container which is not delegated but gets child cgroups later on, e.g.
+//! one per exec session, can set the `org.youki.cgroup.init-leaf` annotation
+//! to `true`. The cgroup manager then places the init process in the `init`
+//! leaf right away, so that the container cgroup is free of processes and
+//! controllers can be enabled for its children. The leaf is the root of the
+//! cgroup namespace of such a container.
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Component, Path, PathBuf};
+
+use libcgroups::common::{self, CgroupSetup, CGROUP_PROCS, DEFAULT_CGROUP_ROOT};
+use libcgroups::v2::manager::{Manager, V2ManagerError, INIT_LEAF};
+use libcgroups::v2::threaded::{self, ThreadedError};
+use nix::unistd::{chown, Gid, Pid, Uid};
+use oci_spec::runtime::LinuxIdMapping;
+use procfs::process::Process;
+
+use crate::user_ns::UserNamespaceConfig;
+
+pub const SUBTREE_CONTROL_ANNOTATION: &str = "org.youki.cgroup.subtree-control";
+pub const THREADED_CGROUP_ANNOTATION: &str = "org.youki.cgroup.threaded";
+pub const INIT_LEAF_ANNOTATION: &str = "org.youki.cgroup.init-leaf";
+
+const CGROUP_CONTROLLERS: &str = "cgroup.controllers";
+const CGROUP_SUBTREE_CONTROL: &str = "cgroup.subtree_control";
+const CGROUP_DELEGATE: &str = "/sys/kernel/cgroup/delegate";
+// files of a cgroup which are delegated on kernels without CGROUP_DELEGATE
+const DEFAULT_DELEGATE_FILES: &[&str] =
+    &["cgroup.procs", "cgroup.subtree_control", "cgroup.threads"];
+const CONTROLLERS: &[&str] = &[
+    "cpu", "cpuset", "io", "memory", "pids", "hugetlb", "rdma", "misc",
+];
+
+#[derive(Debug, thiserror::Error)]
+pub enum CgroupDelegationError {
+    #[error("invalid controller {0:?} in the subtree control annotation")]
+    InvalidController(String),
+    #[error("cgroup delegation requires cgroup v2, the host uses {0}")]
+    Unsupported(CgroupSetup),
+    #[error(transparent)]
+    CgroupSetup(#[from] common::GetCgroupSetupError),
+    #[error("failed to read the cgroup of process {pid}")]
+    ProcessCgroup { pid: Pid, source: procfs::ProcError },
+    #[error("process {0} is not in a cgroup v2 hierarchy")]
+    NoUnifiedCgroup(Pid),
+    #[error("controller {controller} is not available in cgroup {path:?}")]
+    Unavailable { controller: String, path: PathBuf },
+    #[error("failed to delegate cgroup {path:?}")]
+    Io { path: PathBuf, source: io::Error },
+    #[error("failed to change the owner of {path:?}")]
+    Chown { path: PathBuf, source: nix::Error },
+    #[error("invalid name {0:?} of the threaded cgroup, expected a single path component")]
+    InvalidThreadedName(String),
+    #[error(transparent)]
+    Threaded(#[from] ThreadedError),
+    #[error(transparent)]
+    Manager(Box<V2ManagerError>),
+    #[error("invalid value {0:?} of the init leaf annotation, expected true or false")]
+    InvalidInitLeaf(String),
+}
+
+// boxed, the errors of the cgroup manager would make every error of the
+// delegation as large as they are
+impl From<V2ManagerError> for CgroupDelegationError {
+    fn from(err: V2ManagerError) -> Self {
+        Self::Manager(Box::new(err))
+    }
+}
+
+type Result<T> = std::result::Result<T, CgroupDelegationError>;
+
+/// Controllers to enable in the subtree of the container cgroup
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SubtreeControl {
+    controllers: Vec<String>,
+}
+
+impl SubtreeControl {
+    /// Reads the controllers from the annotations of the spec. Returns None
+    /// if the annotation is not set.
+    pub fn from_annotations(annotations: Option<&HashMap<String, String>>) -> Result<Option<Self>> {
+        let Some(value) = annotations.and_then(|a| a.get(SUBTREE_CONTROL_ANNOTATION)) else {
+            return Ok(None);
+        };
+
+        let mut controllers: Vec<String> = Vec::new();
+        for controller in value.split(',').map(str::trim) {
+            if !CONTROLLERS.contains(&controller) {
+                return Err(CgroupDelegationError::InvalidController(
+                    controller.to_owned(),
+                ));
+            }
+            if !controllers.iter().any(|c| c == controller) {
+                controllers.push(controller.to_owned());
+            }
+        }
+
+        Ok(Some(Self { controllers }))
+    }
+
+    /// Moves the processes of the container cgroup into the leaf, hands the
+    /// cgroup to the root user of the container and enables the controllers
+    /// in the subtree of the container cgroup. Has to be called after the
+    /// init process entered its cgroup namespace.
+    pub fn delegate(&self, init_pid: Pid, user_ns: Option<&UserNamespaceConfig>) -> Result<()> {
+        let cgroup_path = unified_cgroup_path(init_pid)?;
+        let manager = Manager::new(PathBuf::from(DEFAULT_CGROUP_ROOT), cgroup_path.clone())?;
+        let cgroup = Path::new(DEFAULT_CGROUP_ROOT).join(&cgroup_path);
+        let available = read(&cgroup.join(CGROUP_CONTROLLERS))?;
+        let available: Vec<&str> = available.split_whitespace().collect();
+        if let Some(controller) = self
+            .controllers
+            .iter()
+            .find(|c| !available.contains(&c.as_str()))
+        {
+            tracing::error!(
+                ?cgroup,
+                ?available,
+                controller,
+                "controller is not available"
+            );
+            return Err(CgroupDelegationError::Unavailable {
+                controller: controller.to_owned(),
+                path: cgroup,
+            });
+        }
+
+        let leaf = manager.move_to_init_leaf()?;
+
+        if let Some(owner) = user_ns.and_then(container_root) {
+            for dir in [&cgroup, &leaf] {
+                chown_delegated(dir, owner)?;
+            }
+        }
+
+        let enable = self
+            .controllers
+            .iter()
+            .map(|c| format!("+{c}"))
+            .collect::<Vec<_>>()
+            .join(" ");
+        write(&cgroup.join(CGROUP_SUBTREE_CONTROL), &enable)?;
+        tracing::debug!(?cgroup, controllers = ?self.controllers, "delegated container cgroup");
+
+        Ok(())
+    }
+}
+
+/// Name of the threaded cgroup, the one given to the builder or else the one
+/// of the annotation. None if there is neither.
+pub fn threaded_cgroup(
+    annotations: Option<&HashMap<String, String>>,
+    name: Option<&str>,
+) -> Result<Option<String>> {
+    let Some(name) = name.or_else(|| {
+        annotations
+            .and_then(|a| a.get(THREADED_CGROUP_ANNOTATION))
+            .map(String::as_str)
+    }) else {
+        return Ok(None);
+    };
+
+    let mut components = Path::new(name).components();
+    match (components.next(), components.next()) {
+        (Some(Component::Normal(_)), None) if name != INIT_LEAF => Ok(Some(name.to_owned())),
+        _ => Err(CgroupDelegationError::InvalidThreadedName(name.to_owned())),
+    }
+}
+
+/// Creates the threaded cgroup below the cgroup of the init process with the
+/// cpu controller enabled, and hands it to the root user of the container if
+/// the container has a user namespace. Has to be called after the cgroup is
+/// delegated.
+pub fn create_threaded(
+    init_pid: Pid,
+    name: &str,
+    user_ns: Option<&UserNamespaceConfig>,
+) -> Result<()> {
+    let cgroup = unified_cgroup(init_pid)?;
+    let path = threaded::create_threaded(&cgroup, name, &["cpu"]).map_err(|err| {
+        tracing::error!(?cgroup, name, ?err, "failed to create threaded cgroup");
+        err
+    })?;
+    if let Some(owner) = user_ns.and_then(container_root) {
+        chown_delegated(&path, owner)?;
+    }
+
+    Ok(())
+}
+
+/// Whether the cgroup manager places the processes of the container in the
+/// init leaf. A delegated cgroup gets the leaf on delegation instead.
+pub fn init_leaf(annotations: Option<&HashMap<String, String>>) -> Result<bool> {
+    let Some(value) = annotations.and_then(|a| a.get(INIT_LEAF_ANNOTATION)) else {
+        return Ok(false);
+    };
+    let init_leaf = match value.as_str() {
+        "true" => true,
+        "false" => false,
+        _ => return Err(CgroupDelegationError::InvalidInitLeaf(value.to_owned())),
+    };
+
+    Ok(init_leaf && SubtreeControl::from_annotations(annotations)?.is_none())
+}
+
+/// Adds a process to a cgroup, used by tenants of a container with a
+/// delegated cgroup to join the cgroup of the init process
+pub fn join(cgroup: &Path, pid: Pid) -> Result<()> {
+    write(&cgroup.join(CGROUP_PROCS), &pid.to_string())
+}
+
+/// Path of the cgroup v2 cgroup of a process, seen from the cgroup namespace
+/// of the caller
+pub fn unified_cgroup(pid: Pid) -> Result<PathBuf> {
+    Ok(Path::new(DEFAULT_CGROUP_ROOT).join(unified_cgroup_path(pid)?))
+}
+
+/// Path of the cgroup v2 cgroup of a process relative to the cgroup root
+fn unified_cgroup_path(pid: Pid) -> Result<PathBuf> {
+    let setup = common::get_cgroup_setup()?;
+    if setup != CgroupSetup::Unified {
+        return Err(CgroupDelegationError::Unsupported(setup));
+    }
+
+    let cgroups = Process::new(pid.as_raw())
+        .and_then(|process| process.cgroups())
+        .map_err(|source| CgroupDelegationError::ProcessCgroup { pid, source })?;
+    let cgroup = cgroups
+        .0
+        .into_iter()
+        .find(|cgroup| cgroup.hierarchy == 0)
+        .ok_or(CgroupDelegationError::NoUnifiedCgroup(pid))?;
+
+    Ok(PathBuf::from(cgroup.pathname.trim_start_matches('/')))
+}
+
+/// Host ids of the root user of the container
+fn container_root(user_ns: &UserNamespaceConfig) -> Option<(Uid, Gid)> {
+    let uid = host_id(user_ns.uid_mappings.as_deref()?, 0)?;
+    let gid = host_id(user_ns.gid_mappings.as_deref()?, 0)?;
+    Some((Uid::from_raw(uid), Gid::from_raw(gid)))
+}
+
+fn host_id(mappings: &[LinuxIdMapping], id: u32) -> Option<u32> {
+    mappings
+        .iter()
+        .find(|m| m.container_id() <= id && id - m.container_id() < m.size())
+        .map(|m| m.host_id() + id - m.container_id())
+}
+
+/// Changes the owner of a cgroup and of the files the kernel allows to
+/// delegate
+fn chown_delegated(dir: &Path, (uid, gid): (Uid, Gid)) -> Result<()> {
+    let files: Vec<String> = match fs::read_to_string(CGROUP_DELEGATE) {
+        Ok(files) => files.lines().map(str::to_owned).collect(),
+        Err(_) => DEFAULT_DELEGATE_FILES
+            .iter()
+            .map(|f| f.to_string())
+            .collect(),
+    };
+
+    let paths = std::iter::once(dir.to_path_buf())
+        .chain(files.iter().map(|file| dir.join(file)))
+        .filter(|path| path.exists());
+    for path in paths {
+        let metadata = fs::metadata(&path).map_err(|source| CgroupDelegationError::Io {
+            path: path.clone(),
+            source,
+        })?;
+        // an unprivileged user can not chown, but owns the cgroup already
+        if metadata.uid() == uid.as_raw() && metadata.gid() == gid.as_raw() {
+            continue;
+        }
+        chown(&path, Some(uid), Some(gid))
+            .map_err(|source| CgroupDelegationError::Chown { path, source })?;
+    }
+
+    Ok(())
+}
+
+fn read(path: &Path) -> Result<String> {
+    fs::read_to_string(path).map_err(|source| CgroupDelegationError::Io {
+        path: path.to_owned(),
+        source,
+    })
+}
+
+fn write(path: &Path, data: &str) -> Result<()> {
+    fs::write(path, data).map_err(|source| {
+        tracing::error!(?path, data, ?source, "failed to write cgroup file");
+        CgroupDelegationError::Io {
+            path: path.to_owned(),
+            source,
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use oci_spec::runtime::LinuxIdMappingBuilder;
+
+    use super::*;
+
+    fn annotations(value: &str) -> HashMap<String, String> {
+        HashMap::from([(SUBTREE_CONTROL_ANNOTATION.to_owned(), value.to_owned())])
+    }
+
+    #[test]
+    fn test_from_annotations() {
+        assert_eq!(SubtreeControl::from_annotations(None).unwrap(), None);
+        assert_eq!(
+            SubtreeControl::from_annotations(Some(&HashMap::new())).unwrap(),
+            None
+        );
+
+        let subtree_control =
+            SubtreeControl::from_annotations(Some(&annotations("cpu, memory,pids,cpu")))
+                .unwrap()
+                .unwrap();
+        assert_eq!(subtree_control.controllers, ["cpu", "memory", "pids"]);
+
+        for invalid in ["", "cpu,", "freezer", "+cpu", "cpu memory"] {
+            assert!(matches!(
+                SubtreeControl::from_annotations(Some(&annotations(invalid))),
+                Err(CgroupDelegationError::InvalidController(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_threaded_cgroup() {
+        let annotations =
+            HashMap::from([(THREADED_CGROUP_ANNOTATION.to_owned(), "workers".to_owned())]);
+        assert_eq!(threaded_cgroup(None, None).unwrap(), None);
+        assert_eq!(
+            threaded_cgroup(Some(&annotations), None)
+                .unwrap()
+                .as_deref(),
+            Some("workers")
+        );
+        assert_eq!(
+            threaded_cgroup(Some(&annotations), Some("io"))
+                .unwrap()
+                .as_deref(),
+            Some("io")
+        );
+        for invalid in ["", "a/b", "..", "/workers", INIT_LEAF] {
+            assert!(matches!(
+                threaded_cgroup(None, Some(invalid)),
+                Err(CgroupDelegationError::InvalidThreadedName(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_init_leaf() {
+        let mut annotations = HashMap::from([(INIT_LEAF_ANNOTATION.to_owned(), "true".to_owned())]);
+        assert!(!init_leaf(None).unwrap());
+        assert!(init_leaf(Some(&annotations)).unwrap());
+
+        annotations.insert(SUBTREE_CONTROL_ANNOTATION.to_owned(), "cpu".to_owned());
+        assert!(!init_leaf(Some(&annotations)).unwrap());
+
+        annotations.insert(INIT_LEAF_ANNOTATION.to_owned(), "yes".to_owned());
+        assert!(matches!(
+            init_leaf(Some(&annotations)),
+            Err(CgroupDelegationError::InvalidInitLeaf(_))
+        ));
+    }
+
+    #[test]
+    fn test_host_id() {
+        let mappings = [
+            LinuxIdMappingBuilder::default()
+                .container_id(0u32)
+                .host_id(100000u32)
+                .size(1u32)
+                .build()
+                .unwrap(),
+            LinuxIdMappingBuilder::default()
+                .container_id(1u32)
+                .host_id(200000u32)
+                .size(10u32)
+                .build()
+                .unwrap(),
+        ];
+        assert_eq!(host_id(&mappings, 0), Some(100000));
+        assert_eq!(host_id(&mappings, 5), Some(200004));
+        assert_eq!(host_id(&mappings, 11), None);
+        assert_eq!(host_id(&[], 0), None);
+    }
+}
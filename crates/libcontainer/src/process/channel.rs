@@ -1,31 +1,146 @@
+use std::fmt;
 use std::os::unix::prelude::{AsRawFd, RawFd};
+use std::time::Instant;
 
 use nix::unistd::Pid;
 
 use crate::channel::{channel, Receiver, Sender};
-use crate::process::message::Message;
+use crate::process::message::{ErrorFrame, Frame, Message, ProcessRole, PROTOCOL_VERSION};
+use crate::process::timeouts::Phase;
+use crate::workload::ExecFailure;
 
 #[derive(Debug, thiserror::Error)]
 pub enum ChannelError {
-    #[error("received unexpected message: {received:?}, expected: {expected:?}")]
+    #[error("received unexpected message {received} while {state}, expected {expected}")]
     UnexpectedMessage {
+        state: ProtocolState,
         expected: Message,
         received: Message,
     },
-    #[error("failed to receive. {msg:?}. {source:?}")]
+    #[error("failed to receive while {state}: {source}")]
     ReceiveError {
-        msg: String,
+        state: ProtocolState,
         #[source]
         source: crate::channel::ChannelError,
     },
+    #[error("received a frame of protocol version {received} while {state}, expected version {PROTOCOL_VERSION}")]
+    VersionMismatch { state: ProtocolState, received: u32 },
     #[error(transparent)]
     BaseChannelError(#[from] crate::channel::ChannelError),
     #[error("missing fds from seccomp request")]
     MissingSeccompFds,
     #[error("exec process failed with error {0}")]
-    ExecError(String),
-    #[error("intermediate process error {0}")]
-    OtherError(String),
+    ExecError(ExecFailure),
+    #[error("{0}")]
+    Peer(ErrorFrame),
+}
+
+impl ChannelError {
+    /// Whether no message arrived before the deadline of the receiver
+    pub fn is_timeout(&self) -> bool {
+        matches!(
+            self,
+            Self::ReceiveError {
+                source: crate::channel::ChannelError::Timeout,
+                ..
+            } | Self::BaseChannelError(crate::channel::ChannelError::Timeout)
+        )
+    }
+
+    /// State of the protocol in which receiving failed, if it failed
+    pub fn state(&self) -> Option<ProtocolState> {
+        match self {
+            Self::UnexpectedMessage { state, .. }
+            | Self::ReceiveError { state, .. }
+            | Self::VersionMismatch { state, .. } => Some(*state),
+            _ => None,
+        }
+    }
+}
+
+/// States of the protocol in which a process waits for a message. Each one
+/// belongs to a phase of the creation, whose timeout applies while waiting.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProtocolState {
+    /// The main process waits for the intermediate process to fork the init
+    /// process
+    IntermediateReady,
+    /// The main process waits for a process to enter its user namespace
+    MappingRequest,
+    /// The intermediate process waits for its id mappings
+    MappingAck,
+    /// The main process waits for the seccomp notify fd of the init process
+    SeccompRequest,
+    /// The init process waits for the fd to be passed to the listener
+    SeccompRequestDone,
+    /// The main process waits for the init process to ask for the hooks
+    HooksRequest,
+    /// The init process waits for the main process to run the hooks
+    HooksDone,
+    /// The main process waits for the init process to set up the container
+    InitReady,
+}
+
+impl ProtocolState {
+    /// Phase of the creation the state belongs to
+    pub fn phase(&self) -> Phase {
+        match self {
+            Self::IntermediateReady | Self::MappingRequest | Self::MappingAck => {
+                Phase::Intermediate
+            }
+            Self::SeccompRequest | Self::HooksRequest | Self::InitReady => Phase::Init,
+            Self::SeccompRequestDone => Phase::SeccompListener,
+            Self::HooksDone => Phase::Hooks,
+        }
+    }
+}
+
+impl fmt::Display for ProtocolState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = match self {
+            Self::IntermediateReady => "waiting for the intermediate process",
+            Self::MappingRequest => "waiting for the mapping request",
+            Self::MappingAck => "waiting for the mapping ack",
+            Self::SeccompRequest => "waiting for the seccomp request",
+            Self::SeccompRequestDone => "waiting for the seccomp listener",
+            Self::HooksRequest => "waiting for the hooks request",
+            Self::HooksDone => "waiting for the hooks",
+            Self::InitReady => "waiting for init ready",
+        };
+        f.write_str(state)
+    }
+}
+
+// Unwraps the message of a received frame. Failures of the other process
+// end the protocol in any state.
+fn open_frame(frame: Frame, state: ProtocolState) -> Result<Message, ChannelError> {
+    if frame.version != PROTOCOL_VERSION {
+        return Err(ChannelError::VersionMismatch {
+            state,
+            received: frame.version,
+        });
+    }
+
+    match frame.message {
+        Message::ExecFailed(failure) => Err(ChannelError::ExecError(failure)),
+        Message::Error(err) => Err(ChannelError::Peer(*err)),
+        message => Ok(message),
+    }
+}
+
+fn receive(receiver: &mut Receiver<Frame>, state: ProtocolState) -> Result<Message, ChannelError> {
+    let frame = receiver
+        .recv()
+        .map_err(|source| ChannelError::ReceiveError { state, source })?;
+    open_frame(frame, state)
+}
+
+fn unexpected(state: ProtocolState, expected: Message, received: Message) -> ChannelError {
+    ChannelError::UnexpectedMessage {
+        state,
+        expected,
+        received,
+    }
 }
 
 /// Channel Design
@@ -38,14 +153,30 @@ pub enum ChannelError {
 /// receiver to receive all message sent to the main process. The other
 /// processes will share the main_sender and use it to send message to the main
 /// process.
+///
+/// Every message is sent in a [`Frame`] carrying the version of the protocol.
+/// A process which fails sends an error frame with the chain of causes of its
+/// error to the process waiting for it, which ends the protocol there.
 
 pub fn main_channel() -> Result<(MainSender, MainReceiver), ChannelError> {
-    let (sender, receiver) = channel::<Message>()?;
-    Ok((MainSender { sender }, MainReceiver { receiver }))
+    let (sender, receiver) = channel::<Frame>()?;
+    Ok((
+        MainSender { sender },
+        MainReceiver {
+            receiver,
+            hooks_requested: false,
+        },
+    ))
 }
 
 pub struct MainSender {
-    sender: Sender<Message>,
+    sender: Sender<Frame>,
+}
+
+impl AsRawFd for MainSender {
+    fn as_raw_fd(&self) -> RawFd {
+        self.sender.as_raw_fd()
+    }
 }
 
 impl MainSender {
@@ -53,14 +184,14 @@ impl MainSender {
     // this needs to be done from the parent see https://man7.org/linux/man-pages/man7/user_namespaces.7.html
     pub fn identifier_mapping_request(&mut self) -> Result<(), ChannelError> {
         tracing::debug!("send identifier mapping request");
-        self.sender.send(Message::WriteMapping)?;
+        self.sender.send(Frame::new(Message::WriteMapping))?;
 
         Ok(())
     }
 
     pub fn seccomp_notify_request(&mut self, fd: RawFd) -> Result<(), ChannelError> {
         self.sender
-            .send_fds(Message::SeccompNotify, &[fd.as_raw_fd()])?;
+            .send_fds(Frame::new(Message::SeccompNotify), &[fd.as_raw_fd()])?;
 
         Ok(())
     }
@@ -68,24 +199,43 @@ impl MainSender {
     pub fn intermediate_ready(&mut self, pid: Pid) -> Result<(), ChannelError> {
         // Send over the IntermediateReady follow by the pid.
         tracing::debug!("sending init pid ({:?})", pid);
-        self.sender.send(Message::IntermediateReady(pid.as_raw()))?;
+        self.sender
+            .send(Frame::new(Message::IntermediateReady(pid.as_raw())))?;
+
+        Ok(())
+    }
+
+    // asks the main process to run the hooks which run in the runtime namespace
+    // once the container process exists
+    pub fn hooks_request(&mut self) -> Result<(), ChannelError> {
+        tracing::debug!("send hooks request");
+        self.sender.send(Frame::new(Message::HooksRequest))?;
 
         Ok(())
     }
 
     pub fn init_ready(&mut self) -> Result<(), ChannelError> {
-        self.sender.send(Message::InitReady)?;
+        self.sender.send(Frame::new(Message::InitReady))?;
 
         Ok(())
     }
 
-    pub fn exec_failed(&mut self, err: String) -> Result<(), ChannelError> {
-        self.sender.send(Message::ExecFailed(err))?;
+    pub fn exec_failed(&mut self, failure: ExecFailure) -> Result<(), ChannelError> {
+        self.sender.send(Frame::new(Message::ExecFailed(failure)))?;
         Ok(())
     }
 
-    pub fn send_error(&mut self, err: String) -> Result<(), ChannelError> {
-        self.sender.send(Message::OtherError(err))?;
+    /// Reports the failure of the intermediate or init process to the main
+    /// process
+    pub fn send_error(
+        &mut self,
+        process: ProcessRole,
+        err: &dyn std::error::Error,
+    ) -> Result<(), ChannelError> {
+        self.sender
+            .send(Frame::new(Message::Error(Box::new(ErrorFrame::new(
+                process, err,
+            )))))?;
         Ok(())
     }
 
@@ -97,58 +247,50 @@ impl MainSender {
 }
 
 pub struct MainReceiver {
-    receiver: Receiver<Message>,
+    receiver: Receiver<Frame>,
+    // The intermediate and the init process send to the main process
+    // independently, so the hooks request of the init process may arrive
+    // before the intermediate process reported that it is ready.
+    hooks_requested: bool,
 }
 
 impl MainReceiver {
+    /// Sets the point in time until which the main process waits for the
+    /// next message, `None` waits forever
+    pub fn set_deadline(&mut self, deadline: Option<Instant>) {
+        self.receiver.set_deadline(deadline);
+    }
+
     /// Waits for associated intermediate process to send ready message
     /// and return the pid of init process which is forked by intermediate process
     pub fn wait_for_intermediate_ready(&mut self) -> Result<Pid, ChannelError> {
-        let msg = self
-            .receiver
-            .recv()
-            .map_err(|err| ChannelError::ReceiveError {
-                msg: "waiting for intermediate process".to_string(),
-                source: err,
-            })?;
-
-        match msg {
+        let state = ProtocolState::IntermediateReady;
+        match receive(&mut self.receiver, state)? {
             Message::IntermediateReady(pid) => Ok(Pid::from_raw(pid)),
-            Message::ExecFailed(err) => Err(ChannelError::ExecError(err)),
-            Message::OtherError(err) => Err(ChannelError::OtherError(err)),
-            msg => Err(ChannelError::UnexpectedMessage {
-                expected: Message::IntermediateReady(0),
-                received: msg,
-            }),
+            Message::HooksRequest if !self.hooks_requested => {
+                self.hooks_requested = true;
+                self.wait_for_intermediate_ready()
+            }
+            msg => Err(unexpected(state, Message::IntermediateReady(0), msg)),
         }
     }
 
     pub fn wait_for_mapping_request(&mut self) -> Result<(), ChannelError> {
-        let msg = self
-            .receiver
-            .recv()
-            .map_err(|err| ChannelError::ReceiveError {
-                msg: "waiting for mapping request".to_string(),
-                source: err,
-            })?;
-        match msg {
+        let state = ProtocolState::MappingRequest;
+        match receive(&mut self.receiver, state)? {
             Message::WriteMapping => Ok(()),
-            msg => Err(ChannelError::UnexpectedMessage {
-                expected: Message::WriteMapping,
-                received: msg,
-            }),
+            msg => Err(unexpected(state, Message::WriteMapping, msg)),
         }
     }
 
     pub fn wait_for_seccomp_request(&mut self) -> Result<i32, ChannelError> {
-        let (msg, fds) = self.receiver.recv_with_fds::<[RawFd; 1]>().map_err(|err| {
-            ChannelError::ReceiveError {
-                msg: "waiting for seccomp request".to_string(),
-                source: err,
-            }
-        })?;
+        let state = ProtocolState::SeccompRequest;
+        let (frame, fds) = self
+            .receiver
+            .recv_with_fds::<[RawFd; 1]>()
+            .map_err(|source| ChannelError::ReceiveError { state, source })?;
 
-        match msg {
+        match open_frame(frame, state)? {
             Message::SeccompNotify => {
                 let fd = match fds {
                     Some(fds) => {
@@ -162,33 +304,37 @@ impl MainReceiver {
                 }?;
                 Ok(fd)
             }
-            msg => Err(ChannelError::UnexpectedMessage {
-                expected: Message::SeccompNotify,
-                received: msg,
-            }),
+            msg => Err(unexpected(state, Message::SeccompNotify, msg)),
+        }
+    }
+
+    /// Waits for the init process to ask for the hooks which run in the
+    /// runtime namespace
+    pub fn wait_for_hooks_request(&mut self) -> Result<(), ChannelError> {
+        if std::mem::take(&mut self.hooks_requested) {
+            return Ok(());
+        }
+
+        let state = ProtocolState::HooksRequest;
+        match receive(&mut self.receiver, state)? {
+            Message::HooksRequest => Ok(()),
+            msg => Err(unexpected(state, Message::HooksRequest, msg)),
         }
     }
 
     /// Waits for associated init process to send ready message
     /// and return the pid of init process which is forked by init process
     pub fn wait_for_init_ready(&mut self) -> Result<(), ChannelError> {
-        let msg = self
-            .receiver
-            .recv()
-            .map_err(|err| ChannelError::ReceiveError {
-                msg: "waiting for init ready".to_string(),
-                source: err,
-            })?;
-        match msg {
-            Message::InitReady => Ok(()),
+        let state = ProtocolState::InitReady;
+        match receive(&mut self.receiver, state) {
+            Ok(Message::InitReady) => Ok(()),
             // this case in unique and known enough to have a special error format
-            Message::ExecFailed(err) => Err(ChannelError::ExecError(format!(
-                "error in executing process : {err}"
-            ))),
-            msg => Err(ChannelError::UnexpectedMessage {
-                expected: Message::InitReady,
-                received: msg,
-            }),
+            Err(ChannelError::ExecError(failure)) => Err(ChannelError::ExecError(ExecFailure {
+                message: format!("error in executing process : {}", failure.message),
+                ..failure
+            })),
+            Err(err) => Err(err),
+            Ok(msg) => Err(unexpected(state, Message::InitReady, msg)),
         }
     }
 
@@ -200,7 +346,7 @@ impl MainReceiver {
 }
 
 pub fn intermediate_channel() -> Result<(IntermediateSender, IntermediateReceiver), ChannelError> {
-    let (sender, receiver) = channel::<Message>()?;
+    let (sender, receiver) = channel::<Frame>()?;
     Ok((
         IntermediateSender { sender },
         IntermediateReceiver { receiver },
@@ -208,13 +354,13 @@ pub fn intermediate_channel() -> Result<(IntermediateSender, IntermediateReceive
 }
 
 pub struct IntermediateSender {
-    sender: Sender<Message>,
+    sender: Sender<Frame>,
 }
 
 impl IntermediateSender {
     pub fn mapping_written(&mut self) -> Result<(), ChannelError> {
         tracing::debug!("identifier mapping written");
-        self.sender.send(Message::MappingWritten)?;
+        self.sender.send(Frame::new(Message::MappingWritten))?;
 
         Ok(())
     }
@@ -227,26 +373,17 @@ impl IntermediateSender {
 }
 
 pub struct IntermediateReceiver {
-    receiver: Receiver<Message>,
+    receiver: Receiver<Frame>,
 }
 
 impl IntermediateReceiver {
     // wait until the parent process has finished writing the id mappings
     pub fn wait_for_mapping_ack(&mut self) -> Result<(), ChannelError> {
         tracing::debug!("waiting for mapping ack");
-        let msg = self
-            .receiver
-            .recv()
-            .map_err(|err| ChannelError::ReceiveError {
-                msg: "waiting for mapping ack".to_string(),
-                source: err,
-            })?;
-        match msg {
+        let state = ProtocolState::MappingAck;
+        match receive(&mut self.receiver, state)? {
             Message::MappingWritten => Ok(()),
-            msg => Err(ChannelError::UnexpectedMessage {
-                expected: Message::MappingWritten,
-                received: msg,
-            }),
+            msg => Err(unexpected(state, Message::MappingWritten, msg)),
         }
     }
 
@@ -258,17 +395,31 @@ impl IntermediateReceiver {
 }
 
 pub fn init_channel() -> Result<(InitSender, InitReceiver), ChannelError> {
-    let (sender, receiver) = channel::<Message>()?;
+    let (sender, receiver) = channel::<Frame>()?;
     Ok((InitSender { sender }, InitReceiver { receiver }))
 }
 
 pub struct InitSender {
-    sender: Sender<Message>,
+    sender: Sender<Frame>,
 }
 
 impl InitSender {
     pub fn seccomp_notify_done(&mut self) -> Result<(), ChannelError> {
-        self.sender.send(Message::SeccompNotifyDone)?;
+        self.sender.send(Frame::new(Message::SeccompNotifyDone))?;
+
+        Ok(())
+    }
+
+    pub fn hooks_done(&mut self) -> Result<(), ChannelError> {
+        self.sender.send(Frame::new(Message::HooksDone))?;
+
+        Ok(())
+    }
+
+    pub fn hooks_failed(&mut self, err: &dyn std::error::Error) -> Result<(), ChannelError> {
+        let err = ErrorFrame::new(ProcessRole::Main, err);
+        self.sender
+            .send(Frame::new(Message::Error(Box::new(err))))?;
 
         Ok(())
     }
@@ -281,25 +432,29 @@ impl InitSender {
 }
 
 pub struct InitReceiver {
-    receiver: Receiver<Message>,
+    receiver: Receiver<Frame>,
+}
+
+impl AsRawFd for InitReceiver {
+    fn as_raw_fd(&self) -> RawFd {
+        self.receiver.as_raw_fd()
+    }
 }
 
 impl InitReceiver {
-    pub fn wait_for_seccomp_request_done(&mut self) -> Result<(), ChannelError> {
-        let msg = self
-            .receiver
-            .recv()
-            .map_err(|err| ChannelError::ReceiveError {
-                msg: "waiting for seccomp request".to_string(),
-                source: err,
-            })?;
+    pub fn wait_for_hooks_done(&mut self) -> Result<(), ChannelError> {
+        let state = ProtocolState::HooksDone;
+        match receive(&mut self.receiver, state)? {
+            Message::HooksDone => Ok(()),
+            msg => Err(unexpected(state, Message::HooksDone, msg)),
+        }
+    }
 
-        match msg {
+    pub fn wait_for_seccomp_request_done(&mut self) -> Result<(), ChannelError> {
+        let state = ProtocolState::SeccompRequestDone;
+        match receive(&mut self.receiver, state)? {
             Message::SeccompNotifyDone => Ok(()),
-            msg => Err(ChannelError::UnexpectedMessage {
-                expected: Message::SeccompNotifyDone,
-                received: msg,
-            }),
+            msg => Err(unexpected(state, Message::SeccompNotifyDone, msg)),
         }
     }
 
@@ -318,6 +473,7 @@ mod tests {
     use serial_test::serial;
 
     use super::*;
+    use crate::workload::ExecFailureKind;
 
     // Note: due to cargo test by default runs tests in parallel using a single
     // process, these tests should not be running in parallel with other tests.
@@ -414,6 +570,29 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    #[serial]
+    fn test_channel_hooks_request_before_intermediate_ready() -> Result<()> {
+        let (sender, receiver) = &mut main_channel()?;
+        match unsafe { unistd::fork()? } {
+            unistd::ForkResult::Parent { child } => {
+                wait::waitpid(child, None)?;
+                let pid = receiver.wait_for_intermediate_ready()?;
+                assert_eq!(pid, child);
+                receiver.wait_for_hooks_request()?;
+                receiver.close()?;
+            }
+            unistd::ForkResult::Child => {
+                sender.hooks_request()?;
+                sender.intermediate_ready(unistd::getpid())?;
+                sender.close()?;
+                std::process::exit(0);
+            }
+        };
+
+        Ok(())
+    }
+
     #[test]
     #[serial]
     fn test_channel_main_graceful_exit() -> Result<()> {
@@ -459,4 +638,125 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    #[serial]
+    fn test_channel_exec_failed() -> Result<()> {
+        let (sender, receiver) = &mut main_channel()?;
+        match unsafe { unistd::fork()? } {
+            unistd::ForkResult::Parent { child } => {
+                wait::waitpid(child, None)?;
+                match receiver.wait_for_init_ready() {
+                    Err(ChannelError::ExecError(failure)) => {
+                        assert_eq!(failure.kind, ExecFailureKind::NotFound);
+                        assert_eq!(
+                            failure.message,
+                            "error in executing process : executable 'missing' not found"
+                        );
+                    }
+                    ret => panic!("expected exec error, got {:?}", ret),
+                }
+                receiver.close()?;
+            }
+            unistd::ForkResult::Child => {
+                sender.exec_failed(ExecFailure::new(
+                    ExecFailureKind::NotFound,
+                    "executable 'missing' not found",
+                ))?;
+                sender.close()?;
+                std::process::exit(0);
+            }
+        };
+
+        Ok(())
+    }
+
+    #[test]
+    #[serial]
+    fn test_channel_error_frame() -> Result<()> {
+        let (sender, receiver) = &mut main_channel()?;
+        match unsafe { unistd::fork()? } {
+            unistd::ForkResult::Parent { child } => {
+                wait::waitpid(child, None)?;
+                match receiver.wait_for_mapping_request() {
+                    Err(ChannelError::Peer(err)) => {
+                        assert_eq!(err.process, ProcessRole::Intermediate);
+                        assert_eq!(
+                            err.to_string(),
+                            "intermediate process failed: failed to receive while waiting for \
+                             the mapping ack: channel connection broken"
+                        );
+                    }
+                    ret => panic!("expected error frame, got {:?}", ret),
+                }
+                receiver.close()?;
+            }
+            unistd::ForkResult::Child => {
+                let err = ChannelError::ReceiveError {
+                    state: ProtocolState::MappingAck,
+                    source: crate::channel::ChannelError::BrokenChannel,
+                };
+                sender.send_error(ProcessRole::Intermediate, &err)?;
+                sender.close()?;
+                std::process::exit(0);
+            }
+        };
+
+        Ok(())
+    }
+
+    #[test]
+    #[serial]
+    fn test_channel_version_mismatch() -> Result<()> {
+        let (mut sender, receiver) = channel::<Frame>()?;
+        let mut receiver = InitReceiver { receiver };
+        sender.send(Frame {
+            version: PROTOCOL_VERSION + 1,
+            message: Message::HooksDone,
+        })?;
+
+        let err = receiver.wait_for_hooks_done().unwrap_err();
+        assert!(
+            matches!(
+                err,
+                ChannelError::VersionMismatch {
+                    state: ProtocolState::HooksDone,
+                    received,
+                } if received == PROTOCOL_VERSION + 1
+            ),
+            "{err:?}"
+        );
+        assert_eq!(err.state().map(|state| state.phase()), Some(Phase::Hooks));
+        sender.close()?;
+        receiver.close()?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_error_frame_causes() {
+        #[derive(Debug, thiserror::Error)]
+        enum Inner {
+            #[error("no such file")]
+            NotFound,
+        }
+        #[derive(Debug, thiserror::Error)]
+        enum Outer {
+            #[error("failed to mount rootfs")]
+            Mount(#[source] Inner),
+            #[error("io error: {0}")]
+            Wrapped(#[source] Inner),
+        }
+
+        let frame = ErrorFrame::new(ProcessRole::Init, &Outer::Mount(Inner::NotFound));
+        assert_eq!(frame.causes, vec!["no such file".to_owned()]);
+        assert_eq!(
+            frame.to_string(),
+            "init process failed: failed to mount rootfs: no such file"
+        );
+
+        // a cause repeated in the message is not repeated again
+        let frame = ErrorFrame::new(ProcessRole::Init, &Outer::Wrapped(Inner::NotFound));
+        assert!(frame.causes.is_empty());
+    }
 }
@@ -1,37 +1,48 @@
 use std::collections::{HashMap, HashSet};
-use std::os::unix::io::AsRawFd;
-use std::path::{Path, PathBuf};
-use std::{env, fs, mem};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::path::Path;
+use std::{env, fs};
 
 use nc;
+use nix::fcntl;
 use nix::mount::{MntFlags, MsFlags};
 use nix::sched::CloneFlags;
 use nix::sys::stat::Mode;
+use nix::sys::statvfs::{statvfs, FsFlags};
 use nix::unistd::{self, close, dup2, setsid, Gid, Uid};
 use oci_spec::runtime::{
-    IOPriorityClass, LinuxIOPriority, LinuxNamespaceType, LinuxSchedulerFlag, LinuxSchedulerPolicy,
-    Scheduler, Spec, User,
+    IOPriorityClass, LinuxIOPriority, LinuxNamespaceType, Scheduler, Spec, User,
 };
 
 use super::args::{ContainerArgs, ContainerType};
+use crate::debug_capture::{InitReportFile, InitStage};
 use crate::error::MissingSpecError;
-use crate::namespaces::{NamespaceError, Namespaces};
-use crate::process::channel;
+use crate::fault_injection::{self, FaultPoint};
+use crate::hooks::LifecyclePoint;
+use crate::namespaces::{NamespaceError, Namespaces, CLONE_NEWTIME};
+use crate::prefault::Prefault;
+use crate::process::{channel, parent_death, scheduling};
 use crate::rootfs::RootFS;
 #[cfg(feature = "libseccomp")]
 use crate::seccomp;
+use crate::syscall::trace::SetupTrace;
 use crate::syscall::{Syscall, SyscallError};
 use crate::user_ns::UserNamespaceConfig;
-use crate::{apparmor, capabilities, hooks, notify_socket, rootfs, tty, utils, workload};
+use crate::workload::{ExecFailure, ExecFailureKind};
+use crate::{apparmor, capabilities, hooks, notify_socket, rootfs, sysctl, tty, utils, workload};
 
 #[derive(Debug, thiserror::Error)]
 pub enum InitProcessError {
-    #[error("failed to set sysctl")]
-    Sysctl(#[source] std::io::Error),
+    #[error(transparent)]
+    Sysctl(#[from] sysctl::SysctlError),
     #[error("failed to mount path as readonly")]
     MountPathReadonly(#[source] SyscallError),
     #[error("failed to mount path as masked")]
     MountPathMasked(#[source] SyscallError),
+    #[error("failed to remount root as readonly")]
+    RemountRootReadonly(#[source] SyscallError),
+    #[error("root is still writable after remounting it as readonly")]
+    RootNotReadonly,
     #[error(transparent)]
     Namespaces(#[from] NamespaceError),
     #[error("failed to set hostname")]
@@ -81,28 +92,31 @@ pub enum InitProcessError {
     SchedSetattr(String),
     #[error("failed to verify if current working directory is safe")]
     InvalidCwd(#[source] nix::Error),
+    #[error(transparent)]
+    ParentDeath(#[from] parent_death::ParentDeathError),
+    #[error(transparent)]
+    FaultInjection(#[from] fault_injection::InjectedFault),
+    #[error(transparent)]
+    DebugCapture(#[from] crate::debug_capture::DebugCaptureError),
+    #[error(transparent)]
+    Prefault(#[from] crate::prefault::PrefaultError),
 }
 
-type Result<T> = std::result::Result<T, InitProcessError>;
-
-fn sysctl(kernel_params: &HashMap<String, String>) -> Result<()> {
-    let sys = PathBuf::from("/proc/sys");
-    for (kernel_param, value) in kernel_params {
-        let path = sys.join(kernel_param.replace('.', "/"));
-        tracing::debug!(
-            "apply value {} to kernel parameter {}.",
-            value,
-            kernel_param
-        );
-        fs::write(path, value.as_bytes()).map_err(|err| {
-            tracing::error!("failed to set sysctl {kernel_param}={value}: {err}");
-            InitProcessError::Sysctl(err)
-        })?;
+impl InitProcessError {
+    /// Describes the error for the process waiting for the container to be
+    /// set up
+    pub fn exec_failure(&self) -> ExecFailure {
+        let kind = match self {
+            Self::Workload(err) => err.kind(),
+            Self::WorkloadValidation(err) => err.kind(),
+            _ => ExecFailureKind::Other,
+        };
+        ExecFailure::new(kind, self.to_string())
     }
-
-    Ok(())
 }
 
+type Result<T> = std::result::Result<T, InitProcessError>;
+
 // make a read only path
 // The first time we bind mount, other flags are ignored,
 // so we need to mount it once and then remount it with the necessary flags specified.
@@ -148,6 +162,62 @@ fn readonly_path(path: &Path, syscall: &dyn Syscall) -> Result<()> {
     Ok(())
 }
 
+// Remounting a bind mount replaces all of its per-mount flags, and the
+// kernel refuses to clear flags which are locked because the mount was
+// inherited from a more privileged mount namespace. Keep the flags the root
+// mount already has and only add MS_RDONLY on top.
+fn readonly_root_remount_flags(current: FsFlags) -> MsFlags {
+    let preserved = [
+        (FsFlags::ST_NOSUID, MsFlags::MS_NOSUID),
+        (FsFlags::ST_NODEV, MsFlags::MS_NODEV),
+        (FsFlags::ST_NOEXEC, MsFlags::MS_NOEXEC),
+        (FsFlags::ST_NOATIME, MsFlags::MS_NOATIME),
+        (FsFlags::ST_NODIRATIME, MsFlags::MS_NODIRATIME),
+        (FsFlags::ST_RELATIME, MsFlags::MS_RELATIME),
+    ];
+
+    preserved
+        .into_iter()
+        .filter(|(fs_flag, _)| current.contains(*fs_flag))
+        .fold(
+            MsFlags::MS_RDONLY | MsFlags::MS_REMOUNT | MsFlags::MS_BIND,
+            |flags, (_, ms_flag)| flags | ms_flag,
+        )
+}
+
+// Remount the root of the container as readonly and check that the kernel
+// actually applied it. This has to run after pivot_root (or the move of the
+// rootfs in the no-pivot case) and after every other mount was set up, so
+// that no later mount can target the root again.
+fn remount_root_readonly(syscall: &dyn Syscall) -> Result<()> {
+    let root = Path::new("/");
+    let current = statvfs(root).map_err(|err| {
+        tracing::error!(?err, "failed to statvfs root `/`");
+        InitProcessError::NixOther(err)
+    })?;
+
+    syscall
+        .mount(
+            None,
+            root,
+            None,
+            readonly_root_remount_flags(current.flags()),
+            None,
+        )
+        .map_err(|err| {
+            tracing::error!(?err, "failed to remount root `/` as readonly");
+            InitProcessError::RemountRootReadonly(err)
+        })?;
+
+    let remounted = statvfs(root).map_err(InitProcessError::NixOther)?;
+    if !remounted.flags().contains(FsFlags::ST_RDONLY) {
+        tracing::error!(flags = ?remounted.flags(), "root `/` is still writable");
+        return Err(InitProcessError::RootNotReadonly);
+    }
+
+    Ok(())
+}
+
 // For files, bind mounts /dev/null over the top of the specified path.
 // For directories, mounts read-only tmpfs over the top of the specified path.
 fn masked_path(path: &Path, mount_label: &Option<String>, syscall: &dyn Syscall) -> Result<()> {
@@ -194,23 +264,27 @@ fn masked_path(path: &Path, mount_label: &Option<String>, syscall: &dyn Syscall)
     Ok(())
 }
 
-// Enter into rest of namespace. Note, we already entered into user and pid
-// namespace. We also have to enter into mount namespace last since
-// namespace may be bind to /proc path. The /proc path will need to be
-// accessed before pivot_root.
+// Enter into rest of namespace. Note, we already entered into user, pid and
+// time namespace, as well as the namespaces the process was cloned into. We
+// also have to enter into mount namespace last since namespace may be bind to
+// /proc path. The /proc path will need to be accessed before pivot_root.
 fn apply_rest_namespaces(
     namespaces: &Namespaces,
+    cloned_namespaces: CloneFlags,
     spec: &Spec,
     syscall: &dyn Syscall,
 ) -> Result<()> {
     namespaces
         .apply_namespaces(|ns_type| -> bool {
-            ns_type != CloneFlags::CLONE_NEWUSER && ns_type != CloneFlags::CLONE_NEWPID
+            ns_type != CloneFlags::CLONE_NEWUSER
+                && ns_type != CloneFlags::CLONE_NEWPID
+                && ns_type != CLONE_NEWTIME
+                && !cloned_namespaces.contains(ns_type)
         })
         .map_err(|err| {
             tracing::error!(
                 ?err,
-                "failed to apply rest of the namespaces (exclude user and pid)"
+                "failed to apply rest of the namespaces (exclude user, pid and time)"
             );
             InitProcessError::Namespaces(err)
         })?;
@@ -346,6 +420,7 @@ pub fn container_init_process(
     args: &ContainerArgs,
     main_sender: &mut channel::MainSender,
     init_receiver: &mut channel::InitReceiver,
+    cloned_namespaces: CloneFlags,
 ) -> Result<()> {
     let syscall = args.syscall.create_syscall();
     let spec = &args.spec;
@@ -358,6 +433,9 @@ pub fn container_init_process(
     let container = args.container.as_ref();
     let namespaces = Namespaces::try_from(linux.namespaces().as_ref())?;
     let notify_listener = &args.notify_listener;
+    let mut init_report = args.init_report.clone();
+
+    args.parent_death.arm_for_setup()?;
 
     setsid().map_err(|err| {
         tracing::error!(?err, "failed to setsid to create a session");
@@ -389,23 +467,25 @@ pub fn container_init_process(
         }
     }
 
-    apply_rest_namespaces(&namespaces, spec, syscall.as_ref())?;
+    apply_rest_namespaces(&namespaces, cloned_namespaces, spec, syscall.as_ref())?;
 
     if let Some(true) = proc.no_new_privileges() {
         let _ = prctl::set_no_new_privileges(true);
     }
 
     if matches!(args.container_type, ContainerType::InitContainer) {
-        // create_container hook needs to be called after the namespace setup, but
-        // before pivot_root is called. This runs in the container namespaces.
-        if let Some(hooks) = hooks {
-            hooks::run_hooks(hooks.create_container().as_ref(), container, None).map_err(
-                |err| {
-                    tracing::error!(?err, "failed to run create container hooks");
-                    InitProcessError::Hooks(err)
-                },
-            )?;
-        }
+        // The prestart and createRuntime hooks are run by the main process in
+        // the runtime namespace and have to finish before the createContainer
+        // hooks start.
+        main_sender.hooks_request()?;
+        init_receiver.wait_for_hooks_done()?;
+
+        // Verify the rootfs before anything gets mounted on top of it, so
+        // that only the content shipped in the bundle is measured.
+        rootfs::integrity::verify(spec.annotations().as_ref(), rootfs_path).map_err(|err| {
+            tracing::error!(?err, "failed to verify rootfs integrity");
+            InitProcessError::RootFS(err.into())
+        })?;
 
         let in_user_ns = utils::is_in_new_userns().map_err(InitProcessError::Io)?;
         let bind_service = namespaces.get(LinuxNamespaceType::User)?.is_some() || in_user_ns;
@@ -416,11 +496,19 @@ pub fn container_init_process(
                 rootfs_path,
                 bind_service,
                 namespaces.get(LinuxNamespaceType::Cgroup)?.is_some(),
+                &args.idmapped_mounts,
             )
             .map_err(|err| {
                 tracing::error!(?err, "failed to prepare rootfs");
                 InitProcessError::RootFS(err)
             })?;
+        fault_injection::inject(args.fault_point, FaultPoint::AfterRootfsPrepare)?;
+
+        // The createContainer hooks run in the container namespaces once the
+        // mounts of the rootfs are set up, but before pivot_root, like with
+        // runc. The readonly and masked paths are applied after them, so
+        // files the hooks create below those paths are covered as well.
+        hooks::run_lifecycle_hooks(LifecyclePoint::ContainerCreated, hooks, container, None)?;
 
         // Entering into the rootfs jail. If mount namespace is specified, then
         // we use pivot_root, but if we are on the host mount namespace, we will
@@ -443,8 +531,16 @@ pub fn container_init_process(
             err
         })?;
 
+        // The sysctls have been checked against the namespaces the
+        // container owns while validating the spec.
         if let Some(kernel_params) = linux.sysctl() {
-            sysctl(kernel_params)?;
+            sysctl::apply(kernel_params)?;
+        }
+
+        // The cgroup limits the memory already, so the prefaulted memory is
+        // charged to the container.
+        if let Some(prefault) = Prefault::from_spec(spec)? {
+            prefault.apply()?;
         }
     }
 
@@ -455,21 +551,6 @@ pub fn container_init_process(
         })?;
     }
 
-    if let Some(true) = spec.root().as_ref().map(|r| r.readonly().unwrap_or(false)) {
-        syscall
-            .mount(
-                None,
-                Path::new("/"),
-                None,
-                MsFlags::MS_RDONLY | MsFlags::MS_REMOUNT | MsFlags::MS_BIND,
-                None,
-            )
-            .map_err(|err| {
-                tracing::error!(?err, "failed to remount root `/` as readonly");
-                InitProcessError::SyscallOther(err)
-            })?;
-    }
-
     if let Some(umask) = proc.user().umask() {
         match Mode::from_bits(umask) {
             Some(mode) => {
@@ -501,6 +582,10 @@ pub fn container_init_process(
         }
     }
 
+    if let Some(true) = spec.root().as_ref().map(|r| r.readonly().unwrap_or(false)) {
+        remount_root_readonly(syscall.as_ref())?;
+    }
+
     let cwd = format!("{}", proc.cwd().display());
     let do_chdir = if cwd.is_empty() {
         false
@@ -534,6 +619,8 @@ pub fn container_init_process(
             tracing::error!(?err, ?uid, ?gid, "failed to set uid and gid");
             InitProcessError::SyscallOther(err)
         })?;
+    // changing the credentials cleared the parent death signal
+    args.parent_death.arm_for_setup()?;
 
     // Take care of LISTEN_FDS used for systemd-active-socket. If the value is
     // not 0, then we have to preserve those fds as well, and set up the correct
@@ -585,6 +672,23 @@ pub fn container_init_process(
         InitProcessError::SyscallOther(err)
     })?;
 
+    // The main process marked all fds CLOEXEC before cloning, including the
+    // preserved ones. Fds of youki which got a number in the preserved range,
+    // because the caller passed fewer fds than it asked to preserve, must
+    // still not leak into the container.
+    let mut internal_fds = vec![
+        notify_listener.as_raw_fd(),
+        main_sender.as_raw_fd(),
+        init_receiver.as_raw_fd(),
+    ];
+    internal_fds.extend(args.console_socket);
+    internal_fds.extend(init_report.as_ref().map(InitReportFile::as_raw_fd));
+    internal_fds.extend(SetupTrace::get().map(SetupTrace::as_raw_fd));
+    if let ContainerType::TenantContainer { exec_notify_fd } = args.container_type {
+        internal_fds.push(exec_notify_fd);
+    }
+    inherit_preserved_fds(preserve_fds, &internal_fds)?;
+
     // Without no new privileges, seccomp is a privileged operation. We have to
     // do this before dropping capabilities. Otherwise, we should do it later,
     // as close to exec as possible.
@@ -663,6 +767,10 @@ pub fn container_init_process(
     args.executor.validate(spec)?;
     args.executor.setup_envs(envs)?;
 
+    // From here on the init outlives a detached youki, so the setup signal
+    // is replaced by the one configured for the container.
+    args.parent_death.arm_for_init()?;
+
     // Notify main process that the init process is ready to execute the
     // payload.  Note, because we are already inside the pid namespace, the pid
     // outside the pid namespace should be recorded by the intermediate process
@@ -678,6 +786,9 @@ pub fn container_init_process(
         tracing::error!(?err, "failed to close down main sender in init process");
         InitProcessError::Channel(err)
     })?;
+    if let Some(report) = &mut init_report {
+        report.reached(InitStage::Ready)?;
+    }
 
     // listing on the notify socket for container start command
     notify_listener.wait_for_container_start().map_err(|err| {
@@ -688,16 +799,17 @@ pub fn container_init_process(
         tracing::error!(?err, "failed to close notify socket");
         err
     })?;
+    if let Some(report) = &mut init_report {
+        report.reached(InitStage::Started).map_err(|err| {
+            tracing::error!(?err, "failed to record the start of the container");
+            err
+        })?;
+    }
 
-    // create_container hook needs to be called after the namespace setup, but
-    // before pivot_root is called. This runs in the container namespaces.
+    // start_container hook needs to be called right before the user process
+    // is executed. This runs in the container namespaces.
     if matches!(args.container_type, ContainerType::InitContainer) {
-        if let Some(hooks) = hooks {
-            hooks::run_hooks(hooks.start_container().as_ref(), container, None).map_err(|err| {
-                tracing::error!(?err, "failed to run start container hooks");
-                err
-            })?;
-        }
+        hooks::run_lifecycle_hooks(LifecyclePoint::ContainerStarting, hooks, container, None)?;
     }
 
     if proc.args().is_none() {
@@ -705,6 +817,10 @@ pub fn container_init_process(
         Err(MissingSpecError::Args)?;
     }
 
+    fault_injection::inject(args.fault_point, FaultPoint::BeforeExec)?;
+    if let Some(report) = &mut init_report {
+        report.reached(InitStage::Exec)?;
+    }
     args.executor.exec(spec).map_err(|err| {
         tracing::error!(?err, "failed to execute payload");
         err
@@ -834,43 +950,7 @@ fn set_io_priority(syscall: &dyn Syscall, io_priority_op: &Option<LinuxIOPriorit
 /// Set the RT priority of a thread
 fn setup_scheduler(sc_op: &Option<Scheduler>) -> Result<()> {
     if let Some(sc) = sc_op {
-        let policy: u32 = match *sc.policy() {
-            LinuxSchedulerPolicy::SchedOther => 0,
-            LinuxSchedulerPolicy::SchedFifo => 1,
-            LinuxSchedulerPolicy::SchedRr => 2,
-            LinuxSchedulerPolicy::SchedBatch => 3,
-            LinuxSchedulerPolicy::SchedIso => 4,
-            LinuxSchedulerPolicy::SchedIdle => 5,
-            LinuxSchedulerPolicy::SchedDeadline => 6,
-        };
-        let mut flags_value: u64 = 0;
-        if let Some(flags) = sc.flags() {
-            for flag in flags {
-                match *flag {
-                    LinuxSchedulerFlag::SchedResetOnFork => flags_value |= 0x01,
-                    LinuxSchedulerFlag::SchedFlagReclaim => flags_value |= 0x02,
-                    LinuxSchedulerFlag::SchedFlagDLOverrun => flags_value |= 0x04,
-                    LinuxSchedulerFlag::SchedFlagKeepPolicy => flags_value |= 0x08,
-                    LinuxSchedulerFlag::SchedFlagKeepParams => flags_value |= 0x10,
-                    LinuxSchedulerFlag::SchedFlagUtilClampMin => flags_value |= 0x20,
-                    LinuxSchedulerFlag::SchedFlagUtilClampMax => flags_value |= 0x40,
-                }
-            }
-        }
-        let a = nc::sched_attr_t {
-            // size of the structure should always be within u32 bounds,
-            // so this unwrap should never fail
-            size: mem::size_of::<nc::sched_attr_t>().try_into().unwrap(),
-            sched_policy: policy,
-            sched_flags: flags_value,
-            sched_nice: sc.nice().unwrap_or(0),
-            sched_priority: sc.priority().unwrap_or(0) as u32,
-            sched_runtime: sc.runtime().unwrap_or(0),
-            sched_deadline: sc.deadline().unwrap_or(0),
-            sched_period: sc.period().unwrap_or(0),
-            sched_util_min: 0,
-            sched_util_max: 0,
-        };
+        let a = scheduling::sched_attr(sc);
         // TODO when nix or libc support this function, replace nx crates.
         unsafe {
             let result = nc::sched_setattr(0, &a, 0);
@@ -935,15 +1015,35 @@ fn verify_cwd() -> Result<()> {
     Ok(())
 }
 
+/// Clears CLOEXEC of the preserved fds following stdio, except for the
+/// given fds which belong to youki itself. Gaps in the preserved range are
+/// skipped.
+fn inherit_preserved_fds(preserve_fds: i32, internal_fds: &[RawFd]) -> Result<()> {
+    for fd in 3..3 + preserve_fds {
+        if internal_fds.contains(&fd) {
+            continue;
+        }
+        match fcntl::fcntl(fd, fcntl::F_SETFD(fcntl::FdFlag::empty())) {
+            Ok(_) | Err(nix::Error::EBADF) => {}
+            Err(err) => {
+                tracing::error!(?err, fd, "failed to clear CLOEXEC of preserved fd");
+                return Err(InitProcessError::NixOther(err));
+            }
+        }
+    }
+
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use std::fs;
+    use std::path::PathBuf;
 
     use anyhow::Result;
     #[cfg(feature = "libseccomp")]
     use nix::unistd;
     use oci_spec::runtime::{LinuxNamespaceBuilder, SpecBuilder, UserBuilder};
-    #[cfg(feature = "libseccomp")]
     use serial_test::serial;
 
     use super::*;
@@ -987,6 +1087,23 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_readonly_root_remount_flags() {
+        let base = MsFlags::MS_RDONLY | MsFlags::MS_REMOUNT | MsFlags::MS_BIND;
+        assert_eq!(readonly_root_remount_flags(FsFlags::empty()), base);
+        assert_eq!(
+            readonly_root_remount_flags(
+                FsFlags::ST_NOSUID | FsFlags::ST_NODEV | FsFlags::ST_RELATIME
+            ),
+            base | MsFlags::MS_NOSUID | MsFlags::MS_NODEV | MsFlags::MS_RELATIME
+        );
+        // flags which a bind remount cannot change are not passed on
+        assert_eq!(
+            readonly_root_remount_flags(FsFlags::ST_RDONLY | FsFlags::ST_SYNCHRONOUS),
+            base
+        );
+    }
+
     #[test]
     fn test_apply_rest_namespaces() -> Result<()> {
         let syscall = create_syscall();
@@ -1001,7 +1118,7 @@ mod tests {
         ];
         let namespaces = Namespaces::try_from(Some(&linux_spaces))?;
 
-        apply_rest_namespaces(&namespaces, &spec, syscall.as_ref())?;
+        apply_rest_namespaces(&namespaces, CloneFlags::empty(), &spec, syscall.as_ref())?;
 
         let got_hostnames = syscall
             .as_ref()
@@ -1217,4 +1334,23 @@ mod tests {
         let set_io_prioritys = test_command.get_io_priority_args();
         assert_eq!(set_io_prioritys[0], want_io_priority);
     }
+
+    #[test]
+    #[serial]
+    fn test_inherit_preserved_fds() -> Result<()> {
+        let preserved = fs::File::open("/dev/null")?;
+        let internal = fs::File::open("/dev/null")?;
+        let last = preserved.as_raw_fd().max(internal.as_raw_fd());
+
+        inherit_preserved_fds(last - 2, &[internal.as_raw_fd()])?;
+
+        let cloexec = |fd: RawFd| -> Result<bool> {
+            let flags = fcntl::FdFlag::from_bits_truncate(fcntl::fcntl(fd, fcntl::F_GETFD)?);
+            Ok(flags.contains(fcntl::FdFlag::FD_CLOEXEC))
+        };
+        assert!(!cloexec(preserved.as_raw_fd())?);
+        assert!(cloexec(internal.as_raw_fd())?);
+
+        Ok(())
+    }
 }
@@ -1,16 +1,20 @@
 use std::os::fd::FromRawFd;
 
 use libcgroups::common::CgroupManager;
+use nix::errno::Errno;
+use nix::sched::CloneFlags;
 use nix::unistd::{close, write, Gid, Pid, Uid};
 use oci_spec::runtime::{LinuxNamespace, LinuxNamespaceType, LinuxResources};
 use procfs::process::Process;
 
 use super::args::{ContainerArgs, ContainerType};
+use super::cgroup_delegation;
 use super::channel::{IntermediateReceiver, MainSender};
 use super::container_init_process::container_init_process;
 use super::fork::CloneCb;
 use crate::error::MissingSpecError;
-use crate::namespaces::Namespaces;
+use crate::fault_injection::{self, FaultPoint};
+use crate::namespaces::{NamespaceError, Namespaces};
 use crate::process::{channel, fork};
 
 #[derive(Debug, thiserror::Error)]
@@ -31,6 +35,12 @@ pub enum IntermediateProcessError {
     ExecNotify(#[source] nix::Error),
     #[error(transparent)]
     MissingSpec(#[from] crate::error::MissingSpecError),
+    #[error(transparent)]
+    ParentDeath(#[from] crate::process::parent_death::ParentDeathError),
+    #[error(transparent)]
+    CgroupDelegation(#[from] crate::process::cgroup_delegation::CgroupDelegationError),
+    #[error(transparent)]
+    FaultInjection(#[from] crate::fault_injection::InjectedFault),
     #[error("other error")]
     Other(String),
 }
@@ -45,6 +55,7 @@ pub fn container_intermediate_process(
 ) -> Result<()> {
     let (inter_sender, inter_receiver) = intermediate_chan;
     let (init_sender, init_receiver) = init_chan;
+    args.parent_death.arm_for_setup()?;
     let command = args.syscall.create_syscall();
     let spec = &args.spec;
     let linux = spec.linux().as_ref().ok_or(MissingSpecError::Linux)?;
@@ -62,11 +73,17 @@ pub fn container_intermediate_process(
     // In addition this needs to be done before we enter the cgroup namespace as
     // the cgroup of the process will form the root of the cgroup hierarchy in
     // the cgroup namespace.
-    apply_cgroups(
-        &cgroup_manager,
-        linux.resources().as_ref(),
-        matches!(args.container_type, ContainerType::InitContainer),
-    )?;
+    let pid = Pid::from_raw(Process::myself()?.pid());
+    match &args.tenant_cgroup {
+        Some(cgroup) => cgroup_delegation::join(cgroup, pid)?,
+        None => apply_cgroups(
+            &cgroup_manager,
+            pid,
+            linux.resources().as_ref(),
+            matches!(args.container_type, ContainerType::InitContainer),
+        )?,
+    }
+    fault_injection::inject(args.fault_point, FaultPoint::AfterCgroupCreate)?;
 
     // if new user is specified in specification, this will be true and new
     // namespace will be created, check
@@ -82,24 +99,34 @@ pub fn container_intermediate_process(
         // root in the user namespace likely is mapped to an non-privileged user
         // on the parent user namespace.
         command.set_id(Uid::from_raw(0), Gid::from_raw(0))?;
+        // changing the credentials cleared the parent death signal
+        args.parent_death.arm_for_setup()?;
     }
 
     // set limits and namespaces to the process
-    let proc = spec.process().as_ref().ok_or(MissingSpecError::Process)?;
-    if let Some(rlimits) = proc.rlimits() {
-        for rlimit in rlimits {
-            command.set_rlimit(rlimit).map_err(|err| {
-                tracing::error!(?err, ?rlimit, "failed to set rlimit");
-                err
-            })?;
-        }
-    }
+    set_rlimits(args)?;
 
     // Pid namespace requires an extra fork to enter, so we enter pid namespace now.
     if let Some(pid_namespace) = namespaces.get(LinuxNamespaceType::Pid)? {
         namespaces.unshare_or_setns(pid_namespace)?;
     }
 
+    // The same holds for a new time namespace. Its clock offsets could only
+    // be set before the init process enters it, which youki does not support
+    // yet. Joining a time namespace requires a single threaded process, which
+    // the intermediate process is.
+    if let Some(time_namespace) = namespaces.get(LinuxNamespaceType::Time)? {
+        let has_offsets = linux
+            .time_offsets()
+            .as_ref()
+            .map_or(false, |offsets| !offsets.is_empty());
+        if time_namespace.path().is_none() && has_offsets {
+            tracing::error!("time offsets of a new time namespace are not supported");
+            return Err(NamespaceError::NotSupported("time offsets".to_string()).into());
+        }
+        namespaces.unshare_or_setns(time_namespace)?;
+    }
+
     let cb: CloneCb = {
         Box::new(|| {
             if let Err(ret) = prctl::set_name("youki:[2:INIT]") {
@@ -118,18 +145,27 @@ pub fn container_intermediate_process(
                 tracing::error!(?err, "failed to close sender in the intermediate process");
                 return -1;
             }
-            match container_init_process(args, main_sender, init_receiver) {
+            match container_init_process(args, main_sender, init_receiver, CloneFlags::empty()) {
                 Ok(_) => 0,
                 Err(e) => {
+                    let last_errno = Errno::last();
                     tracing::error!("failed to initialize container process: {e}");
-                    if let Err(err) = main_sender.exec_failed(e.to_string()) {
+                    let failure = e.exec_failure();
+                    let exit_code = failure.kind.exit_code().unwrap_or(-1);
+                    if let Some(mut report) = args.init_report.clone() {
+                        report.failed(&e, last_errno, exit_code);
+                    }
+                    if let Err(err) = main_sender.exec_failed(failure.clone()) {
                         tracing::error!(?err, "failed sending error to main sender");
                     }
                     if let ContainerType::TenantContainer { exec_notify_fd } = args.container_type {
-                        let buf = format!("{e}");
+                        // the tenant builder reads the failure until the pipe
+                        // is closed
+                        let buf = serde_json::to_vec(&failure)
+                            .unwrap_or_else(|_| failure.message.clone().into_bytes());
                         let exec_notify_fd =
                             unsafe { std::os::fd::OwnedFd::from_raw_fd(exec_notify_fd) };
-                        if let Err(err) = write(&exec_notify_fd, buf.as_bytes()) {
+                        if let Err(err) = write(&exec_notify_fd, &buf) {
                             tracing::error!(?err, "failed to write to exec notify fd");
                         }
 
@@ -137,7 +173,7 @@ pub fn container_intermediate_process(
                         // we need to explicitly close the pipe.
                         drop(exec_notify_fd);
                     }
-                    -1
+                    exit_code
                 }
             }
         })
@@ -201,6 +237,12 @@ fn setup_userns(
     }
 
     tracing::debug!("creating new user namespace");
+    request_id_mapping(sender, receiver)
+}
+
+// Asks the main process to write the uid and gid mappings of the new user
+// namespace of this process and waits until it did
+fn request_id_mapping(sender: &mut MainSender, receiver: &mut IntermediateReceiver) -> Result<()> {
     // child needs to be dumpable, otherwise the non root parent is not
     // allowed to write the uid/gid maps
     prctl::set_dumpable(true).map_err(|e| {
@@ -226,15 +268,53 @@ fn setup_userns(
     Ok(())
 }
 
-fn apply_cgroups<
+fn set_rlimits(args: &ContainerArgs) -> Result<()> {
+    let command = args.syscall.create_syscall();
+    let proc = args
+        .spec
+        .process()
+        .as_ref()
+        .ok_or(MissingSpecError::Process)?;
+    if let Some(rlimits) = proc.rlimits() {
+        for rlimit in rlimits {
+            command.set_rlimit(rlimit).map_err(|err| {
+                tracing::error!(?err, ?rlimit, "failed to set rlimit");
+                err
+            })?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Does what the intermediate process does before forking the init process,
+/// for an init process which the main process cloned directly into its user
+/// namespace. The main process added it to the cgroup already.
+pub(super) fn setup_cloned_init_process(
+    args: &ContainerArgs,
+    main_sender: &mut MainSender,
+    inter_receiver: &mut IntermediateReceiver,
+) -> Result<()> {
+    args.parent_death.arm_for_setup()?;
+    request_id_mapping(main_sender, inter_receiver)?;
+
+    let command = args.syscall.create_syscall();
+    command.set_id(Uid::from_raw(0), Gid::from_raw(0))?;
+    // changing the credentials cleared the parent death signal
+    args.parent_death.arm_for_setup()?;
+
+    set_rlimits(args)
+}
+
+pub(super) fn apply_cgroups<
     C: CgroupManager<Error = E> + ?Sized,
     E: std::error::Error + Send + Sync + 'static,
 >(
     cmanager: &C,
+    pid: Pid,
     resources: Option<&LinuxResources>,
     init: bool,
 ) -> Result<()> {
-    let pid = Pid::from_raw(Process::myself()?.pid());
     cmanager.add_task(pid).map_err(|err| {
         tracing::error!(?pid, ?err, ?init, "failed to add task to cgroup");
         IntermediateProcessError::Cgroup(err.to_string())
@@ -242,14 +322,7 @@ fn apply_cgroups<
 
     if let Some(resources) = resources {
         if init {
-            let controller_opt = libcgroups::common::ControllerOpt {
-                resources,
-                freezer_state: None,
-                oom_score_adj: None,
-                disable_oom_killer: false,
-            };
-
-            cmanager.apply(&controller_opt).map_err(|err| {
+            libcgroups::common::apply_resources(cmanager, resources).map_err(|err| {
                 tracing::error!(?pid, ?err, ?init, "failed to apply cgroup");
                 IntermediateProcessError::Cgroup(err.to_string())
             })?;
@@ -276,7 +349,12 @@ mod tests {
         let resources = LinuxResources::default();
 
         // act
-        apply_cgroups(&cmanager, Some(&resources), true)?;
+        apply_cgroups(
+            &cmanager,
+            Pid::from_raw(Process::myself()?.pid()),
+            Some(&resources),
+            true,
+        )?;
 
         // assert
         assert!(cmanager.get_add_task_args().len() == 1);
@@ -295,7 +373,12 @@ mod tests {
         let resources = LinuxResources::default();
 
         // act
-        apply_cgroups(&cmanager, Some(&resources), false)?;
+        apply_cgroups(
+            &cmanager,
+            Pid::from_raw(Process::myself()?.pid()),
+            Some(&resources),
+            false,
+        )?;
 
         // assert
         assert_eq!(
@@ -312,7 +395,12 @@ mod tests {
         let cmanager = TestManager::default();
 
         // act
-        apply_cgroups(&cmanager, None, true)?;
+        apply_cgroups(
+            &cmanager,
+            Pid::from_raw(Process::myself()?.pid()),
+            None,
+            true,
+        )?;
         // assert
         assert_eq!(
             cmanager.get_add_task_args()[0],
@@ -1,9 +1,26 @@
+use std::time::Instant;
+
+use libcgroups::namespace::{self, ProcessCgroup};
+use nix::errno::Errno;
+use nix::sched::CloneFlags;
+use nix::sys::signal::{self, Signal};
 use nix::sys::wait::{waitpid, WaitStatus};
 use nix::unistd::Pid;
-
-use crate::process::args::ContainerArgs;
+use oci_spec::runtime::{LinuxNamespace, LinuxNamespaceType, Spec};
+
+use crate::fault_injection::{self, FaultPoint};
+use crate::hooks::{self, LifecyclePoint};
+use crate::kernel::{self, Feature};
+use crate::namespaces::Namespaces;
+use crate::process::args::{ContainerArgs, ContainerType};
+use crate::process::cgroup_delegation::{self, SubtreeControl};
+use crate::process::container_init_process::container_init_process;
 use crate::process::fork::{self, CloneCb};
 use crate::process::intel_rdt::setup_intel_rdt;
+use crate::process::message::ProcessRole;
+#[cfg(feature = "libseccomp")]
+use crate::process::seccomp_listener::SeccompListenerError;
+use crate::process::timeouts::{Deadline, Phase};
 use crate::process::{channel, container_intermediate_process};
 use crate::syscall::SyscallError;
 use crate::user_ns::UserNamespaceConfig;
@@ -27,73 +44,76 @@ pub enum ProcessError {
     #[error("failed seccomp listener")]
     #[cfg(feature = "libseccomp")]
     SeccompListener(#[from] crate::process::seccomp_listener::SeccompListenerError),
+    #[error(transparent)]
+    SeccompBroker(#[from] crate::seccomp_broker::SeccompBrokerError),
     #[error("failed syscall")]
     SyscallOther(#[source] SyscallError),
+    #[error("cgroup error: {0}")]
+    Cgroup(String),
+    #[error(transparent)]
+    Hooks(#[from] hooks::HookError),
+    #[error(transparent)]
+    CgroupDelegation(#[from] cgroup_delegation::CgroupDelegationError),
+    #[error(transparent)]
+    CgroupNamespace(#[from] libcgroups::namespace::NamespaceError),
+    #[error(transparent)]
+    FaultInjection(#[from] fault_injection::InjectedFault),
+    #[error("timed out in the {0} phase of the container setup")]
+    Timeout(Phase),
 }
 
 type Result<T> = std::result::Result<T, ProcessError>;
 
-pub fn container_main_process(container_args: &ContainerArgs) -> Result<(Pid, bool)> {
+/// Starts the processes of the container. Returns the pid of the init
+/// process, whether the intel rdt directory has to be removed with the
+/// container, and the roots of the cgroup namespace the init process created.
+pub fn container_main_process(
+    container_args: &ContainerArgs,
+) -> Result<(Pid, bool, Option<Vec<ProcessCgroup>>)> {
     // We use a set of channels to communicate between parent and child process.
     // Each channel is uni-directional. Because we will pass these channel to
     // cloned process, we have to be deligent about closing any unused channel.
     // At minimum, we have to close down any unused senders. The corresponding
     // receivers will be cleaned up once the senders are closed down.
-    let (mut main_sender, mut main_receiver) = channel::main_channel()?;
+    let (mut main_sender, main_receiver) = channel::main_channel()?;
     let mut inter_chan = channel::intermediate_channel()?;
     let mut init_chan = channel::init_channel()?;
 
-    let cb: CloneCb = {
-        Box::new(|| {
-            if let Err(ret) = prctl::set_name("youki:[1:INTER]") {
-                tracing::error!(?ret, "failed to set name for child process");
-                return ret;
-            }
-
-            match container_intermediate_process::container_intermediate_process(
-                container_args,
-                &mut inter_chan,
-                &mut init_chan,
-                &mut main_sender,
-            ) {
-                Ok(_) => 0,
-                Err(err) => {
-                    tracing::error!("failed to run intermediate process {}", err);
-                    match main_sender.send_error(err.to_string()) {
-                        Ok(_) => {}
-                        Err(e) => {
-                            tracing::error!(
-                                "error in sending intermediate error message {} to main: {}",
-                                err,
-                                e
-                            )
-                        }
-                    }
-                    -1
-                }
-            }
-        })
-    };
-
     // Before starting the intermediate process, mark all non-stdio open files as O_CLOEXEC
     // to ensure we don't leak any file descriptors to the intermediate process.
     // Please refer to https://github.com/opencontainers/runc/security/advisories/GHSA-xr7r-f8xq-vfvv for more details.
     let syscall = container_args.syscall.create_syscall();
+    let overall_deadline = container_args
+        .timeouts
+        .create()
+        .map(|timeout| Instant::now() + timeout);
     syscall.close_range(0).map_err(|err| {
         tracing::error!(?err, "failed to cleanup extra fds");
         ProcessError::SyscallOther(err)
     })?;
 
-    let container_clone_fn = if container_args.as_sibling {
-        fork::container_clone_sibling
-    } else {
-        fork::container_clone
+    // Where possible the init process is cloned into its namespaces right
+    // away, otherwise it is forked by the intermediate process once that
+    // entered them.
+    let cloned_init = match direct_clone_namespaces(container_args) {
+        Some(namespaces) => clone_init_process(
+            container_args,
+            namespaces,
+            &mut inter_chan,
+            &mut init_chan,
+            &mut main_sender,
+        ),
+        None => None,
+    };
+    let cloned = match cloned_init {
+        Some(init_pid) => Cloned::Init(init_pid),
+        None => Cloned::Intermediate(clone_intermediate_process(
+            container_args,
+            &mut inter_chan,
+            &mut init_chan,
+            &mut main_sender,
+        )?),
     };
-
-    let intermediate_pid = container_clone_fn(cb).map_err(|err| {
-        tracing::error!("failed to fork intermediate process: {}", err);
-        ProcessError::IntermediateProcessFailed(err)
-    })?;
 
     // Close down unused fds. The corresponding fds are duplicated to the
     // child process during clone.
@@ -102,19 +122,66 @@ pub fn container_main_process(container_args: &ContainerArgs) -> Result<(Pid, bo
         err
     })?;
 
+    let mut init_pid = None;
+    let result = wait_for_container(
+        container_args,
+        cloned,
+        overall_deadline,
+        &mut init_pid,
+        main_receiver,
+        inter_chan,
+        init_chan,
+    );
+    if let Err(ProcessError::Timeout(_)) = &result {
+        kill_stuck_processes(cloned, init_pid);
+    }
+
+    result
+}
+
+// Waits for the cloned process and the init process to set up the container,
+// and does the parts of the setup which belong to the main process
+fn wait_for_container(
+    container_args: &ContainerArgs,
+    cloned: Cloned,
+    overall_deadline: Option<Instant>,
+    known_init_pid: &mut Option<Pid>,
+    mut main_receiver: channel::MainReceiver,
+    inter_chan: (channel::IntermediateSender, channel::IntermediateReceiver),
+    init_chan: (channel::InitSender, channel::InitReceiver),
+) -> Result<(Pid, bool, Option<Vec<ProcessCgroup>>)> {
+    let timeouts = &container_args.timeouts;
     let (mut inter_sender, inter_receiver) = inter_chan;
-    #[cfg(feature = "libseccomp")]
     let (mut init_sender, init_receiver) = init_chan;
-    #[cfg(not(feature = "libseccomp"))]
-    let (init_sender, init_receiver) = init_chan;
 
-    // If creating a container with new user namespace, the intermediate process will ask
-    // the main process to set up uid and gid mapping, once the intermediate
-    // process enters into a new user namespace.
-    if let Some(config) = &container_args.user_ns_config {
-        main_receiver.wait_for_mapping_request()?;
-        setup_mapping(config, intermediate_pid)?;
-        inter_sender.mapping_written()?;
+    let deadline = Deadline::begin(timeouts, Phase::Intermediate, overall_deadline);
+    main_receiver.set_deadline(deadline.at);
+    let mut setup_cloned = || -> Result<()> {
+        // An init process cloned right away is added to the cgroup by the main
+        // process, before it continues in its user namespace like the
+        // intermediate process would.
+        if let Cloned::Init(init_pid) = cloned {
+            join_cgroup(container_args, init_pid)?;
+            fault_injection::inject(container_args.fault_point, FaultPoint::AfterCgroupCreate)?;
+        }
+
+        // If creating a container with new user namespace, the intermediate process will ask
+        // the main process to set up uid and gid mapping, once the intermediate
+        // process enters into a new user namespace.
+        if let Some(config) = &container_args.user_ns_config {
+            timed(deadline, main_receiver.wait_for_mapping_request())?;
+            setup_mapping(config, cloned.pid())?;
+            inter_sender.mapping_written()?;
+        }
+        Ok(())
+    };
+    if let Err(err) = setup_cloned() {
+        // The init process waits for its id mappings, which it never gets if
+        // the main process fails before writing them
+        if let Cloned::Init(init_pid) = cloned {
+            kill_child(init_pid);
+        }
+        return Err(err);
     }
 
     // At this point, we don't need to send any message to intermediate process anymore,
@@ -126,9 +193,23 @@ pub fn container_main_process(container_args: &ContainerArgs) -> Result<(Pid, bo
 
     // The intermediate process will send the init pid once it forks the init
     // process.  The intermediate process should exit after this point.
-    let init_pid = main_receiver.wait_for_intermediate_ready()?;
+    let init_pid = match cloned {
+        Cloned::Intermediate(_) => timed(deadline, main_receiver.wait_for_intermediate_ready())?,
+        Cloned::Init(init_pid) => init_pid,
+    };
+    *known_init_pid = Some(init_pid);
     let mut need_to_clean_up_intel_rdt_subdirectory = false;
 
+    if matches!(container_args.container_type, ContainerType::InitContainer) {
+        run_created_hooks(
+            container_args,
+            init_pid,
+            overall_deadline,
+            &mut main_receiver,
+            &mut init_sender,
+        )?;
+    }
+
     if let Some(linux) = container_args.spec.linux() {
         #[cfg(feature = "libseccomp")]
         if let Some(seccomp) = linux.seccomp() {
@@ -145,12 +226,35 @@ pub fn container_main_process(container_args: &ContainerArgs) -> Result<(Pid, bo
                     .state
                     .clone(),
             };
+            // exec processes share the broker of the init process
+            let broker_dir = match container_args.container_type {
+                ContainerType::InitContainer
+                    if crate::seccomp_broker::requested(&container_args.spec)? =>
+                {
+                    container_args
+                        .container
+                        .as_ref()
+                        .map(|container| container.root.as_path())
+                }
+                _ => None,
+            };
+            let deadline = Deadline::begin(timeouts, Phase::Init, overall_deadline);
+            main_receiver.set_deadline(deadline.at);
             crate::process::seccomp_listener::sync_seccomp(
                 seccomp,
                 &state,
                 &mut init_sender,
                 &mut main_receiver,
-            )?;
+                Deadline::begin(timeouts, Phase::SeccompListener, overall_deadline),
+                broker_dir,
+            )
+            .map_err(|err| match err {
+                SeccompListenerError::Timeout => ProcessError::Timeout(Phase::SeccompListener),
+                SeccompListenerError::ChannelError(err) if err.is_timeout() => {
+                    ProcessError::Timeout(deadline.phase)
+                }
+                err => err.into(),
+            })?;
         }
 
         if let Some(intel_rdt) = linux.intel_rdt() {
@@ -170,13 +274,41 @@ pub fn container_main_process(container_args: &ContainerArgs) -> Result<(Pid, bo
         err
     })?;
 
-    main_receiver.wait_for_init_ready().map_err(|err| {
-        tracing::error!("failed to wait for init ready: {}", err);
-        err
-    })?;
+    let deadline = Deadline::begin(timeouts, Phase::Init, overall_deadline);
+    main_receiver.set_deadline(deadline.at);
+    timed(
+        deadline,
+        main_receiver.wait_for_init_ready().map_err(|err| {
+            tracing::error!("failed to wait for init ready: {}", err);
+            err
+        }),
+    )?;
 
     tracing::debug!("init pid is {:?}", init_pid);
 
+    // The init process entered its cgroup namespace by now, which keeps the
+    // container cgroup as its root after the processes moved to the leaf.
+    let mut cgroup_namespace_roots = None;
+    if matches!(container_args.container_type, ContainerType::InitContainer) {
+        // the cgroups the init process created the namespace in are its
+        // roots, read before a delegation moves the process to the leaf
+        if creates_cgroup_namespace(container_args.spec.as_ref()) {
+            cgroup_namespace_roots = Some(namespace::process_cgroups(init_pid.as_raw())?);
+        }
+        if let Some(subtree_control) =
+            SubtreeControl::from_annotations(container_args.spec.annotations().as_ref())?
+        {
+            subtree_control.delegate(init_pid, container_args.user_ns_config.as_ref())?;
+        }
+        if let Some(name) = &container_args.threaded_cgroup {
+            cgroup_delegation::create_threaded(
+                init_pid,
+                name,
+                container_args.user_ns_config.as_ref(),
+            )?;
+        }
+    }
+
     // Close the receiver ends to avoid leaking file descriptors.
 
     inter_receiver.close().map_err(|err| {
@@ -198,6 +330,16 @@ pub fn container_main_process(container_args: &ContainerArgs) -> Result<(Pid, bo
     // process is exit and reaped. By this point, the intermediate process
     // should already exited successfully. If intermediate process errors out,
     // the `init_ready` will not be sent.
+    let intermediate_pid = match cloned {
+        Cloned::Intermediate(intermediate_pid) => intermediate_pid,
+        Cloned::Init(_) => {
+            return Ok((
+                init_pid,
+                need_to_clean_up_intel_rdt_subdirectory,
+                cgroup_namespace_roots,
+            ))
+        }
+    };
     match waitpid(intermediate_pid, None) {
         Ok(WaitStatus::Exited(_, 0)) => (),
         Ok(WaitStatus::Exited(_, s)) => {
@@ -215,7 +357,317 @@ pub fn container_main_process(container_args: &ContainerArgs) -> Result<(Pid, bo
         Err(err) => return Err(ProcessError::WaitIntermediateProcess(err)),
     };
 
-    Ok((init_pid, need_to_clean_up_intel_rdt_subdirectory))
+    Ok((
+        init_pid,
+        need_to_clean_up_intel_rdt_subdirectory,
+        cgroup_namespace_roots,
+    ))
+}
+
+/// Whether the init process creates a cgroup namespace instead of joining one
+fn creates_cgroup_namespace(spec: &Spec) -> bool {
+    spec.linux()
+        .as_ref()
+        .and_then(|linux| linux.namespaces().as_ref())
+        .map_or(false, |namespaces| {
+            namespaces.iter().any(|namespace| {
+                namespace.typ() == LinuxNamespaceType::Cgroup && namespace.path().is_none()
+            })
+        })
+}
+
+// The prestart and createRuntime hooks run in the runtime namespace once the
+// init process has set up the container namespaces, and the init process waits
+// for them before it runs the createContainer hooks. This is the order runc
+// uses, and the hooks can rely on the pid in the container state.
+fn run_created_hooks(
+    container_args: &ContainerArgs,
+    init_pid: Pid,
+    overall_deadline: Option<Instant>,
+    main_receiver: &mut channel::MainReceiver,
+    init_sender: &mut channel::InitSender,
+) -> Result<()> {
+    let timeouts = &container_args.timeouts;
+    let deadline = Deadline::begin(timeouts, Phase::Init, overall_deadline);
+    main_receiver.set_deadline(deadline.at);
+    timed(deadline, main_receiver.wait_for_hooks_request())?;
+
+    let mut container = container_args
+        .container
+        .clone()
+        .ok_or(ProcessError::ContainerStateRequired)?;
+    container.set_pid(init_pid.as_raw());
+
+    let deadline = Deadline::begin(timeouts, Phase::Hooks, overall_deadline);
+    if let Err(err) = hooks::run_lifecycle_hooks_until(
+        LifecyclePoint::Created,
+        container_args.spec.hooks().as_ref(),
+        Some(&container),
+        None,
+        deadline.at,
+    ) {
+        // Unblock the init process, otherwise it waits for the hooks forever.
+        if let Err(send_err) = init_sender.hooks_failed(&err) {
+            tracing::warn!(
+                ?send_err,
+                "failed to notify init process about failed hooks"
+            );
+        }
+        return Err(match err {
+            hooks::HookError::DeadlineExceeded => ProcessError::Timeout(deadline.phase),
+            err => err.into(),
+        });
+    }
+
+    init_sender.hooks_done()?;
+    Ok(())
+}
+
+fn clone_intermediate_process(
+    container_args: &ContainerArgs,
+    inter_chan: &mut (channel::IntermediateSender, channel::IntermediateReceiver),
+    init_chan: &mut (channel::InitSender, channel::InitReceiver),
+    main_sender: &mut channel::MainSender,
+) -> Result<Pid> {
+    let cb: CloneCb = {
+        Box::new(|| {
+            if let Err(ret) = prctl::set_name("youki:[1:INTER]") {
+                tracing::error!(?ret, "failed to set name for child process");
+                return ret;
+            }
+
+            match container_intermediate_process::container_intermediate_process(
+                container_args,
+                inter_chan,
+                init_chan,
+                main_sender,
+            ) {
+                Ok(_) => 0,
+                Err(err) => {
+                    tracing::error!("failed to run intermediate process {}", err);
+                    match main_sender.send_error(ProcessRole::Intermediate, &err) {
+                        Ok(_) => {}
+                        Err(e) => {
+                            tracing::error!(
+                                "error in sending intermediate error message {} to main: {}",
+                                err,
+                                e
+                            )
+                        }
+                    }
+                    -1
+                }
+            }
+        })
+    };
+
+    let container_clone_fn = if container_args.as_sibling {
+        fork::container_clone_sibling
+    } else {
+        fork::container_clone
+    };
+
+    container_clone_fn(cb).map_err(|err| {
+        tracing::error!("failed to fork intermediate process: {}", err);
+        ProcessError::IntermediateProcessFailed(err)
+    })
+}
+
+/// The first process the main process cloned for the container
+#[derive(Debug, Clone, Copy)]
+enum Cloned {
+    /// The intermediate process, which forks the init process
+    Intermediate(Pid),
+    /// The init process, cloned into its namespaces right away
+    Init(Pid),
+}
+
+impl Cloned {
+    fn pid(&self) -> Pid {
+        match self {
+            Cloned::Intermediate(pid) | Cloned::Init(pid) => *pid,
+        }
+    }
+}
+
+// The intermediate process exists to order the user namespace setup before
+// entering the other namespaces, and because a pid namespace only applies to
+// the children of the process which entered it. If the container gets new
+// user and pid namespaces, clone3 creates the init process in all of them at
+// once and the main process does the work of the intermediate process from
+// outside, which saves a process and a round trip on every create.
+fn direct_clone_namespaces(args: &ContainerArgs) -> Option<CloneFlags> {
+    if !matches!(args.container_type, ContainerType::InitContainer)
+        || args.user_ns_config.is_none()
+        || !kernel::has(Feature::Clone3)
+    {
+        return None;
+    }
+
+    clone_namespaces(args.spec.linux().as_ref()?.namespaces().as_ref())
+}
+
+/// Returns the namespaces the init process can be cloned into, None if it
+/// has to be forked by the intermediate process
+fn clone_namespaces(namespaces: Option<&Vec<LinuxNamespace>>) -> Option<CloneFlags> {
+    let namespaces = Namespaces::try_from(namespaces).ok()?;
+    let new_namespace = |typ| match namespaces.get(typ).ok()? {
+        Some(namespace) => Some(namespace.path().is_none()),
+        None => Some(false),
+    };
+
+    // Joining a user namespace does not need a mapping, and joining a pid
+    // namespace needs setns before the fork. New time namespaces can not
+    // have their offsets set yet.
+    let joins =
+        |typ| matches!(namespaces.get(typ), Ok(Some(namespace)) if namespace.path().is_some());
+    if !new_namespace(LinuxNamespaceType::User)?
+        || joins(LinuxNamespaceType::Pid)
+        || namespaces.get(LinuxNamespaceType::Time).ok()?.is_some()
+    {
+        return None;
+    }
+
+    let mut flags = CloneFlags::CLONE_NEWUSER;
+    if new_namespace(LinuxNamespaceType::Pid)? {
+        flags |= CloneFlags::CLONE_NEWPID;
+    }
+    if new_namespace(LinuxNamespaceType::Mount)? {
+        flags |= CloneFlags::CLONE_NEWNS;
+    }
+    Some(flags)
+}
+
+// Clones the init process into its namespaces. Returns None if the kernel
+// refuses, the staged path then reports the actual error if it fails as well.
+fn clone_init_process(
+    container_args: &ContainerArgs,
+    namespaces: CloneFlags,
+    inter_chan: &mut (channel::IntermediateSender, channel::IntermediateReceiver),
+    init_chan: &mut (channel::InitSender, channel::InitReceiver),
+    main_sender: &mut channel::MainSender,
+) -> Option<Pid> {
+    let (inter_sender, inter_receiver) = inter_chan;
+    let (init_sender, init_receiver) = init_chan;
+    let cb: CloneCb = Box::new(|| {
+        if let Err(ret) = prctl::set_name("youki:[2:INIT]") {
+            tracing::error!(?ret, "failed to set name for child process");
+            return ret;
+        }
+
+        if let Err(err) = init_sender.close() {
+            tracing::error!(?err, "failed to close sender in init process");
+            return -1;
+        }
+        if let Err(err) = inter_sender.close() {
+            tracing::error!(?err, "failed to close intermediate sender in init process");
+            return -1;
+        }
+        if let Err(err) = container_intermediate_process::setup_cloned_init_process(
+            container_args,
+            main_sender,
+            inter_receiver,
+        ) {
+            tracing::error!("failed to set up cloned init process: {err}");
+            if let Err(err) = main_sender.send_error(ProcessRole::Init, &err) {
+                tracing::error!(?err, "failed sending error to main sender");
+            }
+            return -1;
+        }
+        match container_init_process(container_args, main_sender, init_receiver, namespaces) {
+            Ok(_) => 0,
+            Err(err) => {
+                let last_errno = Errno::last();
+                tracing::error!("failed to initialize container process: {err}");
+                let failure = err.exec_failure();
+                let exit_code = failure.kind.exit_code().unwrap_or(-1);
+                if let Some(mut report) = container_args.init_report.clone() {
+                    report.failed(&err, last_errno, exit_code);
+                }
+                if let Err(err) = main_sender.exec_failed(failure) {
+                    tracing::error!(?err, "failed sending error to main sender");
+                }
+                exit_code
+            }
+        }
+    });
+
+    match fork::container_clone_into_namespaces(cb, namespaces, container_args.as_sibling) {
+        Ok(pid) => {
+            tracing::debug!(?pid, ?namespaces, "cloned init process into its namespaces");
+            Some(pid)
+        }
+        Err(err) => {
+            tracing::debug!(
+                ?err,
+                ?namespaces,
+                "failed to clone init process into its namespaces, fallback to the intermediate process"
+            );
+            None
+        }
+    }
+}
+
+// Does for the directly cloned init process what the intermediate process
+// does for itself before it enters the user namespace
+fn join_cgroup(container_args: &ContainerArgs, init_pid: Pid) -> Result<()> {
+    let cgroup_manager =
+        libcgroups::common::create_cgroup_manager(container_args.cgroup_config.to_owned())
+            .map_err(|err| {
+                tracing::error!(?err, "failed to create cgroup manager");
+                ProcessError::Cgroup(err.to_string())
+            })?;
+    let resources = container_args
+        .spec
+        .linux()
+        .as_ref()
+        .and_then(|linux| linux.resources().as_ref());
+    container_intermediate_process::apply_cgroups(&cgroup_manager, init_pid, resources, true)
+        .map_err(|err| ProcessError::Cgroup(err.to_string()))
+}
+
+// Kills and reaps a process cloned by the main process
+fn kill_child(pid: Pid) {
+    match signal::kill(pid, Signal::SIGKILL) {
+        Ok(()) | Err(nix::errno::Errno::ESRCH) => {}
+        Err(err) => {
+            tracing::warn!(?err, ?pid, "failed to kill container process");
+            return;
+        }
+    }
+    match waitpid(pid, None) {
+        // already reaped after an earlier failure
+        Ok(_) | Err(nix::errno::Errno::ECHILD) => {}
+        Err(err) => tracing::warn!(?err, ?pid, "failed to reap container process"),
+    }
+}
+
+// Processes stuck in their setup do not notice that the main process gave up
+// on them. The init process forked by the intermediate process is not a child
+// of the main process, so it is only killed here.
+fn kill_stuck_processes(cloned: Cloned, init_pid: Option<Pid>) {
+    if let Some(init_pid) = init_pid.filter(|pid| *pid != cloned.pid()) {
+        match signal::kill(init_pid, Signal::SIGKILL) {
+            Ok(()) | Err(nix::errno::Errno::ESRCH) => {}
+            Err(err) => tracing::warn!(?err, pid = ?init_pid, "failed to kill init process"),
+        }
+    }
+    kill_child(cloned.pid());
+}
+
+// Names the phase which got stuck if the main process waited in vain
+fn timed<T>(
+    deadline: Deadline,
+    result: std::result::Result<T, channel::ChannelError>,
+) -> Result<T> {
+    result.map_err(|err| {
+        if err.is_timeout() {
+            tracing::error!(phase = %deadline.phase, state = ?err.state(), "container setup timed out");
+            ProcessError::Timeout(deadline.phase)
+        } else {
+            err.into()
+        }
+    })
 }
 
 fn setup_mapping(config: &UserNamespaceConfig, pid: Pid) -> Result<()> {
@@ -245,7 +697,7 @@ mod tests {
     use anyhow::Result;
     use nix::sched::{unshare, CloneFlags};
     use nix::unistd::{self, getgid, getuid};
-    use oci_spec::runtime::LinuxIdMappingBuilder;
+    use oci_spec::runtime::{LinuxIdMappingBuilder, LinuxNamespaceBuilder};
     use serial_test::serial;
 
     use super::*;
@@ -364,4 +816,60 @@ mod tests {
         }
         Ok(())
     }
+
+    fn namespace(typ: LinuxNamespaceType, path: Option<&str>) -> LinuxNamespace {
+        let mut namespace = LinuxNamespaceBuilder::default().typ(typ).build().unwrap();
+        namespace.set_path(path.map(Into::into));
+        namespace
+    }
+
+    #[test]
+    fn test_clone_namespaces() {
+        use LinuxNamespaceType::*;
+
+        let cases = [
+            (
+                vec![
+                    namespace(User, None),
+                    namespace(Pid, None),
+                    namespace(Mount, None),
+                ],
+                Some(
+                    CloneFlags::CLONE_NEWUSER | CloneFlags::CLONE_NEWPID | CloneFlags::CLONE_NEWNS,
+                ),
+            ),
+            (
+                vec![
+                    namespace(User, None),
+                    namespace(Mount, Some("/proc/1/ns/mnt")),
+                ],
+                Some(CloneFlags::CLONE_NEWUSER),
+            ),
+            // no new user namespace
+            (vec![namespace(Pid, None), namespace(Mount, None)], None),
+            (
+                vec![
+                    namespace(User, Some("/proc/1/ns/user")),
+                    namespace(Pid, None),
+                ],
+                None,
+            ),
+            // namespaces which need the intermediate process
+            (
+                vec![
+                    namespace(User, None),
+                    namespace(Pid, Some("/proc/1/ns/pid")),
+                ],
+                None,
+            ),
+            (vec![namespace(User, None), namespace(Time, None)], None),
+        ];
+        for (namespaces, expected) in cases {
+            assert_eq!(
+                clone_namespaces(Some(&namespaces)),
+                expected,
+                "{namespaces:?}"
+            );
+        }
+    }
 }
@@ -2,9 +2,12 @@ use std::ffi::c_int;
 use std::num::NonZeroUsize;
 
 use libc::SIGCHLD;
+use nix::sched::CloneFlags;
 use nix::sys::{mman, resource};
 use nix::unistd::Pid;
 
+use crate::kernel::{self, Feature};
+
 #[derive(Debug, thiserror::Error)]
 pub enum CloneError {
     #[error("failed to clone process")]
@@ -55,20 +58,39 @@ pub fn container_clone(cb: CloneCb) -> Result<Pid, CloneError> {
     clone_internal(cb, 0, Some(SIGCHLD as u64))
 }
 
+// Clone the container init process directly into new namespaces, as a child
+// or as a sibling of the calling process. This saves the intermediate process
+// if no namespace requires the staged setup, see `container_main_process`.
+pub fn container_clone_into_namespaces(
+    cb: CloneCb,
+    namespaces: CloneFlags,
+    as_sibling: bool,
+) -> Result<Pid, CloneError> {
+    let flags = namespaces.bits() as u64;
+    if as_sibling {
+        clone_internal(cb, flags | libc::CLONE_PARENT as u64, None)
+    } else {
+        clone_internal(cb, flags, Some(SIGCHLD as u64))
+    }
+}
+
 // An internal wrapper to manage the clone3 vs clone fallback logic.
 fn clone_internal(
     mut cb: CloneCb,
     flags: u64,
     exit_signal: Option<u64>,
 ) -> Result<Pid, CloneError> {
+    if !kernel::has(Feature::Clone3) {
+        tracing::debug!("clone3 is not supported, fallback to clone");
+        return clone(cb, flags, exit_signal);
+    }
+
     match clone3(&mut cb, flags, exit_signal) {
         Ok(pid) => Ok(pid),
-        // For now, we decide to only fallback on ENOSYS
+        // A seccomp filter loaded after the probe may still block clone3
         Err(CloneError::Clone(nix::Error::ENOSYS)) => {
-            tracing::debug!("clone3 is not supported, fallback to clone");
-            let pid = clone(cb, flags, exit_signal)?;
-
-            Ok(pid)
+            tracing::debug!("clone3 is blocked, fallback to clone");
+            clone(cb, flags, exit_signal)
         }
         Err(err) => Err(err),
     }
@@ -2,6 +2,32 @@ use core::fmt;
 
 use serde::{Deserialize, Serialize};
 
+use crate::workload::ExecFailure;
+
+/// Version of the protocol between the main, intermediate and init process.
+/// Frames of another version are refused rather than misread, e.g. after
+/// youki was replaced while a container was created.
+pub const PROTOCOL_VERSION: u32 = 2;
+
+/// What is sent over the channels, a message along with the version of the
+/// protocol it belongs to
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Frame {
+    // frames of the first, unversioned protocol have none
+    #[serde(default)]
+    pub version: u32,
+    pub message: Message,
+}
+
+impl Frame {
+    pub fn new(message: Message) -> Self {
+        Self {
+            version: PROTOCOL_VERSION,
+            message,
+        }
+    }
+}
+
 /// Used as a wrapper for messages to be sent between child and parent processes
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub enum Message {
@@ -11,8 +37,10 @@ pub enum Message {
     MappingWritten,
     SeccompNotify,
     SeccompNotifyDone,
-    ExecFailed(String),
-    OtherError(String),
+    HooksRequest,
+    HooksDone,
+    ExecFailed(ExecFailure),
+    Error(Box<ErrorFrame>),
 }
 
 impl fmt::Display for Message {
@@ -24,8 +52,70 @@ impl fmt::Display for Message {
             Message::MappingWritten => write!(f, "MappingWritten"),
             Message::SeccompNotify => write!(f, "SeccompNotify"),
             Message::SeccompNotifyDone => write!(f, "SeccompNotifyDone"),
+            Message::HooksRequest => write!(f, "HooksRequest"),
+            Message::HooksDone => write!(f, "HooksDone"),
             Message::ExecFailed(s) => write!(f, "ExecFailed({})", s),
-            Message::OtherError(s) => write!(f, "OtherError({})", s),
+            Message::Error(e) => write!(f, "Error({})", e),
+        }
+    }
+}
+
+/// Process taking part in the creation of a container
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum ProcessRole {
+    Main,
+    Intermediate,
+    Init,
+}
+
+impl fmt::Display for ProcessRole {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProcessRole::Main => write!(f, "main"),
+            ProcessRole::Intermediate => write!(f, "intermediate"),
+            ProcessRole::Init => write!(f, "init"),
+        }
+    }
+}
+
+/// Failure of a process sent to the process waiting for it, with the whole
+/// chain of causes as the error itself is not serializable
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct ErrorFrame {
+    pub process: ProcessRole,
+    pub message: String,
+    pub causes: Vec<String>,
+}
+
+impl ErrorFrame {
+    pub fn new(process: ProcessRole, err: &dyn std::error::Error) -> Self {
+        let message = err.to_string();
+        let mut causes: Vec<String> = Vec::new();
+        let mut source = err.source();
+        while let Some(cause) = source {
+            // many errors repeat their source in their own message
+            let cause_message = cause.to_string();
+            let shown = causes.last().unwrap_or(&message);
+            if !shown.contains(&cause_message) {
+                causes.push(cause_message);
+            }
+            source = cause.source();
+        }
+
+        Self {
+            process,
+            message,
+            causes,
+        }
+    }
+}
+
+impl fmt::Display for ErrorFrame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} process failed: {}", self.process, self.message)?;
+        for cause in &self.causes {
+            write!(f, ": {}", cause)?;
         }
+        Ok(())
     }
 }
@@ -2,12 +2,16 @@
 //! with enums and functions specific to youki implemented
 
 pub mod args;
+pub mod cgroup_delegation;
 pub mod channel;
 pub mod container_init_process;
 pub mod container_intermediate_process;
 pub mod container_main_process;
 mod fork;
 pub mod intel_rdt;
-mod message;
+pub(crate) mod message;
+pub mod parent_death;
+pub mod scheduling;
 #[cfg(feature = "libseccomp")]
 mod seccomp_listener;
+pub mod timeouts;
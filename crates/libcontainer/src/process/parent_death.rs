@@ -0,0 +1,229 @@
+//! Controls what happens to the container processes when the process they
+//! were spawned under dies, so that a crashing youki or shim does not leave
+//! orphaned containers behind.
+//!
+//! Two settings are available, either through the container builder or
+//! through annotations in the spec:
+//!
+//! - `org.youki.pdeathsig`: signal delivered to the container init once its
+//!   parent exits, set with PR_SET_PDEATHSIG right before the init signals
+//!   that it is ready. The parent is the youki process which created the
+//!   container, or the caller of youki if the container is created as a
+//!   sibling. A detached youki exits as soon as the container is created,
+//!   so the signal can only be used for detached containers if they are
+//!   created as a sibling of the calling process.
+//! - `org.youki.orphan-policy`: `kill` makes the intermediate and init
+//!   processes receive SIGKILL if youki dies while the container is still
+//!   being created, `keep` (the default) leaves them alone.
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::str::FromStr;
+
+use nix::sys::signal::Signal;
+use serde::{Deserialize, Serialize};
+
+use crate::signal::Signal as SignalArg;
+
+pub const PDEATHSIG_ANNOTATION: &str = "org.youki.pdeathsig";
+pub const ORPHAN_POLICY_ANNOTATION: &str = "org.youki.orphan-policy";
+
+#[derive(Debug, thiserror::Error)]
+pub enum ParentDeathError {
+    #[error("invalid parent death signal {0}")]
+    InvalidSignal(String),
+    #[error("invalid orphan policy {0}, expected kill or keep")]
+    InvalidOrphanPolicy(String),
+    #[error("a parent death signal requires the container to stay attached to youki or to be created as a sibling")]
+    Detached,
+    #[error("failed to set the parent death signal")]
+    SetDeathSignal(#[source] nix::Error),
+}
+
+type Result<T> = std::result::Result<T, ParentDeathError>;
+
+/// Behavior of the container processes when youki dies during creation
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "camelCase")]
+pub enum OrphanPolicy {
+    /// Leave the processes running
+    #[default]
+    Keep,
+    /// Kill the processes which are still being set up
+    Kill,
+}
+
+impl FromStr for OrphanPolicy {
+    type Err = ParentDeathError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "keep" => Ok(Self::Keep),
+            "kill" => Ok(Self::Kill),
+            _ => Err(ParentDeathError::InvalidOrphanPolicy(s.to_owned())),
+        }
+    }
+}
+
+impl Display for OrphanPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let print = match self {
+            Self::Keep => "keep",
+            Self::Kill => "kill",
+        };
+
+        write!(f, "{print}")
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct ParentDeath {
+    /// Signal delivered to the container init when its parent exits
+    #[serde(default, with = "signal_name", skip_serializing_if = "Option::is_none")]
+    pub signal: Option<Signal>,
+    pub orphan_policy: OrphanPolicy,
+}
+
+impl ParentDeath {
+    /// Reads the configuration from the annotations of the spec
+    pub fn from_annotations(annotations: Option<&HashMap<String, String>>) -> Result<Self> {
+        let mut parent_death = Self::default();
+        let Some(annotations) = annotations else {
+            return Ok(parent_death);
+        };
+
+        if let Some(signal) = annotations.get(PDEATHSIG_ANNOTATION) {
+            parent_death.signal = Some(parse_signal(signal)?);
+        }
+        if let Some(policy) = annotations.get(ORPHAN_POLICY_ANNOTATION) {
+            parent_death.orphan_policy = policy.parse()?;
+        }
+
+        Ok(parent_death)
+    }
+
+    pub fn is_default(&self) -> bool {
+        self == &Self::default()
+    }
+
+    /// Checks that the configuration is usable for a container created with
+    /// the given options
+    pub fn validate(&self, detached: bool, as_sibling: bool) -> Result<()> {
+        if self.signal.is_some() && detached && !as_sibling {
+            return Err(ParentDeathError::Detached);
+        }
+
+        Ok(())
+    }
+
+    /// Arms the death signal for a process which is still setting up the
+    /// container. Has to be called again after changing credentials, since
+    /// the kernel clears the setting then.
+    pub(crate) fn arm_for_setup(&self) -> Result<()> {
+        match self.orphan_policy {
+            OrphanPolicy::Kill => set_death_signal(Some(Signal::SIGKILL)),
+            OrphanPolicy::Keep => Ok(()),
+        }
+    }
+
+    /// Replaces the setup death signal with the configured one once the
+    /// container init is fully set up
+    pub(crate) fn arm_for_init(&self) -> Result<()> {
+        if self.is_default() {
+            return Ok(());
+        }
+
+        set_death_signal(self.signal)
+    }
+}
+
+/// Parses a signal given by name, with or without the SIG prefix, or by number
+pub fn parse_signal(signal: &str) -> Result<Signal> {
+    SignalArg::try_from(signal)
+        .map(SignalArg::into_raw)
+        .map_err(|_| ParentDeathError::InvalidSignal(signal.to_owned()))
+}
+
+fn set_death_signal(signal: Option<Signal>) -> Result<()> {
+    let signal = signal.map_or(0, |signal| signal as isize);
+    prctl::set_death_signal(signal).map_err(|errno| {
+        let err = nix::Error::from_raw(errno);
+        tracing::error!(?err, signal, "failed to set parent death signal");
+        ParentDeathError::SetDeathSignal(err)
+    })
+}
+
+mod signal_name {
+    use nix::sys::signal::Signal;
+    use serde::{de, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(signal: &Option<Signal>, s: S) -> Result<S::Ok, S::Error> {
+        match signal {
+            Some(signal) => s.serialize_some(signal.as_str()),
+            None => s.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Option<Signal>, D::Error> {
+        Option::<String>::deserialize(d)?
+            .map(|name| name.parse().map_err(de::Error::custom))
+            .transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_annotations() {
+        assert_eq!(
+            ParentDeath::from_annotations(None).unwrap(),
+            ParentDeath::default()
+        );
+
+        let annotations = HashMap::from([
+            (PDEATHSIG_ANNOTATION.to_owned(), "TERM".to_owned()),
+            (ORPHAN_POLICY_ANNOTATION.to_owned(), "kill".to_owned()),
+        ]);
+        let parent_death = ParentDeath::from_annotations(Some(&annotations)).unwrap();
+        assert_eq!(parent_death.signal, Some(Signal::SIGTERM));
+        assert_eq!(parent_death.orphan_policy, OrphanPolicy::Kill);
+
+        let annotations = HashMap::from([(ORPHAN_POLICY_ANNOTATION.to_owned(), "x".to_owned())]);
+        assert!(ParentDeath::from_annotations(Some(&annotations)).is_err());
+    }
+
+    #[test]
+    fn test_validate_detached() {
+        let parent_death = ParentDeath {
+            signal: Some(Signal::SIGKILL),
+            orphan_policy: OrphanPolicy::Keep,
+        };
+        assert!(parent_death.validate(false, false).is_ok());
+        assert!(parent_death.validate(true, true).is_ok());
+        assert!(matches!(
+            parent_death.validate(true, false),
+            Err(ParentDeathError::Detached)
+        ));
+        assert!(ParentDeath::default().validate(true, false).is_ok());
+    }
+
+    #[test]
+    fn test_serialize_signal_name() {
+        let parent_death = ParentDeath {
+            signal: Some(Signal::SIGKILL),
+            orphan_policy: OrphanPolicy::Kill,
+        };
+        let json = serde_json::to_string(&parent_death).unwrap();
+        assert_eq!(json, r#"{"signal":"SIGKILL","orphanPolicy":"kill"}"#);
+        assert_eq!(
+            serde_json::from_str::<ParentDeath>(&json).unwrap(),
+            parent_death
+        );
+        assert_eq!(
+            serde_json::to_string(&ParentDeath::default()).unwrap(),
+            r#"{"orphanPolicy":"keep"}"#
+        );
+    }
+}
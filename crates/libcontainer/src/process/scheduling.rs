@@ -0,0 +1,200 @@
+//! Changes the io priority and the scheduler of processes which are already
+//! running, e.g. to adjust a container after it was started. The container
+//! init sets the ones of the spec for itself while it is set up.
+//!
+//! Both attributes belong to a single thread, so they are set for every
+//! thread of a process. Processes forked afterwards inherit them from their
+//! parent, except for the scheduler with the SCHED_RESET_ON_FORK flag.
+use std::mem;
+use std::path::PathBuf;
+
+use nix::unistd::Pid;
+use oci_spec::runtime::{
+    IOPriorityClass, LinuxIOPriority, LinuxSchedulerFlag, LinuxSchedulerPolicy, Scheduler,
+};
+
+#[derive(Debug, thiserror::Error)]
+pub enum SchedulingError {
+    #[error("failed to list the threads of process {pid}")]
+    Threads {
+        pid: Pid,
+        #[source]
+        source: std::io::Error,
+    },
+    #[error("failed to set the io priority of thread {tid}")]
+    IoPriority {
+        tid: Pid,
+        #[source]
+        source: nix::Error,
+    },
+    #[error("failed to set the scheduler of thread {tid}: {errno}")]
+    Scheduler { tid: Pid, errno: String },
+}
+
+type Result<T> = std::result::Result<T, SchedulingError>;
+
+const IOPRIO_WHO_PROCESS: nix::libc::c_int = 1;
+const IOPRIO_CLASS_SHIFT: i64 = 13;
+
+/// Value of the io priority class for ioprio_set
+pub fn io_priority_class(class: IOPriorityClass) -> i64 {
+    match class {
+        IOPriorityClass::IoprioClassRt => 1,
+        IOPriorityClass::IoprioClassBe => 2,
+        IOPriorityClass::IoprioClassIdle => 3,
+    }
+}
+
+/// Attributes for sched_setattr
+pub(crate) fn sched_attr(scheduler: &Scheduler) -> nc::sched_attr_t {
+    let policy: u32 = match *scheduler.policy() {
+        LinuxSchedulerPolicy::SchedOther => 0,
+        LinuxSchedulerPolicy::SchedFifo => 1,
+        LinuxSchedulerPolicy::SchedRr => 2,
+        LinuxSchedulerPolicy::SchedBatch => 3,
+        LinuxSchedulerPolicy::SchedIso => 4,
+        LinuxSchedulerPolicy::SchedIdle => 5,
+        LinuxSchedulerPolicy::SchedDeadline => 6,
+    };
+    let flags = scheduler
+        .flags()
+        .iter()
+        .flatten()
+        .fold(0, |flags, flag| match *flag {
+            LinuxSchedulerFlag::SchedResetOnFork => flags | 0x01,
+            LinuxSchedulerFlag::SchedFlagReclaim => flags | 0x02,
+            LinuxSchedulerFlag::SchedFlagDLOverrun => flags | 0x04,
+            LinuxSchedulerFlag::SchedFlagKeepPolicy => flags | 0x08,
+            LinuxSchedulerFlag::SchedFlagKeepParams => flags | 0x10,
+            LinuxSchedulerFlag::SchedFlagUtilClampMin => flags | 0x20,
+            LinuxSchedulerFlag::SchedFlagUtilClampMax => flags | 0x40,
+        });
+
+    nc::sched_attr_t {
+        // size of the structure should always be within u32 bounds,
+        // so this unwrap should never fail
+        size: mem::size_of::<nc::sched_attr_t>().try_into().unwrap(),
+        sched_policy: policy,
+        sched_flags: flags,
+        sched_nice: scheduler.nice().unwrap_or(0),
+        sched_priority: scheduler.priority().unwrap_or(0) as u32,
+        sched_runtime: scheduler.runtime().unwrap_or(0),
+        sched_deadline: scheduler.deadline().unwrap_or(0),
+        sched_period: scheduler.period().unwrap_or(0),
+        sched_util_min: 0,
+        sched_util_max: 0,
+    }
+}
+
+/// Lists the threads of a process
+pub fn threads(pid: Pid) -> Result<Vec<Pid>> {
+    let task_dir = PathBuf::from(format!("/proc/{pid}/task"));
+    let entries = std::fs::read_dir(task_dir).map_err(|source| {
+        tracing::error!(?source, ?pid, "failed to list threads");
+        SchedulingError::Threads { pid, source }
+    })?;
+
+    Ok(entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| entry.file_name().to_str()?.parse().ok())
+        .map(Pid::from_raw)
+        .collect())
+}
+
+/// Sets the io priority of every thread of a process
+pub fn set_io_priority(pid: Pid, io_priority: &LinuxIOPriority) -> Result<()> {
+    let value =
+        (io_priority_class(io_priority.class()) << IOPRIO_CLASS_SHIFT) | io_priority.priority();
+    for tid in threads(pid)? {
+        // SAFETY: ioprio_set only takes integer arguments
+        let ret = unsafe {
+            nix::libc::syscall(
+                nix::libc::SYS_ioprio_set,
+                IOPRIO_WHO_PROCESS,
+                tid.as_raw(),
+                value as nix::libc::c_ulong,
+            )
+        };
+        if ret == -1 {
+            let source = nix::Error::last();
+            tracing::error!(?source, ?tid, ?io_priority, "failed to set io priority");
+            return Err(SchedulingError::IoPriority { tid, source });
+        }
+    }
+
+    Ok(())
+}
+
+/// Sets the scheduler of every thread of a process
+pub fn set_scheduler(pid: Pid, scheduler: &Scheduler) -> Result<()> {
+    let attr = sched_attr(scheduler);
+    for tid in threads(pid)? {
+        // SAFETY: the attributes outlive the syscall
+        unsafe { nc::sched_setattr(tid.as_raw(), &attr, 0) }.map_err(|errno| {
+            tracing::error!(?errno, ?tid, ?scheduler, "failed to set scheduler");
+            SchedulingError::Scheduler {
+                tid,
+                errno: errno.to_string(),
+            }
+        })?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use oci_spec::runtime::{LinuxIOPriorityBuilder, SchedulerBuilder};
+
+    use super::*;
+
+    #[test]
+    fn test_sched_attr() -> Result<()> {
+        let scheduler = SchedulerBuilder::default()
+            .policy(LinuxSchedulerPolicy::SchedBatch)
+            .nice(5)
+            .flags(vec![
+                LinuxSchedulerFlag::SchedResetOnFork,
+                LinuxSchedulerFlag::SchedFlagKeepParams,
+            ])
+            .build()?;
+        let attr = sched_attr(&scheduler);
+        assert_eq!(attr.sched_policy, 3);
+        assert_eq!(attr.sched_nice, 5);
+        assert_eq!(attr.sched_flags, 0x11);
+        assert_eq!(attr.sched_priority, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_set_own_priority() -> Result<()> {
+        // Lowering the priority is allowed without privileges, the test
+        // runs in its own process to not slow down the other tests.
+        match unsafe { nix::unistd::fork()? } {
+            nix::unistd::ForkResult::Parent { child } => {
+                let status = nix::sys::wait::waitpid(child, None)?;
+                assert_eq!(status, nix::sys::wait::WaitStatus::Exited(child, 0));
+            }
+            nix::unistd::ForkResult::Child => {
+                let pid = nix::unistd::getpid();
+                let io_priority = LinuxIOPriorityBuilder::default()
+                    .class(IOPriorityClass::IoprioClassBe)
+                    .priority(7)
+                    .build()
+                    .unwrap();
+                let scheduler = SchedulerBuilder::default()
+                    .policy(LinuxSchedulerPolicy::SchedOther)
+                    .nice(19)
+                    .build()
+                    .unwrap();
+                let ok = threads(pid).map_or(false, |threads| threads == vec![pid])
+                    && set_io_priority(pid, &io_priority).is_ok()
+                    && set_scheduler(pid, &scheduler).is_ok()
+                    && unsafe { nix::libc::nice(0) } == 19;
+                std::process::exit(if ok { 0 } else { 1 });
+            }
+        }
+        Ok(())
+    }
+}
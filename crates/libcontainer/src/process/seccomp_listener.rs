@@ -2,13 +2,16 @@ use std::io::IoSlice;
 use std::os::fd::AsRawFd;
 use std::path::Path;
 
-use nix::sys::socket::{self, UnixAddr};
+use nix::errno::Errno;
+use nix::sys::socket::{self, sockopt, UnixAddr};
+use nix::sys::time::{TimeVal, TimeValLike};
 use nix::unistd;
 use oci_spec::runtime;
 
 use super::channel;
+use super::timeouts::Deadline;
 use crate::container::ContainerProcessState;
-use crate::seccomp;
+use crate::{seccomp, seccomp_broker};
 
 #[derive(Debug, thiserror::Error)]
 pub enum SeccompListenerError {
@@ -20,15 +23,24 @@ pub enum SeccompListenerError {
     ChannelError(#[from] channel::ChannelError),
     #[error("unix syscall fails")]
     UnixOther(#[source] nix::Error),
+    #[error("seccomp listener did not accept the notify fd before the deadline")]
+    Timeout,
+    #[error(transparent)]
+    Broker(#[from] seccomp_broker::SeccompBrokerError),
 }
 
 type Result<T> = std::result::Result<T, SeccompListenerError>;
 
+/// Sends the notify fd of the init process to the seccomp listener. With
+/// `broker_dir`, the state directory of the container, a broker keeps the fd
+/// for agents which reconnect, see [`seccomp_broker`].
 pub fn sync_seccomp(
     seccomp: &runtime::LinuxSeccomp,
     state: &ContainerProcessState,
     init_sender: &mut channel::InitSender,
     main_receiver: &mut channel::MainReceiver,
+    listener_deadline: Deadline,
+    broker_dir: Option<&Path>,
 ) -> Result<()> {
     if seccomp::is_notify(seccomp) {
         tracing::debug!("main process waiting for sync seccomp");
@@ -38,10 +50,15 @@ pub fn sync_seccomp(
             .as_ref()
             .ok_or(SeccompListenerError::MissingListenerPath)?;
         let encoded_state = serde_json::to_vec(state).map_err(SeccompListenerError::EncodeState)?;
-        sync_seccomp_send_msg(listener_path, &encoded_state, seccomp_fd).map_err(|err| {
-            tracing::error!("failed to send msg to seccomp listener: {}", err);
-            err
-        })?;
+        sync_seccomp_send_msg(listener_path, &encoded_state, seccomp_fd, listener_deadline)
+            .map_err(|err| {
+                tracing::error!("failed to send msg to seccomp listener: {}", err);
+                err
+            })?;
+        if let Some(container_dir) = broker_dir {
+            let broker = seccomp_broker::start(container_dir, seccomp_fd, &encoded_state)?;
+            tracing::debug!(?broker, "started seccomp broker");
+        }
         init_sender.seccomp_notify_done()?;
         // Once we sent the seccomp notify fd to the seccomp listener, we can
         // safely close the fd. The SCM_RIGHTS msg will duplicate the fd to the
@@ -52,7 +69,12 @@ pub fn sync_seccomp(
     Ok(())
 }
 
-fn sync_seccomp_send_msg(listener_path: &Path, msg: &[u8], fd: i32) -> Result<()> {
+fn sync_seccomp_send_msg(
+    listener_path: &Path,
+    msg: &[u8],
+    fd: i32,
+    deadline: Deadline,
+) -> Result<()> {
     // The seccomp listener has specific instructions on how to transmit the
     // information through seccomp listener.  Therefore, we have to use
     // libc/nix APIs instead of Rust std lib APIs to maintain flexibility.
@@ -77,7 +99,28 @@ fn sync_seccomp_send_msg(listener_path: &Path, msg: &[u8], fd: i32) -> Result<()
         );
         SeccompListenerError::UnixOther(err)
     })?;
+    // A listener which does not accept the connection or read the message
+    // makes connect and sendmsg fail with EAGAIN once the deadline passed
+    if let Some(remaining) = deadline.remaining() {
+        if remaining.is_zero() {
+            return Err(SeccompListenerError::Timeout);
+        }
+        let timeout = TimeVal::milliseconds(remaining.as_millis().max(1) as i64);
+        socket::setsockopt(&socket, sockopt::SendTimeout, &timeout).map_err(|err| {
+            tracing::error!(?err, "failed to set send timeout for seccomp listener");
+            SeccompListenerError::UnixOther(err)
+        })?;
+    }
+    let timed_out = |err: &nix::Error| deadline.at.is_some() && *err == Errno::EAGAIN;
+
     socket::connect(socket.as_raw_fd(), &unix_addr).map_err(|err| {
+        if timed_out(&err) {
+            tracing::error!(
+                ?listener_path,
+                "seccomp notify listener did not accept in time"
+            );
+            return SeccompListenerError::Timeout;
+        }
         tracing::error!(
             ?err,
             ?listener_path,
@@ -100,6 +143,10 @@ fn sync_seccomp_send_msg(listener_path: &Path, msg: &[u8], fd: i32) -> Result<()
         None,
     )
     .map_err(|err| {
+        if timed_out(&err) {
+            tracing::error!("seccomp notify listener did not read the container state in time");
+            return SeccompListenerError::Timeout;
+        }
         tracing::error!(?err, "failed to write container state to seccomp listener");
         SeccompListenerError::UnixOther(err)
     })?;
@@ -117,6 +164,7 @@ mod tests {
     use super::*;
     use crate::container::ContainerProcessState;
     use crate::process::channel;
+    use crate::process::timeouts::{Phase, Timeouts};
 
     #[test]
     #[serial]
@@ -159,6 +207,8 @@ mod tests {
                 &state,
                 &mut init_sender,
                 &mut main_receiver,
+                Deadline::begin(&Timeouts::default(), Phase::SeccompListener, None),
+                None,
             )
             .unwrap();
         });
@@ -0,0 +1,220 @@
+//! Time limits for creating and starting a container. Without them youki
+//! waits forever for a container process stuck in its setup, e.g. on a hung
+//! NFS mount, for an unresponsive hook or for a blocked seccomp listener.
+//!
+//! Every phase can be limited on its own, and the creation as a whole with
+//! [`Timeouts::with_create`]. Whichever limit ends first applies. When a
+//! limit is exceeded, the container processes are killed, the creation is
+//! rolled back like for any other error and the error names the phase which
+//! got stuck.
+use std::fmt::Display;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, thiserror::Error)]
+pub enum TimeoutError {
+    #[error(
+        "unknown phase {0}, expected one of intermediate, init, hooks, seccomp-listener or start"
+    )]
+    UnknownPhase(String),
+    #[error("invalid phase timeout {0}, expected phase=seconds")]
+    InvalidPhaseTimeout(String),
+}
+
+type Result<T> = std::result::Result<T, TimeoutError>;
+
+/// Part of the creation or the start of a container which can get stuck
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    /// Until the container process joined its cgroup and namespaces and the
+    /// init process was forked
+    Intermediate,
+    /// Every step of the init process setting up the container, e.g.
+    /// preparing the rootfs
+    Init,
+    /// The prestart and createRuntime hooks, or the poststart hooks on start
+    Hooks,
+    /// Handing the seccomp notify fd to the seccomp listener
+    SeccompListener,
+    /// Notifying the created container to start
+    Start,
+}
+
+const PHASES: &[(Phase, &str)] = &[
+    (Phase::Intermediate, "intermediate"),
+    (Phase::Init, "init"),
+    (Phase::Hooks, "hooks"),
+    (Phase::SeccompListener, "seccomp-listener"),
+    (Phase::Start, "start"),
+];
+
+impl Display for Phase {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = PHASES
+            .iter()
+            .find(|(phase, _)| phase == self)
+            .map(|(_, name)| *name)
+            .unwrap_or_default();
+        f.write_str(name)
+    }
+}
+
+impl FromStr for Phase {
+    type Err = TimeoutError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        PHASES
+            .iter()
+            .find(|(_, name)| *name == s)
+            .map(|(phase, _)| *phase)
+            .ok_or_else(|| TimeoutError::UnknownPhase(s.to_owned()))
+    }
+}
+
+/// Time limits of the creation and its phases, nothing is limited by default
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Timeouts {
+    create: Option<Duration>,
+    phases: [Option<Duration>; PHASES.len()],
+}
+
+impl Timeouts {
+    /// Limits the creation as a whole, from the moment the container
+    /// processes are spawned until the container is created
+    pub fn with_create(mut self, timeout: Duration) -> Self {
+        self.create = Some(timeout);
+        self
+    }
+
+    /// Limits a single phase
+    pub fn with_phase(mut self, phase: Phase, timeout: Duration) -> Self {
+        self.phases[phase as usize] = Some(timeout);
+        self
+    }
+
+    pub fn create(&self) -> Option<Duration> {
+        self.create
+    }
+
+    pub fn phase(&self, phase: Phase) -> Option<Duration> {
+        self.phases[phase as usize]
+    }
+}
+
+/// Parses a phase timeout given as phase=seconds
+pub fn parse_phase_timeout(arg: &str) -> Result<(Phase, Duration)> {
+    let (phase, seconds) = arg
+        .split_once('=')
+        .ok_or_else(|| TimeoutError::InvalidPhaseTimeout(arg.to_owned()))?;
+    let seconds: u64 = seconds
+        .parse()
+        .map_err(|_| TimeoutError::InvalidPhaseTimeout(arg.to_owned()))?;
+
+    Ok((phase.parse()?, Duration::from_secs(seconds)))
+}
+
+/// Builds the timeouts from the limit of the creation in seconds and phase
+/// timeouts given as phase=seconds
+pub fn parse_timeouts<S: AsRef<str>>(create: Option<u64>, phases: &[S]) -> Result<Timeouts> {
+    let mut timeouts = Timeouts::default();
+    if let Some(create) = create {
+        timeouts = timeouts.with_create(Duration::from_secs(create));
+    }
+    for phase in phases {
+        let (phase, timeout) = parse_phase_timeout(phase.as_ref())?;
+        timeouts = timeouts.with_phase(phase, timeout);
+    }
+
+    Ok(timeouts)
+}
+
+/// Point in time at which a phase is considered stuck
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Deadline {
+    pub phase: Phase,
+    pub at: Option<Instant>,
+}
+
+impl Deadline {
+    /// Deadline of a phase beginning now, which also has to end before the
+    /// deadline of the operation it is part of
+    pub fn begin(timeouts: &Timeouts, phase: Phase, overall: Option<Instant>) -> Self {
+        let phase_end = timeouts
+            .phase(phase)
+            .map(|timeout| Instant::now() + timeout);
+        let at = match (phase_end, overall) {
+            (Some(phase_end), Some(overall)) => Some(phase_end.min(overall)),
+            (phase_end, overall) => phase_end.or(overall),
+        };
+
+        Self { phase, at }
+    }
+
+    /// Time left until the deadline, `None` if the phase is not limited
+    pub fn remaining(&self) -> Option<Duration> {
+        self.at
+            .map(|at| at.saturating_duration_since(Instant::now()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_phase_timeout() {
+        assert_eq!(
+            parse_phase_timeout("seccomp-listener=5").unwrap(),
+            (Phase::SeccompListener, Duration::from_secs(5))
+        );
+        for (phase, name) in PHASES {
+            assert_eq!(name.parse::<Phase>().unwrap(), *phase);
+            assert_eq!(phase.to_string(), *name);
+        }
+        assert!(matches!(
+            parse_phase_timeout("mount=5"),
+            Err(TimeoutError::UnknownPhase(_))
+        ));
+        assert!(matches!(
+            parse_phase_timeout("init"),
+            Err(TimeoutError::InvalidPhaseTimeout(_))
+        ));
+        assert!(matches!(
+            parse_phase_timeout("init=soon"),
+            Err(TimeoutError::InvalidPhaseTimeout(_))
+        ));
+    }
+
+    #[test]
+    fn test_parse_timeouts() {
+        let timeouts = parse_timeouts(Some(30), &["init=10", "hooks=5"]).unwrap();
+        assert_eq!(timeouts.create(), Some(Duration::from_secs(30)));
+        assert_eq!(timeouts.phase(Phase::Init), Some(Duration::from_secs(10)));
+        assert_eq!(timeouts.phase(Phase::Hooks), Some(Duration::from_secs(5)));
+        assert_eq!(timeouts.phase(Phase::Start), None);
+        assert_eq!(
+            parse_timeouts::<&str>(None, &[]).unwrap(),
+            Timeouts::default()
+        );
+    }
+
+    #[test]
+    fn test_deadline() {
+        let timeouts = Timeouts::default().with_phase(Phase::Init, Duration::from_secs(60));
+        assert_eq!(Deadline::begin(&timeouts, Phase::Hooks, None).at, None);
+
+        let deadline = Deadline::begin(&timeouts, Phase::Init, None);
+        assert!(deadline.remaining().unwrap() > Duration::from_secs(59));
+        assert!(!deadline.remaining().unwrap().is_zero());
+
+        // the overall deadline ends the phase early
+        let overall = Instant::now();
+        let deadline = Deadline::begin(&timeouts, Phase::Init, Some(overall));
+        assert_eq!(deadline.at, Some(overall));
+        assert_eq!(deadline.remaining(), Some(Duration::ZERO));
+        assert_eq!(
+            Deadline::begin(&timeouts, Phase::Hooks, Some(overall)).at,
+            Some(overall)
+        );
+    }
+}
@@ -0,0 +1,347 @@
+//! Shared mount propagation set up by youki. A bind mount with the `shared`
+//! or `rshared` option, or a rootfs with `shared` propagation, is in one
+//! peer group with its source on the host, so that the mounts the container
+//! creates beneath it appear beneath the source on the host as well. These
+//! copies are not removed with the mount namespace of the container and
+//! stay on the host after it is deleted.
+//!
+//! youki records the mounts beneath each shared source when it creates the
+//! container. From the host, a mount the container created can't be told
+//! apart from one made beneath the source on the host later on, so only a
+//! container with the `org.youki.propagation.detach-stray-mounts` annotation
+//! set to `true` has the mounts which appeared beneath the source since
+//! detached on delete, unless another container which shares the source
+//! still runs and has a mount in their peer group. `youki verify` reports
+//! these mounts of such a container while it runs.
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use nix::errno::Errno;
+use nix::mount::{umount2, MntFlags};
+use oci_spec::runtime::Spec;
+use procfs::process::{MountInfo, MountOptFields, Process};
+use serde::{Deserialize, Serialize};
+
+use crate::config::YoukiConfig;
+use crate::container::{Container, ContainerStatus, State};
+
+pub const DETACH_STRAY_MOUNTS_ANNOTATION: &str = "org.youki.propagation.detach-stray-mounts";
+
+#[derive(Debug, thiserror::Error)]
+pub enum PropagationError {
+    #[error("failed to read mounts")]
+    MountInfo(#[from] procfs::ProcError),
+    #[error("failed to detach mount {path:?}")]
+    Detach { path: PathBuf, source: Errno },
+    #[error("invalid value {0:?} of the detach stray mounts annotation, expected true or false")]
+    InvalidDetachStrayMounts(String),
+}
+
+type Result<T> = std::result::Result<T, PropagationError>;
+
+/// Whether the stray mounts of the shared mounts of the container are
+/// detached when it is deleted, which the container opts in to
+pub fn detach_stray_mounts_enabled(annotations: Option<&HashMap<String, String>>) -> Result<bool> {
+    match annotations
+        .and_then(|a| a.get(DETACH_STRAY_MOUNTS_ANNOTATION))
+        .map(String::as_str)
+    {
+        None | Some("false") => Ok(false),
+        Some("true") => Ok(true),
+        Some(value) => Err(PropagationError::InvalidDetachStrayMounts(value.to_owned())),
+    }
+}
+
+/// Source of a mount which propagates mounts to the host
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SharedMount {
+    pub source: PathBuf,
+    /// Mount points beneath the source when the container was created
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub existing: Vec<PathBuf>,
+}
+
+impl SharedMount {
+    fn overlaps(&self, other: &SharedMount) -> bool {
+        self.source.starts_with(&other.source) || other.source.starts_with(&self.source)
+    }
+}
+
+/// Shared mounts of the spec, with the mounts beneath their sources in the
+/// mount namespace of the calling process
+pub fn shared_mounts(spec: &Spec, bundle: &Path, rootfs: &Path) -> Result<Vec<SharedMount>> {
+    let mut sources = Vec::new();
+    if spec
+        .linux()
+        .as_ref()
+        .and_then(|linux| linux.rootfs_propagation().as_deref())
+        == Some("shared")
+    {
+        sources.push(rootfs.to_owned());
+    }
+    for mount in spec.mounts().iter().flatten() {
+        let options = mount.options().as_deref().unwrap_or_default();
+        let has = |names: &[&str]| {
+            options
+                .iter()
+                .any(|option| names.contains(&option.as_str()))
+        };
+        let bind = mount.typ().as_deref() == Some("bind") || has(&["bind", "rbind"]);
+        if !bind || !has(&["shared", "rshared"]) {
+            continue;
+        }
+        let source = match mount.source() {
+            Some(source) => bundle.join(source),
+            None => continue,
+        };
+        match fs::canonicalize(&source) {
+            Ok(source) => sources.push(source),
+            Err(err) => {
+                tracing::warn!(?err, ?source, "failed to resolve source of shared mount");
+            }
+        }
+    }
+    if sources.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let mounts = Process::myself()?.mountinfo()?.0;
+    Ok(sources
+        .into_iter()
+        .map(|source| SharedMount {
+            existing: beneath(&source, &mounts)
+                .iter()
+                .map(|mount| mount.mount_point.clone())
+                .collect(),
+            source,
+        })
+        .collect())
+}
+
+fn beneath<'a>(source: &Path, mounts: &'a [MountInfo]) -> Vec<&'a MountInfo> {
+    mounts
+        .iter()
+        .filter(|mount| mount.mount_point != source && mount.mount_point.starts_with(source))
+        .collect()
+}
+
+fn peer_group(mount: &MountInfo) -> Option<u32> {
+    mount.opt_fields.iter().find_map(|field| match field {
+        MountOptFields::Shared(group) => Some(*group),
+        _ => None,
+    })
+}
+
+/// Containers which share a source with the shared mounts and still run
+#[derive(Debug, Default)]
+pub struct LivePeers {
+    /// Peer groups of the mounts of these containers
+    pub groups: HashSet<u32>,
+    pub in_use: bool,
+}
+
+impl LivePeers {
+    /// Finds the containers in the root path whose shared mounts overlap
+    /// with the shared mounts. Containers which can't be inspected are
+    /// skipped.
+    pub fn find(root_path: &Path, shared_mounts: &[SharedMount]) -> Self {
+        let mut peers = Self::default();
+        let entries = match fs::read_dir(root_path) {
+            Ok(entries) => entries,
+            Err(err) => {
+                tracing::warn!(?err, ?root_path, "failed to list containers");
+                return peers;
+            }
+        };
+        for entry in entries.flatten() {
+            let container_dir = entry.path();
+            if !State::file_path(&container_dir).exists() {
+                continue;
+            }
+            let container = match Container::load(container_dir.clone()) {
+                Ok(container) => container,
+                Err(err) => {
+                    tracing::debug!(?err, ?container_dir, "skipping container");
+                    continue;
+                }
+            };
+            let pid = match (container.status(), container.pid()) {
+                (ContainerStatus::Stopped, _) | (_, None) => continue,
+                (_, Some(pid)) => pid,
+            };
+            let overlaps = YoukiConfig::load(&container_dir).map_or(false, |config| {
+                config
+                    .shared_mounts
+                    .iter()
+                    .any(|theirs| shared_mounts.iter().any(|ours| ours.overlaps(theirs)))
+            });
+            if !overlaps {
+                continue;
+            }
+
+            peers.in_use = true;
+            match Process::new(pid.as_raw()).and_then(|process| process.mountinfo()) {
+                Ok(mounts) => peers.groups.extend(mounts.iter().filter_map(peer_group)),
+                Err(err) => {
+                    tracing::warn!(?err, id = container.id(), "failed to read container mounts")
+                }
+            }
+        }
+        peers
+    }
+}
+
+/// Mounts beneath the source which were not there when the container was
+/// created and belong to none of the live peers, deepest first. A mount
+/// without a peer group is kept as long as the source is in use.
+pub fn stray_mounts<'a>(
+    shared: &SharedMount,
+    mounts: &'a [MountInfo],
+    peers: &LivePeers,
+) -> Vec<&'a MountInfo> {
+    let mut stray: Vec<_> = beneath(&shared.source, mounts)
+        .into_iter()
+        .filter(|mount| !shared.existing.contains(&mount.mount_point))
+        .filter(|mount| match peer_group(mount) {
+            Some(group) => !peers.groups.contains(&group),
+            None => !peers.in_use,
+        })
+        .collect();
+    // mountinfo lists a mount after its parent
+    stray.reverse();
+    stray
+}
+
+/// Detaches the stray mounts of the shared mounts of a deleted container
+pub fn detach_stray_mounts(root_path: &Path, shared_mounts: &[SharedMount]) -> Result<()> {
+    if shared_mounts.is_empty() {
+        return Ok(());
+    }
+    let mounts = Process::myself()?.mountinfo()?.0;
+    let peers = LivePeers::find(root_path, shared_mounts);
+    for shared in shared_mounts {
+        for mount in stray_mounts(shared, &mounts, &peers) {
+            tracing::debug!(path = ?mount.mount_point, source = ?shared.source, "detaching stray mount");
+            match umount2(&mount.mount_point, MntFlags::MNT_DETACH) {
+                // detached together with a mount above it
+                Ok(()) | Err(Errno::EINVAL | Errno::ENOENT) => {}
+                Err(source) => {
+                    return Err(PropagationError::Detach {
+                        path: mount.mount_point.clone(),
+                        source,
+                    })
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use oci_spec::runtime::{LinuxBuilder, MountBuilder, SpecBuilder};
+
+    use super::*;
+
+    fn mount_info(id: i32, mount_point: &str, optional: &str) -> MountInfo {
+        MountInfo::from_line(&format!(
+            "{id} 1 0:50 / {mount_point} rw,relatime {optional} - tmpfs tmpfs rw"
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_shared_mounts() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let source = tmp.path().join("data");
+        fs::create_dir(&source)?;
+        let spec = SpecBuilder::default()
+            .linux(
+                LinuxBuilder::default()
+                    .rootfs_propagation("shared")
+                    .build()?,
+            )
+            .mounts(vec![
+                MountBuilder::default()
+                    .destination("/data")
+                    .typ("bind")
+                    .source("data")
+                    .options(vec!["rbind".to_owned(), "rshared".to_owned()])
+                    .build()?,
+                MountBuilder::default()
+                    .destination("/private")
+                    .typ("bind")
+                    .source("data")
+                    .options(vec!["rbind".to_owned(), "rprivate".to_owned()])
+                    .build()?,
+                MountBuilder::default()
+                    .destination("/proc")
+                    .typ("proc")
+                    .source("proc")
+                    .options(vec!["shared".to_owned()])
+                    .build()?,
+            ])
+            .build()?;
+
+        let rootfs = tmp.path().join("rootfs");
+        let shared = shared_mounts(&spec, tmp.path(), &rootfs)?;
+        let sources: Vec<_> = shared.iter().map(|shared| shared.source.clone()).collect();
+        assert_eq!(sources, vec![rootfs, fs::canonicalize(source)?]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_detach_stray_mounts_enabled() {
+        let annotations = |value: &str| {
+            HashMap::from([(DETACH_STRAY_MOUNTS_ANNOTATION.to_owned(), value.to_owned())])
+        };
+        assert!(!detach_stray_mounts_enabled(None).unwrap());
+        assert!(!detach_stray_mounts_enabled(Some(&HashMap::new())).unwrap());
+        assert!(!detach_stray_mounts_enabled(Some(&annotations("false"))).unwrap());
+        assert!(detach_stray_mounts_enabled(Some(&annotations("true"))).unwrap());
+        assert!(matches!(
+            detach_stray_mounts_enabled(Some(&annotations("yes"))),
+            Err(PropagationError::InvalidDetachStrayMounts(_))
+        ));
+    }
+
+    #[test]
+    fn test_stray_mounts() {
+        let shared = SharedMount {
+            source: PathBuf::from("/srv/data"),
+            existing: vec![PathBuf::from("/srv/data/old")],
+        };
+        let mounts = vec![
+            mount_info(20, "/srv/data", "shared:1"),
+            mount_info(21, "/srv/data/old", "shared:2"),
+            mount_info(22, "/srv/data/a", "shared:3"),
+            mount_info(23, "/srv/data/a/b", "shared:3"),
+            mount_info(24, "/srv/data/live", "shared:4"),
+            mount_info(25, "/srv/data/private", ""),
+            mount_info(26, "/srv/other", "shared:5"),
+        ];
+        let stray_points = |peers: &LivePeers| -> Vec<String> {
+            stray_mounts(&shared, &mounts, peers)
+                .iter()
+                .map(|mount| mount.mount_point.display().to_string())
+                .collect()
+        };
+
+        assert_eq!(
+            stray_points(&LivePeers::default()),
+            vec![
+                "/srv/data/private",
+                "/srv/data/live",
+                "/srv/data/a/b",
+                "/srv/data/a"
+            ]
+        );
+        let peers = LivePeers {
+            groups: HashSet::from([4]),
+            in_use: true,
+        };
+        assert_eq!(stray_points(&peers), vec!["/srv/data/a/b", "/srv/data/a"]);
+    }
+}
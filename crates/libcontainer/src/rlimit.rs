@@ -0,0 +1,257 @@
+//! Default resource limits (ulimits) for containers, in the way container
+//! engines provide them: a default only applies to the resource types the
+//! spec of the container does not limit itself.
+//!
+//! Defaults are given as `type=soft[:hard]`, where the type is the name of
+//! the resource with or without the `RLIMIT_` prefix (`nofile`, `RLIMIT_NPROC`,
+//! ...) and limits are numbers or `unlimited`. A missing hard limit is the
+//! same as the soft one. A default given only as `type` copies the current
+//! limits of the invoking process. These are read with getrlimit, never by
+//! evaluating a shell or the environment variables of the caller.
+use std::str::FromStr;
+
+use nix::sys::resource::{getrlimit, Resource};
+use oci_spec::runtime::{PosixRlimit, PosixRlimitBuilder, PosixRlimitType, Spec};
+
+#[derive(Debug, thiserror::Error)]
+pub enum RlimitError {
+    #[error("unknown rlimit type {0}")]
+    UnknownType(String),
+    #[error("invalid rlimit value {value:?} for {typ}")]
+    InvalidValue { typ: PosixRlimitType, value: String },
+    #[error("soft limit {soft} of {typ} exceeds its hard limit {hard}")]
+    SoftAboveHard {
+        typ: PosixRlimitType,
+        soft: u64,
+        hard: u64,
+    },
+    #[error("failed to get the current {typ} limits")]
+    Inherit {
+        typ: PosixRlimitType,
+        source: nix::Error,
+    },
+    #[error("failed to build rlimit")]
+    SpecBuild(#[from] oci_spec::OciSpecError),
+}
+
+type Result<T> = std::result::Result<T, RlimitError>;
+
+const UNLIMITED: &str = "unlimited";
+
+/// Parses a default rlimit given as `type=soft[:hard]` or `type`
+pub fn parse_default_rlimit(value: &str) -> Result<PosixRlimit> {
+    let (typ, limits) = match value.split_once('=') {
+        Some((typ, limits)) => (parse_type(typ)?, Some(limits)),
+        None => (parse_type(value)?, None),
+    };
+
+    let (soft, hard) = match limits {
+        Some(limits) => {
+            let (soft, hard) = limits.split_once(':').unwrap_or((limits, limits));
+            (parse_limit(typ, soft)?, parse_limit(typ, hard)?)
+        }
+        None => inherit(typ)?,
+    };
+    if soft > hard {
+        return Err(RlimitError::SoftAboveHard { typ, soft, hard });
+    }
+
+    Ok(PosixRlimitBuilder::default()
+        .typ(typ)
+        .soft(soft)
+        .hard(hard)
+        .build()?)
+}
+
+/// Parses every default rlimit of `values`
+pub fn parse_default_rlimits<S: AsRef<str>>(values: &[S]) -> Result<Vec<PosixRlimit>> {
+    values
+        .iter()
+        .map(|value| parse_default_rlimit(value.as_ref()))
+        .collect()
+}
+
+/// Merges the default rlimits with the ones of the spec. The spec always
+/// takes precedence, and of several defaults for the same type the last one
+/// wins. Returns None if neither sets any rlimit.
+pub fn merge(
+    spec_rlimits: Option<&[PosixRlimit]>,
+    defaults: &[PosixRlimit],
+) -> Option<Vec<PosixRlimit>> {
+    let mut merged: Vec<PosixRlimit> = spec_rlimits.unwrap_or_default().to_vec();
+    let spec_len = merged.len();
+
+    for default in defaults {
+        if merged[..spec_len]
+            .iter()
+            .any(|rlimit| rlimit.typ() == default.typ())
+        {
+            continue;
+        }
+        match merged[spec_len..]
+            .iter_mut()
+            .find(|rlimit| rlimit.typ() == default.typ())
+        {
+            Some(rlimit) => *rlimit = *default,
+            None => merged.push(*default),
+        }
+    }
+
+    if merged.is_empty() && spec_rlimits.is_none() {
+        None
+    } else {
+        Some(merged)
+    }
+}
+
+/// Adds the default rlimits to the process of the spec
+pub fn apply_defaults(spec: &mut Spec, defaults: &[PosixRlimit]) {
+    if defaults.is_empty() {
+        return;
+    }
+
+    if let Some(process) = spec.process_mut() {
+        let rlimits = merge(process.rlimits().as_deref(), defaults);
+        process.set_rlimits(rlimits);
+    }
+}
+
+fn parse_type(typ: &str) -> Result<PosixRlimitType> {
+    let name = typ.trim().to_uppercase();
+    let name = if name.starts_with("RLIMIT_") {
+        name
+    } else {
+        format!("RLIMIT_{name}")
+    };
+
+    PosixRlimitType::from_str(&name).map_err(|_| RlimitError::UnknownType(typ.to_owned()))
+}
+
+fn parse_limit(typ: PosixRlimitType, value: &str) -> Result<u64> {
+    let value = value.trim();
+    if value == UNLIMITED {
+        return Ok(libc::RLIM_INFINITY);
+    }
+
+    value.parse().map_err(|_| RlimitError::InvalidValue {
+        typ,
+        value: value.to_owned(),
+    })
+}
+
+fn inherit(typ: PosixRlimitType) -> Result<(u64, u64)> {
+    getrlimit(resource(typ)).map_err(|err| {
+        tracing::error!(?err, ?typ, "failed to get rlimit of the invoking process");
+        RlimitError::Inherit { typ, source: err }
+    })
+}
+
+fn resource(typ: PosixRlimitType) -> Resource {
+    match typ {
+        PosixRlimitType::RlimitCpu => Resource::RLIMIT_CPU,
+        PosixRlimitType::RlimitFsize => Resource::RLIMIT_FSIZE,
+        PosixRlimitType::RlimitData => Resource::RLIMIT_DATA,
+        PosixRlimitType::RlimitStack => Resource::RLIMIT_STACK,
+        PosixRlimitType::RlimitCore => Resource::RLIMIT_CORE,
+        PosixRlimitType::RlimitRss => Resource::RLIMIT_RSS,
+        PosixRlimitType::RlimitNproc => Resource::RLIMIT_NPROC,
+        PosixRlimitType::RlimitNofile => Resource::RLIMIT_NOFILE,
+        PosixRlimitType::RlimitMemlock => Resource::RLIMIT_MEMLOCK,
+        PosixRlimitType::RlimitAs => Resource::RLIMIT_AS,
+        PosixRlimitType::RlimitLocks => Resource::RLIMIT_LOCKS,
+        PosixRlimitType::RlimitSigpending => Resource::RLIMIT_SIGPENDING,
+        PosixRlimitType::RlimitMsgqueue => Resource::RLIMIT_MSGQUEUE,
+        PosixRlimitType::RlimitNice => Resource::RLIMIT_NICE,
+        PosixRlimitType::RlimitRtprio => Resource::RLIMIT_RTPRIO,
+        PosixRlimitType::RlimitRttime => Resource::RLIMIT_RTTIME,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use oci_spec::runtime::ProcessBuilder;
+
+    use super::*;
+
+    fn rlimit(typ: PosixRlimitType, soft: u64, hard: u64) -> PosixRlimit {
+        PosixRlimitBuilder::default()
+            .typ(typ)
+            .soft(soft)
+            .hard(hard)
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_parse_default_rlimit() {
+        assert_eq!(
+            parse_default_rlimit("nofile=1024:4096").unwrap(),
+            rlimit(PosixRlimitType::RlimitNofile, 1024, 4096)
+        );
+        assert_eq!(
+            parse_default_rlimit("RLIMIT_NPROC=512").unwrap(),
+            rlimit(PosixRlimitType::RlimitNproc, 512, 512)
+        );
+        assert_eq!(
+            parse_default_rlimit("core=0:unlimited").unwrap(),
+            rlimit(PosixRlimitType::RlimitCore, 0, libc::RLIM_INFINITY)
+        );
+
+        assert!(matches!(
+            parse_default_rlimit("files=1"),
+            Err(RlimitError::UnknownType(_))
+        ));
+        assert!(matches!(
+            parse_default_rlimit("nofile=$(id -u)"),
+            Err(RlimitError::InvalidValue { .. })
+        ));
+        assert!(matches!(
+            parse_default_rlimit("nofile=10:5"),
+            Err(RlimitError::SoftAboveHard { .. })
+        ));
+    }
+
+    #[test]
+    fn test_parse_inherited_rlimit() {
+        let (soft, hard) = getrlimit(Resource::RLIMIT_NOFILE).unwrap();
+        assert_eq!(
+            parse_default_rlimit("nofile").unwrap(),
+            rlimit(PosixRlimitType::RlimitNofile, soft, hard)
+        );
+    }
+
+    #[test]
+    fn test_merge_precedence() {
+        let spec = [rlimit(PosixRlimitType::RlimitNofile, 1024, 1024)];
+        let defaults = [
+            rlimit(PosixRlimitType::RlimitNofile, 65536, 65536),
+            rlimit(PosixRlimitType::RlimitNproc, 100, 100),
+            rlimit(PosixRlimitType::RlimitNproc, 200, 200),
+        ];
+
+        assert_eq!(
+            merge(Some(&spec), &defaults).unwrap(),
+            vec![
+                rlimit(PosixRlimitType::RlimitNofile, 1024, 1024),
+                rlimit(PosixRlimitType::RlimitNproc, 200, 200),
+            ]
+        );
+        assert_eq!(merge(None, &defaults[..1]).unwrap(), defaults[..1]);
+        assert_eq!(merge(Some(&[]), &[]), Some(vec![]));
+        assert_eq!(merge(None, &[]), None);
+    }
+
+    #[test]
+    fn test_apply_defaults() {
+        let mut spec = Spec::default();
+        spec.set_process(Some(
+            ProcessBuilder::default().rlimits(vec![]).build().unwrap(),
+        ));
+        apply_defaults(&mut spec, &[rlimit(PosixRlimitType::RlimitCore, 0, 0)]);
+
+        assert_eq!(
+            spec.process().as_ref().unwrap().rlimits().as_deref(),
+            Some(&[rlimit(PosixRlimitType::RlimitCore, 0, 0)][..])
+        );
+    }
+}
@@ -0,0 +1,331 @@
+//! Optional verification of the container rootfs before the container is
+//! jailed into it, for deployments which need measured containers. The
+//! verification is configured through annotations:
+//!
+//! - `org.youki.rootfs.verity-root-hash`: the rootfs must be the mount point
+//!   of a dm-verity device whose root hash matches the given hex digest.
+//! - `org.youki.rootfs.hash-manifest`: absolute path of a manifest in
+//!   `sha256sum` format. Every file listed in it, relative to the rootfs, must
+//!   have the given sha256 digest.
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Read};
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::io::AsRawFd;
+use std::path::{Component, Path, PathBuf};
+
+use sha2::{Digest, Sha256};
+
+pub const VERITY_ROOT_HASH_ANNOTATION: &str = "org.youki.rootfs.verity-root-hash";
+pub const HASH_MANIFEST_ANNOTATION: &str = "org.youki.rootfs.hash-manifest";
+
+#[derive(Debug, thiserror::Error)]
+pub enum IntegrityError {
+    #[error("rootfs {0:?} is not backed by a dm-verity device")]
+    NotVerity(PathBuf),
+    #[error("dm-verity root hash mismatch: expected {expected}, got {actual}")]
+    RootHashMismatch { expected: String, actual: String },
+    #[error("dm-verity device of the rootfs reports corruption")]
+    Corrupted,
+    #[error("failed to query device mapper for {major}:{minor}")]
+    DeviceMapper {
+        major: u64,
+        minor: u64,
+        source: nix::Error,
+    },
+    #[error("invalid hash manifest entry on line {line}: {reason}")]
+    InvalidManifest { line: usize, reason: String },
+    #[error("hash mismatch for {path:?}: expected {expected}, got {actual}")]
+    HashMismatch {
+        path: PathBuf,
+        expected: String,
+        actual: String,
+    },
+    #[error("io error on {path:?}")]
+    Io { path: PathBuf, source: io::Error },
+}
+
+type Result<T> = std::result::Result<T, IntegrityError>;
+
+/// Runs the verification requested by the annotations against the rootfs.
+/// Without any of the annotations this is a no-op.
+pub fn verify(annotations: Option<&HashMap<String, String>>, rootfs: &Path) -> Result<()> {
+    let Some(annotations) = annotations else {
+        return Ok(());
+    };
+
+    if let Some(root_hash) = annotations.get(VERITY_ROOT_HASH_ANNOTATION) {
+        verify_verity_root_hash(rootfs, root_hash)?;
+    }
+
+    if let Some(manifest) = annotations.get(HASH_MANIFEST_ANNOTATION) {
+        verify_hash_manifest(rootfs, Path::new(manifest))?;
+    }
+
+    Ok(())
+}
+
+/// Checks that the rootfs is the mount of a dm-verity device with the
+/// expected root hash, and that the kernel has not detected corruption so
+/// far. The table is read from the device mapper, so this does not depend on
+/// veritysetup being installed.
+pub fn verify_verity_root_hash(rootfs: &Path, expected: &str) -> Result<()> {
+    let dev = fs::metadata(rootfs)
+        .map_err(|err| io_error(rootfs, err))?
+        .dev();
+    let (major, minor) = (nix::sys::stat::major(dev), nix::sys::stat::minor(dev));
+
+    let dm_query = |flags| {
+        device_mapper::table_status(dev, flags).map_err(|err| IntegrityError::DeviceMapper {
+            major,
+            minor,
+            source: err,
+        })
+    };
+
+    let (target_type, table) = dm_query(device_mapper::DM_STATUS_TABLE_FLAG)?;
+    if target_type != "verity" {
+        tracing::error!(?rootfs, ?target_type, "rootfs is not a dm-verity device");
+        return Err(IntegrityError::NotVerity(rootfs.to_owned()));
+    }
+
+    // <version> <data_dev> <hash_dev> <data_block_size> <hash_block_size>
+    // <num_data_blocks> <hash_start_block> <algorithm> <root_digest> ...
+    let actual = table.split_whitespace().nth(8).unwrap_or_default();
+    if !actual.eq_ignore_ascii_case(expected.trim()) {
+        tracing::error!(?rootfs, expected, actual, "dm-verity root hash mismatch");
+        return Err(IntegrityError::RootHashMismatch {
+            expected: expected.trim().to_owned(),
+            actual: actual.to_owned(),
+        });
+    }
+
+    // The status is "V" while verified and "C" once corruption was found.
+    let (_, status) = dm_query(0)?;
+    if status.trim_start().starts_with('C') {
+        return Err(IntegrityError::Corrupted);
+    }
+
+    Ok(())
+}
+
+/// Checks every file listed in a `sha256sum` style manifest. Paths in the
+/// manifest are resolved inside the rootfs and may not escape it.
+pub fn verify_hash_manifest(rootfs: &Path, manifest: &Path) -> Result<()> {
+    let file = File::open(manifest).map_err(|err| io_error(manifest, err))?;
+    for (index, line) in BufReader::new(file).lines().enumerate() {
+        let line = line.map_err(|err| io_error(manifest, err))?;
+        let Some((expected, path)) = parse_manifest_line(index + 1, &line)? else {
+            continue;
+        };
+
+        let full_path = safe_path::scoped_join(rootfs, path).map_err(|err| io_error(path, err))?;
+        let actual = sha256_file(&full_path)?;
+        if !actual.eq_ignore_ascii_case(expected) {
+            tracing::error!(?path, expected, actual, "rootfs file hash mismatch");
+            return Err(IntegrityError::HashMismatch {
+                path: path.to_owned(),
+                expected: expected.to_owned(),
+                actual,
+            });
+        }
+    }
+
+    Ok(())
+}
+
+fn parse_manifest_line(line_number: usize, line: &str) -> Result<Option<(&str, &Path)>> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return Ok(None);
+    }
+
+    let invalid = |reason: &str| IntegrityError::InvalidManifest {
+        line: line_number,
+        reason: reason.to_owned(),
+    };
+    let (digest, path) = line
+        .split_once(char::is_whitespace)
+        .ok_or_else(|| invalid("expected '<sha256> <path>'"))?;
+    // sha256sum marks binary mode with a '*' in front of the path
+    let path = Path::new(path.trim_start().trim_start_matches('*'));
+
+    if digest.len() != 64 || !digest.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(invalid("digest is not a sha256 hex string"));
+    }
+    if path.components().any(|c| c == Component::ParentDir) {
+        return Err(invalid("path must not contain '..'"));
+    }
+
+    Ok(Some((digest, path)))
+}
+
+fn sha256_file(path: &Path) -> Result<String> {
+    let mut file = File::open(path).map_err(|err| io_error(path, err))?;
+    let mut hasher = Sha256::new();
+    let mut buf = [0u8; 64 * 1024];
+    loop {
+        let n = file.read(&mut buf).map_err(|err| io_error(path, err))?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+
+    Ok(hasher
+        .finalize()
+        .iter()
+        .fold(String::with_capacity(64), |mut hex, b| {
+            let _ = write!(hex, "{b:02x}");
+            hex
+        }))
+}
+
+fn io_error(path: &Path, source: io::Error) -> IntegrityError {
+    IntegrityError::Io {
+        path: path.to_owned(),
+        source,
+    }
+}
+
+/// Minimal client for the device mapper control interface, see
+/// include/uapi/linux/dm-ioctl.h.
+mod device_mapper {
+    use super::*;
+
+    pub const DM_STATUS_TABLE_FLAG: u32 = 1 << 4;
+
+    const DM_VERSION: [u32; 3] = [4, 0, 0];
+    const DM_BUFFER_SIZE: usize = 16 * 1024;
+    // _IOWR(DM_IOCTL, DM_TABLE_STATUS_CMD, struct dm_ioctl)
+    const DM_TABLE_STATUS: libc::c_ulong =
+        (3 << 30) | ((std::mem::size_of::<DmIoctl>() as libc::c_ulong) << 16) | (0xfd << 8) | 12;
+
+    #[repr(C)]
+    struct DmIoctl {
+        version: [u32; 3],
+        data_size: u32,
+        data_start: u32,
+        target_count: u32,
+        open_count: i32,
+        flags: u32,
+        event_nr: u32,
+        padding: u32,
+        dev: u64,
+        name: [u8; 128],
+        uuid: [u8; 129],
+        data: [u8; 7],
+    }
+
+    #[repr(C)]
+    struct DmTargetSpec {
+        sector_start: u64,
+        length: u64,
+        status: i32,
+        next: u32,
+        target_type: [u8; 16],
+    }
+
+    /// Returns the target type and the table (or status, depending on the
+    /// flags) of the first target of the device.
+    pub fn table_status(dev: u64, flags: u32) -> nix::Result<(String, String)> {
+        let control = File::open("/dev/mapper/control")
+            .map_err(|err| nix::Error::from_raw(err.raw_os_error().unwrap_or(libc::EIO)))?;
+
+        // The header is followed by the buffer the kernel writes into.
+        let mut buf = vec![0u64; DM_BUFFER_SIZE / 8];
+        let header = buf.as_mut_ptr() as *mut DmIoctl;
+        // Safe because the buffer is zeroed, aligned and larger than the header.
+        unsafe {
+            (*header).version = DM_VERSION;
+            (*header).data_size = DM_BUFFER_SIZE as u32;
+            (*header).data_start = std::mem::size_of::<DmIoctl>() as u32;
+            (*header).flags = flags;
+            (*header).dev = dev;
+        }
+
+        let ret = unsafe { libc::ioctl(control.as_raw_fd(), DM_TABLE_STATUS, header) };
+        nix::errno::Errno::result(ret)?;
+
+        let bytes =
+            unsafe { std::slice::from_raw_parts(buf.as_ptr() as *const u8, DM_BUFFER_SIZE) };
+        let (target_count, data_start) = unsafe { ((*header).target_count, (*header).data_start) };
+        if target_count == 0 {
+            return Ok((String::new(), String::new()));
+        }
+
+        let spec_start = data_start as usize;
+        let params_start = spec_start + std::mem::size_of::<DmTargetSpec>();
+        if params_start >= bytes.len() {
+            return Err(nix::Error::EOVERFLOW);
+        }
+        let target_type = c_str(&bytes[spec_start + 24..spec_start + 40]);
+        let params = c_str(&bytes[params_start..]);
+
+        Ok((target_type, params))
+    }
+
+    fn c_str(bytes: &[u8]) -> String {
+        let end = bytes.iter().position(|b| *b == 0).unwrap_or(bytes.len());
+        String::from_utf8_lossy(&bytes[..end]).into_owned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+
+    use super::*;
+
+    // sha256 of "youki"
+    const YOUKI_SHA256: &str = "e770213bea73f3712407efaa470aeca3c8c3510df2189c05ad6befaf7b80d668";
+
+    #[test]
+    fn test_no_annotations() -> Result<()> {
+        verify(None, Path::new("/does/not/exist"))?;
+        verify(Some(&HashMap::new()), Path::new("/does/not/exist"))?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_manifest_line() {
+        assert!(parse_manifest_line(1, "").unwrap().is_none());
+        assert!(parse_manifest_line(1, "# comment").unwrap().is_none());
+
+        let line = format!("{YOUKI_SHA256} *bin/sh");
+        let (digest, path) = parse_manifest_line(1, &line).unwrap().unwrap();
+        assert_eq!(digest, YOUKI_SHA256);
+        assert_eq!(path, Path::new("bin/sh"));
+
+        assert!(parse_manifest_line(1, "deadbeef bin/sh").is_err());
+        assert!(parse_manifest_line(1, &format!("{YOUKI_SHA256}  ../etc/shadow")).is_err());
+        assert!(parse_manifest_line(1, YOUKI_SHA256).is_err());
+    }
+
+    #[test]
+    fn test_verify_hash_manifest() -> Result<()> {
+        let rootfs = tempfile::tempdir()?;
+        fs::create_dir(rootfs.path().join("etc"))?;
+        fs::write(rootfs.path().join("etc/name"), "youki")?;
+
+        let manifest = rootfs.path().join("manifest");
+        fs::write(&manifest, format!("{YOUKI_SHA256}  /etc/name\n"))?;
+        verify_hash_manifest(rootfs.path(), &manifest)?;
+
+        fs::write(rootfs.path().join("etc/name"), "runc")?;
+        assert!(matches!(
+            verify_hash_manifest(rootfs.path(), &manifest),
+            Err(IntegrityError::HashMismatch { .. })
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_verify_root_hash_not_verity() -> Result<()> {
+        let rootfs = tempfile::tempdir()?;
+        assert!(verify_verity_root_hash(rootfs.path(), YOUKI_SHA256).is_err());
+        Ok(())
+    }
+}
@@ -11,6 +11,9 @@ pub use device::Device;
 pub(super) mod mount;
 pub(super) mod symlink;
 
+pub mod integrity;
+pub mod readonly;
+pub mod remap;
 pub mod utils;
 
 #[derive(Debug, thiserror::Error)]
@@ -27,6 +30,12 @@ pub enum RootfsError {
     Mount(#[from] mount::MountError),
     #[error(transparent)]
     Device(#[from] device::DeviceError),
+    #[error(transparent)]
+    Integrity(#[from] integrity::IntegrityError),
+    #[error(transparent)]
+    Remap(#[from] remap::RemapError),
+    #[error(transparent)]
+    ReadonlyRoot(#[from] readonly::ReadonlyRootError),
 }
 
 type Result<T> = std::result::Result<T, RootfsError>;
@@ -1,9 +1,9 @@
 use std::fs::{canonicalize, create_dir_all, OpenOptions};
-use std::mem;
 use std::os::unix::io::AsRawFd;
 use std::path::{Path, PathBuf};
 #[cfg(feature = "v1")]
 use std::{borrow::Cow, collections::HashMap};
+use std::{fmt, mem};
 
 use libcgroups::common::CgroupSetup::{Hybrid, Legacy, Unified};
 #[cfg(feature = "v1")]
@@ -22,6 +22,7 @@ use safe_path;
 use super::symlink::Symlink;
 use super::symlink::SymlinkError;
 use super::utils::{parse_mount, MountOptionConfig};
+use crate::kernel::{self, Feature};
 use crate::syscall::syscall::create_syscall;
 use crate::syscall::{linux, Syscall, SyscallError};
 use crate::utils::PathBufExt;
@@ -48,6 +49,99 @@ pub enum MountError {
     Procfs(#[from] procfs::ProcError),
     #[error("unknown mount option: {0}")]
     UnsupportedMountOption(String),
+    #[error("recursive mount options require mount_setattr, which the kernel does not support")]
+    RecursiveMountUnsupported,
+    #[error("mount source {path:?} does not exist on the host, create it or fix the source of the mount")]
+    MissingSource {
+        path: PathBuf,
+        #[source]
+        err: std::io::Error,
+    },
+    #[error("failed to mount {context}: {err}")]
+    Mount {
+        context: Box<MountContext>,
+        err: SyscallError,
+    },
+}
+
+/// Arguments of a failed mount call, after the source and destination were
+/// resolved
+#[derive(Debug)]
+pub struct MountContext {
+    pub source: Option<PathBuf>,
+    pub destination: PathBuf,
+    pub fstype: Option<String>,
+    pub flags: MsFlags,
+    pub data: Option<String>,
+    pub hint: Option<&'static str>,
+}
+
+impl MountContext {
+    fn new(
+        source: Option<&Path>,
+        destination: &Path,
+        fstype: Option<&str>,
+        flags: MsFlags,
+        data: Option<&str>,
+        err: &SyscallError,
+    ) -> Self {
+        Self {
+            source: source.map(Path::to_path_buf),
+            destination: destination.to_path_buf(),
+            fstype: fstype.map(str::to_owned),
+            flags,
+            data: data.filter(|data| !data.is_empty()).map(str::to_owned),
+            hint: mount_hint(flags, err),
+        }
+    }
+}
+
+impl fmt::Display for MountContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.source {
+            Some(source) => write!(f, "{source:?} on {:?}", self.destination)?,
+            None => write!(f, "{:?}", self.destination)?,
+        }
+        write!(
+            f,
+            " (type {}, flags {:?}",
+            self.fstype.as_deref().unwrap_or("none"),
+            self.flags
+        )?;
+        if let Some(data) = &self.data {
+            write!(f, ", data {data:?}")?;
+        }
+        write!(f, ")")?;
+        if let Some(hint) = self.hint {
+            write!(f, ", {hint}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Returns a remediation hint for common causes of mount failures
+fn mount_hint(flags: MsFlags, err: &SyscallError) -> Option<&'static str> {
+    let errno = match err {
+        SyscallError::Nix(errno) => *errno,
+        _ => return None,
+    };
+    let propagation =
+        MsFlags::MS_SHARED | MsFlags::MS_SLAVE | MsFlags::MS_PRIVATE | MsFlags::MS_UNBINDABLE;
+    // inside the user namespace of a rootless container the uid map does not
+    // cover the whole range
+    let rootless = || crate::utils::is_in_new_userns().unwrap_or(false);
+
+    match errno {
+        Errno::ENOENT => Some("the source or the destination does not exist"),
+        Errno::ENODEV => Some("the kernel does not support the filesystem type"),
+        Errno::EINVAL | Errno::EPERM if flags.intersects(propagation) && rootless() => Some(
+            "mounts inherited from the host are locked in a rootless container, use private or slave propagation instead of shared",
+        ),
+        Errno::EPERM if rootless() => Some(
+            "a rootless container cannot mount this filesystem type, bind mount it from the host instead",
+        ),
+        _ => None,
+    }
 }
 
 type Result<T> = std::result::Result<T, MountError>;
@@ -77,6 +171,20 @@ impl Mount {
         }
     }
 
+    /// Mounts like the mount syscall, failures carry the arguments of the call
+    fn mount(
+        &self,
+        source: Option<&Path>,
+        destination: &Path,
+        fstype: Option<&str>,
+        flags: MsFlags,
+        data: Option<&str>,
+    ) -> Result<()> {
+        self.syscall
+            .mount(source, destination, fstype, flags, data)
+            .map_err(|err| mount_error(source, destination, fstype, flags, data, err))
+    }
+
     pub fn setup_mount(&self, mount: &SpecMount, options: &MountOptions) -> Result<()> {
         tracing::debug!("mounting {:?}", mount);
         let mut mount_option_config = parse_mount(mount)?;
@@ -464,7 +572,7 @@ impl Mount {
             .iter()
             .any(|field| matches!(field, MountOptFields::Shared(_)))
         {
-            self.syscall.mount(
+            self.mount(
                 None,
                 &parent_mount.mount_point,
                 None,
@@ -511,7 +619,13 @@ impl Mount {
         let src = if typ == Some("bind") {
             let src = canonicalize(source).map_err(|err| {
                 tracing::error!("failed to canonicalize {:?}: {}", source, err);
-                err
+                match err.kind() {
+                    std::io::ErrorKind::NotFound => MountError::MissingSource {
+                        path: source.clone(),
+                        err,
+                    },
+                    _ => MountError::Io(err),
+                }
             })?;
             let dir = if src.is_file() {
                 Path::new(&dest).parent().unwrap()
@@ -553,22 +667,28 @@ impl Mount {
             if let SyscallError::Nix(errno) = err {
                 if !matches!(errno, Errno::EINVAL) {
                     tracing::error!("mount of {:?} failed. {}", m.destination(), errno);
-                    return Err(err.into());
+                    return Err(mount_error(
+                        Some(&src),
+                        dest,
+                        typ,
+                        mount_option_config.flags,
+                        Some(&d),
+                        err,
+                    ));
                 }
             }
 
-            self.syscall
-                .mount(
-                    Some(&*src),
-                    dest,
-                    typ,
-                    mount_option_config.flags,
-                    Some(&mount_option_config.data),
-                )
-                .map_err(|err| {
-                    tracing::error!("failed to mount {src:?} to {dest:?}");
-                    err
-                })?;
+            self.mount(
+                Some(&*src),
+                dest,
+                typ,
+                mount_option_config.flags,
+                Some(&mount_option_config.data),
+            )
+            .map_err(|err| {
+                tracing::error!("failed to mount {src:?} to {dest:?}");
+                err
+            })?;
         }
 
         if typ == Some("bind")
@@ -581,21 +701,24 @@ impl Mount {
                     | MsFlags::MS_SLAVE),
             )
         {
-            self.syscall
-                .mount(
-                    Some(dest),
-                    dest,
-                    None,
-                    mount_option_config.flags | MsFlags::MS_REMOUNT,
-                    None,
-                )
-                .map_err(|err| {
-                    tracing::error!("failed to remount {:?}: {}", dest, err);
-                    err
-                })?;
+            self.mount(
+                Some(dest),
+                dest,
+                None,
+                mount_option_config.flags | MsFlags::MS_REMOUNT,
+                None,
+            )
+            .map_err(|err| {
+                tracing::error!("failed to remount {:?}: {}", dest, err);
+                err
+            })?;
         }
 
         if let Some(mount_attr) = &mount_option_config.rec_attr {
+            if !kernel::has(Feature::MountSetattr) {
+                tracing::error!(?dest, "kernel does not support recursive mount options");
+                return Err(MountError::RecursiveMountUnsupported);
+            }
             let open_dir = Dir::open(dest, OFlag::O_DIRECTORY, Mode::empty())?;
             let dir_fd_pathbuf = PathBuf::from(format!("/proc/self/fd/{}", open_dir.as_raw_fd()));
             self.syscall.mount_setattr(
@@ -611,6 +734,27 @@ impl Mount {
     }
 }
 
+fn mount_error(
+    source: Option<&Path>,
+    destination: &Path,
+    fstype: Option<&str>,
+    flags: MsFlags,
+    data: Option<&str>,
+    err: SyscallError,
+) -> MountError {
+    MountError::Mount {
+        context: Box::new(MountContext::new(
+            source,
+            destination,
+            fstype,
+            flags,
+            data,
+            &err,
+        )),
+        err,
+    }
+}
+
 /// Find parent mount of rootfs in given mount infos
 pub fn find_parent_mount(
     rootfs: &Path,
@@ -1050,4 +1194,56 @@ mod tests {
         let res = find_parent_mount(Path::new("/path/to/rootfs"), mount_infos);
         assert!(res.is_err());
     }
+
+    #[test]
+    fn test_mount_error_context() {
+        let err = mount_error(
+            Some(Path::new("tmpfs")),
+            Path::new("/rootfs/tmp"),
+            Some("tmpfs"),
+            MsFlags::MS_NOSUID,
+            Some("size=64k"),
+            SyscallError::Nix(Errno::ENODEV),
+        );
+        assert_eq!(
+            err.to_string(),
+            "failed to mount \"tmpfs\" on \"/rootfs/tmp\" (type tmpfs, flags MsFlags(MS_NOSUID), \
+             data \"size=64k\"), the kernel does not support the filesystem type: ENODEV: No such device"
+        );
+
+        let err = mount_error(
+            None,
+            Path::new("/"),
+            None,
+            MsFlags::MS_PRIVATE,
+            None,
+            SyscallError::Nix(Errno::EBUSY),
+        );
+        assert_eq!(
+            err.to_string(),
+            "failed to mount \"/\" (type none, flags MsFlags(MS_PRIVATE)): EBUSY: Device or resource busy"
+        );
+    }
+
+    #[test]
+    fn test_mount_missing_bind_source() -> Result<()> {
+        let tmp_dir = tempfile::tempdir()?;
+        let mount = SpecMountBuilder::default()
+            .destination("/data")
+            .typ("bind")
+            .source(tmp_dir.path().join("missing"))
+            .options(vec!["rbind".to_string()])
+            .build()?;
+        let mount_option_config = parse_mount(&mount)?;
+
+        let err = Mount::new()
+            .mount_into_container(&mount, tmp_dir.path(), &mount_option_config, None)
+            .unwrap_err();
+        assert!(
+            matches!(&err, MountError::MissingSource { path, .. } if path.ends_with("missing")),
+            "{err}"
+        );
+
+        Ok(())
+    }
 }
@@ -0,0 +1,185 @@
+//! Read-only lower roots. A container whose root is read-only may be created
+//! from a rootfs on a read-only filesystem, e.g. an image which is mounted
+//! read-only once and shared by many containers. Setting up the mount points,
+//! devices and symlinks of the container writes to the rootfs though, so the
+//! init process covers such a rootfs with an overlay whose lower layer is the
+//! rootfs and whose upper layer is a tmpfs. Once all mounts are set up, the
+//! overlay is remounted read-only together with the rest of the root, so
+//! only youki writes to the upper layer.
+use std::fs;
+use std::io;
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd};
+use std::path::{Path, PathBuf};
+
+use nix::fcntl::{open, AtFlags, OFlag};
+use nix::mount::MsFlags;
+use nix::sys::stat::{mkdirat, Mode};
+use nix::sys::statvfs::{statvfs, FsFlags};
+use nix::unistd::{fchownat, Gid, Uid};
+
+use crate::syscall::{Syscall, SyscallError};
+
+#[derive(Debug, thiserror::Error)]
+pub enum ReadonlyRootError {
+    #[error("failed syscall")]
+    Syscall(#[from] SyscallError),
+    #[error("io error on {path:?}")]
+    Io { path: PathBuf, source: io::Error },
+    #[error("failed to {action} {path:?}")]
+    Nix {
+        action: &'static str,
+        path: PathBuf,
+        source: nix::Error,
+    },
+}
+
+type Result<T> = std::result::Result<T, ReadonlyRootError>;
+
+/// Covers the rootfs with an overlay if it is on a read-only filesystem.
+/// Returns false if the rootfs is writable and was left as it is.
+pub fn cover(syscall: &dyn Syscall, rootfs: &Path, in_user_ns: bool) -> Result<bool> {
+    let stat = statvfs(rootfs).map_err(|source| ReadonlyRootError::Nix {
+        action: "statvfs",
+        path: rootfs.to_owned(),
+        source,
+    })?;
+    if !stat.flags().contains(FsFlags::ST_RDONLY) {
+        return Ok(false);
+    }
+
+    overlay(syscall, rootfs, in_user_ns)?;
+    tracing::debug!(?rootfs, "covered read-only rootfs with an overlay");
+    Ok(true)
+}
+
+fn overlay(syscall: &dyn Syscall, rootfs: &Path, in_user_ns: bool) -> Result<()> {
+    let metadata = fs::metadata(rootfs).map_err(|source| ReadonlyRootError::Io {
+        path: rootfs.to_owned(),
+        source,
+    })?;
+    let open_path = |path: &Path| {
+        open(
+            path,
+            OFlag::O_PATH | OFlag::O_DIRECTORY | OFlag::O_CLOEXEC,
+            Mode::empty(),
+        )
+        .map(|fd| unsafe { OwnedFd::from_raw_fd(fd) })
+        .map_err(|source| ReadonlyRootError::Nix {
+            action: "open",
+            path: path.to_owned(),
+            source,
+        })
+    };
+    // the rootfs and the tmpfs on top of it stay reachable through their fds
+    // once they are covered
+    let lower = open_path(rootfs)?;
+    syscall.mount(
+        Some(Path::new("tmpfs")),
+        rootfs,
+        Some("tmpfs"),
+        MsFlags::MS_NOSUID | MsFlags::MS_NODEV,
+        Some("mode=700"),
+    )?;
+    let scratch = open_path(rootfs)?;
+    for dir in ["upper", "work"] {
+        mkdirat(
+            Some(scratch.as_raw_fd()),
+            dir,
+            Mode::from_bits_truncate(0o700),
+        )
+        .map_err(|source| ReadonlyRootError::Nix {
+            action: "create overlay directory in",
+            path: rootfs.join(dir),
+            source,
+        })?;
+    }
+    // the root of the overlay has the attributes of the upper directory
+    let upper = PathBuf::from(format!("/proc/self/fd/{}/upper", scratch.as_raw_fd()));
+    fs::set_permissions(&upper, metadata.permissions()).map_err(|source| {
+        ReadonlyRootError::Io {
+            path: upper.clone(),
+            source,
+        }
+    })?;
+    fchownat(
+        None,
+        &upper,
+        Some(Uid::from_raw(metadata.uid())),
+        Some(Gid::from_raw(metadata.gid())),
+        AtFlags::empty(),
+    )
+    .map_err(|source| ReadonlyRootError::Nix {
+        action: "chown",
+        path: upper.clone(),
+        source,
+    })?;
+
+    let mut data = format!(
+        "lowerdir=/proc/self/fd/{lower},upperdir=/proc/self/fd/{scratch}/upper,workdir=/proc/self/fd/{scratch}/work",
+        lower = lower.as_raw_fd(),
+        scratch = scratch.as_raw_fd(),
+    );
+    if in_user_ns {
+        // trusted xattrs are not available to overlays of user namespaces
+        data.push_str(",userxattr");
+    }
+    syscall.mount(
+        Some(Path::new("overlay")),
+        rootfs,
+        Some("overlay"),
+        MsFlags::empty(),
+        Some(&data),
+    )?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+
+    use super::*;
+    use crate::syscall::syscall::create_syscall;
+    use crate::syscall::test::TestHelperSyscall;
+
+    #[test]
+    fn test_cover_writable_rootfs() -> Result<()> {
+        let rootfs = tempfile::tempdir()?;
+        let syscall = create_syscall();
+        assert!(!cover(syscall.as_ref(), rootfs.path(), false)?);
+
+        let mocks = syscall
+            .as_any()
+            .downcast_ref::<TestHelperSyscall>()
+            .unwrap();
+        assert!(mocks.get_mount_args().is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_overlay() -> Result<()> {
+        let rootfs = tempfile::tempdir()?;
+        let syscall = create_syscall();
+        overlay(syscall.as_ref(), rootfs.path(), true)?;
+
+        let mocks = syscall
+            .as_any()
+            .downcast_ref::<TestHelperSyscall>()
+            .unwrap();
+        let mounts = mocks.get_mount_args();
+        assert_eq!(mounts.len(), 2);
+        assert_eq!(mounts[0].fstype.as_deref(), Some("tmpfs"));
+        assert_eq!(mounts[0].target, rootfs.path());
+        assert_eq!(mounts[1].fstype.as_deref(), Some("overlay"));
+        assert_eq!(mounts[1].target, rootfs.path());
+        let data = mounts[1].data.as_deref().unwrap_or_default();
+        assert!(data.starts_with("lowerdir=/proc/self/fd/"), "{data}");
+        assert!(data.ends_with(",userxattr"), "{data}");
+
+        // without the tmpfs of the mock, the directories end up in the rootfs
+        assert!(rootfs.path().join("upper").is_dir());
+        assert!(rootfs.path().join("work").is_dir());
+        Ok(())
+    }
+}
@@ -0,0 +1,777 @@
+//! Remapped ownership of bind mounts, for host directories whose files belong
+//! to another user than the container process, e.g. a directory written by a
+//! rootless container with ids of the user's subuid range. Instead of a
+//! `chown -R` of the directory on the host, the mount option
+//!
+//! - `x-youki.remap`, or `x-youki.remap=UID:GID`,
+//!
+//! of a bind mount exposes the files owned by the owner of the mount source as
+//! owned by the user of the container process, or by the given ids of the
+//! container. The files on the host are left as they are.
+//!
+//! If youki may create idmapped mounts, which requires root on the host and
+//! kernel 5.12 or later, the source is idmapped by the main process before the
+//! container is created, swapping the host ids of its owner and of the target.
+//! Otherwise the init process covers the mount with an overlay whose upper
+//! layer is a tmpfs, and chowns the files in it. Outside of a user namespace
+//! the overlay uses metacopy, so only the metadata of the files is copied up,
+//! inside of one their content is copied into memory as well, up to
+//! [`COPY_LIMIT`] bytes. With the overlay, changes to the mount stay in the
+//! container and are lost when it exits.
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::mem;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use nix::fcntl::{open, OFlag};
+use nix::mount::MsFlags;
+use nix::sched::{unshare, CloneFlags};
+use nix::sys::stat::{mkdirat, Mode};
+use nix::sys::wait::waitpid;
+use nix::unistd::{self, fchownat, ForkResult, Gid, Uid};
+use oci_spec::runtime::{LinuxIdMapping, LinuxNamespaceType, Mount as SpecMount, Spec};
+
+use super::utils::parse_mount;
+use crate::kernel::{self, Feature};
+use crate::syscall::{linux, Syscall, SyscallError};
+
+pub const REMAP_OPTION: &str = "x-youki.remap";
+
+/// Size of the tmpfs of the overlay, which bounds the content of the files
+/// the overlay copies into memory when it can't copy only their metadata
+pub const COPY_LIMIT: u64 = 64 << 20;
+
+#[derive(Debug, thiserror::Error)]
+pub enum RemapError {
+    #[error("invalid remap option {0:?}, expected x-youki.remap or x-youki.remap=UID:GID")]
+    InvalidOption(String),
+    #[error("remapped mount {0:?} is not a bind mount")]
+    NotBind(PathBuf),
+    #[error("remapped mount {0:?} is not a directory, which requires an idmapped mount")]
+    NotDirectory(PathBuf),
+    #[error("io error on {path:?}")]
+    Io { path: PathBuf, source: io::Error },
+    #[error("failed to {action} {path:?}")]
+    Nix {
+        action: &'static str,
+        path: PathBuf,
+        source: nix::Error,
+    },
+    #[error("remapping {path:?} copies {size} bytes into memory, more than the limit of {limit} bytes without idmapped mounts or overlay metacopy")]
+    CopyLimit {
+        path: PathBuf,
+        size: u64,
+        limit: u64,
+    },
+    #[error("failed syscall")]
+    Syscall(#[from] SyscallError),
+}
+
+type Result<T> = std::result::Result<T, RemapError>;
+
+/// Owner the files of the mount source are remapped to, ids of the container
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RemapTarget {
+    pub uid: u32,
+    pub gid: u32,
+}
+
+/// Detached idmapped mounts the main process prepared for the init process,
+/// by the destination of their mount
+#[derive(Debug, Clone, Default)]
+pub struct IdmappedMounts(Rc<HashMap<PathBuf, OwnedFd>>);
+
+impl IdmappedMounts {
+    pub fn get(&self, destination: &Path) -> Option<&OwnedFd> {
+        self.0.get(destination)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+/// Target of the remap option of the mount, if it has one. Without ids the
+/// target is the user of the container process.
+pub fn remap_target(mount: &SpecMount, spec: &Spec) -> Result<Option<RemapTarget>> {
+    let option = match mount
+        .options()
+        .iter()
+        .flatten()
+        .find(|option| is_remap_option(option))
+    {
+        Some(option) => option,
+        None => return Ok(None),
+    };
+    if mount.typ().as_deref() != Some("bind") {
+        return Err(RemapError::NotBind(mount.destination().clone()));
+    }
+
+    let target = match option.strip_prefix(REMAP_OPTION) {
+        Some("") => {
+            let user = spec.process().as_ref().map(|process| process.user());
+            RemapTarget {
+                uid: user.map_or(0, |user| user.uid()),
+                gid: user.map_or(0, |user| user.gid()),
+            }
+        }
+        Some(ids) => ids
+            .strip_prefix('=')
+            .and_then(|ids| ids.split_once(':'))
+            .and_then(|(uid, gid)| Some((uid.parse().ok()?, gid.parse().ok()?)))
+            .map(|(uid, gid)| RemapTarget { uid, gid })
+            .ok_or_else(|| RemapError::InvalidOption(option.to_owned()))?,
+        None => unreachable!("remap option without its prefix"),
+    };
+    Ok(Some(target))
+}
+
+/// Whether the mount option is the remap option, which is not passed on to
+/// the mount
+pub fn is_remap_option(option: &str) -> bool {
+    option == REMAP_OPTION || option.starts_with("x-youki.remap=")
+}
+
+/// Creates the idmapped mounts of the remapped mounts of the spec. It runs in
+/// the main process, the only one which may create idmapped mounts of host
+/// filesystems. Mounts which can't be idmapped are left to the overlay of the
+/// init process.
+pub fn prepare_idmapped_mounts(spec: &Spec) -> IdmappedMounts {
+    let mut idmapped = HashMap::new();
+    for mount in spec.mounts().iter().flatten() {
+        let target = match remap_target(mount, spec) {
+            Ok(Some(target)) => target,
+            // invalid options fail the mount in the init process
+            Ok(None) | Err(_) => continue,
+        };
+        if !kernel::has(Feature::MountSetattr) || !unistd::geteuid().is_root() {
+            tracing::debug!(destination = ?mount.destination(), "idmapped mounts are not available, remapping with an overlay");
+            continue;
+        }
+
+        match idmap_mount(mount, spec, target) {
+            Ok(Some(fd)) => {
+                idmapped.insert(mount.destination().clone(), fd);
+            }
+            Ok(None) => {}
+            Err(err) => {
+                tracing::warn!(destination = ?mount.destination(), ?err, "failed to idmap the mount, remapping with an overlay");
+            }
+        }
+    }
+    IdmappedMounts(Rc::new(idmapped))
+}
+
+fn idmap_mount(mount: &SpecMount, spec: &Spec, target: RemapTarget) -> Result<Option<OwnedFd>> {
+    let source = mount
+        .source()
+        .as_ref()
+        .ok_or_else(|| RemapError::NotBind(mount.destination().clone()))?;
+    let metadata = fs::metadata(source).map_err(|source_err| RemapError::Io {
+        path: source.clone(),
+        source: source_err,
+    })?;
+
+    let linux = spec.linux().as_ref();
+    let user_ns = linux
+        .and_then(|linux| linux.namespaces().as_ref())
+        .map_or(false, |namespaces| {
+            namespaces
+                .iter()
+                .any(|ns| ns.typ() == LinuxNamespaceType::User)
+        });
+    let mappings = |mappings: Option<&Vec<LinuxIdMapping>>| {
+        if user_ns {
+            mappings.cloned()
+        } else {
+            None
+        }
+    };
+    let host_uid = host_id(
+        target.uid,
+        mappings(linux.and_then(|l| l.uid_mappings().as_ref())).as_deref(),
+    );
+    let host_gid = host_id(
+        target.gid,
+        mappings(linux.and_then(|l| l.gid_mappings().as_ref())).as_deref(),
+    );
+    let (host_uid, host_gid) = match (host_uid, host_gid) {
+        (Some(uid), Some(gid)) => (uid, gid),
+        _ => {
+            tracing::warn!(destination = ?mount.destination(), ?target, "remap target is not mapped to the host");
+            return Ok(None);
+        }
+    };
+    if metadata.uid() == host_uid && metadata.gid() == host_gid {
+        tracing::debug!(destination = ?mount.destination(), "mount source is owned by the remap target already");
+        return Ok(None);
+    }
+
+    let userns = create_userns(
+        &swap_map(metadata.uid(), host_uid),
+        &swap_map(metadata.gid(), host_gid),
+    )?;
+
+    let recursive = parse_mount(mount)
+        .map(|config| config.flags.contains(MsFlags::MS_REC))
+        .unwrap_or_default();
+    let tree = open_tree(source, recursive)?;
+    let attr = linux::MountAttr {
+        attr_set: libc::MOUNT_ATTR_IDMAP,
+        attr_clr: 0,
+        propagation: 0,
+        userns_fd: userns.as_raw_fd() as u64,
+    };
+    let flags = libc::AT_EMPTY_PATH as u32 | if recursive { linux::AT_RECURSIVE } else { 0 };
+    mount_setattr(&tree, flags, &attr).map_err(|err| RemapError::Nix {
+        action: "idmap",
+        path: source.clone(),
+        source: err,
+    })?;
+    tracing::debug!(?source, destination = ?mount.destination(), "idmapped remapped mount");
+
+    Ok(Some(tree))
+}
+
+/// Host id of the container id through the mappings, the id itself without
+/// a user namespace
+fn host_id(id: u32, mappings: Option<&[LinuxIdMapping]>) -> Option<u32> {
+    match mappings {
+        Some(mappings) => mappings.iter().find_map(|mapping| {
+            let offset = id.checked_sub(mapping.container_id())?;
+            (offset < mapping.size()).then(|| mapping.host_id() + offset)
+        }),
+        None => Some(id),
+    }
+}
+
+/// Id map which swaps the two ids and maps all other ids to themselves
+fn swap_map(a: u32, b: u32) -> String {
+    // u32::MAX is not a valid id
+    if a == b {
+        return format!("0 0 {}\n", u32::MAX);
+    }
+    let (low, high) = (a.min(b), a.max(b));
+    let mut map = String::new();
+    let mut line = |inside: u32, outside: u32, count: u32| {
+        if count > 0 {
+            map.push_str(&format!("{inside} {outside} {count}\n"));
+        }
+    };
+    line(0, 0, low);
+    line(low, high, 1);
+    line(low + 1, low + 1, high - low - 1);
+    line(high, low, 1);
+    line(high + 1, high + 1, u32::MAX - high - 1);
+    map
+}
+
+/// User namespace with the id maps, created by a child which unshares it and
+/// waits until the parent opened it
+fn create_userns(uid_map: &str, gid_map: &str) -> Result<File> {
+    let nix_err = |action| {
+        move |source| RemapError::Nix {
+            action,
+            path: PathBuf::from("/proc/self/ns/user"),
+            source,
+        }
+    };
+    let (ready_read, ready_write) = unistd::pipe().map_err(nix_err("create pipe for"))?;
+    let (done_read, done_write) = unistd::pipe().map_err(nix_err("create pipe for"))?;
+
+    match unsafe { unistd::fork() }.map_err(nix_err("fork to create"))? {
+        ForkResult::Child => {
+            drop(ready_read);
+            drop(done_write);
+            let status = match unshare(CloneFlags::CLONE_NEWUSER) {
+                Ok(()) => {
+                    let mut ready = File::from(ready_write);
+                    let _ = ready.write_all(&[0]);
+                    let _ = File::from(done_read).read(&mut [0]);
+                    0
+                }
+                Err(_) => 1,
+            };
+            unsafe { libc::_exit(status) };
+        }
+        ForkResult::Parent { child } => {
+            drop(ready_write);
+            drop(done_read);
+            let userns = (|| {
+                let mut ready = [0];
+                if File::from(ready_read).read(&mut ready)? == 0 {
+                    return Err(io::Error::new(
+                        io::ErrorKind::Other,
+                        "child failed to unshare the user namespace",
+                    ));
+                }
+                fs::write(format!("/proc/{child}/uid_map"), uid_map)?;
+                fs::write(format!("/proc/{child}/gid_map"), gid_map)?;
+                File::open(format!("/proc/{child}/ns/user"))
+            })();
+            drop(done_write);
+            let _ = waitpid(child, None);
+            userns.map_err(|source| RemapError::Io {
+                path: PathBuf::from(format!("/proc/{child}/ns/user")),
+                source,
+            })
+        }
+    }
+}
+
+fn open_tree(path: &Path, recursive: bool) -> Result<OwnedFd> {
+    let path_c = CString::new(path.as_os_str().as_bytes()).map_err(|err| RemapError::Io {
+        path: path.to_owned(),
+        source: err.into(),
+    })?;
+    let mut flags = libc::OPEN_TREE_CLONE | libc::OPEN_TREE_CLOEXEC;
+    if recursive {
+        flags |= libc::AT_RECURSIVE as u32;
+    }
+    match unsafe { libc::syscall(libc::SYS_open_tree, libc::AT_FDCWD, path_c.as_ptr(), flags) } {
+        -1 => Err(RemapError::Nix {
+            action: "open tree of",
+            path: path.to_owned(),
+            source: nix::Error::last(),
+        }),
+        fd => Ok(unsafe { OwnedFd::from_raw_fd(fd as i32) }),
+    }
+}
+
+fn mount_setattr(tree: &OwnedFd, flags: u32, attr: &linux::MountAttr) -> nix::Result<()> {
+    match unsafe {
+        libc::syscall(
+            libc::SYS_mount_setattr,
+            tree.as_raw_fd(),
+            b"\0".as_ptr(),
+            flags,
+            attr as *const linux::MountAttr,
+            mem::size_of::<linux::MountAttr>(),
+        )
+    } {
+        0 => Ok(()),
+        _ => Err(nix::Error::last()),
+    }
+}
+
+fn move_mount(tree: &OwnedFd, dest: &Path) -> Result<()> {
+    let dest_c = CString::new(dest.as_os_str().as_bytes()).map_err(|err| RemapError::Io {
+        path: dest.to_owned(),
+        source: err.into(),
+    })?;
+    match unsafe {
+        libc::syscall(
+            libc::SYS_move_mount,
+            tree.as_raw_fd(),
+            b"\0".as_ptr(),
+            libc::AT_FDCWD,
+            dest_c.as_ptr(),
+            libc::MOVE_MOUNT_F_EMPTY_PATH,
+        )
+    } {
+        0 => Ok(()),
+        _ => Err(RemapError::Nix {
+            action: "attach idmapped mount to",
+            path: dest.to_owned(),
+            source: nix::Error::last(),
+        }),
+    }
+}
+
+/// Remaps the ownership of the bind mount at `dest` in the rootfs, with the
+/// idmapped mount of the main process if there is one or with an overlay.
+/// Runs in the init process after the mount was set up.
+pub fn remap_mount(
+    syscall: &dyn Syscall,
+    mount: &SpecMount,
+    dest: &Path,
+    target: RemapTarget,
+    idmapped: Option<&OwnedFd>,
+    in_user_ns: bool,
+) -> Result<()> {
+    match idmapped {
+        Some(tree) => move_mount(tree, dest)?,
+        None => {
+            if !overlay(syscall, dest, target, in_user_ns)? {
+                return Ok(());
+            }
+        }
+    }
+
+    // the flags of the bind mount apply to the mount on top of it as well
+    let flags = parse_mount(mount)
+        .map(|config| config.flags)
+        .unwrap_or_else(|_| MsFlags::empty());
+    let remount_flags =
+        flags & (MsFlags::MS_RDONLY | MsFlags::MS_NOSUID | MsFlags::MS_NODEV | MsFlags::MS_NOEXEC);
+    if !remount_flags.is_empty() {
+        syscall.mount(
+            Some(dest),
+            dest,
+            None,
+            remount_flags | MsFlags::MS_BIND | MsFlags::MS_REMOUNT,
+            None,
+        )?;
+    }
+    Ok(())
+}
+
+/// Covers the mount with an overlay in which the files of the owner of the
+/// mount are chowned to the target. Returns false if nothing had to be
+/// remapped.
+fn overlay(
+    syscall: &dyn Syscall,
+    dest: &Path,
+    target: RemapTarget,
+    in_user_ns: bool,
+) -> Result<bool> {
+    let io_err = |path: &Path| {
+        let path = path.to_owned();
+        move |source| RemapError::Io { path, source }
+    };
+    let metadata = fs::symlink_metadata(dest).map_err(io_err(dest))?;
+    if !metadata.is_dir() {
+        return Err(RemapError::NotDirectory(dest.to_owned()));
+    }
+    let owner = (metadata.uid(), metadata.gid());
+    if owner == (target.uid, target.gid) {
+        tracing::debug!(?dest, "mount is owned by the remap target already");
+        return Ok(false);
+    }
+
+    let open_path = |path: &Path| {
+        open(
+            path,
+            OFlag::O_PATH | OFlag::O_DIRECTORY | OFlag::O_CLOEXEC,
+            Mode::empty(),
+        )
+        .map(|fd| unsafe { OwnedFd::from_raw_fd(fd) })
+        .map_err(|source| RemapError::Nix {
+            action: "open",
+            path: path.to_owned(),
+            source,
+        })
+    };
+    // the bind mount and the tmpfs on top of it stay reachable through their
+    // fds once they are covered
+    let lower = open_path(dest)?;
+    let lower_path = PathBuf::from(format!("/proc/self/fd/{}", lower.as_raw_fd()));
+    syscall.mount(
+        Some(Path::new("tmpfs")),
+        dest,
+        Some("tmpfs"),
+        MsFlags::MS_NOSUID | MsFlags::MS_NODEV,
+        Some(&format!("mode=700,size={COPY_LIMIT}")),
+    )?;
+    let scratch = open_path(dest)?;
+    for dir in ["upper", "work"] {
+        mkdirat(
+            Some(scratch.as_raw_fd()),
+            dir,
+            Mode::from_bits_truncate(0o700),
+        )
+        .map_err(|source| RemapError::Nix {
+            action: "create overlay directory in",
+            path: dest.join(dir),
+            source,
+        })?;
+    }
+    // the root of the overlay has the attributes of the upper directory
+    let upper = PathBuf::from(format!("/proc/self/fd/{}/upper", scratch.as_raw_fd()));
+    fs::set_permissions(&upper, metadata.permissions()).map_err(io_err(&upper))?;
+    fchownat(
+        None,
+        &upper,
+        Some(Uid::from_raw(owner.0)),
+        Some(Gid::from_raw(owner.1)),
+        nix::fcntl::AtFlags::empty(),
+    )
+    .map_err(|source| RemapError::Nix {
+        action: "chown",
+        path: upper.clone(),
+        source,
+    })?;
+
+    let mut data = format!(
+        "lowerdir=/proc/self/fd/{lower},upperdir=/proc/self/fd/{scratch}/upper,workdir=/proc/self/fd/{scratch}/work",
+        lower = lower.as_raw_fd(),
+        scratch = scratch.as_raw_fd(),
+    );
+    if in_user_ns {
+        // metacopy is not available to overlays of user namespaces
+        tracing::warn!(
+            ?dest,
+            "remapping in a user namespace copies the files of the mount into memory"
+        );
+        check_copy_limit(dest, copied_size(&lower_path, owner)?)?;
+        data.push_str(",userxattr");
+        syscall.mount(
+            Some(Path::new("overlay")),
+            dest,
+            Some("overlay"),
+            MsFlags::empty(),
+            Some(&data),
+        )?;
+    } else if syscall
+        .mount(
+            Some(Path::new("overlay")),
+            dest,
+            Some("overlay"),
+            MsFlags::empty(),
+            Some(&format!("{data},metacopy=on")),
+        )
+        .is_err()
+    {
+        tracing::warn!(
+            ?dest,
+            "overlay without metacopy support, remapping copies the files of the mount into memory"
+        );
+        check_copy_limit(dest, copied_size(&lower_path, owner)?)?;
+        syscall.mount(
+            Some(Path::new("overlay")),
+            dest,
+            Some("overlay"),
+            MsFlags::empty(),
+            Some(&data),
+        )?;
+    }
+
+    chown_tree(dest, owner, target)?;
+    tracing::debug!(?dest, ?owner, ?target, "remapped mount with an overlay");
+    Ok(true)
+}
+
+/// Fails if copying `size` bytes would exceed the tmpfs of the overlay
+fn check_copy_limit(dest: &Path, size: u64) -> Result<()> {
+    if size > COPY_LIMIT {
+        return Err(RemapError::CopyLimit {
+            path: dest.to_owned(),
+            size,
+            limit: COPY_LIMIT,
+        });
+    }
+    Ok(())
+}
+
+/// Size of the content which chowning the tree copies up, the regular files
+/// owned by either of the owner ids
+fn copied_size(path: &Path, owner: (u32, u32)) -> Result<u64> {
+    let mut size = 0u64;
+    walk_tree(path, |_, metadata| {
+        if metadata.is_file() && (metadata.uid() == owner.0 || metadata.gid() == owner.1) {
+            size = size.saturating_add(metadata.len());
+        }
+        Ok(())
+    })?;
+    Ok(size)
+}
+
+/// Chowns the files of the tree owned by the owner ids to the target,
+/// without following symlinks
+fn chown_tree(path: &Path, owner: (u32, u32), target: RemapTarget) -> Result<()> {
+    walk_tree(path, |path, metadata| {
+        let uid = (metadata.uid() == owner.0).then(|| Uid::from_raw(target.uid));
+        let gid = (metadata.gid() == owner.1).then(|| Gid::from_raw(target.gid));
+        if uid.is_some() || gid.is_some() {
+            fchownat(
+                None,
+                path,
+                uid,
+                gid,
+                nix::fcntl::AtFlags::AT_SYMLINK_NOFOLLOW,
+            )
+            .map_err(|source| RemapError::Nix {
+                action: "chown",
+                path: path.to_owned(),
+                source,
+            })?;
+        }
+        Ok(())
+    })
+}
+
+/// Visits every file of the tree, directories before their entries, without
+/// following symlinks. The pending entries are kept on the heap, so the depth
+/// of the tree is not limited by the stack.
+fn walk_tree<F>(root: &Path, mut visit: F) -> Result<()>
+where
+    F: FnMut(&Path, &fs::Metadata) -> Result<()>,
+{
+    let io_err = |path: &Path| {
+        let path = path.to_owned();
+        move |source| RemapError::Io { path, source }
+    };
+    let mut pending = vec![root.to_owned()];
+    while let Some(path) = pending.pop() {
+        let metadata = fs::symlink_metadata(&path).map_err(io_err(&path))?;
+        visit(&path, &metadata)?;
+        if metadata.is_dir() {
+            for entry in fs::read_dir(&path).map_err(io_err(&path))? {
+                pending.push(entry.map_err(io_err(&path))?.path());
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use oci_spec::runtime::{
+        LinuxIdMappingBuilder, MountBuilder, ProcessBuilder, SpecBuilder, UserBuilder,
+    };
+
+    use super::*;
+
+    fn bind_mount(options: &[&str]) -> Result<SpecMount> {
+        Ok(MountBuilder::default()
+            .destination("/data")
+            .typ("bind")
+            .source("/tmp")
+            .options(options.iter().map(|o| o.to_string()).collect::<Vec<_>>())
+            .build()?)
+    }
+
+    #[test]
+    fn test_remap_target() -> Result<()> {
+        let spec = SpecBuilder::default()
+            .process(
+                ProcessBuilder::default()
+                    .user(UserBuilder::default().uid(1000u32).gid(100u32).build()?)
+                    .build()?,
+            )
+            .build()?;
+
+        let mount = bind_mount(&["rbind"])?;
+        assert_eq!(remap_target(&mount, &spec)?, None);
+
+        let mount = bind_mount(&["rbind", REMAP_OPTION])?;
+        assert_eq!(
+            remap_target(&mount, &spec)?,
+            Some(RemapTarget {
+                uid: 1000,
+                gid: 100
+            })
+        );
+
+        let mount = bind_mount(&["x-youki.remap=5:6"])?;
+        assert_eq!(
+            remap_target(&mount, &spec)?,
+            Some(RemapTarget { uid: 5, gid: 6 })
+        );
+
+        for invalid in ["x-youki.remap=5", "x-youki.remap=a:b"] {
+            let mount = bind_mount(&[invalid])?;
+            assert!(matches!(
+                remap_target(&mount, &spec),
+                Err(RemapError::InvalidOption(_))
+            ));
+        }
+
+        let mount = MountBuilder::default()
+            .destination("/data")
+            .typ("tmpfs")
+            .options(vec![REMAP_OPTION.to_owned()])
+            .build()?;
+        assert!(matches!(
+            remap_target(&mount, &spec),
+            Err(RemapError::NotBind(_))
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_walk_deep_tree() -> Result<()> {
+        let root = tempfile::tempdir()?;
+        let mut deepest = root.path().to_owned();
+        for _ in 0..1000 {
+            deepest.push("d");
+        }
+        fs::create_dir_all(&deepest)?;
+        fs::write(deepest.join("file"), "youki")?;
+
+        let mut visited = 0;
+        walk_tree(root.path(), |_, _| {
+            visited += 1;
+            Ok(())
+        })?;
+        assert_eq!(visited, 1002);
+
+        let metadata = fs::metadata(root.path())?;
+        let owner = (metadata.uid(), metadata.gid());
+        chown_tree(
+            root.path(),
+            owner,
+            RemapTarget {
+                uid: owner.0,
+                gid: owner.1,
+            },
+        )?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_copied_size() -> Result<()> {
+        let root = tempfile::tempdir()?;
+        fs::create_dir(root.path().join("dir"))?;
+        fs::write(root.path().join("a"), vec![0; 100])?;
+        fs::write(root.path().join("dir/b"), vec![0; 23])?;
+        std::os::unix::fs::symlink("a", root.path().join("link"))?;
+
+        let metadata = fs::metadata(root.path())?;
+        let owner = (metadata.uid(), metadata.gid());
+        assert_eq!(copied_size(root.path(), owner)?, 123);
+        assert_eq!(copied_size(root.path(), (u32::MAX, u32::MAX))?, 0);
+
+        check_copy_limit(root.path(), COPY_LIMIT)?;
+        assert!(matches!(
+            check_copy_limit(root.path(), COPY_LIMIT + 1),
+            Err(RemapError::CopyLimit { .. })
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_host_id() -> Result<()> {
+        let mappings = [
+            LinuxIdMappingBuilder::default()
+                .container_id(0u32)
+                .host_id(1000u32)
+                .size(1u32)
+                .build()?,
+            LinuxIdMappingBuilder::default()
+                .container_id(1u32)
+                .host_id(100000u32)
+                .size(65536u32)
+                .build()?,
+        ];
+        assert_eq!(host_id(0, Some(&mappings)), Some(1000));
+        assert_eq!(host_id(1000, Some(&mappings)), Some(100999));
+        assert_eq!(host_id(65537, Some(&mappings)), None);
+        assert_eq!(host_id(42, None), Some(42));
+        Ok(())
+    }
+
+    #[test]
+    fn test_swap_map() {
+        assert_eq!(
+            swap_map(100999, 1000),
+            format!(
+                "0 0 1000\n1000 100999 1\n1001 1001 99998\n100999 1000 1\n101000 101000 {}\n",
+                u32::MAX - 101000
+            )
+        );
+        assert_eq!(
+            swap_map(0, 1),
+            format!("0 1 1\n1 0 1\n2 2 {}\n", u32::MAX - 2)
+        );
+        assert_eq!(swap_map(7, 7), format!("0 0 {}\n", u32::MAX));
+    }
+}
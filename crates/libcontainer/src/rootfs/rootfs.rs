@@ -6,6 +6,8 @@ use oci_spec::runtime::{Linux, Spec};
 
 use super::device::Device;
 use super::mount::{Mount, MountOptions};
+use super::readonly;
+use super::remap::{self, IdmappedMounts};
 use super::symlink::Symlink;
 use super::utils::default_devices;
 use super::{Result, RootfsError};
@@ -37,6 +39,7 @@ impl RootFS {
         spec: &Spec,
         rootfs: &Path,
         cgroup_ns: bool,
+        idmapped_mounts: &IdmappedMounts,
     ) -> Result<()> {
         let mut flags = MsFlags::MS_REC;
         match linux.rootfs_propagation().as_deref() {
@@ -78,6 +81,25 @@ impl RootFS {
                 err
             })?;
 
+        let in_user_ns = crate::utils::is_in_new_userns().map_err(|err| {
+            tracing::error!(?err, "failed to check for a user namespace");
+            RootfsError::Remap(remap::RemapError::Io {
+                path: "/proc/self/uid_map".into(),
+                source: err,
+            })
+        })?;
+        let readonly_root = spec
+            .root()
+            .as_ref()
+            .and_then(|root| root.readonly())
+            .unwrap_or(false);
+        if readonly_root {
+            readonly::cover(self.syscall.as_ref(), rootfs, in_user_ns).map_err(|err| {
+                tracing::error!(?rootfs, ?err, "failed to cover the read-only rootfs");
+                err
+            })?;
+        }
+
         let global_options = MountOptions {
             root: rootfs,
             label: linux.mount_label().as_deref(),
@@ -86,7 +108,29 @@ impl RootFS {
 
         if let Some(mounts) = spec.mounts() {
             for mount in mounts {
+                let remap_target = remap::remap_target(mount, spec)?;
                 mounter.setup_mount(mount, &global_options)?;
+                if let Some(target) = remap_target {
+                    let dest =
+                        safe_path::scoped_join(rootfs, mount.destination()).map_err(|err| {
+                            RootfsError::Remap(remap::RemapError::Io {
+                                path: mount.destination().clone(),
+                                source: err,
+                            })
+                        })?;
+                    remap::remap_mount(
+                        self.syscall.as_ref(),
+                        mount,
+                        &dest,
+                        target,
+                        idmapped_mounts.get(mount.destination()),
+                        in_user_ns,
+                    )
+                    .map_err(|err| {
+                        tracing::error!(?err, ?dest, "failed to remap the ownership of the mount");
+                        err
+                    })?;
+                }
             }
         }
         Ok(())
@@ -98,11 +142,12 @@ impl RootFS {
         rootfs: &Path,
         bind_devices: bool,
         cgroup_ns: bool,
+        idmapped_mounts: &IdmappedMounts,
     ) -> Result<()> {
         tracing::debug!(?rootfs, "prepare rootfs");
         let linux = spec.linux().as_ref().ok_or(MissingSpecError::Linux)?;
 
-        self.mount_to_rootfs(linux, spec, rootfs, cgroup_ns)?;
+        self.mount_to_rootfs(linux, spec, rootfs, cgroup_ns, idmapped_mounts)?;
 
         let symlinker = Symlink::new();
         symlinker.setup_kcore_symlink(rootfs)?;
@@ -6,6 +6,7 @@ use nix::sys::stat::SFlag;
 use oci_spec::runtime::{LinuxDevice, LinuxDeviceBuilder, LinuxDeviceType, Mount};
 
 use super::mount::MountError;
+use super::remap;
 use crate::syscall::linux::{self, MountRecursive};
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -89,6 +90,10 @@ pub fn parse_mount(m: &Mount) -> std::result::Result<MountOptionConfig, MountErr
 
     if let Some(options) = &m.options() {
         for option in options {
+            if remap::is_remap_option(option) {
+                continue;
+            }
+
             if let Ok(mount_attr_option) = linux::MountRecursive::from_str(option.as_str()) {
                 // Some options aren't corresponding to the mount flags.
                 // These options need `AT_RECURSIVE` options.
@@ -250,6 +255,26 @@ mod tests {
             mount_option_config
         );
 
+        let mount_option_config = parse_mount(
+            &MountBuilder::default()
+                .destination(PathBuf::from("/data"))
+                .typ("bind")
+                .source(PathBuf::from("/tmp"))
+                .options(vec![
+                    "rbind".to_string(),
+                    "x-youki.remap=1000:1000".to_string(),
+                ])
+                .build()?,
+        )?;
+        assert_eq!(
+            MountOptionConfig {
+                flags: MsFlags::MS_BIND | MsFlags::MS_REC,
+                data: "".to_string(),
+                rec_attr: None,
+            },
+            mount_option_config
+        );
+
         let mount_option_config = parse_mount(
             &MountBuilder::default()
                 .destination(PathBuf::from("/dev/pts"))
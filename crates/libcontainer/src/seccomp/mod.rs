@@ -8,6 +8,10 @@ use oci_spec::runtime::{
     Arch, LinuxSeccomp, LinuxSeccompAction, LinuxSeccompFilterFlag, LinuxSeccompOperator,
 };
 
+use crate::kernel;
+
+pub mod summary;
+
 #[derive(Debug, thiserror::Error)]
 pub enum SeccompError {
     #[error("failed to translate trace action due to failed to convert errno {errno} into i16")]
@@ -16,6 +20,8 @@ pub enum SeccompError {
     NotifyAsDefaultAction,
     #[error("SCMP_ACT_NOTIFY cannot be used for the write syscall")]
     NotifyWriteSyscall,
+    #[error("SCMP_ACT_NOTIFY requires kernel 5.0 or later")]
+    NotifyUnsupported,
     #[error("failed to add arch to seccomp")]
     AddArch {
         source: libseccomp::error::SeccompError,
@@ -109,7 +115,7 @@ fn translate_op(op: LinuxSeccompOperator, datum_b: Option<u64>) -> ScmpCompareOp
     }
 }
 
-fn check_seccomp(seccomp: &LinuxSeccomp) -> Result<()> {
+pub(crate) fn check_seccomp(seccomp: &LinuxSeccomp) -> Result<()> {
     // We don't support notify as default action. After the seccomp filter is
     // created with notify, the container process will have to communicate the
     // returned fd to another process. Therefore, we need the write syscall or
@@ -124,6 +130,11 @@ fn check_seccomp(seccomp: &LinuxSeccomp) -> Result<()> {
         return Err(SeccompError::NotifyAsDefaultAction);
     }
 
+    // Seccomp user notification was introduced in kernel 5.0.
+    if is_notify(seccomp) && !kernel::at_least(5, 0) {
+        return Err(SeccompError::NotifyUnsupported);
+    }
+
     if let Some(syscalls) = seccomp.syscalls() {
         for syscall in syscalls {
             if syscall.action() == LinuxSeccompAction::ScmpActNotify {
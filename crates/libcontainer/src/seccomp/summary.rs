@@ -0,0 +1,340 @@
+//! Summary of what a seccomp profile effectively does per architecture, which
+//! allows to audit a profile without reading the generated BPF program. The
+//! rules are merged the way [`initialize_seccomp`](super::initialize_seccomp)
+//! adds them to the filter: rules with the default action are dropped,
+//! duplicated rules are merged and syscalls which can not be resolved are
+//! skipped.
+use std::collections::{BTreeMap, BTreeSet};
+
+use libseccomp::{ScmpAction, ScmpArch, ScmpSyscall};
+use oci_spec::runtime::{Arch, LinuxSeccomp, LinuxSeccompAction, LinuxSeccompArg};
+use serde::Serialize;
+
+use super::{translate_action, translate_arch, Result};
+
+/// Action of a rule with the errno it effectively returns
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct RuleAction {
+    pub action: LinuxSeccompAction,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub errno: Option<u32>,
+}
+
+impl RuleAction {
+    fn new(action: LinuxSeccompAction, errno: Option<u32>) -> Self {
+        let errno = match action {
+            LinuxSeccompAction::ScmpActErrno | LinuxSeccompAction::ScmpActTrace => {
+                Some(errno.unwrap_or(libc::EPERM as u32))
+            }
+            _ => None,
+        };
+        let action = match action {
+            // both kill the calling thread only
+            LinuxSeccompAction::ScmpActKill => LinuxSeccompAction::ScmpActKillThread,
+            action => action,
+        };
+
+        Self { action, errno }
+    }
+}
+
+/// Rule which only applies when an argument of the syscall matches
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct ConditionalRule {
+    #[serde(flatten)]
+    pub action: RuleAction,
+    pub arg: LinuxSeccompArg,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct SyscallSummary {
+    /// Action taken regardless of the arguments
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub action: Option<RuleAction>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub conditional: Vec<ConditionalRule>,
+    /// Actions of later rules conflicting with `action`, a profile with
+    /// conflicting rules fails to load
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub conflicting: Vec<RuleAction>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ArchSummary {
+    pub arch: Arch,
+    /// Syscalls allowed regardless of their arguments. When the default
+    /// action allows, every syscall without a rule is allowed as well.
+    pub allowed: Vec<String>,
+    /// Syscalls with an action other than the default one
+    pub syscalls: BTreeMap<String, SyscallSummary>,
+    /// Syscalls of the profile which do not exist on the architecture
+    #[serde(skip_serializing_if = "BTreeSet::is_empty")]
+    pub unresolved: BTreeSet<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SeccompSummary {
+    pub default_action: RuleAction,
+    /// The native architecture followed by the ones of the profile, the
+    /// native architecture is always part of the filter
+    pub architectures: Vec<ArchSummary>,
+}
+
+/// Computes the effective default and syscall actions of a profile for every
+/// architecture of the filter
+pub fn summarize(seccomp: &LinuxSeccomp) -> Result<SeccompSummary> {
+    let default_action = translate_action(seccomp.default_action(), seccomp.default_errno_ret())?;
+
+    let listed = seccomp.architectures().as_deref().unwrap_or_default();
+    let mut arches: Vec<(Arch, ScmpArch)> = Vec::new();
+    let native = ScmpArch::native();
+    if !listed
+        .iter()
+        .any(|&arch| is_native(translate_arch(arch), native))
+    {
+        arches.push((Arch::ScmpArchNative, ScmpArch::Native));
+    }
+    for &arch in listed {
+        let scmp_arch = translate_arch(arch);
+        if !arches.iter().any(|(_, added)| *added == scmp_arch) {
+            arches.push((arch, scmp_arch));
+        }
+    }
+
+    let architectures = arches
+        .into_iter()
+        .map(|(arch, scmp_arch)| summarize_arch(seccomp, default_action, arch, scmp_arch))
+        .collect::<Result<_>>()?;
+
+    Ok(SeccompSummary {
+        default_action: RuleAction::new(seccomp.default_action(), seccomp.default_errno_ret()),
+        architectures,
+    })
+}
+
+fn is_native(arch: ScmpArch, native: ScmpArch) -> bool {
+    arch == ScmpArch::Native || arch == native
+}
+
+/// Syscalls which do not exist on an architecture resolve to a negative
+/// pseudo syscall number, for which libseccomp adds no rule.
+fn exists_on(name: &str, arch: ScmpArch) -> bool {
+    ScmpSyscall::from_name_by_arch_rewrite(name, arch)
+        .map_or(false, |syscall| i32::from(syscall) >= 0)
+}
+
+fn summarize_arch(
+    seccomp: &LinuxSeccomp,
+    default_action: ScmpAction,
+    arch: Arch,
+    scmp_arch: ScmpArch,
+) -> Result<ArchSummary> {
+    let mut syscalls: BTreeMap<String, SyscallSummary> = BTreeMap::new();
+    let mut unresolved = BTreeSet::new();
+
+    for syscall in seccomp.syscalls().iter().flatten() {
+        if translate_action(syscall.action(), syscall.errno_ret())? == default_action {
+            continue;
+        }
+        let action = RuleAction::new(syscall.action(), syscall.errno_ret());
+
+        for name in syscall.names() {
+            // A syscall libseccomp does not know on the native architecture
+            // is skipped for all of them.
+            if ScmpSyscall::from_name(name).is_err() || !exists_on(name, scmp_arch) {
+                unresolved.insert(name.clone());
+                continue;
+            }
+
+            match syscall.args() {
+                Some(args) => {
+                    for &arg in args {
+                        let rule = ConditionalRule { action, arg };
+                        let summary = syscalls.entry(name.clone()).or_default();
+                        if !summary.conditional.contains(&rule) {
+                            summary.conditional.push(rule);
+                        }
+                    }
+                }
+                None => {
+                    let summary = syscalls.entry(name.clone()).or_default();
+                    match summary.action {
+                        None => summary.action = Some(action),
+                        Some(existing) if existing == action => {}
+                        Some(_) => {
+                            if !summary.conflicting.contains(&action) {
+                                summary.conflicting.push(action);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    let allowed = syscalls
+        .iter()
+        .filter(|(_, summary)| {
+            summary.action.map_or(false, |action| {
+                matches!(
+                    action.action,
+                    LinuxSeccompAction::ScmpActAllow | LinuxSeccompAction::ScmpActLog
+                )
+            })
+        })
+        .map(|(name, _)| name.clone())
+        .collect();
+
+    Ok(ArchSummary {
+        arch,
+        allowed,
+        syscalls,
+        unresolved,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use oci_spec::runtime::{
+        LinuxSeccompArgBuilder, LinuxSeccompBuilder, LinuxSeccompOperator, LinuxSyscallBuilder,
+    };
+
+    use super::*;
+
+    fn rule(
+        names: &[&str],
+        action: LinuxSeccompAction,
+        errno: Option<u32>,
+        args: Option<Vec<LinuxSeccompArg>>,
+    ) -> Result<oci_spec::runtime::LinuxSyscall> {
+        let mut builder = LinuxSyscallBuilder::default()
+            .names(
+                names
+                    .iter()
+                    .map(|name| name.to_string())
+                    .collect::<Vec<_>>(),
+            )
+            .action(action);
+        if let Some(errno) = errno {
+            builder = builder.errno_ret(errno);
+        }
+        if let Some(args) = args {
+            builder = builder.args(args);
+        }
+        Ok(builder.build()?)
+    }
+
+    #[test]
+    fn test_summarize() -> Result<()> {
+        let arg = LinuxSeccompArgBuilder::default()
+            .index(0usize)
+            .value(0u64)
+            .op(LinuxSeccompOperator::ScmpCmpEq)
+            .build()?;
+        let seccomp = LinuxSeccompBuilder::default()
+            .default_action(LinuxSeccompAction::ScmpActErrno)
+            .architectures(vec![Arch::ScmpArchAarch64])
+            .syscalls(vec![
+                rule(
+                    &["read", "write", "open", "not_a_syscall"],
+                    LinuxSeccompAction::ScmpActAllow,
+                    None,
+                    None,
+                )?,
+                rule(&["read"], LinuxSeccompAction::ScmpActAllow, None, None)?,
+                // same as the default action
+                rule(&["mount"], LinuxSeccompAction::ScmpActErrno, None, None)?,
+                rule(
+                    &["getcwd"],
+                    LinuxSeccompAction::ScmpActErrno,
+                    Some(libc::ENOSYS as u32),
+                    None,
+                )?,
+                rule(&["getcwd"], LinuxSeccompAction::ScmpActAllow, None, None)?,
+                rule(
+                    &["personality"],
+                    LinuxSeccompAction::ScmpActAllow,
+                    None,
+                    Some(vec![arg]),
+                )?,
+                rule(
+                    &["personality"],
+                    LinuxSeccompAction::ScmpActAllow,
+                    None,
+                    Some(vec![arg]),
+                )?,
+            ])
+            .build()?;
+
+        let summary = summarize(&seccomp)?;
+        assert_eq!(
+            summary.default_action,
+            RuleAction {
+                action: LinuxSeccompAction::ScmpActErrno,
+                errno: Some(libc::EPERM as u32)
+            }
+        );
+        assert!(!summary.architectures.is_empty());
+
+        // open only exists on the older architectures
+        let aarch64 = summary
+            .architectures
+            .iter()
+            .find(|arch| arch.arch == Arch::ScmpArchAarch64)
+            .unwrap();
+        assert_eq!(aarch64.allowed, vec!["read", "write"]);
+        assert_eq!(
+            aarch64.unresolved.iter().collect::<Vec<_>>(),
+            vec!["not_a_syscall", "open"]
+        );
+        assert!(!aarch64.syscalls.contains_key("mount"));
+
+        let getcwd = &aarch64.syscalls["getcwd"];
+        assert_eq!(
+            getcwd.action,
+            Some(RuleAction {
+                action: LinuxSeccompAction::ScmpActErrno,
+                errno: Some(libc::ENOSYS as u32)
+            })
+        );
+        assert_eq!(
+            getcwd.conflicting,
+            vec![RuleAction::new(LinuxSeccompAction::ScmpActAllow, None)]
+        );
+
+        let personality = &aarch64.syscalls["personality"];
+        assert_eq!(personality.action, None);
+        assert_eq!(personality.conditional.len(), 1);
+        assert_eq!(personality.conditional[0].arg, arg);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_summarize_native() -> Result<()> {
+        let seccomp = LinuxSeccompBuilder::default()
+            .default_action(LinuxSeccompAction::ScmpActAllow)
+            .architectures(vec![Arch::ScmpArchNative, Arch::ScmpArchNative])
+            .syscalls(vec![rule(
+                &["getcwd"],
+                LinuxSeccompAction::ScmpActKill,
+                None,
+                None,
+            )?])
+            .build()?;
+
+        let summary = summarize(&seccomp)?;
+        assert_eq!(summary.architectures.len(), 1);
+        assert!(summary.architectures[0].allowed.is_empty());
+        assert_eq!(
+            summary.architectures[0].syscalls["getcwd"].action,
+            Some(RuleAction {
+                action: LinuxSeccompAction::ScmpActKillThread,
+                errno: None
+            })
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,413 @@
+//! Broker of the seccomp notify fd of a container, for seccomp agents which
+//! have to survive a restart. Usually youki sends the notify fd to the
+//! listener of `linux.seccomp.listenerPath` once and closes it, so an agent
+//! which dies takes the only fd with it. With the annotation
+//!
+//! - `org.youki.seccomp.broker`: `true` or `false`,
+//!
+//! youki keeps the notify fd in a broker process, which lives as long as
+//! the container uses the filter. An agent which reconnects receives the fd
+//! again through the `seccomp-broker.sock` socket of the container state
+//! directory, in the same message as from the listener: the container
+//! process state with the fd attached. The agent keeps the connection open
+//! as long as it supervises the container, closing it marks the agent as
+//! disconnected. While an agent is connected, further connections are
+//! closed without the fd.
+//!
+//! Notifications which the agent had not received yet are delivered to the
+//! agent which reconnects. A notification which the agent received but did
+//! not answer before it died stays pending, the kernel only fails it once
+//! the fd is closed. `youki state --verbose` shows the connection of the
+//! agent.
+use std::fs::{self, File};
+use std::io::{self, IoSlice, Read};
+use std::os::fd::{AsFd, AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+
+use nix::errno::Errno;
+use nix::fcntl::OFlag;
+use nix::poll::{poll, PollFd, PollFlags, PollTimeout};
+use nix::sys::signal::{self, Signal};
+use nix::sys::socket::{self, ControlMessage, MsgFlags, UnixAddr};
+use nix::unistd::{self, dup2, ForkResult, Pid};
+use oci_spec::runtime::{LinuxSeccompAction, Spec};
+use serde::{Deserialize, Serialize};
+
+use crate::utils::{self, SocketPath};
+
+pub const SECCOMP_BROKER_ANNOTATION: &str = "org.youki.seccomp.broker";
+pub const BROKER_SOCKET: &str = "seccomp-broker.sock";
+
+const BROKER_STATE: &str = "seccomp-broker.json";
+/// Name of the broker process, checked before it is stopped
+const BROKER_NAME: &str = "seccomp-broker";
+
+#[derive(Debug, thiserror::Error)]
+pub enum SeccompBrokerError {
+    #[error("invalid seccomp broker annotation {0:?}, expected true or false")]
+    InvalidValue(String),
+    #[error("a seccomp broker requires a seccomp profile with a notify action")]
+    NoNotify,
+    #[error("failed to bind seccomp broker socket {path:?}")]
+    Bind { path: PathBuf, source: io::Error },
+    #[error("failed to create pipe for seccomp broker")]
+    Pipe(#[source] nix::Error),
+    #[error("failed to fork seccomp broker")]
+    Fork(#[source] nix::Error),
+}
+
+type Result<T> = std::result::Result<T, SeccompBrokerError>;
+
+/// Connection of the seccomp agent
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum AgentConnection {
+    /// The fd was only sent to the listener of the spec, whose agent is not
+    /// tracked
+    Listener,
+    Connected,
+    Disconnected,
+}
+
+/// Seccomp agent of a container with a broker
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SeccompAgent {
+    /// Socket agents connect to for the notify fd
+    pub socket: PathBuf,
+    pub broker_pid: i32,
+    /// Whether the broker still runs, it exits with the container
+    #[serde(default)]
+    pub broker_running: bool,
+    pub connection: AgentConnection,
+    /// Number of agents the fd was delivered to through the socket
+    pub deliveries: u32,
+}
+
+/// Whether the spec requests a broker, checks that the seccomp profile of
+/// the spec has a notify action if it does
+pub fn requested(spec: &Spec) -> Result<bool> {
+    let requested = match spec
+        .annotations()
+        .as_ref()
+        .and_then(|a| a.get(SECCOMP_BROKER_ANNOTATION))
+    {
+        Some(value) => match value.trim() {
+            "true" => true,
+            "false" => false,
+            _ => return Err(SeccompBrokerError::InvalidValue(value.to_owned())),
+        },
+        None => return Ok(false),
+    };
+    let notify = spec
+        .linux()
+        .as_ref()
+        .and_then(|linux| linux.seccomp().as_ref())
+        .map_or(false, |seccomp| {
+            seccomp
+                .syscalls()
+                .iter()
+                .flatten()
+                .any(|syscall| syscall.action() == LinuxSeccompAction::ScmpActNotify)
+        });
+    if requested && !notify {
+        return Err(SeccompBrokerError::NoNotify);
+    }
+
+    Ok(requested)
+}
+
+/// Starts the broker of the notify fd of the container with the state
+/// directory `container_dir`. `message` is the container process state the
+/// listener received with the fd. The caller may close its fd afterwards.
+pub fn start(container_dir: &Path, notify_fd: RawFd, message: &[u8]) -> Result<Pid> {
+    let socket_path = container_dir.join(BROKER_SOCKET);
+    let listener = SocketPath::new(&socket_path)
+        .and_then(|short| UnixListener::bind(short.as_path()))
+        .map_err(|err| {
+            tracing::error!(?err, ?socket_path, "failed to bind seccomp broker socket");
+            SeccompBrokerError::Bind {
+                path: socket_path.clone(),
+                source: err,
+            }
+        })?;
+    let (ready, ready_sender) =
+        unistd::pipe2(OFlag::O_CLOEXEC).map_err(SeccompBrokerError::Pipe)?;
+
+    // SAFETY: the child only runs the broker loop and exits without
+    // returning to the caller
+    match unsafe { unistd::fork() }.map_err(SeccompBrokerError::Fork)? {
+        ForkResult::Parent { child } => {
+            drop(ready_sender);
+            // the broker closes its end once it recorded its state, so that
+            // the state can be read as soon as the container is created
+            let _ = File::from(ready).read(&mut [0u8; 1]);
+            Ok(child)
+        }
+        ForkResult::Child => {
+            drop(ready);
+            let agent = SeccompAgent {
+                socket: socket_path,
+                broker_pid: unistd::getpid().as_raw(),
+                broker_running: true,
+                connection: AgentConnection::Listener,
+                deliveries: 0,
+            };
+            // SAFETY: the fd of the caller is not used by anything else in
+            // the broker
+            let notify = unsafe { OwnedFd::from_raw_fd(notify_fd) };
+            let state_path = container_dir.join(BROKER_STATE);
+            let code = match broker(listener, notify, message, &state_path, agent, ready_sender) {
+                Ok(()) => 0,
+                Err(err) => {
+                    tracing::error!(?err, "seccomp broker failed");
+                    1
+                }
+            };
+            std::process::exit(code);
+        }
+    }
+}
+
+fn broker(
+    listener: UnixListener,
+    notify: OwnedFd,
+    message: &[u8],
+    state_path: &Path,
+    mut agent: SeccompAgent,
+    ready_sender: OwnedFd,
+) -> io::Result<()> {
+    let name = std::ffi::CString::new(BROKER_NAME).expect("name without nul");
+    if unsafe { libc::prctl(libc::PR_SET_NAME, name.as_ptr()) } < 0 {
+        tracing::warn!("failed to name seccomp broker, delete does not stop it");
+    }
+    unistd::setsid()?;
+    save(state_path, &agent)?;
+    drop(ready_sender);
+
+    // Do not keep the stdio or any other file of the caller open, callers
+    // may wait for them to be closed.
+    let null = File::options().read(true).write(true).open("/dev/null")?;
+    for stdio in 0..3 {
+        dup2(null.as_raw_fd(), stdio)?;
+    }
+    drop(null);
+    utils::close_other_fds(&[listener.as_raw_fd(), notify.as_raw_fd()])?;
+
+    let mut connection: Option<UnixStream> = None;
+    let mut buf = [0u8; 256];
+    loop {
+        let mut fds = vec![
+            // the kernel reports POLLHUP once no process uses the filter
+            PollFd::new(notify.as_fd(), PollFlags::empty()),
+            PollFd::new(listener.as_fd(), PollFlags::POLLIN),
+        ];
+        if let Some(connection) = &connection {
+            fds.push(PollFd::new(connection.as_fd(), PollFlags::POLLIN));
+        }
+        match poll(&mut fds, PollTimeout::NONE) {
+            Err(Errno::EINTR) => continue,
+            result => result?,
+        };
+        let ready: Vec<bool> = fds
+            .iter()
+            .map(|fd| fd.revents().map_or(false, |revents| !revents.is_empty()))
+            .collect();
+        drop(fds);
+
+        if ready[0] {
+            let _ = fs::remove_file(&agent.socket);
+            return Ok(());
+        }
+        if ready[1] {
+            let (stream, _) = listener.accept()?;
+            if connection.is_some() {
+                tracing::warn!("refusing seccomp agent while another one is connected");
+            } else if let Err(err) = send_fd(&stream, message, notify.as_raw_fd()) {
+                tracing::warn!(?err, "failed to deliver notify fd to seccomp agent");
+            } else {
+                connection = Some(stream);
+                agent.connection = AgentConnection::Connected;
+                agent.deliveries += 1;
+                save(state_path, &agent)?;
+            }
+        }
+        if ready.get(2) == Some(&true) {
+            // anything the agent sends is ignored
+            let read = connection.as_mut().map(|stream| stream.read(&mut buf));
+            let closed = matches!(read, Some(Ok(0) | Err(_)));
+            if closed {
+                connection = None;
+                agent.connection = AgentConnection::Disconnected;
+                save(state_path, &agent)?;
+            }
+        }
+    }
+}
+
+fn send_fd(stream: &UnixStream, message: &[u8], fd: RawFd) -> nix::Result<usize> {
+    let iov = [IoSlice::new(message)];
+    let fds = [fd];
+    socket::sendmsg::<UnixAddr>(
+        stream.as_raw_fd(),
+        &iov,
+        &[ControlMessage::ScmRights(&fds)],
+        MsgFlags::empty(),
+        None,
+    )
+}
+
+fn save(path: &Path, agent: &SeccompAgent) -> io::Result<()> {
+    let tmp = path.with_extension("json.tmp");
+    fs::write(&tmp, serde_json::to_vec(agent)?)?;
+    fs::rename(tmp, path)
+}
+
+fn is_broker(pid: i32) -> bool {
+    fs::read_to_string(format!("/proc/{pid}/comm"))
+        .map_or(false, |comm| comm.trim_end() == BROKER_NAME)
+}
+
+/// The seccomp agent of the container with the state directory
+/// `container_dir`, none if the container has no broker
+pub fn agent(container_dir: &Path) -> Option<SeccompAgent> {
+    let state_path = container_dir.join(BROKER_STATE);
+    let content = fs::read(&state_path).ok()?;
+    match serde_json::from_slice::<SeccompAgent>(&content) {
+        Ok(mut agent) => {
+            agent.broker_running = is_broker(agent.broker_pid);
+            Some(agent)
+        }
+        Err(err) => {
+            tracing::warn!(?err, ?state_path, "failed to parse seccomp broker state");
+            None
+        }
+    }
+}
+
+/// Stops the broker of a deleted container. A broker exits by itself once
+/// the container processes are gone, but kernels before 5.8 do not tell it.
+pub fn stop(container_dir: &Path) {
+    if let Some(agent) = agent(container_dir).filter(|agent| agent.broker_running) {
+        if let Err(err) = signal::kill(Pid::from_raw(agent.broker_pid), Signal::SIGKILL) {
+            tracing::warn!(
+                ?err,
+                pid = agent.broker_pid,
+                "failed to stop seccomp broker"
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::io::Write;
+    use std::thread;
+    use std::time::Duration;
+
+    use anyhow::Result;
+    use nix::sys::wait::{waitpid, WaitStatus};
+    use oci_spec::runtime::{LinuxBuilder, LinuxSeccompBuilder, LinuxSyscallBuilder, SpecBuilder};
+
+    use super::*;
+    use crate::console_tee::receive_master;
+
+    fn spec(annotation: Option<&str>, action: LinuxSeccompAction) -> Result<Spec> {
+        let annotations: HashMap<_, _> = annotation
+            .map(|value| (SECCOMP_BROKER_ANNOTATION.to_owned(), value.to_owned()))
+            .into_iter()
+            .collect();
+        Ok(SpecBuilder::default()
+            .annotations(annotations)
+            .linux(
+                LinuxBuilder::default()
+                    .seccomp(
+                        LinuxSeccompBuilder::default()
+                            .syscalls(vec![LinuxSyscallBuilder::default()
+                                .names(vec!["mkdir".to_owned()])
+                                .action(action)
+                                .build()?])
+                            .build()?,
+                    )
+                    .build()?,
+            )
+            .build()?)
+    }
+
+    fn wait_for(dir: &Path, connection: AgentConnection) -> SeccompAgent {
+        for _ in 0..500 {
+            match agent(dir) {
+                Some(agent) if agent.connection == connection => return agent,
+                _ => thread::sleep(Duration::from_millis(10)),
+            }
+        }
+        panic!("seccomp agent did not become {connection:?}");
+    }
+
+    #[test]
+    fn test_requested() -> Result<()> {
+        let notify = LinuxSeccompAction::ScmpActNotify;
+        assert!(!requested(&spec(None, notify)?)?);
+        assert!(!requested(&spec(Some("false"), notify)?)?);
+        assert!(requested(&spec(Some("true"), notify)?)?);
+        assert!(matches!(
+            requested(&spec(Some("yes"), notify)?),
+            Err(SeccompBrokerError::InvalidValue(_))
+        ));
+        assert!(matches!(
+            requested(&spec(Some("true"), LinuxSeccompAction::ScmpActErrno)?),
+            Err(SeccompBrokerError::NoNotify)
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_broker() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        // the read end of a pipe stands in for the notify fd, it hangs up
+        // once the write end is closed like the fd once the container exited
+        let (notify, container) = unistd::pipe()?;
+        let pid = start(tmp.path(), notify.as_raw_fd(), b"{}")?;
+        drop(notify);
+
+        let state = agent(tmp.path()).unwrap();
+        assert_eq!(state.connection, AgentConnection::Listener);
+        assert!(state.broker_running);
+        assert_eq!(state.broker_pid, pid.as_raw());
+
+        let socket = tmp.path().join(BROKER_SOCKET);
+        let first = UnixStream::connect(&socket)?;
+        let fd = receive_master(&OwnedFd::from(first.try_clone()?))?;
+        let state = wait_for(tmp.path(), AgentConnection::Connected);
+        assert_eq!(state.deliveries, 1);
+
+        // the fd an agent receives is the notify fd of the broker
+        let mut container = File::from(container);
+        container.write_all(b"x")?;
+        let mut buf = [0u8; 1];
+        File::from(fd).read_exact(&mut buf)?;
+        assert_eq!(&buf, b"x");
+
+        // only one agent at a time
+        let mut refused = UnixStream::connect(&socket)?;
+        assert_eq!(refused.read(&mut buf)?, 0);
+
+        drop(first);
+        wait_for(tmp.path(), AgentConnection::Disconnected);
+        let second = OwnedFd::from(UnixStream::connect(&socket)?);
+        receive_master(&second)?;
+        assert_eq!(
+            wait_for(tmp.path(), AgentConnection::Connected).deliveries,
+            2
+        );
+
+        drop(container);
+        drop(second);
+        assert_eq!(waitpid(pid, None)?, WaitStatus::Exited(pid, 0));
+        assert!(!socket.exists());
+        assert!(!agent(tmp.path()).unwrap().broker_running);
+        Ok(())
+    }
+}
@@ -0,0 +1,345 @@
+//! Seccomp profiles shared between bundles. Instead of carrying the profile
+//! in `linux.seccomp` of every spec, a spec may reference a JSON file holding
+//! the `linux.seccomp` object with the annotation
+//!
+//! - `org.youki.seccomp.profile`: `file://` URL of the profile, e.g.
+//!   `file:///etc/youki/seccomp/default.json`.
+//!
+//! The profile is loaded and validated when the container is created, and
+//! again for every process executed in it, and replaces `linux.seccomp` of
+//! the spec. A spec must not have both. Loaded profiles are cached below the
+//! youki root: a profile file whose modification time did not change is not
+//! read again, and one which was touched but has the same content is not
+//! parsed and validated again.
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::fs;
+use std::io;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+use oci_spec::runtime::{LinuxSeccomp, Spec};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+pub const SECCOMP_PROFILE_ANNOTATION: &str = "org.youki.seccomp.profile";
+
+const FILE_SCHEME: &str = "file://";
+/// Directory of the cached profiles in the root path. Container ids can't take
+/// its name.
+pub const CACHE_DIR: &str = ".seccomp-profiles";
+
+#[derive(Debug, thiserror::Error)]
+pub enum SeccompProfileError {
+    #[error("invalid seccomp profile reference {0:?}, expected a file:// URL of an absolute path")]
+    InvalidReference(String),
+    #[error("the spec has both linux.seccomp and a seccomp profile annotation")]
+    Conflict,
+    #[error("failed to read seccomp profile {path:?}")]
+    Read { path: PathBuf, source: io::Error },
+    #[error("failed to parse seccomp profile {path:?}")]
+    Parse {
+        path: PathBuf,
+        source: serde_json::Error,
+    },
+    #[cfg(feature = "libseccomp")]
+    #[error("invalid seccomp profile {path:?}")]
+    Invalid {
+        path: PathBuf,
+        source: crate::seccomp::SeccompError,
+    },
+}
+
+type Result<T> = std::result::Result<T, SeccompProfileError>;
+
+/// Path of the profile referenced by the annotation, none without one
+pub fn profile_path(annotations: Option<&HashMap<String, String>>) -> Result<Option<PathBuf>> {
+    let reference = match annotations.and_then(|a| a.get(SECCOMP_PROFILE_ANNOTATION)) {
+        Some(reference) => reference,
+        None => return Ok(None),
+    };
+
+    let path = reference
+        .strip_prefix(FILE_SCHEME)
+        .map(Path::new)
+        .filter(|path| path.is_absolute())
+        .ok_or_else(|| SeccompProfileError::InvalidReference(reference.to_owned()))?;
+    Ok(Some(path.to_path_buf()))
+}
+
+/// Cache of validated profiles, an entry per profile file
+#[derive(Debug, Clone)]
+pub struct ProfileCache {
+    dir: PathBuf,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CacheEntry {
+    path: PathBuf,
+    mtime: i64,
+    mtime_nsec: i64,
+    size: u64,
+    digest: String,
+    profile: LinuxSeccomp,
+}
+
+impl ProfileCache {
+    pub fn new<P: Into<PathBuf>>(dir: P) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    /// Cache kept in the root directory of the containers
+    pub fn in_root(root_path: &Path) -> Self {
+        Self::new(root_path.join(CACHE_DIR))
+    }
+
+    /// Loads the profile from the file, or from the cache if the file did not
+    /// change since it was cached
+    pub fn load(&self, path: &Path) -> Result<LinuxSeccomp> {
+        let read_err = |source| SeccompProfileError::Read {
+            path: path.to_path_buf(),
+            source,
+        };
+        let metadata = fs::metadata(path).map_err(read_err)?;
+        let entry_path = self.entry_path(path);
+        let cached = self.read_entry(&entry_path, path);
+
+        if let Some(entry) = &cached {
+            if entry.mtime == metadata.mtime()
+                && entry.mtime_nsec == metadata.mtime_nsec()
+                && entry.size == metadata.size()
+            {
+                tracing::debug!(?path, "using cached seccomp profile");
+                return Ok(entry.profile.clone());
+            }
+        }
+
+        let content = fs::read(path).map_err(read_err)?;
+        let digest = hex_digest(&content);
+        let profile = match cached {
+            Some(entry) if entry.digest == digest => {
+                tracing::debug!(?path, "seccomp profile was touched but did not change");
+                entry.profile
+            }
+            _ => {
+                let profile = serde_json::from_slice(&content).map_err(|source| {
+                    SeccompProfileError::Parse {
+                        path: path.to_path_buf(),
+                        source,
+                    }
+                })?;
+                validate(path, &profile)?;
+                profile
+            }
+        };
+
+        let entry = CacheEntry {
+            path: path.to_path_buf(),
+            mtime: metadata.mtime(),
+            mtime_nsec: metadata.mtime_nsec(),
+            size: metadata.size(),
+            digest,
+            profile,
+        };
+        // the cache only saves work, the container does not depend on it
+        if let Err(err) = self.write_entry(&entry_path, &entry) {
+            tracing::warn!(?err, ?path, "failed to cache seccomp profile");
+        }
+
+        Ok(entry.profile)
+    }
+
+    fn entry_path(&self, path: &Path) -> PathBuf {
+        let name = hex_digest(path.as_os_str().to_string_lossy().as_bytes());
+        self.dir.join(format!("{name}.json"))
+    }
+
+    fn read_entry(&self, entry_path: &Path, path: &Path) -> Option<CacheEntry> {
+        let content = fs::read(entry_path).ok()?;
+        match serde_json::from_slice::<CacheEntry>(&content) {
+            Ok(entry) if entry.path == path => Some(entry),
+            Ok(_) => None,
+            Err(err) => {
+                tracing::warn!(?err, ?entry_path, "ignoring corrupt seccomp profile cache");
+                None
+            }
+        }
+    }
+
+    fn write_entry(&self, entry_path: &Path, entry: &CacheEntry) -> io::Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        // replaced at once, so that concurrent creates never read half of it
+        let tmp = entry_path.with_extension(format!("tmp.{}", std::process::id()));
+        fs::write(&tmp, serde_json::to_vec(entry)?)?;
+        fs::rename(&tmp, entry_path)
+    }
+}
+
+#[cfg(feature = "libseccomp")]
+fn validate(path: &Path, profile: &LinuxSeccomp) -> Result<()> {
+    crate::seccomp::check_seccomp(profile).map_err(|source| SeccompProfileError::Invalid {
+        path: path.to_path_buf(),
+        source,
+    })
+}
+
+#[cfg(not(feature = "libseccomp"))]
+fn validate(_path: &Path, _profile: &LinuxSeccomp) -> Result<()> {
+    Ok(())
+}
+
+fn hex_digest(content: &[u8]) -> String {
+    Sha256::digest(content)
+        .iter()
+        .fold(String::new(), |mut hex, byte| {
+            let _ = write!(hex, "{byte:02x}");
+            hex
+        })
+}
+
+/// Replaces `linux.seccomp` of the spec with the profile referenced by its
+/// annotation. Without the annotation this is a no-op.
+pub fn apply(spec: &mut Spec, cache: &ProfileCache) -> Result<()> {
+    let path = match profile_path(spec.annotations().as_ref())? {
+        Some(path) => path,
+        None => return Ok(()),
+    };
+    let mut linux = spec.linux().clone().unwrap_or_default();
+    if linux.seccomp().is_some() {
+        return Err(SeccompProfileError::Conflict);
+    }
+
+    tracing::debug!(?path, "loading seccomp profile");
+    linux.set_seccomp(Some(cache.load(&path)?));
+    spec.set_linux(Some(linux));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use oci_spec::runtime::{LinuxBuilder, LinuxSeccompAction, LinuxSeccompBuilder, SpecBuilder};
+
+    use super::*;
+
+    fn spec_with_profile(reference: &str) -> Spec {
+        SpecBuilder::default()
+            .annotations(HashMap::from([(
+                SECCOMP_PROFILE_ANNOTATION.to_owned(),
+                reference.to_owned(),
+            )]))
+            .build()
+            .unwrap()
+    }
+
+    fn write_profile(path: &Path, default_action: LinuxSeccompAction) -> Result<()> {
+        let profile = LinuxSeccompBuilder::default()
+            .default_action(default_action)
+            .build()?;
+        fs::write(path, serde_json::to_vec(&profile)?)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_profile_path() {
+        assert_eq!(profile_path(None).unwrap(), None);
+        let annotations = HashMap::from([(
+            SECCOMP_PROFILE_ANNOTATION.to_owned(),
+            "file:///etc/youki/default.json".to_owned(),
+        )]);
+        assert_eq!(
+            profile_path(Some(&annotations)).unwrap(),
+            Some(PathBuf::from("/etc/youki/default.json"))
+        );
+
+        for invalid in [
+            "/etc/youki/default.json",
+            "file://default.json",
+            "http://a/b",
+        ] {
+            let annotations =
+                HashMap::from([(SECCOMP_PROFILE_ANNOTATION.to_owned(), invalid.to_owned())]);
+            assert!(profile_path(Some(&annotations)).is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn test_apply_profile() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let profile_file = tmp.path().join("profile.json");
+        write_profile(&profile_file, LinuxSeccompAction::ScmpActErrno)?;
+        let cache = ProfileCache::new(tmp.path().join("cache"));
+
+        let mut spec = spec_with_profile(&format!("file://{}", profile_file.display()));
+        apply(&mut spec, &cache)?;
+        let seccomp = spec.linux().as_ref().and_then(|l| l.seccomp().clone());
+        assert_eq!(
+            seccomp.map(|s| s.default_action()),
+            Some(LinuxSeccompAction::ScmpActErrno)
+        );
+
+        // a profile in the spec and one referenced by annotation conflict
+        let mut spec = spec_with_profile(&format!("file://{}", profile_file.display()));
+        spec.set_linux(Some(
+            LinuxBuilder::default()
+                .seccomp(
+                    LinuxSeccompBuilder::default()
+                        .default_action(LinuxSeccompAction::ScmpActAllow)
+                        .build()?,
+                )
+                .build()?,
+        ));
+        assert!(matches!(
+            apply(&mut spec, &cache),
+            Err(SeccompProfileError::Conflict)
+        ));
+
+        let mut spec = spec_with_profile("file:///does/not/exist.json");
+        assert!(matches!(
+            apply(&mut spec, &cache),
+            Err(SeccompProfileError::Read { .. })
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_profile_cache() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let profile_file = tmp.path().join("profile.json");
+        write_profile(&profile_file, LinuxSeccompAction::ScmpActErrno)?;
+        let cache = ProfileCache::new(tmp.path().join("cache"));
+
+        let profile = cache.load(&profile_file)?;
+        assert_eq!(profile.default_action(), LinuxSeccompAction::ScmpActErrno);
+        let entry_path = cache.entry_path(&profile_file);
+        assert!(entry_path.exists());
+
+        // an unchanged file is taken from the cache without reading it
+        let mut entry = cache.read_entry(&entry_path, &profile_file).unwrap();
+        entry.profile = LinuxSeccompBuilder::default()
+            .default_action(LinuxSeccompAction::ScmpActKill)
+            .build()?;
+        cache.write_entry(&entry_path, &entry)?;
+        assert_eq!(
+            cache.load(&profile_file)?.default_action(),
+            LinuxSeccompAction::ScmpActKill
+        );
+
+        // a changed file is loaded again, its size differs as well so that the
+        // test does not depend on the resolution of the modification time
+        fs::write(&profile_file, b"{\"defaultAction\": \"SCMP_ACT_LOG\"}\n")?;
+        assert_eq!(
+            cache.load(&profile_file)?.default_action(),
+            LinuxSeccompAction::ScmpActLog
+        );
+
+        fs::write(&profile_file, b"not json")?;
+        assert!(matches!(
+            cache.load(&profile_file),
+            Err(SeccompProfileError::Parse { .. })
+        ));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,364 @@
+//! Swap provisioned for a single container, so that containers with a swap
+//! limit can use swap on hosts without any swap configured.
+//!
+//! The swap is requested with annotations in the spec:
+//!
+//! - `org.youki.swap`: `file` creates a swap file, `zram` a compressed swap
+//!   device in memory.
+//! - `org.youki.swap.dir`: directory of the swap file, `/var/lib/youki/swap`
+//!   by default. It has to be on a filesystem which supports swap files, so
+//!   not on a tmpfs.
+//!
+//! The swap is sized to the swap the spec allows the container on top of its
+//! memory limit, i.e. `memory.swap` minus `memory.limit`, which is the value
+//! youki sets as `memory.swap.max` of the container cgroup. Swap is a host
+//! wide resource, the container is held to the size of its swap by the
+//! cgroup limit. The swap is disabled and removed when the container is
+//! deleted.
+use std::ffi::CString;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use nix::errno::Errno;
+use nix::fcntl::{fallocate, FallocateFlags};
+use nix::unistd::{sysconf, SysconfVar};
+use oci_spec::runtime::Spec;
+use serde::{Deserialize, Serialize};
+
+pub const SWAP_ANNOTATION: &str = "org.youki.swap";
+pub const SWAP_DIR_ANNOTATION: &str = "org.youki.swap.dir";
+
+const DEFAULT_SWAP_DIR: &str = "/var/lib/youki/swap";
+const ZRAM_CONTROL: &str = "/sys/class/zram-control";
+// the kernel refuses swap areas with fewer pages
+const MIN_SWAP_PAGES: u64 = 10;
+const SWAP_MAGIC: &[u8] = b"SWAPSPACE2";
+const SWAP_VERSION: u32 = 1;
+const SWAP_INFO_OFFSET: usize = 1024;
+
+#[derive(Debug, thiserror::Error)]
+pub enum SwapError {
+    #[error("invalid swap backend {0}, expected file or zram")]
+    InvalidBackend(String),
+    #[error("swap requires a memory.swap limit above the memory limit")]
+    NoSwapLimit,
+    #[error("swap of {0} bytes is too small")]
+    TooSmall(u64),
+    #[error("failed to create swap file {path:?}")]
+    File { path: PathBuf, source: io::Error },
+    #[error("failed to set up zram device {path:?}")]
+    Zram { path: PathBuf, source: io::Error },
+    #[error("failed to enable swap on {path:?}")]
+    SwapOn { path: PathBuf, source: Errno },
+    #[error("failed to disable swap on {path:?}")]
+    SwapOff { path: PathBuf, source: Errno },
+}
+
+type Result<T> = std::result::Result<T, SwapError>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SwapBackend {
+    File,
+    Zram,
+}
+
+impl FromStr for SwapBackend {
+    type Err = SwapError;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "file" => Ok(Self::File),
+            "zram" => Ok(Self::Zram),
+            _ => Err(SwapError::InvalidBackend(s.to_owned())),
+        }
+    }
+}
+
+/// Swap provisioned for a container, saved with the container to remove it
+/// when the container is deleted
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase", tag = "backend")]
+pub enum ProvisionedSwap {
+    File { path: PathBuf },
+    Zram { device: u32 },
+}
+
+/// Provisions and enables the swap the annotations of the spec request.
+/// Returns None if they do not request any.
+pub fn provision(spec: &Spec, container_id: &str) -> Result<Option<ProvisionedSwap>> {
+    let annotations = spec.annotations().clone().unwrap_or_default();
+    let backend = match annotations.get(SWAP_ANNOTATION) {
+        Some(backend) => backend.parse::<SwapBackend>()?,
+        None => return Ok(None),
+    };
+    let page_size = page_size();
+    let size = swap_size(spec, page_size)?;
+
+    let swap = match backend {
+        SwapBackend::File => {
+            let dir = annotations
+                .get(SWAP_DIR_ANNOTATION)
+                .map_or_else(|| PathBuf::from(DEFAULT_SWAP_DIR), PathBuf::from);
+            let path = dir.join(format!("{container_id}.swap"));
+            create_swap_file(&path, size, page_size)?;
+            ProvisionedSwap::File { path }
+        }
+        SwapBackend::Zram => {
+            let device = create_zram_device(size, page_size)?;
+            ProvisionedSwap::Zram { device }
+        }
+    };
+
+    let path = swap.path();
+    if let Err(err) = swap_on(&path) {
+        swap.remove();
+        return Err(err);
+    }
+
+    tracing::debug!(?swap, size, "provisioned swap for container");
+    Ok(Some(swap))
+}
+
+impl ProvisionedSwap {
+    /// Path of the swap file or device
+    pub fn path(&self) -> PathBuf {
+        match self {
+            Self::File { path } => path.clone(),
+            Self::Zram { device } => PathBuf::from(format!("/dev/zram{device}")),
+        }
+    }
+
+    /// Disables the swap and removes it
+    pub fn release(&self) -> Result<()> {
+        let path = self.path();
+        // EINVAL means the swap is not enabled, e.g. after a failed release
+        let result = unsafe { libc::swapoff(c_path(&path).as_ptr()) };
+        match Errno::result(result) {
+            Ok(_) | Err(Errno::EINVAL) | Err(Errno::ENOENT) => {}
+            Err(err) => return Err(SwapError::SwapOff { path, source: err }),
+        }
+
+        self.remove();
+        Ok(())
+    }
+
+    fn remove(&self) {
+        let result = match self {
+            Self::File { path } => match fs::remove_file(path) {
+                Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+                result => result,
+            },
+            Self::Zram { device } => fs::write(
+                Path::new(ZRAM_CONTROL).join("hot_remove"),
+                device.to_string(),
+            ),
+        };
+        if let Err(err) = result {
+            tracing::warn!(?err, swap = ?self, "failed to remove swap");
+        }
+    }
+}
+
+fn page_size() -> u64 {
+    sysconf(SysconfVar::PAGE_SIZE)
+        .ok()
+        .flatten()
+        .unwrap_or(4096) as u64
+}
+
+/// Returns the swap the spec allows on top of the memory limit, rounded down
+/// to whole pages
+fn swap_size(spec: &Spec, page_size: u64) -> Result<u64> {
+    let memory = spec
+        .linux()
+        .as_ref()
+        .and_then(|linux| linux.resources().as_ref())
+        .and_then(|resources| resources.memory().as_ref())
+        .ok_or(SwapError::NoSwapLimit)?;
+    let size = match (memory.limit(), memory.swap()) {
+        (Some(limit), Some(swap)) if limit > 0 && swap > limit => (swap - limit) as u64,
+        _ => return Err(SwapError::NoSwapLimit),
+    };
+
+    let size = size - size % page_size;
+    if size < MIN_SWAP_PAGES * page_size {
+        return Err(SwapError::TooSmall(size));
+    }
+
+    Ok(size)
+}
+
+/// Returns the first page of a swap area, as mkswap writes it
+fn swap_header(size: u64, page_size: u64) -> Vec<u8> {
+    let mut header = vec![0; page_size as usize];
+    let last_page = (size / page_size - 1) as u32;
+    header[SWAP_INFO_OFFSET..SWAP_INFO_OFFSET + 4].copy_from_slice(&SWAP_VERSION.to_ne_bytes());
+    header[SWAP_INFO_OFFSET + 4..SWAP_INFO_OFFSET + 8].copy_from_slice(&last_page.to_ne_bytes());
+    let magic_offset = header.len() - SWAP_MAGIC.len();
+    header[magic_offset..].copy_from_slice(SWAP_MAGIC);
+    header
+}
+
+fn create_swap_file(path: &Path, size: u64, page_size: u64) -> Result<()> {
+    let wrap = |err: io::Error| SwapError::File {
+        path: path.to_owned(),
+        source: err,
+    };
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(wrap)?;
+    }
+
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(path)
+        .map_err(wrap)?;
+    // swap files must not have holes, so the blocks are allocated up front
+    let written = fallocate(file.as_raw_fd(), FallocateFlags::empty(), 0, size as i64)
+        .map_err(io::Error::from)
+        .and_then(|_| file.write_all(&swap_header(size, page_size)))
+        .and_then(|_| file.sync_all());
+    if let Err(err) = written {
+        let _ = fs::remove_file(path);
+        return Err(wrap(err));
+    }
+
+    Ok(())
+}
+
+fn create_zram_device(size: u64, page_size: u64) -> Result<u32> {
+    let hot_add = Path::new(ZRAM_CONTROL).join("hot_add");
+    let device = fs::read_to_string(&hot_add)
+        .and_then(|device| {
+            device
+                .trim()
+                .parse::<u32>()
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+        })
+        .map_err(|err| SwapError::Zram {
+            path: hot_add,
+            source: err,
+        })?;
+
+    let disksize = PathBuf::from(format!("/sys/block/zram{device}/disksize"));
+    let dev = PathBuf::from(format!("/dev/zram{device}"));
+    let result = fs::write(&disksize, size.to_string())
+        .map_err(|err| (disksize, err))
+        .and_then(|_| {
+            OpenOptions::new()
+                .write(true)
+                .open(&dev)
+                .and_then(|mut file| file.write_all(&swap_header(size, page_size)))
+                .map_err(|err| (dev, err))
+        });
+    if let Err((path, err)) = result {
+        ProvisionedSwap::Zram { device }.remove();
+        return Err(SwapError::Zram { path, source: err });
+    }
+
+    Ok(device)
+}
+
+fn c_path(path: &Path) -> CString {
+    // paths are built by youki and from annotations, which cannot contain
+    // nul bytes in valid JSON strings of a spec youki accepts
+    CString::new(path.as_os_str().as_bytes()).unwrap_or_default()
+}
+
+fn swap_on(path: &Path) -> Result<()> {
+    let result = unsafe { libc::swapon(c_path(path).as_ptr(), 0) };
+    Errno::result(result).map_err(|err| {
+        tracing::error!(?err, ?path, "failed to enable swap");
+        SwapError::SwapOn {
+            path: path.to_owned(),
+            source: err,
+        }
+    })?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use oci_spec::runtime::{LinuxBuilder, LinuxMemoryBuilder, LinuxResourcesBuilder, SpecBuilder};
+
+    use super::*;
+
+    fn spec_with_memory(limit: i64, swap: i64) -> Result<Spec> {
+        Ok(SpecBuilder::default()
+            .linux(
+                LinuxBuilder::default()
+                    .resources(
+                        LinuxResourcesBuilder::default()
+                            .memory(
+                                LinuxMemoryBuilder::default()
+                                    .limit(limit)
+                                    .swap(swap)
+                                    .build()?,
+                            )
+                            .build()?,
+                    )
+                    .build()?,
+            )
+            .build()?)
+    }
+
+    #[test]
+    fn test_parse_backend() {
+        assert_eq!("file".parse::<SwapBackend>().unwrap(), SwapBackend::File);
+        assert_eq!("zram".parse::<SwapBackend>().unwrap(), SwapBackend::Zram);
+        assert!(matches!(
+            "disk".parse::<SwapBackend>(),
+            Err(SwapError::InvalidBackend(_))
+        ));
+    }
+
+    #[test]
+    fn test_swap_size() -> Result<()> {
+        let spec = spec_with_memory(1 << 20, (1 << 20) + (1 << 16) + 100)?;
+        assert_eq!(swap_size(&spec, 4096)?, 1 << 16);
+
+        for (limit, swap) in [(1 << 20, 1 << 20), (1 << 20, -1), (-1, 1 << 20)] {
+            assert!(matches!(
+                swap_size(&spec_with_memory(limit, swap)?, 4096),
+                Err(SwapError::NoSwapLimit)
+            ));
+        }
+        assert!(matches!(
+            swap_size(&spec_with_memory(1 << 20, (1 << 20) + 8192)?, 4096),
+            Err(SwapError::TooSmall(8192))
+        ));
+        assert!(matches!(
+            swap_size(&Spec::default(), 4096),
+            Err(SwapError::NoSwapLimit)
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_swap_header() {
+        let header = swap_header(16 * 4096, 4096);
+        assert_eq!(header.len(), 4096);
+        assert_eq!(&header[4096 - 10..], b"SWAPSPACE2");
+        assert_eq!(&header[1024..1028], &1u32.to_ne_bytes());
+        assert_eq!(&header[1028..1032], &15u32.to_ne_bytes());
+        assert!(header[..1024].iter().all(|byte| *byte == 0));
+    }
+
+    #[test]
+    fn test_no_swap_requested() -> Result<()> {
+        assert_eq!(
+            provision(&spec_with_memory(1 << 20, 2 << 20)?, "test")?,
+            None
+        );
+        Ok(())
+    }
+}
@@ -21,6 +21,7 @@ use nix::unistd::{chown, chroot, fchdir, pivot_root, sethostname, Gid, Uid};
 use oci_spec::runtime::PosixRlimit;
 
 use super::{Result, Syscall, SyscallError};
+use crate::kernel::{self, Feature};
 use crate::{capabilities, utils};
 
 // Flags used in mount_setattr(2).
@@ -493,6 +494,12 @@ impl Syscall for LinuxSyscall {
 
     #[tracing::instrument(skip(self))]
     fn close_range(&self, preserve_fds: i32) -> Result<()> {
+        // close_range was introduced in kernel 5.9 and CLOSEEXEC was introduced in
+        // kernel 5.11. If the kernel is older we emulate close_range in userspace.
+        if !kernel::has(Feature::CloseRange) {
+            return Self::emulate_close_range(preserve_fds);
+        }
+
         match unsafe {
             libc::syscall(
                 libc::SYS_close_range,
@@ -502,16 +509,11 @@ impl Syscall for LinuxSyscall {
             )
         } {
             0 => Ok(()),
-            -1 => {
-                match nix::errno::Errno::last() {
-                    nix::errno::Errno::ENOSYS | nix::errno::Errno::EINVAL => {
-                        // close_range was introduced in kernel 5.9 and CLOSEEXEC was introduced in
-                        // kernel 5.11. If the kernel is older we emulate close_range in userspace.
-                        Self::emulate_close_range(preserve_fds)
-                    }
-                    e => Err(SyscallError::Nix(e)),
-                }
-            }
+            -1 => match nix::errno::Errno::last() {
+                // a seccomp filter loaded after the probe may still block it
+                nix::errno::Errno::ENOSYS => Self::emulate_close_range(preserve_fds),
+                e => Err(SyscallError::Nix(e)),
+            },
             _ => Err(SyscallError::Nix(nix::errno::Errno::UnknownErrno)),
         }?;
 
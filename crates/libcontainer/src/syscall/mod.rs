@@ -6,6 +6,7 @@ pub mod linux;
 #[allow(clippy::module_inception)]
 pub mod syscall;
 pub mod test;
+pub mod trace;
 
 pub use syscall::Syscall;
 #[derive(Debug, thiserror::Error)]
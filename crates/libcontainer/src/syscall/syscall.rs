@@ -16,6 +16,7 @@ use oci_spec::runtime::PosixRlimit;
 
 use crate::syscall::linux::{LinuxSyscall, MountAttr};
 use crate::syscall::test::TestHelperSyscall;
+use crate::syscall::trace::{SetupTrace, TracedSyscall};
 use crate::syscall::Result;
 
 /// This specifies various kernel/other functionalities required for
@@ -76,7 +77,10 @@ impl Default for SyscallType {
 impl SyscallType {
     pub fn create_syscall(&self) -> Box<dyn Syscall> {
         match self {
-            SyscallType::Linux => Box::new(LinuxSyscall),
+            SyscallType::Linux => match SetupTrace::get() {
+                Some(trace) => Box::new(TracedSyscall::new(Box::new(LinuxSyscall), trace)),
+                None => Box::new(LinuxSyscall),
+            },
             SyscallType::Test => Box::<TestHelperSyscall>::default(),
         }
     }
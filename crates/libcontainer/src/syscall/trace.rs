@@ -0,0 +1,402 @@
+//! Trace of the syscall wrappers youki calls while it sets up a container,
+//! an strace of its own setup without ptrace, to debug failures which only
+//! happen on some hosts.
+//!
+//! Once the trace is enabled for the process, every [`Syscall`] created with
+//! [`SyscallType::Linux`](super::syscall::SyscallType) records its calls, with
+//! their arguments, result and duration, in the trace file. The file is
+//! inherited by the intermediate and the init process, so the trace covers
+//! the whole creation of the container and the start of its process up to
+//! the exec of the payload. Each call is one line of JSON:
+//!
+//! ```json
+//! {"pid":4242,"call":"mount","args":{"source":"proc","target":"/proc","fstype":"proc","flags":"MsFlags(0x0)","data":null},"error":null,"durationUs":31}
+//! ```
+use std::any::Any;
+use std::ffi::OsStr;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Instant;
+
+use caps::{CapSet, CapsHashSet};
+use nix::mount::{MntFlags, MsFlags};
+use nix::sched::CloneFlags;
+use nix::sys::stat::{Mode, SFlag};
+use nix::unistd::{Gid, Uid};
+use oci_spec::runtime::PosixRlimit;
+use once_cell::sync::OnceCell;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use super::linux::MountAttr;
+use super::{Result, Syscall, SyscallError};
+
+pub const SETUP_TRACE_FILE: &str = "setup-trace.jsonl";
+
+static SETUP_TRACE: OnceCell<SetupTrace> = OnceCell::new();
+
+/// One call of the trace
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct TraceEntry {
+    /// Process which made the call, youki, the intermediate or the init
+    pub pid: i32,
+    pub call: String,
+    pub args: Value,
+    /// Error of the call, none if it succeeded
+    pub error: Option<String>,
+    pub duration_us: u64,
+}
+
+/// Trace file of the process
+#[derive(Debug)]
+pub struct SetupTrace {
+    path: PathBuf,
+    file: File,
+}
+
+impl SetupTrace {
+    /// Enables the trace of the process in the file of the directory,
+    /// replacing the trace of an earlier container. The trace stays enabled
+    /// for the lifetime of the process, a second call keeps the first file.
+    pub fn enable(dir: &Path) -> io::Result<&'static SetupTrace> {
+        if let Some(trace) = SETUP_TRACE.get() {
+            tracing::warn!(path = ?trace.path, "setup trace is already enabled");
+            return Ok(trace);
+        }
+
+        fs::create_dir_all(dir)?;
+        let path = dir.join(SETUP_TRACE_FILE);
+        // appends of a single write do not interleave between the processes
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .mode(0o644)
+            .open(&path)?;
+        file.set_len(0)?;
+        tracing::debug!(?path, "enabled setup trace");
+
+        Ok(SETUP_TRACE.get_or_init(|| SetupTrace { path, file }))
+    }
+
+    /// Trace of the process, none if it was not enabled
+    pub fn get() -> Option<&'static SetupTrace> {
+        SETUP_TRACE.get()
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub(crate) fn as_raw_fd(&self) -> RawFd {
+        self.file.as_raw_fd()
+    }
+
+    fn record(&self, entry: &TraceEntry) {
+        let mut line = match serde_json::to_vec(entry) {
+            Ok(line) => line,
+            Err(err) => {
+                tracing::warn!(?err, call = entry.call, "failed to serialize setup trace");
+                return;
+            }
+        };
+        line.push(b'\n');
+        // the trace must never make the setup fail itself
+        if let Err(err) = (&self.file).write_all(&line) {
+            tracing::warn!(?err, call = entry.call, "failed to write setup trace");
+        }
+    }
+}
+
+/// Reads the entries of a trace file
+pub fn read_trace(path: &Path) -> io::Result<Vec<TraceEntry>> {
+    fs::read_to_string(path)?
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| serde_json::from_str(line).map_err(io::Error::from))
+        .collect()
+}
+
+/// Records the calls of the wrapped syscall in the trace
+pub struct TracedSyscall {
+    inner: Box<dyn Syscall>,
+    trace: &'static SetupTrace,
+}
+
+impl TracedSyscall {
+    pub fn new(inner: Box<dyn Syscall>, trace: &'static SetupTrace) -> Self {
+        Self { inner, trace }
+    }
+
+    fn traced<T>(&self, call: &str, args: Value, f: impl FnOnce() -> Result<T>) -> Result<T> {
+        let start = Instant::now();
+        let result = f();
+        self.trace.record(&TraceEntry {
+            pid: nix::unistd::getpid().as_raw(),
+            call: call.to_owned(),
+            args,
+            error: result.as_ref().err().map(SyscallError::to_string),
+            duration_us: start.elapsed().as_micros() as u64,
+        });
+        result
+    }
+}
+
+fn path_arg(path: &Path) -> Value {
+    Value::String(path.display().to_string())
+}
+
+fn debug_arg(value: impl std::fmt::Debug) -> Value {
+    Value::String(format!("{value:?}"))
+}
+
+impl Syscall for TracedSyscall {
+    fn as_any(&self) -> &dyn Any {
+        self.inner.as_any()
+    }
+
+    fn pivot_rootfs(&self, path: &Path) -> Result<()> {
+        self.traced("pivot_rootfs", json!({ "path": path_arg(path) }), || {
+            self.inner.pivot_rootfs(path)
+        })
+    }
+
+    fn chroot(&self, path: &Path) -> Result<()> {
+        self.traced("chroot", json!({ "path": path_arg(path) }), || {
+            self.inner.chroot(path)
+        })
+    }
+
+    fn set_ns(&self, rawfd: i32, nstype: CloneFlags) -> Result<()> {
+        self.traced(
+            "set_ns",
+            json!({ "fd": rawfd, "nstype": debug_arg(nstype) }),
+            || self.inner.set_ns(rawfd, nstype),
+        )
+    }
+
+    fn set_id(&self, uid: Uid, gid: Gid) -> Result<()> {
+        self.traced(
+            "set_id",
+            json!({ "uid": uid.as_raw(), "gid": gid.as_raw() }),
+            || self.inner.set_id(uid, gid),
+        )
+    }
+
+    fn unshare(&self, flags: CloneFlags) -> Result<()> {
+        self.traced("unshare", json!({ "flags": debug_arg(flags) }), || {
+            self.inner.unshare(flags)
+        })
+    }
+
+    fn set_capability(&self, cset: CapSet, value: &CapsHashSet) -> Result<()> {
+        let mut caps: Vec<String> = value.iter().map(ToString::to_string).collect();
+        caps.sort();
+        self.traced(
+            "set_capability",
+            json!({ "set": debug_arg(cset), "caps": caps }),
+            || self.inner.set_capability(cset, value),
+        )
+    }
+
+    fn set_hostname(&self, hostname: &str) -> Result<()> {
+        self.traced("set_hostname", json!({ "hostname": hostname }), || {
+            self.inner.set_hostname(hostname)
+        })
+    }
+
+    fn set_domainname(&self, domainname: &str) -> Result<()> {
+        self.traced(
+            "set_domainname",
+            json!({ "domainname": domainname }),
+            || self.inner.set_domainname(domainname),
+        )
+    }
+
+    fn set_rlimit(&self, rlimit: &PosixRlimit) -> Result<()> {
+        self.traced(
+            "set_rlimit",
+            json!({
+                "type": debug_arg(rlimit.typ()),
+                "soft": rlimit.soft(),
+                "hard": rlimit.hard(),
+            }),
+            || self.inner.set_rlimit(rlimit),
+        )
+    }
+
+    fn get_pwuid(&self, uid: u32) -> Option<Arc<OsStr>> {
+        let start = Instant::now();
+        let user = self.inner.get_pwuid(uid);
+        self.trace.record(&TraceEntry {
+            pid: nix::unistd::getpid().as_raw(),
+            call: "get_pwuid".to_owned(),
+            args: json!({ "uid": uid }),
+            error: user.is_none().then(|| "no such user".to_owned()),
+            duration_us: start.elapsed().as_micros() as u64,
+        });
+        user
+    }
+
+    fn mount(
+        &self,
+        source: Option<&Path>,
+        target: &Path,
+        fstype: Option<&str>,
+        flags: MsFlags,
+        data: Option<&str>,
+    ) -> Result<()> {
+        self.traced(
+            "mount",
+            json!({
+                "source": source.map(path_arg),
+                "target": path_arg(target),
+                "fstype": fstype,
+                "flags": debug_arg(flags),
+                "data": data,
+            }),
+            || self.inner.mount(source, target, fstype, flags, data),
+        )
+    }
+
+    fn symlink(&self, original: &Path, link: &Path) -> Result<()> {
+        self.traced(
+            "symlink",
+            json!({ "original": path_arg(original), "link": path_arg(link) }),
+            || self.inner.symlink(original, link),
+        )
+    }
+
+    fn mknod(&self, path: &Path, kind: SFlag, perm: Mode, dev: u64) -> Result<()> {
+        self.traced(
+            "mknod",
+            json!({
+                "path": path_arg(path),
+                "kind": debug_arg(kind),
+                "perm": format!("{:o}", perm.bits()),
+                "dev": dev,
+            }),
+            || self.inner.mknod(path, kind, perm, dev),
+        )
+    }
+
+    fn chown(&self, path: &Path, owner: Option<Uid>, group: Option<Gid>) -> Result<()> {
+        self.traced(
+            "chown",
+            json!({
+                "path": path_arg(path),
+                "owner": owner.map(Uid::as_raw),
+                "group": group.map(Gid::as_raw),
+            }),
+            || self.inner.chown(path, owner, group),
+        )
+    }
+
+    fn set_groups(&self, groups: &[Gid]) -> Result<()> {
+        let gids: Vec<u32> = groups.iter().map(|gid| gid.as_raw()).collect();
+        self.traced("set_groups", json!({ "groups": gids }), || {
+            self.inner.set_groups(groups)
+        })
+    }
+
+    fn close_range(&self, preserve_fds: i32) -> Result<()> {
+        self.traced(
+            "close_range",
+            json!({ "preserveFds": preserve_fds }),
+            || self.inner.close_range(preserve_fds),
+        )
+    }
+
+    fn mount_setattr(
+        &self,
+        dirfd: i32,
+        pathname: &Path,
+        flags: u32,
+        mount_attr: &MountAttr,
+        size: libc::size_t,
+    ) -> Result<()> {
+        self.traced(
+            "mount_setattr",
+            json!({
+                "dirfd": dirfd,
+                "pathname": path_arg(pathname),
+                "flags": flags,
+                "attrSet": mount_attr.attr_set,
+                "attrClr": mount_attr.attr_clr,
+                "propagation": mount_attr.propagation,
+            }),
+            || {
+                self.inner
+                    .mount_setattr(dirfd, pathname, flags, mount_attr, size)
+            },
+        )
+    }
+
+    fn set_io_priority(&self, class: i64, priority: i64) -> Result<()> {
+        self.traced(
+            "set_io_priority",
+            json!({ "class": class, "priority": priority }),
+            || self.inner.set_io_priority(class, priority),
+        )
+    }
+
+    fn umount2(&self, target: &Path, flags: MntFlags) -> Result<()> {
+        self.traced(
+            "umount2",
+            json!({ "target": path_arg(target), "flags": debug_arg(flags) }),
+            || self.inner.umount2(target, flags),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+
+    use super::*;
+    use crate::syscall::test::TestHelperSyscall;
+
+    #[test]
+    fn test_traced_syscall() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        // a trace of its own, the one of the process may be enabled by
+        // another test
+        let path = tmp.path().join(SETUP_TRACE_FILE);
+        let trace: &'static SetupTrace = Box::leak(Box::new(SetupTrace {
+            path: path.clone(),
+            file: OpenOptions::new().create(true).append(true).open(&path)?,
+        }));
+
+        let syscall = TracedSyscall::new(Box::<TestHelperSyscall>::default(), trace);
+        syscall.set_hostname("youki")?;
+        syscall.mount(
+            Some(Path::new("proc")),
+            Path::new("/proc"),
+            Some("proc"),
+            MsFlags::MS_NOSUID,
+            None,
+        )?;
+        // the calls still reach the wrapped syscall
+        let mocks = syscall
+            .as_any()
+            .downcast_ref::<TestHelperSyscall>()
+            .unwrap();
+        assert_eq!(mocks.get_hostname_args(), vec!["youki".to_owned()]);
+
+        let entries = read_trace(&path)?;
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].call, "set_hostname");
+        assert_eq!(entries[0].args, json!({ "hostname": "youki" }));
+        assert_eq!(entries[0].error, None);
+        assert_eq!(entries[0].pid, nix::unistd::getpid().as_raw());
+        assert_eq!(entries[1].call, "mount");
+        assert_eq!(entries[1].args["target"], "/proc");
+        assert_eq!(entries[1].args["data"], Value::Null);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,232 @@
+//! Kernel parameters (sysctls) are only safe to set from a container if they
+//! belong to a namespace the container owns. Otherwise writing them either
+//! fails late inside the init process or, worse, changes the host. This
+//! module classifies sysctls by the namespace they belong to, following the
+//! same rules as runc, and applies the ones which passed validation.
+use std::collections::HashMap;
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+use oci_spec::runtime::{LinuxNamespace, LinuxNamespaceType};
+
+#[derive(Debug, thiserror::Error)]
+pub enum SysctlError {
+    #[error("sysctl {0:?} is not in a separate kernel namespace")]
+    NotNamespaced(String),
+    #[error("sysctl {key:?} requires a new {namespace:?} namespace")]
+    MissingNamespace {
+        key: String,
+        namespace: LinuxNamespaceType,
+    },
+    #[error("sysctl {key:?} is not allowed in the host {namespace:?} namespace")]
+    HostNamespace {
+        key: String,
+        namespace: LinuxNamespaceType,
+    },
+    #[error("failed to inspect namespace {path:?}")]
+    InspectNamespace {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("failed to set sysctl {key}={value}")]
+    Write {
+        key: String,
+        value: String,
+        source: std::io::Error,
+    },
+}
+
+type Result<T> = std::result::Result<T, SysctlError>;
+
+/// IPC namespaced sysctls, see ipc_namespaces(7).
+const IPC_SYSCTLS: &[&str] = &[
+    "kernel.msgmax",
+    "kernel.msgmnb",
+    "kernel.msgmni",
+    "kernel.sem",
+    "kernel.shmall",
+    "kernel.shmmax",
+    "kernel.shmmni",
+    "kernel.shm_rmid_forced",
+];
+
+/// Returns the namespace type the sysctl belongs to, or None if the sysctl is
+/// not namespaced at all. Both `.` and `/` are accepted as separators.
+pub fn namespace_of(key: &str) -> Option<LinuxNamespaceType> {
+    let key = normalize(key);
+    if IPC_SYSCTLS.contains(&key.as_str()) || key.starts_with("fs.mqueue.") {
+        return Some(LinuxNamespaceType::Ipc);
+    }
+    if key.starts_with("net.") {
+        return Some(LinuxNamespaceType::Network);
+    }
+    if key == "kernel.hostname" || key == "kernel.domainname" {
+        return Some(LinuxNamespaceType::Uts);
+    }
+
+    None
+}
+
+/// Checks that every sysctl belongs to a namespace which the container does
+/// not share with the host. A namespace configured with a path is treated as
+/// shared with the host if the path refers to the namespace of the calling
+/// process.
+pub fn validate(
+    kernel_params: &HashMap<String, String>,
+    namespaces: Option<&Vec<LinuxNamespace>>,
+) -> Result<()> {
+    let namespaces = namespaces.map(Vec::as_slice).unwrap_or_default();
+    for key in kernel_params.keys() {
+        let namespace = namespace_of(key).ok_or_else(|| {
+            tracing::error!(?key, "sysctl is not namespaced");
+            SysctlError::NotNamespaced(key.to_owned())
+        })?;
+
+        let Some(ns) = namespaces.iter().find(|ns| ns.typ() == namespace) else {
+            tracing::error!(?key, ?namespace, "sysctl requires a new namespace");
+            return Err(SysctlError::MissingNamespace {
+                key: key.to_owned(),
+                namespace,
+            });
+        };
+
+        if let Some(path) = ns.path() {
+            if is_host_namespace(namespace, path)? {
+                tracing::error!(?key, ?namespace, ?path, "sysctl targets a host namespace");
+                return Err(SysctlError::HostNamespace {
+                    key: key.to_owned(),
+                    namespace,
+                });
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Writes the sysctls into /proc/sys. This has to run inside the container
+/// namespaces, after they were checked with `validate`.
+pub fn apply(kernel_params: &HashMap<String, String>) -> Result<()> {
+    let sys = PathBuf::from("/proc/sys");
+    for (key, value) in kernel_params {
+        let path = sys.join(normalize(key).replace('.', "/"));
+        tracing::debug!("apply value {} to kernel parameter {}.", value, key);
+        fs::write(path, value.as_bytes()).map_err(|err| {
+            tracing::error!("failed to set sysctl {key}={value}: {err}");
+            SysctlError::Write {
+                key: key.to_owned(),
+                value: value.to_owned(),
+                source: err,
+            }
+        })?;
+    }
+
+    Ok(())
+}
+
+pub(crate) fn normalize(key: &str) -> String {
+    key.replace('/', ".")
+}
+
+fn is_host_namespace(namespace: LinuxNamespaceType, path: &Path) -> Result<bool> {
+    let host_path = PathBuf::from("/proc/self/ns").join(match namespace {
+        LinuxNamespaceType::Ipc => "ipc",
+        LinuxNamespaceType::Network => "net",
+        LinuxNamespaceType::Uts => "uts",
+        _ => return Ok(false),
+    });
+
+    let stat = |path: &Path| {
+        fs::metadata(path).map_err(|err| SysctlError::InspectNamespace {
+            path: path.to_owned(),
+            source: err,
+        })
+    };
+    let (host, target) = (stat(&host_path)?, stat(path)?);
+
+    Ok(host.dev() == target.dev() && host.ino() == target.ino())
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use oci_spec::runtime::LinuxNamespaceBuilder;
+
+    use super::*;
+
+    fn namespace(typ: LinuxNamespaceType, path: Option<&str>) -> Result<LinuxNamespace> {
+        let mut builder = LinuxNamespaceBuilder::default().typ(typ);
+        if let Some(path) = path {
+            builder = builder.path(path);
+        }
+        Ok(builder.build()?)
+    }
+
+    fn params(key: &str) -> HashMap<String, String> {
+        HashMap::from([(key.to_owned(), "1".to_owned())])
+    }
+
+    #[test]
+    fn test_namespace_of() {
+        let tests = [
+            ("kernel.shmmax", Some(LinuxNamespaceType::Ipc)),
+            ("fs.mqueue.msg_max", Some(LinuxNamespaceType::Ipc)),
+            ("net.ipv4.ip_forward", Some(LinuxNamespaceType::Network)),
+            ("net/ipv4/ip_forward", Some(LinuxNamespaceType::Network)),
+            ("kernel.hostname", Some(LinuxNamespaceType::Uts)),
+            ("kernel.pid_max", None),
+            ("vm.swappiness", None),
+        ];
+        for (key, want) in tests {
+            assert_eq!(namespace_of(key), want, "{key}");
+        }
+    }
+
+    #[test]
+    fn test_validate() -> Result<()> {
+        let namespaces = vec![
+            namespace(LinuxNamespaceType::Network, None)?,
+            namespace(LinuxNamespaceType::Ipc, None)?,
+        ];
+
+        assert!(validate(&params("net.ipv4.ip_forward"), Some(&namespaces)).is_ok());
+        assert!(validate(&params("kernel.shmmax"), Some(&namespaces)).is_ok());
+        assert!(matches!(
+            validate(&params("kernel.hostname"), Some(&namespaces)),
+            Err(SysctlError::MissingNamespace { .. })
+        ));
+        assert!(matches!(
+            validate(&params("vm.swappiness"), Some(&namespaces)),
+            Err(SysctlError::NotNamespaced(_))
+        ));
+        assert!(matches!(
+            validate(&params("net.ipv4.ip_forward"), None),
+            Err(SysctlError::MissingNamespace { .. })
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_validate_joined_namespace() -> Result<()> {
+        let host = vec![namespace(
+            LinuxNamespaceType::Network,
+            Some("/proc/self/ns/net"),
+        )?];
+        assert!(matches!(
+            validate(&params("net.ipv4.ip_forward"), Some(&host)),
+            Err(SysctlError::HostNamespace { .. })
+        ));
+
+        // Any other namespace file is assumed to belong to another container.
+        let other = tempfile::NamedTempFile::new()?;
+        let other = vec![namespace(
+            LinuxNamespaceType::Network,
+            other.path().to_str(),
+        )?];
+        assert!(validate(&params("net.ipv4.ip_forward"), Some(&other)).is_ok());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,211 @@
+//! Timezone of standalone containers. Images usually come without a
+//! configured local time, so containers run in UTC unless the spec mounts a
+//! zoneinfo file itself. A timezone given to youki bind mounts the zoneinfo
+//! file of the host read only to `/etc/localtime` of the container and points
+//! `TZ` at it, which also works for images without a zoneinfo database.
+//!
+//! A timezone is either a name of the zoneinfo database of the host, e.g.
+//! `Europe/Berlin`, or `local` for the timezone of the host itself.
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::str::FromStr;
+
+use oci_spec::runtime::{MountBuilder, Spec};
+
+#[derive(Debug, thiserror::Error)]
+pub enum TimezoneError {
+    #[error("invalid timezone {0:?}")]
+    InvalidName(String),
+    #[error("unknown timezone {zone}, {path:?} does not exist")]
+    Unknown { zone: String, path: PathBuf },
+    #[error("failed to resolve the local timezone of the host from {path:?}")]
+    Local {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    #[error("failed to build timezone mount")]
+    SpecBuild(#[from] oci_spec::OciSpecError),
+}
+
+type Result<T> = std::result::Result<T, TimezoneError>;
+
+const ZONEINFO_DIR: &str = "/usr/share/zoneinfo";
+const LOCALTIME: &str = "/etc/localtime";
+const LOCAL: &str = "local";
+const TZ_ENV: &str = "TZ";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Timezone {
+    /// The timezone of the host, as configured by its /etc/localtime
+    Local,
+    /// A timezone of the zoneinfo database of the host
+    Zone(String),
+}
+
+impl FromStr for Timezone {
+    type Err = TimezoneError;
+
+    fn from_str(value: &str) -> Result<Self> {
+        if value == LOCAL {
+            return Ok(Timezone::Local);
+        }
+
+        // the name is joined to the zoneinfo directory and must stay in it
+        let path = Path::new(value);
+        let valid = !value.is_empty()
+            && path
+                .components()
+                .all(|component| matches!(component, Component::Normal(_)));
+        if !valid {
+            return Err(TimezoneError::InvalidName(value.to_owned()));
+        }
+
+        Ok(Timezone::Zone(value.to_owned()))
+    }
+}
+
+impl Timezone {
+    /// Returns the zoneinfo file of the host for the timezone
+    pub fn zoneinfo(&self) -> Result<PathBuf> {
+        match self {
+            // /etc/localtime is usually a symlink into the zoneinfo database,
+            // resolve it so the mount does not depend on the link
+            Timezone::Local => fs::canonicalize(LOCALTIME).map_err(|err| TimezoneError::Local {
+                path: PathBuf::from(LOCALTIME),
+                source: err,
+            }),
+            Timezone::Zone(zone) => {
+                let path = Path::new(ZONEINFO_DIR).join(zone);
+                if !path.is_file() {
+                    return Err(TimezoneError::Unknown {
+                        zone: zone.clone(),
+                        path,
+                    });
+                }
+                Ok(path)
+            }
+        }
+    }
+}
+
+/// Sets the timezone of the container. The timezone replaces a mount of
+/// /etc/localtime and a TZ variable the spec has already.
+pub fn apply(spec: &mut Spec, timezone: &Timezone) -> Result<()> {
+    let zoneinfo = timezone.zoneinfo()?;
+    tracing::debug!(?timezone, ?zoneinfo, "setting timezone of the container");
+    apply_zoneinfo(spec, zoneinfo)
+}
+
+fn apply_zoneinfo(spec: &mut Spec, zoneinfo: PathBuf) -> Result<()> {
+    let mut mounts = spec.mounts().clone().unwrap_or_default();
+    mounts.retain(|mount| mount.destination() != Path::new(LOCALTIME));
+    mounts.push(
+        MountBuilder::default()
+            .destination(LOCALTIME)
+            .typ("bind")
+            .source(zoneinfo)
+            .options(
+                ["rbind", "ro", "nosuid", "nodev", "noexec"]
+                    .iter()
+                    .map(|option| option.to_string())
+                    .collect::<Vec<_>>(),
+            )
+            .build()?,
+    );
+    spec.set_mounts(Some(mounts));
+
+    if let Some(mut process) = spec.process().clone() {
+        let mut env = process.env().clone().unwrap_or_default();
+        env.retain(|var| var.split_once('=').map_or(true, |(name, _)| name != TZ_ENV));
+        // a leading colon makes the libc read the zoneinfo file directly
+        env.push(format!("{TZ_ENV}=:{LOCALTIME}"));
+        process.set_env(Some(env));
+        spec.set_process(Some(process));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use oci_spec::runtime::{ProcessBuilder, SpecBuilder};
+
+    use super::*;
+
+    #[test]
+    fn test_parse_timezone() {
+        assert_eq!("local".parse::<Timezone>().unwrap(), Timezone::Local);
+        assert_eq!(
+            "Europe/Berlin".parse::<Timezone>().unwrap(),
+            Timezone::Zone("Europe/Berlin".to_owned())
+        );
+        for invalid in [
+            "",
+            "/etc/passwd",
+            "../../etc/passwd",
+            "Europe/../UTC",
+            "./UTC",
+        ] {
+            assert!(invalid.parse::<Timezone>().is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn test_apply_replaces_timezone_of_spec() -> Result<()> {
+        let mut spec = SpecBuilder::default()
+            .process(
+                ProcessBuilder::default()
+                    .env(vec![
+                        "PATH=/usr/bin".to_owned(),
+                        "TZ=UTC".to_owned(),
+                        "TZDIR=/zoneinfo".to_owned(),
+                    ])
+                    .build()?,
+            )
+            .mounts(vec![
+                MountBuilder::default()
+                    .destination("/proc")
+                    .typ("proc")
+                    .source("proc")
+                    .build()?,
+                MountBuilder::default()
+                    .destination(LOCALTIME)
+                    .typ("bind")
+                    .source("/old")
+                    .build()?,
+            ])
+            .build()?;
+
+        apply_zoneinfo(&mut spec, PathBuf::from("/usr/share/zoneinfo/Asia/Tokyo"))?;
+
+        let mounts = spec.mounts().as_ref().unwrap();
+        assert_eq!(mounts.len(), 2);
+        assert_eq!(mounts[0].destination(), Path::new("/proc"));
+        assert_eq!(mounts[1].destination(), Path::new(LOCALTIME));
+        assert_eq!(
+            mounts[1].source().as_deref(),
+            Some(Path::new("/usr/share/zoneinfo/Asia/Tokyo"))
+        );
+        assert!(mounts[1]
+            .options()
+            .as_ref()
+            .unwrap()
+            .contains(&"ro".to_owned()));
+        assert_eq!(
+            spec.process().as_ref().unwrap().env().as_ref().unwrap(),
+            &["PATH=/usr/bin", "TZDIR=/zoneinfo", "TZ=:/etc/localtime"]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_unknown_timezone() {
+        let timezone = Timezone::Zone("Nowhere/Atlantis".to_owned());
+        assert!(matches!(
+            timezone.zoneinfo(),
+            Err(TimezoneError::Unknown { .. })
+        ));
+    }
+}
@@ -1,6 +1,5 @@
 //! tty (teletype) for user-system interaction
 
-use std::env;
 use std::io::IoSlice;
 use std::os::fd::OwnedFd;
 use std::os::unix::fs::symlink;
@@ -11,6 +10,8 @@ use std::path::{Path, PathBuf};
 use nix::sys::socket::{self, UnixAddr};
 use nix::unistd::{close, dup2};
 
+use crate::utils::SocketPath;
+
 #[derive(Debug)]
 pub enum StdIO {
     Stdin = 0,
@@ -53,6 +54,11 @@ pub enum TTYError {
         linked: Box<PathBuf>,
         console_socket_path: Box<PathBuf>,
     },
+    #[error("failed to open the directory of console socket {path:?}")]
+    SocketDir {
+        source: std::io::Error,
+        path: PathBuf,
+    },
     #[error("invalid socket name: {socket_name:?}")]
     InvalidSocketName {
         socket_name: String,
@@ -76,22 +82,7 @@ pub fn setup_console_socket(
     console_socket_path: &Path,
     socket_name: &str,
 ) -> Result<OwnedFd> {
-    struct CurrentDirGuard {
-        path: PathBuf,
-    }
-    impl Drop for CurrentDirGuard {
-        fn drop(&mut self) {
-            let _ = env::set_current_dir(&self.path);
-        }
-    }
-    // Move into the container directory to avoid sun family conflicts with long socket path names.
-    // ref: https://github.com/containers/youki/issues/2910
-
-    let prev_dir = env::current_dir().unwrap();
-    let _ = env::set_current_dir(container_dir);
-    let _guard = CurrentDirGuard { path: prev_dir };
-
-    let linked = PathBuf::from(socket_name);
+    let linked = container_dir.join(socket_name);
 
     symlink(console_socket_path, &linked).map_err(|err| TTYError::Symlink {
         source: err,
@@ -105,11 +96,19 @@ pub fn setup_console_socket(
         None,
     )
     .map_err(|err| TTYError::CreateConsoleSocketFd { source: err })?;
+    // Connect relative to the container directory to avoid sun family conflicts with long socket path names.
+    // ref: https://github.com/containers/youki/issues/2910
+    let short_path = SocketPath::new(&linked).map_err(|err| TTYError::SocketDir {
+        source: err,
+        path: container_dir.to_owned(),
+    })?;
     socket::connect(
         csocketfd.as_raw_fd(),
-        &socket::UnixAddr::new(linked.as_path()).map_err(|err| TTYError::InvalidSocketName {
-            source: err,
-            socket_name: socket_name.to_string(),
+        &socket::UnixAddr::new(short_path.as_path()).map_err(|err| {
+            TTYError::InvalidSocketName {
+                source: err,
+                socket_name: socket_name.to_string(),
+            }
         })?,
     )
     .map_err(|e| TTYError::CreateConsoleSocket {
@@ -169,6 +168,7 @@ fn connect_stdio(stdin: &RawFd, stdout: &RawFd, stderr: &RawFd) -> Result<()> {
 
 #[cfg(test)]
 mod tests {
+    use std::env;
     use std::fs::File;
     use std::os::fd::IntoRawFd;
     use std::os::unix::net::UnixListener;
@@ -215,6 +215,33 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_setup_console_socket_concurrently() -> Result<()> {
+        let cwd = env::current_dir()?;
+        let testdir = tempfile::tempdir()?;
+        let socket_path = Path::join(testdir.path(), "test-socket");
+        let _lis = UnixListener::bind(&socket_path)?;
+
+        let handles: Vec<_> = (0..8)
+            .map(|i| {
+                // longer than a unix socket address can hold
+                let container_dir = testdir.path().join(format!("{}-{i}", "c".repeat(120)));
+                let socket_path = socket_path.clone();
+                std::thread::spawn(move || -> Result<()> {
+                    std::fs::create_dir(&container_dir)?;
+                    setup_console_socket(&container_dir, &socket_path, CONSOLE_SOCKET)?;
+                    Ok(())
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap()?;
+        }
+
+        assert_eq!(env::current_dir()?, cwd);
+        Ok(())
+    }
+
     #[test]
     #[serial]
     fn test_setup_console() -> Result<()> {
@@ -0,0 +1,126 @@
+//! Annotations which are a shorthand for an entry of the unified resources
+//! of the spec, such as `org.youki.memory.oom-group` for `memory.oom.group`.
+//! The annotation and the entry may both be given as long as they agree.
+use std::collections::HashMap;
+
+use oci_spec::runtime::Spec;
+
+#[derive(Debug, thiserror::Error)]
+#[error("annotation {annotation} sets {key} to {value:?}, which conflicts with {unified:?} of the unified resources")]
+pub struct UnifiedConflict {
+    pub annotation: String,
+    pub key: String,
+    pub value: String,
+    pub unified: String,
+}
+
+/// Sets `key` of the unified resources of the spec to what `parse` maps the
+/// value of `annotation` to. Without the annotation this is a no-op.
+pub(crate) fn apply<E, F>(spec: &mut Spec, annotation: &str, key: &str, parse: F) -> Result<(), E>
+where
+    E: From<UnifiedConflict>,
+    F: FnOnce(&str) -> Result<String, E>,
+{
+    let value = match spec.annotations().as_ref().and_then(|a| a.get(annotation)) {
+        Some(value) => parse(value.trim())?,
+        None => return Ok(()),
+    };
+
+    let mut linux = spec.linux().clone().unwrap_or_default();
+    let mut resources = linux.resources().clone().unwrap_or_default();
+    let mut unified: HashMap<String, String> = resources.unified().clone().unwrap_or_default();
+    if let Some(existing) = unified.get(key) {
+        if existing.trim() != value {
+            return Err(UnifiedConflict {
+                annotation: annotation.to_owned(),
+                key: key.to_owned(),
+                value,
+                unified: existing.to_owned(),
+            }
+            .into());
+        }
+    }
+
+    tracing::debug!(
+        annotation,
+        key,
+        value,
+        "setting unified resource from annotation"
+    );
+    unified.insert(key.to_owned(), value);
+    resources.set_unified(Some(unified));
+    linux.set_resources(Some(resources));
+    spec.set_linux(Some(linux));
+    Ok(())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use anyhow::Result;
+    use oci_spec::runtime::{LinuxBuilder, LinuxResourcesBuilder, SpecBuilder};
+
+    use super::*;
+
+    const ANNOTATION: &str = "org.youki.test.value";
+    const KEY: &str = "test.value";
+
+    pub(crate) fn spec_with(
+        annotation: (&str, &str),
+        unified: Option<(&str, &str)>,
+    ) -> Result<Spec> {
+        let mut resources = LinuxResourcesBuilder::default();
+        if let Some((key, value)) = unified {
+            resources = resources.unified(HashMap::from([(key.to_owned(), value.to_owned())]));
+        }
+        Ok(SpecBuilder::default()
+            .annotations(HashMap::from([(
+                annotation.0.to_owned(),
+                annotation.1.to_owned(),
+            )]))
+            .linux(
+                LinuxBuilder::default()
+                    .resources(resources.build()?)
+                    .build()?,
+            )
+            .build()?)
+    }
+
+    pub(crate) fn unified_value(spec: &Spec, key: &str) -> Option<String> {
+        spec.linux()
+            .as_ref()
+            .and_then(|linux| linux.resources().as_ref())
+            .and_then(|resources| resources.unified().as_ref())
+            .and_then(|unified| unified.get(key).cloned())
+    }
+
+    fn apply_upper(spec: &mut Spec) -> std::result::Result<(), UnifiedConflict> {
+        apply(spec, ANNOTATION, KEY, |value| Ok(value.to_uppercase()))
+    }
+
+    #[test]
+    fn test_apply() -> Result<()> {
+        let mut spec = spec_with((ANNOTATION, " on "), None)?;
+        apply_upper(&mut spec)?;
+        assert_eq!(unified_value(&spec, KEY).as_deref(), Some("ON"));
+
+        // the same value in both places is fine
+        let mut spec = spec_with((ANNOTATION, "on"), Some((KEY, "ON\n")))?;
+        apply_upper(&mut spec)?;
+        assert_eq!(unified_value(&spec, KEY).as_deref(), Some("ON"));
+
+        let mut spec = spec_with((ANNOTATION, "on"), Some((KEY, "OFF")))?;
+        let err = apply_upper(&mut spec).unwrap_err();
+        assert_eq!(err.value, "ON");
+        assert_eq!(err.unified, "OFF");
+
+        let mut spec = spec_with(("other", "on"), None)?;
+        apply_upper(&mut spec)?;
+        assert_eq!(unified_value(&spec, KEY), None);
+
+        let mut spec = Spec::default();
+        apply_upper(&mut spec)?;
+        assert_eq!(unified_value(&spec, KEY), None);
+
+        Ok(())
+    }
+}
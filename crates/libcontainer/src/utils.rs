@@ -3,15 +3,16 @@
 use std::collections::HashMap;
 use std::fs::{self, DirBuilder, File};
 use std::os::linux::fs::MetadataExt;
-use std::os::unix::fs::DirBuilderExt;
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+use std::os::unix::io::{AsRawFd, RawFd};
 use std::path::{Component, Path, PathBuf};
 
 use nix::sys::stat::Mode;
 use nix::sys::statfs;
 use nix::unistd::{Uid, User};
-use oci_spec::runtime::Spec;
+use oci_spec::runtime::{LinuxSchedulerPolicy, Spec};
 
-use crate::error::LibcontainerError;
+use crate::error::{ErrInvalidSpec, LibcontainerError};
 use crate::user_ns::UserNamespaceConfig;
 
 #[derive(Debug, thiserror::Error)]
@@ -181,6 +182,62 @@ pub fn open<P: AsRef<Path>>(path: P) -> Result<File, std::io::Error> {
     })
 }
 
+/// Closes all fds above stdio except for the ones to keep, in a process
+/// forked to run a helper which must not hold on to the files of the caller.
+pub fn close_other_fds(keep: &[RawFd]) -> Result<(), std::io::Error> {
+    let fds: Vec<RawFd> = fs::read_dir("/proc/self/fd")?
+        .filter_map(|entry| entry.ok()?.file_name().to_str()?.parse().ok())
+        .collect();
+    for fd in fds {
+        if fd > 2 && !keep.contains(&fd) {
+            // the fd of the directory listing is already closed
+            let _ = nix::unistd::close(fd);
+        }
+    }
+
+    Ok(())
+}
+
+/// A path to bind or connect a unix socket, however long the real path is.
+/// A unix socket address only holds 108 bytes, so the path goes through an
+/// fd of the parent directory in /proc/self/fd instead. Unlike changing the
+/// working directory for the duration of the call, this does not affect
+/// other threads. The path is valid as long as the value lives.
+pub struct SocketPath {
+    _dir: File,
+    path: PathBuf,
+}
+
+impl SocketPath {
+    pub fn new(socket_path: &Path) -> Result<Self, std::io::Error> {
+        let name = socket_path.file_name().ok_or_else(|| {
+            std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("invalid socket path {socket_path:?}"),
+            )
+        })?;
+        let parent = match socket_path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        let dir = fs::OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_PATH | libc::O_DIRECTORY)
+            .open(parent)
+            .map_err(|err| {
+                tracing::error!(?err, ?parent, "failed to open socket directory");
+                err
+            })?;
+        let path = PathBuf::from(format!("/proc/self/fd/{}", dir.as_raw_fd())).join(name);
+
+        Ok(Self { _dir: dir, path })
+    }
+
+    pub fn as_path(&self) -> &Path {
+        &self.path
+    }
+}
+
 #[derive(Debug, thiserror::Error)]
 pub enum MkdirWithModeError {
     #[error("IO error")]
@@ -285,6 +342,41 @@ pub fn validate_spec_for_new_user_ns(spec: &Spec) -> Result<(), LibcontainerErro
     Ok(())
 }
 
+/// checks that a container whose cgroup is marked as idle (cpu.idle), i.e.
+/// is only given cpu time that would otherwise be unused, does not request a
+/// realtime or deadline scheduling policy for its process, which contradict
+/// each other. SCHED_IDLE and SCHED_BATCH complement an idle cgroup.
+pub fn validate_cpu_idle(spec: &Spec) -> Result<(), ErrInvalidSpec> {
+    let idle = spec
+        .linux()
+        .as_ref()
+        .and_then(|linux| linux.resources().as_ref())
+        .and_then(|resources| resources.cpu().as_ref())
+        .and_then(|cpu| cpu.idle());
+    let policy = spec
+        .process()
+        .as_ref()
+        .and_then(|process| process.scheduler().as_ref())
+        .map(|scheduler| *scheduler.policy());
+
+    if let (Some(1), Some(policy)) = (idle, policy) {
+        if matches!(
+            policy,
+            LinuxSchedulerPolicy::SchedFifo
+                | LinuxSchedulerPolicy::SchedRr
+                | LinuxSchedulerPolicy::SchedDeadline
+        ) {
+            tracing::error!(
+                ?policy,
+                "scheduler policy cannot be used together with an idle cpu cgroup"
+            );
+            return Err(ErrInvalidSpec::Scheduler);
+        }
+    }
+
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use anyhow::{bail, Result};
@@ -426,4 +518,41 @@ mod tests {
             Ok(())
         })
     }
+
+    #[test]
+    fn test_validate_cpu_idle() -> Result<()> {
+        use oci_spec::runtime::{
+            LinuxBuilder, LinuxCpuBuilder, LinuxResourcesBuilder, ProcessBuilder, SchedulerBuilder,
+            SpecBuilder,
+        };
+
+        let spec = |idle: i64, policy: LinuxSchedulerPolicy| -> Result<Spec> {
+            Ok(SpecBuilder::default()
+                .process(
+                    ProcessBuilder::default()
+                        .scheduler(SchedulerBuilder::default().policy(policy).build()?)
+                        .build()?,
+                )
+                .linux(
+                    LinuxBuilder::default()
+                        .resources(
+                            LinuxResourcesBuilder::default()
+                                .cpu(LinuxCpuBuilder::default().idle(idle).build()?)
+                                .build()?,
+                        )
+                        .build()?,
+                )
+                .build()?)
+        };
+
+        assert!(validate_cpu_idle(&spec(1, LinuxSchedulerPolicy::SchedIdle)?).is_ok());
+        assert!(validate_cpu_idle(&spec(1, LinuxSchedulerPolicy::SchedBatch)?).is_ok());
+        assert!(validate_cpu_idle(&spec(0, LinuxSchedulerPolicy::SchedFifo)?).is_ok());
+        assert!(validate_cpu_idle(&spec(1, LinuxSchedulerPolicy::SchedFifo)?).is_err());
+        assert!(validate_cpu_idle(&spec(1, LinuxSchedulerPolicy::SchedRr)?).is_err());
+        assert!(validate_cpu_idle(&spec(1, LinuxSchedulerPolicy::SchedDeadline)?).is_err());
+        assert!(validate_cpu_idle(&Spec::default()).is_ok());
+
+        Ok(())
+    }
 }
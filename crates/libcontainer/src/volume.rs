@@ -0,0 +1,438 @@
+//! Provisioning of volumes by an external helper. This is a youki extension
+//! for standalone deployments without a higher level runtime which prepares
+//! volumes: a mount whose source is `volume://<name>` is resolved to a host
+//! path by running the helper binary before the container is created.
+//!
+//! The helper receives a JSON request on stdin and must exit with status 0:
+//!
+//! ```json
+//! {"version": "1", "action": "mount", "volume": "data", "containerId": "web"}
+//! ```
+//!
+//! For `mount` it answers with the host path of the volume on stdout,
+//! `{"path": "/srv/volumes/data"}`. When the container is deleted the helper
+//! is run again with the `unmount` action for each volume, its output is
+//! ignored then.
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use oci_spec::runtime::Spec;
+use serde::{Deserialize, Serialize};
+
+/// Scheme of mount sources which are provisioned by the volume helper
+pub const VOLUME_SCHEME: &str = "volume://";
+const PROTOCOL_VERSION: &str = "1";
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug, thiserror::Error)]
+pub enum VolumeError {
+    #[error("mount of {destination:?} uses volume {volume} but no volume helper is configured")]
+    NoHelper {
+        volume: String,
+        destination: PathBuf,
+    },
+    #[error("invalid volume name {0:?}")]
+    InvalidName(String),
+    #[error("failed to run volume helper {path:?}")]
+    Spawn { path: PathBuf, source: io::Error },
+    #[error("failed to communicate with volume helper")]
+    Io(#[source] io::Error),
+    #[error("volume helper did not answer for volume {volume} within {timeout:?}")]
+    Timeout { volume: String, timeout: Duration },
+    #[error("volume helper failed for volume {volume} with {status}: {stderr}")]
+    Failed {
+        volume: String,
+        status: ExitStatus,
+        stderr: String,
+    },
+    #[error("invalid response of volume helper for volume {volume}")]
+    InvalidResponse {
+        volume: String,
+        source: serde_json::Error,
+    },
+    #[error("volume helper returned the relative path {path:?} for volume {volume}")]
+    RelativePath { volume: String, path: PathBuf },
+}
+
+type Result<T> = std::result::Result<T, VolumeError>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum Action {
+    Mount,
+    Unmount,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Request<'a> {
+    version: &'static str,
+    action: Action,
+    volume: &'a str,
+    container_id: &'a str,
+}
+
+#[derive(Debug, Deserialize)]
+struct Response {
+    path: PathBuf,
+}
+
+/// Helper binary which provisions volumes
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VolumeHelper {
+    path: PathBuf,
+    timeout: Duration,
+}
+
+impl VolumeHelper {
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        Self {
+            path: path.into(),
+            timeout: DEFAULT_TIMEOUT,
+        }
+    }
+
+    /// Sets how long the helper may take for one request, 30 seconds by
+    /// default
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Replaces the volume sources of the mounts of the spec by the host
+    /// paths of the volumes. A volume used by several mounts is provisioned
+    /// once. Returns the names of the provisioned volumes.
+    pub fn provision(&self, spec: &mut Spec, container_id: &str) -> Result<Vec<String>> {
+        let mut paths: HashMap<String, PathBuf> = HashMap::new();
+        let mut volumes = Vec::new();
+        let mut mounts = spec.mounts().clone().unwrap_or_default();
+        for mount in mounts.iter_mut() {
+            let volume = match volume_name(mount.source().as_deref())? {
+                Some(volume) => volume,
+                None => continue,
+            };
+            let path = match paths.get(&volume) {
+                Some(path) => path.clone(),
+                None => {
+                    let path = self.mount(&volume, container_id).map_err(|err| {
+                        // release what was provisioned so far, the container
+                        // will not be created
+                        self.release_all(&volumes, container_id);
+                        err
+                    })?;
+                    tracing::debug!(volume, ?path, "provisioned volume");
+                    volumes.push(volume.clone());
+                    paths.insert(volume, path.clone());
+                    path
+                }
+            };
+            mount.set_source(Some(path));
+        }
+
+        if !volumes.is_empty() {
+            spec.set_mounts(Some(mounts));
+        }
+        Ok(volumes)
+    }
+
+    /// Notifies the helper that the volumes are not used by the container
+    /// anymore. Failures are logged, they must not prevent the deletion of
+    /// the container.
+    pub fn release_all(&self, volumes: &[String], container_id: &str) {
+        for volume in volumes {
+            if let Err(err) = self.call(Action::Unmount, volume, container_id) {
+                tracing::warn!(?err, volume, "failed to release volume");
+            }
+        }
+    }
+
+    fn mount(&self, volume: &str, container_id: &str) -> Result<PathBuf> {
+        let output = self.call(Action::Mount, volume, container_id)?;
+        let response: Response =
+            serde_json::from_slice(&output).map_err(|err| VolumeError::InvalidResponse {
+                volume: volume.to_owned(),
+                source: err,
+            })?;
+        if !response.path.is_absolute() {
+            return Err(VolumeError::RelativePath {
+                volume: volume.to_owned(),
+                path: response.path,
+            });
+        }
+
+        Ok(response.path)
+    }
+
+    /// Runs the helper for one request and returns its stdout
+    fn call(&self, action: Action, volume: &str, container_id: &str) -> Result<Vec<u8>> {
+        let request = serde_json::to_vec(&Request {
+            version: PROTOCOL_VERSION,
+            action,
+            volume,
+            container_id,
+        })
+        .expect("volume requests are serializable");
+
+        let mut child = Command::new(&self.path)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|err| {
+                tracing::error!(?err, path = ?self.path, "failed to spawn volume helper");
+                VolumeError::Spawn {
+                    path: self.path.clone(),
+                    source: err,
+                }
+            })?;
+
+        // The pipes are drained by threads, so a helper writing a lot of
+        // output can not block and the timeout holds.
+        let mut stdin = child.stdin.take().expect("stdin is piped");
+        let stdout = drain(child.stdout.take().expect("stdout is piped"));
+        let stderr = drain(child.stderr.take().expect("stderr is piped"));
+        // a helper which does not read its request fails on its own
+        let _ = stdin.write_all(&request);
+        drop(stdin);
+
+        let status = wait_timeout(&mut child, self.timeout).map_err(VolumeError::Io)?;
+        let status = match status {
+            Some(status) => status,
+            None => {
+                let _ = child.kill();
+                let _ = child.wait();
+                tracing::error!(volume, timeout = ?self.timeout, "volume helper timed out");
+                return Err(VolumeError::Timeout {
+                    volume: volume.to_owned(),
+                    timeout: self.timeout,
+                });
+            }
+        };
+
+        let stdout = stdout.join().expect("reader thread does not panic");
+        let stderr = stderr.join().expect("reader thread does not panic");
+        if !status.success() {
+            return Err(VolumeError::Failed {
+                volume: volume.to_owned(),
+                status,
+                stderr: String::from_utf8_lossy(&stderr).trim().to_owned(),
+            });
+        }
+
+        Ok(stdout)
+    }
+}
+
+/// Returns the volume name of a mount source, None if the source is no
+/// volume
+fn volume_name(source: Option<&Path>) -> Result<Option<String>> {
+    let name = match source
+        .and_then(|source| source.to_str())
+        .and_then(|source| source.strip_prefix(VOLUME_SCHEME))
+    {
+        Some(name) => name,
+        None => return Ok(None),
+    };
+
+    if name.is_empty() || name.contains('/') || name == "." || name == ".." {
+        return Err(VolumeError::InvalidName(name.to_owned()));
+    }
+    Ok(Some(name.to_owned()))
+}
+
+/// Returns an error if the spec uses volumes, for containers created without
+/// a volume helper
+pub fn ensure_no_volumes(spec: &Spec) -> Result<()> {
+    for mount in spec.mounts().iter().flatten() {
+        if let Some(volume) = volume_name(mount.source().as_deref())? {
+            return Err(VolumeError::NoHelper {
+                volume,
+                destination: mount.destination().clone(),
+            });
+        }
+    }
+
+    Ok(())
+}
+
+fn drain<R: Read + Send + 'static>(mut reader: R) -> thread::JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut buf = Vec::new();
+        let _ = reader.read_to_end(&mut buf);
+        buf
+    })
+}
+
+fn wait_timeout(child: &mut Child, timeout: Duration) -> io::Result<Option<ExitStatus>> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(Some(status));
+        }
+        if Instant::now() >= deadline {
+            return Ok(None);
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+
+    use anyhow::Result;
+    use oci_spec::runtime::{Mount, MountBuilder, SpecBuilder};
+
+    use super::*;
+
+    fn write_helper(dir: &Path, script: &str) -> Result<PathBuf> {
+        let path = dir.join("helper");
+        fs::write(&path, format!("#!/bin/sh\n{script}"))?;
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755))?;
+        Ok(path)
+    }
+
+    fn mount(source: &str, destination: &str) -> Mount {
+        MountBuilder::default()
+            .source(source)
+            .destination(destination)
+            .typ("bind")
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_volume_name() {
+        assert_eq!(
+            volume_name(Some(Path::new("volume://data"))).unwrap(),
+            Some("data".to_owned())
+        );
+        assert_eq!(volume_name(Some(Path::new("/srv/data"))).unwrap(), None);
+        assert_eq!(volume_name(None).unwrap(), None);
+        for invalid in ["volume://", "volume://a/b", "volume://.."] {
+            assert!(volume_name(Some(Path::new(invalid))).is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn test_provision_and_release() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let log = tmp.path().join("log");
+        // logs each request and answers with a path named after the volume
+        let helper = write_helper(
+            tmp.path(),
+            &format!(
+                r#"request=$(cat)
+echo "$request" >> {log}
+volume=$(echo "$request" | sed 's/.*"volume":"\([^"]*\)".*/\1/')
+echo "{{\"path\": \"/srv/$volume\"}}"
+"#,
+                log = log.display()
+            ),
+        )?;
+        let mut spec = SpecBuilder::default()
+            .mounts(vec![
+                mount("volume://data", "/data"),
+                mount("/tmp", "/tmp"),
+                mount("volume://data", "/backup"),
+                mount("volume://cache", "/cache"),
+            ])
+            .build()?;
+
+        let helper = VolumeHelper::new(helper);
+        let volumes = helper.provision(&mut spec, "web")?;
+        assert_eq!(volumes, ["data", "cache"]);
+        let sources: Vec<_> = spec
+            .mounts()
+            .as_ref()
+            .unwrap()
+            .iter()
+            .map(|mount| mount.source().clone().unwrap())
+            .collect();
+        assert_eq!(
+            sources,
+            [
+                PathBuf::from("/srv/data"),
+                PathBuf::from("/tmp"),
+                PathBuf::from("/srv/data"),
+                PathBuf::from("/srv/cache"),
+            ]
+        );
+
+        helper.release_all(&volumes, "web");
+        let requests: Vec<serde_json::Value> = fs::read_to_string(&log)?
+            .lines()
+            .map(serde_json::from_str)
+            .collect::<std::result::Result<_, _>>()?;
+        let actions: Vec<_> = requests
+            .iter()
+            .map(|r| format!("{} {}", r["action"], r["volume"]))
+            .collect();
+        assert_eq!(
+            actions,
+            [
+                r#""mount" "data""#,
+                r#""mount" "cache""#,
+                r#""unmount" "data""#,
+                r#""unmount" "cache""#
+            ]
+        );
+        assert_eq!(requests[0]["containerId"], "web");
+        assert_eq!(requests[0]["version"], "1");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_helper_errors() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let mut spec = SpecBuilder::default()
+            .mounts(vec![mount("volume://data", "/data")])
+            .build()?;
+
+        let failing =
+            VolumeHelper::new(write_helper(tmp.path(), "echo no such volume >&2\nexit 3")?);
+        match failing.provision(&mut spec.clone(), "web") {
+            Err(VolumeError::Failed { stderr, .. }) => assert_eq!(stderr, "no such volume"),
+            other => panic!("unexpected result {other:?}"),
+        }
+
+        let relative = VolumeHelper::new(write_helper(tmp.path(), r#"echo '{"path": "data"}'"#)?);
+        assert!(matches!(
+            relative.provision(&mut spec.clone(), "web"),
+            Err(VolumeError::RelativePath { .. })
+        ));
+
+        let slow = VolumeHelper::new(write_helper(tmp.path(), "exec sleep 10")?)
+            .with_timeout(Duration::from_millis(100));
+        assert!(matches!(
+            slow.provision(&mut spec, "web"),
+            Err(VolumeError::Timeout { .. })
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_ensure_no_volumes() -> Result<()> {
+        let spec = SpecBuilder::default()
+            .mounts(vec![mount("/tmp", "/tmp")])
+            .build()?;
+        ensure_no_volumes(&spec)?;
+
+        let spec = SpecBuilder::default()
+            .mounts(vec![mount("volume://data", "/data")])
+            .build()?;
+        assert!(matches!(
+            ensure_no_volumes(&spec),
+            Err(VolumeError::NoHelper { .. })
+        ));
+
+        Ok(())
+    }
+}
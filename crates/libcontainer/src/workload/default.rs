@@ -32,13 +32,10 @@ impl Executor for DefaultExecutor {
             .collect();
         unistd::execvp(&cstring_path, &a).map_err(|err| {
             tracing::error!(?err, filename = ?cstring_path, args = ?a, "failed to execvp");
-            ExecutorError::Execution(
-                format!(
-                    "error '{}' executing '{:?}' with args '{:?}'",
-                    err, cstring_path, a
-                )
-                .into(),
-            )
+            ExecutorError::Exec {
+                executable: executable.to_owned(),
+                err,
+            }
         })?;
 
         // After execvp is called, the process is replaced with the container
@@ -47,6 +44,15 @@ impl Executor for DefaultExecutor {
     }
 
     fn validate(&self, spec: &Spec) -> Result<(), ExecutorValidationError> {
+        // a container asking for a virtual machine must not run on the host
+        // kernel instead, see the vm executor
+        if spec.vm().is_some() {
+            tracing::error!("spec has a vm section, which the default executor can't run");
+            Err(ExecutorValidationError::ArgValidationError(
+                "spec has a vm section, which the default executor can't run".into(),
+            ))?;
+        }
+
         let proc = spec
             .process()
             .as_ref()
@@ -70,10 +76,7 @@ impl Executor for DefaultExecutor {
                         executable = ?args[0],
                         "executable for container process not found in PATH",
                     );
-                    Err(ExecutorValidationError::ArgValidationError(format!(
-                        "executable '{}' not found in $PATH",
-                        args[0]
-                    )))?;
+                    Err(ExecutorValidationError::NotFound(args[0].clone()))?;
                 }
                 Some(path) => match is_executable(&path) {
                     Ok(true) => {
@@ -84,10 +87,10 @@ impl Executor for DefaultExecutor {
                             executable = ?path,
                             "executable does not have the correct permission set",
                         );
-                        Err(ExecutorValidationError::ArgValidationError(format!(
-                            "executable '{}' at path '{:?}' does not have correct permissions",
-                            args[0], path
-                        )))?;
+                        Err(ExecutorValidationError::NotExecutable {
+                            executable: args[0].clone(),
+                            path,
+                        })?;
                     }
                     Err(err) => {
                         tracing::error!(
@@ -141,9 +144,11 @@ mod tests {
     use std::collections::HashMap;
     use std::env;
 
+    use nix::errno::Errno;
     use serial_test::serial;
 
     use super::*;
+    use crate::workload::ExecFailureKind;
 
     #[test]
     fn test_get_executable_path() {
@@ -183,6 +188,25 @@ mod tests {
         assert!(!is_executable(directory_path).unwrap());
     }
 
+    #[test]
+    fn test_exec_error_kind() {
+        let exec_error = |err| ExecutorError::Exec {
+            executable: "/bin/app".to_owned(),
+            err,
+        };
+        assert_eq!(exec_error(Errno::ENOENT).kind(), ExecFailureKind::NotFound);
+        assert_eq!(
+            exec_error(Errno::EACCES).kind(),
+            ExecFailureKind::NotExecutable
+        );
+        assert_eq!(
+            exec_error(Errno::ENOEXEC).kind(),
+            ExecFailureKind::NotExecutable
+        );
+        assert_eq!(exec_error(Errno::ENOMEM).kind(), ExecFailureKind::Other);
+        assert_eq!(ExecutorError::InvalidArg.kind(), ExecFailureKind::Other);
+    }
+
     #[test]
     #[serial]
     fn test_executor_set_envs() {
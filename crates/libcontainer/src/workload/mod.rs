@@ -1,9 +1,14 @@
 use std::collections::HashMap;
-use std::env;
+use std::path::PathBuf;
+use std::{env, fmt};
 
+use nix::errno::Errno;
 use oci_spec::runtime::Spec;
+use serde::{Deserialize, Serialize};
 
 pub mod default;
+pub mod pause;
+pub mod vm;
 
 pub static EMPTY: Vec<String> = Vec::new();
 
@@ -17,6 +22,23 @@ pub enum ExecutorError {
     Other(String),
     #[error("{0} executor can't handle spec")]
     CantHandle(&'static str),
+    #[error("error '{err}' executing {executable:?}")]
+    Exec { executable: String, err: Errno },
+}
+
+impl ExecutorError {
+    pub fn kind(&self) -> ExecFailureKind {
+        match self {
+            Self::Exec {
+                err: Errno::ENOENT, ..
+            } => ExecFailureKind::NotFound,
+            Self::Exec {
+                err: Errno::EACCES | Errno::EPERM | Errno::ENOEXEC,
+                ..
+            } => ExecFailureKind::NotExecutable,
+            _ => ExecFailureKind::Other,
+        }
+    }
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -25,6 +47,66 @@ pub enum ExecutorValidationError {
     CantHandle(&'static str),
     #[error("{0}")]
     ArgValidationError(String),
+    #[error("executable '{0}' not found in $PATH")]
+    NotFound(String),
+    #[error("executable '{executable}' at path '{path:?}' does not have correct permissions")]
+    NotExecutable { executable: String, path: PathBuf },
+}
+
+impl ExecutorValidationError {
+    pub fn kind(&self) -> ExecFailureKind {
+        match self {
+            Self::NotFound(_) => ExecFailureKind::NotFound,
+            Self::NotExecutable { .. } => ExecFailureKind::NotExecutable,
+            _ => ExecFailureKind::Other,
+        }
+    }
+}
+
+/// Why the process of a container could not be executed. Command line
+/// runtimes tell the cases apart with the exit codes of shells, 127 for a
+/// command which was not found and 126 for one which cannot be executed.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum ExecFailureKind {
+    NotFound,
+    NotExecutable,
+    Other,
+}
+
+impl ExecFailureKind {
+    /// Exit code of a shell failing to execute a command for this reason, if
+    /// it has a conventional one
+    pub fn exit_code(&self) -> Option<i32> {
+        match self {
+            Self::NotFound => Some(127),
+            Self::NotExecutable => Some(126),
+            Self::Other => None,
+        }
+    }
+}
+
+/// Failure to set up or execute the process of a container, as sent from the
+/// container process to youki
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ExecFailure {
+    pub kind: ExecFailureKind,
+    pub message: String,
+}
+
+impl ExecFailure {
+    pub fn new(kind: ExecFailureKind, message: impl Into<String>) -> Self {
+        Self {
+            kind,
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for ExecFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
 }
 
 #[derive(Debug, thiserror::Error)]
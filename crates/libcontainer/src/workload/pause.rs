@@ -0,0 +1,450 @@
+//! A pause process built into the runtime, for containers which only hold
+//! namespaces, e.g. the sandbox of a pod, and for tests which need a
+//! container that runs until it is stopped, without a binary in the rootfs.
+//!
+//! The helper is a few hundred bytes of machine code, which are wrapped in
+//! a static ELF executable, written to a memfd and executed from there, so
+//! it works with an empty rootfs. It:
+//!
+//! - exits with 0 on SIGTERM and SIGINT, which the kernel would otherwise
+//!   ignore as the helper usually is the init of a pid namespace,
+//! - ignores SIGCHLD, so that the kernel reaps the orphans it inherits,
+//! - sleeps for the number of seconds given as its only argument and exits
+//!   with 0, exits with 2 if the argument is not a number, or else pauses
+//!   until it is signaled.
+//!
+//! A spec runs the helper with [`BUILTIN_PAUSE`] as the first argument of
+//! its process, e.g. `["youki:pause", "30"]`. The helper is available on
+//! x86_64 and aarch64.
+use std::ffi::CString;
+use std::os::fd::{AsRawFd, OwnedFd};
+
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+use nix::sys::memfd::{memfd_create, MemFdCreateFlag};
+use nix::unistd;
+use oci_spec::runtime::Spec;
+
+use super::{Executor, ExecutorError, ExecutorValidationError, EMPTY};
+
+const EXECUTOR_NAME: &str = "pause";
+
+/// First argument of the process of a spec which runs the pause helper
+pub const BUILTIN_PAUSE: &str = "youki:pause";
+
+/// Address the helper is loaded at
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+const BASE_ADDRESS: u64 = 0x40_0000;
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+const ELF_HEADER_SIZE: usize = 64;
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+const PROGRAM_HEADER_SIZE: usize = 56;
+
+// x86_64:
+//
+//      mov rbx, rsp                    ; argc, argv
+//      sub rsp, 0x20                   ; struct sigaction
+//      mov r12, rsp
+//      lea rax, [rip + exit_ok]
+//      mov [r12], rax                  ; sa_handler
+//      mov qword [r12 + 0x08], 0x4000000 ; sa_flags = SA_RESTORER
+//      mov [r12 + 0x10], rax           ; sa_restorer, never returned to
+//      mov qword [r12 + 0x18], 0       ; sa_mask
+//      mov edi, 15                     ; SIGTERM
+//      call sigaction
+//      mov edi, 2                      ; SIGINT
+//      call sigaction
+//      mov qword [r12], 1              ; SIG_IGN
+//      mov edi, 17                     ; SIGCHLD
+//      call sigaction
+//      cmp qword [rbx], 2
+//      jb pause_loop
+//      mov rsi, [rbx + 0x10]           ; argv[1]
+//      xor eax, eax
+//  parse:
+//      movzx ecx, byte [rsi]
+//      test ecx, ecx
+//      je sleep
+//      sub ecx, '0'
+//      cmp ecx, 9
+//      ja exit_usage
+//      imul rax, rax, 10
+//      add rax, rcx
+//      inc rsi
+//      jmp parse
+//  sleep:
+//      mov [r12], rax                  ; tv_sec
+//      mov qword [r12 + 0x08], 0       ; tv_nsec
+//      mov rdi, r12
+//      xor esi, esi
+//      mov eax, 35                     ; nanosleep
+//      syscall
+//      jmp exit_ok
+//  pause_loop:
+//      mov eax, 34                     ; pause
+//      syscall
+//      jmp pause_loop
+//  sigaction:
+//      mov eax, 13                     ; rt_sigaction
+//      mov rsi, r12
+//      xor edx, edx
+//      mov r10d, 8
+//      syscall
+//      ret
+//  exit_usage:
+//      mov edi, 2
+//      jmp exit
+//  exit_ok:
+//      xor edi, edi
+//  exit:
+//      mov eax, 231                    ; exit_group
+//      syscall
+#[cfg(target_arch = "x86_64")]
+const MACHINE: u16 = 62;
+#[cfg(target_arch = "x86_64")]
+const CODE: &[u8] = &[
+    0x48, 0x89, 0xe3, 0x48, 0x83, 0xec, 0x20, 0x49, 0x89, 0xe4, 0x48, 0x8d, 0x05, 0xa6, 0x00, 0x00,
+    0x00, 0x49, 0x89, 0x04, 0x24, 0x49, 0xc7, 0x44, 0x24, 0x08, 0x00, 0x00, 0x00, 0x04, 0x49, 0x89,
+    0x44, 0x24, 0x10, 0x49, 0xc7, 0x44, 0x24, 0x18, 0x00, 0x00, 0x00, 0x00, 0xbf, 0x0f, 0x00, 0x00,
+    0x00, 0xe8, 0x67, 0x00, 0x00, 0x00, 0xbf, 0x02, 0x00, 0x00, 0x00, 0xe8, 0x5d, 0x00, 0x00, 0x00,
+    0x49, 0xc7, 0x04, 0x24, 0x01, 0x00, 0x00, 0x00, 0xbf, 0x11, 0x00, 0x00, 0x00, 0xe8, 0x4b, 0x00,
+    0x00, 0x00, 0x48, 0x83, 0x3b, 0x02, 0x72, 0x3c, 0x48, 0x8b, 0x73, 0x10, 0x31, 0xc0, 0x0f, 0xb6,
+    0x0e, 0x85, 0xc9, 0x74, 0x14, 0x83, 0xe9, 0x30, 0x83, 0xf9, 0x09, 0x77, 0x43, 0x48, 0x6b, 0xc0,
+    0x0a, 0x48, 0x01, 0xc8, 0x48, 0xff, 0xc6, 0xeb, 0xe5, 0x49, 0x89, 0x04, 0x24, 0x49, 0xc7, 0x44,
+    0x24, 0x08, 0x00, 0x00, 0x00, 0x00, 0x4c, 0x89, 0xe7, 0x31, 0xf6, 0xb8, 0x23, 0x00, 0x00, 0x00,
+    0x0f, 0x05, 0xeb, 0x23, 0xb8, 0x22, 0x00, 0x00, 0x00, 0x0f, 0x05, 0xeb, 0xf7, 0xb8, 0x0d, 0x00,
+    0x00, 0x00, 0x4c, 0x89, 0xe6, 0x31, 0xd2, 0x41, 0xba, 0x08, 0x00, 0x00, 0x00, 0x0f, 0x05, 0xc3,
+    0xbf, 0x02, 0x00, 0x00, 0x00, 0xeb, 0x02, 0x31, 0xff, 0xb8, 0xe7, 0x00, 0x00, 0x00, 0x0f, 0x05,
+];
+
+// aarch64, the same program with ppoll instead of pause, which aarch64
+// does not have:
+//
+//      mov x19, sp                     // argc, argv
+//      sub sp, sp, #32                 // struct sigaction
+//      mov x20, sp
+//      adr x0, exit_ok
+//      stp x0, xzr, [x20]              // sa_handler, sa_flags
+//      stp xzr, xzr, [x20, #16]        // sa_restorer, sa_mask
+//      mov x0, #15                     // SIGTERM
+//      bl sigaction
+//      mov x0, #2                      // SIGINT
+//      bl sigaction
+//      mov x0, #1                      // SIG_IGN
+//      str x0, [x20]
+//      mov x0, #17                     // SIGCHLD
+//      bl sigaction
+//      ldr x0, [x19]
+//      cmp x0, #2
+//      b.lo pause_loop
+//      ldr x1, [x19, #16]              // argv[1]
+//      mov x0, #0
+//      mov x3, #10
+//  parse:
+//      ldrb w2, [x1], #1
+//      cbz w2, sleep
+//      sub w2, w2, #'0'
+//      cmp w2, #9
+//      b.hi exit_usage
+//      madd x0, x0, x3, x2
+//      b parse
+//  sleep:
+//      stp x0, xzr, [x20]              // tv_sec, tv_nsec
+//      mov x0, x20
+//      mov x1, #0
+//      mov x8, #101                    // nanosleep
+//      svc #0
+//      b exit_ok
+//  pause_loop:
+//      mov x0, #0
+//      mov x1, #0
+//      mov x2, #0
+//      mov x3, #0
+//      mov x4, #0
+//      mov x8, #73                     // ppoll
+//      svc #0
+//      b pause_loop
+//  sigaction:
+//      mov x1, x20
+//      mov x2, #0
+//      mov x3, #8
+//      mov x8, #134                    // rt_sigaction
+//      svc #0
+//      ret
+//  exit_usage:
+//      mov x0, #2
+//      b exit
+//  exit_ok:
+//      mov x0, #0
+//  exit:
+//      mov x8, #94                     // exit_group
+//      svc #0
+#[cfg(target_arch = "aarch64")]
+const MACHINE: u16 = 183;
+#[cfg(target_arch = "aarch64")]
+const CODE: &[u8] = &[
+    0xf3, 0x03, 0x00, 0x91, 0xff, 0x83, 0x00, 0xd1, 0xf4, 0x03, 0x00, 0x91, 0xc0, 0x05, 0x00, 0x10,
+    0x80, 0x7e, 0x00, 0xa9, 0x9f, 0x7e, 0x01, 0xa9, 0xe0, 0x01, 0x80, 0xd2, 0x22, 0x00, 0x00, 0x94,
+    0x40, 0x00, 0x80, 0xd2, 0x20, 0x00, 0x00, 0x94, 0x20, 0x00, 0x80, 0xd2, 0x80, 0x02, 0x00, 0xf9,
+    0x20, 0x02, 0x80, 0xd2, 0x1c, 0x00, 0x00, 0x94, 0x60, 0x02, 0x40, 0xf9, 0x1f, 0x08, 0x00, 0xf1,
+    0x23, 0x02, 0x00, 0x54, 0x61, 0x0a, 0x40, 0xf9, 0x00, 0x00, 0x80, 0xd2, 0x43, 0x01, 0x80, 0xd2,
+    0x22, 0x14, 0x40, 0x38, 0xc2, 0x00, 0x00, 0x34, 0x42, 0xc0, 0x00, 0x51, 0x5f, 0x24, 0x00, 0x71,
+    0xe8, 0x02, 0x00, 0x54, 0x00, 0x08, 0x03, 0x9b, 0xfa, 0xff, 0xff, 0x17, 0x80, 0x7e, 0x00, 0xa9,
+    0xe0, 0x03, 0x14, 0xaa, 0x01, 0x00, 0x80, 0xd2, 0xa8, 0x0c, 0x80, 0xd2, 0x01, 0x00, 0x00, 0xd4,
+    0x11, 0x00, 0x00, 0x14, 0x00, 0x00, 0x80, 0xd2, 0x01, 0x00, 0x80, 0xd2, 0x02, 0x00, 0x80, 0xd2,
+    0x03, 0x00, 0x80, 0xd2, 0x04, 0x00, 0x80, 0xd2, 0x28, 0x09, 0x80, 0xd2, 0x01, 0x00, 0x00, 0xd4,
+    0xf9, 0xff, 0xff, 0x17, 0xe1, 0x03, 0x14, 0xaa, 0x02, 0x00, 0x80, 0xd2, 0x03, 0x01, 0x80, 0xd2,
+    0xc8, 0x10, 0x80, 0xd2, 0x01, 0x00, 0x00, 0xd4, 0xc0, 0x03, 0x5f, 0xd6, 0x40, 0x00, 0x80, 0xd2,
+    0x02, 0x00, 0x00, 0x14, 0x00, 0x00, 0x80, 0xd2, 0xc8, 0x0b, 0x80, 0xd2, 0x01, 0x00, 0x00, 0xd4,
+];
+
+/// Whether the helper is available on this architecture
+pub fn is_available() -> bool {
+    cfg!(any(target_arch = "x86_64", target_arch = "aarch64"))
+}
+
+/// Wraps the code in an ELF executable with one loadable segment, which
+/// maps the whole file, and a non-executable stack.
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+fn elf() -> Vec<u8> {
+    const PT_LOAD: u32 = 1;
+    const PT_GNU_STACK: u32 = 0x6474_e551;
+    const PF_X: u32 = 1;
+    const PF_W: u32 = 2;
+    const PF_R: u32 = 4;
+
+    let code_offset = ELF_HEADER_SIZE + 2 * PROGRAM_HEADER_SIZE;
+    let size = (code_offset + CODE.len()) as u64;
+    let mut elf = Vec::with_capacity(code_offset + CODE.len());
+
+    // ELF64, little endian, version 1, System V ABI
+    elf.extend_from_slice(&[0x7f, b'E', b'L', b'F', 2, 1, 1, 0]);
+    elf.extend_from_slice(&[0; 8]);
+    elf.extend_from_slice(&2u16.to_le_bytes()); // ET_EXEC
+    elf.extend_from_slice(&MACHINE.to_le_bytes());
+    elf.extend_from_slice(&1u32.to_le_bytes());
+    elf.extend_from_slice(&(BASE_ADDRESS + code_offset as u64).to_le_bytes());
+    elf.extend_from_slice(&(ELF_HEADER_SIZE as u64).to_le_bytes()); // program headers
+    elf.extend_from_slice(&0u64.to_le_bytes()); // no section headers
+    elf.extend_from_slice(&0u32.to_le_bytes());
+    elf.extend_from_slice(&(ELF_HEADER_SIZE as u16).to_le_bytes());
+    elf.extend_from_slice(&(PROGRAM_HEADER_SIZE as u16).to_le_bytes());
+    elf.extend_from_slice(&2u16.to_le_bytes());
+    elf.extend_from_slice(&[0; 6]);
+
+    for (kind, flags, size, align) in [
+        (PT_LOAD, PF_R | PF_X, size, 0x1000u64),
+        (PT_GNU_STACK, PF_R | PF_W, 0, 0x10),
+    ] {
+        let address = if kind == PT_LOAD { BASE_ADDRESS } else { 0 };
+        elf.extend_from_slice(&kind.to_le_bytes());
+        elf.extend_from_slice(&flags.to_le_bytes());
+        elf.extend_from_slice(&0u64.to_le_bytes()); // offset
+        elf.extend_from_slice(&address.to_le_bytes());
+        elf.extend_from_slice(&address.to_le_bytes());
+        elf.extend_from_slice(&size.to_le_bytes());
+        elf.extend_from_slice(&size.to_le_bytes());
+        elf.extend_from_slice(&align.to_le_bytes());
+    }
+
+    elf.extend_from_slice(CODE);
+    elf
+}
+
+/// Memfd with the executable of the helper, closed on exec
+#[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+pub fn helper() -> Result<OwnedFd, ExecutorError> {
+    let name = CString::new("youki-pause").unwrap_or_default();
+    let fd = memfd_create(&name, MemFdCreateFlag::MFD_CLOEXEC).map_err(|err| {
+        tracing::error!(?err, "failed to create the memfd of the pause helper");
+        ExecutorError::Other(format!("failed to create memfd: {err}"))
+    })?;
+    let elf = elf();
+    let mut written = 0;
+    while written < elf.len() {
+        written += unistd::write(&fd, &elf[written..]).map_err(|err| {
+            tracing::error!(?err, "failed to write the pause helper");
+            ExecutorError::Other(format!("failed to write the pause helper: {err}"))
+        })?;
+    }
+
+    Ok(fd)
+}
+
+/// Memfd with the executable of the helper, closed on exec
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+pub fn helper() -> Result<OwnedFd, ExecutorError> {
+    Err(ExecutorError::Other(format!(
+        "the pause helper is not available on {}",
+        std::env::consts::ARCH
+    )))
+}
+
+/// Checks the arguments of the helper, at most a number of seconds
+fn validate_args(args: &[String]) -> Result<(), String> {
+    match args {
+        [] => Ok(()),
+        [seconds] if !seconds.is_empty() && seconds.bytes().all(|b| b.is_ascii_digit()) => seconds
+            .parse::<u32>()
+            .map(|_| ())
+            .map_err(|_| format!("{BUILTIN_PAUSE} can sleep at most {} seconds", u32::MAX)),
+        [arg] => Err(format!(
+            "{BUILTIN_PAUSE} takes a number of seconds, got {arg:?}"
+        )),
+        _ => Err(format!("{BUILTIN_PAUSE} takes at most one argument")),
+    }
+}
+
+/// Runs the pause helper for specs whose process starts with
+/// [`BUILTIN_PAUSE`]. Other specs are left to the next executor, unless
+/// the executor is [`forced`](Self::forced).
+#[derive(Clone, Default)]
+pub struct PauseExecutor {
+    forced: bool,
+}
+
+impl PauseExecutor {
+    pub fn new() -> Self {
+        Self { forced: false }
+    }
+
+    /// An executor which runs the helper for every spec, in place of the
+    /// process of the spec
+    pub fn forced() -> Self {
+        Self { forced: true }
+    }
+
+    /// Arguments of the helper for the spec, none if the spec is left to
+    /// the next executor
+    fn args<'a>(&self, spec: &'a Spec) -> Option<&'a [String]> {
+        let args = spec
+            .process()
+            .as_ref()
+            .and_then(|process| process.args().as_ref())
+            .unwrap_or(&EMPTY);
+        match args.split_first() {
+            Some((first, rest)) if first == BUILTIN_PAUSE => Some(rest),
+            _ if self.forced => Some(&[]),
+            _ => None,
+        }
+    }
+}
+
+impl Executor for PauseExecutor {
+    fn exec(&self, spec: &Spec) -> Result<(), ExecutorError> {
+        let args = self
+            .args(spec)
+            .ok_or(ExecutorError::CantHandle(EXECUTOR_NAME))?;
+        validate_args(args).map_err(|err| {
+            tracing::error!(%err, "invalid arguments of the pause helper");
+            ExecutorError::Other(err)
+        })?;
+
+        tracing::debug!(?args, "executing workload with pause handler");
+        let fd = helper()?;
+        let args: Vec<CString> = std::iter::once("pause")
+            .chain(args.iter().map(String::as_str))
+            .map(|arg| CString::new(arg).unwrap_or_default())
+            .collect();
+        let envs: Vec<CString> = std::env::vars()
+            .filter_map(|(key, value)| CString::new(format!("{key}={value}")).ok())
+            .collect();
+        unistd::fexecve(fd.as_raw_fd(), &args, &envs).map_err(|err| {
+            tracing::error!(?err, "failed to execute the pause helper");
+            ExecutorError::Exec {
+                executable: BUILTIN_PAUSE.to_owned(),
+                err,
+            }
+        })?;
+
+        unreachable!();
+    }
+
+    fn validate(&self, spec: &Spec) -> Result<(), ExecutorValidationError> {
+        let args = self
+            .args(spec)
+            .ok_or(ExecutorValidationError::CantHandle(EXECUTOR_NAME))?;
+        if !is_available() {
+            tracing::error!("the pause helper is not available on this architecture");
+            Err(ExecutorValidationError::ArgValidationError(format!(
+                "the pause helper is not available on {}",
+                std::env::consts::ARCH
+            )))?;
+        }
+        validate_args(args).map_err(|err| {
+            tracing::error!(%err, "invalid arguments of the pause helper");
+            ExecutorValidationError::ArgValidationError(err)
+        })?;
+
+        Ok(())
+    }
+}
+
+pub fn get_executor() -> Box<dyn Executor> {
+    Box::new(PauseExecutor::new())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::process::ExitStatusExt;
+    use std::process::Command;
+    use std::thread;
+    use std::time::Duration;
+
+    use anyhow::Result;
+    use nix::sys::signal::{kill, Signal};
+    use nix::unistd::Pid;
+    use oci_spec::runtime::{ProcessBuilder, SpecBuilder};
+
+    use super::*;
+
+    fn spec(args: &[&str]) -> Result<Spec> {
+        let process = ProcessBuilder::default()
+            .args(args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>())
+            .build()?;
+        Ok(SpecBuilder::default().process(process).build()?)
+    }
+
+    #[test]
+    fn test_validate() -> Result<()> {
+        let executor = PauseExecutor::new();
+        assert!(matches!(
+            executor.validate(&spec(&["sh"])?),
+            Err(ExecutorValidationError::CantHandle(_))
+        ));
+        assert!(executor.validate(&spec(&[BUILTIN_PAUSE])?).is_ok());
+        assert!(executor.validate(&spec(&[BUILTIN_PAUSE, "30"])?).is_ok());
+        for args in [
+            &[BUILTIN_PAUSE, ""][..],
+            &[BUILTIN_PAUSE, "1x"],
+            &[BUILTIN_PAUSE, "99999999999"],
+            &[BUILTIN_PAUSE, "1", "2"],
+        ] {
+            assert!(
+                executor.validate(&spec(args)?).is_err(),
+                "{args:?} should be invalid"
+            );
+        }
+        assert!(PauseExecutor::forced().validate(&spec(&["sh"])?).is_ok());
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+    fn test_helper() -> Result<()> {
+        let fd = helper()?;
+        let path = format!("/proc/self/fd/{}", fd.as_raw_fd());
+
+        let status = Command::new(&path).arg("0").status()?;
+        assert_eq!(status.code(), Some(0));
+        let status = Command::new(&path).arg("1x").status()?;
+        assert_eq!(status.code(), Some(2));
+
+        let mut child = Command::new(&path).spawn()?;
+        thread::sleep(Duration::from_millis(100));
+        assert!(child.try_wait()?.is_none(), "the helper should pause");
+        kill(Pid::from_raw(child.id() as i32), Signal::SIGTERM)?;
+        let status = child.wait()?;
+        assert_eq!(status.code(), Some(0), "{:?}", status.signal());
+        Ok(())
+    }
+}
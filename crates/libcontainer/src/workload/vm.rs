@@ -0,0 +1,515 @@
+//! Containers with the `vm` section of the spec, which asks to boot a kernel
+//! in a virtual machine instead of running the process on the kernel of the
+//! host. youki has no hypervisor of its own: [`VmExecutor`] replaces the
+//! process of the container with a VMM command built from a template, or
+//! fails if no template is configured, so that such a container never
+//! silently runs as a plain container.
+//!
+//! A template is a command line whose words may contain placeholders for
+//! the fields of the section, e.g.
+//! `{hypervisor} {hypervisor_params} -kernel {kernel} -append {kernel_params}`:
+//!
+//! - `{hypervisor}`, `{kernel}`, `{initrd}`, `{image}` and `{image_format}`
+//!   are replaced by the fields of the same name, the placeholder fails if
+//!   the spec has no such field,
+//! - `{kernel_params}` is replaced by the parameters of the kernel, joined
+//!   by spaces,
+//! - `{hypervisor_params}` and `{args}` have to be whole words, which are
+//!   replaced by one word per parameter of the hypervisor or argument of
+//!   the process of the container.
+//!
+//! The VMM runs as the process of the container, so the paths of the
+//! section and of the template are resolved in its rootfs.
+use std::ffi::CString;
+use std::fmt;
+use std::path::Path;
+
+use nix::unistd;
+use oci_spec::runtime::{Spec, VM};
+
+use super::{Executor, ExecutorError, ExecutorValidationError, EMPTY};
+
+const EXECUTOR_NAME: &str = "vm";
+
+/// Root image formats of the runtime spec
+const IMAGE_FORMATS: &[&str] = &["raw", "qcow2", "vdi", "vmdk", "vhd"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Placeholder {
+    Hypervisor,
+    HypervisorParams,
+    Kernel,
+    KernelParams,
+    Initrd,
+    Image,
+    ImageFormat,
+    Args,
+}
+
+impl Placeholder {
+    fn parse(name: &str) -> Option<Self> {
+        let placeholder = match name {
+            "hypervisor" => Self::Hypervisor,
+            "hypervisor_params" => Self::HypervisorParams,
+            "kernel" => Self::Kernel,
+            "kernel_params" => Self::KernelParams,
+            "initrd" => Self::Initrd,
+            "image" => Self::Image,
+            "image_format" => Self::ImageFormat,
+            "args" => Self::Args,
+            _ => return None,
+        };
+        Some(placeholder)
+    }
+
+    /// Whether the placeholder expands to any number of words
+    fn is_list(self) -> bool {
+        matches!(self, Self::HypervisorParams | Self::Args)
+    }
+}
+
+impl fmt::Display for Placeholder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::Hypervisor => "hypervisor",
+            Self::HypervisorParams => "hypervisor_params",
+            Self::Kernel => "kernel",
+            Self::KernelParams => "kernel_params",
+            Self::Initrd => "initrd",
+            Self::Image => "image",
+            Self::ImageFormat => "image_format",
+            Self::Args => "args",
+        };
+        write!(f, "{{{name}}}")
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    Literal(String),
+    Placeholder(Placeholder),
+}
+
+#[derive(Debug, thiserror::Error, PartialEq, Eq)]
+pub enum VmmTemplateError {
+    #[error("the vmm command template is empty")]
+    Empty,
+    #[error("the vmm command template expands to no arguments")]
+    EmptyCommand,
+    #[error("unknown placeholder {0:?} in the vmm command template")]
+    UnknownPlaceholder(String),
+    #[error("unclosed placeholder in word {0:?} of the vmm command template")]
+    Unclosed(String),
+    #[error("placeholder {0} has to be a whole word of the vmm command template")]
+    NotWholeWord(String),
+    #[error("placeholder {placeholder} needs {field} in the vm section of the spec")]
+    MissingField {
+        placeholder: String,
+        field: &'static str,
+    },
+}
+
+/// Parsed VMM command template, a word of segments per argument
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct VmmTemplate {
+    words: Vec<Vec<Segment>>,
+}
+
+impl VmmTemplate {
+    fn parse(template: &str) -> Result<Self, VmmTemplateError> {
+        let words = template
+            .split_whitespace()
+            .map(parse_word)
+            .collect::<Result<Vec<_>, _>>()?;
+        if words.is_empty() {
+            return Err(VmmTemplateError::Empty);
+        }
+
+        Ok(Self { words })
+    }
+
+    /// The arguments of the VMM for the section `vm` and the process
+    /// arguments `args`
+    fn command(&self, vm: &VM, args: &[String]) -> Result<Vec<String>, VmmTemplateError> {
+        let mut command = Vec::new();
+        for word in &self.words {
+            if let [Segment::Placeholder(placeholder)] = word.as_slice() {
+                if placeholder.is_list() {
+                    command.extend(list(*placeholder, vm, args));
+                    continue;
+                }
+            }
+
+            let mut arg = String::new();
+            for segment in word {
+                match segment {
+                    Segment::Literal(literal) => arg.push_str(literal),
+                    Segment::Placeholder(placeholder) => arg.push_str(&value(*placeholder, vm)?),
+                }
+            }
+            command.push(arg);
+        }
+        if command.is_empty() {
+            return Err(VmmTemplateError::EmptyCommand);
+        }
+
+        Ok(command)
+    }
+}
+
+fn parse_word(word: &str) -> Result<Vec<Segment>, VmmTemplateError> {
+    let mut segments = Vec::new();
+    let mut rest = word;
+    while let Some(start) = rest.find('{') {
+        if start > 0 {
+            segments.push(Segment::Literal(rest[..start].to_owned()));
+        }
+        let end = rest[start..]
+            .find('}')
+            .ok_or_else(|| VmmTemplateError::Unclosed(word.to_owned()))?;
+        let name = &rest[start + 1..start + end];
+        let placeholder = Placeholder::parse(name)
+            .ok_or_else(|| VmmTemplateError::UnknownPlaceholder(name.to_owned()))?;
+        segments.push(Segment::Placeholder(placeholder));
+        rest = &rest[start + end + 1..];
+    }
+    if !rest.is_empty() {
+        segments.push(Segment::Literal(rest.to_owned()));
+    }
+
+    let list = segments.iter().find_map(|segment| match segment {
+        Segment::Placeholder(placeholder) if placeholder.is_list() => Some(placeholder),
+        _ => None,
+    });
+    if let (Some(placeholder), true) = (list, segments.len() > 1) {
+        return Err(VmmTemplateError::NotWholeWord(placeholder.to_string()));
+    }
+
+    Ok(segments)
+}
+
+fn value(placeholder: Placeholder, vm: &VM) -> Result<String, VmmTemplateError> {
+    let missing = |field| VmmTemplateError::MissingField {
+        placeholder: placeholder.to_string(),
+        field,
+    };
+    let path = |path: &Path| path.to_string_lossy().into_owned();
+    let value = match placeholder {
+        Placeholder::Hypervisor => vm
+            .hypervisor()
+            .as_ref()
+            .map(|hypervisor| path(hypervisor.path()))
+            .ok_or_else(|| missing("hypervisor"))?,
+        Placeholder::Kernel => path(vm.kernel().path()),
+        Placeholder::KernelParams => vm
+            .kernel()
+            .parameters()
+            .as_deref()
+            .unwrap_or_default()
+            .join(" "),
+        Placeholder::Initrd => vm
+            .kernel()
+            .initrd()
+            .clone()
+            .ok_or_else(|| missing("kernel.initrd"))?,
+        Placeholder::Image => vm
+            .image()
+            .as_ref()
+            .map(|image| path(image.path()))
+            .ok_or_else(|| missing("image"))?,
+        Placeholder::ImageFormat => vm
+            .image()
+            .as_ref()
+            .map(|image| image.format().clone())
+            .ok_or_else(|| missing("image"))?,
+        Placeholder::HypervisorParams | Placeholder::Args => unreachable!("list placeholder"),
+    };
+
+    Ok(value)
+}
+
+fn list(placeholder: Placeholder, vm: &VM, args: &[String]) -> Vec<String> {
+    match placeholder {
+        Placeholder::HypervisorParams => vm
+            .hypervisor()
+            .as_ref()
+            .and_then(|hypervisor| hypervisor.parameters().clone())
+            .unwrap_or_default(),
+        Placeholder::Args => args.to_vec(),
+        _ => unreachable!("scalar placeholder"),
+    }
+}
+
+/// Checks the fields the runtime spec requires of the section
+fn validate_vm(vm: &VM) -> Result<(), String> {
+    let absolute = |field: &str, path: &Path| {
+        if path.is_absolute() {
+            Ok(())
+        } else {
+            Err(format!("vm {field} {path:?} is not an absolute path"))
+        }
+    };
+
+    absolute("kernel path", vm.kernel().path())?;
+    if let Some(initrd) = vm.kernel().initrd() {
+        absolute("kernel initrd", Path::new(initrd))?;
+    }
+    if let Some(hypervisor) = vm.hypervisor() {
+        absolute("hypervisor path", hypervisor.path())?;
+    }
+    if let Some(image) = vm.image() {
+        absolute("image path", image.path())?;
+        if !IMAGE_FORMATS.contains(&image.format().as_str()) {
+            return Err(format!(
+                "vm image format {:?} is not one of {}",
+                image.format(),
+                IMAGE_FORMATS.join(", ")
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// Executes the VMM command for specs with a `vm` section, see [`vm`](self).
+/// Other specs are left to the next executor.
+#[derive(Clone, Default)]
+pub struct VmExecutor {
+    template: Option<String>,
+}
+
+impl VmExecutor {
+    /// An executor with the VMM command template `template`, without one
+    /// specs with a `vm` section fail
+    pub fn new(template: Option<String>) -> Self {
+        Self { template }
+    }
+
+    fn command(&self, spec: &Spec, vm: &VM) -> Result<Vec<String>, String> {
+        validate_vm(vm)?;
+        let template = self.template.as_deref().ok_or_else(|| {
+            "the spec has a vm section, but no vmm command is configured to run it".to_owned()
+        })?;
+        let args = spec
+            .process()
+            .as_ref()
+            .and_then(|process| process.args().as_ref())
+            .unwrap_or(&EMPTY);
+
+        VmmTemplate::parse(template)
+            .and_then(|template| template.command(vm, args))
+            .map_err(|err| err.to_string())
+    }
+}
+
+impl Executor for VmExecutor {
+    fn exec(&self, spec: &Spec) -> Result<(), ExecutorError> {
+        let vm = spec
+            .vm()
+            .as_ref()
+            .ok_or(ExecutorError::CantHandle(EXECUTOR_NAME))?;
+        let command = self.command(spec, vm).map_err(|err| {
+            tracing::error!(%err, "failed to build the vmm command");
+            ExecutorError::Other(err)
+        })?;
+
+        tracing::debug!(?command, "executing workload with vm handler");
+        let executable = command[0].as_str();
+        let args: Vec<CString> = command
+            .iter()
+            .map(|arg| CString::new(arg.as_bytes()))
+            .collect::<Result<_, _>>()
+            .map_err(|err| {
+                tracing::error!(?err, ?command, "vmm command contains a nul byte");
+                ExecutorError::InvalidArg
+            })?;
+        unistd::execvp(&args[0], &args).map_err(|err| {
+            tracing::error!(?err, ?command, "failed to execvp the vmm");
+            ExecutorError::Exec {
+                executable: executable.to_owned(),
+                err,
+            }
+        })?;
+
+        unreachable!();
+    }
+
+    fn validate(&self, spec: &Spec) -> Result<(), ExecutorValidationError> {
+        let vm = spec
+            .vm()
+            .as_ref()
+            .ok_or(ExecutorValidationError::CantHandle(EXECUTOR_NAME))?;
+        self.command(spec, vm).map_err(|err| {
+            tracing::error!(%err, "invalid vm section or vmm command");
+            ExecutorValidationError::ArgValidationError(err)
+        })?;
+
+        Ok(())
+    }
+}
+
+pub fn get_executor(template: Option<String>) -> Box<dyn Executor> {
+    Box::new(VmExecutor::new(template))
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use oci_spec::runtime::{
+        ProcessBuilder, SpecBuilder, VMBuilder, VMHypervisorBuilder, VMImageBuilder,
+        VMKernelBuilder,
+    };
+
+    use super::*;
+
+    fn vm() -> Result<VM> {
+        Ok(VMBuilder::default()
+            .hypervisor(
+                VMHypervisorBuilder::default()
+                    .path("/usr/bin/qemu-system-x86_64")
+                    .parameters(vec!["-m".to_owned(), "512".to_owned()])
+                    .build()?,
+            )
+            .kernel(
+                VMKernelBuilder::default()
+                    .path("/boot/vmlinuz")
+                    .parameters(vec!["console=ttyS0".to_owned(), "quiet".to_owned()])
+                    .build()?,
+            )
+            .image(
+                VMImageBuilder::default()
+                    .path("/images/root.qcow2")
+                    .format("qcow2")
+                    .build()?,
+            )
+            .build()?)
+    }
+
+    fn spec(vm: Option<VM>) -> Result<Spec> {
+        let mut builder = SpecBuilder::default().process(
+            ProcessBuilder::default()
+                .args(vec!["/init".to_owned(), "--debug".to_owned()])
+                .build()?,
+        );
+        if let Some(vm) = vm {
+            builder = builder.vm(vm);
+        }
+        Ok(builder.build()?)
+    }
+
+    #[test]
+    fn test_parse_template() {
+        assert_eq!(VmmTemplate::parse("  "), Err(VmmTemplateError::Empty));
+        assert_eq!(
+            VmmTemplate::parse("qemu {kernal}"),
+            Err(VmmTemplateError::UnknownPlaceholder("kernal".to_owned()))
+        );
+        assert_eq!(
+            VmmTemplate::parse("qemu -kernel={kernel"),
+            Err(VmmTemplateError::Unclosed("-kernel={kernel".to_owned()))
+        );
+        assert_eq!(
+            VmmTemplate::parse("qemu --args={args}"),
+            Err(VmmTemplateError::NotWholeWord("{args}".to_owned()))
+        );
+    }
+
+    #[test]
+    fn test_command() -> Result<()> {
+        let template = VmmTemplate::parse(
+            "{hypervisor} {hypervisor_params} -kernel {kernel} -append {kernel_params} \
+             -drive file={image},format={image_format} -- {args}",
+        )?;
+        let args = ["/init".to_owned(), "--debug".to_owned()];
+        assert_eq!(
+            template.command(&vm()?, &args)?,
+            vec![
+                "/usr/bin/qemu-system-x86_64",
+                "-m",
+                "512",
+                "-kernel",
+                "/boot/vmlinuz",
+                "-append",
+                "console=ttyS0 quiet",
+                "-drive",
+                "file=/images/root.qcow2,format=qcow2",
+                "--",
+                "/init",
+                "--debug",
+            ]
+        );
+
+        let template = VmmTemplate::parse("vmm -initrd {initrd}")?;
+        assert_eq!(
+            template.command(&vm()?, &args),
+            Err(VmmTemplateError::MissingField {
+                placeholder: "{initrd}".to_owned(),
+                field: "kernel.initrd",
+            })
+        );
+
+        let template = VmmTemplate::parse("{hypervisor_params} {args}")?;
+        let mut vm = vm()?;
+        vm.set_hypervisor(Some(
+            VMHypervisorBuilder::default()
+                .path("/usr/bin/qemu-system-x86_64")
+                .build()?,
+        ));
+        assert_eq!(
+            template.command(&vm, &[]),
+            Err(VmmTemplateError::EmptyCommand)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_exec_empty_command() -> Result<()> {
+        let executor = VmExecutor::new(Some("{args}".to_owned()));
+        let spec = SpecBuilder::default()
+            .process(ProcessBuilder::default().args(vec![]).build()?)
+            .vm(vm()?)
+            .build()?;
+        assert!(matches!(
+            executor.exec(&spec),
+            Err(ExecutorError::Other(err)) if err == VmmTemplateError::EmptyCommand.to_string()
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_validate() -> Result<()> {
+        let template = Some("{hypervisor} -kernel {kernel}".to_owned());
+        let executor = VmExecutor::new(template.clone());
+        assert!(matches!(
+            executor.validate(&spec(None)?),
+            Err(ExecutorValidationError::CantHandle(EXECUTOR_NAME))
+        ));
+        executor.validate(&spec(Some(vm()?))?)?;
+
+        let unconfigured = VmExecutor::new(None);
+        assert!(matches!(
+            unconfigured.validate(&spec(Some(vm()?))?),
+            Err(ExecutorValidationError::ArgValidationError(_))
+        ));
+
+        let mut relative = vm()?;
+        relative.set_kernel(VMKernelBuilder::default().path("vmlinuz").build()?);
+        assert!(matches!(
+            executor.validate(&spec(Some(relative))?),
+            Err(ExecutorValidationError::ArgValidationError(_))
+        ));
+
+        let mut unknown_format = vm()?;
+        unknown_format.set_image(Some(
+            VMImageBuilder::default()
+                .path("/images/root.iso")
+                .format("iso")
+                .build()?,
+        ));
+        assert!(matches!(
+            executor.validate(&spec(Some(unknown_format))?),
+            Err(ExecutorValidationError::ArgValidationError(_))
+        ));
+        Ok(())
+    }
+}
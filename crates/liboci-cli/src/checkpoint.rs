@@ -30,9 +30,9 @@ pub struct Checkpoint {
     /// Use lazy migration mechanism
     #[clap(long)]
     pub lazy_pages: bool,
-    /// Pass a file descriptor fd to criu
+    /// Report the progress of criu and the outcome of the checkpoint to fd, one JSON object per line
     #[clap(long)]
-    pub status_fd: Option<u32>, // TODO: Is u32 the right type?
+    pub status_fd: Option<u32>,
     /// Start a page server at the given URL
     #[clap(long)]
     pub page_server: Option<String>,
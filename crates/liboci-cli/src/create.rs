@@ -13,6 +13,9 @@ pub struct Create {
     /// Unix socket (file) path , which will receive file descriptor of the writing end of the pseudoterminal
     #[clap(short, long)]
     pub console_socket: Option<PathBuf>,
+    /// Append the console output of the container to this file, while still passing the console to the console socket
+    #[clap(long, requires = "console_socket")]
+    pub console_log: Option<PathBuf>,
     /// File to write pid of the container created
     // note that in the end, container is just another process
     #[clap(short, long)]
@@ -26,6 +29,33 @@ pub struct Create {
     /// Pass N additional file descriptors to the container (stdio + $LISTEN_FDS + N in total)
     #[clap(long, default_value = "0")]
     pub preserve_fds: i32,
+    /// What happens to the container processes if youki dies while creating the container
+    #[clap(long, value_parser = ["keep", "kill"])]
+    pub orphan_policy: Option<String>,
+    /// Default rlimit for resource types the spec does not limit, as type=soft[:hard], or only type to use the current limits of youki
+    #[clap(long)]
+    pub default_ulimit: Vec<String>,
+    /// Helper binary which provisions the mounts with volume:// sources
+    #[clap(long)]
+    pub volume_helper: Option<PathBuf>,
+    /// Seconds the volume helper may take to provision one volume
+    #[clap(long, default_value = "30", requires = "volume_helper")]
+    pub volume_helper_timeout: u64,
+    /// Seconds the creation of the container may take, its processes are killed and the creation is rolled back after that
+    #[clap(long)]
+    pub timeout: Option<u64>,
+    /// Seconds one phase of the creation may take, as phase=seconds for the phases intermediate, init, hooks and seccomp-listener
+    #[clap(long)]
+    pub phase_timeout: Vec<String>,
+    /// Keep a report of the stages and the failure of the container init process in the debug directory of the bundle
+    #[clap(long)]
+    pub debug_capture: bool,
+    /// Let the container init process dump its core if it crashes once started, where to is up to kernel.core_pattern of the host
+    #[clap(long, requires = "debug_capture")]
+    pub debug_core_dumps: bool,
+    /// Record the syscalls youki makes to set up the container, with their arguments, results and durations, in the debug directory of the bundle
+    #[clap(long)]
+    pub trace_setup: bool,
 
     /// Name of the container instance to be started
     #[clap(value_parser = clap::builder::NonEmptyStringValueParser::new(), required = true)]
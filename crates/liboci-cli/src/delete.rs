@@ -8,4 +8,7 @@ pub struct Delete {
     /// forces deletion of the container if it is still running (using SIGKILL)
     #[clap(short, long)]
     pub force: bool,
+    /// Keep the state, the spec and the logs of the container under the finished directory of the root
+    #[clap(long)]
+    pub keep_state: bool,
 }
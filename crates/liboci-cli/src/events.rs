@@ -9,7 +9,16 @@ pub struct Events {
     /// Display the container stats only once
     #[clap(long)]
     pub stats: bool,
+    /// Output format of the stats
+    #[clap(long, default_value = "json", value_parser = ["json", "table"])]
+    pub format: String,
+    /// Only show the given groups of stats
+    #[clap(long, value_delimiter = ',', value_parser = ["cpu", "mem", "pids", "io"])]
+    pub fields: Vec<String>,
+    /// Show the stats of all running containers
+    #[clap(long, conflicts_with = "container_id")]
+    pub all: bool,
     /// Name of the container instance
-    #[clap(value_parser = clap::builder::NonEmptyStringValueParser::new(), required = true)]
-    pub container_id: String,
+    #[clap(value_parser = clap::builder::NonEmptyStringValueParser::new(), required_unless_present = "all")]
+    pub container_id: Option<String>,
 }
@@ -39,6 +39,10 @@ pub struct Exec {
     /// Set the apparmor profile for the process
     #[clap(long)]
     pub apparmor: Option<String>,
+    /// Seccomp profile for the process, a JSON file with the seccomp section
+    /// of a runtime spec or `unconfined`. Defaults to the profile of the container
+    #[clap(long)]
+    pub seccomp: Option<String>,
     /// Prevent the process from gaining additional privileges
     #[clap(long)]
     pub no_new_privs: bool,
@@ -54,6 +58,10 @@ pub struct Exec {
     /// Execute a process in a sub-cgroup
     #[clap(long)]
     pub cgroup: Option<String>,
+    /// Execute the process in the least loaded sub-cgroup of the container,
+    /// or in the sub-cgroups in turn
+    #[clap(long, value_parser = ["least-loaded", "round-robin"], conflicts_with = "cgroup")]
+    pub cgroup_policy: Option<String>,
 
     /// Identifier of the container
     #[clap(value_parser = clap::builder::NonEmptyStringValueParser::new(), required = true)]
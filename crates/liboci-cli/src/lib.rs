@@ -39,7 +39,7 @@ pub use pause::Pause;
 pub use ps::Ps;
 pub use resume::Resume;
 pub use run::Run;
-pub use spec::Spec;
+pub use spec::{Spec, SpecCmd, SpecConvert, SpecFormat};
 pub use update::Update;
 
 // Subcommands parsed by liboci-cli, based on the [OCI
@@ -59,6 +59,9 @@ pub enum StandardCmd {
 // but found in
 // [runc](https://github.com/opencontainers/runc/blob/master/man/runc.8.md)
 // and other runtimes.
+// The command is parsed once, boxing the larger variants would only change
+// the public API.
+#[allow(clippy::large_enum_variant)]
 #[derive(Parser, Debug)]
 pub enum CommonCmd {
     Checkpointt(Checkpoint),
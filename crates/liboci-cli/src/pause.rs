@@ -3,6 +3,13 @@ use clap::Parser;
 /// Suspend the processes within the container
 #[derive(Parser, Debug)]
 pub struct Pause {
+    /// Stop the processes with SIGSTOP if the freezer cgroup is not available.
+    /// Unlike freezing, this can be observed by the container processes
+    #[clap(long)]
+    pub sigstop_fallback: bool,
+    /// Seconds to wait for all processes to be frozen, the container is thawed again and the processes which did not freeze are reported after that
+    #[clap(long, default_value = "10")]
+    pub timeout: u64,
     #[clap(value_parser = clap::builder::NonEmptyStringValueParser::new(), required = true)]
     pub container_id: String,
 }
@@ -11,6 +11,9 @@ pub struct Run {
     /// Unix socket (file) path , which will receive file descriptor of the writing end of the pseudoterminal
     #[clap(short, long)]
     pub console_socket: Option<PathBuf>,
+    /// Append the console output of the container to this file, while still passing the console to the console socket
+    #[clap(long, requires = "console_socket")]
+    pub console_log: Option<PathBuf>,
     /// File to write pid of the container created
     // note that in the end, container is just another process
     #[clap(short, long)]
@@ -27,13 +30,55 @@ pub struct Run {
     /// Pass N additional file descriptors to the container (stdio + $LISTEN_FDS + N in total)
     #[clap(long, default_value = "0")]
     pub preserve_fds: i32,
-    // Keep container's state directory and cgroup
+    /// Keep the state, the spec and the exit status of the container under the finished directory of the root once it exits
     #[clap(long)]
     pub keep: bool,
+    /// Remove the container's state and cgroup once it exits, even if youki is interrupted
+    #[clap(long, conflicts_with_all = ["detach", "keep"])]
+    pub rm: bool,
     /// name of the container instance to be started
     #[clap(value_parser = clap::builder::NonEmptyStringValueParser::new(), required = true)]
     pub container_id: String,
     /// Detach from the container process
     #[clap(short, long)]
     pub detach: bool,
+    /// Signal sent to the container init when youki exits, for example SIGKILL. Cannot be used with detach
+    #[clap(long, conflicts_with = "detach")]
+    pub pdeathsig: Option<String>,
+    /// Re-execute the youki binary on SIGUSR2 instead of forwarding the signal, so that youki can be upgraded while the container keeps running
+    #[clap(long, conflicts_with = "detach")]
+    pub allow_upgrade: bool,
+    /// What happens to the container processes if youki dies while creating the container
+    #[clap(long, value_parser = ["keep", "kill"])]
+    pub orphan_policy: Option<String>,
+    /// Default rlimit for resource types the spec does not limit, as type=soft[:hard], or only type to use the current limits of youki
+    #[clap(long)]
+    pub default_ulimit: Vec<String>,
+    /// Attach a user-mode network driver to the network namespace of the container
+    #[clap(long, value_parser = ["pasta", "slirp4netns"])]
+    pub user_net: Option<String>,
+    /// Publish a container port on the host through the user-mode network driver, as [host_ip:]host_port:container_port[/tcp|/udp]
+    #[clap(long)]
+    pub publish: Vec<String>,
+    /// Helper binary which provisions the mounts with volume:// sources
+    #[clap(long)]
+    pub volume_helper: Option<PathBuf>,
+    /// Seconds the volume helper may take to provision one volume
+    #[clap(long, default_value = "30", requires = "volume_helper")]
+    pub volume_helper_timeout: u64,
+    /// Seconds the creation of the container may take, its processes are killed and the creation is rolled back after that
+    #[clap(long)]
+    pub timeout: Option<u64>,
+    /// Seconds one phase of the creation may take, as phase=seconds for the phases intermediate, init, hooks, seccomp-listener and start
+    #[clap(long)]
+    pub phase_timeout: Vec<String>,
+    /// Timezone of the container, a name of the zoneinfo database of the host or local for the timezone of the host
+    #[clap(long)]
+    pub tz: Option<String>,
+    /// Record the syscalls youki makes to set up the container, with their arguments, results and durations, in the debug directory of the bundle
+    #[clap(long)]
+    pub trace_setup: bool,
+    /// Run the pause process built into youki instead of the process of the spec, which pauses until the container is stopped
+    #[clap(long)]
+    pub builtin_pause: bool,
 }
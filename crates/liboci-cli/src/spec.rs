@@ -1,6 +1,6 @@
 use std::path::PathBuf;
 
-use clap::Parser;
+use clap::{Parser, Subcommand, ValueEnum};
 
 /// Command generates a config.json
 #[derive(Parser, Debug)]
@@ -12,4 +12,34 @@ pub struct Spec {
     /// Generate a configuration for a rootless container
     #[clap(long)]
     pub rootless: bool,
+
+    /// Timezone of the container, a name of the zoneinfo database of the host or local for the timezone of the host
+    #[clap(long)]
+    pub tz: Option<String>,
+
+    #[clap(subcommand)]
+    pub cmd: Option<SpecCmd>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum SpecCmd {
+    /// Generate a config.json from a docker-compose service or a kubernetes container
+    Convert(SpecConvert),
+}
+
+#[derive(Parser, Debug)]
+pub struct SpecConvert {
+    /// JSON file with a single docker-compose service or kubernetes pod container
+    #[clap(long)]
+    pub from: PathBuf,
+
+    /// Format of the file, detected from its fields if not set
+    #[clap(long, value_enum)]
+    pub format: Option<SpecFormat>,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SpecFormat {
+    Compose,
+    Kubernetes,
 }
@@ -3,6 +3,9 @@ use clap::Parser;
 /// Start a previously created container
 #[derive(Parser, Debug)]
 pub struct Start {
+    /// Seconds the start of the container may take, including the poststart hooks
+    #[clap(long)]
+    pub timeout: Option<u64>,
     #[clap(value_parser = clap::builder::NonEmptyStringValueParser::new(), required = true)]
     pub container_id: String,
 }
@@ -3,6 +3,12 @@ use clap::Parser;
 /// Show the container state
 #[derive(Parser, Debug)]
 pub struct State {
+    /// Include the security profiles of the container and of the processes executed in it
+    #[clap(short, long)]
+    pub verbose: bool,
+    /// Show the processes executed in the container, who executed them and when, instead of the state
+    #[clap(long)]
+    pub exec_history: bool,
     #[clap(value_parser = clap::builder::NonEmptyStringValueParser::new(), required = true)]
     pub container_id: String,
 }
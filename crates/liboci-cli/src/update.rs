@@ -14,6 +14,11 @@ pub struct Update {
     #[clap(long)]
     pub blkio_weight: Option<u64>,
 
+    /// Set the I/O weight of a device, as <device path or major:minor>:<weight>, which overrides --blkio-weight for it.
+    /// A weight of 0 removes the override. Can be given multiple times.
+    #[clap(long)]
+    pub blkio_weight_device: Vec<String>,
+
     /// Set CPU CFS period to be used for hardcapping (in microseconds)
     #[clap(long)]
     pub cpu_period: Option<u64>,
@@ -22,6 +27,15 @@ pub struct Update {
     #[clap(long)]
     pub cpu_quota: Option<u64>,
 
+    /// Set CPU burst, the runtime (in microseconds) the container may save up while below its quota to exceed it briefly.
+    /// Must not be larger than the quota.
+    #[clap(long)]
+    pub cpu_burst: Option<u64>,
+
+    /// Mark the container as idle (1) so that it only gets the cpu time other containers leave, or not (0)
+    #[clap(long, value_parser = clap::value_parser!(i64).range(0..=1))]
+    pub cpu_idle: Option<i64>,
+
     /// Set CPU realtime period to be used for hardcapping (in microseconds)
     #[clap(long)]
     pub cpu_rt_period: Option<u64>,
@@ -54,10 +68,70 @@ pub struct Update {
     #[clap(long)]
     pub memory_swap: Option<i64>,
 
+    /// Set memory usage throttle limit (memory.high) to num bytes, cgroup v2 only. Use -1 to unset the limit.
+    #[clap(long, allow_hyphen_values = true)]
+    pub memory_high: Option<i64>,
+
+    /// Set the swap usage throttle limit (memory.swap.high) to num bytes, cgroup v2 only. Use -1 to unset the limit.
+    #[clap(long, allow_hyphen_values = true)]
+    pub memory_swap_high: Option<i64>,
+
+    /// Set the limit of the compressed swap cache (memory.zswap.max) to num bytes, cgroup v2 with zswap only.
+    /// Use -1 to unset the limit.
+    #[clap(long, allow_hyphen_values = true)]
+    pub memory_zswap_max: Option<i64>,
+
+    /// Ask the kernel to reclaim num bytes of memory from the container without changing its limits, cgroup v2 only.
+    /// Applied after the other options, including --resources.
+    #[clap(long)]
+    pub memory_reclaim: Option<u64>,
+
     /// Set the maximum number of processes allowed in the container
     #[clap(long)]
     pub pids_limit: Option<i64>,
 
+    /// Set the io priority class of the container init, one of IOPRIO_CLASS_RT, IOPRIO_CLASS_BE or IOPRIO_CLASS_IDLE.
+    /// Processes the init forks afterwards inherit it, use --all-processes to change the existing ones as well.
+    #[clap(long)]
+    pub io_priority_class: Option<String>,
+
+    /// Set the io priority within the io priority class, from 0 (highest) to 7 (lowest)
+    #[clap(long, requires = "io_priority_class")]
+    pub io_priority: Option<i64>,
+
+    /// Set the scheduling policy of the container init, e.g. SCHED_OTHER, SCHED_BATCH or SCHED_FIFO.
+    /// Processes the init forks afterwards inherit it unless SCHED_RESET_ON_FORK is set, use --all-processes to change the existing ones as well.
+    #[clap(long)]
+    pub sched_policy: Option<String>,
+
+    /// Set the nice value for the scheduling policy
+    #[clap(long, requires = "sched_policy", allow_hyphen_values = true)]
+    pub sched_nice: Option<i32>,
+
+    /// Set the static priority for the scheduling policy
+    #[clap(long, requires = "sched_policy")]
+    pub sched_priority: Option<i32>,
+
+    /// Set a scheduling flag, e.g. SCHED_RESET_ON_FORK. Can be given multiple times.
+    #[clap(long, requires = "sched_policy")]
+    pub sched_flag: Vec<String>,
+
+    /// Set the runtime in nanoseconds for SCHED_DEADLINE
+    #[clap(long, requires = "sched_policy")]
+    pub sched_runtime: Option<u64>,
+
+    /// Set the deadline in nanoseconds for SCHED_DEADLINE
+    #[clap(long, requires = "sched_policy")]
+    pub sched_deadline: Option<u64>,
+
+    /// Set the period in nanoseconds for SCHED_DEADLINE
+    #[clap(long, requires = "sched_policy")]
+    pub sched_period: Option<u64>,
+
+    /// Apply the io priority and the scheduling policy to every process in the container, not only to its init
+    #[clap(long)]
+    pub all_processes: bool,
+
     /// Set the value for Intel RDT/CAT L3 cache schema.
     #[clap(long)]
     pub l3_cache_schema: Option<String>,
@@ -0,0 +1,66 @@
+//! Contains functionality of the admit command, which tells whether the
+//! resources of a container fit on the host before it is created
+use std::fs;
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+use anyhow::{bail, Context, Result};
+use clap::Parser;
+use libcontainer::oci_spec::runtime::LinuxResources;
+use tabwriter::TabWriter;
+
+/// Check whether resources fit within the headroom of the host and a cgroup
+#[derive(Parser, Debug)]
+pub struct Admit {
+    /// Path to a JSON file with the resources of the container, in the format
+    /// of linux.resources of the spec, or - for stdin
+    #[clap(long, required = true)]
+    pub resources: PathBuf,
+    /// Cgroup below the root of the cgroup v2 hierarchy the container is
+    /// created in, whose limits and those of its ancestors are checked too
+    #[clap(long)]
+    pub cgroup_parent: Option<PathBuf>,
+    /// Specify the format (table or json)
+    #[clap(long, default_value = "table", value_parser = ["table", "json"])]
+    pub format: String,
+}
+
+pub fn admit(args: Admit) -> Result<()> {
+    let resources: LinuxResources = if args.resources.to_string_lossy() == "-" {
+        serde_json::from_reader(io::stdin())?
+    } else {
+        let file = fs::File::open(&args.resources)
+            .with_context(|| format!("failed to open {:?}", args.resources))?;
+        serde_json::from_reader(io::BufReader::new(file))?
+    };
+    let verdict = libcgroups::fit(&resources, args.cgroup_parent.as_deref())?;
+
+    match args.format.as_str() {
+        "json" => println!("{}", serde_json::to_string_pretty(&verdict)?),
+        _ => {
+            let mut tab_writer = TabWriter::new(io::stdout());
+            writeln!(
+                &mut tab_writer,
+                "RESOURCE\tREQUESTED\tAVAILABLE\tLIMITED BY\tFITS"
+            )?;
+            for fit in &verdict.resources {
+                let limited_by = fit
+                    .limited_by
+                    .as_ref()
+                    .map_or_else(|| "host".to_owned(), |cgroup| cgroup.display().to_string());
+                writeln!(
+                    &mut tab_writer,
+                    "{}\t{}\t{}\t{}\t{}",
+                    fit.resource, fit.requested, fit.available, limited_by, fit.fits
+                )?;
+            }
+            tab_writer.flush()?;
+        }
+    }
+
+    if !verdict.fits {
+        bail!("the resources do not fit");
+    }
+
+    Ok(())
+}
@@ -0,0 +1,57 @@
+//! Contains functionality of the batch command, which runs many container
+//! operations in one invocation
+use std::fs;
+use std::io::{self, Read};
+use std::path::PathBuf;
+
+use anyhow::{bail, Context, Result};
+use clap::Parser;
+use libcontainer::container::batch::{Batch as BatchRunner, Operation, OperationStatus};
+use libcontainer::syscall::syscall::SyscallType;
+
+use crate::workload::executor::default_executor;
+
+/// Create, start, kill and delete containers as listed in a JSON file
+#[derive(Parser, Debug)]
+pub struct Batch {
+    /// JSON file with the list of operations, - reads them from stdin
+    #[clap(long)]
+    pub file: PathBuf,
+    /// Skip the remaining operations after one failed
+    #[clap(long)]
+    pub stop_on_error: bool,
+}
+
+pub fn batch(args: Batch, root_path: PathBuf, systemd_cgroup: bool) -> Result<()> {
+    let operations = read_operations(&args.file)?;
+    let results = BatchRunner::new(root_path, SyscallType::default(), default_executor())?
+        .with_systemd(systemd_cgroup)
+        .with_stop_on_error(args.stop_on_error)
+        .run(&operations);
+    println!("{}", serde_json::to_string_pretty(&results)?);
+
+    let failed = results
+        .iter()
+        .filter(|result| result.status == OperationStatus::Failed)
+        .count();
+    if failed > 0 {
+        bail!("{failed} of {} operations failed", results.len());
+    }
+
+    Ok(())
+}
+
+fn read_operations(file: &PathBuf) -> Result<Vec<Operation>> {
+    let content = if file.as_os_str() == "-" {
+        let mut content = String::new();
+        io::stdin()
+            .read_to_string(&mut content)
+            .context("failed to read operations from stdin")?;
+        content
+    } else {
+        fs::read_to_string(file)
+            .with_context(|| format!("failed to read operations from {}", file.display()))?
+    };
+
+    serde_json::from_str(&content).context("failed to parse operations")
+}
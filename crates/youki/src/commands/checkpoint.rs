@@ -1,4 +1,5 @@
 //! Contains functionality of pause container command
+use std::os::unix::io::RawFd;
 use std::path::PathBuf;
 
 use anyhow::{Context, Result};
@@ -17,6 +18,11 @@ pub fn checkpoint(args: Checkpoint, root_path: PathBuf) -> Result<()> {
         shell_job: args.shell_job,
         tcp_established: args.tcp_established,
         work_path: args.work_path,
+        status_fd: args
+            .status_fd
+            .map(RawFd::try_from)
+            .transpose()
+            .context("invalid status fd")?,
     };
     container
         .checkpoint(&opts)
@@ -0,0 +1,185 @@
+//! Contains functionality of the hidden console-tee command, the process
+//! which runs the console proxy of a container created with a console log,
+//! see [`libcontainer::console_tee`].
+use std::io::{Read, Write};
+use std::os::fd::{AsFd, AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdout, Command, Stdio};
+use std::time::Duration;
+use std::{fs, io};
+
+use anyhow::{bail, Context, Result};
+use clap::Parser;
+use libcontainer::console_tee::ConsoleTee as Tee;
+use libcontainer::utils::SocketPath;
+use nix::errno::Errno;
+use nix::fcntl::{fcntl, FcntlArg, FdFlag};
+use nix::libc;
+use nix::poll::{poll, PollFd, PollFlags, PollTimeout};
+
+/// Copy between the console of a container and its console socket, appending the output to a log
+#[derive(Parser, Debug)]
+pub struct ConsoleTee {
+    /// Console socket to hand the consumer its pty through
+    #[clap(long)]
+    pub console_socket: PathBuf,
+    /// File to append the console output of the container to
+    #[clap(long)]
+    pub console_log: PathBuf,
+}
+
+/// Accepts the connection of the container init on the listening socket
+/// passed as stdin and reports on stdout once the consumer has its pty.
+pub fn console_tee(args: ConsoleTee) -> Result<()> {
+    let listener = UnixListener::from(io::stdin().as_fd().try_clone_to_owned()?);
+    let socket_path = SocketPath::new(&args.console_socket)?;
+    let consumer = UnixStream::connect(socket_path.as_path()).with_context(|| {
+        format!(
+            "failed to connect to console socket {:?}",
+            args.console_socket
+        )
+    })?;
+    let tee = Tee::new(consumer.into(), &args.console_log)?;
+
+    let (receiver, _) = listener
+        .accept()
+        .context("failed to accept the console socket connection")?;
+    drop(listener);
+    let proxy = tee.hand_over(&receiver.into())?;
+    let mut stdout = io::stdout();
+    stdout.write_all(b"1")?;
+    stdout.flush()?;
+
+    proxy.run().context("console proxy failed")
+}
+
+/// A console-tee process started for a container which is being created.
+/// Until it handed over the pty, dropping it stops the process.
+pub struct TeeProcess {
+    child: Option<Child>,
+    handed_over: ChildStdout,
+    socket: PathBuf,
+}
+
+impl TeeProcess {
+    /// Starts the proxy before the container is built. The container has to
+    /// be built with [`TeeProcess::socket`] as console socket.
+    pub fn start(root_path: &Path, console_socket: &Path, console_log: &Path) -> Result<Self> {
+        let socket = root_path.join(format!(".console-tee-{}", std::process::id()));
+        let _ = fs::remove_file(&socket);
+        let listener = UnixListener::bind(SocketPath::new(&socket)?.as_path())
+            .with_context(|| format!("failed to bind {socket:?}"))?;
+
+        let mut child = Command::new("/proc/self/exe")
+            .arg("--root")
+            .arg(root_path)
+            .arg("console-tee")
+            .arg("--console-socket")
+            .arg(console_socket)
+            .arg("--console-log")
+            .arg(console_log)
+            .stdin(Stdio::from(OwnedFd::from(listener)))
+            .stdout(Stdio::piped())
+            .spawn()
+            .context("failed to start the console proxy")?;
+        let handed_over = child.stdout.take().context("no stdout of console proxy")?;
+
+        Ok(Self {
+            child: Some(child),
+            handed_over,
+            socket,
+        })
+    }
+
+    pub fn socket(&self) -> &Path {
+        &self.socket
+    }
+
+    /// Waits until the consumer received its pty, which the proxy hands
+    /// over once the container init sent its own. Returns the running
+    /// proxy, unless the kernel does not support pidfds (5.3).
+    pub fn wait_handed_over(mut self) -> Result<Option<TeeProxy>> {
+        let mut buf = [0u8; 1];
+        if self.handed_over.read(&mut buf)? == 0 {
+            let status = self
+                .child
+                .take()
+                .map(|mut child| child.wait())
+                .transpose()?;
+            bail!("console proxy exited before it handed over the pty: {status:?}");
+        }
+        // the proxy keeps running on its own
+        let proxy = self
+            .child
+            .take()
+            .and_then(|child| TeeProxy::open(child.id()));
+
+        Ok(proxy)
+    }
+}
+
+impl Drop for TeeProcess {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.socket);
+        if let Some(mut child) = self.child.take() {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+}
+
+/// A console-tee process which handed over the pty, referred to by a pidfd
+/// so that it is told apart from a later process with the same pid.
+pub struct TeeProxy {
+    pidfd: OwnedFd,
+}
+
+impl TeeProxy {
+    pub(crate) fn open(pid: u32) -> Option<Self> {
+        let pidfd = unsafe { libc::syscall(libc::SYS_pidfd_open, pid, 0) };
+        if pidfd < 0 {
+            tracing::debug!(err = ?Errno::last(), "failed to open pidfd of the console proxy");
+            return None;
+        }
+        // Safe because the fd has just been opened and is owned by nobody else
+        let pidfd = unsafe { OwnedFd::from_raw_fd(pidfd as RawFd) };
+
+        Some(Self { pidfd })
+    }
+
+    /// Takes over the pidfd a replaced youki passed on an upgrade, see
+    /// [`super::run::handoff`]. It is closed on exec again.
+    pub fn from_handoff(pidfd: RawFd) -> Result<Self> {
+        fcntl(pidfd, FcntlArg::F_SETFD(FdFlag::FD_CLOEXEC))
+            .with_context(|| format!("invalid console proxy pidfd {pidfd}"))?;
+        // Safe because the replaced youki passed the fd for this only
+        let pidfd = unsafe { OwnedFd::from_raw_fd(pidfd) };
+
+        Ok(Self { pidfd })
+    }
+
+    /// Waits up to `timeout` for the proxy to exit, which it does once the
+    /// container closed its terminal, so that the console log is complete.
+    /// Returns if the proxy exited.
+    pub fn wait(&self, timeout: Duration) -> bool {
+        let mut fds = [PollFd::new(self.pidfd.as_fd(), PollFlags::POLLIN)];
+        let timeout = PollTimeout::try_from(timeout).unwrap_or(PollTimeout::MAX);
+        loop {
+            match poll(&mut fds, timeout) {
+                Err(Errno::EINTR) => continue,
+                Ok(ready) => return ready > 0,
+                Err(err) => {
+                    tracing::warn!(?err, "failed to wait for the console proxy");
+                    return false;
+                }
+            }
+        }
+    }
+}
+
+impl AsRawFd for TeeProxy {
+    fn as_raw_fd(&self) -> RawFd {
+        self.pidfd.as_raw_fd()
+    }
+}
@@ -1,11 +1,17 @@
 //! Handles the creation of a new container
 use std::path::PathBuf;
+use std::time::Duration;
 
 use anyhow::Result;
 use libcontainer::container::builder::ContainerBuilder;
+use libcontainer::debug_capture::DebugCapture;
+use libcontainer::process::timeouts::parse_timeouts;
+use libcontainer::rlimit::parse_default_rlimits;
 use libcontainer::syscall::syscall::SyscallType;
+use libcontainer::volume::VolumeHelper;
 use liboci_cli::Create;
 
+use crate::commands::console_tee::TeeProcess;
 use crate::workload::executor::default_executor;
 
 // One thing to note is that in the end, container is just another process in Linux
@@ -14,18 +20,43 @@ use crate::workload::executor::default_executor;
 // it is running, it is just another process, and has attributes such as pid, file descriptors, etc.
 // associated with it like any other process.
 pub fn create(args: Create, root_path: PathBuf, systemd_cgroup: bool) -> Result<()> {
+    // the proxy has to run before the container init sends its pty master
+    let console_tee = match (&args.console_socket, &args.console_log) {
+        (Some(console_socket), Some(console_log)) => {
+            Some(TeeProcess::start(&root_path, console_socket, console_log)?)
+        }
+        _ => None,
+    };
     ContainerBuilder::new(args.container_id.clone(), SyscallType::default())
         .with_executor(default_executor())
         .with_pid_file(args.pid_file.as_ref())?
-        .with_console_socket(args.console_socket.as_ref())
+        .with_console_socket(
+            console_tee
+                .as_ref()
+                .map(TeeProcess::socket)
+                .or(args.console_socket.as_deref()),
+        )
         .with_root_path(root_path)?
         .with_preserved_fds(args.preserve_fds)
+        .with_timeouts(parse_timeouts(args.timeout, &args.phase_timeout)?)
         .validate_id()?
         .as_init(&args.bundle)
         .with_systemd(systemd_cgroup)
         .with_detach(true)
         .with_no_pivot(args.no_pivot)
+        .with_orphan_policy(args.orphan_policy.as_deref().map(str::parse).transpose()?)
+        .with_default_rlimits(parse_default_rlimits(&args.default_ulimit)?)
+        .with_volume_helper(args.volume_helper.as_ref().map(|helper| {
+            VolumeHelper::new(helper).with_timeout(Duration::from_secs(args.volume_helper_timeout))
+        }))
+        .with_debug_capture(args.debug_capture.then_some(DebugCapture {
+            core_dumps: args.debug_core_dumps,
+        }))
+        .with_setup_trace(args.trace_setup)
         .build()?;
+    if let Some(console_tee) = console_tee {
+        console_tee.wait_handed_over()?;
+    }
 
     Ok(())
 }
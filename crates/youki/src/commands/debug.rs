@@ -0,0 +1,356 @@
+//! Contains functionality of the debug command, which bundles helpers for
+//! operators that need to look into a running container
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, IsTerminal, Read, Write};
+use std::os::fd::{AsFd, OwnedFd};
+use std::os::unix::net::UnixListener;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use clap::{Parser, Subcommand};
+use libcontainer::console_tee::{copy_window_size, receive_master};
+use libcontainer::container::builder::ContainerBuilder;
+use libcontainer::container::ContainerStatus;
+#[cfg(feature = "seccomp")]
+use libcontainer::oci_spec::runtime::LinuxSeccomp;
+use libcontainer::oci_spec::runtime::Spec;
+#[cfg(feature = "seccomp")]
+use libcontainer::seccomp::summary::summarize;
+use libcontainer::syscall::syscall::SyscallType;
+use nix::errno::Errno;
+use nix::poll::{poll, PollFd, PollFlags, PollTimeout};
+use nix::sys::signal::{SigSet, Signal};
+use nix::sys::signalfd::SignalFd;
+use nix::sys::termios::{self, SetArg, Termios};
+use nix::sys::wait::{waitpid, WaitStatus};
+
+use crate::commands::{construct_container_root, load_container};
+use crate::workload::executor::default_executor;
+
+/// Debug a running container
+#[derive(Parser, Debug)]
+pub struct Debug {
+    #[clap(subcommand)]
+    pub cmd: DebugCmd,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum DebugCmd {
+    /// Start an interactive shell as root inside of a running container
+    Enter(Enter),
+    /// Print the seccomp profile of a bundle
+    #[cfg(feature = "seccomp")]
+    Seccomp(Seccomp),
+}
+
+#[derive(Parser, Debug)]
+pub struct Enter {
+    /// Shell to start, defaults to bash if the container has one and sh otherwise
+    #[clap(long)]
+    pub shell: Option<String>,
+    /// Name of the container instance
+    #[clap(value_parser = clap::builder::NonEmptyStringValueParser::new(), required = true)]
+    pub container_id: String,
+}
+
+#[cfg(feature = "seccomp")]
+#[derive(Parser, Debug)]
+pub struct Seccomp {
+    /// Print the effective default and syscall actions per architecture as
+    /// JSON instead of the profile
+    #[clap(long)]
+    pub summarize: bool,
+    /// Seccomp profile to read instead of the one in the config of the bundle
+    #[clap(long)]
+    pub profile: Option<PathBuf>,
+    /// Path to the bundle directory
+    #[clap(short, long, default_value = ".")]
+    pub bundle: PathBuf,
+}
+
+impl Debug {
+    pub fn container_id(&self) -> Option<&String> {
+        match &self.cmd {
+            DebugCmd::Enter(enter) => Some(&enter.container_id),
+            #[cfg(feature = "seccomp")]
+            DebugCmd::Seccomp(_) => None,
+        }
+    }
+}
+
+const DEFAULT_PATH: &str = "/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin";
+const DEFAULT_TERM: &str = "xterm";
+const SHELLS: &[&str] = &["/bin/bash", "/bin/sh"];
+const BUFFER_SIZE: usize = 4096;
+
+pub fn debug(args: Debug, root_path: PathBuf) -> Result<i32> {
+    match args.cmd {
+        DebugCmd::Enter(enter) => self::enter(enter, root_path),
+        #[cfg(feature = "seccomp")]
+        DebugCmd::Seccomp(seccomp) => self::seccomp(seccomp),
+    }
+}
+
+#[cfg(feature = "seccomp")]
+fn seccomp(args: Seccomp) -> Result<i32> {
+    let profile: LinuxSeccomp = match &args.profile {
+        Some(path) => {
+            let file = fs::File::open(path)
+                .with_context(|| format!("failed to open seccomp profile {}", path.display()))?;
+            serde_json::from_reader(io::BufReader::new(file))
+                .with_context(|| format!("failed to parse seccomp profile {}", path.display()))?
+        }
+        None => {
+            let spec_path = args.bundle.join("config.json");
+            let spec = Spec::load(&spec_path)
+                .with_context(|| format!("failed to load spec from {}", spec_path.display()))?;
+            spec.linux()
+                .as_ref()
+                .and_then(|linux| linux.seccomp().clone())
+                .with_context(|| format!("{} has no seccomp profile", spec_path.display()))?
+        }
+    };
+
+    let output = if args.summarize {
+        serde_json::to_string_pretty(&summarize(&profile)?)?
+    } else {
+        serde_json::to_string_pretty(&profile)?
+    };
+    println!("{output}");
+    Ok(0)
+}
+
+fn enter(args: Enter, root_path: PathBuf) -> Result<i32> {
+    let container = load_container(&root_path, &args.container_id)?;
+    if container.status() != ContainerStatus::Running {
+        bail!(
+            "container {} is {}, not running",
+            args.container_id,
+            container.status()
+        );
+    }
+    let init_pid = container
+        .pid()
+        .with_context(|| format!("container {} has no init process", args.container_id))?;
+
+    let shell = match args.shell {
+        Some(shell) => shell,
+        None => default_shell(&PathBuf::from(format!("/proc/{init_pid}/root"))),
+    };
+    let spec_path = container.bundle().join("config.json");
+    let spec = Spec::load(&spec_path)
+        .with_context(|| format!("failed to load spec from {}", spec_path.display()))?;
+    let container_env = spec
+        .process()
+        .as_ref()
+        .and_then(|process| process.env().clone())
+        .unwrap_or_default();
+    let env = shell_env(&container_env, std::env::var("TERM").ok());
+
+    // Without a terminal on our side the shell simply inherits the stdio,
+    // which allows to pipe commands into it.
+    let console = if io::stdin().is_terminal() {
+        let container_root = construct_container_root(&root_path, &args.container_id)?;
+        Some(ConsoleListener::bind(&container_root)?)
+    } else {
+        None
+    };
+
+    let pid = ContainerBuilder::new(args.container_id.clone(), SyscallType::default())
+        .with_executor(default_executor())
+        .with_root_path(root_path)?
+        .with_console_socket(console.as_ref().map(|console| &console.path))
+        .validate_id()?
+        .as_tenant()
+        .with_cwd(Some("/"))
+        .with_env(env)
+        .with_terminal(console.is_some())
+        .with_container_args(vec![shell])
+        .build()?;
+
+    if let Some(console) = console {
+        let master = console.accept()?;
+        drop(console);
+        attach(master)?;
+    }
+
+    match waitpid(pid, None)? {
+        WaitStatus::Exited(_, status) => Ok(status),
+        WaitStatus::Signaled(_, sig, _) => Ok(sig as i32),
+        _ => Ok(0),
+    }
+}
+
+/// Returns the first shell of [SHELLS] that exists below `root`. Only the
+/// entry itself is checked, symlinks are resolved inside of the container.
+fn default_shell(root: &Path) -> String {
+    SHELLS
+        .iter()
+        .find(|shell| fs::symlink_metadata(root.join(shell.trim_start_matches('/'))).is_ok())
+        .unwrap_or(SHELLS.last().unwrap())
+        .to_string()
+}
+
+/// Builds the environment of the shell from the environment of the
+/// container process, with the terminal type of the caller and a default
+/// PATH if the container does not set one.
+fn shell_env(container_env: &[String], term: Option<String>) -> HashMap<String, String> {
+    let mut env: HashMap<String, String> = container_env
+        .iter()
+        .filter_map(|entry| entry.split_once('='))
+        .map(|(key, value)| (key.to_owned(), value.to_owned()))
+        .collect();
+    env.entry("PATH".to_owned())
+        .or_insert_with(|| DEFAULT_PATH.to_owned());
+    env.insert(
+        "TERM".to_owned(),
+        term.filter(|term| !term.is_empty())
+            .unwrap_or_else(|| DEFAULT_TERM.to_owned()),
+    );
+    env
+}
+
+/// Console socket on which the container init sends the pty master of the
+/// shell. The socket file is removed on drop.
+struct ConsoleListener {
+    listener: UnixListener,
+    path: PathBuf,
+}
+
+impl ConsoleListener {
+    fn bind(dir: &Path) -> Result<Self> {
+        let path = dir.join(format!("debug-{}.sock", std::process::id()));
+        let listener = UnixListener::bind(&path)
+            .with_context(|| format!("failed to bind console socket {}", path.display()))?;
+        Ok(Self { listener, path })
+    }
+
+    fn accept(&self) -> Result<OwnedFd> {
+        let (stream, _) = self
+            .listener
+            .accept()
+            .context("failed to accept console connection")?;
+        Ok(receive_master(&stream.into())?)
+    }
+}
+
+impl Drop for ConsoleListener {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Restores the terminal attributes of stdin on drop
+struct RawMode {
+    original: Termios,
+}
+
+impl RawMode {
+    fn enable() -> Result<Self> {
+        let original = termios::tcgetattr(io::stdin())?;
+        let mut raw = original.clone();
+        termios::cfmakeraw(&mut raw);
+        termios::tcsetattr(io::stdin(), SetArg::TCSANOW, &raw)?;
+        Ok(Self { original })
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        let _ = termios::tcsetattr(io::stdin(), SetArg::TCSANOW, &self.original);
+    }
+}
+
+/// Connects the terminal of the caller to the pty of the shell until the
+/// shell closed its side of the terminal.
+fn attach(master: OwnedFd) -> Result<()> {
+    let _raw_mode = RawMode::enable()?;
+    copy_window_size(&io::stdin(), &master);
+
+    let mut mask = SigSet::empty();
+    mask.add(Signal::SIGWINCH);
+    mask.thread_block()?;
+    let mut sigfd = SignalFd::new(&mask)?;
+
+    let mut master = fs::File::from(master);
+    let mut stdin = io::stdin();
+    let mut stdout = io::stdout();
+    let mut stdin_open = true;
+    let mut buf = [0u8; BUFFER_SIZE];
+    let result = loop {
+        let mut fds = vec![
+            PollFd::new(master.as_fd(), PollFlags::POLLIN),
+            PollFd::new(sigfd.as_fd(), PollFlags::POLLIN),
+        ];
+        if stdin_open {
+            fds.push(PollFd::new(stdin.as_fd(), PollFlags::POLLIN));
+        }
+        match poll(&mut fds, PollTimeout::NONE) {
+            Err(Errno::EINTR) => continue,
+            Err(err) => break Err(err.into()),
+            Ok(_) => {}
+        };
+        let ready: Vec<bool> = fds
+            .iter()
+            .map(|fd| fd.revents().map_or(false, |revents| !revents.is_empty()))
+            .collect();
+        drop(fds);
+
+        if ready[0] {
+            let n = match master.read(&mut buf) {
+                // reading fails with EIO once the shell closed the terminal
+                Ok(0) | Err(_) => break Ok(()),
+                Ok(n) => n,
+            };
+            stdout.write_all(&buf[..n])?;
+            stdout.flush()?;
+        }
+        if ready[1] && sigfd.read_signal()?.is_some() {
+            copy_window_size(&io::stdin(), &master);
+        }
+        if stdin_open && ready[2] {
+            match stdin.read(&mut buf) {
+                Ok(0) | Err(_) => stdin_open = false,
+                Ok(n) => master.write_all(&buf[..n])?,
+            }
+        }
+    };
+    mask.thread_unblock()?;
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_shell() -> Result<()> {
+        let root = tempfile::tempdir()?;
+        assert_eq!(default_shell(root.path()), "/bin/sh");
+
+        fs::create_dir(root.path().join("bin"))?;
+        fs::write(root.path().join("bin/sh"), "")?;
+        assert_eq!(default_shell(root.path()), "/bin/sh");
+
+        // dangling from the point of view of the host
+        std::os::unix::fs::symlink("/usr/bin/bash", root.path().join("bin/bash"))?;
+        assert_eq!(default_shell(root.path()), "/bin/bash");
+        Ok(())
+    }
+
+    #[test]
+    fn test_shell_env() {
+        let env = shell_env(
+            &["FOO=bar=baz".to_owned(), "TERM=dumb".to_owned()],
+            Some("screen".to_owned()),
+        );
+        assert_eq!(env["FOO"], "bar=baz");
+        assert_eq!(env["TERM"], "screen");
+        assert_eq!(env["PATH"], DEFAULT_PATH);
+
+        let env = shell_env(&["PATH=/opt/bin".to_owned()], None);
+        assert_eq!(env["PATH"], "/opt/bin");
+        assert_eq!(env["TERM"], DEFAULT_TERM);
+    }
+}
@@ -4,6 +4,7 @@ use anyhow::{Context, Result};
 use liboci_cli::Delete;
 
 use crate::commands::{container_exists, load_container};
+use crate::usernet;
 
 pub fn delete(args: Delete, root_path: PathBuf) -> Result<()> {
     tracing::debug!("start deleting {}", args.container_id);
@@ -12,7 +13,19 @@ pub fn delete(args: Delete, root_path: PathBuf) -> Result<()> {
     }
 
     let mut container = load_container(root_path, &args.container_id)?;
-    container
-        .delete(args.force)
-        .with_context(|| format!("failed to delete container {}", args.container_id))
+    usernet::teardown(&container.root)?;
+    if !args.keep_state {
+        return container
+            .delete(args.force)
+            .with_context(|| format!("failed to delete container {}", args.container_id));
+    }
+
+    // the exit status is only known to whoever waited for the process
+    let finished = container
+        .delete_and_keep(args.force, None)
+        .with_context(|| format!("failed to delete container {}", args.container_id))?;
+    if let Some(finished) = finished {
+        println!("{}", finished.display());
+    }
+    Ok(())
 }
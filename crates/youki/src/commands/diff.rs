@@ -0,0 +1,35 @@
+//! Contains functionality of the diff command, which lists the changes a
+//! container made to its overlay rootfs
+use std::path::PathBuf;
+
+use anyhow::Result;
+use clap::Parser;
+
+use crate::commands::load_container;
+
+/// List the paths a container added, changed or deleted in its overlay rootfs
+#[derive(Parser, Debug)]
+pub struct Diff {
+    /// Specify the format (text or json)
+    #[clap(long, default_value = "text", value_parser = ["text", "json"])]
+    pub format: String,
+    /// Name of the container instance
+    #[clap(value_parser = clap::builder::NonEmptyStringValueParser::new(), required = true)]
+    pub container_id: String,
+}
+
+pub fn diff(args: Diff, root_path: PathBuf) -> Result<()> {
+    let container = load_container(root_path, &args.container_id)?;
+    let changes = container.diff()?;
+
+    match args.format.as_str() {
+        "json" => println!("{}", serde_json::to_string_pretty(&changes)?),
+        _ => {
+            for change in &changes {
+                println!("{} {}", change.kind, change.path.display());
+            }
+        }
+    }
+
+    Ok(())
+}
@@ -1,13 +1,443 @@
-use std::path::PathBuf;
+//! Contains functionality of the events command
+mod batch_read;
 
-use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::thread;
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use libcgroups::common::{self, AnyCgroupManager, CgroupManager};
+use libcgroups::stats::{Stats, StatsSample, UsageRates};
+use libcontainer::container::state::State;
+use libcontainer::container::{Container, ContainerStatus};
 use liboci_cli::Events;
+use serde_json::{json, Map, Value};
+
+use crate::commands::top::format_bytes;
+use crate::commands::{create_cgroup_manager, load_container};
+
+/// A group of stats which can be selected with --fields
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Field {
+    Cpu,
+    Mem,
+    Pids,
+    Io,
+}
+
+const ALL_FIELDS: [Field; 4] = [Field::Cpu, Field::Mem, Field::Pids, Field::Io];
+
+impl FromStr for Field {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "cpu" => Ok(Self::Cpu),
+            "mem" => Ok(Self::Mem),
+            "pids" => Ok(Self::Pids),
+            "io" => Ok(Self::Io),
+            _ => bail!("unknown stats field {s}"),
+        }
+    }
+}
+
+impl Field {
+    /// Key of the group in the JSON representation of the stats
+    fn json_key(&self) -> &'static str {
+        match self {
+            Self::Cpu => "cpu",
+            Self::Mem => "memory",
+            Self::Pids => "pids",
+            Self::Io => "blkio",
+        }
+    }
 
-use crate::commands::load_container;
+    fn headers(&self) -> &'static [&'static str] {
+        match self {
+            Self::Cpu => &["CPU %", "CPU TIME"],
+            Self::Mem => &["MEM USAGE / LIMIT", "MEM %"],
+            Self::Pids => &["PIDS"],
+            Self::Io => &["BLOCK READ", "BLOCK WRITE"],
+        }
+    }
+
+    /// Humanized values of the columns, the cpu percentage is only known
+    /// once there are two samples
+    fn values(&self, stats: &Stats, rates: Option<&UsageRates>) -> Vec<String> {
+        match self {
+            Self::Cpu => vec![
+                rates.map_or("-".to_owned(), |rates| format!("{:.2}", rates.cpu_percent)),
+                format_duration_ns(stats.cpu.usage.usage_total),
+            ],
+            Self::Mem => {
+                let memory = &stats.memory.memory;
+                match memory.limit {
+                    0 | u64::MAX => vec![
+                        format!("{} / unlimited", format_bytes(memory.usage)),
+                        "-".to_owned(),
+                    ],
+                    limit => vec![
+                        format!("{} / {}", format_bytes(memory.usage), format_bytes(limit)),
+                        format!("{:.2}", memory.usage as f64 / limit as f64 * 100.0),
+                    ],
+                }
+            }
+            Self::Pids => vec![match stats.pids.limit {
+                0 | u64::MAX => stats.pids.current.to_string(),
+                limit => format!("{} / {}", stats.pids.current, limit),
+            }],
+            Self::Io => {
+                let (read, write) = stats.blkio.io_bytes();
+                vec![format_bytes(read), format_bytes(write)]
+            }
+        }
+    }
+}
+
+// Rows are printed one at a time while streaming, so columns have a fixed
+// width instead of being aligned to their content.
+const COLUMN_WIDTH: usize = 24;
 
 pub fn events(args: Events, root_path: PathBuf) -> Result<()> {
-    let mut container = load_container(root_path, &args.container_id)?;
-    container
-        .events(args.interval, args.stats)
-        .with_context(|| format!("failed to get events from container {}", args.container_id))
+    let fields: Vec<Field> = args
+        .fields
+        .iter()
+        .map(|field| field.parse())
+        .collect::<Result<_>>()?;
+    let Some(container_id) = args.container_id.clone() else {
+        return events_all(&args, &fields, &root_path);
+    };
+    if args.format == "json" && fields.is_empty() {
+        let mut container = load_container(root_path, &container_id)?;
+        return container
+            .events(args.interval, args.stats)
+            .with_context(|| format!("failed to get events from container {container_id}"));
+    }
+    let fields = if fields.is_empty() {
+        ALL_FIELDS.to_vec()
+    } else {
+        fields
+    };
+
+    let container = load_container(&root_path, &container_id)?;
+    if container.status() != ContainerStatus::Running {
+        bail!(
+            "container {} is {}, not running",
+            container_id,
+            container.status()
+        );
+    }
+    let cmanager = create_cgroup_manager(root_path, &container_id)?;
+
+    let mut stdout = io::stdout().lock();
+    let mut previous: Option<StatsSample> = None;
+    loop {
+        let current = StatsSample::new(cmanager.stats()?);
+        let rates = previous
+            .as_ref()
+            .map(|previous| current.rates_since(previous));
+        if args.format == "table" {
+            if previous.is_none() {
+                write_header(&mut stdout, &fields)?;
+            }
+            write_row(&mut stdout, &fields, &current.stats, rates.as_ref())?;
+        } else {
+            writeln!(
+                stdout,
+                "{}",
+                serde_json::to_string_pretty(&stats_event(
+                    &container_id,
+                    &fields,
+                    &current.stats
+                )?)?
+            )?;
+        }
+        stdout.flush()?;
+
+        if args.stats {
+            return Ok(());
+        }
+        previous = Some(current);
+        thread::sleep(Duration::from_secs(args.interval as u64));
+    }
+}
+
+/// Samples the stats of all running containers. The cgroup files read for a
+/// sample are read in a batch for the next one, which keeps the overhead low
+/// with many containers.
+fn events_all(args: &Events, fields: &[Field], root_path: &Path) -> Result<()> {
+    let mut stdout = io::stdout().lock();
+    let mut managers: HashMap<String, AnyCgroupManager> = HashMap::new();
+    let mut files: Vec<PathBuf> = Vec::new();
+    let mut previous: HashMap<String, StatsSample> = HashMap::new();
+    let mut reader = batch_read::BatchReader::new();
+    let mut first = true;
+    loop {
+        refresh_managers(root_path, &mut managers)?;
+        let prefetched = reader.read_files(&files);
+        let (samples, read) = common::with_prefetched(prefetched, || {
+            let mut samples: Vec<(String, StatsSample)> = managers
+                .iter()
+                .filter_map(|(id, manager)| match manager.stats() {
+                    Ok(stats) => Some((id.clone(), StatsSample::new(stats))),
+                    Err(err) => {
+                        tracing::warn!(?err, id, "failed to get stats");
+                        None
+                    }
+                })
+                .collect();
+            samples.sort_by(|(a, _), (b, _)| a.cmp(b));
+            samples
+        });
+        files = read;
+
+        if args.format == "table" {
+            let fields = if fields.is_empty() {
+                &ALL_FIELDS[..]
+            } else {
+                fields
+            };
+            if first {
+                write_cells(&mut stdout, &header_cells(Some("ID"), fields))?;
+            }
+            for (id, sample) in &samples {
+                let rates = previous
+                    .get(id)
+                    .map(|previous| sample.rates_since(previous));
+                write_cells(
+                    &mut stdout,
+                    &row_cells(Some(id), fields, &sample.stats, rates.as_ref()),
+                )?;
+            }
+        } else {
+            for (id, sample) in &samples {
+                writeln!(
+                    stdout,
+                    "{}",
+                    serde_json::to_string_pretty(&stats_event(id, fields, &sample.stats)?)?
+                )?;
+            }
+        }
+        stdout.flush()?;
+
+        if args.stats {
+            return Ok(());
+        }
+        previous = samples.into_iter().collect();
+        first = false;
+        thread::sleep(Duration::from_secs(args.interval as u64));
+    }
+}
+
+/// Adds the cgroup managers of containers which are running now and removes
+/// the ones of containers which are gone. The state of a container which is
+/// already known is not loaded again.
+fn refresh_managers(
+    root_path: &Path,
+    managers: &mut HashMap<String, AnyCgroupManager>,
+) -> Result<()> {
+    let mut ids = Vec::new();
+    for container_dir in std::fs::read_dir(root_path)? {
+        let container_dir = container_dir?.path();
+        if !State::file_path(&container_dir).exists() {
+            continue;
+        }
+        let Some(id) = container_dir.file_name().and_then(|name| name.to_str()) else {
+            continue;
+        };
+        ids.push(id.to_owned());
+        if managers.contains_key(id) {
+            continue;
+        }
+
+        // the container may be deleted meanwhile
+        let Ok(container) = Container::load(container_dir.clone()) else {
+            continue;
+        };
+        if container.status() != ContainerStatus::Running {
+            continue;
+        }
+        let manager = common::create_cgroup_manager(common::CgroupConfig {
+            cgroup_path: container.spec()?.cgroup_path,
+            systemd_cgroup: container.systemd(),
+            container_name: container.id().to_string(),
+            init_leaf: false,
+            host: common::CgroupHost::default(),
+            state_root: None,
+        })?;
+        managers.insert(id.to_owned(), manager);
+    }
+    managers.retain(|id, _| ids.contains(id));
+    Ok(())
+}
+
+fn write_header<W: Write>(out: &mut W, fields: &[Field]) -> Result<()> {
+    write_cells(out, &header_cells(None, fields))
+}
+
+fn write_row<W: Write>(
+    out: &mut W,
+    fields: &[Field],
+    stats: &Stats,
+    rates: Option<&UsageRates>,
+) -> Result<()> {
+    write_cells(out, &row_cells(None, fields, stats, rates))
+}
+
+fn header_cells(id: Option<&str>, fields: &[Field]) -> Vec<String> {
+    id.into_iter()
+        .chain(
+            fields
+                .iter()
+                .flat_map(|field| field.headers().iter().copied()),
+        )
+        .map(str::to_owned)
+        .collect()
+}
+
+fn row_cells(
+    id: Option<&str>,
+    fields: &[Field],
+    stats: &Stats,
+    rates: Option<&UsageRates>,
+) -> Vec<String> {
+    id.map(str::to_owned)
+        .into_iter()
+        .chain(fields.iter().flat_map(|field| field.values(stats, rates)))
+        .collect()
+}
+
+fn write_cells<W: Write, S: AsRef<str>>(out: &mut W, cells: &[S]) -> Result<()> {
+    let line = cells.iter().fold(String::new(), |mut line, cell| {
+        let _ = write!(line, "{:<COLUMN_WIDTH$}", cell.as_ref());
+        line
+    });
+    writeln!(out, "{}", line.trim_end())?;
+    Ok(())
+}
+
+/// Wraps the stats of a container into an event like `runc events` emits
+/// them, `{"type": "stats", "id": ..., "data": ...}`. The fields only select
+/// the groups of the data.
+fn stats_event(id: &str, fields: &[Field], stats: &Stats) -> Result<Value> {
+    let data = if fields.is_empty() {
+        serde_json::to_value(stats)?
+    } else {
+        select_json(fields, stats)?
+    };
+
+    Ok(json!({
+        "type": "stats",
+        "id": id,
+        "data": data,
+    }))
+}
+
+/// Returns the JSON representation of the stats restricted to the groups
+/// of the given fields
+fn select_json(fields: &[Field], stats: &Stats) -> Result<Value> {
+    let Value::Object(mut all) = serde_json::to_value(stats)? else {
+        bail!("stats are not serialized as an object");
+    };
+
+    let selected: Map<String, Value> = fields
+        .iter()
+        .filter_map(|field| {
+            all.remove(field.json_key())
+                .map(|value| (field.json_key().to_owned(), value))
+        })
+        .collect();
+    Ok(Value::Object(selected))
+}
+
+fn format_duration_ns(ns: u64) -> String {
+    let secs = ns as f64 / 1e9;
+    if secs < 60.0 {
+        format!("{secs:.2}s")
+    } else if secs < 3600.0 {
+        format!("{}m{:02}s", (secs / 60.0) as u64, secs as u64 % 60)
+    } else {
+        format!("{}h{:02}m", (secs / 3600.0) as u64, (secs as u64 / 60) % 60)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_stats() -> Stats {
+        let mut stats = Stats::default();
+        stats.cpu.usage.usage_total = 90_500_000_000;
+        stats.memory.memory.usage = 512 * 1024 * 1024;
+        stats.memory.memory.limit = 1024 * 1024 * 1024;
+        stats.pids.current = 3;
+        stats
+    }
+
+    #[test]
+    fn test_format_duration_ns() {
+        assert_eq!(format_duration_ns(1_500_000_000), "1.50s");
+        assert_eq!(format_duration_ns(90_500_000_000), "1m30s");
+        assert_eq!(format_duration_ns(7_260_000_000_000), "2h01m");
+    }
+
+    #[test]
+    fn test_table_with_selected_fields() -> Result<()> {
+        let stats = test_stats();
+        let fields = [Field::Pids, Field::Mem];
+        let mut out = Vec::new();
+        write_header(&mut out, &fields)?;
+        write_row(&mut out, &fields, &stats, None)?;
+
+        let out = String::from_utf8(out)?;
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(
+            lines[0],
+            format!("{:<24}{:<24}MEM %", "PIDS", "MEM USAGE / LIMIT")
+        );
+        assert_eq!(
+            lines[1],
+            format!("{:<24}{:<24}50.00", "3", "512.0MiB / 1.0GiB")
+        );
+
+        let mut out = Vec::new();
+        let rates = UsageRates {
+            cpu_percent: 12.5,
+            ..Default::default()
+        };
+        write_row(&mut out, &[Field::Cpu], &stats, Some(&rates))?;
+        assert_eq!(String::from_utf8(out)?, format!("{:<24}1m30s\n", "12.50"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_select_json() -> Result<()> {
+        let value = select_json(&[Field::Pids, Field::Io], &test_stats())?;
+        let keys: Vec<&String> = value.as_object().unwrap().keys().collect();
+        assert_eq!(keys, ["blkio", "pids"]);
+        assert_eq!(value["pids"]["current"], 3);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_stats_event() -> Result<()> {
+        let event = stats_event("web", &[Field::Pids], &test_stats())?;
+        let keys: Vec<&String> = event.as_object().unwrap().keys().collect();
+        assert_eq!(keys, ["data", "id", "type"]);
+        assert_eq!(event["type"], "stats");
+        assert_eq!(event["id"], "web");
+        let data: Vec<&String> = event["data"].as_object().unwrap().keys().collect();
+        assert_eq!(data, ["pids"]);
+
+        let event = stats_event("web", &[], &test_stats())?;
+        assert_eq!(event["data"], serde_json::to_value(test_stats())?);
+
+        Ok(())
+    }
 }
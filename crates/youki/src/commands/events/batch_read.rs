@@ -0,0 +1,237 @@
+//! Reads the stat files of many cgroups in a batch. With the `io-uring`
+//! feature the reads are submitted to the kernel at once, otherwise or if
+//! io_uring is not available, e.g. because a seccomp profile blocks it, the
+//! files are read by a few threads in parallel.
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::{fs, thread};
+
+const MAX_WORKERS: usize = 8;
+
+/// Reads the stat files of many cgroups in a batch. The io_uring ring is set
+/// up once and used for every batch, as `events --all` reads one per interval.
+pub struct BatchReader {
+    #[cfg(feature = "io-uring")]
+    ring: Option<uring::Reader>,
+}
+
+impl BatchReader {
+    pub fn new() -> Self {
+        Self {
+            #[cfg(feature = "io-uring")]
+            ring: uring::Reader::new()
+                .map_err(|err| {
+                    tracing::debug!(?err, "io_uring is not available, reading in threads")
+                })
+                .ok(),
+        }
+    }
+
+    /// Reads the given files, files which can not be read are left out and
+    /// reported by the regular read later on
+    pub fn read_files(&mut self, paths: &[PathBuf]) -> HashMap<PathBuf, String> {
+        #[cfg(feature = "io-uring")]
+        if let Some(ring) = &mut self.ring {
+            match ring.read_files(paths) {
+                Ok(files) => return files,
+                Err(err) => {
+                    tracing::debug!(?err, "failed to read with io_uring, reading in threads");
+                    self.ring = None;
+                }
+            }
+        }
+
+        read_files_parallel(paths)
+    }
+}
+
+fn read_files_parallel(paths: &[PathBuf]) -> HashMap<PathBuf, String> {
+    let workers = thread::available_parallelism()
+        .map_or(1, |n| n.get())
+        .min(MAX_WORKERS)
+        .min(paths.len());
+    if workers <= 1 {
+        return read_sequential(paths);
+    }
+
+    let chunk_size = (paths.len() + workers - 1) / workers;
+    thread::scope(|scope| {
+        let workers: Vec<_> = paths
+            .chunks(chunk_size)
+            .map(|chunk| scope.spawn(move || read_sequential(chunk)))
+            .collect();
+        workers
+            .into_iter()
+            .flat_map(|worker| worker.join().unwrap_or_default())
+            .collect()
+    })
+}
+
+fn read_sequential(paths: &[PathBuf]) -> HashMap<PathBuf, String> {
+    paths
+        .iter()
+        .filter_map(|path| {
+            fs::read_to_string(path)
+                .ok()
+                .map(|content| (path.clone(), content))
+        })
+        .collect()
+}
+
+#[cfg(feature = "io-uring")]
+mod uring {
+    //! Reads the start of already opened files with io_uring. Files are
+    //! opened and closed with regular syscalls, the reads are the expensive
+    //! part for cgroup files as their content is generated by the kernel.
+    use std::collections::HashMap;
+    use std::fs::File;
+    use std::io;
+    use std::os::fd::AsRawFd;
+    use std::path::PathBuf;
+
+    use io_uring::{opcode, types, IoUring};
+    use nix::libc;
+
+    /// Size of the buffer per file, a file which fills it is left out
+    const BUFFER_SIZE: usize = 16 * 1024;
+    const RING_ENTRIES: u32 = 128;
+
+    pub struct Reader {
+        ring: IoUring,
+        // The kernel writes into the buffers until the reads complete, so
+        // they are kept from one batch to the next and leaked on drop while
+        // reads are in flight.
+        buffers: Vec<Vec<u8>>,
+        in_flight: usize,
+    }
+
+    impl Reader {
+        pub fn new() -> io::Result<Self> {
+            Ok(Self {
+                ring: IoUring::new(RING_ENTRIES)?,
+                buffers: Vec::new(),
+                in_flight: 0,
+            })
+        }
+
+        /// Fails if io_uring can not be used, after which the reader must
+        /// not be used anymore. Single files which can not be read are left
+        /// out.
+        pub fn read_files(&mut self, paths: &[PathBuf]) -> io::Result<HashMap<PathBuf, String>> {
+            let (paths, files): (Vec<&PathBuf>, Vec<File>) = paths
+                .iter()
+                .filter_map(|path| File::open(path).ok().map(|file| (path, file)))
+                .unzip();
+            if self.buffers.len() < files.len() {
+                self.buffers
+                    .resize_with(files.len(), || vec![0u8; BUFFER_SIZE]);
+            }
+            let results = self.read(&files)?;
+
+            Ok(paths
+                .into_iter()
+                .zip(&self.buffers)
+                .zip(results)
+                .filter(|(_, read)| *read >= 0 && (*read as usize) < BUFFER_SIZE)
+                .filter_map(|((path, buffer), read)| {
+                    std::str::from_utf8(&buffer[..read as usize])
+                        .ok()
+                        .map(|content| (path.clone(), content.to_owned()))
+                })
+                .collect())
+        }
+
+        /// Reads the start of every file into its buffer, returns the number
+        /// of bytes read or the negated errno per file
+        fn read(&mut self, files: &[File]) -> io::Result<Vec<i32>> {
+            let mut results = vec![-libc::EIO; files.len()];
+            let batch_size = self.ring.params().sq_entries() as usize;
+
+            for start in (0..files.len()).step_by(batch_size) {
+                let end = files.len().min(start + batch_size);
+                {
+                    let mut submission = self.ring.submission();
+                    for (i, file) in files.iter().enumerate().take(end).skip(start) {
+                        let buffer = &mut self.buffers[i];
+                        let entry = opcode::Read::new(
+                            types::Fd(file.as_raw_fd()),
+                            buffer.as_mut_ptr(),
+                            buffer.len() as u32,
+                        )
+                        .build()
+                        .user_data(i as u64);
+                        // SAFETY: the buffer is not freed before the read
+                        // completed and the queue has room for a whole batch,
+                        // as the previous one was completed
+                        unsafe { submission.push(&entry) }.map_err(|_| {
+                            io::Error::new(io::ErrorKind::Other, "io_uring queue is full")
+                        })?;
+                        self.in_flight += 1;
+                    }
+                }
+
+                while self.in_flight > 0 {
+                    match self.ring.submit_and_wait(1) {
+                        Ok(_) => {}
+                        Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                        Err(err) => return Err(err),
+                    }
+                    for cqe in self.ring.completion() {
+                        results[cqe.user_data() as usize] = cqe.result();
+                        self.in_flight -= 1;
+                    }
+                }
+            }
+
+            Ok(results)
+        }
+    }
+
+    impl Drop for Reader {
+        fn drop(&mut self) {
+            if self.in_flight > 0 {
+                std::mem::forget(std::mem::take(&mut self.buffers));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_files() -> anyhow::Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let mut paths: Vec<PathBuf> = (0..20)
+            .map(|i| tmp.path().join(format!("file{i}")))
+            .collect();
+        for (i, path) in paths.iter().enumerate() {
+            fs::write(path, format!("content {i}\n"))?;
+        }
+        paths.push(tmp.path().join("missing"));
+
+        // io_uring may be blocked in the test environment
+        #[cfg(feature = "io-uring")]
+        let uring = uring::Reader::new()
+            .and_then(|mut reader| Ok([reader.read_files(&paths)?, reader.read_files(&paths)?]))
+            .ok();
+        #[cfg(not(feature = "io-uring"))]
+        let uring: Option<[HashMap<PathBuf, String>; 2]> = None;
+        let mut reader = BatchReader::new();
+        for files in [
+            reader.read_files(&paths),
+            reader.read_files(&paths),
+            read_files_parallel(&paths),
+        ]
+        .into_iter()
+        .chain(uring.into_iter().flatten())
+        {
+            assert_eq!(files.len(), 20);
+            assert_eq!(files[&paths[7]], "content 7\n");
+            assert!(!files.contains_key(&paths[20]));
+        }
+        assert!(reader.read_files(&[]).is_empty());
+        Ok(())
+    }
+}
@@ -1,17 +1,21 @@
+use std::fs::File;
+use std::io::BufReader;
 use std::path::PathBuf;
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 use libcontainer::container::builder::ContainerBuilder;
+use libcontainer::container::tenant_builder::TenantSeccomp;
 use libcontainer::syscall::syscall::SyscallType;
 use liboci_cli::Exec;
 use nix::sys::wait::{waitpid, WaitStatus};
 
+use crate::commands::load_container;
 use crate::workload::executor::default_executor;
 
 pub fn exec(args: Exec, root_path: PathBuf) -> Result<i32> {
     let pid = ContainerBuilder::new(args.container_id.clone(), SyscallType::default())
         .with_executor(default_executor())
-        .with_root_path(root_path)?
+        .with_root_path(root_path.clone())?
         .with_console_socket(args.console_socket.as_ref())
         .with_pid_file(args.pid_file.as_ref())?
         .validate_id()?
@@ -21,6 +25,9 @@ pub fn exec(args: Exec, root_path: PathBuf) -> Result<i32> {
         .with_env(args.env.clone().into_iter().collect())
         .with_process(args.process.as_ref())
         .with_no_new_privs(args.no_new_privs)
+        .with_apparmor_profile(args.apparmor.clone())
+        .with_seccomp(seccomp(args.seccomp.as_deref())?)
+        .with_cgroup_policy(args.cgroup_policy.as_deref().map(str::parse).transpose()?)
         .with_container_args(args.command.clone())
         .build()?;
 
@@ -32,9 +39,31 @@ pub fn exec(args: Exec, root_path: PathBuf) -> Result<i32> {
         return Ok(0);
     }
 
-    match waitpid(pid, None)? {
-        WaitStatus::Exited(_, status) => Ok(status),
-        WaitStatus::Signaled(_, sig, _) => Ok(sig as i32),
-        _ => Ok(0),
+    let status = match waitpid(pid, None)? {
+        WaitStatus::Exited(_, status) => status,
+        WaitStatus::Signaled(_, sig, _) => sig as i32,
+        _ => 0,
+    };
+    // the process exited already, failing to record it must not fail the exec
+    let recorded = load_container(root_path, &args.container_id)
+        .and_then(|container| Ok(container.record_exec_exit(pid, status)?));
+    if let Err(err) = recorded {
+        tracing::warn!(?err, ?pid, "failed to record the exit of the exec process");
+    }
+
+    Ok(status)
+}
+
+fn seccomp(profile: Option<&str>) -> Result<TenantSeccomp> {
+    match profile {
+        None => Ok(TenantSeccomp::Inherit),
+        Some("unconfined") => Ok(TenantSeccomp::Unconfined),
+        Some(path) => {
+            let file = File::open(path)
+                .with_context(|| format!("failed to open seccomp profile {path}"))?;
+            let profile = serde_json::from_reader(BufReader::new(file))
+                .with_context(|| format!("failed to parse seccomp profile {path}"))?;
+            Ok(TenantSeccomp::Profile(profile))
+        }
     }
 }
@@ -1,8 +1,212 @@
 //! Contains Functionality of `features` container command
+use std::collections::HashMap;
+
 use anyhow::Result;
-use liboci_cli::Features;
+use libcontainer::kernel::{self, Feature};
+use libcontainer::oci_spec::runtime::{
+    version, ApparmorBuilder, CgroupBuilder, Features, FeaturesBuilder, LinuxFeatureBuilder,
+    LinuxNamespaceType, SeccompBuilder,
+};
+use liboci_cli::Features as FeaturesArgs;
+
+/// Annotation reporting whether the container processes can be frozen by the
+/// cgroup freezer, which `pause` depends on.
+pub const FREEZER_ANNOTATION: &str = "org.youki.features.cgroup.freezer";
+
+/// Annotation listing the cgroup v2 controllers which are delegated to the
+/// cgroup youki runs in, separated by commas. A rootless youki can only
+/// limit the resources of these controllers.
+pub const DELEGATED_CONTROLLERS_ANNOTATION: &str = "org.youki.features.cgroup.delegated";
+
+/// Annotation reporting whether youki runs from a sealed copy of its binary,
+/// the protection against CVE-2019-5736.
+pub const SEALED_ANNOTATION: &str = "org.youki.features.selfSealing";
+
+/// Prefix of the annotations reporting the kernel version and whether the
+/// kernel features youki uses if available are supported
+pub const KERNEL_ANNOTATION_PREFIX: &str = "org.youki.features.kernel.";
+
+const HOOKS: &[&str] = &[
+    "prestart",
+    "createRuntime",
+    "createContainer",
+    "startContainer",
+    "poststart",
+    "poststop",
+];
 
-/// lists all existing containers
-pub fn features(_: Features) -> Result<()> {
+const MOUNT_OPTIONS: &[&str] = &[
+    "defaults",
+    "ro",
+    "rw",
+    "suid",
+    "nosuid",
+    "dev",
+    "nodev",
+    "exec",
+    "noexec",
+    "sync",
+    "async",
+    "dirsync",
+    "remount",
+    "mand",
+    "nomand",
+    "atime",
+    "noatime",
+    "diratime",
+    "nodiratime",
+    "bind",
+    "rbind",
+    "unbindable",
+    "runbindable",
+    "private",
+    "rprivate",
+    "shared",
+    "rshared",
+    "slave",
+    "rslave",
+    "relatime",
+    "norelatime",
+    "strictatime",
+    "nostrictatime",
+];
+
+const NAMESPACES: &[LinuxNamespaceType] = &[
+    LinuxNamespaceType::Mount,
+    LinuxNamespaceType::Cgroup,
+    LinuxNamespaceType::Uts,
+    LinuxNamespaceType::Ipc,
+    LinuxNamespaceType::User,
+    LinuxNamespaceType::Pid,
+    LinuxNamespaceType::Network,
+    LinuxNamespaceType::Time,
+];
+
+/// prints the features supported by youki and the host as JSON
+pub fn features(_: FeaturesArgs) -> Result<()> {
+    println!("{}", serde_json::to_string_pretty(&get_features()?)?);
     Ok(())
 }
+
+fn get_features() -> Result<Features> {
+    let mut capabilities: Vec<String> = caps::all().iter().map(|cap| cap.to_string()).collect();
+    capabilities.sort();
+
+    let linux = LinuxFeatureBuilder::default()
+        .namespaces(NAMESPACES.to_vec())
+        .capabilities(capabilities)
+        .cgroup(
+            CgroupBuilder::default()
+                .v1(cfg!(feature = "v1"))
+                .v2(cfg!(feature = "v2"))
+                .systemd(cfg!(feature = "systemd"))
+                .systemd_user(cfg!(feature = "systemd"))
+                .build()?,
+        )
+        .seccomp(
+            SeccompBuilder::default()
+                .enabled(cfg!(feature = "seccomp"))
+                .build()?,
+        )
+        .apparmor(
+            ApparmorBuilder::default()
+                .enabled(libcontainer::apparmor::is_enabled().unwrap_or(false))
+                .build()?,
+        )
+        .build()?;
+
+    let features = FeaturesBuilder::default()
+        .oci_version_min("1.0.0")
+        .oci_version_max(version())
+        .hooks(to_strings(HOOKS))
+        .mount_options(to_strings(MOUNT_OPTIONS))
+        .linux(linux)
+        .annotations(host_annotations())
+        .build()?;
+
+    Ok(features)
+}
+
+/// Reports host dependent capabilities which the OCI features document has no
+/// field for. A capability is reported as "false" when the host lacks it, so
+/// callers can tell it apart from an older youki which does not report it.
+fn host_annotations() -> HashMap<String, String> {
+    let freezer = libcgroups::common::freezer_available().unwrap_or_else(|err| {
+        tracing::warn!(?err, "failed to detect the cgroup freezer");
+        false
+    });
+
+    let mut annotations = HashMap::from([
+        (FREEZER_ANNOTATION.to_owned(), freezer.to_string()),
+        (
+            SEALED_ANNOTATION.to_owned(),
+            crate::seal::enabled().to_string(),
+        ),
+    ]);
+    match libcgroups::common::probe_own_controllers() {
+        Ok(Some(controllers)) => {
+            let delegated: Vec<String> = controllers
+                .into_iter()
+                .filter(|availability| availability.delegated)
+                .map(|availability| availability.controller)
+                .collect();
+            annotations.insert(
+                DELEGATED_CONTROLLERS_ANNOTATION.to_owned(),
+                delegated.join(","),
+            );
+        }
+        Ok(None) => {}
+        Err(err) => tracing::warn!(?err, "failed to probe the delegated cgroup controllers"),
+    }
+    if let Some(version) = kernel::version() {
+        annotations.insert(
+            format!("{KERNEL_ANNOTATION_PREFIX}version"),
+            version.to_string(),
+        );
+    }
+    for feature in Feature::ALL {
+        annotations.insert(
+            format!("{KERNEL_ANNOTATION_PREFIX}{feature}"),
+            kernel::has(feature).to_string(),
+        );
+    }
+
+    annotations
+}
+
+fn to_strings(values: &[&str]) -> Vec<String> {
+    values.iter().map(|v| v.to_string()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_get_features() -> Result<()> {
+        let features = get_features()?;
+        assert_eq!(features.oci_version_max(), &version());
+        assert!(features
+            .annotations()
+            .as_ref()
+            .unwrap()
+            .contains_key(FREEZER_ANNOTATION));
+        assert_eq!(
+            features.annotations().as_ref().unwrap()[SEALED_ANNOTATION],
+            "true"
+        );
+        assert!(features
+            .annotations()
+            .as_ref()
+            .unwrap()
+            .contains_key("org.youki.features.kernel.clone3"));
+        let linux = features.linux().as_ref().unwrap();
+        assert!(linux
+            .capabilities()
+            .as_ref()
+            .unwrap()
+            .contains(&"CAP_SYS_ADMIN".to_owned()));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,85 @@
+//! Contains functionality of the finished command, which lists and removes
+//! the records of containers deleted with their state kept
+use std::fmt::Write as _;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local, Utc};
+use clap::Parser;
+use libcontainer::container::finished;
+use tabwriter::TabWriter;
+
+/// List or remove the records of containers which were deleted with their state kept,
+/// by run --keep or delete --keep-state
+#[derive(Parser, Debug)]
+pub struct Finished {
+    /// Remove the records instead of listing them
+    #[clap(long)]
+    pub prune: bool,
+    /// Only records of containers which finished at least this many seconds ago
+    #[clap(long)]
+    pub older_than: Option<u64>,
+    /// Only records of this container
+    pub container_id: Option<String>,
+}
+
+pub fn finished(args: Finished, root_path: PathBuf) -> Result<()> {
+    let older_than = args.older_than.map(Duration::from_secs);
+    let now = Utc::now();
+    let records = finished::list(&root_path)
+        .context("failed to list finished containers")?
+        .into_iter()
+        .filter(|f| {
+            args.container_id
+                .as_ref()
+                .map_or(true, |id| &f.record.id == id)
+        })
+        .filter(|f| {
+            older_than.map_or(true, |older_than| {
+                (now - f.record.finished)
+                    .to_std()
+                    .map_or(false, |age| age >= older_than)
+            })
+        });
+
+    if args.prune {
+        for record in records {
+            finished::remove(&record)?;
+            println!("{}", record.path.display());
+        }
+        return Ok(());
+    }
+
+    let mut content = String::new();
+    for finished in records {
+        let record = &finished.record;
+        let exit_status = record
+            .exit_status
+            .map(|status| status.to_string())
+            .unwrap_or_default();
+        let created = record.created.map(local_time).unwrap_or_default();
+        let _ = writeln!(
+            content,
+            "{}\t{}\t{}\t{}\t{}",
+            record.id,
+            exit_status,
+            created,
+            local_time(record.finished),
+            finished.path.display(),
+        );
+    }
+
+    let mut tab_writer = TabWriter::new(io::stdout());
+    writeln!(&mut tab_writer, "ID\tEXIT\tCREATED\tFINISHED\tRECORD")?;
+    write!(&mut tab_writer, "{content}")?;
+    tab_writer.flush()?;
+
+    Ok(())
+}
+
+fn local_time(utc: DateTime<Utc>) -> String {
+    let local: DateTime<Local> = DateTime::from(utc);
+    local.to_rfc3339_opts(chrono::SecondsFormat::Secs, false)
+}
@@ -8,6 +8,7 @@ use anyhow::Result;
 use clap::Parser;
 #[cfg(feature = "v2")]
 use libcgroups::{common::CgroupSetup, v2::controller_type::ControllerType};
+use libcontainer::kernel::{self, Feature};
 use libcontainer::user_ns;
 use procfs::{CpuInfo, Current, Meminfo};
 /// Show information about the system
@@ -17,6 +18,7 @@ pub struct Info {}
 pub fn info(_: Info) -> Result<()> {
     print_youki();
     print_kernel();
+    print_kernel_features();
     print_os();
     print_hardware();
     print_cgroups();
@@ -52,6 +54,19 @@ pub fn print_kernel() {
     );
 }
 
+/// Print whether the kernel features youki uses if available are supported
+pub fn print_kernel_features() {
+    println!("Kernel Features");
+    for feature in Feature::ALL {
+        let status = if kernel::has(feature) {
+            "available"
+        } else {
+            "unavailable"
+        };
+        println!("  {:<16}{}", feature.name(), status);
+    }
+}
+
 /// Prints OS Distribution information
 // see https://www.freedesktop.org/software/systemd/man/os-release.html
 pub fn print_os() {
@@ -122,6 +137,7 @@ pub fn print_cgroups() {
     print_cgroup_mounts();
     #[cfg(feature = "v2")]
     print_cgroup_v2_controllers();
+    print_cgroup_delegation();
 }
 
 pub fn print_cgroups_setup() {
@@ -152,6 +168,20 @@ pub fn print_cgroup_mounts() {
     }
 }
 
+/// Prints which controllers the cgroup youki runs in delegates to the
+/// cgroups of containers created below it, and why the others are not
+pub fn print_cgroup_delegation() {
+    if let Ok(Some(controllers)) = libcgroups::common::probe_own_controllers() {
+        println!("CGroup v2 delegation");
+        for availability in controllers {
+            let status = availability
+                .reason()
+                .unwrap_or_else(|| "delegated".to_owned());
+            println!("  {:<16}{}", availability.controller, status);
+        }
+    }
+}
+
 #[cfg(feature = "v2")]
 pub fn print_cgroup_v2_controllers() {
     let cgroup_setup = libcgroups::common::get_cgroup_setup();
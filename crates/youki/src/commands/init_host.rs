@@ -0,0 +1,212 @@
+//! Contains functionality of the hidden init-host command, which lets youki
+//! act as the minimal init process (PID 1) of a microVM that runs a single
+//! container from a bundle baked into the image.
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use clap::Parser;
+use libcontainer::container::builder::ContainerBuilder;
+use libcontainer::syscall::syscall::SyscallType;
+use nix::errno::Errno;
+use nix::mount::{mount, MsFlags};
+use nix::sys::reboot::{reboot, set_cad_enabled, RebootMode};
+use nix::sys::signalfd::SigSet;
+use nix::unistd::{getpid, sync, Pid};
+
+use crate::commands::run::handle_foreground;
+use crate::observability::{self, ObservabilityConfig};
+use crate::workload::executor::default_executor;
+use crate::{rootpath, seal};
+
+/// Run as PID 1 of a virtual machine and start the container of the given bundle
+#[derive(Parser, Debug)]
+pub struct InitHost {
+    /// Path to the bundle directory of the container to run
+    #[clap(short, long, default_value = "/etc/youki/bundle")]
+    pub bundle: PathBuf,
+    /// Name of the container instance
+    #[clap(long, default_value = "init")]
+    pub container_id: String,
+}
+
+struct PseudoFs {
+    source: &'static str,
+    target: &'static str,
+    fstype: &'static str,
+    flags: MsFlags,
+    data: Option<&'static str>,
+}
+
+// The filesystems the container runtime itself needs, in mount order.
+const PSEUDO_FILESYSTEMS: &[PseudoFs] = &[
+    PseudoFs {
+        source: "proc",
+        target: "/proc",
+        fstype: "proc",
+        flags: MsFlags::MS_NOSUID
+            .union(MsFlags::MS_NODEV)
+            .union(MsFlags::MS_NOEXEC),
+        data: None,
+    },
+    PseudoFs {
+        source: "sysfs",
+        target: "/sys",
+        fstype: "sysfs",
+        flags: MsFlags::MS_NOSUID
+            .union(MsFlags::MS_NODEV)
+            .union(MsFlags::MS_NOEXEC),
+        data: None,
+    },
+    PseudoFs {
+        source: "devtmpfs",
+        target: "/dev",
+        fstype: "devtmpfs",
+        flags: MsFlags::MS_NOSUID,
+        data: Some("mode=755"),
+    },
+    PseudoFs {
+        source: "tmpfs",
+        target: "/run",
+        fstype: "tmpfs",
+        flags: MsFlags::MS_NOSUID.union(MsFlags::MS_NODEV),
+        data: Some("mode=755"),
+    },
+    PseudoFs {
+        source: "cgroup2",
+        target: "/sys/fs/cgroup",
+        fstype: "cgroup2",
+        flags: MsFlags::MS_NOSUID
+            .union(MsFlags::MS_NODEV)
+            .union(MsFlags::MS_NOEXEC),
+        data: None,
+    },
+];
+
+/// Runs the container and powers off the machine once it exits. This only
+/// returns if the machine could not be powered off, as PID 1 exiting would
+/// make the kernel panic.
+pub fn init_host(
+    args: InitHost,
+    root_path: Option<PathBuf>,
+    observability: ObservabilityConfig,
+) -> Result<()> {
+    if getpid() != Pid::from_raw(1) {
+        bail!("init-host must run as PID 1");
+    }
+
+    match prepare(observability) {
+        // logging may not be set up, so this can only go to the console
+        Err(err) => eprintln!("failed to prepare the machine: {err:?}"),
+        Ok(()) => match run_container(&args, root_path) {
+            Ok(status) => tracing::info!(status, "container {} exited", args.container_id),
+            Err(err) => tracing::error!(?err, "failed to run container {}", args.container_id),
+        },
+    }
+
+    sync();
+    reboot(RebootMode::RB_POWER_OFF).context("failed to power off")?;
+    Ok(())
+}
+
+/// Mounts the pseudo filesystems before the binary is sealed and logging is
+/// set up, as both need /proc. The sealed copy of the binary runs this again
+/// and finds the filesystems mounted already.
+fn prepare(observability: ObservabilityConfig) -> Result<()> {
+    for pseudo_fs in PSEUDO_FILESYSTEMS {
+        mount_pseudo_fs(pseudo_fs)?;
+    }
+    seal::ensure_sealed()?;
+    observability::init(observability).context("failed to initialize observability")
+}
+
+fn run_container(args: &InitHost, root_path: Option<PathBuf>) -> Result<i32> {
+    // Signals have to be blocked before any child exists, so that no
+    // SIGCHLD gets lost before the reaping loop runs.
+    SigSet::all()
+        .thread_block()
+        .context("failed to call pthread_sigmask")?;
+    // Turn Ctrl-Alt-Del into a SIGINT for PID 1, which is forwarded to the
+    // container like any other signal.
+    set_cad_enabled(false).context("failed to disable Ctrl-Alt-Del")?;
+
+    // The state directory lives in /run, which was mounted by prepare.
+    let root_path = rootpath::determine(root_path)?;
+
+    let mut container = ContainerBuilder::new(args.container_id.clone(), SyscallType::default())
+        .with_executor(default_executor())
+        .with_root_path(root_path)?
+        .validate_id()?
+        .as_init(&args.bundle)
+        .with_systemd(false)
+        .with_detach(false)
+        .build()?;
+    container
+        .start()
+        .with_context(|| format!("failed to start container {}", args.container_id))?;
+
+    let init_pid = container
+        .pid()
+        .context("expects a container init pid in the container state")?;
+    let status = handle_foreground(init_pid);
+    if let Err(err) = container.delete(true) {
+        tracing::warn!(?err, "failed to delete container {}", args.container_id);
+    }
+
+    status
+}
+
+fn mount_pseudo_fs(pseudo_fs: &PseudoFs) -> Result<()> {
+    let target = Path::new(pseudo_fs.target);
+    fs::create_dir_all(target).with_context(|| format!("failed to create {}", target.display()))?;
+    // Mounting again would stack a second instance on top of the first one.
+    if is_mount_point(target)? {
+        return Ok(());
+    }
+    match mount(
+        Some(pseudo_fs.source),
+        target,
+        Some(pseudo_fs.fstype),
+        pseudo_fs.flags,
+        pseudo_fs.data,
+    ) {
+        // the kernel or the image may have mounted it already
+        Ok(()) | Err(Errno::EBUSY) => Ok(()),
+        Err(err) => Err(err).with_context(|| format!("failed to mount {}", target.display())),
+    }
+}
+
+/// A directory on a different device than its parent is a mount point.
+fn is_mount_point(path: &Path) -> Result<bool> {
+    let parent = path.parent().unwrap_or(path);
+    let dev = |path: &Path| {
+        fs::metadata(path)
+            .map(|metadata| metadata.dev())
+            .with_context(|| format!("failed to stat {}", path.display()))
+    };
+    Ok(dev(path)? != dev(parent)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_init_host_requires_pid_1() {
+        let args = InitHost {
+            bundle: PathBuf::from("/nonexistent"),
+            container_id: "init".to_owned(),
+        };
+        assert!(init_host(args, None, ObservabilityConfig::default()).is_err());
+    }
+
+    #[test]
+    fn test_is_mount_point() -> Result<()> {
+        assert!(is_mount_point(Path::new("/proc"))?);
+        let tmp = tempfile::tempdir()?;
+        fs::create_dir(tmp.path().join("dir"))?;
+        assert!(!is_mount_point(&tmp.path().join("dir"))?);
+        Ok(())
+    }
+}
@@ -1,28 +1,54 @@
 use std::fs;
 use std::path::{Path, PathBuf};
 
-use anyhow::{bail, Context, Result};
+use anyhow::{Context, Result};
 use libcgroups::common::AnyCgroupManager;
 use libcontainer::container::Container;
+use libcontainer::error::LibcontainerError;
 
+pub mod admit;
+pub mod batch;
 pub mod checkpoint;
 pub mod completion;
+pub mod console_tee;
 pub mod create;
+pub mod debug;
 pub mod delete;
+pub mod diff;
 pub mod events;
 pub mod exec;
 pub mod features;
+pub mod finished;
 pub mod info;
+pub mod init_host;
 pub mod kill;
 pub mod list;
 pub mod pause;
 pub mod ps;
+pub mod purge;
 pub mod resume;
 pub mod run;
 pub mod spec_json;
 pub mod start;
 pub mod state;
+pub mod top;
 pub mod update;
+pub mod verify;
+
+/// Exit code of run and exec if youki failed before the container process
+/// ran, as opposed to an exit code of the process itself
+const RUNTIME_FAILURE_EXIT_CODE: i32 = 125;
+
+/// Returns the exit code of a failed run or exec. Processes which could not
+/// be executed exit with 127 if they were not found and with 126 if they are
+/// not executable, like in a shell, any other failure of youki exits with 125.
+pub fn failure_exit_code(err: &anyhow::Error) -> i32 {
+    err.chain()
+        .filter_map(|cause| cause.downcast_ref::<LibcontainerError>())
+        .find_map(|err| err.exec_failure_kind())
+        .and_then(|kind| kind.exit_code())
+        .unwrap_or(RUNTIME_FAILURE_EXIT_CODE)
+}
 
 fn construct_container_root<P: AsRef<Path>>(root_path: P, container_id: &str) -> Result<PathBuf> {
     // resolves relative paths, symbolic links etc. and get complete path
@@ -40,7 +66,8 @@ fn construct_container_root<P: AsRef<Path>>(root_path: P, container_id: &str) ->
 fn load_container<P: AsRef<Path>>(root_path: P, container_id: &str) -> Result<Container> {
     let container_root = construct_container_root(root_path, container_id)?;
     if !container_root.exists() {
-        bail!("container {} does not exist.", container_id)
+        return Err(LibcontainerError::NoDirectory)
+            .with_context(|| format!("container {container_id} does not exist."));
     }
 
     Container::load(container_root)
@@ -62,6 +89,44 @@ fn create_cgroup_manager<P: AsRef<Path>>(
             cgroup_path: container.spec()?.cgroup_path,
             systemd_cgroup: container.systemd(),
             container_name: container.id().to_string(),
+            init_leaf: false,
+            host: libcgroups::common::CgroupHost::default(),
+            state_root: None,
         },
     )?)
 }
+
+#[cfg(test)]
+mod tests {
+    use anyhow::anyhow;
+    use libcontainer::workload::{ExecFailure, ExecFailureKind};
+
+    use super::*;
+
+    #[test]
+    fn test_failure_exit_code() {
+        let not_found = anyhow::Error::new(LibcontainerError::Exec(ExecFailure::new(
+            ExecFailureKind::NotFound,
+            "executable 'missing' not found in $PATH",
+        )))
+        .context("failed to exec");
+        assert_eq!(failure_exit_code(&not_found), 127);
+
+        let not_executable = anyhow::Error::new(LibcontainerError::Exec(ExecFailure::new(
+            ExecFailureKind::NotExecutable,
+            "executable 'data' does not have correct permissions",
+        )));
+        assert_eq!(failure_exit_code(&not_executable), 126);
+
+        let other = anyhow::Error::new(LibcontainerError::Exec(ExecFailure::new(
+            ExecFailureKind::Other,
+            "failed to set up mounts",
+        )));
+        assert_eq!(failure_exit_code(&other), 125);
+        assert_eq!(failure_exit_code(&anyhow!("invalid config")), 125);
+        assert_eq!(
+            failure_exit_code(&LibcontainerError::NoDirectory.into()),
+            125
+        );
+    }
+}
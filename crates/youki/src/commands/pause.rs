@@ -1,7 +1,9 @@
 //! Contains functionality of pause container command
 use std::path::PathBuf;
+use std::time::Duration;
 
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
+use libcontainer::error::LibcontainerError;
 use liboci_cli::Pause;
 
 use crate::commands::load_container;
@@ -11,10 +13,24 @@ use crate::commands::load_container;
 // For more information see :
 // https://man7.org/linux/man-pages/man7/cgroups.7.html
 // https://www.kernel.org/doc/Documentation/cgroup-v1/freezer-subsystem.txt
+// On cgroup v1 hosts without the freezer controller, --sigstop-fallback
+// stops the processes with SIGSTOP instead.
 pub fn pause(args: Pause, root_path: PathBuf) -> Result<()> {
     tracing::debug!("start pausing container {}", args.container_id);
     let mut container = load_container(root_path, &args.container_id)?;
-    container
-        .pause()
-        .with_context(|| format!("failed to pause container {}", args.container_id))
+    match container.pause_with_timeout(Duration::from_secs(args.timeout)) {
+        Err(LibcontainerError::FreezerUnavailable) if args.sigstop_fallback => {
+            tracing::warn!("freezer cgroup is unavailable, pausing with SIGSTOP");
+            container.pause_with_signal()
+        }
+        Err(LibcontainerError::FreezerUnavailable) => {
+            bail!(
+                "failed to pause container {}: the freezer cgroup is not available, \
+                 use --sigstop-fallback to pause with SIGSTOP instead",
+                args.container_id
+            )
+        }
+        res => res,
+    }
+    .with_context(|| format!("failed to pause container {}", args.container_id))
 }
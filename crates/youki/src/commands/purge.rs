@@ -0,0 +1,26 @@
+//! Contains functionality of the purge command, which cleans up after
+//! containers whose creation was interrupted
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+use clap::Parser;
+
+/// Stop the systemd units left behind by containers which no longer exist
+///
+/// Only units of containers kept in the root directory youki is run with are
+/// stopped, units of containers kept in another root are left alone.
+#[derive(Parser, Debug)]
+pub struct Purge {}
+
+pub fn purge(_: Purge, root_path: PathBuf) -> Result<()> {
+    let units = libcgroups::systemd::units::purge(&root_path, |container_id| {
+        root_path.join(container_id).exists()
+    })
+    .context("failed to purge systemd units")?;
+
+    for unit in units {
+        println!("{unit}");
+    }
+
+    Ok(())
+}
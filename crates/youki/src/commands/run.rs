@@ -1,32 +1,238 @@
+mod handoff;
+mod health;
+
+use std::ops::{Deref, DerefMut};
+use std::os::fd::AsRawFd;
 use std::path::PathBuf;
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::time::Duration;
 
-use anyhow::{Context, Result};
+use anyhow::{bail, Context, Result};
 use libcontainer::container::builder::ContainerBuilder;
+use libcontainer::container::Container;
+use libcontainer::health::HealthCheck;
+use libcontainer::notify_socket::StartHandshake;
+use libcontainer::oci_spec::runtime::Spec;
+use libcontainer::process::parent_death::parse_signal;
+use libcontainer::process::timeouts::parse_timeouts;
+use libcontainer::rlimit::parse_default_rlimits;
 use libcontainer::syscall::syscall::SyscallType;
+use libcontainer::volume::VolumeHelper;
 use liboci_cli::Run;
-use nix::sys::signal::{self, kill};
+use nix::sys::signal::{self, kill, sigaction, SaFlags, SigAction, SigHandler, Signal};
 use nix::sys::signalfd::SigSet;
 use nix::sys::wait::{waitpid, WaitPidFlag, WaitStatus};
 use nix::unistd::Pid;
 
+use self::handoff::{Handoff, Upgrade};
+use self::health::HealthChecker;
+use super::console_tee::{TeeProcess, TeeProxy};
+use super::load_container;
+use crate::usernet::{self, UserNet};
 use crate::workload::executor::default_executor;
 
+/// Signals which would otherwise terminate youki while `run --rm` is still
+/// setting up the container.
+const INTERRUPT_SIGNALS: [Signal; 4] = [
+    Signal::SIGINT,
+    Signal::SIGTERM,
+    Signal::SIGHUP,
+    Signal::SIGQUIT,
+];
+
+/// How long a foreground run waits for the console proxy to copy the last
+/// output of the container into the console log before deleting it.
+const CONSOLE_DRAIN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The last interrupt signal received before the foreground loop took over
+/// signal handling, or 0 if there was none.
+static PENDING_INTERRUPT: AtomicI32 = AtomicI32::new(0);
+
+extern "C" fn record_interrupt(signal: nix::libc::c_int) {
+    PENDING_INTERRUPT.store(signal, Ordering::SeqCst);
+}
+
+fn install_interrupt_handlers() -> Result<()> {
+    let action = SigAction::new(
+        SigHandler::Handler(record_interrupt),
+        SaFlags::SA_RESTART,
+        SigSet::empty(),
+    );
+    for signal in INTERRUPT_SIGNALS {
+        // Safe because the handler only stores into an atomic.
+        unsafe { sigaction(signal, &action) }
+            .with_context(|| format!("failed to install handler for {signal}"))?;
+    }
+
+    Ok(())
+}
+
+fn take_pending_interrupt() -> Option<Signal> {
+    match PENDING_INTERRUPT.swap(0, Ordering::SeqCst) {
+        0 => None,
+        signal => Signal::try_from(signal).ok(),
+    }
+}
+
+/// Deletes the wrapped container when dropped while armed. This is how
+/// `run --rm` guarantees that no early return, including an interrupt before
+/// the container started, leaves its state directory or cgroup behind.
+struct DeleteGuard {
+    container: Container,
+    armed: bool,
+}
+
+impl DeleteGuard {
+    fn new(container: Container, armed: bool) -> Self {
+        Self { container, armed }
+    }
+
+    /// Deletes the container, keeping a record of it with the exit status of
+    /// its process if `keep` is set
+    fn delete(mut self, keep: bool, exit_status: Option<i32>) -> Result<()> {
+        self.armed = false;
+        usernet::teardown(&self.container.root)?;
+        if !keep {
+            return self
+                .container
+                .delete(true)
+                .with_context(|| format!("failed to delete container {}", self.container.id()));
+        }
+
+        let finished = self
+            .container
+            .delete_and_keep(true, exit_status)
+            .with_context(|| format!("failed to delete container {}", self.container.id()))?;
+        tracing::info!(
+            id = self.container.id(),
+            ?finished,
+            "kept finished container"
+        );
+        Ok(())
+    }
+}
+
+impl Deref for DeleteGuard {
+    type Target = Container;
+
+    fn deref(&self) -> &Self::Target {
+        &self.container
+    }
+}
+
+impl DerefMut for DeleteGuard {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.container
+    }
+}
+
+impl Drop for DeleteGuard {
+    fn drop(&mut self) {
+        if !self.armed {
+            return;
+        }
+
+        if let Err(err) = usernet::teardown(&self.container.root) {
+            tracing::warn!(?err, id = ?self.container.id(), "failed to stop user-mode networking");
+        }
+        if let Err(err) = self.container.delete(true) {
+            tracing::warn!(?err, id = ?self.container.id(), "failed to remove container");
+        }
+    }
+}
+
 pub fn run(args: Run, root_path: PathBuf, systemd_cgroup: bool) -> Result<i32> {
-    let mut container = ContainerBuilder::new(args.container_id.clone(), SyscallType::default())
-        .with_executor(default_executor())
+    if let Some(handoff) = Handoff::take()? {
+        return resume(args, root_path, handoff);
+    }
+    let upgrade = args
+        .allow_upgrade
+        .then(Upgrade::from_command_line)
+        .transpose()?;
+
+    if args.rm {
+        // Until the foreground loop below owns signal handling, an interrupt
+        // would kill youki halfway and litter the root path. Record it
+        // instead, so that we can act on it once cleanup is guaranteed.
+        install_interrupt_handlers()?;
+    }
+
+    let spec = Spec::load(args.bundle.join("config.json"))?;
+    let usernet = UserNet::resolve(
+        args.user_net.as_deref(),
+        &args.publish,
+        spec.annotations().as_ref(),
+    )?;
+    if let Some(usernet) = &usernet {
+        usernet.validate(&spec)?;
+    }
+
+    let health_check = HealthCheck::from_annotations(spec.annotations().as_ref())?;
+    if health_check.is_some() && args.detach {
+        tracing::warn!("health checks are only run for containers in the foreground");
+    }
+
+    let timeouts = parse_timeouts(args.timeout, &args.phase_timeout)?;
+    // the proxy has to run before the container init sends its pty master
+    let console_tee = match (&args.console_socket, &args.console_log) {
+        (Some(console_socket), Some(console_log)) => {
+            Some(TeeProcess::start(&root_path, console_socket, console_log)?)
+        }
+        _ => None,
+    };
+    let container = ContainerBuilder::new(args.container_id.clone(), SyscallType::default())
+        .with_executor(default_executor().with_builtin_pause(args.builtin_pause))
         .with_pid_file(args.pid_file.as_ref())?
-        .with_console_socket(args.console_socket.as_ref())
-        .with_root_path(root_path)?
+        .with_console_socket(
+            console_tee
+                .as_ref()
+                .map(TeeProcess::socket)
+                .or(args.console_socket.as_deref()),
+        )
+        .with_root_path(root_path.clone())?
         .with_preserved_fds(args.preserve_fds)
+        .with_timeouts(timeouts)
         .validate_id()?
         .as_init(&args.bundle)
         .with_systemd(systemd_cgroup)
         .with_detach(args.detach)
         .with_no_pivot(args.no_pivot)
+        .with_pdeathsig(args.pdeathsig.as_deref().map(parse_signal).transpose()?)
+        .with_orphan_policy(args.orphan_policy.as_deref().map(str::parse).transpose()?)
+        .with_default_rlimits(parse_default_rlimits(&args.default_ulimit)?)
+        .with_volume_helper(args.volume_helper.as_ref().map(|helper| {
+            VolumeHelper::new(helper).with_timeout(Duration::from_secs(args.volume_helper_timeout))
+        }))
+        .with_timezone(args.tz.as_deref().map(str::parse).transpose()?)
+        .with_setup_trace(args.trace_setup)
+        // youki starts the container itself, there is no need for a socket
+        // other processes could connect to
+        .with_start_handshake(StartHandshake::SocketPair)
         .build()?;
+    let console_proxy = match console_tee {
+        Some(console_tee) => console_tee.wait_handed_over()?,
+        None => None,
+    };
+    let mut container = DeleteGuard::new(container, args.rm);
+
+    if let Some(signal) = take_pending_interrupt() {
+        bail!(
+            "interrupted by {signal} before container {} was started",
+            args.container_id
+        );
+    }
+
+    if let Some(usernet) = &usernet {
+        usernet.setup(&container).with_context(|| {
+            format!(
+                "failed to set up {} for container {}",
+                usernet.driver, args.container_id
+            )
+        })?;
+    }
 
     container
-        .start()
+        .start_with_timeouts(&timeouts)
         .with_context(|| format!("failed to start container {}", args.container_id))?;
 
     if args.detach {
@@ -40,19 +246,115 @@ pub fn run(args: Run, root_path: PathBuf, systemd_cgroup: bool) -> Result<i32> {
         container.pid().is_some(),
         "expects a container init pid in the container state"
     );
-    let foreground_result = handle_foreground(container.pid().unwrap());
+    let init_pid = container.pid().unwrap();
+    if args.rm {
+        // Hand the signals over to the foreground loop without a window in
+        // which they are neither recorded nor forwarded.
+        SigSet::all()
+            .thread_block()
+            .with_context(|| "failed to call pthread_sigmask")?;
+        if let Some(signal) = take_pending_interrupt() {
+            tracing::debug!(?signal, "forwarding interrupt received during setup");
+            kill(init_pid, signal)
+                .with_context(|| format!("failed to forward {signal} to the container"))?;
+        }
+    }
+
+    let mut health = HealthChecker::new(&root_path, &container)?;
+    let handoff = Handoff {
+        container_id: args.container_id,
+        init_pid,
+        console_tee: console_proxy.as_ref().map(AsRawFd::as_raw_fd),
+    };
+    let foreground_result = supervise(
+        init_pid,
+        upgrade.as_ref().map(|upgrade| (upgrade, &handoff)),
+        health.as_mut(),
+    );
+    drain_console(console_proxy.as_ref());
     // execute the destruction action after the container finishes running
-    container.delete(true)?;
+    container.delete(args.keep, foreground_result.as_ref().ok().copied())?;
     // return result
     foreground_result
 }
 
+/// Continues to supervise a container after youki was re-executed for an
+/// upgrade.
+fn resume(args: Run, root_path: PathBuf, handoff: Handoff) -> Result<i32> {
+    if handoff.container_id != args.container_id {
+        bail!(
+            "handed over container {} does not match container {}",
+            handoff.container_id,
+            args.container_id
+        );
+    }
+    let container = load_container(&root_path, &args.container_id)?;
+    if container.pid() != Some(handoff.init_pid) {
+        bail!(
+            "container {} is no longer run by init process {}",
+            args.container_id,
+            handoff.init_pid
+        );
+    }
+    let console_proxy = handoff
+        .console_tee
+        .map(TeeProxy::from_handoff)
+        .transpose()?;
+    let container = DeleteGuard::new(container, args.rm);
+    tracing::info!(id = args.container_id, init_pid = ?handoff.init_pid, "resumed after upgrade");
+    let upgrade = args
+        .allow_upgrade
+        .then(Upgrade::from_command_line)
+        .transpose()?;
+
+    // The init may have exited while youki was re-executed. An error here
+    // means that it is not a child of youki, so it can not be supervised.
+    let foreground_result = match waitpid(handoff.init_pid, Some(WaitPidFlag::WNOHANG))
+        .with_context(|| format!("failed to wait for init process {}", handoff.init_pid))?
+    {
+        WaitStatus::Exited(_, status) => Ok(status),
+        WaitStatus::Signaled(_, signal, _) => Ok(signal as i32),
+        _ => {
+            let mut health = HealthChecker::new(&root_path, &container)?;
+            supervise(
+                handoff.init_pid,
+                upgrade.as_ref().map(|upgrade| (upgrade, &handoff)),
+                health.as_mut(),
+            )
+        }
+    };
+    drain_console(console_proxy.as_ref());
+    container.delete(args.keep, foreground_result.as_ref().ok().copied())?;
+    foreground_result
+}
+
+/// Waits for the console proxy, which exits once the container closed its
+/// terminal.
+fn drain_console(console_proxy: Option<&TeeProxy>) {
+    if let Some(proxy) = console_proxy {
+        if !proxy.wait(CONSOLE_DRAIN_TIMEOUT) {
+            tracing::warn!("console proxy did not finish, the console log may be incomplete");
+        }
+    }
+}
+
 // handle_foreground will match the `runc` behavior running the foreground mode.
 // The youki main process will wait and reap the container init process. The
 // youki main process also forwards most of the signals to the container init
 // process.
-#[tracing::instrument(level = "trace")]
-fn handle_foreground(init_pid: Pid) -> Result<i32> {
+pub(crate) fn handle_foreground(init_pid: Pid) -> Result<i32> {
+    supervise(init_pid, None, None)
+}
+
+// Like handle_foreground, but with an upgrade SIGUSR2 re-executes youki
+// instead of being forwarded, and with a health checker the health checks
+// run in between the signals.
+#[tracing::instrument(level = "trace", skip(upgrade, health))]
+fn supervise(
+    init_pid: Pid,
+    upgrade: Option<(&Upgrade, &Handoff)>,
+    mut health: Option<&mut HealthChecker>,
+) -> Result<i32> {
     tracing::trace!("waiting for container init process to exit");
     // We mask all signals here and forward most of the signals to the container
     // init process.
@@ -61,10 +363,24 @@ fn handle_foreground(init_pid: Pid) -> Result<i32> {
         .thread_block()
         .with_context(|| "failed to call pthread_sigmask")?;
     loop {
-        match signal_set
-            .wait()
-            .with_context(|| "failed to call sigwait")?
-        {
+        let signal = match health.as_deref() {
+            Some(checker) => wait_signal_timeout(&signal_set, checker.timeout())?,
+            None => Some(
+                signal_set
+                    .wait()
+                    .with_context(|| "failed to call sigwait")?,
+            ),
+        };
+        let signal = match signal {
+            Some(signal) => signal,
+            None => {
+                if let Some(checker) = health.as_deref_mut() {
+                    checker.tick();
+                }
+                continue;
+            }
+        };
+        match signal {
             signal::SIGCHLD => {
                 // Reap all child until either container init process exits or
                 // no more child to be reaped. Once the container init process
@@ -76,6 +392,9 @@ fn handle_foreground(init_pid: Pid) -> Result<i32> {
                             if pid.eq(&init_pid) {
                                 return Ok(status);
                             }
+                            if let Some(checker) = health.as_deref_mut() {
+                                checker.reaped(pid, status);
+                            }
 
                             // Else, some random child process exited, ignoring...
                         }
@@ -83,6 +402,9 @@ fn handle_foreground(init_pid: Pid) -> Result<i32> {
                             if pid.eq(&init_pid) {
                                 return Ok(signal as i32);
                             }
+                            if let Some(checker) = health.as_deref_mut() {
+                                checker.reaped(pid, 128 + signal as i32);
+                            }
 
                             // Else, some random child process exited, ignoring...
                         }
@@ -101,6 +423,15 @@ fn handle_foreground(init_pid: Pid) -> Result<i32> {
             signal::SIGWINCH => {
                 // TODO: resize the terminal
             }
+            signal::SIGUSR2 if upgrade.is_some() => {
+                if let Some((upgrade, handoff)) = upgrade {
+                    // Only returns on failure, the container is kept running
+                    // by the current binary then.
+                    if let Err(err) = upgrade.exec(handoff) {
+                        tracing::error!(?err, "failed to upgrade youki");
+                    }
+                }
+            }
             signal => {
                 tracing::trace!(?signal, "forwarding signal");
                 // There is nothing we can do if we fail to forward the signal.
@@ -116,6 +447,31 @@ fn handle_foreground(init_pid: Pid) -> Result<i32> {
     }
 }
 
+/// Waits for one of the signals like sigwait, but for at most the timeout.
+/// Returns none if the timeout expired first.
+fn wait_signal_timeout(signal_set: &SigSet, timeout: Duration) -> Result<Option<Signal>> {
+    let timeout = nix::libc::timespec {
+        tv_sec: timeout.as_secs() as nix::libc::time_t,
+        tv_nsec: timeout.subsec_nanos() as nix::libc::c_long,
+    };
+    loop {
+        // Safe because the set and the timeout outlive the call, and no
+        // signal info is requested.
+        let signal =
+            unsafe { nix::libc::sigtimedwait(signal_set.as_ref(), std::ptr::null_mut(), &timeout) };
+        if signal >= 0 {
+            return Signal::try_from(signal)
+                .map(Some)
+                .with_context(|| format!("unknown signal {signal}"));
+        }
+        match nix::errno::Errno::last() {
+            nix::errno::Errno::EAGAIN => return Ok(None),
+            nix::errno::Errno::EINTR => continue,
+            errno => return Err(errno).with_context(|| "failed to call sigtimedwait"),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::time::Duration;
@@ -186,6 +542,57 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_interrupt_is_recorded() -> Result<()> {
+        // Installing signal handlers must not leak into the other tests, so
+        // the check runs in a dedicated process which reports through its
+        // exit code.
+        match unsafe { unistd::fork()? } {
+            unistd::ForkResult::Parent { child } => {
+                let status = wait::waitpid(child, None)?;
+                assert_eq!(status, wait::WaitStatus::Exited(child, 0));
+            }
+            unistd::ForkResult::Child => {
+                let recorded = install_interrupt_handlers().is_ok()
+                    && take_pending_interrupt().is_none()
+                    && signal::raise(Signal::SIGTERM).is_ok()
+                    && take_pending_interrupt() == Some(Signal::SIGTERM)
+                    && take_pending_interrupt().is_none();
+                std::process::exit(if recorded { 0 } else { 1 });
+            }
+        };
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_wait_signal_timeout() -> Result<()> {
+        // Blocking signals must not leak into the other tests either.
+        match unsafe { unistd::fork()? } {
+            unistd::ForkResult::Parent { child } => {
+                let status = wait::waitpid(child, None)?;
+                assert_eq!(status, wait::WaitStatus::Exited(child, 0));
+            }
+            unistd::ForkResult::Child => {
+                let mut signal_set = SigSet::empty();
+                signal_set.add(Signal::SIGUSR1);
+                let waited = signal_set.thread_block().is_ok()
+                    && matches!(
+                        wait_signal_timeout(&signal_set, Duration::from_millis(10)),
+                        Ok(None)
+                    )
+                    && signal::raise(Signal::SIGUSR1).is_ok()
+                    && matches!(
+                        wait_signal_timeout(&signal_set, Duration::from_secs(1)),
+                        Ok(Some(Signal::SIGUSR1))
+                    );
+                std::process::exit(if waited { 0 } else { 1 });
+            }
+        };
+
+        Ok(())
+    }
+
     #[test]
     fn test_foreground_exit() -> Result<()> {
         // The setup is similar to `handle_foreground`, but instead of
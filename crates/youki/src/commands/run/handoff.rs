@@ -0,0 +1,280 @@
+//! Handoff of a foreground container to an upgraded youki binary. On
+//! SIGUSR2, `run --allow-upgrade` re-executes the binary it was started
+//! from with the same arguments, so that a package upgrade of youki does not
+//! force the container to restart.
+//!
+//! The process stays the same across the exec, so the container init, the
+//! console proxy and the user-mode network helpers stay its children and the
+//! inherited stdio is kept. The user-mode network state is kept in the
+//! container state directory and the start handshake is over once the
+//! container runs. The signal mask survives the exec as well, which keeps
+//! the signals arriving meanwhile pending until the new binary waits for
+//! them.
+//!
+//! What the new binary can not learn from its arguments or the state
+//! directory is passed in [`HANDOFF_ENV`]. That includes the descriptors it
+//! needs, which are all opened close-on-exec: the pidfd of the console
+//! proxy, which is waited for before the container is deleted so that the
+//! console log is complete. Their close-on-exec flag is cleared only for the
+//! exec and the upgraded binary sets it again when it takes them over.
+use std::ffi::{CString, OsString};
+use std::os::fd::RawFd;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use nix::fcntl::{fcntl, FcntlArg, FdFlag};
+use nix::unistd::{self, Pid};
+
+/// Environment variable through which the replaced youki passes the
+/// supervision state to the upgraded one, as
+/// `<init pid>[,console-tee=<pidfd>]:<container id>`
+pub const HANDOFF_ENV: &str = "YOUKI_HANDOFF";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Handoff {
+    pub container_id: String,
+    pub init_pid: Pid,
+    /// pidfd of the console proxy of a container run with a console log
+    pub console_tee: Option<RawFd>,
+}
+
+impl Handoff {
+    fn encode(&self) -> String {
+        let mut value = self.init_pid.to_string();
+        if let Some(fd) = self.console_tee {
+            value.push_str(&format!(",console-tee={fd}"));
+        }
+        format!("{value}:{}", self.container_id)
+    }
+
+    fn decode(value: &str) -> Result<Self> {
+        let invalid = || format!("invalid {HANDOFF_ENV} {value:?}");
+        let (head, container_id) = value.split_once(':').with_context(invalid)?;
+        let mut fields = head.split(',');
+        let init_pid: i32 = fields
+            .next()
+            .unwrap_or_default()
+            .parse()
+            .with_context(|| format!("invalid init pid in {HANDOFF_ENV} {value:?}"))?;
+        if init_pid <= 0 || container_id.is_empty() {
+            bail!(invalid());
+        }
+
+        let mut console_tee = None;
+        for field in fields {
+            match field.split_once('=') {
+                Some(("console-tee", fd)) => {
+                    let fd: RawFd = fd.parse().with_context(invalid)?;
+                    if fd < 0 {
+                        bail!(invalid());
+                    }
+                    console_tee = Some(fd);
+                }
+                _ => bail!(invalid()),
+            }
+        }
+
+        Ok(Self {
+            container_id: container_id.to_owned(),
+            init_pid: Pid::from_raw(init_pid),
+            console_tee,
+        })
+    }
+
+    fn fds(&self) -> impl Iterator<Item = RawFd> {
+        self.console_tee.into_iter()
+    }
+
+    /// Takes the state handed over by the replaced youki, if this process
+    /// is the result of an upgrade. The variable is removed, so that hooks
+    /// and later upgrades do not see a stale value.
+    pub fn take() -> Result<Option<Self>> {
+        let Some(value) = std::env::var_os(HANDOFF_ENV) else {
+            return Ok(None);
+        };
+        std::env::remove_var(HANDOFF_ENV);
+        let value = value
+            .into_string()
+            .map_err(|value| anyhow::anyhow!("invalid {HANDOFF_ENV} {value:?}"))?;
+
+        Self::decode(&value).map(Some)
+    }
+}
+
+/// Binary youki re-executes on an upgrade
+#[derive(Debug, Clone)]
+pub struct Upgrade {
+    binary: PathBuf,
+    args: Vec<OsString>,
+}
+
+impl Upgrade {
+    /// Has to be called at startup, before youki changes its working
+    /// directory. The binary is taken from the command line rather than
+    /// /proc/self/exe, which is the sealed copy of the old binary.
+    pub fn from_command_line() -> Result<Self> {
+        let args: Vec<OsString> = std::env::args_os().collect();
+        let arg0 = args
+            .first()
+            .context("no arguments to re-execute youki with")?;
+        let binary = resolve_binary(
+            Path::new(arg0),
+            std::env::var_os("PATH").as_deref(),
+            &std::env::current_dir().context("failed to get the current directory")?,
+        )?;
+
+        Ok(Self { binary, args })
+    }
+
+    /// Replaces youki with the binary it was started from. Only returns if
+    /// the exec failed, in which case the current youki keeps supervising
+    /// the container.
+    pub fn exec(&self, handoff: &Handoff) -> Result<()> {
+        let binary = CString::new(self.binary.as_os_str().as_bytes())?;
+        let args = self
+            .args
+            .iter()
+            .map(|arg| CString::new(arg.as_bytes()))
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+
+        tracing::info!(binary = ?self.binary, id = handoff.container_id, "re-executing youki");
+        // Not using std::process::Command, which would reset the signal mask
+        // and so deliver the pending signals before the new binary is ready.
+        for fd in handoff.fds() {
+            set_cloexec(fd, false)?;
+        }
+        std::env::set_var(HANDOFF_ENV, handoff.encode());
+        let err = unistd::execv(&binary, &args).unwrap_err();
+        std::env::remove_var(HANDOFF_ENV);
+        for fd in handoff.fds() {
+            set_cloexec(fd, true)?;
+        }
+
+        Err(err).with_context(|| format!("failed to re-execute {:?}", self.binary))
+    }
+}
+
+fn set_cloexec(fd: RawFd, cloexec: bool) -> Result<()> {
+    let flags = if cloexec {
+        FdFlag::FD_CLOEXEC
+    } else {
+        FdFlag::empty()
+    };
+    fcntl(fd, FcntlArg::F_SETFD(flags))
+        .with_context(|| format!("failed to set close-on-exec of fd {fd} to {cloexec}"))?;
+
+    Ok(())
+}
+
+fn resolve_binary(arg0: &Path, path_env: Option<&std::ffi::OsStr>, cwd: &Path) -> Result<PathBuf> {
+    if arg0.as_os_str().as_bytes().contains(&b'/') {
+        return Ok(cwd.join(arg0));
+    }
+
+    path_env
+        .into_iter()
+        .flat_map(std::env::split_paths)
+        .map(|dir| cwd.join(dir).join(arg0))
+        .find(|candidate| candidate.is_file())
+        .with_context(|| format!("failed to find {arg0:?} in PATH"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::fd::AsRawFd;
+    use std::time::Duration;
+
+    use super::*;
+    use crate::commands::console_tee::TeeProxy;
+
+    #[test]
+    fn test_handoff_encoding() -> Result<()> {
+        let handoff = Handoff {
+            container_id: "web:1".to_owned(),
+            init_pid: Pid::from_raw(4242),
+            console_tee: None,
+        };
+        assert_eq!(handoff.encode(), "4242:web:1");
+        assert_eq!(Handoff::decode(&handoff.encode())?, handoff);
+
+        let handoff = Handoff {
+            console_tee: Some(7),
+            ..handoff
+        };
+        assert_eq!(handoff.encode(), "4242,console-tee=7:web:1");
+        assert_eq!(Handoff::decode(&handoff.encode())?, handoff);
+
+        for invalid in [
+            "",
+            "4242",
+            "4242:",
+            "-1:web",
+            "pid:web",
+            "4242,console-tee=-1:web",
+            "4242,console-tee=fd:web",
+            "4242,pty=7:web",
+        ] {
+            assert!(Handoff::decode(invalid).is_err(), "{invalid}");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_handoff_console_tee() -> Result<()> {
+        let mut tee = std::process::Command::new("sleep").arg("1").spawn()?;
+        let proxy = TeeProxy::open(tee.id()).context("no pidfd support")?;
+        let handoff = Handoff {
+            container_id: "web".to_owned(),
+            init_pid: Pid::from_raw(4242),
+            console_tee: Some(proxy.as_raw_fd()),
+        };
+
+        // what the upgraded binary sees between set_cloexec and execv
+        for fd in handoff.fds() {
+            set_cloexec(fd, false)?;
+        }
+        let handed_over = Handoff::decode(&handoff.encode())?;
+        let fd = handed_over
+            .console_tee
+            .context("no console tee handed over")?;
+        let flags = FdFlag::from_bits_truncate(fcntl(fd, FcntlArg::F_GETFD)?);
+        assert!(!flags.contains(FdFlag::FD_CLOEXEC));
+
+        std::mem::forget(proxy);
+        let proxy = TeeProxy::from_handoff(fd)?;
+        let flags = FdFlag::from_bits_truncate(fcntl(fd, FcntlArg::F_GETFD)?);
+        assert!(flags.contains(FdFlag::FD_CLOEXEC));
+
+        assert!(!proxy.wait(Duration::ZERO));
+        tee.wait()?;
+        assert!(proxy.wait(Duration::from_secs(1)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_resolve_binary() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        let bin = tmp.path().join("bin");
+        std::fs::create_dir(&bin)?;
+        std::fs::write(bin.join("youki"), "")?;
+
+        let cwd = Path::new("/work");
+        assert_eq!(
+            resolve_binary(Path::new("/usr/bin/youki"), None, cwd)?,
+            PathBuf::from("/usr/bin/youki")
+        );
+        assert_eq!(
+            resolve_binary(Path::new("./youki"), None, cwd)?,
+            PathBuf::from("/work/./youki")
+        );
+
+        let path_env = std::env::join_paths([tmp.path().join("missing"), bin.clone()])?;
+        assert_eq!(
+            resolve_binary(Path::new("youki"), Some(&path_env), cwd)?,
+            bin.join("youki")
+        );
+        assert!(resolve_binary(Path::new("youki"), None, cwd).is_err());
+        Ok(())
+    }
+}
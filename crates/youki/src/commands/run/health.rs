@@ -0,0 +1,159 @@
+//! Health checks of a container supervised by `run`. The foreground loop
+//! starts the check when it is due by executing `youki exec` for the
+//! container, and learns its result when it reaps the process. The output of
+//! the last check is kept in `health.log` of the container state directory.
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use chrono::Utc;
+use libcontainer::container::Container;
+use libcontainer::health::{self, Health, HealthCheck};
+use nix::sys::signal::{kill, Signal};
+use nix::unistd::Pid;
+
+const HEALTH_LOG_FILE: &str = "health.log";
+
+/// Check of the container currently running
+#[derive(Debug)]
+struct RunningCheck {
+    pid: Pid,
+    deadline: Instant,
+}
+
+#[derive(Debug)]
+pub struct HealthChecker {
+    check: HealthCheck,
+    root_path: PathBuf,
+    container: Container,
+    health: Health,
+    next: Instant,
+    running: Option<RunningCheck>,
+}
+
+impl HealthChecker {
+    /// Checker of the health check of the container annotations, if there is
+    /// one. The first check runs one interval after the start.
+    pub fn new(root_path: &Path, container: &Container) -> Result<Option<Self>> {
+        let check = match HealthCheck::from_annotations(container.state.annotations.as_ref())? {
+            Some(check) => check,
+            None => return Ok(None),
+        };
+        let mut checker = Self {
+            next: Instant::now() + check.interval,
+            check,
+            root_path: root_path.to_owned(),
+            container: container.clone(),
+            health: container.health().cloned().unwrap_or_default(),
+            running: None,
+        };
+        checker.save(true);
+        Ok(Some(checker))
+    }
+
+    /// Time until the checker has to start or time out a check
+    pub fn timeout(&self) -> Duration {
+        let due = self
+            .running
+            .as_ref()
+            .map_or(self.next, |running| running.deadline);
+        due.saturating_duration_since(Instant::now())
+    }
+
+    /// Starts the check if it is due, or fails the running one if it timed
+    /// out
+    pub fn tick(&mut self) {
+        let now = Instant::now();
+        if let Some(running) = &self.running {
+            if now < running.deadline {
+                return;
+            }
+            tracing::warn!(pid = ?running.pid, "health check timed out");
+            // youki exec forwards the signal to the check in the container
+            let _ = kill(running.pid, Signal::SIGTERM);
+            self.running = None;
+            self.record(None);
+        }
+        if now < self.next {
+            return;
+        }
+
+        self.next = now + self.check.interval;
+        match self.spawn() {
+            Ok(pid) => {
+                self.running = Some(RunningCheck {
+                    pid,
+                    deadline: now + self.check.interval,
+                })
+            }
+            Err(err) => {
+                tracing::warn!(?err, "failed to start health check");
+                self.record(None);
+            }
+        }
+    }
+
+    /// Records the result of the check if the reaped process is the one of
+    /// the check. Returns whether it was.
+    pub fn reaped(&mut self, pid: Pid, exit_code: i32) -> bool {
+        match &self.running {
+            Some(running) if running.pid == pid => {
+                self.running = None;
+                self.record(Some(exit_code));
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn spawn(&self) -> Result<Pid> {
+        let log = File::create(self.container.root.join(HEALTH_LOG_FILE))
+            .context("failed to create health check log")?;
+        let child = Command::new("/proc/self/exe")
+            .arg("--root")
+            .arg(&self.root_path)
+            .arg("exec")
+            .arg(self.container.id())
+            .arg("--")
+            .args(&self.check.args)
+            .stdin(Stdio::null())
+            .stdout(log.try_clone()?)
+            .stderr(log)
+            .spawn()
+            .context("failed to execute youki exec")?;
+        // The foreground loop reaps the process, the handle is not waited on
+        Ok(Pid::from_raw(child.id() as i32))
+    }
+
+    fn record(&mut self, exit_code: Option<i32>) {
+        let changed = self
+            .health
+            .record(exit_code, self.check.retries, Utc::now());
+        tracing::debug!(?exit_code, health = ?self.health, "health check finished");
+        self.save(changed);
+    }
+
+    /// Saves the health in the container state and emits an event if the
+    /// status changed. Failures are logged only, they must not stop the
+    /// supervision of the container.
+    fn save(&mut self, changed: bool) {
+        let health = self.health.clone();
+        let saved = self.container.update_state(|container| {
+            container.set_health(health);
+        });
+        if let Err(err) = saved {
+            tracing::warn!(?err, "failed to save container health");
+        }
+
+        if changed {
+            tracing::info!(id = self.container.id(), status = ?self.health.status, "container health changed");
+            if let Err(err) =
+                health::append_event(&self.container.root, self.container.id(), &self.health)
+            {
+                tracing::warn!(?err, "failed to emit health event");
+            }
+        }
+    }
+}
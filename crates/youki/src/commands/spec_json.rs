@@ -1,12 +1,18 @@
+#[cfg(feature = "spec-convert")]
+mod convert;
+
 use std::fs::File;
 use std::io::{BufWriter, Write};
-use std::path::{Path, PathBuf};
+use std::path::Path;
 
 use anyhow::Result;
+use libcontainer::default_mounts::DefaultMounts;
 use libcontainer::oci_spec::runtime::{
     LinuxBuilder, LinuxIdMappingBuilder, LinuxNamespace, LinuxNamespaceBuilder, LinuxNamespaceType,
-    Mount, Spec,
+    Spec,
 };
+use libcontainer::timezone;
+use liboci_cli::SpecCmd;
 use nix;
 use serde_json::to_writer_pretty;
 
@@ -48,33 +54,7 @@ pub fn get_rootless() -> Result<Spec> {
             .build()?])
         .build()?;
 
-    // Prepare the mounts
-
-    let mut mounts: Vec<Mount> = libcontainer::oci_spec::runtime::get_default_mounts();
-    for mount in &mut mounts {
-        if mount.destination().eq(Path::new("/sys")) {
-            mount
-                .set_source(Some(PathBuf::from("/sys")))
-                .set_typ(Some(String::from("none")))
-                .set_options(Some(vec![
-                    "rbind".to_string(),
-                    "nosuid".to_string(),
-                    "noexec".to_string(),
-                    "nodev".to_string(),
-                    "ro".to_string(),
-                ]));
-        } else {
-            let options: Vec<String> = mount
-                .options()
-                .as_ref()
-                .unwrap_or(&vec![])
-                .iter()
-                .filter(|&o| !o.starts_with("gid=") && !o.starts_with("uid="))
-                .map(|o| o.to_string())
-                .collect();
-            mount.set_options(Some(options));
-        }
-    }
+    let mounts = DefaultMounts::new().with_rootless(true).build()?;
 
     let mut spec = get_default()?;
     spec.set_linux(Some(linux)).set_mounts(Some(mounts));
@@ -83,11 +63,35 @@ pub fn get_rootless() -> Result<Spec> {
 
 /// spec Cli command
 pub fn spec(args: liboci_cli::Spec) -> Result<()> {
-    let spec = if args.rootless {
+    let mut spec = if args.rootless {
         get_rootless()?
     } else {
         get_default()?
     };
+    // adapt the default mounts to the rootfs, if it is already in place
+    let rootfs = spec.root().as_ref().map(|root| {
+        args.bundle
+            .as_deref()
+            .unwrap_or_else(|| Path::new("."))
+            .join(root.path())
+    });
+    let mounts = DefaultMounts::new()
+        .with_rootless(args.rootless)
+        .with_rootfs(rootfs.filter(|rootfs| rootfs.is_dir()))
+        .build()?;
+    spec.set_mounts(Some(mounts));
+    if let Some(tz) = &args.tz {
+        timezone::apply(&mut spec, &tz.parse()?)?;
+    }
+    match &args.cmd {
+        #[cfg(feature = "spec-convert")]
+        Some(SpecCmd::Convert(convert)) => convert::convert(&mut spec, convert)?,
+        #[cfg(not(feature = "spec-convert"))]
+        Some(SpecCmd::Convert(_)) => {
+            anyhow::bail!("youki was built without the spec-convert feature")
+        }
+        None => {}
+    }
 
     // write data to config.json
     let file = File::create("config.json")?;
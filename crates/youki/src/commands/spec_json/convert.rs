@@ -0,0 +1,854 @@
+//! Conversion of a docker-compose service or a kubernetes pod container to a
+//! spec. There is no image involved, so only the parts which map directly to
+//! the spec are converted: the command, environment, working directory, user,
+//! mounts and resource limits. Named volumes become bind mounts of a directory
+//! of the same name relative to the bundle.
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use libcontainer::oci_spec::runtime::{
+    LinuxBuilder, LinuxCpuBuilder, LinuxMemoryBuilder, LinuxPidsBuilder, LinuxResources, Mount,
+    MountBuilder, Spec,
+};
+use liboci_cli::{SpecConvert, SpecFormat};
+use serde::Deserialize;
+use serde_json::Value;
+
+const CPU_PERIOD: u64 = 100_000;
+
+// fields which are converted, all others are reported as ignored
+const COMPOSE_FIELDS: &[&str] = &[
+    "entrypoint",
+    "command",
+    "environment",
+    "volumes",
+    "tmpfs",
+    "user",
+    "working_dir",
+    "hostname",
+    "read_only",
+    "mem_limit",
+    "cpus",
+    "pids_limit",
+    "deploy",
+];
+const KUBERNETES_FIELDS: &[&str] = &[
+    "name",
+    "command",
+    "args",
+    "env",
+    "workingDir",
+    "volumeMounts",
+    "resources",
+    "securityContext",
+];
+// fields which only exist in a kubernetes container
+const KUBERNETES_ONLY_FIELDS: &[&str] = &[
+    "args",
+    "workingDir",
+    "volumeMounts",
+    "securityContext",
+    "imagePullPolicy",
+];
+
+/// The parts of a container definition which are converted to the spec
+#[derive(Debug, Default, PartialEq)]
+struct Container {
+    args: Vec<String>,
+    env: Vec<(String, String)>,
+    cwd: Option<PathBuf>,
+    user: Option<(u32, Option<u32>)>,
+    hostname: Option<String>,
+    readonly: bool,
+    mounts: Vec<Mount>,
+    memory: Option<i64>,
+    cpus: Option<f64>,
+    pids: Option<i64>,
+}
+
+/// Converts the file given with `--from` and applies it to the spec
+pub fn convert(spec: &mut Spec, args: &SpecConvert) -> Result<()> {
+    let content = fs::read_to_string(&args.from)
+        .with_context(|| format!("failed to read {}", args.from.display()))?;
+    let value: Value = serde_json::from_str(&content)
+        .with_context(|| format!("failed to parse {}", args.from.display()))?;
+    let container = parse(value, args.format)?;
+    apply(spec, container)
+}
+
+fn parse(value: Value, format: Option<SpecFormat>) -> Result<Container> {
+    let Some(fields) = value.as_object() else {
+        bail!("expected a JSON object with a single service or container");
+    };
+    if fields.contains_key("services") || fields.contains_key("containers") {
+        bail!("expected a single service or container, not a whole compose file or pod");
+    }
+
+    let format = format.unwrap_or_else(|| detect(&value));
+    let known = match format {
+        SpecFormat::Compose => COMPOSE_FIELDS,
+        SpecFormat::Kubernetes => KUBERNETES_FIELDS,
+    };
+    for field in fields.keys().filter(|f| !known.contains(&f.as_str())) {
+        tracing::warn!(field, ?format, "field is not converted");
+    }
+
+    match format {
+        SpecFormat::Compose => serde_json::from_value::<ComposeService>(value)
+            .context("invalid docker-compose service")?
+            .try_into(),
+        SpecFormat::Kubernetes => serde_json::from_value::<KubernetesContainer>(value)
+            .context("invalid kubernetes container")?
+            .try_into(),
+    }
+}
+
+/// Kubernetes uses camel case and a list of objects for the environment,
+/// docker-compose uses snake case and a list of strings or a map.
+fn detect(value: &Value) -> SpecFormat {
+    let kubernetes_env = value["env"]
+        .as_array()
+        .map_or(false, |env| env.iter().any(Value::is_object));
+    if kubernetes_env
+        || KUBERNETES_ONLY_FIELDS
+            .iter()
+            .any(|field| value.get(field).is_some())
+    {
+        SpecFormat::Kubernetes
+    } else {
+        SpecFormat::Compose
+    }
+}
+
+fn apply(spec: &mut Spec, container: Container) -> Result<()> {
+    if let Some(process) = spec.process_mut() {
+        if !container.args.is_empty() {
+            process.set_args(Some(container.args));
+        }
+        let mut env = process.env().clone().unwrap_or_default();
+        for (key, value) in container.env {
+            let entry = format!("{key}={value}");
+            match env.iter_mut().find(|e| e.split('=').next() == Some(&key)) {
+                Some(existing) => *existing = entry,
+                None => env.push(entry),
+            }
+        }
+        process.set_env(Some(env));
+        if let Some(cwd) = container.cwd {
+            process.set_cwd(cwd);
+        }
+        if let Some((uid, gid)) = container.user {
+            process.user_mut().set_uid(uid);
+            process.user_mut().set_gid(gid.unwrap_or(uid));
+        }
+    }
+
+    if let Some(hostname) = container.hostname {
+        spec.set_hostname(Some(hostname));
+    }
+    if let Some(root) = spec.root_mut() {
+        root.set_readonly(Some(container.readonly));
+    }
+    if !container.mounts.is_empty() {
+        let mut mounts = spec.mounts().clone().unwrap_or_default();
+        mounts.extend(container.mounts);
+        spec.set_mounts(Some(mounts));
+    }
+
+    if container.memory.is_some() || container.cpus.is_some() || container.pids.is_some() {
+        if spec.linux().is_none() {
+            spec.set_linux(Some(LinuxBuilder::default().build()?));
+        }
+        if let Some(linux) = spec.linux_mut() {
+            // the default resources contain the device rules, which are kept
+            let mut resources = linux.resources().clone().unwrap_or_default();
+            set_limits(
+                &mut resources,
+                container.memory,
+                container.cpus,
+                container.pids,
+            )?;
+            linux.set_resources(Some(resources));
+        }
+    }
+
+    Ok(())
+}
+
+fn set_limits(
+    resources: &mut LinuxResources,
+    memory: Option<i64>,
+    cpus: Option<f64>,
+    pids: Option<i64>,
+) -> Result<()> {
+    if let Some(memory) = memory {
+        resources.set_memory(Some(LinuxMemoryBuilder::default().limit(memory).build()?));
+    }
+    if let Some(cpus) = cpus {
+        let quota = (cpus * CPU_PERIOD as f64).round() as i64;
+        resources.set_cpu(Some(
+            LinuxCpuBuilder::default()
+                .quota(quota)
+                .period(CPU_PERIOD)
+                .build()?,
+        ));
+    }
+    if let Some(pids) = pids {
+        resources.set_pids(Some(LinuxPidsBuilder::default().limit(pids).build()?));
+    }
+    Ok(())
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum StringOrList {
+    String(String),
+    List(Vec<String>),
+}
+
+impl StringOrList {
+    /// A string is split like a shell would do it
+    fn into_args(self) -> Result<Vec<String>> {
+        match self {
+            Self::String(command) => split_command(&command),
+            Self::List(args) => Ok(args),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Quantity {
+    Number(f64),
+    String(String),
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Scalar {
+    String(String),
+    Number(serde_json::Number),
+    Bool(bool),
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum ComposeEnvironment {
+    List(Vec<String>),
+    Map(BTreeMap<String, Option<Scalar>>),
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum ComposeVolume {
+    Short(String),
+    Long(ComposeMount),
+}
+
+#[derive(Debug, Deserialize)]
+struct ComposeMount {
+    #[serde(rename = "type")]
+    typ: String,
+    source: Option<String>,
+    target: PathBuf,
+    #[serde(default)]
+    read_only: bool,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct ComposeLimits {
+    cpus: Option<Quantity>,
+    memory: Option<Quantity>,
+    pids: Option<i64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct ComposeResources {
+    #[serde(default)]
+    limits: ComposeLimits,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct ComposeDeploy {
+    #[serde(default)]
+    resources: ComposeResources,
+}
+
+#[derive(Debug, Deserialize)]
+struct ComposeService {
+    entrypoint: Option<StringOrList>,
+    command: Option<StringOrList>,
+    environment: Option<ComposeEnvironment>,
+    #[serde(default)]
+    volumes: Vec<ComposeVolume>,
+    tmpfs: Option<StringOrList>,
+    user: Option<String>,
+    working_dir: Option<PathBuf>,
+    hostname: Option<String>,
+    #[serde(default)]
+    read_only: bool,
+    mem_limit: Option<Quantity>,
+    cpus: Option<Quantity>,
+    pids_limit: Option<i64>,
+    #[serde(default)]
+    deploy: ComposeDeploy,
+}
+
+impl TryFrom<ComposeService> for Container {
+    type Error = anyhow::Error;
+
+    fn try_from(service: ComposeService) -> Result<Self> {
+        let mut args = match service.entrypoint {
+            Some(entrypoint) => entrypoint.into_args()?,
+            None => vec![],
+        };
+        if let Some(command) = service.command {
+            args.extend(command.into_args()?);
+        }
+
+        let env = match service.environment {
+            Some(ComposeEnvironment::List(list)) => list
+                .into_iter()
+                .filter_map(|entry| match entry.split_once('=') {
+                    Some((key, value)) => Some((key.to_owned(), value.to_owned())),
+                    None => {
+                        tracing::warn!(
+                            key = entry,
+                            "environment variable without value is not converted"
+                        );
+                        None
+                    }
+                })
+                .collect(),
+            Some(ComposeEnvironment::Map(map)) => map
+                .into_iter()
+                .filter_map(|(key, value)| {
+                    let value = match value? {
+                        Scalar::String(s) => s,
+                        Scalar::Number(n) => n.to_string(),
+                        Scalar::Bool(b) => b.to_string(),
+                    };
+                    Some((key, value))
+                })
+                .collect(),
+            None => vec![],
+        };
+
+        let mut mounts = service
+            .volumes
+            .into_iter()
+            .map(compose_mount)
+            .collect::<Result<Vec<_>>>()?;
+        let tmpfs = match service.tmpfs {
+            Some(tmpfs) => match tmpfs {
+                StringOrList::String(tmpfs) => vec![tmpfs],
+                StringOrList::List(tmpfs) => tmpfs,
+            },
+            None => vec![],
+        };
+        for tmpfs in tmpfs {
+            let (target, options) = match tmpfs.split_once(':') {
+                Some((target, options)) => (target, options.split(',').collect()),
+                None => (tmpfs.as_str(), vec![]),
+            };
+            mounts.push(tmpfs_mount(Path::new(target), &options)?);
+        }
+
+        let limits = service.deploy.resources.limits;
+        let memory = service
+            .mem_limit
+            .or(limits.memory)
+            .map(|memory| parse_compose_bytes(&memory))
+            .transpose()?;
+        let cpus = service
+            .cpus
+            .or(limits.cpus)
+            .map(|cpus| parse_cpus(&cpus))
+            .transpose()?;
+
+        Ok(Self {
+            args,
+            env,
+            cwd: service.working_dir,
+            user: service.user.as_deref().map(parse_user).transpose()?,
+            hostname: service.hostname,
+            readonly: service.read_only,
+            mounts,
+            memory,
+            cpus,
+            pids: service.pids_limit.or(limits.pids),
+        })
+    }
+}
+
+fn compose_mount(volume: ComposeVolume) -> Result<Mount> {
+    let mount = match volume {
+        ComposeVolume::Short(volume) => {
+            let parts: Vec<&str> = volume.split(':').collect();
+            let (source, target, mode) = match parts[..] {
+                [source, target] => (source, target, ""),
+                [source, target, mode] => (source, target, mode),
+                _ => bail!("volume {volume} has no source, anonymous volumes are not supported"),
+            };
+            // all other modes are about selinux labels and caching
+            let readonly = mode.split(',').any(|option| option == "ro");
+            bind_mount(source, Path::new(target), readonly)?
+        }
+        ComposeVolume::Long(mount) => match mount.typ.as_str() {
+            "bind" | "volume" => {
+                let source = mount
+                    .source
+                    .with_context(|| format!("volume {} has no source", mount.target.display()))?;
+                bind_mount(&source, &mount.target, mount.read_only)?
+            }
+            "tmpfs" => tmpfs_mount(&mount.target, &[])?,
+            typ => bail!("volume type {typ} is not supported"),
+        },
+    };
+    Ok(mount)
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct KubernetesEnv {
+    name: String,
+    value: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct KubernetesVolumeMount {
+    name: String,
+    mount_path: PathBuf,
+    #[serde(default)]
+    read_only: bool,
+    sub_path: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct KubernetesLimits {
+    cpu: Option<Quantity>,
+    memory: Option<Quantity>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct KubernetesResources {
+    #[serde(default)]
+    limits: KubernetesLimits,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct KubernetesSecurityContext {
+    run_as_user: Option<u32>,
+    run_as_group: Option<u32>,
+    #[serde(default)]
+    read_only_root_filesystem: bool,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct KubernetesContainer {
+    #[serde(default)]
+    command: Vec<String>,
+    #[serde(default)]
+    args: Vec<String>,
+    #[serde(default)]
+    env: Vec<KubernetesEnv>,
+    working_dir: Option<PathBuf>,
+    #[serde(default)]
+    volume_mounts: Vec<KubernetesVolumeMount>,
+    #[serde(default)]
+    resources: KubernetesResources,
+    #[serde(default)]
+    security_context: KubernetesSecurityContext,
+}
+
+impl TryFrom<KubernetesContainer> for Container {
+    type Error = anyhow::Error;
+
+    fn try_from(container: KubernetesContainer) -> Result<Self> {
+        let env = container
+            .env
+            .into_iter()
+            .filter_map(|env| match env.value {
+                Some(value) => Some((env.name, value)),
+                None => {
+                    tracing::warn!(
+                        key = env.name,
+                        "environment variable without value is not converted"
+                    );
+                    None
+                }
+            })
+            .collect();
+
+        let mounts = container
+            .volume_mounts
+            .into_iter()
+            .map(|mount| {
+                let source = match mount.sub_path {
+                    Some(sub_path) => format!("{}/{sub_path}", mount.name),
+                    None => mount.name,
+                };
+                bind_mount(&source, &mount.mount_path, mount.read_only)
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let security_context = container.security_context;
+        let user = security_context
+            .run_as_user
+            .map(|uid| (uid, security_context.run_as_group));
+        let limits = container.resources.limits;
+
+        Ok(Self {
+            args: container
+                .command
+                .into_iter()
+                .chain(container.args)
+                .collect(),
+            env,
+            cwd: container.working_dir,
+            user,
+            hostname: None,
+            readonly: security_context.read_only_root_filesystem,
+            mounts,
+            memory: limits
+                .memory
+                .map(|memory| parse_kubernetes_bytes(&memory))
+                .transpose()?,
+            cpus: limits.cpu.map(|cpu| parse_cpus(&cpu)).transpose()?,
+            pids: None,
+        })
+    }
+}
+
+fn bind_mount(source: &str, destination: &Path, readonly: bool) -> Result<Mount> {
+    let mode = if readonly { "ro" } else { "rw" };
+    Ok(MountBuilder::default()
+        .destination(destination)
+        .typ("bind")
+        .source(source)
+        .options(vec!["rbind".to_owned(), mode.to_owned()])
+        .build()?)
+}
+
+fn tmpfs_mount(destination: &Path, options: &[&str]) -> Result<Mount> {
+    let mut all_options = vec!["nosuid".to_owned(), "nodev".to_owned()];
+    all_options.extend(options.iter().map(|option| option.to_string()));
+    Ok(MountBuilder::default()
+        .destination(destination)
+        .typ("tmpfs")
+        .source("tmpfs")
+        .options(all_options)
+        .build()?)
+}
+
+/// Parses `uid[:gid]`. User names would have to be looked up in the image.
+fn parse_user(user: &str) -> Result<(u32, Option<u32>)> {
+    let (uid, gid) = match user.split_once(':') {
+        Some((uid, gid)) => (uid, Some(gid)),
+        None => (user, None),
+    };
+    let parse = |id: &str| {
+        id.parse::<u32>()
+            .with_context(|| format!("user {user} has to be numeric, names are not supported"))
+    };
+    Ok((parse(uid)?, gid.map(parse).transpose()?))
+}
+
+/// Parses a number of CPUs like `0.5`, or in millicores like `500m`
+fn parse_cpus(cpus: &Quantity) -> Result<f64> {
+    let value = match cpus {
+        Quantity::Number(n) => *n,
+        Quantity::String(s) => match s.strip_suffix('m') {
+            Some(millis) => millis.parse::<f64>().map(|m| m / 1000.0),
+            None => s.parse::<f64>(),
+        }
+        .with_context(|| format!("invalid number of cpus {s}"))?,
+    };
+    if value <= 0.0 {
+        bail!("number of cpus has to be positive, got {value}");
+    }
+    Ok(value)
+}
+
+/// Parses sizes like docker does, with binary units `b`, `k`, `m` and `g`
+/// and an optional `b` after the unit
+fn parse_compose_bytes(size: &Quantity) -> Result<i64> {
+    let size = match size {
+        Quantity::Number(n) => return Ok(*n as i64),
+        Quantity::String(s) => s.to_lowercase(),
+    };
+    let (number, unit) = split_number(&size);
+    let factor: u64 = match unit.strip_suffix('b').unwrap_or(unit) {
+        "" => 1,
+        "k" => 1 << 10,
+        "m" => 1 << 20,
+        "g" => 1 << 30,
+        "t" => 1 << 40,
+        _ => bail!("invalid size {size}"),
+    };
+    let number: f64 = number
+        .parse()
+        .with_context(|| format!("invalid size {size}"))?;
+    Ok((number * factor as f64) as i64)
+}
+
+/// Parses quantities like kubernetes does, with binary units `Ki`, `Mi`, ...
+/// and decimal units `k`, `M`, ...
+fn parse_kubernetes_bytes(size: &Quantity) -> Result<i64> {
+    let size = match size {
+        Quantity::Number(n) => return Ok(*n as i64),
+        Quantity::String(s) => s,
+    };
+    let (number, unit) = split_number(size);
+    let factor: f64 = match unit {
+        "" => 1.0,
+        "Ki" => 1024f64,
+        "Mi" => 1024f64.powi(2),
+        "Gi" => 1024f64.powi(3),
+        "Ti" => 1024f64.powi(4),
+        "k" => 1e3,
+        "M" => 1e6,
+        "G" => 1e9,
+        "T" => 1e12,
+        _ => bail!("invalid quantity {size}"),
+    };
+    let number: f64 = number
+        .parse()
+        .with_context(|| format!("invalid quantity {size}"))?;
+    Ok((number * factor) as i64)
+}
+
+fn split_number(s: &str) -> (&str, &str) {
+    let end = s
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(s.len());
+    s.split_at(end)
+}
+
+/// Splits a command into words like a shell, honoring quotes and backslashes
+fn split_command(command: &str) -> Result<Vec<String>> {
+    let mut args = vec![];
+    let mut current: Option<String> = None;
+    let mut chars = command.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {
+                if let Some(arg) = current.take() {
+                    args.push(arg);
+                }
+            }
+            '\'' => {
+                let arg = current.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => arg.push(c),
+                        None => bail!("unterminated quote in command {command}"),
+                    }
+                }
+            }
+            '"' => {
+                let arg = current.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c @ ('"' | '\\' | '$' | '`')) => arg.push(c),
+                            Some(c) => {
+                                arg.push('\\');
+                                arg.push(c);
+                            }
+                            None => bail!("unterminated quote in command {command}"),
+                        },
+                        Some(c) => arg.push(c),
+                        None => bail!("unterminated quote in command {command}"),
+                    }
+                }
+            }
+            '\\' => {
+                let arg = current.get_or_insert_with(String::new);
+                match chars.next() {
+                    Some(c) => arg.push(c),
+                    None => bail!("trailing backslash in command {command}"),
+                }
+            }
+            c => current.get_or_insert_with(String::new).push(c),
+        }
+    }
+    args.extend(current);
+    Ok(args)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_split_command() -> Result<()> {
+        assert_eq!(
+            split_command(r#"sh -c 'echo "$HOME"'  "a b\"c" d\ e ''"#)?,
+            ["sh", "-c", r#"echo "$HOME""#, r#"a b"c"#, "d e", ""]
+        );
+        assert!(split_command("echo 'a").is_err());
+        assert!(split_command("echo \\").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_quantities() -> Result<()> {
+        let s = |s: &str| Quantity::String(s.to_owned());
+        assert_eq!(parse_compose_bytes(&s("512m"))?, 512 << 20);
+        assert_eq!(parse_compose_bytes(&s("1.5GB"))?, 3 << 29);
+        assert_eq!(parse_compose_bytes(&Quantity::Number(1024.0))?, 1024);
+        assert!(parse_compose_bytes(&s("1x")).is_err());
+
+        assert_eq!(parse_kubernetes_bytes(&s("128Mi"))?, 128 << 20);
+        assert_eq!(parse_kubernetes_bytes(&s("1G"))?, 1_000_000_000);
+        assert!(parse_kubernetes_bytes(&s("1g")).is_err());
+
+        assert_eq!(parse_cpus(&s("500m"))?, 0.5);
+        assert_eq!(parse_cpus(&s("1.5"))?, 1.5);
+        assert_eq!(parse_cpus(&Quantity::Number(2.0))?, 2.0);
+        assert!(parse_cpus(&s("0")).is_err());
+
+        assert_eq!(parse_user("1000")?, (1000, None));
+        assert_eq!(parse_user("1000:100")?, (1000, Some(100)));
+        assert!(parse_user("nobody").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_compose_service() -> Result<()> {
+        let service = json!({
+            "image": "ignored",
+            "entrypoint": ["/bin/sh", "-c"],
+            "command": "echo 'hello world'",
+            "environment": {"A": "1", "B": 2, "C": null},
+            "volumes": [
+                "./data:/data:ro,z",
+                "cache:/cache",
+                {"type": "tmpfs", "target": "/tmp"}
+            ],
+            "tmpfs": "/run:size=64m",
+            "user": "1000:100",
+            "working_dir": "/data",
+            "mem_limit": "1g",
+            "deploy": {"resources": {"limits": {"cpus": "0.5", "pids": 64}}}
+        });
+        let container = parse(service, None)?;
+        assert_eq!(container.args, ["/bin/sh", "-c", "echo", "hello world"]);
+        assert_eq!(
+            container.env,
+            [("A".into(), "1".into()), ("B".into(), "2".into())]
+        );
+        assert_eq!(container.user, Some((1000, Some(100))));
+        assert_eq!(container.cwd, Some(PathBuf::from("/data")));
+        assert!(!container.readonly);
+        assert_eq!(container.memory, Some(1 << 30));
+        assert_eq!(container.cpus, Some(0.5));
+        assert_eq!(container.pids, Some(64));
+        assert_eq!(
+            container.mounts,
+            [
+                bind_mount("./data", Path::new("/data"), true)?,
+                bind_mount("cache", Path::new("/cache"), false)?,
+                tmpfs_mount(Path::new("/tmp"), &[])?,
+                tmpfs_mount(Path::new("/run"), &["size=64m"])?,
+            ]
+        );
+
+        assert!(parse(json!({"volumes": ["/data"]}), None).is_err());
+        assert!(parse(json!({"services": {"web": {}}}), None).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_kubernetes_container() -> Result<()> {
+        let container = json!({
+            "name": "app",
+            "command": ["sleep"],
+            "args": ["infinity"],
+            "env": [{"name": "A", "value": "1"}, {"name": "B", "valueFrom": {}}],
+            "volumeMounts": [{"name": "config", "mountPath": "/etc/app", "readOnly": true, "subPath": "app"}],
+            "resources": {"limits": {"cpu": "250m", "memory": "64Mi"}},
+            "securityContext": {"runAsUser": 1000, "readOnlyRootFilesystem": true}
+        });
+        let container = parse(container, None)?;
+        assert_eq!(container.args, ["sleep", "infinity"]);
+        assert_eq!(container.env, [("A".into(), "1".into())]);
+        assert_eq!(container.user, Some((1000, None)));
+        assert!(container.readonly);
+        assert_eq!(container.memory, Some(64 << 20));
+        assert_eq!(container.cpus, Some(0.25));
+        assert_eq!(
+            container.mounts,
+            [bind_mount("config/app", Path::new("/etc/app"), true)?]
+        );
+
+        // without any field specific to kubernetes the format has to be given
+        let container = json!({"command": ["true"], "env": []});
+        assert_eq!(detect(&container), SpecFormat::Compose);
+        assert_eq!(
+            parse(container, Some(SpecFormat::Kubernetes))?.args,
+            ["true"]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_apply() -> Result<()> {
+        let mut spec = Spec::default();
+        let devices = spec
+            .linux()
+            .as_ref()
+            .unwrap()
+            .resources()
+            .as_ref()
+            .unwrap()
+            .devices()
+            .clone();
+        let container = Container {
+            args: vec!["sleep".into(), "1".into()],
+            env: vec![("TERM".into(), "dumb".into()), ("A".into(), "1".into())],
+            user: Some((1000, None)),
+            hostname: Some("app".into()),
+            mounts: vec![bind_mount("data", Path::new("/data"), false)?],
+            memory: Some(1 << 20),
+            cpus: Some(1.5),
+            ..Default::default()
+        };
+        apply(&mut spec, container)?;
+
+        let process = spec.process().as_ref().unwrap();
+        assert_eq!(process.args().as_ref().unwrap(), &["sleep", "1"]);
+        let env = process.env().as_ref().unwrap();
+        assert!(env.contains(&"TERM=dumb".to_owned()));
+        assert!(env.contains(&"A=1".to_owned()));
+        assert!(!env.contains(&"TERM=xterm".to_owned()));
+        assert_eq!(process.user().uid(), 1000);
+        assert_eq!(process.user().gid(), 1000);
+        assert_eq!(spec.hostname().as_deref(), Some("app"));
+        assert_eq!(spec.root().as_ref().unwrap().readonly(), Some(false));
+        assert_eq!(
+            spec.mounts()
+                .as_ref()
+                .unwrap()
+                .last()
+                .unwrap()
+                .destination(),
+            Path::new("/data")
+        );
+        let resources = spec.linux().as_ref().unwrap().resources().as_ref().unwrap();
+        assert_eq!(resources.memory().unwrap().limit(), Some(1 << 20));
+        assert_eq!(resources.cpu().as_ref().unwrap().quota(), Some(150_000));
+        assert_eq!(resources.devices(), &devices);
+        Ok(())
+    }
+}
@@ -1,15 +1,21 @@
 //! Starts execution of the container
 
 use std::path::PathBuf;
+use std::time::Duration;
 
 use anyhow::{Context, Result};
+use libcontainer::process::timeouts::{Phase, Timeouts};
 use liboci_cli::Start;
 
 use crate::commands::load_container;
 
 pub fn start(args: Start, root_path: PathBuf) -> Result<()> {
     let mut container = load_container(root_path, &args.container_id)?;
+    let timeouts = match args.timeout {
+        Some(timeout) => Timeouts::default().with_phase(Phase::Start, Duration::from_secs(timeout)),
+        None => Timeouts::default(),
+    };
     container
-        .start()
+        .start_with_timeouts(&timeouts)
         .with_context(|| format!("failed to start container {}", args.container_id))
 }
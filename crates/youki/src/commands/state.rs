@@ -7,6 +7,22 @@ use crate::commands::load_container;
 
 pub fn state(args: State, root_path: PathBuf) -> Result<()> {
     let container = load_container(root_path, &args.container_id)?;
-    println!("{}", serde_json::to_string_pretty(&container.state)?);
+    if args.exec_history {
+        let history = container.exec_history()?;
+        println!("{}", serde_json::to_string_pretty(&history)?);
+        std::process::exit(0);
+    }
+
+    let mut state = container.state.clone();
+    state.cgroup_paths = container.cgroup_paths()?;
+    if args.verbose {
+        // processes which exited since they were recorded are left out
+        state.exec_processes = container.exec_processes();
+        state.seccomp_agent = container.seccomp_agent();
+    } else {
+        state.confinement = None;
+        state.exec_processes.clear();
+    }
+    println!("{}", serde_json::to_string_pretty(&state)?);
     std::process::exit(0);
 }
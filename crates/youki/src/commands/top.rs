@@ -0,0 +1,156 @@
+//! Contains functionality of the top command, which shows a live updating
+//! view of the resources used by a container
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::thread;
+use std::time::Duration;
+
+use anyhow::{bail, Result};
+use clap::Parser;
+use libcgroups::common::CgroupManager;
+use libcgroups::stats::{Stats, StatsSample, UsageRates};
+use libcontainer::container::ContainerStatus;
+use tabwriter::TabWriter;
+
+use crate::commands::{create_cgroup_manager, load_container};
+
+/// Display a live view of the resource usage of a container
+#[derive(Parser, Debug)]
+pub struct Top {
+    /// Refresh interval in seconds
+    #[clap(short, long, default_value = "1")]
+    pub interval: u64,
+    /// Exit after the given number of refreshes instead of running until interrupted
+    #[clap(short = 'n', long)]
+    pub iterations: Option<u64>,
+    /// Name of the container instance
+    #[clap(value_parser = clap::builder::NonEmptyStringValueParser::new(), required = true)]
+    pub container_id: String,
+}
+
+// ANSI escape sequence which clears the terminal and moves the cursor home
+const CLEAR_SCREEN: &str = "\x1b[2J\x1b[H";
+
+pub fn top(args: Top, root_path: PathBuf) -> Result<()> {
+    let container = load_container(&root_path, &args.container_id)?;
+    if !matches!(
+        container.status(),
+        ContainerStatus::Running | ContainerStatus::Paused
+    ) {
+        bail!(
+            "container {} is {}, not running",
+            args.container_id,
+            container.status()
+        );
+    }
+
+    let cmanager = create_cgroup_manager(root_path, &args.container_id)?;
+    let interval = Duration::from_secs(args.interval.max(1));
+    let mut previous = StatsSample::new(cmanager.stats()?);
+    let mut refreshes = 0;
+    while args.iterations.map_or(true, |n| refreshes < n) {
+        thread::sleep(interval);
+        let current = StatsSample::new(cmanager.stats()?);
+        let rates = current.rates_since(&previous);
+
+        let mut stdout = io::stdout().lock();
+        write!(stdout, "{CLEAR_SCREEN}")?;
+        render(&mut stdout, &args.container_id, &current.stats, &rates)?;
+        stdout.flush()?;
+
+        previous = current;
+        refreshes += 1;
+    }
+
+    Ok(())
+}
+
+fn render<W: Write>(out: W, id: &str, stats: &Stats, rates: &UsageRates) -> Result<()> {
+    let memory = &stats.memory.memory;
+    let (limit, memory_percent) = match memory.limit {
+        0 | u64::MAX => ("unlimited".to_owned(), "-".to_owned()),
+        limit => (
+            format_bytes(limit),
+            format!("{:.2}", memory.usage as f64 / limit as f64 * 100.0),
+        ),
+    };
+    let pids = match stats.pids.limit {
+        0 | u64::MAX => stats.pids.current.to_string(),
+        limit => format!("{} / {}", stats.pids.current, limit),
+    };
+
+    let mut tab_writer = TabWriter::new(out);
+    writeln!(
+        tab_writer,
+        "ID\tCPU %\tMEM USAGE / LIMIT\tMEM %\tPIDS\tBLOCK READ/s\tBLOCK WRITE/s"
+    )?;
+    writeln!(
+        tab_writer,
+        "{}\t{:.2}\t{} / {}\t{}\t{}\t{}\t{}",
+        id,
+        rates.cpu_percent,
+        format_bytes(memory.usage),
+        limit,
+        memory_percent,
+        pids,
+        format_bytes(rates.io_read_bytes_per_sec as u64),
+        format_bytes(rates.io_write_bytes_per_sec as u64),
+    )?;
+    tab_writer.flush()?;
+
+    Ok(())
+}
+
+pub(crate) fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        format!("{bytes}{}", UNITS[0])
+    } else {
+        format!("{value:.1}{}", UNITS[unit])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_bytes() {
+        assert_eq!(format_bytes(0), "0B");
+        assert_eq!(format_bytes(1023), "1023B");
+        assert_eq!(format_bytes(1536), "1.5KiB");
+        assert_eq!(format_bytes(3 * 1024 * 1024 * 1024), "3.0GiB");
+    }
+
+    #[test]
+    fn test_render() -> Result<()> {
+        let mut stats = Stats::default();
+        stats.memory.memory.usage = 512 * 1024 * 1024;
+        stats.memory.memory.limit = 1024 * 1024 * 1024;
+        stats.pids.current = 3;
+        let rates = UsageRates {
+            cpu_percent: 12.5,
+            io_read_bytes_per_sec: 2048.0,
+            io_write_bytes_per_sec: 0.0,
+        };
+
+        let mut out = Vec::new();
+        render(&mut out, "test", &stats, &rates)?;
+        let out = String::from_utf8(out)?;
+        let row = out.lines().nth(1).unwrap();
+        assert!(row.starts_with("test"));
+        assert!(row.contains("12.50"));
+        assert!(row.contains("50.00"));
+        assert!(out.contains("512.0MiB / 1.0GiB"));
+        assert!(out.contains("2.0KiB"));
+
+        Ok(())
+    }
+}
@@ -1,19 +1,32 @@
-use std::path::PathBuf;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 use std::{fs, io};
 
-use anyhow::Result;
-use libcgroups::common::{CgroupManager, ControllerOpt};
+use anyhow::{bail, Context, Result};
+use libcgroups::common::{self, AnyCgroupManager, CgroupManager};
 use libcgroups::{self};
-use libcontainer::oci_spec::runtime::{LinuxPidsBuilder, LinuxResources, LinuxResourcesBuilder};
+use libcontainer::oci_spec::runtime::{
+    IOPriorityClass, LinuxBlockIo, LinuxBlockIoBuilder, LinuxCpuBuilder, LinuxIOPriority,
+    LinuxIOPriorityBuilder, LinuxPidsBuilder, LinuxResources, LinuxResourcesBuilder,
+    LinuxSchedulerFlag, LinuxSchedulerPolicy, LinuxWeightDevice, LinuxWeightDeviceBuilder,
+    Scheduler, SchedulerBuilder,
+};
+use libcontainer::process::scheduling::{self, SchedulingError};
 use liboci_cli::Update;
+use nix::sys::stat::{self, SFlag};
+use nix::unistd::Pid;
 
-use crate::commands::create_cgroup_manager;
+use crate::commands::{create_cgroup_manager, load_container};
 
 pub fn update(args: Update, root_path: PathBuf) -> Result<()> {
-    let cmanager = create_cgroup_manager(root_path, &args.container_id)?;
+    let cmanager = create_cgroup_manager(&root_path, &args.container_id)?;
+    // parsed first so that invalid values do not leave a partial update
+    let io_priority = io_priority(&args)?;
+    let scheduler = scheduler(&args)?;
+    let block_io = block_io(&args)?;
 
     let linux_res: LinuxResources;
-    if let Some(resources_path) = args.resources {
+    if let Some(resources_path) = &args.resources {
         linux_res = if resources_path.to_string_lossy() == "-" {
             serde_json::from_reader(io::stdin())?
         } else {
@@ -26,14 +39,233 @@ pub fn update(args: Update, root_path: PathBuf) -> Result<()> {
         if let Some(new_pids_limit) = args.pids_limit {
             builder = builder.pids(LinuxPidsBuilder::default().limit(new_pids_limit).build()?);
         }
+        if args.cpu_burst.is_some()
+            || args.cpu_idle.is_some()
+            || args.cpu_rt_period.is_some()
+            || args.cpu_rt_runtime.is_some()
+        {
+            let mut cpu = LinuxCpuBuilder::default();
+            if let Some(burst) = args.cpu_burst {
+                cpu = cpu.burst(burst);
+            }
+            if let Some(idle) = args.cpu_idle {
+                cpu = cpu.idle(idle);
+            }
+            if let Some(rt_period) = args.cpu_rt_period {
+                cpu = cpu.realtime_period(rt_period);
+            }
+            if let Some(rt_runtime) = args.cpu_rt_runtime {
+                let rt_runtime = i64::try_from(rt_runtime)
+                    .with_context(|| format!("cpu realtime runtime {rt_runtime} is too large"))?;
+                cpu = cpu.realtime_runtime(rt_runtime);
+            }
+            builder = builder.cpu(cpu.build()?);
+        }
+        if let Some(block_io) = block_io {
+            builder = builder.block_io(block_io);
+        }
+        let unified: HashMap<String, String> = [
+            ("memory.high", args.memory_high),
+            ("memory.swap.high", args.memory_swap_high),
+            ("memory.zswap.max", args.memory_zswap_max),
+        ]
+        .into_iter()
+        .filter_map(|(file, value)| Some((file.to_owned(), value?.to_string())))
+        .collect();
+        if !unified.is_empty() {
+            builder = builder.unified(unified);
+        }
         linux_res = builder.build()?;
     }
 
-    cmanager.apply(&ControllerOpt {
-        resources: &linux_res,
-        disable_oom_killer: false,
-        oom_score_adj: None,
-        freezer_state: None,
-    })?;
+    // reject the whole update rather than applying only the controllable part
+    cmanager.capabilities()?.validate(&linux_res)?;
+    // the same way as on create
+    common::apply_resources(&cmanager, &linux_res)?;
+
+    if let Some(bytes) = args.memory_reclaim {
+        cmanager.reclaim(bytes).with_context(|| {
+            format!(
+                "failed to reclaim {bytes} bytes from container {}",
+                args.container_id
+            )
+        })?;
+    }
+
+    if io_priority.is_some() || scheduler.is_some() {
+        update_priorities(
+            &args,
+            &root_path,
+            &cmanager,
+            io_priority.as_ref(),
+            scheduler.as_ref(),
+        )?;
+    }
+    Ok(())
+}
+
+fn block_io(args: &Update) -> Result<Option<LinuxBlockIo>> {
+    if args.blkio_weight.is_none() && args.blkio_weight_device.is_empty() {
+        return Ok(None);
+    }
+
+    let mut block_io = LinuxBlockIoBuilder::default();
+    if let Some(weight) = args.blkio_weight {
+        let weight =
+            u16::try_from(weight).with_context(|| format!("blkio weight {weight} is too large"))?;
+        block_io = block_io.weight(weight);
+    }
+    if !args.blkio_weight_device.is_empty() {
+        let weight_device = args
+            .blkio_weight_device
+            .iter()
+            .map(|value| parse_weight_device(value))
+            .collect::<Result<Vec<_>>>()?;
+        block_io = block_io.weight_device(weight_device);
+    }
+    Ok(Some(block_io.build()?))
+}
+
+/// Parses `<device path or major:minor>:<weight>`
+fn parse_weight_device(value: &str) -> Result<LinuxWeightDevice> {
+    let (device, weight) = value
+        .rsplit_once(':')
+        .with_context(|| format!("blkio weight device {value:?} is not <device>:<weight>"))?;
+    let weight: u16 = weight
+        .parse()
+        .with_context(|| format!("invalid weight of blkio weight device {value:?}"))?;
+
+    let (major, minor) = if device.starts_with('/') {
+        let stat = stat::stat(device)
+            .with_context(|| format!("failed to stat blkio weight device {device}"))?;
+        if SFlag::from_bits_truncate(stat.st_mode) & SFlag::S_IFMT != SFlag::S_IFBLK {
+            bail!("{device} is not a block device");
+        }
+        (
+            stat::major(stat.st_rdev) as i64,
+            stat::minor(stat.st_rdev) as i64,
+        )
+    } else {
+        let numbers = device.split_once(':').and_then(|(major, minor)| {
+            Some((major.parse::<i64>().ok()?, minor.parse::<i64>().ok()?))
+        });
+        numbers.with_context(|| {
+            format!("blkio weight device {value:?} is neither a path nor major:minor")
+        })?
+    };
+
+    Ok(LinuxWeightDeviceBuilder::default()
+        .major(major)
+        .minor(minor)
+        .weight(weight)
+        .build()?)
+}
+
+/// Changes the io priority and the scheduler of the container init, or of
+/// all processes of the container.
+fn update_priorities(
+    args: &Update,
+    root_path: &Path,
+    cmanager: &AnyCgroupManager,
+    io_priority: Option<&LinuxIOPriority>,
+    scheduler: Option<&Scheduler>,
+) -> Result<()> {
+    let pids = if args.all_processes {
+        cmanager.get_all_pids()?
+    } else {
+        let container = load_container(root_path, &args.container_id)?;
+        if !container.can_kill() {
+            bail!("container {} is not running", args.container_id);
+        }
+        vec![container
+            .pid()
+            .with_context(|| format!("container {} has no init process", args.container_id))?]
+    };
+
+    for pid in pids {
+        match set_priorities(pid, io_priority, scheduler) {
+            // a process of the container may exit at any time
+            Err(SchedulingError::Threads { source, .. })
+                if args.all_processes && source.kind() == io::ErrorKind::NotFound =>
+            {
+                tracing::debug!(?pid, "process exited before its priority was changed");
+            }
+            result => result.with_context(|| {
+                format!(
+                    "failed to change the priority of process {pid} of container {}",
+                    args.container_id
+                )
+            })?,
+        }
+    }
+
+    Ok(())
+}
+
+fn set_priorities(
+    pid: Pid,
+    io_priority: Option<&LinuxIOPriority>,
+    scheduler: Option<&Scheduler>,
+) -> Result<(), SchedulingError> {
+    if let Some(io_priority) = io_priority {
+        scheduling::set_io_priority(pid, io_priority)?;
+    }
+    if let Some(scheduler) = scheduler {
+        scheduling::set_scheduler(pid, scheduler)?;
+    }
+
     Ok(())
 }
+
+fn io_priority(args: &Update) -> Result<Option<LinuxIOPriority>> {
+    let Some(class) = &args.io_priority_class else {
+        return Ok(None);
+    };
+    let class: IOPriorityClass = class
+        .parse()
+        .with_context(|| format!("invalid io priority class {class}"))?;
+    let io_priority = LinuxIOPriorityBuilder::default()
+        .class(class)
+        .priority(args.io_priority.unwrap_or_default())
+        .build()?;
+
+    Ok(Some(io_priority))
+}
+
+fn scheduler(args: &Update) -> Result<Option<Scheduler>> {
+    let Some(policy) = &args.sched_policy else {
+        return Ok(None);
+    };
+    let policy: LinuxSchedulerPolicy = policy
+        .parse()
+        .with_context(|| format!("invalid scheduling policy {policy}"))?;
+    let mut builder = SchedulerBuilder::default().policy(policy);
+    if let Some(nice) = args.sched_nice {
+        builder = builder.nice(nice);
+    }
+    if let Some(priority) = args.sched_priority {
+        builder = builder.priority(priority);
+    }
+    if !args.sched_flag.is_empty() {
+        let flags = args
+            .sched_flag
+            .iter()
+            .map(|flag| {
+                flag.parse()
+                    .with_context(|| format!("invalid scheduling flag {flag}"))
+            })
+            .collect::<Result<Vec<LinuxSchedulerFlag>>>()?;
+        builder = builder.flags(flags);
+    }
+    if let Some(runtime) = args.sched_runtime {
+        builder = builder.runtime(runtime);
+    }
+    if let Some(deadline) = args.sched_deadline {
+        builder = builder.deadline(deadline);
+    }
+    if let Some(period) = args.sched_period {
+        builder = builder.period(period);
+    }
+
+    Ok(Some(builder.build()?))
+}
@@ -0,0 +1,52 @@
+//! Contains functionality of the verify command, which compares the spec of a
+//! running container with its state in the kernel
+use std::io::{self, Write};
+use std::path::PathBuf;
+
+use anyhow::{bail, Result};
+use clap::Parser;
+use tabwriter::TabWriter;
+
+use crate::commands::load_container;
+
+/// Report where a running container drifted from its spec
+#[derive(Parser, Debug)]
+pub struct Verify {
+    /// Specify the format (table or json)
+    #[clap(long, default_value = "table", value_parser = ["table", "json"])]
+    pub format: String,
+    /// Name of the container instance
+    #[clap(value_parser = clap::builder::NonEmptyStringValueParser::new(), required = true)]
+    pub container_id: String,
+}
+
+pub fn verify(args: Verify, root_path: PathBuf) -> Result<()> {
+    let mut container = load_container(root_path, &args.container_id)?;
+    let findings = container.verify()?;
+
+    match args.format.as_str() {
+        "json" => println!("{}", serde_json::to_string_pretty(&findings)?),
+        _ => {
+            let mut tab_writer = TabWriter::new(io::stdout());
+            writeln!(&mut tab_writer, "CATEGORY\tSUBJECT\tEXPECTED\tACTUAL")?;
+            for finding in &findings {
+                writeln!(
+                    &mut tab_writer,
+                    "{}\t{}\t{}\t{}",
+                    finding.category, finding.subject, finding.expected, finding.actual
+                )?;
+            }
+            tab_writer.flush()?;
+        }
+    }
+
+    if !findings.is_empty() {
+        bail!(
+            "container {} drifted from its spec in {} places",
+            args.container_id,
+            findings.len()
+        );
+    }
+
+    Ok(())
+}
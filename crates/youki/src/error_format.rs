@@ -0,0 +1,141 @@
+//! Reporting of command failures on stderr. Besides the human readable text,
+//! failures can be reported as a single line of JSON so that engines driving
+//! youki can act on them without parsing free text.
+use clap::ValueEnum;
+use libcgroups::capabilities::UnsupportedResourceError;
+use libcontainer::container::CheckpointError;
+use libcontainer::error::LibcontainerError;
+use serde_json::json;
+
+#[derive(ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ErrorFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+/// Where a failure happened: the subcommand and the container it operated on.
+#[derive(Debug, Default)]
+pub struct ErrorContext {
+    pub phase: &'static str,
+    pub container_id: Option<String>,
+}
+
+pub fn report(format: ErrorFormat, context: &ErrorContext, err: &anyhow::Error) {
+    match format {
+        ErrorFormat::Text => eprintln!("{} failed: {:?}", context.phase, err),
+        ErrorFormat::Json => eprintln!("{}", to_json(context, err)),
+    }
+}
+
+/// Reports a failed command in the given format and exits with the code.
+/// Every failure of a command goes through here, including the ones before
+/// logging is set up, so that it is reported exactly once.
+pub fn exit(format: ErrorFormat, context: &ErrorContext, err: &anyhow::Error, code: i32) -> ! {
+    tracing::error!("error in executing command: {:?}", err);
+    report(format, context, err);
+    std::process::exit(code)
+}
+
+fn to_json(context: &ErrorContext, err: &anyhow::Error) -> String {
+    json!({
+        "code": error_code(err),
+        "message": err.to_string(),
+        "phase": context.phase,
+        "container_id": context.container_id,
+        "causes": err.chain().skip(1).map(ToString::to_string).collect::<Vec<_>>(),
+    })
+    .to_string()
+}
+
+/// Maps the first known error in the chain to a stable code. Callers should
+/// rely on the code rather than on the message, which may change.
+fn error_code(err: &anyhow::Error) -> &'static str {
+    err.chain()
+        .find_map(|cause| {
+            if cause.is::<UnsupportedResourceError>() {
+                return Some("unsupported");
+            }
+            cause
+                .downcast_ref::<LibcontainerError>()
+                .map(libcontainer_error_code)
+        })
+        .unwrap_or("unknown")
+}
+
+fn libcontainer_error_code(err: &LibcontainerError) -> &'static str {
+    match err {
+        LibcontainerError::IncorrectStatus => "incorrect_status",
+        LibcontainerError::Exist => "already_exists",
+        LibcontainerError::NoDirectory => "not_found",
+        LibcontainerError::InvalidInput(_) => "invalid_input",
+        LibcontainerError::InvalidID(_)
+        | LibcontainerError::MissingSpec(_)
+        | LibcontainerError::InvalidSpec(_) => "invalid_spec",
+        LibcontainerError::FreezerUnavailable => "unsupported",
+        LibcontainerError::Checkpoint(CheckpointError::CriuError(_)) => "criu",
+        LibcontainerError::Checkpoint(_) => "checkpoint",
+        _ => "internal",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Context;
+    use libcgroups::capabilities::ResourceType;
+    use serde_json::Value;
+
+    use super::*;
+
+    #[test]
+    fn test_to_json() -> anyhow::Result<()> {
+        let err = Err::<(), _>(LibcontainerError::IncorrectStatus)
+            .context("failed to pause container test")
+            .unwrap_err();
+        let context = ErrorContext {
+            phase: "pause",
+            container_id: Some("test".to_owned()),
+        };
+
+        let out = to_json(&context, &err);
+        assert!(!out.contains('\n'));
+        let value: Value = serde_json::from_str(&out)?;
+        assert_eq!(value["code"], "incorrect_status");
+        assert_eq!(value["message"], "failed to pause container test");
+        assert_eq!(value["phase"], "pause");
+        assert_eq!(value["container_id"], "test");
+        assert_eq!(
+            value["causes"],
+            json!(["failed to perform operation due to incorrect container status"])
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_error_code_unsupported_resource() {
+        let err = anyhow::Error::new(UnsupportedResourceError {
+            resources: vec![ResourceType::Network],
+        })
+        .context("failed to update container test");
+        assert_eq!(error_code(&err), "unsupported");
+    }
+
+    #[test]
+    fn test_error_code_checkpoint() {
+        let err = anyhow::Error::new(LibcontainerError::Checkpoint(CheckpointError::CriuError(
+            "dump failed".to_owned(),
+        )));
+        assert_eq!(error_code(&err), "criu");
+
+        let err = anyhow::Error::new(LibcontainerError::Checkpoint(
+            CheckpointError::MissingImage("inventory.img".into()),
+        ));
+        assert_eq!(error_code(&err), "checkpoint");
+    }
+
+    #[test]
+    fn test_error_code_without_libcontainer_error() {
+        assert_eq!(error_code(&anyhow::anyhow!("plain error")), "unknown");
+    }
+}
@@ -2,8 +2,11 @@
 //! Container Runtime written in Rust, inspired by [railcar](https://github.com/oracle/railcar)
 //! This crate provides a container runtime which can be used by a high-level container runtime to run containers.
 mod commands;
+mod error_format;
 mod observability;
 mod rootpath;
+mod seal;
+mod usernet;
 mod workload;
 
 use anyhow::{Context, Result};
@@ -11,6 +14,8 @@ use clap::{crate_version, CommandFactory, Parser};
 use liboci_cli::{CommonCmd, GlobalOpts, StandardCmd};
 
 use crate::commands::info;
+use crate::error_format::{ErrorContext, ErrorFormat};
+use crate::observability::ObservabilityConfig;
 
 // Additional options that are not defined in OCI runtime-spec, but are used by Youki.
 #[derive(Parser, Debug)]
@@ -21,6 +26,9 @@ struct YoukiExtendOpts {
     /// set the log level (default is 'error')
     #[clap(long)]
     pub log_level: Option<String>,
+    /// Format of the error reported on stderr when a command fails
+    #[clap(long, value_enum, default_value_t)]
+    pub error_format: ErrorFormat,
 }
 
 /// output Youki version in Moby compatible format
@@ -53,7 +61,29 @@ struct Opts {
     youki_extend: YoukiExtendOpts,
 
     #[clap(subcommand)]
-    subcmd: SubCommand,
+    command: Command,
+}
+
+#[derive(Parser, Debug)]
+enum Command {
+    #[clap(flatten)]
+    Runtime(Box<SubCommand>),
+    // Sealing and logging need /proc, which PID 1 of a microVM only has once
+    // init-host mounted it, so init-host is dispatched before both.
+    #[clap(hide = true)]
+    InitHost(commands::init_host::InitHost),
+}
+
+impl Command {
+    fn error_context(&self) -> ErrorContext {
+        match self {
+            Command::Runtime(cmd) => cmd.error_context(),
+            Command::InitHost(c) => ErrorContext {
+                phase: "init-host",
+                container_id: Some(c.container_id.clone()),
+            },
+        }
+    }
 }
 
 // Subcommands accepted by Youki, confirming with [OCI runtime-spec](https://github.com/opencontainers/runtime-spec/blob/master/runtime.md)
@@ -69,40 +99,114 @@ enum SubCommand {
     // Youki specific extensions
     Info(info::Info),
     Completion(commands::completion::Completion),
+    Top(commands::top::Top),
+    Batch(commands::batch::Batch),
+    Verify(commands::verify::Verify),
+    Diff(commands::diff::Diff),
+    Admit(commands::admit::Admit),
+    Debug(commands::debug::Debug),
+    Purge(commands::purge::Purge),
+    Finished(commands::finished::Finished),
+    #[clap(hide = true)]
+    ConsoleTee(commands::console_tee::ConsoleTee),
+}
+
+impl SubCommand {
+    fn error_context(&self) -> ErrorContext {
+        let (phase, container_id) = match self {
+            SubCommand::Standard(cmd) => match cmd.as_ref() {
+                StandardCmd::Create(c) => ("create", Some(&c.container_id)),
+                StandardCmd::Start(c) => ("start", Some(&c.container_id)),
+                StandardCmd::Kill(c) => ("kill", Some(&c.container_id)),
+                StandardCmd::Delete(c) => ("delete", Some(&c.container_id)),
+                StandardCmd::State(c) => ("state", Some(&c.container_id)),
+            },
+            SubCommand::Common(cmd) => match cmd.as_ref() {
+                CommonCmd::Checkpointt(c) => ("checkpoint", Some(&c.container_id)),
+                CommonCmd::Events(c) => ("events", c.container_id.as_ref()),
+                CommonCmd::Exec(c) => ("exec", Some(&c.container_id)),
+                CommonCmd::Features(_) => ("features", None),
+                CommonCmd::List(_) => ("list", None),
+                CommonCmd::Pause(c) => ("pause", Some(&c.container_id)),
+                CommonCmd::Ps(c) => ("ps", Some(&c.container_id)),
+                CommonCmd::Resume(c) => ("resume", Some(&c.container_id)),
+                CommonCmd::Run(c) => ("run", Some(&c.container_id)),
+                CommonCmd::Spec(_) => ("spec", None),
+                CommonCmd::Update(c) => ("update", Some(&c.container_id)),
+            },
+            SubCommand::Info(_) => ("info", None),
+            SubCommand::Completion(_) => ("completion", None),
+            SubCommand::Top(c) => ("top", Some(&c.container_id)),
+            SubCommand::Batch(_) => ("batch", None),
+            SubCommand::Verify(c) => ("verify", Some(&c.container_id)),
+            SubCommand::Diff(c) => ("diff", Some(&c.container_id)),
+            SubCommand::Admit(_) => ("admit", None),
+            SubCommand::Debug(c) => ("debug", c.container_id()),
+            SubCommand::Purge(_) => ("purge", None),
+            SubCommand::Finished(c) => ("finished", c.container_id.as_ref()),
+            SubCommand::ConsoleTee(_) => ("console-tee", None),
+        };
+
+        ErrorContext {
+            phase,
+            container_id: container_id.cloned(),
+        }
+    }
 }
 
 /// This is the entry point in the container runtime. The binary is run by a high-level container runtime,
 /// with various flags passed. This parses the flags, creates and manages appropriate resources.
-fn main() -> Result<()> {
-    // A malicious container can gain access to the host machine by modifying youki's host
-    // binary and infect it with malicious code. This vulnerability was first discovered
-    // in runc and was assigned as CVE-2019-5736, but it also affects youki.
-    //
-    // The fix is to copy /proc/self/exe in an anonymous file descriptor (created via memfd_create),
-    // seal it and re-execute it. Because the final step is re-execution, this needs to be done at
-    // the beginning of this process.
-    //
-    // Ref: https://github.com/opencontainers/runc/commit/0a8e4117e7f715d5fbeef398405813ce8e88558b
-    // Ref: https://github.com/lxc/lxc/commit/6400238d08cdf1ca20d49bafb85f4e224348bf9d
-    pentacle::ensure_sealed().context("failed to seal /proc/self/exe")?;
-
+fn main() {
     let opts = Opts::parse();
-    let mut app = Opts::command();
+    let observability = ObservabilityConfig::from(&opts);
+    let error_format = opts.youki_extend.error_format;
+    let error_context = opts.command.error_context();
+
+    let cmd_result = match opts.command {
+        Command::InitHost(init_host) => {
+            commands::init_host::init_host(init_host, opts.global.root, observability)
+        }
+        Command::Runtime(subcmd) => run(
+            *subcmd,
+            opts.global,
+            observability,
+            error_format,
+            &error_context,
+        ),
+    };
+
+    if let Err(err) = cmd_result {
+        error_format::exit(error_format, &error_context, &err, 1);
+    }
+}
 
-    observability::init(&opts).map_err(|err| {
-        eprintln!("failed to initialize observability: {}", err);
-        err
-    })?;
+fn run(
+    subcmd: SubCommand,
+    global: GlobalOpts,
+    observability: ObservabilityConfig,
+    error_format: ErrorFormat,
+    error_context: &ErrorContext,
+) -> Result<()> {
+    seal::ensure_sealed()?;
+    observability::init(observability).context("failed to initialize observability")?;
 
     tracing::debug!(
         "started by user {} with {:?}",
         nix::unistd::geteuid(),
         std::env::args_os()
     );
-    let root_path = rootpath::determine(opts.global.root)?;
-    let systemd_cgroup = opts.global.systemd_cgroup;
+    if !seal::enabled() {
+        tracing::warn!(
+            "{} is set, running without sealing the youki binary",
+            seal::SKIP_SEAL_ENV
+        );
+    }
+
+    let root_path = rootpath::determine(global.root)?;
+    let systemd_cgroup = global.systemd_cgroup;
+    let mut app = Opts::command();
 
-    let cmd_result = match opts.subcmd {
+    match subcmd {
         SubCommand::Standard(cmd) => match *cmd {
             StandardCmd::Create(create) => {
                 commands::create::create(create, root_path, systemd_cgroup)
@@ -119,11 +223,12 @@ fn main() -> Result<()> {
             CommonCmd::Events(events) => commands::events::events(events, root_path),
             CommonCmd::Exec(exec) => match commands::exec::exec(exec, root_path) {
                 Ok(exit_code) => std::process::exit(exit_code),
-                Err(e) => {
-                    tracing::error!("error in executing command: {:?}", e);
-                    eprintln!("exec failed : {e}");
-                    std::process::exit(-1);
-                }
+                Err(e) => error_format::exit(
+                    error_format,
+                    error_context,
+                    &e,
+                    commands::failure_exit_code(&e),
+                ),
             },
             CommonCmd::Features(features) => commands::features::features(features),
             CommonCmd::List(list) => commands::list::list(list, root_path),
@@ -132,11 +237,12 @@ fn main() -> Result<()> {
             CommonCmd::Resume(resume) => commands::resume::resume(resume, root_path),
             CommonCmd::Run(run) => match commands::run::run(run, root_path, systemd_cgroup) {
                 Ok(exit_code) => std::process::exit(exit_code),
-                Err(e) => {
-                    tracing::error!("error in executing command: {:?}", e);
-                    eprintln!("run failed : {e}");
-                    std::process::exit(-1);
-                }
+                Err(e) => error_format::exit(
+                    error_format,
+                    error_context,
+                    &e,
+                    commands::failure_exit_code(&e),
+                ),
             },
             CommonCmd::Spec(spec) => commands::spec_json::spec(spec),
             CommonCmd::Update(update) => commands::update::update(update, root_path),
@@ -146,11 +252,17 @@ fn main() -> Result<()> {
         SubCommand::Completion(completion) => {
             commands::completion::completion(completion, &mut app)
         }
-    };
-
-    if let Err(ref e) = cmd_result {
-        tracing::error!("error in executing command: {:?}", e);
-        eprintln!("error in executing command: {:?}", e);
+        SubCommand::Top(top) => commands::top::top(top, root_path),
+        SubCommand::Batch(batch) => commands::batch::batch(batch, root_path, systemd_cgroup),
+        SubCommand::Verify(verify) => commands::verify::verify(verify, root_path),
+        SubCommand::Diff(diff) => commands::diff::diff(diff, root_path),
+        SubCommand::Admit(admit) => commands::admit::admit(admit),
+        SubCommand::Debug(debug) => match commands::debug::debug(debug, root_path) {
+            Ok(exit_code) => std::process::exit(exit_code),
+            Err(e) => Err(e),
+        },
+        SubCommand::Purge(purge) => commands::purge::purge(purge, root_path),
+        SubCommand::Finished(finished) => commands::finished::finished(finished, root_path),
+        SubCommand::ConsoleTee(console_tee) => commands::console_tee::console_tee(console_tee),
     }
-    cmd_result
 }
@@ -0,0 +1,40 @@
+//! Protection of the youki binary against CVE-2019-5736. A malicious
+//! container can gain access to the host machine by modifying youki's host
+//! binary and infect it with malicious code. This vulnerability was first
+//! discovered in runc, but it also affects youki.
+//!
+//! The fix is to copy /proc/self/exe in an anonymous file descriptor (created
+//! via memfd_create), seal it and re-execute it. Copying the binary on every
+//! invocation is noticeable on some filesystems, so builds with the
+//! `allow-unsealed` feature skip it when [`SKIP_SEAL_ENV`] is set. This is
+//! only safe where the runtime binary is read-only for everything that runs
+//! in containers anyway.
+//!
+//! Ref: https://github.com/opencontainers/runc/commit/0a8e4117e7f715d5fbeef398405813ce8e88558b
+//! Ref: https://github.com/lxc/lxc/commit/6400238d08cdf1ca20d49bafb85f4e224348bf9d
+use anyhow::{Context, Result};
+
+/// Environment variable to skip sealing, set to 1 or true
+pub const SKIP_SEAL_ENV: &str = "YOUKI_SKIP_SEAL";
+
+/// Returns if youki runs from a sealed copy of its binary. Sealing is
+/// decided before any flag takes effect, so it can only be turned off by the
+/// environment.
+pub fn enabled() -> bool {
+    if !cfg!(feature = "allow-unsealed") {
+        return true;
+    }
+
+    !std::env::var(SKIP_SEAL_ENV).map_or(false, |value| value == "1" || value == "true")
+}
+
+/// Re-executes youki from a sealed copy of its binary unless sealing is
+/// turned off. Because the final step is re-execution, this needs to be
+/// done at the beginning of the process.
+pub fn ensure_sealed() -> Result<()> {
+    if enabled() {
+        pentacle::ensure_sealed().context("failed to seal /proc/self/exe")?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,538 @@
+//! User-mode networking for containers run standalone with their own network
+//! namespace, typically rootless ones which cannot create veth pairs. Either
+//! pasta or slirp4netns is attached to the network namespace of the container
+//! init to give it outbound connectivity and to publish ports on the host.
+//!
+//! The driver and the published ports can be given on the command line or
+//! through the `org.youki.usernet` and `org.youki.usernet.ports` annotations,
+//! the latter being a comma separated list of port mappings. A mapping has the
+//! form `[host_ip:]host_port:container_port[/tcp|/udp]`, IPv6 host addresses
+//! are written in brackets, e.g. `[::1]:8080:80`.
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::fs;
+use std::io::{Read, Write};
+use std::net::{IpAddr, Shutdown};
+use std::os::unix::net::UnixStream;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Context, Result};
+use libcontainer::container::Container;
+use libcontainer::oci_spec::runtime::{LinuxNamespaceType, Spec};
+use nix::errno::Errno;
+use nix::sys::signal::{kill, Signal};
+use nix::unistd::Pid;
+use serde_json::{json, Value};
+
+pub const MODE_ANNOTATION: &str = "org.youki.usernet";
+pub const PORTS_ANNOTATION: &str = "org.youki.usernet.ports";
+
+// records the running driver in the container directory, so that delete can
+// stop it
+const STATE_FILE: &str = "usernet.json";
+const PASTA_PID_FILE: &str = "pasta.pid";
+const SLIRP4NETNS_API_SOCKET: &str = "slirp4netns.sock";
+const SLIRP4NETNS_READY_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Driver {
+    Pasta,
+    Slirp4netns,
+}
+
+impl FromStr for Driver {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "pasta" => Ok(Self::Pasta),
+            "slirp4netns" => Ok(Self::Slirp4netns),
+            _ => bail!("unknown user-mode network driver {s}, expected pasta or slirp4netns"),
+        }
+    }
+}
+
+impl Display for Driver {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let print = match self {
+            Self::Pasta => "pasta",
+            Self::Slirp4netns => "slirp4netns",
+        };
+
+        write!(f, "{print}")
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Protocol {
+    Tcp,
+    Udp,
+}
+
+impl Display for Protocol {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let print = match self {
+            Self::Tcp => "tcp",
+            Self::Udp => "udp",
+        };
+
+        write!(f, "{print}")
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PortMapping {
+    pub host_ip: Option<IpAddr>,
+    pub host_port: u16,
+    pub container_port: u16,
+    pub protocol: Protocol,
+}
+
+impl FromStr for PortMapping {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (mapping, protocol) = match s.rsplit_once('/') {
+            Some((mapping, "tcp")) => (mapping, Protocol::Tcp),
+            Some((mapping, "udp")) => (mapping, Protocol::Udp),
+            Some((_, protocol)) => bail!("unknown protocol {protocol} in port mapping {s}"),
+            None => (s, Protocol::Tcp),
+        };
+
+        let (host_ip, ports) = if let Some(rest) = mapping.strip_prefix('[') {
+            let (ip, ports) = rest
+                .split_once("]:")
+                .with_context(|| format!("invalid port mapping {s}"))?;
+            (Some(ip), ports)
+        } else {
+            match mapping.matches(':').count() {
+                1 => (None, mapping),
+                2 => {
+                    let (ip, ports) = mapping.split_once(':').unwrap();
+                    (Some(ip), ports)
+                }
+                _ => bail!("invalid port mapping {s}, expected [host_ip:]host_port:container_port"),
+            }
+        };
+        let host_ip = host_ip
+            .map(|ip| ip.parse())
+            .transpose()
+            .with_context(|| format!("invalid host address in port mapping {s}"))?;
+        let (host_port, container_port) = ports
+            .split_once(':')
+            .with_context(|| format!("invalid port mapping {s}"))?;
+
+        Ok(Self {
+            host_ip,
+            host_port: parse_port(host_port, s)?,
+            container_port: parse_port(container_port, s)?,
+            protocol,
+        })
+    }
+}
+
+fn parse_port(port: &str, mapping: &str) -> Result<u16> {
+    match port.parse() {
+        Ok(0) | Err(_) => bail!("invalid port {port} in port mapping {mapping}"),
+        Ok(port) => Ok(port),
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UserNet {
+    pub driver: Driver,
+    pub ports: Vec<PortMapping>,
+}
+
+impl UserNet {
+    /// Resolves the configuration from the command line, falling back to
+    /// the annotations. Returns None if user-mode networking is not wanted.
+    pub fn resolve(
+        driver: Option<&str>,
+        publish: &[String],
+        annotations: Option<&HashMap<String, String>>,
+    ) -> Result<Option<Self>> {
+        let annotation = |key| annotations.and_then(|a| a.get(key)).map(String::as_str);
+        let Some(driver) = driver.or_else(|| annotation(MODE_ANNOTATION)) else {
+            if !publish.is_empty() {
+                bail!("publishing ports requires a user-mode network driver");
+            }
+            return Ok(None);
+        };
+        let driver: Driver = driver.parse()?;
+
+        let ports = if publish.is_empty() {
+            annotation(PORTS_ANNOTATION)
+                .map(|ports| {
+                    ports
+                        .split(',')
+                        .map(str::trim)
+                        .filter(|port| !port.is_empty())
+                        .map(str::parse)
+                        .collect::<Result<Vec<PortMapping>>>()
+                })
+                .transpose()?
+                .unwrap_or_default()
+        } else {
+            publish
+                .iter()
+                .map(|port| port.parse())
+                .collect::<Result<Vec<PortMapping>>>()?
+        };
+
+        if driver == Driver::Slirp4netns {
+            if let Some(port) = ports
+                .iter()
+                .find(|p| matches!(p.host_ip, Some(IpAddr::V6(_))))
+            {
+                bail!(
+                    "slirp4netns only publishes ports on IPv4 addresses, use pasta for {}",
+                    port.host_port
+                );
+            }
+        }
+
+        Ok(Some(Self { driver, ports }))
+    }
+
+    /// Checks that the container gets a network namespace of its own, which
+    /// is the one the driver is attached to
+    pub fn validate(&self, spec: &Spec) -> Result<()> {
+        let own_netns = spec
+            .linux()
+            .as_ref()
+            .and_then(|linux| linux.namespaces().as_ref())
+            .and_then(|namespaces| {
+                namespaces
+                    .iter()
+                    .find(|ns| ns.typ() == LinuxNamespaceType::Network)
+            })
+            .map_or(false, |ns| ns.path().is_none());
+        if !own_netns {
+            bail!(
+                "{} requires the container to have a new network namespace",
+                self.driver
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Attaches the driver to the network namespace of the created container
+    pub fn setup(&self, container: &Container) -> Result<()> {
+        let pid = container
+            .pid()
+            .context("expects a container init pid in the container state")?;
+        let driver_pid = match self.driver {
+            Driver::Pasta => self.start_pasta(&container.root, pid)?,
+            Driver::Slirp4netns => self.start_slirp4netns(&container.root, pid)?,
+        };
+        tracing::debug!(driver = %self.driver, ?driver_pid, "started user-mode networking");
+
+        let state = json!({ "driver": self.driver.to_string(), "pid": driver_pid.as_raw() });
+        let state_file = container.root.join(STATE_FILE);
+        if let Err(err) = fs::write(&state_file, state.to_string()) {
+            stop(self.driver, driver_pid);
+            return Err(err).with_context(|| format!("failed to write {}", state_file.display()));
+        }
+
+        Ok(())
+    }
+
+    fn start_pasta(&self, container_root: &Path, pid: Pid) -> Result<Pid> {
+        let pid_file = container_root.join(PASTA_PID_FILE);
+        let status = Command::new("pasta")
+            .args(self.pasta_args(&pid_file, pid))
+            .stdin(Stdio::null())
+            .status()
+            .context("failed to run pasta")?;
+        if !status.success() {
+            bail!("pasta failed with {status}");
+        }
+
+        // pasta daemonizes once the namespace is configured
+        let driver_pid = fs::read_to_string(&pid_file)
+            .with_context(|| format!("failed to read {}", pid_file.display()))?;
+        Ok(Pid::from_raw(driver_pid.trim().parse().with_context(
+            || format!("invalid pid in {}", pid_file.display()),
+        )?))
+    }
+
+    fn pasta_args(&self, pid_file: &Path, pid: Pid) -> Vec<String> {
+        let mut args = vec![
+            "--config-net".to_owned(),
+            "--quiet".to_owned(),
+            "--pid".to_owned(),
+            pid_file.display().to_string(),
+        ];
+        for protocol in [Protocol::Tcp, Protocol::Udp] {
+            let flag = match protocol {
+                Protocol::Tcp => "--tcp-ports",
+                Protocol::Udp => "--udp-ports",
+            };
+            let mut published = self
+                .ports
+                .iter()
+                .filter(|p| p.protocol == protocol)
+                .peekable();
+            if published.peek().is_none() {
+                // pasta would forward every port bound in the container otherwise
+                args.extend([flag.to_owned(), "none".to_owned()]);
+            }
+            for port in published {
+                let address = port.host_ip.map(|ip| format!("{ip}/")).unwrap_or_default();
+                args.extend([
+                    flag.to_owned(),
+                    format!("{address}{}:{}", port.host_port, port.container_port),
+                ]);
+            }
+        }
+        // attaching by pid joins the user namespace of the process as well
+        args.push(pid.to_string());
+
+        args
+    }
+
+    fn start_slirp4netns(&self, container_root: &Path, pid: Pid) -> Result<Pid> {
+        let api_socket = container_root.join(SLIRP4NETNS_API_SOCKET);
+        let mut command = Command::new("slirp4netns");
+        command
+            .args(["--configure", "--mtu=65520", "--disable-host-loopback"])
+            .arg(format!("--api-socket={}", api_socket.display()));
+        if has_own_userns(pid)? {
+            command.arg(format!("--userns-path=/proc/{pid}/ns/user"));
+        }
+        let child = command
+            .arg(pid.to_string())
+            .arg("tap0")
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            // keep it out of the way of signals sent to the terminal
+            .process_group(0)
+            .spawn()
+            .context("failed to start slirp4netns")?;
+        let driver_pid = Pid::from_raw(child.id() as i32);
+
+        if let Err(err) = self.forward_slirp4netns_ports(&api_socket) {
+            stop(Driver::Slirp4netns, driver_pid);
+            return Err(err);
+        }
+
+        Ok(driver_pid)
+    }
+
+    fn forward_slirp4netns_ports(&self, api_socket: &Path) -> Result<()> {
+        let started = Instant::now();
+        while UnixStream::connect(api_socket).is_err() {
+            if started.elapsed() > SLIRP4NETNS_READY_TIMEOUT {
+                bail!("timed out waiting for slirp4netns to become ready");
+            }
+            thread::sleep(Duration::from_millis(20));
+        }
+
+        for port in &self.ports {
+            let response = slirp4netns_request(api_socket, &hostfwd_request(port))?;
+            if let Some(error) = response.get("error") {
+                bail!(
+                    "slirp4netns failed to publish port {}: {}",
+                    port.host_port,
+                    error["desc"].as_str().unwrap_or("unknown error")
+                );
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn hostfwd_request(port: &PortMapping) -> Value {
+    json!({
+        "execute": "add_hostfwd",
+        "arguments": {
+            "proto": port.protocol.to_string(),
+            "host_addr": port.host_ip.map_or("0.0.0.0".to_owned(), |ip| ip.to_string()),
+            "host_port": port.host_port,
+            "guest_port": port.container_port,
+        }
+    })
+}
+
+fn slirp4netns_request(api_socket: &Path, request: &Value) -> Result<Value> {
+    let mut stream = UnixStream::connect(api_socket)
+        .with_context(|| format!("failed to connect to {}", api_socket.display()))?;
+    stream.write_all(request.to_string().as_bytes())?;
+    // slirp4netns answers once the request is complete
+    stream.shutdown(Shutdown::Write)?;
+    let mut response = String::new();
+    stream.read_to_string(&mut response)?;
+
+    serde_json::from_str(&response).context("invalid response from slirp4netns")
+}
+
+fn has_own_userns(pid: Pid) -> Result<bool> {
+    let namespace = |path: PathBuf| {
+        fs::read_link(&path).with_context(|| format!("failed to read {}", path.display()))
+    };
+    Ok(namespace(PathBuf::from(format!("/proc/{pid}/ns/user")))?
+        != namespace(PathBuf::from("/proc/self/ns/user"))?)
+}
+
+/// Stops the user-mode networking of the container, if it has any
+pub fn teardown(container_root: &Path) -> Result<()> {
+    let state_file = container_root.join(STATE_FILE);
+    let state = match fs::read_to_string(&state_file) {
+        Ok(state) => state,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(err) => {
+            return Err(err).with_context(|| format!("failed to read {}", state_file.display()))
+        }
+    };
+    let state: Value = serde_json::from_str(&state)
+        .with_context(|| format!("failed to parse {}", state_file.display()))?;
+    let driver: Driver = state["driver"]
+        .as_str()
+        .context("missing driver in user-mode network state")?
+        .parse()?;
+    let pid = state["pid"]
+        .as_i64()
+        .context("missing pid in user-mode network state")?;
+
+    stop(driver, Pid::from_raw(pid as i32));
+    fs::remove_file(&state_file)
+        .with_context(|| format!("failed to remove {}", state_file.display()))
+}
+
+fn stop(driver: Driver, pid: Pid) {
+    // the pid may have been reused since the driver exited on its own
+    let comm = fs::read_to_string(format!("/proc/{pid}/comm")).unwrap_or_default();
+    if comm.trim() != driver.to_string() {
+        return;
+    }
+
+    match kill(pid, Signal::SIGTERM) {
+        Ok(()) | Err(Errno::ESRCH) => {}
+        Err(err) => tracing::warn!(?err, %driver, ?pid, "failed to stop user-mode networking"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    use super::*;
+
+    #[test]
+    fn test_parse_port_mapping() -> Result<()> {
+        assert_eq!(
+            "8080:80".parse::<PortMapping>()?,
+            PortMapping {
+                host_ip: None,
+                host_port: 8080,
+                container_port: 80,
+                protocol: Protocol::Tcp,
+            }
+        );
+        assert_eq!(
+            "127.0.0.1:5353:53/udp".parse::<PortMapping>()?,
+            PortMapping {
+                host_ip: Some(IpAddr::V4(Ipv4Addr::LOCALHOST)),
+                host_port: 5353,
+                container_port: 53,
+                protocol: Protocol::Udp,
+            }
+        );
+        assert_eq!(
+            "[::1]:8443:443/tcp".parse::<PortMapping>()?.host_ip,
+            Some(IpAddr::V6(Ipv6Addr::LOCALHOST))
+        );
+
+        for invalid in ["80", "0:80", "8080:80/sctp", "::1:8080:80", "x:8080:80"] {
+            assert!(invalid.parse::<PortMapping>().is_err(), "{invalid}");
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_resolve() -> Result<()> {
+        assert_eq!(UserNet::resolve(None, &[], None)?, None);
+        assert!(UserNet::resolve(None, &["8080:80".to_owned()], None).is_err());
+
+        let annotations = HashMap::from([
+            (MODE_ANNOTATION.to_owned(), "slirp4netns".to_owned()),
+            (
+                PORTS_ANNOTATION.to_owned(),
+                "8080:80, 5353:53/udp".to_owned(),
+            ),
+        ]);
+        let usernet = UserNet::resolve(None, &[], Some(&annotations))?.unwrap();
+        assert_eq!(usernet.driver, Driver::Slirp4netns);
+        assert_eq!(usernet.ports.len(), 2);
+
+        // flags take precedence over annotations
+        let usernet = UserNet::resolve(
+            Some("pasta"),
+            &["[::1]:80:80".to_owned()],
+            Some(&annotations),
+        )?
+        .unwrap();
+        assert_eq!(usernet.driver, Driver::Pasta);
+        assert_eq!(usernet.ports.len(), 1);
+
+        assert!(UserNet::resolve(Some("slirp4netns"), &["[::1]:80:80".to_owned()], None).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_pasta_args() -> Result<()> {
+        let usernet = UserNet::resolve(
+            Some("pasta"),
+            &["8080:80".to_owned(), "[::1]:8443:443".to_owned()],
+            None,
+        )?
+        .unwrap();
+        let args = usernet.pasta_args(Path::new("/run/youki/c/pasta.pid"), Pid::from_raw(42));
+        assert_eq!(
+            args,
+            [
+                "--config-net",
+                "--quiet",
+                "--pid",
+                "/run/youki/c/pasta.pid",
+                "--tcp-ports",
+                "8080:80",
+                "--tcp-ports",
+                "::1/8443:443",
+                "--udp-ports",
+                "none",
+                "42",
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_hostfwd_request() -> Result<()> {
+        let request = hostfwd_request(&"5353:53/udp".parse()?);
+        assert_eq!(request["execute"], "add_hostfwd");
+        assert_eq!(request["arguments"]["proto"], "udp");
+        assert_eq!(request["arguments"]["host_addr"], "0.0.0.0");
+        assert_eq!(request["arguments"]["host_port"], 5353);
+        assert_eq!(request["arguments"]["guest_port"], 53);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_teardown_without_state() -> Result<()> {
+        let tmp = tempfile::tempdir()?;
+        teardown(tmp.path())
+    }
+}
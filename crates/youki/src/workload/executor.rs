@@ -1,11 +1,41 @@
 use libcontainer::oci_spec::runtime::Spec;
+use libcontainer::workload::pause::PauseExecutor;
+use libcontainer::workload::vm::VmExecutor;
 use libcontainer::workload::{Executor, ExecutorError, ExecutorValidationError};
 
+/// Environment variable with the command template of the VMM which runs
+/// containers with a `vm` section, see [`libcontainer::workload::vm`]
+pub const VMM_COMMAND_ENV: &str = "YOUKI_VMM_COMMAND";
+
 #[derive(Clone)]
-pub struct DefaultExecutor {}
+pub struct DefaultExecutor {
+    pause: PauseExecutor,
+    vm: VmExecutor,
+}
+
+impl DefaultExecutor {
+    /// Runs the pause helper of libcontainer in place of the process of
+    /// every spec, see [`libcontainer::workload::pause`]
+    pub fn with_builtin_pause(mut self, builtin_pause: bool) -> Self {
+        if builtin_pause {
+            self.pause = PauseExecutor::forced();
+        }
+        self
+    }
+}
 
 impl Executor for DefaultExecutor {
     fn exec(&self, spec: &Spec) -> Result<(), ExecutorError> {
+        match self.pause.exec(spec) {
+            Ok(_) => return Ok(()),
+            Err(ExecutorError::CantHandle(_)) => (),
+            Err(err) => return Err(err),
+        }
+        match self.vm.exec(spec) {
+            Ok(_) => return Ok(()),
+            Err(ExecutorError::CantHandle(_)) => (),
+            Err(err) => return Err(err),
+        }
         #[cfg(feature = "wasm-wasmer")]
         match super::wasmer::get_executor().exec(spec) {
             Ok(_) => return Ok(()),
@@ -31,6 +61,16 @@ impl Executor for DefaultExecutor {
     }
 
     fn validate(&self, spec: &Spec) -> Result<(), ExecutorValidationError> {
+        match self.pause.validate(spec) {
+            Ok(_) => return Ok(()),
+            Err(ExecutorValidationError::CantHandle(_)) => (),
+            Err(err) => return Err(err),
+        }
+        match self.vm.validate(spec) {
+            Ok(_) => return Ok(()),
+            Err(ExecutorValidationError::CantHandle(_)) => (),
+            Err(err) => return Err(err),
+        }
         #[cfg(feature = "wasm-wasmer")]
         match super::wasmer::get_executor().validate(spec) {
             Ok(_) => return Ok(()),
@@ -55,5 +95,8 @@ impl Executor for DefaultExecutor {
 }
 
 pub fn default_executor() -> DefaultExecutor {
-    DefaultExecutor {}
+    DefaultExecutor {
+        pause: PauseExecutor::new(),
+        vm: VmExecutor::new(std::env::var(VMM_COMMAND_ENV).ok()),
+    }
 }
@@ -9,15 +9,20 @@ use contest::logger;
 use test_framework::TestManager;
 use tests::cgroups;
 
+use crate::tests::builtin_pause::get_builtin_pause_test;
+use crate::tests::capabilities::get_capabilities_test;
+use crate::tests::checkpoint_restore::get_checkpoint_restore_test;
 use crate::tests::devices::get_devices_test;
 use crate::tests::domainname::get_domainname_tests;
 use crate::tests::example::get_example_test;
+use crate::tests::fault_injection::get_fault_injection_test;
 use crate::tests::hooks::get_hooks_tests;
 use crate::tests::hostname::get_hostname_test;
 use crate::tests::intel_rdt::get_intel_rdt_test;
 use crate::tests::io_priority::get_io_priority_test;
 use crate::tests::lifecycle::{ContainerCreate, ContainerLifecycle};
 use crate::tests::linux_ns_itype::get_ns_itype_tests;
+use crate::tests::masked_paths::get_masked_paths_test;
 use crate::tests::mounts_recursive::get_mounts_recursive_test;
 use crate::tests::no_pivot::get_no_pivot_test;
 use crate::tests::pidfile::get_pidfile_test;
@@ -125,6 +130,11 @@ fn main() -> Result<()> {
     let process_rlimtis = get_process_rlimits_test();
     let no_pivot = get_no_pivot_test();
     let process_oom_score_adj = get_process_oom_score_adj_test();
+    let capabilities = get_capabilities_test();
+    let masked_paths = get_masked_paths_test();
+    let fault_injection = get_fault_injection_test();
+    let checkpoint_restore = get_checkpoint_restore_test();
+    let builtin_pause = get_builtin_pause_test();
 
     tm.add_test_group(Box::new(cl));
     tm.add_test_group(Box::new(cc));
@@ -154,6 +164,11 @@ fn main() -> Result<()> {
     tm.add_test_group(Box::new(process_rlimtis));
     tm.add_test_group(Box::new(no_pivot));
     tm.add_test_group(Box::new(process_oom_score_adj));
+    tm.add_test_group(Box::new(capabilities));
+    tm.add_test_group(Box::new(masked_paths));
+    tm.add_test_group(Box::new(fault_injection));
+    tm.add_test_group(Box::new(checkpoint_restore));
+    tm.add_test_group(Box::new(builtin_pause));
 
     tm.add_test_group(Box::new(io_priority_test));
     tm.add_cleanup(Box::new(cgroups::cleanup_v1));
@@ -0,0 +1,108 @@
+use std::path::Path;
+use std::thread::sleep;
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+use libcontainer::workload::pause::BUILTIN_PAUSE;
+use oci_spec::runtime::{ProcessBuilder, Spec};
+use test_framework::{test_result, ConditionalTest, TestGroup, TestResult};
+
+use crate::utils::test_utils::{start_container, CreateOptions};
+use crate::utils::{
+    create_container, delete_container, generate_uuid, get_state, is_runtime_runc, kill_container,
+    prepare_bundle, set_config, State,
+};
+
+// The pause helper is built into youki only, for these architectures
+fn is_supported() -> bool {
+    !is_runtime_runc() && cfg!(any(target_arch = "x86_64", target_arch = "aarch64"))
+}
+
+fn create_spec(args: &[&str]) -> Result<Spec> {
+    let mut spec = Spec::default();
+    spec.set_process(Some(
+        ProcessBuilder::default()
+            .args(args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>())
+            .build()
+            .context("failed to build process spec")?,
+    ));
+
+    Ok(spec)
+}
+
+fn run_container(id: &str, bundle: &Path, args: &[&str]) -> Result<()> {
+    set_config(bundle, &create_spec(args)?)?;
+    create_container(id, bundle, &CreateOptions::default())?.wait()?;
+    start_container(id, bundle)?.wait()?;
+
+    Ok(())
+}
+
+fn container_status(id: &str, bundle: &Path) -> Result<String> {
+    let (out, err) = get_state(id, bundle)?;
+    let state: State = serde_json::from_str(&out)
+        .with_context(|| format!("failed to parse state {out:?} {err:?}"))?;
+
+    Ok(state.status)
+}
+
+fn cleanup(id: &str, bundle: &Path) {
+    let _ = kill_container(id, bundle).and_then(|mut c| Ok(c.wait()?));
+    let _ = delete_container(id, bundle).and_then(|mut c| Ok(c.wait()?));
+}
+
+// Without an argument the helper pauses until it is killed
+fn check_pause(id: &str, bundle: &Path) -> Result<()> {
+    run_container(id, bundle, &[BUILTIN_PAUSE])?;
+    let status = container_status(id, bundle)?;
+    if status != "running" {
+        bail!("expected the container to be running, got {status}");
+    }
+
+    kill_container(id, bundle)?.wait()?;
+    let status = container_status(id, bundle)?;
+    if status != "stopped" {
+        bail!("expected the container to be stopped after the kill, got {status}");
+    }
+
+    Ok(())
+}
+
+// With a number of seconds the helper exits once they passed
+fn check_sleep(id: &str, bundle: &Path) -> Result<()> {
+    run_container(id, bundle, &[BUILTIN_PAUSE, "1"])?;
+    sleep(Duration::from_secs(2));
+    let status = container_status(id, bundle)?;
+    if status != "stopped" {
+        bail!("expected the container to be stopped after its sleep, got {status}");
+    }
+
+    Ok(())
+}
+
+fn test_builtin_pause(check: fn(&str, &Path) -> Result<()>) -> TestResult {
+    let id = generate_uuid().to_string();
+    let bundle = test_result!(prepare_bundle());
+    let result = check(&id, bundle.path());
+    cleanup(&id, bundle.path());
+    test_result!(result);
+
+    TestResult::Passed
+}
+
+pub fn get_builtin_pause_test() -> TestGroup {
+    let pause = ConditionalTest::new(
+        "builtin_pause",
+        Box::new(is_supported),
+        Box::new(|| test_builtin_pause(check_pause)),
+    );
+    let sleep = ConditionalTest::new(
+        "builtin_pause_sleep",
+        Box::new(is_supported),
+        Box::new(|| test_builtin_pause(check_sleep)),
+    );
+
+    let mut tg = TestGroup::new("builtin_pause");
+    tg.add(vec![Box::new(pause), Box::new(sleep)]);
+    tg
+}
@@ -0,0 +1,2 @@
+mod builtin_pause_test;
+pub use builtin_pause_test::get_builtin_pause_test;
@@ -0,0 +1,58 @@
+use std::collections::HashSet;
+
+use anyhow::{Context, Result};
+use oci_spec::runtime::{Capability, LinuxCapabilitiesBuilder, ProcessBuilder, Spec, SpecBuilder};
+use test_framework::{test_result, Test, TestGroup, TestResult};
+
+use crate::utils::test_inside_container;
+use crate::utils::test_utils::CreateOptions;
+
+fn create_spec() -> Result<Spec> {
+    // The permitted and effective sets equal the bounding set, so the
+    // effective set of the container process is the same after exec.
+    let capabilities: HashSet<Capability> = [
+        Capability::Chown,
+        Capability::Kill,
+        Capability::NetBindService,
+        Capability::Setgid,
+        Capability::Setuid,
+    ]
+    .into_iter()
+    .collect();
+
+    let spec = SpecBuilder::default()
+        .process(
+            ProcessBuilder::default()
+                .args(vec!["runtimetest".to_string(), "capabilities".to_string()])
+                .capabilities(
+                    LinuxCapabilitiesBuilder::default()
+                        .bounding(capabilities.clone())
+                        .effective(capabilities.clone())
+                        .permitted(capabilities)
+                        .inheritable(HashSet::new())
+                        .ambient(HashSet::new())
+                        .build()
+                        .context("failed to build capabilities")?,
+                )
+                .build()
+                .context("failed to build process spec")?,
+        )
+        .build()
+        .context("failed to build spec")?;
+
+    Ok(spec)
+}
+
+fn capabilities_test() -> TestResult {
+    let spec = test_result!(create_spec());
+    test_inside_container(spec, &CreateOptions::default(), &|_| Ok(()))
+}
+
+pub fn get_capabilities_test() -> TestGroup {
+    let mut capabilities_test_group = TestGroup::new("capabilities");
+
+    let test = Test::new("capabilities_test", Box::new(capabilities_test));
+    capabilities_test_group.add(vec![Box::new(test)]);
+
+    capabilities_test_group
+}
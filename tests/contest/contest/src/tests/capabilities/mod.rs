@@ -0,0 +1,2 @@
+mod capabilities_test;
+pub use capabilities_test::get_capabilities_test;
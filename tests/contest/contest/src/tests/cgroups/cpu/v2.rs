@@ -1,11 +1,13 @@
 use std::fs;
 use std::path::{Path, PathBuf};
 
-use anyhow::{bail, Context, Result};
+use anyhow::{anyhow, bail, Context, Result};
 use libcgroups::common::{self, CgroupSetup, DEFAULT_CGROUP_ROOT};
 use libcgroups::v2::controller_type::ControllerType;
 use libcontainer::utils::PathBufExt;
-use oci_spec::runtime::{LinuxCpuBuilder, Spec};
+use oci_spec::runtime::{
+    LinuxCpuBuilder, LinuxSchedulerPolicy, ProcessBuilder, SchedulerBuilder, Spec,
+};
 use test_framework::{assert_result_eq, test_result, ConditionalTest, TestGroup, TestResult};
 use tracing::debug;
 
@@ -55,6 +57,56 @@ fn test_cpu_idle_default() -> TestResult {
     })
 }
 
+/// Tests if a cpu idle value other than 0 or 1 is rejected
+fn test_cpu_idle_invalid() -> TestResult {
+    let cpu = test_result!(LinuxCpuBuilder::default()
+        .idle(2i64)
+        .build()
+        .context("build cpu spec"));
+
+    let spec = test_result!(create_spec("test_cpu_idle_invalid", cpu));
+    test_outside_container(spec, &|data| match data.create_result {
+        Err(e) => TestResult::Failed(anyhow!(e)),
+        Ok(res) if res.success() => {
+            TestResult::Failed(anyhow!("invalid cpu idle value 2 was allowed"))
+        }
+        Ok(_) => TestResult::Passed,
+    })
+}
+
+/// Tests if an idle cgroup can be combined with the SCHED_IDLE policy for
+/// the container process
+fn test_cpu_idle_sched_idle() -> TestResult {
+    let idle: i64 = 1;
+    let spec = test_result!(create_idle_spec(
+        "test_cpu_idle_sched_idle",
+        idle,
+        LinuxSchedulerPolicy::SchedIdle
+    ));
+    test_outside_container(spec, &|data| {
+        test_result!(check_container_created(&data));
+        test_result!(check_cpu_idle("test_cpu_idle_sched_idle", idle));
+        TestResult::Passed
+    })
+}
+
+/// Tests if an idle cgroup combined with a realtime policy for the container
+/// process is rejected
+fn test_cpu_idle_sched_fifo_rejected() -> TestResult {
+    let spec = test_result!(create_idle_spec(
+        "test_cpu_idle_sched_fifo_rejected",
+        1,
+        LinuxSchedulerPolicy::SchedFifo
+    ));
+    test_outside_container(spec, &|data| match data.create_result {
+        Err(e) => TestResult::Failed(anyhow!(e)),
+        Ok(res) if res.success() => TestResult::Failed(anyhow!(
+            "realtime scheduler policy in an idle cgroup was allowed"
+        )),
+        Ok(_) => TestResult::Passed,
+    })
+}
+
 /// Tests if a cpu weight that is in the valid range [1, 10000] is successfully set
 fn test_cpu_weight_valid_set() -> TestResult {
     let cpu_weight = 22_000u64;
@@ -271,6 +323,25 @@ fn check_cpu_idle(cgroup_name: &str, expected_value: i64) -> Result<()> {
     )
 }
 
+fn create_idle_spec(cgroup_name: &str, idle: i64, policy: LinuxSchedulerPolicy) -> Result<Spec> {
+    let cpu = LinuxCpuBuilder::default()
+        .idle(idle)
+        .build()
+        .context("build cpu spec")?;
+    let mut spec = create_spec(cgroup_name, cpu)?;
+    let scheduler = SchedulerBuilder::default()
+        .policy(policy)
+        .build()
+        .context("build scheduler spec")?;
+    spec.set_process(Some(
+        ProcessBuilder::default()
+            .scheduler(scheduler)
+            .build()
+            .context("build process spec")?,
+    ));
+    Ok(spec)
+}
+
 fn check_cpu_max(cgroup_name: &str, expected_quota: i64, expected_period: u64) -> Result<()> {
     let data = read_cgroup_data(cgroup_name, "cpu.max")?;
     let parts: Vec<&str> = data.split_whitespace().collect();
@@ -443,6 +514,24 @@ pub fn get_test_group() -> TestGroup {
         Box::new(test_cpu_idle_default),
     );
 
+    let test_cpu_idle_invalid = ConditionalTest::new(
+        "test_cpu_idle_invalid",
+        Box::new(can_run_idle),
+        Box::new(test_cpu_idle_invalid),
+    );
+
+    let test_cpu_idle_sched_idle = ConditionalTest::new(
+        "test_cpu_idle_sched_idle",
+        Box::new(can_run_idle),
+        Box::new(test_cpu_idle_sched_idle),
+    );
+
+    let test_cpu_idle_sched_fifo_rejected = ConditionalTest::new(
+        "test_cpu_idle_sched_fifo_rejected",
+        Box::new(can_run_idle),
+        Box::new(test_cpu_idle_sched_fifo_rejected),
+    );
+
     test_group.add(vec![
         Box::new(test_cpu_weight_valid_set),
         Box::new(test_cpu_weight_zero_ignored),
@@ -455,6 +544,9 @@ pub fn get_test_group() -> TestGroup {
         Box::new(test_cpu_period_and_quota_valid_set),
         Box::new(test_cpu_idle_set),
         Box::new(test_cpu_idle_default),
+        Box::new(test_cpu_idle_invalid),
+        Box::new(test_cpu_idle_sched_idle),
+        Box::new(test_cpu_idle_sched_fifo_rejected),
     ]);
     test_group
 }
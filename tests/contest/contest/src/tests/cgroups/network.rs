@@ -1,6 +1,7 @@
-use std::path::Path;
+use std::fs;
+use std::path::{Path, PathBuf};
 
-use anyhow::{anyhow, Context, Result};
+use anyhow::{anyhow, bail, Context, Result};
 use oci_spec::runtime::{
     LinuxBuilder, LinuxInterfacePriorityBuilder, LinuxNamespace, LinuxNamespaceType,
     LinuxNetworkBuilder, LinuxResourcesBuilder, Spec, SpecBuilder,
@@ -9,7 +10,10 @@ use pnet_datalink::interfaces;
 use test_framework::{test_result, ConditionalTest, TestGroup, TestResult};
 
 use crate::utils::test_outside_container;
-use crate::utils::test_utils::check_container_created;
+use crate::utils::test_utils::{check_container_created, CGROUP_ROOT};
+
+// net_cls and net_prio are often co-mounted, in either order
+const CONTROLLER_DIRS: &[&str] = &["net_cls,net_prio", "net_prio,net_cls"];
 
 fn create_spec(
     cgroup_name: &str,
@@ -115,8 +119,9 @@ fn test_network_cgroups() -> TestResult {
     ];
 
     for spec in cases.into_iter() {
-        let test_result = test_outside_container(spec, &|data| {
+        let test_result = test_outside_container(spec.clone(), &|data| {
             test_result!(check_container_created(&data));
+            test_result!(check_network_set(cgroup_name, &spec));
 
             TestResult::Passed
         });
@@ -128,17 +133,61 @@ fn test_network_cgroups() -> TestResult {
     TestResult::Passed
 }
 
+/// Directory of the hierarchy of the controller, with the file `file`
+fn controller_dir(controller: &str, file: &str) -> Option<PathBuf> {
+    std::iter::once(controller)
+        .chain(CONTROLLER_DIRS.iter().copied())
+        .map(|dir| PathBuf::from(CGROUP_ROOT).join(dir))
+        .find(|dir| dir.join(file).exists())
+}
+
+fn check_network_set(cgroup_name: &str, spec: &Spec) -> Result<()> {
+    let network = spec
+        .linux()
+        .as_ref()
+        .and_then(|linux| linux.resources().as_ref())
+        .and_then(|resources| resources.network().as_ref())
+        .context("spec has no network resources")?;
+    let cgroup = Path::new("runtime-test").join(cgroup_name);
+
+    if let Some(expected) = network.class_id() {
+        let dir = controller_dir("net_cls", "net_cls.classid")
+            .context("net_cls controller is not mounted")?;
+        let path = dir.join(&cgroup).join("net_cls.classid");
+        let content =
+            fs::read_to_string(&path).with_context(|| format!("failed to read {path:?}"))?;
+        let actual: u32 = content
+            .trim()
+            .parse()
+            .with_context(|| format!("could not parse {content:?}"))?;
+        if actual != expected {
+            bail!("expected {path:?} to contain the class id {expected}, but it was {actual}");
+        }
+    }
+
+    if let Some(priorities) = network.priorities() {
+        let dir = controller_dir("net_prio", "net_prio.ifpriomap")
+            .context("net_prio controller is not mounted")?;
+        let path = dir.join(&cgroup).join("net_prio.ifpriomap");
+        let content =
+            fs::read_to_string(&path).with_context(|| format!("failed to read {path:?}"))?;
+        for priority in priorities {
+            let expected = format!("{} {}", priority.name(), priority.priority());
+            if !content.lines().any(|line| line.trim() == expected) {
+                bail!("expected {path:?} to contain {expected:?}, but it was {content:?}");
+            }
+        }
+    }
+
+    Ok(())
+}
+
 fn can_run() -> bool {
     // Ensure the expected network interfaces exist on the system running the test
     let iface_exists = get_network_interfaces().is_some();
 
-    // This is kind of annoying, network controller can be at a number of mount points
-    let cgroup_paths_exists = (Path::new("/sys/fs/cgroup/net_cls/net_cls.classid").exists()
-        && Path::new("/sys/fs/cgroup/net_prio/net_prio.ifpriomap").exists())
-        || (Path::new("/sys/fs/cgroup/net_cls,net_prio/net_cls.classid").exists()
-            && Path::new("/sys/fs/cgroup/net_cls,net_prio/net_prio.ifpriomap").exists())
-        || (Path::new("/sys/fs/cgroup/net_prio,net_cls/net_cls.classid").exists()
-            && Path::new("/sys/fs/cgroup/net_prio,net_cl/net_prio.ifpriomap").exists());
+    let cgroup_paths_exists = controller_dir("net_cls", "net_cls.classid").is_some()
+        && controller_dir("net_prio", "net_prio.ifpriomap").is_some();
 
     iface_exists && cgroup_paths_exists
 }
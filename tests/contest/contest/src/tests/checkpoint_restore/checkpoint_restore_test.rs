@@ -0,0 +1,204 @@
+use std::path::Path;
+use std::process::{Command, Output, Stdio};
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Context, Result};
+use oci_spec::runtime::{LinuxNamespaceType, ProcessBuilder, Spec};
+use test_framework::TestResult;
+
+use crate::utils::{
+    delete_container, generate_uuid, get_runtime_path, get_state, kill_container, prepare_bundle,
+    set_config, State,
+};
+
+const COUNTER_FILE: &str = "counter";
+/// Count reached before the checkpoint
+const MIN_COUNT: u64 = 20;
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Needs CRIU and a runtime which can restore
+pub fn can_run() -> bool {
+    which::which("criu").is_ok()
+        && Command::new(get_runtime_path())
+            .args(["restore", "--help"])
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .map_or(false, |status| status.success())
+}
+
+fn runtime(project_path: &Path) -> Command {
+    let mut command = Command::new(get_runtime_path());
+    command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .arg("--root")
+        .arg(project_path.join("runtime"));
+    command
+}
+
+fn check_output(output: std::io::Result<Output>, command: &str) -> Result<()> {
+    let output = output.with_context(|| format!("failed to run {command}"))?;
+    if !output.status.success() {
+        bail!(
+            "{command} failed with {}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+    Ok(())
+}
+
+fn create_spec() -> Result<Spec> {
+    let mut spec = Spec::default();
+    // counts in memory, so that a restarted process starts over
+    let process = ProcessBuilder::default()
+        .args(vec![
+            "sh".to_owned(),
+            "-c".to_owned(),
+            format!("i=0; while true; do i=$((i+1)); echo $i > /{COUNTER_FILE}; sleep 0.1; done"),
+        ])
+        .build()?;
+    spec.set_process(Some(process));
+
+    if let Some(root) = spec.root().as_ref() {
+        let mut root = root.clone();
+        root.set_readonly(Some(false));
+        spec.set_root(Some(root));
+    }
+    // CRIU would need the loopback device to be set up in a network namespace
+    if let Some(linux) = spec.linux().as_ref() {
+        let mut linux = linux.clone();
+        let namespaces = linux
+            .namespaces()
+            .iter()
+            .flatten()
+            .filter(|ns| ns.typ() != LinuxNamespaceType::Network)
+            .cloned()
+            .collect();
+        linux.set_namespaces(Some(namespaces));
+        spec.set_linux(Some(linux));
+    }
+
+    Ok(spec)
+}
+
+fn read_counter(counter_file: &Path) -> Option<u64> {
+    std::fs::read_to_string(counter_file)
+        .ok()?
+        .trim()
+        .parse()
+        .ok()
+}
+
+/// Waits until the counter reaches the value
+fn wait_for_counter(counter_file: &Path, value: u64) -> Result<u64> {
+    let start = Instant::now();
+    loop {
+        match read_counter(counter_file) {
+            Some(counter) if counter >= value => return Ok(counter),
+            _ if start.elapsed() > TIMEOUT => {
+                bail!("counter did not reach {value} within {TIMEOUT:?}")
+            }
+            _ => sleep(Duration::from_millis(100)),
+        }
+    }
+}
+
+fn container_state(project_path: &Path, id: &str) -> Result<State> {
+    let (out, err) = get_state(id, project_path)?;
+    serde_json::from_str(&out).with_context(|| format!("failed to parse state {out:?}: {err}"))
+}
+
+fn checkpoint_restore(project_path: &Path, id: &str) -> Result<()> {
+    let bundle = project_path.join("bundle");
+    let counter_file = bundle.join("rootfs").join(COUNTER_FILE);
+    set_config(project_path, &create_spec()?)?;
+
+    check_output(
+        runtime(project_path)
+            .args(["create", id, "--bundle"])
+            .arg(&bundle)
+            .output(),
+        "create",
+    )?;
+    check_output(runtime(project_path).args(["start", id]).output(), "start")?;
+    wait_for_counter(&counter_file, MIN_COUNT)?;
+
+    let images = tempfile::tempdir()?;
+    let checkpoint = if get_runtime_path().ends_with("youki") {
+        "checkpointt"
+    } else {
+        "checkpoint"
+    };
+    check_output(
+        runtime(project_path)
+            .args([checkpoint, "--image-path"])
+            .arg(images.path())
+            .arg(id)
+            .output(),
+        "checkpoint",
+    )?;
+
+    // the process does not run anymore
+    let checkpointed = read_counter(&counter_file).context("counter file is missing")?;
+    sleep(Duration::from_millis(500));
+    if read_counter(&counter_file) != Some(checkpointed) {
+        bail!("counter changed after the checkpoint, the process still runs");
+    }
+
+    check_output(
+        runtime(project_path)
+            .args(["restore", "--detach", "--image-path"])
+            .arg(images.path())
+            .arg("--bundle")
+            .arg(&bundle)
+            .arg(id)
+            .output(),
+        "restore",
+    )?;
+    let state = container_state(project_path, id)?;
+    if state.status != "running" {
+        bail!(
+            "expected the restored container to run, got {}",
+            state.status
+        );
+    }
+
+    // a restarted process counts from 1 again
+    let start = Instant::now();
+    loop {
+        match read_counter(&counter_file) {
+            Some(counter) if counter < checkpointed => {
+                bail!("counter went back from {checkpointed} to {counter}, the process restarted")
+            }
+            Some(counter) if counter > checkpointed => break,
+            _ if start.elapsed() > TIMEOUT => {
+                bail!("counter did not continue from {checkpointed} within {TIMEOUT:?}")
+            }
+            _ => sleep(Duration::from_millis(100)),
+        }
+    }
+
+    Ok(())
+}
+
+pub fn test_checkpoint_restore() -> TestResult {
+    let id = generate_uuid().to_string();
+    let project_path = match prepare_bundle() {
+        Ok(project_path) => project_path,
+        Err(err) => return TestResult::Failed(err),
+    };
+
+    let result = checkpoint_restore(project_path.path(), &id);
+
+    let _ = kill_container(&id, &project_path).and_then(|mut child| Ok(child.wait()?));
+    let _ = delete_container(&id, &project_path).and_then(|mut child| Ok(child.wait()?));
+
+    match result {
+        Ok(()) => TestResult::Passed,
+        Err(err) => TestResult::Failed(err),
+    }
+}
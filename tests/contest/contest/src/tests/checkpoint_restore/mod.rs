@@ -0,0 +1,18 @@
+use test_framework::{ConditionalTest, TestGroup};
+
+use self::checkpoint_restore_test::{can_run, test_checkpoint_restore};
+
+mod checkpoint_restore_test;
+
+pub fn get_checkpoint_restore_test() -> TestGroup {
+    let mut test_group = TestGroup::new("checkpoint_restore");
+    let checkpoint_restore = ConditionalTest::new(
+        "checkpoint_restore",
+        Box::new(can_run),
+        Box::new(test_checkpoint_restore),
+    );
+
+    test_group.add(vec![Box::new(checkpoint_restore)]);
+
+    test_group
+}
@@ -0,0 +1,155 @@
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus, Stdio};
+
+use anyhow::{bail, Context, Result};
+use libcontainer::workload::pause::BUILTIN_PAUSE;
+use oci_spec::runtime::{ProcessBuilder, Spec};
+use test_framework::{test_result, Test, TestGroup, TestResult};
+
+use crate::tests::cgroups::list_subsystem_mount_points;
+use crate::utils::test_utils::{start_container, CGROUP_ROOT};
+use crate::utils::{
+    delete_container, generate_uuid, get_runtime_path, get_state, kill_container, prepare_bundle,
+    set_config, State,
+};
+
+// The runtime has to be built with fault injection for these tests, a
+// runtime without it ignores the variable and the tests are skipped.
+const FAULT_INJECTION_ENV: &str = "YOUKI_FAULT_INJECTION";
+
+fn create_spec(cgroup_name: &str) -> Result<Spec> {
+    let mut spec = Spec::default();
+    spec.set_process(Some(
+        ProcessBuilder::default()
+            .args(vec![BUILTIN_PAUSE.to_string(), "10".to_string()])
+            .build()
+            .context("failed to build process spec")?,
+    ));
+    let mut linux = spec.linux().clone().context("default spec has no linux")?;
+    linux.set_cgroups_path(Some(Path::new("/runtime-test").join(cgroup_name)));
+    spec.set_linux(Some(linux));
+
+    Ok(spec)
+}
+
+fn create_with_fault(id: &str, bundle: &Path, fault_point: &str) -> Result<ExitStatus> {
+    Command::new(get_runtime_path())
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .env(FAULT_INJECTION_ENV, fault_point)
+        .arg("--root")
+        .arg(bundle.join("runtime"))
+        .arg("create")
+        .arg(id)
+        .arg("--bundle")
+        .arg(bundle.join("bundle"))
+        .status()
+        .context("could not create container")
+}
+
+fn cleanup(id: &str, bundle: &Path) {
+    let _ = kill_container(id, bundle).and_then(|mut c| Ok(c.wait()?));
+    let _ = delete_container(id, bundle).and_then(|mut c| Ok(c.wait()?));
+}
+
+// The cgroup of the container, wherever it would be with either cgroup version
+fn cgroup_paths(cgroup_name: &str) -> Result<Vec<PathBuf>> {
+    let relative = Path::new("runtime-test").join(cgroup_name);
+    let mut paths: Vec<PathBuf> = list_subsystem_mount_points()?
+        .into_iter()
+        .map(|mount_point| mount_point.join(&relative))
+        .collect();
+    paths.push(Path::new(CGROUP_ROOT).join(&relative));
+
+    Ok(paths)
+}
+
+fn check_cleaned_up(id: &str, bundle: &Path) -> Result<()> {
+    let state_dir = bundle.join("runtime").join(id);
+    if state_dir.exists() {
+        bail!("state directory {state_dir:?} was left behind");
+    }
+
+    for path in cgroup_paths(id)? {
+        if path.exists() {
+            bail!("cgroup {path:?} was left behind");
+        }
+    }
+
+    Ok(())
+}
+
+// A failure while the container is created must not leave anything behind
+fn test_create_failure(fault_point: &str) -> TestResult {
+    let id = generate_uuid().to_string();
+    let bundle = test_result!(prepare_bundle());
+    let spec = test_result!(create_spec(&id));
+    test_result!(set_config(&bundle, &spec));
+
+    let status = test_result!(create_with_fault(&id, bundle.path(), fault_point));
+    if status.success() {
+        cleanup(&id, bundle.path());
+        return TestResult::Skipped;
+    }
+
+    test_result!(check_cleaned_up(&id, bundle.path()));
+    TestResult::Passed
+}
+
+// A failure after the container was started stops it, and deleting it
+// has to remove everything
+fn test_start_failure() -> TestResult {
+    let id = generate_uuid().to_string();
+    let bundle = test_result!(prepare_bundle());
+    let spec = test_result!(create_spec(&id));
+    test_result!(set_config(&bundle, &spec));
+
+    let status = test_result!(create_with_fault(&id, bundle.path(), "before-exec"));
+    if !status.success() {
+        cleanup(&id, bundle.path());
+        return TestResult::Failed(anyhow::anyhow!("failed to create container"));
+    }
+    // the start may or may not report the failure of the container process
+    test_result!(start_container(&id, &bundle).and_then(|mut c| Ok(c.wait()?)));
+
+    let (out, err) = test_result!(get_state(&id, &bundle));
+    let state: Result<State, _> = serde_json::from_str(&out);
+    match state {
+        Ok(state) if state.status == "running" => {
+            cleanup(&id, bundle.path());
+            return TestResult::Skipped;
+        }
+        Ok(state) if state.status == "stopped" => {}
+        _ => {
+            cleanup(&id, bundle.path());
+            return TestResult::Failed(anyhow::anyhow!(
+                "expected the container to be stopped, got state {out:?} {err:?}"
+            ));
+        }
+    }
+
+    test_result!(delete_container(&id, &bundle).and_then(|mut c| Ok(c.wait()?)));
+    test_result!(check_cleaned_up(&id, bundle.path()));
+    TestResult::Passed
+}
+
+pub fn get_fault_injection_test() -> TestGroup {
+    let after_cgroup_create = Test::new(
+        "after_cgroup_create",
+        Box::new(|| test_create_failure("after-cgroup-create")),
+    );
+    let after_rootfs_prepare = Test::new(
+        "after_rootfs_prepare",
+        Box::new(|| test_create_failure("after-rootfs-prepare")),
+    );
+    let before_exec = Test::new("before_exec", Box::new(test_start_failure));
+
+    let mut tg = TestGroup::new("fault_injection");
+    tg.add(vec![
+        Box::new(after_cgroup_create),
+        Box::new(after_rootfs_prepare),
+        Box::new(before_exec),
+    ]);
+    tg
+}
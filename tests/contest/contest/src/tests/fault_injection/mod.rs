@@ -0,0 +1,2 @@
+mod fault_injection_test;
+pub use fault_injection_test::get_fault_injection_test;
@@ -0,0 +1,108 @@
+use std::fs;
+
+use anyhow::{Context, Result};
+use oci_spec::runtime::{
+    HookBuilder, HooksBuilder, LinuxBuilder, ProcessBuilder, Spec, SpecBuilder,
+};
+use test_framework::{test_result, Test, TestGroup, TestResult};
+
+use crate::utils::test_inside_container;
+use crate::utils::test_utils::CreateOptions;
+
+const MASKED_DIR: &str = "masked_dir";
+const MASKED_FILE: &str = "masked_file";
+
+fn create_spec(masked_paths: Vec<String>) -> Result<Spec> {
+    let spec = SpecBuilder::default()
+        .linux(
+            LinuxBuilder::default()
+                .masked_paths(masked_paths)
+                .build()
+                .context("failed to build linux spec")?,
+        )
+        .process(
+            ProcessBuilder::default()
+                .args(vec!["runtimetest".to_string(), "masked_paths".to_string()])
+                .build()
+                .context("failed to build process spec")?,
+        )
+        .build()
+        .context("failed to build spec")?;
+
+    Ok(spec)
+}
+
+fn masked_paths_test() -> TestResult {
+    let spec = test_result!(create_spec(vec![
+        format!("/{MASKED_DIR}"),
+        format!("/{MASKED_FILE}"),
+        format!("/{MASKED_DIR}/{MASKED_FILE}"),
+        "/masked_missing".to_string(),
+    ]));
+
+    // the masked paths have content which must not be visible in the
+    // container
+    test_inside_container(spec, &CreateOptions::default(), &|rootfs| {
+        let dir = rootfs.join(MASKED_DIR);
+        fs::create_dir(&dir).context("failed to create masked dir")?;
+        fs::write(dir.join(MASKED_FILE), "secret")
+            .context("failed to create file in masked dir")?;
+        fs::write(rootfs.join(MASKED_FILE), "secret").context("failed to create masked file")?;
+        Ok(())
+    })
+}
+
+const HOOK_MASKED_DIR: &str = "/dev/hook_masked_dir";
+const HOOK_MASKED_FILE: &str = "/dev/hook_masked_file";
+const HOOK_READONLY_DIR: &str = "/dev/hook_readonly_dir";
+
+// The paths are created by a createContainer hook in /dev, which is only
+// mounted once the rootfs is prepared. The hook finds the rootfs through the
+// bundle in the state it gets on stdin.
+fn hook_created_paths_test() -> TestResult {
+    let script = format!(
+        "rootfs=$(sed -n 's/.*\"bundle\":\"\\([^\"]*\\)\".*/\\1/p')/rootfs && \
+         mkdir \"$rootfs{HOOK_MASKED_DIR}\" \"$rootfs{HOOK_READONLY_DIR}\" && \
+         echo secret > \"$rootfs{HOOK_MASKED_DIR}/secret\" && \
+         echo secret > \"$rootfs{HOOK_MASKED_FILE}\""
+    );
+    let hook = test_result!(HookBuilder::default()
+        .path("/bin/sh")
+        .args(vec!["sh".to_string(), "-c".to_string(), script])
+        .build()
+        .context("failed to build hook"));
+    let spec = test_result!(SpecBuilder::default()
+        .hooks(test_result!(HooksBuilder::default()
+            .create_container(vec![hook])
+            .build()
+            .context("failed to build hooks")))
+        .linux(test_result!(LinuxBuilder::default()
+            .masked_paths(vec![
+                HOOK_MASKED_DIR.to_string(),
+                HOOK_MASKED_FILE.to_string()
+            ])
+            .readonly_paths(vec![HOOK_READONLY_DIR.to_string()])
+            .build()
+            .context("failed to build linux spec")))
+        .process(test_result!(ProcessBuilder::default()
+            .args(vec![
+                "runtimetest".to_string(),
+                "hook_created_paths".to_string()
+            ])
+            .build()
+            .context("failed to build process spec")))
+        .build()
+        .context("failed to build spec"));
+
+    test_inside_container(spec, &CreateOptions::default(), &|_| Ok(()))
+}
+
+pub fn get_masked_paths_test() -> TestGroup {
+    let mut masked_paths_test_group = TestGroup::new("masked_paths");
+
+    let test = Test::new("masked_paths_test", Box::new(masked_paths_test));
+    let hook_test = Test::new("hook_created_paths_test", Box::new(hook_created_paths_test));
+    masked_paths_test_group.add(vec![Box::new(test), Box::new(hook_test)]);
+
+    masked_paths_test_group
+}
@@ -0,0 +1,2 @@
+mod masked_paths_test;
+pub use masked_paths_test::get_masked_paths_test;
@@ -1,13 +1,18 @@
+pub mod builtin_pause;
+pub mod capabilities;
 pub mod cgroups;
+pub mod checkpoint_restore;
 pub mod devices;
 pub mod domainname;
 pub mod example;
+pub mod fault_injection;
 pub mod hooks;
 pub mod hostname;
 pub mod intel_rdt;
 pub mod io_priority;
 pub mod lifecycle;
 pub mod linux_ns_itype;
+pub mod masked_paths;
 pub mod mounts_recursive;
 pub mod no_pivot;
 pub mod pidfile;
@@ -1,5 +1,5 @@
 use anyhow::{Context, Ok, Result};
-use oci_spec::runtime::{ProcessBuilder, RootBuilder, Spec, SpecBuilder};
+use oci_spec::runtime::{MountBuilder, ProcessBuilder, RootBuilder, Spec, SpecBuilder};
 use test_framework::{test_result, Test, TestGroup, TestResult};
 
 use crate::utils::test_inside_container;
@@ -30,6 +30,33 @@ fn root_readonly_false_test() -> TestResult {
     test_inside_container(spec_false, &CreateOptions::default(), &|_| Ok(()))
 }
 
+fn root_readonly_no_pivot_test() -> TestResult {
+    let spec = test_result!(create_spec(true));
+    test_inside_container(
+        spec,
+        &CreateOptions::default().with_no_pivot_root(),
+        &|_| Ok(()),
+    )
+}
+
+// Additional mounts must not leave the root writable, and must themselves keep
+// their own options.
+fn root_readonly_with_mounts_test() -> TestResult {
+    let mut spec = test_result!(create_spec(true));
+    let mut mounts = spec.mounts().clone().unwrap_or_default();
+    mounts.push(
+        MountBuilder::default()
+            .destination("/mnt")
+            .typ("tmpfs")
+            .source("tmpfs")
+            .options(vec!["nosuid".to_string(), "nodev".to_string()])
+            .build()
+            .unwrap(),
+    );
+    spec.set_mounts(Some(mounts));
+    test_inside_container(spec, &CreateOptions::default(), &|_| Ok(()))
+}
+
 pub fn get_root_readonly_test() -> TestGroup {
     let mut root_readonly_test_group = TestGroup::new("root_readonly");
 
@@ -38,7 +65,20 @@ pub fn get_root_readonly_test() -> TestGroup {
         "root_readonly_false_test",
         Box::new(root_readonly_false_test),
     );
-    root_readonly_test_group.add(vec![Box::new(test_true), Box::new(test_false)]);
+    let test_no_pivot = Test::new(
+        "root_readonly_no_pivot_test",
+        Box::new(root_readonly_no_pivot_test),
+    );
+    let test_with_mounts = Test::new(
+        "root_readonly_with_mounts_test",
+        Box::new(root_readonly_with_mounts_test),
+    );
+    root_readonly_test_group.add(vec![
+        Box::new(test_true),
+        Box::new(test_false),
+        Box::new(test_no_pivot),
+        Box::new(test_with_mounts),
+    ]);
 
     root_readonly_test_group
 }
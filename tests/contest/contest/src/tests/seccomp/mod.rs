@@ -1,13 +1,13 @@
 use oci_spec::runtime::{
-    LinuxBuilder, LinuxSeccomp, LinuxSeccompAction, LinuxSeccompBuilder, LinuxSyscallBuilder,
-    ProcessBuilder, Spec, SpecBuilder,
+    LinuxBuilder, LinuxSeccomp, LinuxSeccompAction, LinuxSeccompArgBuilder, LinuxSeccompBuilder,
+    LinuxSeccompOperator, LinuxSyscallBuilder, ProcessBuilder, Spec, SpecBuilder,
 };
 use test_framework::{Test, TestGroup, TestResult};
 
 use crate::utils::test_inside_container;
 use crate::utils::test_utils::CreateOptions;
 
-fn create_spec(seccomp: LinuxSeccomp) -> Spec {
+fn create_spec(seccomp: LinuxSeccomp, runtimetest: &str) -> Spec {
     SpecBuilder::default()
         .linux(
             LinuxBuilder::default()
@@ -17,7 +17,7 @@ fn create_spec(seccomp: LinuxSeccomp) -> Spec {
         )
         .process(
             ProcessBuilder::default()
-                .args(vec!["runtimetest".to_string(), "seccomp".to_string()])
+                .args(vec!["runtimetest".to_string(), runtimetest.to_string()])
                 .build()
                 .expect("error in creating process config"),
         )
@@ -36,6 +36,31 @@ fn seccomp_test() -> TestResult {
                 .unwrap()])
             .build()
             .unwrap(),
+        "seccomp",
+    );
+    test_inside_container(spec, &CreateOptions::default(), &|_| Ok(()))
+}
+
+fn seccomp_args_test() -> TestResult {
+    // personality(0xffffffff) only queries the persona, so denying it only
+    // for this argument can not affect the container process
+    let spec = create_spec(
+        LinuxSeccompBuilder::default()
+            .default_action(LinuxSeccompAction::ScmpActAllow)
+            .syscalls(vec![LinuxSyscallBuilder::default()
+                .names(vec![String::from("personality")])
+                .action(LinuxSeccompAction::ScmpActErrno)
+                .args(vec![LinuxSeccompArgBuilder::default()
+                    .index(0usize)
+                    .value(0xffffffffu64)
+                    .op(LinuxSeccompOperator::ScmpCmpEq)
+                    .build()
+                    .unwrap()])
+                .build()
+                .unwrap()])
+            .build()
+            .unwrap(),
+        "seccomp_args",
     );
     test_inside_container(spec, &CreateOptions::default(), &|_| Ok(()))
 }
@@ -43,7 +68,8 @@ fn seccomp_test() -> TestResult {
 pub fn get_seccomp_test() -> TestGroup {
     let mut test_group = TestGroup::new("seccomp");
     let seccomp_test = Test::new("seccomp_test", Box::new(seccomp_test));
-    test_group.add(vec![Box::new(seccomp_test)]);
+    let seccomp_args_test = Test::new("seccomp_args_test", Box::new(seccomp_args_test));
+    test_group.add(vec![Box::new(seccomp_test), Box::new(seccomp_args_test)]);
 
     test_group
 }
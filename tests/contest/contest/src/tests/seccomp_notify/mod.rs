@@ -1,33 +1,23 @@
-use std::path::PathBuf;
-use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::mpsc;
 use std::thread;
 
 use anyhow::{anyhow, bail, Result};
+use libcontainer::container::ContainerProcessState;
 use oci_spec::runtime::{
     Arch, LinuxBuilder, LinuxSeccompAction, LinuxSeccompBuilder, LinuxSyscallBuilder, SpecBuilder,
 };
-use test_framework::{Test, TestGroup, TestResult};
+use test_framework::seccomp_agent::AgentConnection;
+use test_framework::{SeccompAgent, Test, TestGroup, TestResult};
 
 use crate::utils::{get_runtime_path, test_outside_container};
 
-mod seccomp_agent;
-
 const SECCOMP_LISTENER_PATH: &str = "/tmp/youki_seccomp_agent.unix";
 const SECCOMP_METADATA: &str = "Hello World! This is an opaque seccomp metadata string";
 
-fn get_seccomp_listener() -> PathBuf {
-    let seccomp_listener_path = PathBuf::from(SECCOMP_LISTENER_PATH);
-    // We will have to clean up leftover unix domain socket from previous runs.
-    if seccomp_listener_path.exists() {
-        std::fs::remove_file(&seccomp_listener_path)
-            .expect("failed to clean up existing seccomp listener");
-    }
-
-    seccomp_listener_path
-}
-
 fn test_seccomp_notify() -> Result<()> {
-    let seccomp_listener_path = get_seccomp_listener();
+    // The agent listens before the container is created, otherwise the
+    // container creation would fail to connect to the seccomp listener.
+    let agent = SeccompAgent::bind(SECCOMP_LISTENER_PATH)?;
     let seccomp_meta = String::from(SECCOMP_METADATA);
     // Create a spec to include seccomp notify. We will need to have at least
     // one syscall set to seccomp notify. We also need to set seccomp listener
@@ -39,7 +29,7 @@ fn test_seccomp_notify() -> Result<()> {
                     LinuxSeccompBuilder::default()
                         .default_action(LinuxSeccompAction::ScmpActAllow)
                         .architectures(vec![Arch::ScmpArchX86_64])
-                        .listener_path(&seccomp_listener_path)
+                        .listener_path(SECCOMP_LISTENER_PATH)
                         .listener_metadata(seccomp_meta)
                         .syscalls(vec![LinuxSyscallBuilder::default()
                             .names(vec![String::from("getcwd")])
@@ -55,25 +45,21 @@ fn test_seccomp_notify() -> Result<()> {
         .build()
         .unwrap();
 
-    // two threads. One run container life cycle. Another one run seccomp agent...
-    let (sender, receiver): (
-        Sender<seccomp_agent::SeccompAgentResult>,
-        Receiver<seccomp_agent::SeccompAgentResult>,
-    ) = mpsc::channel();
-    // We have to launch the seccomp agent before we launch the container.
-    // Otherwise, the container creation will be blocked on trying to send to
-    // the seccomp listener and never returns.
+    // Two threads. One runs the container life cycle, the other one the
+    // seccomp agent, as creating the container blocks until the agent
+    // received the seccomp notify fd.
+    let (sender, receiver) = mpsc::channel::<Result<AgentConnection<ContainerProcessState>>>();
     let child = thread::spawn(move || {
-        let res = seccomp_agent::recv_seccomp_listener(&seccomp_listener_path);
         sender
-            .send(res)
+            .send(agent.accept())
             .expect("failed to send seccomp agent result back to main thread");
     });
     if let TestResult::Failed(err) = test_outside_container(spec, &move |data| {
-        let (container_process_state, _) = receiver
+        let container_process_state = receiver
             .recv()
             .expect("failed to receive from channel")
-            .expect("failed to receive from seccomp listener");
+            .expect("failed to receive from seccomp listener")
+            .state;
 
         let state = match data.state {
             Some(s) => s,
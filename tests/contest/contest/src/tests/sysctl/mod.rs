@@ -1,10 +1,11 @@
 use std::collections::HashMap;
 
+use anyhow::anyhow;
 use oci_spec::runtime::{LinuxBuilder, ProcessBuilder, Spec, SpecBuilder};
 use test_framework::{Test, TestGroup, TestResult};
 
-use crate::utils::test_inside_container;
 use crate::utils::test_utils::CreateOptions;
+use crate::utils::{test_inside_container, test_outside_container};
 
 fn create_spec(sysctl: HashMap<String, String>) -> Spec {
     SpecBuilder::default()
@@ -36,10 +37,33 @@ fn sysctl_test() -> TestResult {
     })
 }
 
+fn sysctl_not_namespaced_test() -> TestResult {
+    // vm.* sysctls are global to the host, so the runtime has to refuse them
+    // instead of changing the host configuration.
+    let spec = create_spec(HashMap::from([(
+        "vm.swappiness".to_string(),
+        "10".to_string(),
+    )]));
+    test_outside_container(spec, &|data| match data.create_result {
+        Err(e) => TestResult::Failed(anyhow!(e)),
+        Ok(res) if res.success() => {
+            TestResult::Failed(anyhow!("non namespaced sysctl vm.swappiness was allowed"))
+        }
+        Ok(_) => TestResult::Passed,
+    })
+}
+
 pub fn get_sysctl_test() -> TestGroup {
     let mut test_group = TestGroup::new("sysctl");
     let sysctl_test = Test::new("sysctl_test", Box::new(sysctl_test));
-    test_group.add(vec![Box::new(sysctl_test)]);
+    let sysctl_not_namespaced_test = Test::new(
+        "sysctl_not_namespaced_test",
+        Box::new(sysctl_not_namespaced_test),
+    );
+    test_group.add(vec![
+        Box::new(sysctl_test),
+        Box::new(sysctl_not_namespaced_test),
+    ]);
 
     test_group
 }
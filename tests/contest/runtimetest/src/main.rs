@@ -37,6 +37,7 @@ fn main() {
         "mounts_recursive" => tests::validate_mounts_recursive(&spec),
         "domainname_test" => tests::validate_domainname(&spec),
         "seccomp" => tests::validate_seccomp(&spec),
+        "seccomp_args" => tests::validate_seccomp_args(&spec),
         "sysctl" => tests::validate_sysctl(&spec),
         "scheduler_policy_other" => tests::validate_scheduler_policy(&spec),
         "scheduler_policy_batch" => tests::validate_scheduler_policy(&spec),
@@ -50,6 +51,9 @@ fn main() {
         "process_rlimits" => tests::validate_process_rlimits(&spec),
         "no_pivot" => tests::validate_rootfs(),
         "process_oom_score_adj" => tests::validate_process_oom_score_adj(&spec),
+        "capabilities" => tests::validate_capabilities(&spec),
+        "masked_paths" => tests::validate_masked_paths(&spec),
+        "hook_created_paths" => tests::validate_hook_created_paths(&spec),
         _ => eprintln!("error due to unexpected execute test name: {execute_test}"),
     }
 }
@@ -9,6 +9,7 @@ use nix::errno::Errno;
 use nix::libc;
 use nix::sys::resource::{getrlimit, Resource};
 use nix::sys::stat::{umask, Mode};
+use nix::sys::statvfs::{statvfs, FsFlags};
 use nix::sys::utsname;
 use nix::unistd::{getcwd, getgid, getgroups, getuid, Gid, Uid};
 use oci_spec::runtime::IOPriorityClass::{self, IoprioClassBe, IoprioClassIdle, IoprioClassRt};
@@ -318,6 +319,42 @@ pub fn validate_seccomp(spec: &Spec) {
     }
 }
 
+// The contest seccomp_args test denies personality(2) only when its persona
+// argument is 0xffffffff, which merely queries the current persona.
+const SECCOMP_ARGS_DENIED_PERSONA: libc::c_ulong = 0xffffffff;
+
+pub fn validate_seccomp_args(spec: &Spec) {
+    let linux = spec.linux().as_ref().unwrap();
+    if linux.seccomp().is_none() {
+        eprintln!("in seccomp args, expected a seccomp profile to be set, found none");
+        return;
+    }
+
+    // SAFETY: querying the persona changes nothing
+    let ret = unsafe { libc::personality(SECCOMP_ARGS_DENIED_PERSONA) };
+    if ret != -1 {
+        eprintln!(
+            "'personality({SECCOMP_ARGS_DENIED_PERSONA:#x})' succeeded. It was expected to fail due to seccomp policies."
+        );
+    } else if Errno::last() != Errno::EPERM {
+        eprintln!(
+            "'personality({SECCOMP_ARGS_DENIED_PERSONA:#x})' failed with unexpected error code '{}', expected 'EPERM'",
+            Errno::last()
+        );
+    }
+
+    // Any other argument does not match the rule. PER_LINUX is the persona
+    // the container process has anyway.
+    // SAFETY: setting the persona to the one already set changes nothing
+    let ret = unsafe { libc::personality(0) };
+    if ret == -1 {
+        eprintln!(
+            "'personality(0)' failed with '{}'. Only the argument {SECCOMP_ARGS_DENIED_PERSONA:#x} was expected to be denied.",
+            Errno::last()
+        );
+    }
+}
+
 pub fn validate_sysctl(spec: &Spec) {
     let linux = spec.linux().as_ref().unwrap();
     if let Some(expected_linux_sysctl) = linux.sysctl() {
@@ -556,6 +593,13 @@ pub fn test_io_priority_class(spec: &Spec, io_priority_class: IOPriorityClass) {
 pub fn test_validate_root_readonly(spec: &Spec) {
     let root = spec.root().as_ref().unwrap();
     if root.readonly().unwrap() {
+        match statvfs("/") {
+            Ok(stat) if !stat.flags().contains(FsFlags::ST_RDONLY) => {
+                eprintln!("readonly root filesystem, but / is not mounted readonly");
+            }
+            Ok(_) => {}
+            Err(e) => eprintln!("error in statvfs for path /, error: {}", e),
+        }
         if let Err(e) = test_dir_write_access("/") {
             let errno = Errno::from_raw(e.raw_os_error().unwrap());
             if errno == Errno::EROFS {
@@ -775,3 +819,159 @@ pub fn validate_process_oom_score_adj(spec: &Spec) {
         eprintln!("Unexpected oom_score_adj, expected: {expected_value} found: {actual_value}");
     }
 }
+
+pub fn validate_capabilities(spec: &Spec) {
+    let process = spec.process().as_ref().unwrap();
+    let capabilities = match process.capabilities() {
+        Some(capabilities) => capabilities,
+        None => {
+            eprintln!("in capabilities, expected capabilities to be set, found none");
+            return;
+        }
+    };
+
+    let last_cap: u8 = match fs::read_to_string("/proc/sys/kernel/cap_last_cap")
+        .map_err(anyhow::Error::from)
+        .and_then(|last_cap| Ok(last_cap.trim().parse()?))
+    {
+        Ok(last_cap) => last_cap,
+        Err(e) => {
+            eprintln!("error in reading the last capability of the kernel: {e:?}");
+            return;
+        }
+    };
+    let status = match fs::read_to_string("/proc/self/status") {
+        Ok(status) => status,
+        Err(e) => {
+            eprintln!("error in reading /proc/self/status: {e:?}");
+            return;
+        }
+    };
+
+    // The sets of the spec are expected to be consistent, i.e. the effective
+    // set is what the kernel computes on exec from the other sets, which is
+    // the case if the permitted and effective sets equal the bounding set.
+    let expected_sets = [
+        ("CapEff", capabilities.effective()),
+        ("CapBnd", capabilities.bounding()),
+    ];
+    for (field, expected) in expected_sets {
+        let expected_mask = match expected {
+            Some(expected) => match capability_mask(expected, last_cap) {
+                Ok(mask) => mask,
+                Err(e) => {
+                    eprintln!("error in converting the {field} capabilities of the spec: {e:?}");
+                    return;
+                }
+            },
+            None => 0,
+        };
+        let actual_mask = match status_capability_mask(&status, field) {
+            Ok(mask) => mask,
+            Err(e) => {
+                eprintln!("error in reading {field} from /proc/self/status: {e:?}");
+                return;
+            }
+        };
+
+        if actual_mask != expected_mask {
+            eprintln!(
+                "unexpected {field}, expected: {expected_mask:016x} found: {actual_mask:016x}"
+            );
+        }
+    }
+}
+
+// capabilities unknown to the kernel can not be set, so they are ignored
+fn capability_mask(capabilities: &oci_spec::runtime::Capabilities, last_cap: u8) -> Result<u64> {
+    let mut mask = 0;
+    for capability in capabilities {
+        let capability: caps::Capability = format!("CAP_{capability}").parse()?;
+        if capability.index() <= last_cap {
+            mask |= capability.bitmask();
+        }
+    }
+
+    Ok(mask)
+}
+
+fn status_capability_mask(status: &str, field: &str) -> Result<u64> {
+    let value = status
+        .lines()
+        .find_map(|line| line.strip_prefix(field)?.strip_prefix(':'))
+        .ok_or_else(|| anyhow::anyhow!("no {field} line"))?;
+
+    Ok(u64::from_str_radix(value.trim(), 16)?)
+}
+
+pub fn validate_masked_paths(spec: &Spec) {
+    let linux = spec.linux().as_ref().unwrap();
+    let masked_paths = match linux.masked_paths() {
+        Some(p) => p,
+        None => {
+            eprintln!("in masked paths, expected some masked paths to be set, found none");
+            return;
+        }
+    };
+
+    for path in masked_paths {
+        if let Err(e) = validate_masked_path(path) {
+            eprintln!("in masked paths, path {path} : {e}");
+        }
+    }
+}
+
+// The masked and readonly paths of this test are created by a createContainer
+// hook, so unlike in the other tests all of them have to exist.
+pub fn validate_hook_created_paths(spec: &Spec) {
+    let linux = spec.linux().as_ref().unwrap();
+    for path in linux.masked_paths().iter().flatten() {
+        if !Path::new(path).exists() {
+            eprintln!("in hook created paths, masked path {path} does not exist");
+        } else if let Err(e) = validate_masked_path(path) {
+            eprintln!("in hook created paths, masked path {path} : {e}");
+        }
+    }
+    for path in linux.readonly_paths().iter().flatten() {
+        match test_dir_write_access(path) {
+            Ok(_) => eprintln!("in hook created paths, readonly path {path} is writable"),
+            Err(e) if e.raw_os_error() == Some(libc::EROFS) => {}
+            Err(e) => eprintln!("in hook created paths, readonly path {path} : {e:?}"),
+        }
+    }
+}
+
+// Masked files are covered by /dev/null and masked directories by an empty
+// read-only tmpfs. Either way nothing of the original content is visible and
+// nothing written to the path is kept.
+fn validate_masked_path(path: &str) -> Result<()> {
+    let metadata = match fs::metadata(path) {
+        Ok(metadata) => metadata,
+        // Non-existing masked paths are allowed, they are not mounted over
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => bail!("error in getting metadata: {e:?}"),
+    };
+
+    if metadata.is_dir() {
+        if fs::read_dir(path)?.next().is_some() {
+            bail!("expected masked directory to be empty");
+        }
+        match test_dir_write_access(path) {
+            Ok(_) => bail!("expected masked directory to not be writable, found writable"),
+            Err(e) if e.raw_os_error() == Some(libc::EROFS) => {}
+            Err(e) => bail!("unexpected error in testing write access: {e:?}"),
+        }
+        return Ok(());
+    }
+
+    let null = fs::metadata("/dev/null")?;
+    if !metadata.file_type().is_char_device() || metadata.st_rdev() != null.st_rdev() {
+        bail!("expected masked file to be /dev/null");
+    }
+    fs::write(path, "masked")?;
+    if !fs::read(path)?.is_empty() {
+        bail!("expected masked file to be empty after writing to it");
+    }
+
+    Ok(())
+}
@@ -1,9 +1,11 @@
 mod conditional_test;
+pub mod seccomp_agent;
 mod test;
 mod test_group;
 mod test_manager;
 pub mod testable;
 pub use conditional_test::ConditionalTest;
+pub use seccomp_agent::SeccompAgent;
 pub use test::Test;
 pub use test_group::TestGroup;
 pub use test_manager::TestManager;
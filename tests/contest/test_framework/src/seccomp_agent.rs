@@ -0,0 +1,217 @@
+//! Seccomp notify agent which can be shared by tests. The runtime connects to
+//! the seccomp listener of the spec once the seccomp filter of the container
+//! is loaded and sends the container process state together with the seccomp
+//! notify fd. The agent receives both and can then answer the syscalls the
+//! filter forwards to it.
+//!
+//! The listener can either be bound by the agent itself, or be passed to it
+//! already listening with systemd style socket activation. In both cases the
+//! socket listens before the container is created, so the runtime never
+//! races against the agent.
+use std::fs;
+use std::io::IoSliceMut;
+use std::os::fd::{AsFd, AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::os::unix::net::UnixListener;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use nix::errno::Errno;
+use nix::poll::{poll, PollFd, PollFlags, PollTimeout};
+use nix::sys::socket::{self, ControlMessageOwned, MsgFlags, UnixAddr};
+use serde::de::DeserializeOwned;
+
+const DEFAULT_BUFFER_SIZE: usize = 4096;
+// First fd passed with socket activation, see sd_listen_fds(3)
+const SD_LISTEN_FDS_START: RawFd = 3;
+
+nix::ioctl_readwrite!(seccomp_notif_recv, b'!', 0, libc::seccomp_notif);
+nix::ioctl_readwrite!(seccomp_notif_send, b'!', 1, libc::seccomp_notif_resp);
+
+/// Answer of the agent to a notified syscall
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NotifyResponse {
+    /// Let the kernel run the syscall as if there was no filter
+    Continue,
+    /// Fail the syscall with the given errno
+    Error(Errno),
+    /// Skip the syscall and return the given value
+    Value(i64),
+}
+
+pub struct SeccompAgent {
+    listener: UnixListener,
+    // only set if the agent created the socket file and has to remove it
+    path: Option<PathBuf>,
+}
+
+impl SeccompAgent {
+    /// Listens on the given path, a socket left over from previous runs is
+    /// removed first
+    pub fn bind<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        if path.exists() {
+            fs::remove_file(path)
+                .with_context(|| format!("failed to remove stale seccomp listener {path:?}"))?;
+        }
+        let listener = UnixListener::bind(path)
+            .with_context(|| format!("failed to bind seccomp listener {path:?}"))?;
+
+        Ok(Self {
+            listener,
+            path: Some(path.to_owned()),
+        })
+    }
+
+    /// Takes over the listening socket passed with socket activation, i.e.
+    /// as fd 3 with LISTEN_PID and LISTEN_FDS set in the environment
+    pub fn from_listen_fds() -> Result<Self> {
+        let listen_pid: i32 = std::env::var("LISTEN_PID")
+            .context("LISTEN_PID is not set")?
+            .parse()
+            .context("invalid LISTEN_PID")?;
+        if listen_pid != std::process::id() as i32 {
+            bail!("the listen fds were passed to process {listen_pid}");
+        }
+        let listen_fds: i32 = std::env::var("LISTEN_FDS")
+            .context("LISTEN_FDS is not set")?
+            .parse()
+            .context("invalid LISTEN_FDS")?;
+        if listen_fds != 1 {
+            bail!("expected 1 listen fd, got {listen_fds}");
+        }
+        // the fds must not be passed on to children
+        std::env::remove_var("LISTEN_PID");
+        std::env::remove_var("LISTEN_FDS");
+
+        // SAFETY: with socket activation fd 3 is passed to and owned by this
+        // process
+        let fd = unsafe { OwnedFd::from_raw_fd(SD_LISTEN_FDS_START) };
+        Ok(Self::from_listener(UnixListener::from(fd)))
+    }
+
+    pub fn from_listener(listener: UnixListener) -> Self {
+        Self {
+            listener,
+            path: None,
+        }
+    }
+
+    /// Waits for the runtime to connect and receives the container process
+    /// state and the seccomp notify fd. The state is decoded into `S`, which
+    /// is usually `libcontainer::container::ContainerProcessState`.
+    pub fn accept<S: DeserializeOwned>(&self) -> Result<AgentConnection<S>> {
+        let (conn, _) = self
+            .listener
+            .accept()
+            .context("failed to accept connection to seccomp listener")?;
+
+        let mut cmsgspace = nix::cmsg_space!([RawFd; 1]);
+        let mut buf = vec![0u8; DEFAULT_BUFFER_SIZE];
+        let mut iov = [IoSliceMut::new(&mut buf)];
+        let msg = socket::recvmsg::<UnixAddr>(
+            conn.as_raw_fd(),
+            &mut iov,
+            Some(&mut cmsgspace),
+            MsgFlags::MSG_CMSG_CLOEXEC,
+        )
+        .context("failed to receive message from runtime")?;
+
+        // We are expecting 1 SCM_RIGHTS message with 1 fd.
+        let cmsg = msg
+            .cmsgs()
+            .next()
+            .context("expecting at least 1 SCM_RIGHTS message")?;
+        let fd = match cmsg {
+            ControlMessageOwned::ScmRights(fds) if fds.len() == 1 => fds[0],
+            cmsg => bail!("expecting 1 SCM_RIGHTS message with 1 fd, but received {cmsg:?}"),
+        };
+        // SAFETY: the fd was just received and is not owned by anything else
+        let notify_fd = unsafe { OwnedFd::from_raw_fd(fd) };
+
+        // A message filling the whole buffer may have been truncated.
+        if msg.bytes >= DEFAULT_BUFFER_SIZE {
+            bail!("received more than the DEFAULT_BUFFER_SIZE");
+        }
+        let len = msg.bytes;
+        let state = serde_json::from_slice(&buf[..len])
+            .context("failed to parse the received message as container process state")?;
+
+        Ok(AgentConnection { state, notify_fd })
+    }
+}
+
+impl Drop for SeccompAgent {
+    fn drop(&mut self) {
+        if let Some(path) = &self.path {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+/// What the runtime sent to the agent for one container
+pub struct AgentConnection<S> {
+    pub state: S,
+    pub notify_fd: OwnedFd,
+}
+
+impl<S> AgentConnection<S> {
+    /// Answers notified syscalls with the handler until all processes which
+    /// use the filter exited
+    pub fn respond<F>(&self, mut handler: F) -> Result<()>
+    where
+        F: FnMut(&libc::seccomp_notif) -> NotifyResponse,
+    {
+        while self.respond_once(&mut handler)? {}
+        Ok(())
+    }
+
+    /// Answers the next notified syscall with the handler. Returns false
+    /// instead if all processes which use the filter exited.
+    pub fn respond_once<F>(&self, handler: F) -> Result<bool>
+    where
+        F: FnOnce(&libc::seccomp_notif) -> NotifyResponse,
+    {
+        let mut fds = [PollFd::new(self.notify_fd.as_fd(), PollFlags::POLLIN)];
+        loop {
+            match poll(&mut fds, PollTimeout::NONE) {
+                Err(Errno::EINTR) => continue,
+                result => result.context("failed to poll seccomp notify fd")?,
+            };
+            break;
+        }
+        let revents = fds[0].revents().unwrap_or_else(PollFlags::empty);
+        if !revents.contains(PollFlags::POLLIN) {
+            return Ok(false);
+        }
+
+        // SAFETY: an all zero seccomp_notif is valid, the kernel requires
+        // it to be zeroed
+        let mut notif: libc::seccomp_notif = unsafe { std::mem::zeroed() };
+        // SAFETY: notif is a valid seccomp_notif for the kernel to fill
+        match unsafe { seccomp_notif_recv(self.notify_fd.as_raw_fd(), &mut notif) } {
+            Ok(_) => {}
+            // the process which made the syscall was killed in the meantime
+            Err(Errno::ENOENT) => return Ok(true),
+            Err(err) => return Err(err).context("failed to receive seccomp notification"),
+        }
+
+        let mut resp = libc::seccomp_notif_resp {
+            id: notif.id,
+            val: 0,
+            error: 0,
+            flags: 0,
+        };
+        match handler(&notif) {
+            NotifyResponse::Continue => {
+                resp.flags = libc::SECCOMP_USER_NOTIF_FLAG_CONTINUE as u32;
+            }
+            NotifyResponse::Error(errno) => resp.error = -(errno as i32),
+            NotifyResponse::Value(val) => resp.val = val,
+        }
+        // SAFETY: resp is a valid seccomp_notif_resp
+        match unsafe { seccomp_notif_send(self.notify_fd.as_raw_fd(), &mut resp) } {
+            Ok(_) | Err(Errno::ENOENT) => Ok(true),
+            Err(err) => Err(err).context("failed to send seccomp notification response"),
+        }
+    }
+}